@@ -0,0 +1,164 @@
+//! The client without async, for code that has no executor. Same methods as `crate::Client`,
+//! they block until the server answered, and between tries
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use playground::http::{Reply, Target};
+use playground::json::Json;
+use playground::transaction::{self, ClientId, Transaction};
+use playground::transaction_engine::TransactionEngine;
+
+use crate::{
+    account, expect_json, idempotency_key, outcome, Account, AccountEvent, Outcome, Retry,
+};
+
+#[derive(Clone)]
+pub struct Client {
+    target: Target,
+    retry: Retry,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            target: Target::parse(url)?,
+            retry: Retry::default(),
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    pub const fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// See `crate::Client::submit`
+    pub fn submit(&self, transactions: &[Transaction]) -> Result<Vec<Outcome>> {
+        let mut body = vec![];
+        transaction::write_transactions(transactions, &mut body)?;
+        let key = idempotency_key();
+        let reply = self.send(
+            "POST",
+            "transactions",
+            Some(("text/csv", &body)),
+            Some(&key),
+        )?;
+        match expect_json(reply)? {
+            Json::Array(outcomes) => outcomes.iter().map(outcome).collect(),
+            other => bail!("Expected an array of outcomes, got {}", other),
+        }
+    }
+
+    /// See `crate::Client::get_account`
+    pub fn get_account(&self, client: ClientId) -> Result<Option<Account>> {
+        let reply = self.send("GET", &format!("accounts/{}", client), None, None)?;
+        if reply.status == 404 {
+            return Ok(None);
+        }
+        account(&expect_json(reply)?).map(Some)
+    }
+
+    /// See `crate::Client::stream_events`, the iterator doesn't end
+    pub fn stream_events(&self, since: u64, every: Duration) -> Events {
+        Events {
+            client: self.clone(),
+            replica: TransactionEngine::new(),
+            since,
+            every,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<(&str, &[u8])>,
+        idempotency_key: Option<&str>,
+    ) -> Result<Reply> {
+        let headers = idempotency_key
+            .map(|key| ("Idempotency-Key", key.to_string()))
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut backoffs = self.retry.backoffs();
+        loop {
+            let reply = self
+                .target
+                .send_with_headers(method, path, &headers, body, self.timeout);
+            match backoffs.next() {
+                Some(backoff) if Retry::is_transient(&reply) => thread::sleep(backoff),
+                _ => return reply,
+            }
+        }
+    }
+}
+
+/// See `Client::stream_events`. Keeps a replica of the server's engine, from its
+/// `GET /export/changes`, to know which accounts changed
+pub struct Events {
+    client: Client,
+    replica: TransactionEngine,
+    since: u64,
+    every: Duration,
+    pending: VecDeque<AccountEvent>,
+}
+
+impl Events {
+    /// Applies what changed on the server, returns whether any account did
+    fn poll(&mut self) -> Result<bool> {
+        let at = self.replica.sequence();
+        let path = format!("export/changes?since={}", at);
+        let mut reply = self.client.send("GET", &path, None, None)?;
+        if reply.status == 409 {
+            self.replica = TransactionEngine::new();
+            self.since = 0;
+            reply = self.client.send("GET", "export/changes", None, None)?;
+        }
+        if reply.status != 200 {
+            bail!(
+                "The server answered {}: {}",
+                reply.status,
+                String::from_utf8_lossy(&reply.body)
+            );
+        }
+        let from = self.since.max(self.replica.sequence());
+        self.replica
+            .apply_changes(&reply.body)
+            .context("The server sent changes that can't be applied")?;
+        for client in self.replica.changed_since(from) {
+            if let Some(account) = self.replica.account(client) {
+                self.pending.push_back(AccountEvent {
+                    sequence: self.replica.sequence(),
+                    applied: self.replica.applied_sequence(),
+                    client,
+                    available: account.available().clone(),
+                    held: account.held().clone(),
+                    total: account.total(),
+                    locked: account.locked(),
+                });
+            }
+        }
+        Ok(!self.pending.is_empty())
+    }
+}
+
+impl Iterator for Events {
+    type Item = Result<AccountEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            match self.poll() {
+                Ok(true) => {}
+                Ok(false) => thread::sleep(self.every),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
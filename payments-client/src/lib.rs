@@ -0,0 +1,775 @@
+//! A typed client for the HTTP API of `playground serve`, so services don't have to build the
+//! requests and parse the answers themselves:
+//! ```text
+//! let client = Client::new("http://payments:8080")?;
+//! let outcomes = client.submit(&[Transaction::Deposit { client: 1, tx: 1, amount, wallet: None }]).await?;
+//! let account = client.get_account(1).await?;
+//! let mut events = client.stream_events(0, Duration::from_secs(1));
+//! while let Some(event) = events.next().await { ... }
+//! ```
+//! The methods are async and don't need a particular executor. There's no async runtime or
+//! HTTP crate in the build, so every request runs on a thread of its own and wakes the task when
+//! it's answered, and `Events` has one that reads the stream. The waits between tries and polls
+//! don't hold up the executor either.
+//! `blocking::Client` has the same methods for code without one. Requests that fail to connect
+//! or are answered 429, 502, 503 or 504 are tried again, see `Retry`
+
+pub mod blocking;
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{self, Poll, Waker};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+use playground::http::Reply;
+use playground::json::Json;
+use playground::transaction::{ClientId, Transaction};
+
+/// How often a request is tried, and how long is waited before the next try. The wait doubles
+/// every time, up to `max_backoff`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    /// Tries in all, 1 never tries again
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Retry {
+    /// The waits before the second try, the third and so on
+    pub fn backoffs(&self) -> impl Iterator<Item = Duration> {
+        let max = self.max_backoff;
+        std::iter::successors(Some(self.backoff.min(max)), move |backoff| {
+            Some((*backoff * 2).min(max))
+        })
+        .take(self.attempts.saturating_sub(1) as usize)
+    }
+
+    /// Whether the request is tried again: it couldn't be sent, or the server or a proxy in
+    /// front of it was too busy or couldn't reach it
+    pub fn is_transient(reply: &Result<Reply>) -> bool {
+        match reply {
+            Err(_) => true,
+            Ok(reply) => matches!(reply.status, 429 | 502 | 503 | 504),
+        }
+    }
+}
+
+/// Every call runs its request, with the retries, on a thread of its own, which isn't shared
+/// with other calls. Dropping the future doesn't stop the request, the thread carries on until
+/// it's answered or the tries are up
+#[derive(Clone)]
+pub struct Client {
+    blocking: blocking::Client,
+}
+
+/// What the server made of a submitted transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// With the number the server gave it, see `TransactionEngine::applied_sequence`
+    Applied {
+        client: ClientId,
+        tx: u32,
+        sequence: u64,
+    },
+    Rejected {
+        client: ClientId,
+        tx: u32,
+        /// E.g. `insufficient_funds`
+        reason: String,
+        /// E.g. `E1002`
+        code: String,
+    },
+    /// The server saw it before, within its `--dedup-window`
+    Duplicate { client: ClientId, tx: u32 },
+    /// The server couldn't read the row
+    Invalid { error: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub client: ClientId,
+    pub available: BigDecimal,
+    pub held: BigDecimal,
+    pub total: BigDecimal,
+    pub locked: bool,
+    pub pockets: Vec<(String, BigDecimal)>,
+    pub tags: Vec<String>,
+}
+
+/// An account as a change on the server left it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountEvent {
+    /// The server's sequence number the account is as of, to resume streaming from
+    pub sequence: u64,
+    /// The number of the latest transaction applied by then
+    pub applied: u64,
+    pub client: ClientId,
+    pub available: BigDecimal,
+    pub held: BigDecimal,
+    pub total: BigDecimal,
+    pub locked: bool,
+}
+
+impl Client {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            blocking: blocking::Client::new(url)?,
+        })
+    }
+
+    pub fn with_retry(self, retry: Retry) -> Self {
+        Self {
+            blocking: self.blocking.with_retry(retry),
+        }
+    }
+
+    /// Posts the transactions as one batch and returns what became of each, in order. Every
+    /// try of the batch has the same `Idempotency-Key`, so a server with `--idempotency-keys`
+    /// applies it once however often it's retried. Without them a batch the server applied
+    /// but couldn't answer may be applied twice
+    pub async fn submit(&self, transactions: &[Transaction]) -> Result<Vec<Outcome>> {
+        let (client, transactions) = (self.blocking.clone(), transactions.to_vec());
+        off_thread(move || client.submit(&transactions)).await
+    }
+
+    /// `None` if the server has no account of the client
+    pub async fn get_account(&self, client: ClientId) -> Result<Option<Account>> {
+        let blocking = self.blocking.clone();
+        off_thread(move || blocking.get_account(client)).await
+    }
+
+    /// The accounts that change on the server after sequence number `since`, as they change,
+    /// asking for news every `every`. 0 starts with every account. A failed request is an error
+    /// event and the next one asks again. A server that went back to an older snapshot starts
+    /// over with every account
+    pub fn stream_events(&self, since: u64, every: Duration) -> Events {
+        let feed = Arc::new(Feed {
+            state: Mutex::new(FeedState {
+                events: VecDeque::new(),
+                waker: None,
+                panic: None,
+                dropped: false,
+            }),
+            taken: Condvar::new(),
+        });
+        let events = self.blocking.stream_events(since, every);
+        let read = Arc::clone(&feed);
+        thread::spawn(move || read.fill(events));
+        Events { feed }
+    }
+}
+
+/// How many events the thread of `Events` reads ahead of `Events::next`
+const READ_AHEAD: usize = 64;
+
+/// See `Client::stream_events`. A thread of its own reads the events from the server and
+/// queues them for `next`, so a `next` that's dropped before it's done loses nothing. The
+/// thread stops once this is dropped, after the request it's waiting for if any
+pub struct Events {
+    feed: Arc<Feed>,
+}
+
+struct Feed {
+    state: Mutex<FeedState>,
+    /// Notified when an event is taken or the `Events` dropped
+    taken: Condvar,
+}
+
+struct FeedState {
+    events: VecDeque<Result<AccountEvent>>,
+    /// Of the latest poll of a `next` that found nothing
+    waker: Option<Waker>,
+    /// Of the thread reading the events, resumed in `next`
+    panic: Option<Box<dyn Any + Send>>,
+    dropped: bool,
+}
+
+impl Feed {
+    /// Reads the events into the queue until the `Events` is dropped, `READ_AHEAD` at most
+    fn fill(&self, mut events: blocking::Events) {
+        loop {
+            let mut state = lock(&self.state);
+            while state.events.len() >= READ_AHEAD && !state.dropped {
+                state = self
+                    .taken
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            if state.dropped {
+                return;
+            }
+            drop(state);
+            let event = panic::catch_unwind(AssertUnwindSafe(|| events.next()));
+            let mut state = lock(&self.state);
+            match event {
+                Ok(Some(event)) => state.events.push_back(event),
+                // the blocking events don't end
+                Ok(None) => return,
+                Err(panic) => state.panic = Some(panic),
+            }
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            if state.panic.is_some() {
+                return;
+            }
+        }
+    }
+}
+
+impl Events {
+    /// The next event, the stream doesn't end. Safe to drop before it's done, e.g. in a
+    /// `select!` or a timeout, the event it was waiting for is kept for the next call
+    pub async fn next(&mut self) -> Option<Result<AccountEvent>> {
+        Some(NextEvent { feed: &self.feed }.await)
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        lock(&self.feed.state).dropped = true;
+        self.feed.taken.notify_all();
+    }
+}
+
+struct NextEvent<'a> {
+    feed: &'a Feed,
+}
+
+impl Future for NextEvent<'_> {
+    type Output = Result<AccountEvent>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.feed.state);
+        if let Some(event) = state.events.pop_front() {
+            drop(state);
+            self.feed.taken.notify_all();
+            return Poll::Ready(event);
+        }
+        if let Some(panic) = state.panic.take() {
+            panic::resume_unwind(panic);
+        }
+        state.waker = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Runs `work` on a thread of its own, the future is ready with what it returned. A panic in
+/// `work` is resumed where the future is polled
+fn off_thread<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> OffThread<T> {
+    let slot = Arc::new(Mutex::new(Slot {
+        done: None,
+        waker: None,
+    }));
+    let filled = Arc::clone(&slot);
+    thread::spawn(move || {
+        let done = panic::catch_unwind(AssertUnwindSafe(work));
+        let mut slot = lock(&filled);
+        slot.done = Some(done);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    });
+    OffThread { slot }
+}
+
+struct OffThread<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+struct Slot<T> {
+    done: Option<thread::Result<T>>,
+    /// Of the latest poll
+    waker: Option<Waker>,
+}
+
+impl<T> Future for OffThread<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut task::Context<'_>) -> Poll<T> {
+        let mut slot = lock(&self.slot);
+        match slot.done.take() {
+            Some(Ok(output)) => Poll::Ready(output),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                slot.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Unique to this process and call
+fn idempotency_key() -> String {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "payments-client-{}-{}-{}",
+        std::process::id(),
+        now.as_nanos(),
+        CALLS.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn expect_json(reply: Reply) -> Result<Json> {
+    let body = String::from_utf8_lossy(&reply.body);
+    if reply.status != 200 {
+        bail!("The server answered {}: {}", reply.status, body);
+    }
+    Json::parse(&body).context("The server's answer isn't JSON")
+}
+
+fn outcome(json: &Json) -> Result<Outcome> {
+    if let Some(error) = json.get("error") {
+        return Ok(Outcome::Invalid {
+            error: error.as_str().unwrap_or_default().to_string(),
+        });
+    }
+    let (client, tx) = (number(json, "client")?, number(json, "tx")?);
+    match text(json, "status")? {
+        "applied" => Ok(Outcome::Applied {
+            client,
+            tx,
+            sequence: number(json, "sequence")?,
+        }),
+        "rejected" => Ok(Outcome::Rejected {
+            client,
+            tx,
+            reason: text(json, "reason")?.to_string(),
+            code: text(json, "code")?.to_string(),
+        }),
+        "duplicate" => Ok(Outcome::Duplicate { client, tx }),
+        other => bail!("Unknown status {}", other),
+    }
+}
+
+fn account(json: &Json) -> Result<Account> {
+    let pockets = match json.get("pockets") {
+        Some(Json::Object(pockets)) => pockets
+            .iter()
+            .map(|(pocket, balance)| Ok((pocket.clone(), decimal(balance)?)))
+            .collect::<Result<_>>()?,
+        _ => vec![],
+    };
+    let tags = match json.get("tags") {
+        Some(Json::Array(tags)) => tags
+            .iter()
+            .filter_map(|tag| tag.as_str().map(str::to_string))
+            .collect(),
+        _ => vec![],
+    };
+    Ok(Account {
+        client: number(json, "client")?,
+        available: decimal(field(json, "available")?)?,
+        held: decimal(field(json, "held")?)?,
+        total: decimal(field(json, "total")?)?,
+        locked: matches!(json.get("locked"), Some(Json::Bool(true))),
+        pockets,
+        tags,
+    })
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json> {
+    json.get(key)
+        .with_context(|| format!("The answer has no {}", key))
+}
+
+fn text<'a>(json: &'a Json, key: &str) -> Result<&'a str> {
+    field(json, key)?
+        .as_str()
+        .with_context(|| format!("The {} isn't a string", key))
+}
+
+fn number<T: FromStr>(json: &Json, key: &str) -> Result<T> {
+    match field(json, key)? {
+        Json::Number(number) => number
+            .parse()
+            .ok()
+            .with_context(|| format!("The {} {} is out of range", key, number)),
+        other => bail!("The {} {} isn't a number", key, other),
+    }
+}
+
+/// Amounts are strings, so they aren't rounded on the way
+fn decimal(json: &Json) -> Result<BigDecimal> {
+    let amount = json.as_str().context("Expected an amount")?;
+    amount
+        .parse()
+        .with_context(|| format!("Invalid amount {}", amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::pin::pin;
+    use std::task::Wake;
+    use std::thread::JoinHandle;
+    use std::time::Instant;
+
+    use playground::transaction_engine::TransactionEngine;
+
+    use super::*;
+
+    /// Polls on this thread, which parks until it's woken
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(thread::Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut context = task::Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// The head lines and the body
+    type Request = (Vec<String>, Vec<u8>);
+
+    /// A server that gives the answers in order, one per connection, and returns the requests
+    fn serve(answers: Vec<(u16, Vec<u8>)>) -> (String, JoinHandle<Vec<Request>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        (url, answer(listener, answers))
+    }
+
+    /// Starts answering on a listener that was bound before, until then connections wait
+    fn answer(listener: TcpListener, answers: Vec<(u16, Vec<u8>)>) -> JoinHandle<Vec<Request>> {
+        thread::spawn(move || {
+            let mut requests = vec![];
+            for (status, body) in answers {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    head.push(line.trim().to_string());
+                }
+                let length = head
+                    .iter()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body_read = vec![0; length];
+                reader.read_exact(&mut body_read).unwrap();
+                requests.push((head, body_read));
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                reader.get_mut().write_all(&body).unwrap();
+            }
+            requests
+        })
+    }
+
+    const fn fast() -> Retry {
+        Retry {
+            attempts: 5,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn should_retry_a_batch_with_the_same_idempotency_key() {
+        let (url, server) = serve(vec![
+            (503, b"{}".to_vec()),
+            (
+                200,
+                br#"[{"type":"deposit","client":1,"tx":1,"amount":"2.5000","status":"applied","sequence":7},{"type":"withdrawal","client":1,"tx":2,"amount":"9.0000","status":"rejected","reason":"insufficient_funds","code":"E1002"}]"#
+                    .to_vec(),
+            ),
+        ]);
+
+        let client = Client::new(&url).unwrap().with_retry(fast());
+        let outcomes = block_on(client.submit(&[
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: "2.5".parse().unwrap(),
+                wallet: None,
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(9),
+                wallet: None,
+            },
+        ]))
+        .unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Applied {
+                    client: 1,
+                    tx: 1,
+                    sequence: 7
+                },
+                Outcome::Rejected {
+                    client: 1,
+                    tx: 2,
+                    reason: "insufficient_funds".to_string(),
+                    code: "E1002".to_string()
+                }
+            ]
+        );
+
+        let requests = server.join().unwrap();
+        let key = |head: &[String]| {
+            head.iter()
+                .find(|line| line.starts_with("Idempotency-Key: "))
+                .cloned()
+        };
+        assert!(requests[0].0[0].starts_with("POST /transactions "));
+        assert!(key(&requests[0].0).is_some());
+        assert_eq!(key(&requests[0].0), key(&requests[1].0));
+        assert_eq!(
+            String::from_utf8(requests[1].1.clone()).unwrap(),
+            "type,client,tx,amount,from_pocket,to_pocket,seller,wallet,from_wallet,to_wallet,expires_at\ndeposit,1,1,2.5,,,,,,,\nwithdrawal,1,2,9,,,,,,,\n"
+        );
+    }
+
+    #[test]
+    fn should_double_the_backoff_up_to_the_max() {
+        let millis = |retry: Retry| {
+            retry
+                .backoffs()
+                .map(|backoff| backoff.as_millis())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(millis(Retry::default()), [100, 200, 400, 800]);
+        let capped = Retry {
+            attempts: 7,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+        assert_eq!(millis(capped), [100, 200, 300, 300, 300, 300]);
+        assert_eq!(
+            millis(Retry {
+                backoff: Duration::from_secs(9),
+                ..Retry::default()
+            }),
+            [5000, 5000, 5000, 5000]
+        );
+        for attempts in [0, 1] {
+            assert_eq!(
+                millis(Retry {
+                    attempts,
+                    ..Retry::default()
+                }),
+                [] as [u128; 0]
+            );
+        }
+    }
+
+    #[test]
+    fn should_only_retry_what_might_work_the_next_time() {
+        let account = br#"{"client":1,"available":"3.0000","held":"1.0000","total":"4.0000","locked":false,"pockets":{"savings":"0.5000"},"tags":["vip"]}"#;
+        let (url, server) = serve(vec![
+            (429, vec![]),
+            (502, vec![]),
+            (504, vec![]),
+            (200, account.to_vec()),
+        ]);
+        let client = Client::new(&url).unwrap().with_retry(fast());
+        assert_eq!(
+            block_on(client.get_account(1)).unwrap(),
+            Some(Account {
+                client: 1,
+                available: BigDecimal::from(3),
+                held: BigDecimal::from(1),
+                total: BigDecimal::from(4),
+                locked: false,
+                pockets: vec![("savings".to_string(), "0.5".parse().unwrap())],
+                tags: vec!["vip".to_string()],
+            })
+        );
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests
+            .iter()
+            .all(|(head, _)| head[0].starts_with("GET /accounts/1 ")));
+
+        // a server error or a missing account isn't tried again
+        let (url, server) = serve(vec![(500, b"{\"error\":\"oops\"}".to_vec()), (404, vec![])]);
+        let client = Client::new(&url).unwrap().with_retry(fast());
+        let error = block_on(client.get_account(1)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The server answered 500: {\"error\":\"oops\"}"
+        );
+        assert_eq!(block_on(client.get_account(2)).unwrap(), None);
+        assert_eq!(server.join().unwrap().len(), 2);
+
+        // the last answer counts once the tries are up
+        let (url, server) = serve(vec![(503, vec![]), (503, vec![])]);
+        let client = Client::new(&url).unwrap().with_retry(Retry {
+            attempts: 2,
+            ..fast()
+        });
+        let error = block_on(client.get_account(1)).unwrap_err();
+        assert_eq!(error.to_string(), "The server answered 503: ");
+        assert_eq!(server.join().unwrap().len(), 2);
+
+        // nothing listens, every try waits for the backoff before it
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let retry = Retry {
+            attempts: 3,
+            backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(40),
+        };
+        let client = blocking::Client::new(&url).unwrap().with_retry(retry);
+        let start = Instant::now();
+        let error = client.get_account(1).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to connect to"));
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn should_stream_the_accounts_as_they_change() {
+        let deposit = |client, tx, amount: u32| Transaction::Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        let mut engine = TransactionEngine::new();
+        engine.process(deposit(1, 1, 10)).unwrap();
+        engine.process(deposit(2, 2, 5)).unwrap();
+        let everything = engine.changes_since(0).unwrap();
+        let at = engine.sequence();
+        engine.process(deposit(2, 3, 1)).unwrap();
+        let later = engine.changes_since(at).unwrap();
+        // a server restored from a snapshot that's older than what the stream saw
+        let mut restored = TransactionEngine::new();
+        restored.process(deposit(1, 1, 3)).unwrap();
+
+        let (url, server) = serve(vec![
+            (200, everything),
+            (200, later),
+            (409, b"{\"error\":\"start over\"}".to_vec()),
+            (200, restored.changes_since(0).unwrap()),
+            (200, b"not changes".to_vec()),
+        ]);
+        let client = Client::new(&url).unwrap().with_retry(fast());
+        let mut events = client.stream_events(0, Duration::from_millis(1));
+        let mut next = || {
+            let event = block_on(events.next()).unwrap().unwrap();
+            (event.client, event.available, event.total, event.sequence)
+        };
+        assert_eq!(next(), (1, BigDecimal::from(10), BigDecimal::from(10), at));
+        assert_eq!(next(), (2, BigDecimal::from(5), BigDecimal::from(5), at));
+        assert_eq!(
+            next(),
+            (
+                2,
+                BigDecimal::from(6),
+                BigDecimal::from(6),
+                engine.sequence()
+            )
+        );
+        assert_eq!(
+            next(),
+            (
+                1,
+                BigDecimal::from(3),
+                BigDecimal::from(3),
+                restored.sequence()
+            )
+        );
+        let error = block_on(events.next()).unwrap().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The server sent changes that can't be applied"
+        );
+
+        let paths = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(head, _)| head[0].split(' ').nth(1).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/export/changes?since=0".to_string(),
+                format!("/export/changes?since={}", at),
+                format!("/export/changes?since={}", engine.sequence()),
+                "/export/changes".to_string(),
+                format!("/export/changes?since={}", restored.sequence()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_keep_the_event_a_dropped_next_was_waiting_for() {
+        let mut engine = TransactionEngine::new();
+        engine
+            .process(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            })
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let client = Client::new(&url).unwrap().with_retry(fast());
+        let mut events = client.stream_events(0, Duration::from_millis(1));
+
+        // the server isn't answering yet, like a timeout around `next` would give up
+        {
+            let mut next = pin!(events.next());
+            let mut context = task::Context::from_waker(Waker::noop());
+            assert!(next.as_mut().poll(&mut context).is_pending());
+        }
+        let server = answer(listener, vec![(200, engine.changes_since(0).unwrap())]);
+        let event = block_on(events.next()).unwrap().unwrap();
+        assert_eq!((event.client, event.total), (1, BigDecimal::from(10)));
+        drop(events);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,94 @@
+//! A plugin for the tests of `playground::plugin`: a `fee` handler that takes its amount out
+//! of the available balance, and a scorer that only distrusts withdrawals
+
+use std::ffi::{c_char, CStr};
+
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+type ClientId = u16;
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+type ClientId = u32;
+#[cfg(feature = "client-id-u64")]
+type ClientId = u64;
+
+/// `playground::plugin::ABI_VERSION` of the build with the same client ids
+const ABI_VERSION: u32 = match ClientId::BITS {
+    16 => 1,
+    bits => bits << 16 | 1,
+};
+
+#[no_mangle]
+pub extern "C" fn playground_plugin_abi() -> u32 {
+    if cfg!(feature = "wrong-abi") {
+        ABI_VERSION + 1
+    } else {
+        ABI_VERSION
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn playground_handler_kind() -> *const c_char {
+    c"fee".as_ptr()
+}
+
+/// Applies the fee if the account can pay it, rejects it as `fee_unpaid` if it can't
+///
+/// # Safety
+/// `amount` is null or a nul terminated string, `available` is one and `out` has room for
+/// `out_len` bytes
+#[cfg(not(feature = "half-handler"))]
+#[no_mangle]
+pub unsafe extern "C" fn playground_handler_apply(
+    _client: ClientId,
+    _tx: u32,
+    amount: *const c_char,
+    available: *const c_char,
+    out: *mut c_char,
+    out_len: usize,
+) -> i32 {
+    // SAFETY: the caller gives room for `out_len` bytes
+    let out = unsafe { std::slice::from_raw_parts_mut(out.cast::<u8>(), out_len) };
+    if cfg!(feature = "unterminated") {
+        out.fill(b'1');
+        return 1;
+    }
+    if amount.is_null() {
+        return reply(out, "no_amount", 1);
+    }
+    // SAFETY: both are nul terminated when they're not null
+    let (amount, available) = unsafe { (CStr::from_ptr(amount), CStr::from_ptr(available)) };
+    let (Ok(amount), Ok(available)) = (amount.to_str(), available.to_str()) else {
+        return reply(out, "not_utf8", 1);
+    };
+    let parse = |decimal: &str| decimal.parse::<f64>().unwrap_or(f64::NAN);
+    if parse(amount) > parse(available) {
+        return reply(out, "fee_unpaid", 1);
+    }
+    reply(out, &format!("-{}", amount), 0)
+}
+
+/// Writes `text` and its nul terminator to `out`, cut short if it doesn't fit
+fn reply(out: &mut [u8], text: &str, code: i32) -> i32 {
+    let len = text.len().min(out.len() - 1);
+    out[..len].copy_from_slice(&text.as_bytes()[..len]);
+    out[len] = 0;
+    code
+}
+
+/// 0.9 for withdrawals, 0.1 for everything else
+///
+/// # Safety
+/// `kind` is a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn playground_score(
+    kind: *const c_char,
+    _client: ClientId,
+    _tx: u32,
+    _amount: *const c_char,
+    _available: *const c_char,
+) -> f64 {
+    // SAFETY: the caller passes a nul terminated type
+    match unsafe { CStr::from_ptr(kind) }.to_bytes() {
+        b"withdrawal" => 0.9,
+        _ => 0.1,
+    }
+}
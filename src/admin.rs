@@ -0,0 +1,106 @@
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use playground::http::Target;
+use playground::json::Json;
+
+use crate::cli::AdminCommand;
+
+/// Runs an admin command against the HTTP API of a running `serve`, the reply goes to `writer`
+pub fn run(url: &str, command: &AdminCommand, writer: &mut dyn Write) -> Result<()> {
+    let target = Target::parse(url)?;
+    let mut body = None;
+    let (method, path) = match command {
+        AdminCommand::Accounts => ("GET", "export/accounts".to_string()),
+        AdminCommand::Lock { client, reason } => {
+            let path = format!("accounts/{}/lock", client);
+            match reason {
+                None => ("POST", path),
+                Some(reason) => (
+                    "POST",
+                    format!("{}?reason={}", path, percent_encode(reason)),
+                ),
+            }
+        }
+        AdminCommand::Unlock(client) => ("POST", format!("accounts/{}/unlock", client)),
+        AdminCommand::Close(client) => ("POST", format!("accounts/{}/close", client)),
+        AdminCommand::Adjust { client, amount } => (
+            "POST",
+            format!("accounts/{}/adjust?amount={}", client, amount),
+        ),
+        AdminCommand::Stats => ("GET", "stats".to_string()),
+        AdminCommand::Notes(client) => ("GET", format!("accounts/{}/notes", client)),
+        AdminCommand::Note {
+            client,
+            text,
+            author,
+        } => {
+            body = Some(("text/plain", text.as_bytes()));
+            let path = format!("accounts/{}/notes", client);
+            match author {
+                None => ("POST", path),
+                Some(author) => (
+                    "POST",
+                    format!("{}?author={}", path, percent_encode(author)),
+                ),
+            }
+        }
+        AdminCommand::Withdrawals => ("GET", "withdrawals".to_string()),
+        AdminCommand::Approve { client, tx } => {
+            ("POST", format!("withdrawals/{}/{}/approve", client, tx))
+        }
+        AdminCommand::Deny { client, tx } => {
+            ("POST", format!("withdrawals/{}/{}/deny", client, tx))
+        }
+        AdminCommand::Holds(client) => ("GET", format!("accounts/{}/holds", client)),
+        AdminCommand::Hold {
+            client,
+            amount,
+            reason,
+            author,
+            release,
+        } => {
+            let action = if *release { "release" } else { "hold" };
+            let path = format!(
+                "accounts/{}/{}?amount={}&reason={}",
+                client, action, amount, reason
+            );
+            match author {
+                None => ("POST", path),
+                Some(author) => (
+                    "POST",
+                    format!("{}&author={}", path, percent_encode(author)),
+                ),
+            }
+        }
+    };
+    let reply = target.send(method, &path, body, Duration::from_secs(30))?;
+    if !(200..300).contains(&reply.status) {
+        let body = String::from_utf8_lossy(&reply.body);
+        let error = Json::parse(&body)
+            .ok()
+            .and_then(|json| json.get("error").and_then(Json::as_str).map(String::from))
+            .unwrap_or_else(|| body.into_owned());
+        bail!("The server answered {}: {}", reply.status, error);
+    }
+    writer.write_all(&reply.body)?;
+    if !reply.body.ends_with(b"\n") {
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Everything but unreserved characters, for a query value
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
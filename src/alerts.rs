@@ -0,0 +1,276 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+
+use crate::tags::AccountTags;
+use crate::transaction::ClientId;
+
+/// Balance thresholds to keep an eye on, read from a file with one alert per line:
+/// ```text
+/// # name: [client <id> | tag <tag>] available|held|total <|<=|>|>= amount
+/// overdrawn: available < 0
+/// vip_large_hold: tag vip held > 10000
+/// treasury_low: client 7 total < 1000000
+/// ```
+/// Without a client or tag the alert applies to every account. An alert fires when a transaction
+/// makes its condition true for an account, not again while it stays true
+#[derive(Clone, Debug, Default)]
+pub struct AlertRules {
+    rules: Vec<AlertRule>,
+}
+
+#[derive(Clone, Debug)]
+struct AlertRule {
+    name: String,
+    scope: Scope,
+    field: Field,
+    comparison: Comparison,
+    threshold: BigDecimal,
+}
+
+#[derive(Clone, Debug)]
+enum Scope {
+    All,
+    Client(ClientId),
+    Tag(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Available,
+    Held,
+    Total,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// An account crossed an alert's threshold
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub name: String,
+    pub client: ClientId,
+    pub available: BigDecimal,
+    pub held: BigDecimal,
+}
+
+/// Told about every alert as it fires, register with `TransactionEngine::register_alert_observer`.
+/// Called in the middle of processing, so it should hand slow work off rather than do it
+pub trait AlertObserver: Send + Sync {
+    fn alert(&self, alert: &Alert);
+}
+
+/// Writes alerts to stderr, like the rest of the logs
+pub struct LogAlerts;
+
+impl AlertObserver for LogAlerts {
+    fn alert(&self, alert: &Alert) {
+        eprintln!(
+            "Alert {}: client {} has {:.4} available and {:.4} held",
+            alert.name, alert.client, alert.available, alert.held
+        );
+    }
+}
+
+impl AlertRules {
+    pub fn parse(alerts: &str) -> Result<Self> {
+        let rules = alerts
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                AlertRule::parse(line)
+                    .with_context(|| format!("Invalid alert on line {}", index + 1))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The alerts a change of the client's balances fires, `before` is `None` for a new account
+    pub fn fired(
+        &self,
+        client: ClientId,
+        tags: &AccountTags,
+        before: Option<(&BigDecimal, &BigDecimal)>,
+        (available, held): (&BigDecimal, &BigDecimal),
+    ) -> Vec<Alert> {
+        self.rules
+            .iter()
+            .filter(|rule| match &rule.scope {
+                Scope::All => true,
+                Scope::Client(id) => *id == client,
+                Scope::Tag(tag) => tags.has(client, tag),
+            })
+            .filter(|rule| {
+                rule.holds(available, held)
+                    && !before.is_some_and(|(available, held)| rule.holds(available, held))
+            })
+            .map(|rule| Alert {
+                name: rule.name.clone(),
+                client,
+                available: available.clone(),
+                held: held.clone(),
+            })
+            .collect()
+    }
+}
+
+impl AlertRule {
+    fn parse(line: &str) -> Result<Self> {
+        let (name, condition) = line.split_once(':').context("Expected name: condition")?;
+        let mut tokens = condition.split_whitespace().peekable();
+        let scope = match tokens.peek().copied() {
+            Some("client") => {
+                tokens.next();
+                let client = tokens.next().context("Expected a client id")?;
+                Scope::Client(client.parse().context("Invalid client id")?)
+            }
+            Some("tag") => {
+                tokens.next();
+                Scope::Tag(tokens.next().context("Expected a tag")?.to_string())
+            }
+            _ => Scope::All,
+        };
+        let field = match tokens.next() {
+            Some("available") => Field::Available,
+            Some("held") => Field::Held,
+            Some("total") => Field::Total,
+            other => bail!(
+                "Expected available, held or total, got {}",
+                other.unwrap_or("nothing")
+            ),
+        };
+        let comparison = match tokens.next() {
+            Some("<") => Comparison::Less,
+            Some("<=") => Comparison::LessOrEqual,
+            Some(">") => Comparison::Greater,
+            Some(">=") => Comparison::GreaterOrEqual,
+            other => bail!("Expected < <= > or >=, got {}", other.unwrap_or("nothing")),
+        };
+        let threshold = tokens.next().context("Expected an amount")?;
+        let threshold = BigDecimal::from_str(threshold)
+            .with_context(|| format!("Invalid amount {}", threshold))?;
+        if let Some(token) = tokens.next() {
+            bail!("Unexpected {}", token);
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            scope,
+            field,
+            comparison,
+            threshold,
+        })
+    }
+
+    fn holds(&self, available: &BigDecimal, held: &BigDecimal) -> bool {
+        let total;
+        let value = match self.field {
+            Field::Available => available,
+            Field::Held => held,
+            Field::Total => {
+                total = available + held;
+                &total
+            }
+        };
+        match self.comparison {
+            Comparison::Less => value < &self.threshold,
+            Comparison::LessOrEqual => value <= &self.threshold,
+            Comparison::Greater => value > &self.threshold,
+            Comparison::GreaterOrEqual => value >= &self.threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::transaction::Transaction::{Deposit, Dispute, Withdrawal};
+    use crate::transaction_engine::TransactionEngine;
+
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl AlertObserver for Collect {
+        fn alert(&self, alert: &Alert) {
+            let mut alerts = self.0.lock().unwrap();
+            alerts.push(format!("{} {}", alert.name, alert.client));
+        }
+    }
+
+    #[test]
+    fn should_fire_when_a_threshold_is_crossed() {
+        let alerts = AlertRules::parse(
+            "# comment\nlow: available < 10\nvip_hold: tag vip held >= 50\nseven: client 7 total > 0\n",
+        )
+        .unwrap();
+        assert_eq!(alerts.len(), 3);
+        assert!(AlertRules::parse("low: available << 10").is_err());
+
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            alerts,
+            ..EngineConfig::default()
+        });
+        engine.tags_mut().add(2, "vip".to_string());
+        let fired = Arc::new(Mutex::new(vec![]));
+        engine.register_alert_observer(Box::new(Collect(Arc::clone(&fired))));
+
+        let transactions = vec![
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            },
+            Deposit {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(20),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: BigDecimal::from(18),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            },
+            Deposit {
+                client: 2,
+                tx: 5,
+                amount: BigDecimal::from(50),
+                wallet: None,
+            },
+            Dispute { client: 2, tx: 5 },
+        ];
+        for transaction in transactions {
+            engine.process(transaction).unwrap();
+        }
+        // client 1 was low from the start, fine after the second deposit
+        // and low again after the first withdrawal, but not again after the second
+        assert_eq!(
+            *fired.lock().unwrap(),
+            vec!["low 1", "low 1", "low 2", "vip_hold 2"]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, Signed, ToPrimitive, Zero};
+
+/// How the amounts of an input are written. Partner files use their own locale,
+/// `1,234.56` or `1.234,56`, and would otherwise need to be rewritten before processing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AmountFormat {
+    decimal: char,
+    /// Amounts don't have to use it, but if they do the digits have to be grouped in threes
+    thousands: Option<char>,
+    scale: Option<ScaleLimit>,
+}
+
+impl Default for AmountFormat {
+    /// Plain `1234.56`, with any number of decimals
+    fn default() -> Self {
+        Self {
+            decimal: '.',
+            thousands: None,
+            scale: None,
+        }
+    }
+}
+
+/// The most decimals an amount may have, e.g. 2 for a currency with cents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScaleLimit {
+    pub decimals: u32,
+    pub policy: ScalePolicy,
+}
+
+/// What happens to an amount with more decimals than the `ScaleLimit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalePolicy {
+    /// The transaction is rejected
+    Reject,
+    /// The excess decimals are dropped
+    Truncate,
+    /// Rounded half to even, as banks do, so rounding doesn't drift the totals up
+    Round,
+}
+
+impl FromStr for ScalePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "reject" => Ok(ScalePolicy::Reject),
+            "truncate" => Ok(ScalePolicy::Truncate),
+            "round" => Ok(ScalePolicy::Round),
+            other => bail!(
+                "Unknown scale policy {}, expected reject, truncate or round",
+                other
+            ),
+        }
+    }
+}
+
+/// An amount checked against a `ScaleLimit`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scaled {
+    Fits,
+    Adjusted(BigDecimal),
+    Rejected,
+}
+
+impl ScaleLimit {
+    pub fn limit(&self, amount: &BigDecimal) -> Scaled {
+        let decimals = i64::from(self.decimals);
+        let (_, scale) = amount.as_bigint_and_exponent();
+        // 1.50 with a limit of 1 only looks like it has too many decimals
+        if scale <= decimals || *amount == amount.with_scale(decimals) {
+            return Scaled::Fits;
+        }
+        match self.policy {
+            ScalePolicy::Reject => Scaled::Rejected,
+            ScalePolicy::Truncate => Scaled::Adjusted(amount.with_scale(decimals)),
+            ScalePolicy::Round => Scaled::Adjusted(round_half_even(amount, decimals)),
+        }
+    }
+}
+
+fn round_half_even(amount: &BigDecimal, decimals: i64) -> BigDecimal {
+    let (digits, scale) = amount.as_bigint_and_exponent();
+    let exponent = u32::try_from(scale - decimals).unwrap_or(u32::MAX);
+    let divisor = BigInt::from(10).pow(exponent);
+    // both truncate towards zero, the remainder has the sign of the amount
+    let quotient = &digits / &divisor;
+    let remainder = &digits % &divisor;
+    let away_from_zero = match (remainder.abs() * 2u8).cmp(&divisor) {
+        Ordering::Less => false,
+        Ordering::Greater => true,
+        Ordering::Equal => !(&quotient % 2u8).is_zero(),
+    };
+    let quotient = if away_from_zero {
+        quotient + digits.signum()
+    } else {
+        quotient
+    };
+    BigDecimal::new(quotient, decimals)
+}
+
+impl AmountFormat {
+    pub fn new(decimal: char, thousands: Option<char>) -> Result<Self> {
+        for separator in [Some(decimal), thousands].into_iter().flatten() {
+            if separator.is_ascii_digit() || separator == '-' || separator == '+' {
+                bail!("{} can't be used as a separator", separator);
+            }
+        }
+        if thousands == Some(decimal) {
+            bail!("The decimal and thousands separators have to differ");
+        }
+        Ok(Self {
+            decimal,
+            thousands,
+            scale: None,
+        })
+    }
+
+    #[must_use]
+    pub const fn with_scale(mut self, scale: ScaleLimit) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    pub const fn scale(&self) -> Option<ScaleLimit> {
+        self.scale
+    }
+
+    /// The same scale limit, for inputs whose amounts are always written as `1234.56`
+    #[must_use]
+    pub const fn without_locale(self) -> Self {
+        Self {
+            decimal: '.',
+            thousands: None,
+            scale: self.scale,
+        }
+    }
+
+    /// The amount in the `1234.56` form the decimal parser takes, an empty amount stays empty
+    pub fn normalize<'a>(&self, amount: &'a str) -> Result<Cow<'a, str>> {
+        if (self.decimal, self.thousands) == ('.', None) || amount.is_empty() {
+            return Ok(Cow::Borrowed(amount));
+        }
+        // an exponent is kept as it is, `1,5e3` is `1.5e3`
+        let (mantissa, exponent) = match amount.find(['e', 'E']) {
+            Some(index) => amount.split_at(index),
+            None => (amount, ""),
+        };
+        let (integer, fraction) = match mantissa.split_once(self.decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (mantissa, None),
+        };
+        let (sign, integer) = match integer.strip_prefix('-') {
+            Some(integer) => ("-", integer),
+            None => ("", integer),
+        };
+        let integer = match self.thousands {
+            Some(thousands) if integer.contains(thousands) => {
+                let mut groups = integer.split(thousands);
+                let first = groups.next().unwrap_or_default();
+                if !(1..=3).contains(&first.len()) || groups.any(|group| group.len() != 3) {
+                    bail!(
+                        "Invalid amount {}, the thousands aren't grouped in threes",
+                        amount
+                    );
+                }
+                Cow::Owned(integer.replace(thousands, ""))
+            }
+            _ => Cow::Borrowed(integer),
+        };
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        let exponent_digits = exponent
+            .get(1..)
+            .map(|digits| digits.strip_prefix(['-', '+']).unwrap_or(digits));
+        if !digits(&integer) || !fraction.is_none_or(digits) || !exponent_digits.is_none_or(digits)
+        {
+            bail!("Invalid amount {}", amount);
+        }
+        Ok(Cow::Owned(match fraction {
+            Some(fraction) => format!("{}{}.{}{}", sign, integer, fraction, exponent),
+            None => format!("{}{}{}", sign, integer, exponent),
+        }))
+    }
+}
+
+/// An amount as a fixed point integer and its number of decimals, 16 bytes without a heap
+/// allocation, for storing lots of them. `BigDecimal` is what's calculated with, this is only
+/// how they're kept, and amounts whose digits don't fit in an `i64` can't be compact
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactAmount {
+    units: i64,
+    scale: i16,
+}
+
+impl CompactAmount {
+    pub fn new(amount: &BigDecimal) -> Option<Self> {
+        let (digits, scale) = amount.as_bigint_and_exponent();
+        Some(Self {
+            units: digits.to_i64()?,
+            scale: i16::try_from(scale).ok()?,
+        })
+    }
+
+    pub fn to_decimal(self) -> BigDecimal {
+        BigDecimal::new(BigInt::from(self.units), i64::from(self.scale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_normalize_locale_formatted_amounts() {
+        let english = AmountFormat::new('.', Some(',')).unwrap();
+        assert_eq!(english.normalize("1,234.56").unwrap(), "1234.56");
+        assert_eq!(english.normalize("1234.56").unwrap(), "1234.56");
+        assert_eq!(english.normalize("-12,345,678").unwrap(), "-12345678");
+        assert!(english.normalize("1,23.5").is_err());
+        assert!(english.normalize("1.234,56").is_err());
+
+        let german = AmountFormat::new(',', Some('.')).unwrap();
+        assert_eq!(german.normalize("1.234,56").unwrap(), "1234.56");
+        assert_eq!(german.normalize("0,5").unwrap(), "0.5");
+        assert_eq!(german.normalize("").unwrap(), "");
+        assert!(german.normalize("1,234.56").is_err());
+
+        assert_eq!(AmountFormat::default().normalize("1e3").unwrap(), "1e3");
+        assert_eq!(german.normalize("1e400").unwrap(), "1e400");
+        assert_eq!(german.normalize("-1.234,5E-2").unwrap(), "-1234.5E-2");
+        assert!(german.normalize("1e").is_err());
+        assert!(german.normalize("1e4,5").is_err());
+        assert!(AmountFormat::new(',', Some(',')).is_err());
+    }
+
+    #[test]
+    fn should_limit_the_scale() {
+        let limit = |policy: &str, amount: &str| {
+            let limit = ScaleLimit {
+                decimals: 2,
+                policy: policy.parse().unwrap(),
+            };
+            match limit.limit(&amount.parse().unwrap()) {
+                Scaled::Fits => "fits".to_string(),
+                Scaled::Adjusted(adjusted) => adjusted.to_string(),
+                Scaled::Rejected => "rejected".to_string(),
+            }
+        };
+        assert_eq!(limit("reject", "1.25"), "fits");
+        assert_eq!(limit("reject", "1.2500"), "fits");
+        assert_eq!(limit("reject", "1.255"), "rejected");
+        assert_eq!(limit("truncate", "1.259"), "1.25");
+        assert_eq!(limit("truncate", "-1.259"), "-1.25");
+        assert_eq!(limit("round", "1.255"), "1.26");
+        assert_eq!(limit("round", "1.245"), "1.24");
+        assert_eq!(limit("round", "1.2451"), "1.25");
+        assert_eq!(limit("round", "-1.235"), "-1.24");
+        assert_eq!(limit("round", "0.004"), "0.00");
+    }
+
+    #[test]
+    fn should_keep_amounts_compact_when_they_fit() {
+        for amount in ["1.5000", "-0.0001", "0", "92233720368547.75807", "1e-300"] {
+            let amount = BigDecimal::from_str(amount).unwrap();
+            let compact = CompactAmount::new(&amount).unwrap();
+            assert_eq!(compact.to_decimal().to_string(), amount.to_string());
+        }
+        assert!(
+            CompactAmount::new(&BigDecimal::from_str("92233720368547.75808").unwrap()).is_none()
+        );
+        assert!(CompactAmount::new(&BigDecimal::from_str("1e-40000").unwrap()).is_none());
+        assert_eq!(std::mem::size_of::<CompactAmount>(), 16);
+    }
+}
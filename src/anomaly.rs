@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::risk::AmountStats;
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+/// Clients need this many deposits and withdrawals before the batch to have a baseline
+pub const MIN_BASELINE: u64 = 5;
+
+/// What the clients had done before a batch, to compare the batch's activity with once it's
+/// processed. Taken of an engine that's continuing from a snapshot, the clients of an empty
+/// one have no history to deviate from
+pub struct Baseline {
+    started_at: u64,
+    clients: HashMap<ClientId, (AmountStats, Option<u64>)>,
+}
+
+/// A client whose batch deviates from their baseline. The amounts are the mean of the
+/// deposits and withdrawals, the rates how many there were per day
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    pub client: ClientId,
+    /// The larger of the amount's z-score and the rate ratio, what anomalies are ranked by
+    pub score: f64,
+    /// How many of the baseline's standard deviations the batch's mean amount is from the
+    /// baseline's, `None` if the baseline's amounts don't vary
+    pub amount_z: Option<f64>,
+    /// How many times the baseline's rate the batch's is, `None` unless the input has
+    /// timestamps
+    pub rate_ratio: Option<f64>,
+    pub batch: AmountStats,
+    pub baseline: AmountStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CsvAnomaly {
+    pub client: ClientId,
+    pub score: String,
+    pub amount_z: Option<String>,
+    pub rate_ratio: Option<String>,
+    pub batch_count: u64,
+    pub batch_mean: String,
+    pub baseline_count: u64,
+    pub baseline_mean: String,
+    pub baseline_std_dev: String,
+}
+
+impl Baseline {
+    pub fn capture(transaction_engine: &TransactionEngine) -> Self {
+        Self {
+            started_at: transaction_engine.now(),
+            clients: transaction_engine
+                .account_views()
+                .map(|account| {
+                    (
+                        account.client(),
+                        (account.amount_stats().clone(), account.first_stored_at()),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The clients whose batch is `threshold` or more off their baseline in amount or rate,
+    /// most anomalous first. Clients with no baseline or nothing in the batch aren't compared
+    pub fn anomalies(
+        &self,
+        transaction_engine: &TransactionEngine,
+        threshold: f64,
+    ) -> Vec<Anomaly> {
+        let ended_at = transaction_engine.now();
+        // the wall clock only tells how long processing took, not when the batch happened
+        let timed = transaction_engine.time().is_some();
+        let mut anomalies = transaction_engine
+            .account_views()
+            .filter_map(|account| {
+                let (baseline, first_at) = self.clients.get(&account.client())?;
+                let batch = account.amount_stats().since(baseline);
+                if baseline.count() < MIN_BASELINE || batch.count() == 0 {
+                    return None;
+                }
+                let amount_z = baseline.z_score(batch.mean());
+                let rate_ratio = match (timed, first_at) {
+                    (true, Some(first_at)) => {
+                        rate(&batch, ended_at.saturating_sub(self.started_at))
+                            .zip(rate(baseline, self.started_at.saturating_sub(*first_at)))
+                            .map(|(batch, baseline)| batch / baseline)
+                    }
+                    _ => None,
+                };
+                let score = amount_z
+                    .map(f64::abs)
+                    .into_iter()
+                    .chain(rate_ratio)
+                    .fold(0.0, f64::max);
+                (score >= threshold).then(|| Anomaly {
+                    client: account.client(),
+                    score,
+                    amount_z,
+                    rate_ratio,
+                    batch,
+                    baseline: baseline.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        anomalies.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.client.cmp(&b.client)));
+        anomalies
+    }
+}
+
+/// Per day
+fn rate(stats: &AmountStats, seconds: u64) -> Option<f64> {
+    #[allow(clippy::cast_precision_loss)]
+    (seconds > 0).then(|| stats.count() as f64 * 86_400.0 / seconds as f64)
+}
+
+impl From<&Anomaly> for CsvAnomaly {
+    fn from(anomaly: &Anomaly) -> Self {
+        Self {
+            client: anomaly.client,
+            score: format!("{:.2}", anomaly.score),
+            amount_z: anomaly.amount_z.map(|z| format!("{:.2}", z)),
+            rate_ratio: anomaly.rate_ratio.map(|ratio| format!("{:.2}", ratio)),
+            batch_count: anomaly.batch.count(),
+            batch_mean: format!("{:.4}", anomaly.batch.mean()),
+            baseline_count: anomaly.baseline.count(),
+            baseline_mean: format!("{:.4}", anomaly.baseline.mean()),
+            baseline_std_dev: format!("{:.4}", anomaly.baseline.std_dev()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::transaction::Transaction;
+
+    use super::*;
+
+    fn deposit(engine: &mut TransactionEngine, client: ClientId, tx: u32, amount: u32) {
+        engine
+            .process(Transaction::Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(amount),
+                wallet: None,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn should_rank_clients_that_deviate_from_their_baseline() {
+        let mut engine = TransactionEngine::new();
+        engine.advance_time(0);
+        // a day of history, client 1 deposits around 10, client 2 around 100
+        for tx in 0..10 {
+            engine.advance_time(u64::from(tx) * 8640);
+            deposit(&mut engine, 1, tx, 9 + tx % 3);
+            deposit(&mut engine, 2, 100 + tx, 99 + tx % 3);
+        }
+        engine.advance_time(86_400);
+        let baseline = Baseline::capture(&engine);
+
+        // client 1 deposits much more than usual, client 2 as usual but ten times as often
+        deposit(&mut engine, 1, 20, 500);
+        for tx in 0..10 {
+            engine.advance_time(86_400 + u64::from(tx) * 864);
+            deposit(&mut engine, 2, 200 + tx, 99 + tx % 3);
+        }
+        engine.advance_time(86_400 + 8640);
+        deposit(&mut engine, 3, 300, 1_000_000);
+
+        let anomalies = baseline.anomalies(&engine, 3.0);
+        let clients = anomalies
+            .iter()
+            .map(|anomaly| anomaly.client)
+            .collect::<Vec<_>>();
+        assert_eq!(clients, [1, 2]);
+        assert!(anomalies[0].amount_z.unwrap() > 100.0);
+        let client_2 = &anomalies[1];
+        assert!(client_2.amount_z.unwrap().abs() < 1.0);
+        assert!((client_2.rate_ratio.unwrap() - 10.0).abs() < 1.0);
+        assert_eq!(client_2.batch.count(), 10);
+        assert_eq!(client_2.baseline.count(), 10);
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+
+/// Which withdrawals wait for an `approve` before they settle, read from a file with the
+/// threshold of every account and those of the accounts that differ:
+/// ```text
+/// # withdrawals above 1000 need an approval, client 7's above 50000 and client 9's never
+/// default 1000
+/// client 7 50000
+/// client 9 none
+/// ```
+/// A withdrawal of exactly the threshold settles right away
+#[derive(Clone, Debug, Default)]
+pub struct ApprovalPolicy {
+    default: Option<BigDecimal>,
+    clients: HashMap<ClientId, Option<BigDecimal>>,
+}
+
+impl ApprovalPolicy {
+    pub fn parse(policy: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (index, line) in policy.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("Invalid threshold on line {}", index + 1);
+            let threshold = |threshold: &str| match threshold {
+                "none" => Ok(None),
+                threshold => threshold.parse().map(Some).with_context(invalid),
+            };
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["default", amount] => parsed.default = threshold(amount)?,
+                ["client", client, amount] => {
+                    let client = client
+                        .parse()
+                        .with_context(|| format!("Invalid client on line {}", index + 1))?;
+                    parsed.clients.insert(client, threshold(amount)?);
+                }
+                _ => bail!(
+                    "Expected default <amount> or client <id> <amount> on line {}",
+                    index + 1
+                ),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.clients.values().all(Option::is_none)
+    }
+
+    /// The amount above which the client's withdrawals need an approval, if any
+    pub fn threshold(&self, client: ClientId) -> Option<&BigDecimal> {
+        match self.clients.get(&client) {
+            Some(threshold) => threshold.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+
+    pub fn requires_approval(&self, client: ClientId, amount: &BigDecimal) -> bool {
+        self.threshold(client)
+            .is_some_and(|threshold| amount > threshold)
+    }
+}
+
+/// A withdrawal above the account's threshold, waiting for its `approve` or `deny`. Its amount
+/// is held until then, the fee was taken when it came in
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingWithdrawal {
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: BigDecimal,
+    /// Unix timestamp (seconds)
+    pub requested_at: u64,
+    /// The wallet the amount is held in, `None` for the main one
+    pub wallet: Option<String>,
+}
+
+impl HeapSize for PendingWithdrawal {
+    fn heap_size(&self) -> usize {
+        self.amount.heap_size() + self.wallet.as_ref().map_or(0, String::capacity)
+    }
+}
+
+impl Snapshot for PendingWithdrawal {
+    fn encode(&self, encoder: &mut Encoder) {
+        Client(self.client).encode(encoder);
+        self.tx.encode(encoder);
+        self.amount.encode(encoder);
+        self.requested_at.encode(encoder);
+        self.wallet.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            client: Client::decode(decoder)?.0,
+            tx: u32::decode(decoder)?,
+            amount: BigDecimal::decode(decoder)?,
+            requested_at: u64::decode(decoder)?,
+            wallet: match decoder.version() {
+                ..=16 => None,
+                _ => Option::decode(decoder)?,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvPendingWithdrawal {
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: String,
+    pub requested_at: u64,
+    pub wallet: Option<String>,
+}
+
+impl From<&PendingWithdrawal> for CsvPendingWithdrawal {
+    fn from(pending: &PendingWithdrawal) -> Self {
+        Self {
+            client: pending.client,
+            tx: pending.tx,
+            amount: format!("{:.4}", pending.amount),
+            requested_at: pending.requested_at,
+            wallet: pending.wallet.clone(),
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::BigDecimal;
+use csv::StringRecord;
+
+use crate::amount::AmountFormat;
+use crate::json::Json;
+use crate::transaction::{RawTransaction, RowParser};
+
+/// The columns of a transactions csv, a record's fields are mapped to them by name
+const FIELDS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+
+/// Blocks bigger than this are refused rather than allocated
+const MAX_BLOCK_LENGTH: i64 = 64 << 20;
+
+/// An Avro schema, as far as decoding transactions needs it. Named types can't be referenced
+/// by name again and amounts can be strings, doubles or `bytes` with the `decimal` logical type
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Decimal { scale: i64 },
+    Enum(Vec<String>),
+    Fixed(usize),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Record(Vec<(String, Schema)>),
+}
+
+impl Schema {
+    pub fn parse(schema: &str) -> Result<Self> {
+        Self::from_json(&Json::parse(schema).context("The schema isn't valid JSON")?)
+    }
+
+    fn from_json(json: &Json) -> Result<Self> {
+        Ok(match json {
+            Json::String(name) => Self::primitive(name)?,
+            Json::Array(variants) => Schema::Union(
+                variants
+                    .iter()
+                    .map(Self::from_json)
+                    .collect::<Result<_>>()?,
+            ),
+            Json::Object(_) => {
+                let field =
+                    |name: &str| json.get(name).with_context(|| format!("Expected {}", name));
+                match field("type")?.as_str() {
+                    None => Self::from_json(field("type")?)?,
+                    Some("record" | "error") => {
+                        let fields = match field("fields")? {
+                            Json::Array(fields) => fields,
+                            _ => bail!("Expected the fields of the record to be an array"),
+                        };
+                        Schema::Record(
+                            fields
+                                .iter()
+                                .map(|field| {
+                                    let name = field
+                                        .get("name")
+                                        .and_then(Json::as_str)
+                                        .context("Expected a field name")?;
+                                    let schema = field.get("type").with_context(|| {
+                                        format!("Expected the type of {}", name)
+                                    })?;
+                                    Ok((name.to_string(), Self::from_json(schema)?))
+                                })
+                                .collect::<Result<_>>()?,
+                        )
+                    }
+                    Some("enum") => match field("symbols")? {
+                        Json::Array(symbols) => Schema::Enum(
+                            symbols
+                                .iter()
+                                .map(|symbol| symbol.as_str().map(String::from))
+                                .collect::<Option<_>>()
+                                .context("Expected the symbols to be strings")?,
+                        ),
+                        _ => bail!("Expected the symbols of the enum to be an array"),
+                    },
+                    Some("array") => Schema::Array(Box::new(Self::from_json(field("items")?)?)),
+                    Some("map") => Schema::Map(Box::new(Self::from_json(field("values")?)?)),
+                    Some("fixed") => match field("size")? {
+                        Json::Number(size) => Schema::Fixed(size.parse()?),
+                        _ => bail!("Expected the size of the fixed to be a number"),
+                    },
+                    Some("bytes")
+                        if json.get("logicalType").and_then(Json::as_str) == Some("decimal") =>
+                    {
+                        let scale = match json.get("scale") {
+                            None => 0,
+                            Some(Json::Number(scale)) => scale.parse()?,
+                            Some(_) => bail!("Expected the scale of the decimal to be a number"),
+                        };
+                        Schema::Decimal { scale }
+                    }
+                    // other logical types decode as the type underneath
+                    Some(name) => Self::primitive(name)?,
+                }
+            }
+            _ => bail!("Invalid schema {}", json),
+        })
+    }
+
+    fn primitive(name: &str) -> Result<Self> {
+        Ok(match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            other => bail!(
+                "Unsupported type {}, named types can't be referenced",
+                other
+            ),
+        })
+    }
+
+    /// The value as csv text, `None` for values that don't fit in a column
+    fn decode(&self, reader: &mut impl Read) -> Result<Option<String>> {
+        Ok(match self {
+            Schema::Null => Some(String::new()),
+            Schema::Boolean => Some((read_bytes(reader, 1)?[0] != 0).to_string()),
+            Schema::Int | Schema::Long => Some(long(reader)?.to_string()),
+            Schema::Float => {
+                let bytes = read_bytes(reader, 4)?;
+                Some(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string())
+            }
+            Schema::Double => {
+                let mut bytes = [0; 8];
+                reader.read_exact(&mut bytes)?;
+                Some(f64::from_le_bytes(bytes).to_string())
+            }
+            Schema::String => Some(String::from_utf8(length_prefixed(reader)?)?),
+            Schema::Decimal { scale } => {
+                let unscaled = BigInt::from_signed_bytes_be(&length_prefixed(reader)?);
+                Some(BigDecimal::new(unscaled, *scale).to_string())
+            }
+            Schema::Bytes => {
+                length_prefixed(reader)?;
+                None
+            }
+            Schema::Fixed(size) => {
+                read_bytes(reader, *size)?;
+                None
+            }
+            Schema::Enum(symbols) => {
+                let index = long(reader)?;
+                let symbol = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| symbols.get(index))
+                    .with_context(|| format!("Enum index {} out of range", index))?;
+                Some(symbol.to_ascii_lowercase())
+            }
+            Schema::Union(variants) => {
+                let index = long(reader)?;
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| variants.get(index))
+                    .with_context(|| format!("Union index {} out of range", index))?
+                    .decode(reader)?
+            }
+            Schema::Array(items) => {
+                while let Some(count) = block_count(reader)? {
+                    for _ in 0..count {
+                        items.decode(reader)?;
+                    }
+                }
+                None
+            }
+            Schema::Map(values) => {
+                while let Some(count) = block_count(reader)? {
+                    for _ in 0..count {
+                        length_prefixed(reader)?;
+                        values.decode(reader)?;
+                    }
+                }
+                None
+            }
+            Schema::Record(fields) => {
+                for (_, field) in fields {
+                    field.decode(reader)?;
+                }
+                None
+            }
+        })
+    }
+
+    /// A record of transaction fields as a transactions csv record, other fields are skipped
+    fn decode_transaction(&self, reader: &mut impl Read) -> Result<StringRecord> {
+        let fields = match self {
+            Schema::Record(fields) => fields,
+            _ => bail!("Expected the schema of a transaction to be a record"),
+        };
+        let mut values = HashMap::new();
+        for (name, schema) in fields {
+            let value = schema
+                .decode(reader)
+                .with_context(|| format!("Failed to decode {}", name))?;
+            if let Some(value) = value {
+                values.insert(name.as_str(), value);
+            }
+        }
+        Ok(FIELDS
+            .iter()
+            .map(|field| values.get(field).map_or("", String::as_str))
+            .collect())
+    }
+}
+
+/// Reads an Avro object container file, a block of records at a time. Only uncompressed
+/// files can be read: there's no deflate, snappy or zstd implementation in the build
+pub fn read_container<R: Read>(
+    reader: R,
+    format: AmountFormat,
+) -> impl Iterator<Item = Result<RawTransaction>> {
+    let parser = RowParser::new(StringRecord::from(FIELDS.to_vec()), format.without_locale());
+    let mut reader = BufReader::new(reader);
+    let mut container: Option<Result<Container>> = None;
+    let mut block: Vec<u8> = vec![];
+    let mut position = 0;
+    let mut remaining = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let result = (|| {
+            let container =
+                match container.get_or_insert_with(|| Container::read_header(&mut reader)) {
+                    Ok(container) => container,
+                    Err(e) => bail!("Invalid Avro header: {:#}", e),
+                };
+            while remaining == 0 {
+                match container.next_block(&mut reader)? {
+                    None => return Ok(None),
+                    Some((count, data)) => {
+                        (remaining, block, position) = (count, data, 0);
+                    }
+                }
+            }
+            remaining -= 1;
+            let mut slice = &block[position..];
+            let before = slice.len();
+            let record = container.schema.decode_transaction(&mut slice)?;
+            position += before - slice.len();
+            parser.parse(record).map(Some)
+        })();
+        match result {
+            Ok(None) => None,
+            Ok(Some(raw)) => Some(Ok(raw)),
+            Err(e) => {
+                done = true;
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+struct Container {
+    schema: Schema,
+    sync: [u8; 16],
+}
+
+impl Container {
+    fn read_header(reader: &mut impl Read) -> Result<Self> {
+        if read_bytes(reader, 4)? != b"Obj\x01" {
+            bail!("Not an Avro object container file");
+        }
+        let mut metadata = HashMap::new();
+        while let Some(count) = block_count(reader)? {
+            for _ in 0..count {
+                let key = String::from_utf8(length_prefixed(reader)?)?;
+                metadata.insert(key, length_prefixed(reader)?);
+            }
+        }
+        match metadata.get("avro.codec").map(Vec::as_slice) {
+            None | Some(b"null") => {}
+            Some(codec) => bail!(
+                "The {} codec isn't supported in this build, only uncompressed files",
+                String::from_utf8_lossy(codec)
+            ),
+        }
+        let schema = metadata
+            .get("avro.schema")
+            .context("The file has no schema")?;
+        let schema = Schema::parse(std::str::from_utf8(schema)?)?;
+        let mut sync = [0; 16];
+        reader.read_exact(&mut sync)?;
+        Ok(Self { schema, sync })
+    }
+
+    /// The record count and data of the next block, `None` at the end of the file
+    fn next_block(&self, reader: &mut impl Read) -> Result<Option<(i64, Vec<u8>)>> {
+        let count = match long_or_end(reader)? {
+            None => return Ok(None),
+            Some(count) => count,
+        };
+        let length = long(reader)?;
+        if !(0..=MAX_BLOCK_LENGTH).contains(&length) || count < 0 {
+            bail!("Invalid block of {} records in {} bytes", count, length);
+        }
+        let data = read_bytes(reader, usize::try_from(length)?)?;
+        if read_bytes(reader, 16)? != self.sync {
+            bail!("The block doesn't end in the file's sync marker");
+        }
+        Ok(Some((count, data)))
+    }
+}
+
+/// Finds the writer's schema of a Confluent schema registry id
+pub trait SchemaRegistry {
+    fn schema(&self, id: u32) -> Result<&Schema>;
+}
+
+/// Schemas registered up front, e.g. fetched from the registry at startup
+#[derive(Debug, Default)]
+pub struct StaticRegistry {
+    schemas: HashMap<u32, Schema>,
+}
+
+impl StaticRegistry {
+    pub fn insert(&mut self, id: u32, schema: Schema) {
+        self.schemas.insert(id, schema);
+    }
+}
+
+impl SchemaRegistry for StaticRegistry {
+    fn schema(&self, id: u32) -> Result<&Schema> {
+        self.schemas
+            .get(&id)
+            .with_context(|| format!("Unknown schema id {}", id))
+    }
+}
+
+/// Decodes a message in the Confluent wire format, a zero byte, the big endian id of
+/// the writer's schema in the registry and the Avro encoded record, as Kafka producers
+/// using the registry send them
+pub fn decode_confluent(
+    message: &[u8],
+    registry: &dyn SchemaRegistry,
+    format: AmountFormat,
+) -> Result<RawTransaction> {
+    let (id, mut record) = match message {
+        [0, a, b, c, d, record @ ..] => (u32::from_be_bytes([*a, *b, *c, *d]), record),
+        _ => bail!("Not a schema registry message, expected a zero magic byte"),
+    };
+    let record = registry.schema(id)?.decode_transaction(&mut record)?;
+    RowParser::new(StringRecord::from(FIELDS.to_vec()), format.without_locale()).parse(record)
+}
+
+fn read_bytes(reader: &mut impl Read, length: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; length];
+    reader
+        .read_exact(&mut bytes)
+        .context("The input ended in the middle of a value")?;
+    Ok(bytes)
+}
+
+/// A zigzag encoded varint, `None` at the end of the input
+fn long_or_end(reader: &mut impl Read) -> Result<Option<i64>> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            bail!("The input ended in the middle of a number");
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            #[allow(clippy::cast_possible_wrap)]
+            return Ok(Some((value >> 1) as i64 ^ -((value & 1) as i64)));
+        }
+    }
+    bail!("Number longer than 10 bytes")
+}
+
+fn long(reader: &mut impl Read) -> Result<i64> {
+    long_or_end(reader)?.context("The input ended in the middle of a value")
+}
+
+fn length_prefixed(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let length = long(reader)?;
+    if !(0..=MAX_BLOCK_LENGTH).contains(&length) {
+        bail!("Invalid length {}", length);
+    }
+    read_bytes(reader, usize::try_from(length)?)
+}
+
+/// Arrays and maps come in blocks, `None` after the last one
+fn block_count(reader: &mut impl Read) -> Result<Option<u64>> {
+    match long(reader)? {
+        0 => Ok(None),
+        // a negative count is followed by the block's size in bytes
+        count if count < 0 => {
+            long(reader)?;
+            Ok(Some(count.unsigned_abs()))
+        }
+        count => Ok(Some(count.unsigned_abs())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionType;
+
+    const SCHEMA: &str = r#"{"type": "record", "name": "Transaction", "fields": [
+        {"name": "type", "type": {"type": "enum", "name": "Type", "symbols": ["DEPOSIT", "WITHDRAWAL", "DISPUTE", "RESOLVE", "CHARGEBACK"]}},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", {"type": "bytes", "logicalType": "decimal", "precision": 20, "scale": 4}]},
+        {"name": "source", "type": {"type": "map", "values": "string"}}
+    ]}"#;
+
+    fn long_bytes(value: i64, out: &mut Vec<u8>) {
+        #[allow(clippy::cast_sign_loss)]
+        let mut value = ((value << 1) ^ (value >> 63)) as u64;
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn records() -> Vec<u8> {
+        let mut records = vec![];
+        // deposit of 1.5000 by client 1, with a source map entry
+        for value in [0, 1, 1, 1] {
+            long_bytes(value, &mut records);
+        }
+        long_bytes(2, &mut records);
+        records.extend(15000_i32.to_be_bytes()[2..].iter());
+        long_bytes(1, &mut records);
+        long_bytes(1, &mut records);
+        records.push(b'k');
+        long_bytes(1, &mut records);
+        records.push(b'v');
+        long_bytes(0, &mut records);
+        // dispute of it, no amount and no source
+        for value in [2, 1, 1, 0, 0] {
+            long_bytes(value, &mut records);
+        }
+        records
+    }
+
+    #[test]
+    fn should_read_an_object_container_file() {
+        let mut file = b"Obj\x01".to_vec();
+        long_bytes(1, &mut file);
+        long_bytes(11, &mut file);
+        file.extend(b"avro.schema");
+        long_bytes(SCHEMA.len() as i64, &mut file);
+        file.extend(SCHEMA.as_bytes());
+        long_bytes(0, &mut file);
+        let sync = [7; 16];
+        file.extend(sync);
+        let records = records();
+        long_bytes(2, &mut file);
+        long_bytes(records.len() as i64, &mut file);
+        file.extend(&records);
+        file.extend(sync);
+
+        let transactions = read_container(&file[..], AmountFormat::default())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].transaction_type(), TransactionType::Deposit);
+        assert_eq!(
+            transactions[0]
+                .amount()
+                .map(|amount| amount.normalized().to_string()),
+            Some("1.5".to_string())
+        );
+        assert_eq!(transactions[1].transaction_type(), TransactionType::Dispute);
+        assert_eq!(transactions[1].amount(), None);
+
+        let mut corrupt = file.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] = 0;
+        assert!(read_container(&corrupt[..], AmountFormat::default())
+            .any(|transaction| transaction.is_err()));
+    }
+
+    #[test]
+    fn should_decode_schema_registry_messages() {
+        let mut registry = StaticRegistry::default();
+        registry.insert(42, Schema::parse(SCHEMA).unwrap());
+        let mut message = vec![0, 0, 0, 0, 42];
+        message.extend(records());
+        let raw = decode_confluent(&message, &registry, AmountFormat::default()).unwrap();
+        assert_eq!(raw.client(), 1);
+        assert!(decode_confluent(&[0, 0, 0, 0, 1], &registry, AmountFormat::default()).is_err());
+    }
+}
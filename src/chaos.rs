@@ -0,0 +1,220 @@
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+/// The places faults can be injected at, when built with the test-only `chaos` feature.
+/// Without it `inject` does nothing, so the seams cost nothing in a real build
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seam {
+    /// The event log writing a change or a snapshot. An error tears the record in two
+    Store,
+    /// The server taking a request off a connection. An error is answered 503, a dropped
+    /// request is never applied or answered
+    Source,
+    /// An HTTP request to another service, e.g. a relayed event. An error fails before the
+    /// request is sent, a dropped one is sent and its reply is lost
+    Sink,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    Error,
+    /// The message goes on, but only after this long
+    Delay(Duration),
+    Drop,
+}
+
+/// What a seam does with its message after the faults
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Deliver,
+    Drop,
+}
+
+/// Faults and how likely they are, with every message at their seam:
+/// ```text
+/// store:error:0.1,source:delay=200ms:0.5,sink:drop:0.2
+/// ```
+/// Writes can't be dropped, a store that drops writes isn't one a log could survive
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Faults(Vec<(Seam, Fault, f64)>);
+
+impl FromStr for Faults {
+    type Err = anyhow::Error;
+
+    fn from_str(faults: &str) -> Result<Self> {
+        let mut rules = vec![];
+        for rule in faults.split(',').filter(|rule| !rule.trim().is_empty()) {
+            let mut parts = rule.trim().split(':');
+            let (Some(seam), Some(fault), Some(probability), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                bail!("Expected seam:fault:probability, got {}", rule);
+            };
+            let seam = match seam {
+                "store" => Seam::Store,
+                "source" => Seam::Source,
+                "sink" => Seam::Sink,
+                other => bail!("Unknown seam {}, expected store, source or sink", other),
+            };
+            let fault = match fault.split_once('=') {
+                None if fault == "error" => Fault::Error,
+                None if fault == "drop" => Fault::Drop,
+                Some(("delay", delay)) => Fault::Delay(parse_delay(delay)?),
+                _ => bail!(
+                    "Unknown fault {}, expected error, drop or delay=<ms>ms",
+                    fault
+                ),
+            };
+            if (seam, fault) == (Seam::Store, Fault::Drop) {
+                bail!("The store can't drop writes, only fail or delay them");
+            }
+            let probability = probability
+                .parse::<f64>()
+                .ok()
+                .filter(|p| (0.0..=1.0).contains(p))
+                .with_context(|| format!("Invalid probability {}", probability))?;
+            rules.push((seam, fault, probability));
+        }
+        Ok(Self(rules))
+    }
+}
+
+/// `200ms` or `2s`
+fn parse_delay(delay: &str) -> Result<Duration> {
+    let parsed = match delay.strip_suffix("ms") {
+        Some(millis) => millis.parse().map(Duration::from_millis),
+        None => match delay.strip_suffix('s') {
+            Some(seconds) => seconds.parse().map(Duration::from_secs),
+            None => bail!("Invalid delay {}, expected e.g. 200ms or 2s", delay),
+        },
+    };
+    parsed.with_context(|| format!("Invalid delay {}", delay))
+}
+
+/// Called at a seam before its message goes on. Fails with an injected error, sleeps for an
+/// injected delay, and says whether the message was dropped
+#[cfg(not(feature = "chaos"))]
+#[inline]
+pub fn inject(_seam: Seam) -> io::Result<Verdict> {
+    Ok(Verdict::Deliver)
+}
+
+/// Called at a seam before its message goes on. Fails with an injected error, sleeps for an
+/// injected delay, and says whether the message was dropped
+#[cfg(feature = "chaos")]
+pub fn inject(seam: Seam) -> io::Result<Verdict> {
+    let (delay, verdict) = enabled::roll(seam);
+    if !delay.is_zero() {
+        std::thread::sleep(delay);
+    }
+    match verdict {
+        Some(Fault::Error) => Err(io::Error::other(format!("Injected fault at {:?}", seam))),
+        Some(Fault::Drop) => Ok(Verdict::Drop),
+        _ => Ok(Verdict::Deliver),
+    }
+}
+
+#[cfg(feature = "chaos")]
+pub use enabled::{injected, install};
+
+#[cfg(feature = "chaos")]
+mod enabled {
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Duration;
+
+    use super::{Fault, Faults, Seam};
+    use crate::generator::splitmix64;
+
+    struct Chaos {
+        faults: Faults,
+        state: u64,
+        /// By seam
+        injected: [u64; 3],
+    }
+
+    /// The faults of `PLAYGROUND_CHAOS` until some are installed, seeded with
+    /// `PLAYGROUND_CHAOS_SEED`, so the binary can be tested from the outside
+    fn chaos() -> &'static Mutex<Chaos> {
+        static CHAOS: OnceLock<Mutex<Chaos>> = OnceLock::new();
+        CHAOS.get_or_init(|| {
+            let faults = match std::env::var("PLAYGROUND_CHAOS") {
+                Ok(faults) => faults.parse().expect("Invalid PLAYGROUND_CHAOS"),
+                Err(_) => Faults::default(),
+            };
+            let seed = std::env::var("PLAYGROUND_CHAOS_SEED")
+                .ok()
+                .and_then(|seed| seed.parse().ok())
+                .unwrap_or(0);
+            Mutex::new(Chaos {
+                faults,
+                state: seed,
+                injected: [0; 3],
+            })
+        })
+    }
+
+    /// Replaces the faults of the whole process, `Faults::default()` stops injecting them
+    pub fn install(faults: Faults, seed: u64) {
+        let mut chaos = chaos().lock().unwrap_or_else(|e| e.into_inner());
+        chaos.faults = faults;
+        chaos.state = seed;
+        chaos.injected = [0; 3];
+    }
+
+    /// How many faults were injected at the seam since they were installed
+    pub fn injected(seam: Seam) -> u64 {
+        chaos().lock().unwrap_or_else(|e| e.into_inner()).injected[seam as usize]
+    }
+
+    /// The delays of the faults that hit, and the error or drop if one did
+    pub(super) fn roll(seam: Seam) -> (Duration, Option<Fault>) {
+        let mut chaos = chaos().lock().unwrap_or_else(|e| e.into_inner());
+        let mut delay = Duration::ZERO;
+        let mut verdict = None;
+        for index in 0..chaos.faults.0.len() {
+            let (at, fault, probability) = chaos.faults.0[index];
+            if at != seam {
+                continue;
+            }
+            let roll = (splitmix64(&mut chaos.state) >> 11) as f64 / (1u64 << 53) as f64;
+            if roll >= probability {
+                continue;
+            }
+            match fault {
+                Fault::Delay(by) => delay += by,
+                fault => verdict = verdict.or(Some(fault)),
+            }
+            chaos.injected[seam as usize] += 1;
+        }
+        (delay, verdict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_faults() {
+        let faults = "store:error:0.1, source:delay=200ms:0.5,sink:drop:1"
+            .parse::<Faults>()
+            .unwrap();
+        assert_eq!(
+            faults,
+            Faults(vec![
+                (Seam::Store, Fault::Error, 0.1),
+                (Seam::Source, Fault::Delay(Duration::from_millis(200)), 0.5),
+                (Seam::Sink, Fault::Drop, 1.0),
+            ])
+        );
+        assert_eq!("".parse::<Faults>().unwrap(), Faults::default());
+        assert!("store:drop:0.1".parse::<Faults>().is_err());
+        assert!("disk:error:0.1".parse::<Faults>().is_err());
+        assert!("sink:error:2".parse::<Faults>().is_err());
+        assert!("sink:delay=soon:0.1".parse::<Faults>().is_err());
+        assert!("sink:error".parse::<Faults>().is_err());
+    }
+}
@@ -0,0 +1,1146 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+
+use playground::alerts::AlertRules;
+use playground::amount::{AmountFormat, ScaleLimit, ScalePolicy};
+use playground::approval::ApprovalPolicy;
+use playground::config::{EngineConfig, OpeningPolicy, Pruning};
+use playground::dedup::DedupWindow;
+use playground::fees::FeeSchedule;
+use playground::filter::AccountFilter;
+use playground::flows::FlowFormat;
+use playground::generator::Mix;
+use playground::history::HistoryFormat;
+use playground::hold::HoldReason;
+use playground::joint::JointAccounts;
+use playground::locale::{Locale, Message};
+use playground::lock::ReviewLockPolicy;
+use playground::pseudonym::Pseudonymizer;
+use playground::reserve::ReserveRequirements;
+use playground::rules::RuleSet;
+use playground::screening::Blocklist;
+use playground::transaction::ClientId;
+use playground::transaction_engine::OnConflict;
+use playground::transform::Transform;
+
+use crate::input::InputFormat;
+
+pub enum Command {
+    /// Process the transactions and output the resulting accounts
+    Process,
+    /// Process the transactions on a virtual clock, one `step` of seconds apart from `start`
+    /// on, then fast-forward `then` seconds and output the accounts
+    Simulate { start: u64, step: u64, then: u64 },
+    /// Process the transactions and output a payout file
+    Settle { template: Option<String> },
+    /// Process the transactions and output them as a standard banking format
+    Export(ExportOptions),
+    /// Process the transactions and output a single client's history
+    History {
+        client: ClientId,
+        format: HistoryFormat,
+    },
+    /// Process the transactions and output the dispute cases
+    Disputes(DisputesCommand),
+    /// Process the transactions and output the escrows between clients
+    Escrows,
+    /// Process the transactions and output the graph of what the clients paid each other
+    Flows { format: FlowFormat },
+    /// Process the transactions and output totals per account tag, per client group
+    /// from a `client,group` csv, or per source for reconciling the feeds, or the
+    /// balances of the accounts' pockets or wallets, or the accounts with a negative balance, or what
+    /// every client's enriched transactions came to per category, or the withdrawals waiting
+    /// for an approval, or what every holder of a joint account did to it, or the promotional
+    /// credits that are left, or how every client's disputes turned out
+    Report {
+        groups: Option<String>,
+        by_source: bool,
+        by_category: bool,
+        by_holder: bool,
+        pockets: bool,
+        wallets: bool,
+        negative: bool,
+        pending_withdrawals: bool,
+        promo_credits: bool,
+        dispute_stats: bool,
+    },
+    /// Combine the snapshots of engines that processed different transactions into one
+    MergeSnapshots {
+        snapshots: Vec<String>,
+        on_conflict: OnConflict,
+    },
+    /// Compare two snapshots, e.g. of the start and end of a day, account by account
+    DiffSnapshots { before: String, after: String },
+    /// The input processed under two settings files, to see which accounts a policy change
+    /// would end differently
+    CompareConfigs { before: String, after: String },
+    /// Check that the disputes, resolves and chargebacks of the files, read in order, refer
+    /// to earlier deposits and withdrawals of their clients, without processing them
+    CheckRefs { files: Vec<String> },
+    /// Look up accounts or transactions in a snapshot, which is never changed
+    Query { snapshot: String, query: Query },
+    /// Run the inputs in a directory and compare the outputs with the expected ones
+    TestFixtures { dir: String, update: bool },
+    /// Encrypt a state file with the current key, e.g. after rotating it
+    ReEncrypt { state: String },
+    /// Process the transactions, if any, then keep serving the HTTP API
+    Serve(ServeOptions),
+    /// Manage a running server through its HTTP API
+    Admin { url: String, command: AdminCommand },
+    /// Keep a snapshot in sync with a running server, every so many seconds or once
+    Replicate {
+        url: String,
+        state: String,
+        every: Option<u64>,
+    },
+    /// Publish the changes a server logged in its event log, every so many seconds or once
+    Relay {
+        dir: String,
+        url: String,
+        /// Where the sequence number of the last change published is kept
+        cursor: String,
+        every: Option<u64>,
+    },
+    /// Post generated transactions to a running server and measure how it copes
+    Loadtest(LoadtestOptions),
+}
+
+impl Command {
+    /// As it's given on the command line
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Command::Process => "process",
+            Command::Simulate { .. } => "simulate",
+            Command::Settle { .. } => "settle",
+            Command::Export(_) => "export",
+            Command::History { .. } => "history",
+            Command::Disputes(DisputesCommand::List) => "disputes list",
+            Command::Disputes(DisputesCommand::Show { .. }) => "disputes show",
+            Command::Escrows => "escrows",
+            Command::Flows { .. } => "flows",
+            Command::Report { .. } => "report",
+            Command::MergeSnapshots { .. } => "merge-snapshots",
+            Command::DiffSnapshots { .. } => "diff-snapshots",
+            Command::CompareConfigs { .. } => "compare-configs",
+            Command::CheckRefs { .. } => "check-refs",
+            Command::Query { .. } => "query",
+            Command::TestFixtures { .. } => "test-fixtures",
+            Command::ReEncrypt { .. } => "re-encrypt",
+            Command::Serve(_) => "serve",
+            Command::Admin { .. } => "admin",
+            Command::Replicate { .. } => "replicate",
+            Command::Relay { .. } => "relay",
+            Command::Loadtest(_) => "loadtest",
+        }
+    }
+}
+
+pub enum DisputesCommand {
+    List,
+    Show { client: ClientId, tx: u32 },
+}
+
+pub enum Query {
+    /// Every account, or those matching the `--expr`, as csv
+    Accounts(Option<AccountFilter>),
+    Account(ClientId),
+    /// The deposit or withdrawal with the tx id
+    Transaction(u32),
+    /// The account's ledger
+    History(ClientId),
+}
+
+pub enum AdminCommand {
+    /// Every account, as csv
+    Accounts,
+    /// Locks an account for a review
+    Lock {
+        client: ClientId,
+        reason: Option<String>,
+    },
+    Unlock(ClientId),
+    /// Closes an account for good
+    Close(ClientId),
+    /// Corrects the available balance, the amount can be negative
+    Adjust {
+        client: ClientId,
+        amount: String,
+    },
+    Stats,
+    /// The notes of an account
+    Notes(ClientId),
+    /// Adds a note to an account
+    Note {
+        client: ClientId,
+        text: String,
+        author: Option<String>,
+    },
+    /// The withdrawals waiting for an approval
+    Withdrawals,
+    /// Settles a withdrawal that waits for an approval
+    Approve {
+        client: ClientId,
+        tx: u32,
+    },
+    /// Drops a withdrawal that waits for an approval, its amount is available again
+    Deny {
+        client: ClientId,
+        tx: u32,
+    },
+    /// The holds and releases of operators on an account
+    Holds(ClientId),
+    /// Moves an amount from available to held, or back with `release`
+    Hold {
+        client: ClientId,
+        amount: String,
+        reason: String,
+        author: Option<String>,
+        release: bool,
+    },
+}
+
+/// How `--output-metadata` describes the run an output came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMetadata {
+    /// `#` lines at the top of the csv outputs
+    Comments,
+    /// A `<output>.meta.json` next to every output
+    Sidecar,
+}
+
+pub enum ExportFormat {
+    /// ISO 20022 bank to customer statement of every account
+    Camt053,
+    /// ISO 20022 credit transfer initiation of the settlement payouts
+    Pain001 { debtor_account: String },
+}
+
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub currency: String,
+}
+
+pub struct ServeOptions {
+    pub listen: String,
+    pub dedup: Option<DedupWindow>,
+    /// Where the rows that couldn't be parsed or were rejected are written to
+    pub dead_letter: Option<String>,
+    /// Where alerts are posted to as they fire
+    pub alert_webhook: Option<String>,
+    /// How the amounts of posted transactions are written
+    pub amount_format: AmountFormat,
+    /// Settings file with tunables that are reloaded when it changes
+    pub config: Option<String>,
+    /// Only serve while holding this leader lock, stand by until then
+    pub ha_lock: Option<String>,
+    /// Where the state is written after every change, and continued from when taking over
+    pub ha_state: Option<String>,
+    /// Directory the changes are logged to, and recovered from on a restart
+    pub event_log: Option<String>,
+    /// Seconds between compactions of the event log
+    pub compact_every: u64,
+    /// How many snapshots the event log keeps
+    pub event_log_retention: usize,
+    /// Where the responses to requests with an `Idempotency-Key` are kept
+    pub idempotency_keys: Option<String>,
+    /// How long idempotency keys are kept for, in seconds
+    pub idempotency_retention: u64,
+    /// OpenTelemetry collector the spans of the requests are sent to
+    pub otlp_endpoint: Option<String>,
+    /// Requests with a longer body are answered with 413 without reading it
+    pub max_body_bytes: usize,
+    /// How long reading a request or writing its response may take, an idle connection
+    /// would otherwise keep every other client waiting
+    pub request_timeout: Duration,
+}
+
+pub struct LoadtestOptions {
+    pub url: String,
+    /// Transactions a second, across all connections
+    pub rate: u64,
+    pub duration: Duration,
+    pub mix: Mix,
+    /// Transactions posted per request
+    pub batch: usize,
+    pub connections: usize,
+    pub clients: u64,
+    pub seed: u64,
+}
+
+/// Hacky argument parsing, for a real CLI I would've used a crate like clap
+pub struct Options {
+    pub command: Command,
+    /// Only optional when serving
+    pub input: Option<String>,
+    /// Snapshot to continue from instead of starting with no accounts
+    pub snapshot: Option<String>,
+    /// Accounts csv of a previous run to continue from, when there's no snapshot of it
+    pub resume_from_accounts: Option<String>,
+    /// Process the input with this many engines in parallel, clients are spread over them
+    pub partitions: Option<usize>,
+    /// Where each partition's accounts go as NDJSON as soon as it's done, before the merge
+    pub shard_accounts_out: Option<String>,
+    /// Where to write the JSON report of the run
+    pub report_out: Option<String>,
+    /// Where to write the clients whose batch deviates from their baseline, the snapshot's
+    /// state, most anomalous first
+    pub anomalies_out: Option<String>,
+    /// How many standard deviations or times the usual rate is anomalous
+    pub anomaly_threshold: f64,
+    /// Where to write how long the stages of the run took, as folded stacks
+    pub profile: Option<String>,
+    /// Where to write a snapshot of the engine once the input is processed
+    pub snapshot_out: Option<String>,
+    /// Encrypt the snapshots written, with the key from the environment
+    pub encrypt_state: bool,
+    /// Replaces client ids in the csv outputs and the run report
+    pub pseudonymizer: Option<Pseudonymizer>,
+    /// The input's clients have ids of their own, interned to the engine's
+    pub external_client_ids: bool,
+    /// Where the main output goes, defaults to stdout
+    pub outputs: Vec<String>,
+    /// Where rejected transactions go, if anywhere
+    pub rejects: Vec<String>,
+    pub engine: EngineConfig,
+    /// `client,tag` csv of account tags
+    pub tags: Option<String>,
+    /// Only output accounts with this tag
+    pub tag: Option<String>,
+    /// Only output the accounts the input changed
+    pub changed_only: bool,
+    /// Output the accounts with the extra columns of `CsvExtendedAccount`
+    pub extended: bool,
+    /// Describe the run in or next to the outputs
+    pub output_metadata: Option<OutputMetadata>,
+    /// How the amounts of the input are written
+    pub amount_format: AmountFormat,
+    /// Otherwise it goes by the input's extension
+    pub input_format: Option<InputFormat>,
+    /// Report how much the engine holds and how much memory the process uses
+    pub memory_stats: bool,
+    /// Shared libraries with transaction handlers or risk scorers to load into the engine
+    pub plugins: Vec<String>,
+    /// What the ledger entries of the input's transactions are tagged with
+    pub source: Option<String>,
+    /// Rows of the input after which the run fails, instead of reading on
+    pub max_rows: Option<usize>,
+    /// Fail the run without applying anything if any row of the input is rejected
+    pub atomic: bool,
+    /// Where a snapshot is written when the process gets SIGUSR1 while processing
+    pub signal_snapshot: Option<String>,
+    /// OpenTelemetry collector the run's spans and metrics are sent to, a server's spans
+    /// go there too
+    pub otlp_endpoint: Option<String>,
+    /// The language of the log and of statements, not of the machine readable outputs
+    pub locale: Locale,
+    /// Where every transaction the blocklist stops is recorded
+    pub audit_log: Option<String>,
+    /// A lookup file or service the deposits and withdrawals are enriched from
+    pub enrich: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut args = args.skip(1).peekable();
+        let mut command = match args.peek().map(String::as_str) {
+            Some(
+                command @ ("simulate" | "settle" | "export" | "history" | "disputes" | "escrows"
+                | "flows" | "report" | "merge-snapshots" | "diff-snapshots"
+                | "compare-configs" | "check-refs" | "query" | "re-encrypt" | "serve"
+                | "test-fixtures" | "admin" | "replicate" | "relay" | "loadtest"),
+            ) => command.to_string(),
+            _ => String::new(),
+        };
+        // process is the default, but can be spelled out
+        if !command.is_empty() || args.peek().is_some_and(|arg| arg == "process") {
+            args.next();
+        }
+        if command == "disputes" {
+            match args.next().as_deref() {
+                Some("list") => command.push_str(" list"),
+                Some("show") => command.push_str(" show"),
+                _ => bail!("Expected disputes list or disputes show"),
+            }
+        }
+        let mut positionals = vec![];
+        let mut outputs = vec![];
+        let mut rejects = vec![];
+        let mut engine = EngineConfig::default();
+        let mut template = None;
+        let mut format = None;
+        let mut currency = None;
+        let mut debtor_account = None;
+        let mut listen = None;
+        let mut url = None;
+        let mut author = None;
+        let mut every = None;
+        let mut cursor = None;
+        let mut config = None;
+        let mut ha_lock = None;
+        let mut ha_state = None;
+        let mut event_log = None;
+        let mut compact_every = 3600;
+        let mut event_log_retention = 2;
+        let mut idempotency_keys = None;
+        let mut idempotency_retention_hours = 24;
+        let mut max_body_bytes = 1024 * 1024;
+        let mut request_timeout_secs = 30;
+        let mut dedup = None;
+        let mut dead_letter = None;
+        let mut alert_webhook = None;
+        let mut tags = None;
+        let mut tag = None;
+        let mut groups = None;
+        let mut by_source = false;
+        let mut by_category = false;
+        let mut by_holder = false;
+        let mut pending_withdrawals = false;
+        let mut promo_credits = false;
+        let mut dispute_stats = false;
+        let mut pockets = false;
+        let mut wallets = false;
+        let mut negative = false;
+        let mut source = None;
+        let mut plugins = vec![];
+        let mut decimal_separator = '.';
+        let mut thousands_separator = None;
+        let mut scale = None;
+        let mut input_format = None;
+        let mut scale_policy = None;
+        let mut snapshot = None;
+        let mut snapshot_out = None;
+        let mut resume_from_accounts = None;
+        let mut partitions = None;
+        let mut shard_accounts_out = None;
+        let mut profile = None;
+        let mut report_out = None;
+        let mut anomalies_out = None;
+        let mut anomaly_threshold = 3.0;
+        let mut on_conflict = None;
+        let mut encrypt_state = false;
+        let mut memory_stats = false;
+        let mut max_rows = None;
+        let mut atomic = false;
+        let mut signal_snapshot = None;
+        let mut otlp_endpoint = None;
+        let mut audit_log = None;
+        let mut enrich = None;
+        let mut locale = Locale::default();
+        let mut update = false;
+        let mut pseudonymizer = None;
+        let mut external_client_ids = false;
+        let mut changed_only = false;
+        let mut extended = false;
+        let mut output_metadata = None;
+        let mut expr = None;
+        let mut rate = None;
+        let mut duration = None;
+        let mut mix = None;
+        let mut batch = 100;
+        let mut connections = 4;
+        let mut clients = 10_000;
+        let mut seed = 0;
+        let mut start = 0;
+        let mut step = 60;
+        let mut then = 0;
+
+        while let Some(arg) = args.next() {
+            match (command.as_str(), arg.as_str()) {
+                (_, "--out" | "-o") => outputs.push(value(&arg, args.next())?),
+                (_, "--rejects-out") => rejects.push(value(&arg, args.next())?),
+                (_, "--risk-threshold") => {
+                    engine.risk_threshold = Some(parse_value(&arg, args.next())?);
+                }
+                (_, "--rules") => {
+                    let path = value(&arg, args.next())?;
+                    let rules = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read rules file {}", path))?;
+                    engine.rules = RuleSet::parse(&rules)?;
+                }
+                (_, "--blocklist") => {
+                    let path = value(&arg, args.next())?;
+                    let blocklist = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read blocklist {}", path))?;
+                    engine.blocklist = Blocklist::parse(&blocklist)?;
+                }
+                (_, "--audit-log") => audit_log = Some(value(&arg, args.next())?),
+                (_, "--enrich") => enrich = Some(value(&arg, args.next())?),
+                (_, "--fees") => {
+                    let path = value(&arg, args.next())?;
+                    let fees = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read fee schedule {}", path))?;
+                    engine.fees = FeeSchedule::parse(&fees)?;
+                }
+                (_, "--joint-accounts") => {
+                    let path = value(&arg, args.next())?;
+                    let file = std::fs::File::open(&path)
+                        .with_context(|| format!("Could not read joint accounts {}", path))?;
+                    engine.joint_accounts = JointAccounts::read_csv(file)
+                        .with_context(|| format!("Invalid joint accounts in {}", path))?;
+                }
+                (_, "--approvals") => {
+                    let path = value(&arg, args.next())?;
+                    let approvals = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read approval policy {}", path))?;
+                    engine.approvals = ApprovalPolicy::parse(&approvals)?;
+                }
+                (_, "--reserves") => {
+                    let path = value(&arg, args.next())?;
+                    let reserves = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read reserves {}", path))?;
+                    engine.reserves = ReserveRequirements::parse(&reserves)?;
+                }
+                (_, "--transform") => {
+                    let path = value(&arg, args.next())?;
+                    let transform = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read transform file {}", path))?;
+                    engine.transform = Transform::parse(&transform)?;
+                }
+                (_, "--alerts") => {
+                    let path = value(&arg, args.next())?;
+                    let alerts = std::fs::read_to_string(&path)
+                        .with_context(|| format!("Could not read alerts file {}", path))?;
+                    engine.alerts = AlertRules::parse(&alerts)?;
+                }
+                (_, "--test-clients") => {
+                    for client in value(&arg, args.next())?.split(',') {
+                        engine.test_clients.insert(
+                            client
+                                .trim()
+                                .parse()
+                                .with_context(|| format!("Invalid test client {}", client))?,
+                        );
+                    }
+                }
+                (_, "--test-tag") => engine.test_tag = Some(value(&arg, args.next())?),
+                (_, "--shard-accounts-out") => shard_accounts_out = Some(value(&arg, args.next())?),
+                (_, "--partition-by-client") => {
+                    partitions = match parse_value(&arg, args.next())? {
+                        0 => bail!("--partition-by-client needs at least one partition"),
+                        partitions => Some(partitions),
+                    };
+                }
+                (_, "--report-out") => report_out = Some(value(&arg, args.next())?),
+                (_, "--anomalies-out") => anomalies_out = Some(value(&arg, args.next())?),
+                (_, "--anomaly-threshold") => anomaly_threshold = parse_value(&arg, args.next())?,
+                (_, "--profile") => profile = Some(value(&arg, args.next())?),
+                (_, "--snapshot") => snapshot = Some(value(&arg, args.next())?),
+                (_, "--resume-from-accounts") => {
+                    resume_from_accounts = Some(value(&arg, args.next())?);
+                }
+                (_, "--snapshot-out") => snapshot_out = Some(value(&arg, args.next())?),
+                (_, "--encrypt-state") => encrypt_state = true,
+                (_, "--check-invariants") => engine.check_invariants = true,
+                (_, "--memory-stats") => memory_stats = true,
+                (_, "--prune-history") => {
+                    engine.pruning.get_or_insert_with(Pruning::default);
+                }
+                (_, "--prune-after-days") => {
+                    let days: u64 = parse_value(&arg, args.next())?;
+                    engine.pruning = Some(Pruning {
+                        after: Some(days.saturating_mul(24 * 60 * 60)),
+                    });
+                }
+                (_, "--opening-conflict") => {
+                    engine.opening_policy = match value(&arg, args.next())?.as_str() {
+                        "error" => OpeningPolicy::Error,
+                        "prefer-file" => OpeningPolicy::PreferFile,
+                        "prefer-seed" => OpeningPolicy::PreferSeed,
+                        "sum" => OpeningPolicy::Sum,
+                        other => bail!(
+                            "Unknown --opening-conflict {}, expected error, prefer-file, prefer-seed or sum",
+                            other
+                        ),
+                    }
+                }
+                (_, "--compat") => engine.compat = value(&arg, args.next())?.parse()?,
+                (_, "--review-lock-allows") => {
+                    engine.review_lock = ReviewLockPolicy::parse(&value(&arg, args.next())?)?;
+                }
+                (_, "--max-accounts") => {
+                    engine.limits.max_accounts = Some(parse_value(&arg, args.next())?);
+                }
+                (_, "--max-stored-transactions") => {
+                    engine.limits.max_stored_transactions = Some(parse_value(&arg, args.next())?);
+                }
+                (_, "--max-rows") => max_rows = Some(parse_value(&arg, args.next())?),
+                (_, "--atomic") => atomic = true,
+                (_, "--signal-snapshot") => signal_snapshot = Some(value(&arg, args.next())?),
+                (_, "--otlp-endpoint") => otlp_endpoint = Some(value(&arg, args.next())?),
+                (_, "--locale") => locale = value(&arg, args.next())?.parse()?,
+                (_, "--latency-budget-us") => {
+                    let micros = parse_value(&arg, args.next())?;
+                    engine.latency_budget = Some(std::time::Duration::from_micros(micros));
+                }
+                ("" | "report" | "disputes list", "--pseudonymize") => {
+                    let secret = std::env::var("PLAYGROUND_PSEUDONYM_SECRET").context(
+                        "--pseudonymize needs the secret in PLAYGROUND_PSEUDONYM_SECRET",
+                    )?;
+                    pseudonymizer = Some(Pseudonymizer::new(secret.as_bytes()));
+                }
+                (_, "--pseudonymize") => {
+                    bail!("--pseudonymize only applies to process, report and disputes list")
+                }
+                ("serve", "--external-client-ids") => {
+                    bail!("--external-client-ids doesn't apply to serve, its requests have numeric clients")
+                }
+                (_, "--external-client-ids") => external_client_ids = true,
+                ("merge-snapshots", "--on-conflict") => {
+                    on_conflict = match value(&arg, args.next())?.as_str() {
+                        "fail" => Some(OnConflict::Fail),
+                        "sum" => Some(OnConflict::Sum),
+                        other => bail!("Unknown --on-conflict {}, expected fail or sum", other),
+                    };
+                }
+                (_, "--decimal-separator") => decimal_separator = char_value(&arg, args.next())?,
+                (_, "--thousands-separator") => {
+                    thousands_separator = Some(char_value(&arg, args.next())?);
+                }
+                (_, "--scale") => scale = Some(parse_value(&arg, args.next())?),
+                (_, "--scale-policy") => scale_policy = Some(value(&arg, args.next())?.parse()?),
+                (_, "--tags") => tags = Some(value(&arg, args.next())?),
+                ("report", "--groups") => groups = Some(value(&arg, args.next())?),
+                ("report", "--by-source") => by_source = true,
+                ("report", "--by-category") => by_category = true,
+                ("report", "--by-holder") => by_holder = true,
+                ("report", "--pockets") => pockets = true,
+                ("report", "--wallets") => wallets = true,
+                ("report", "--negative") => negative = true,
+                ("report", "--pending-withdrawals") => pending_withdrawals = true,
+                ("report", "--promo-credits") => promo_credits = true,
+                ("report", "--dispute-stats") => dispute_stats = true,
+                (_, "--source") => source = Some(value(&arg, args.next())?),
+                (_, "--plugin") => plugins.push(value(&arg, args.next())?),
+                (_, "--output-metadata") => {
+                    output_metadata = match value(&arg, args.next())?.as_str() {
+                        "comments" => Some(OutputMetadata::Comments),
+                        "sidecar" => Some(OutputMetadata::Sidecar),
+                        other => bail!(
+                            "Unknown --output-metadata {}, expected comments or sidecar",
+                            other
+                        ),
+                    }
+                }
+                ("query", "--expr") => {
+                    let expression = value(&arg, args.next())?;
+                    expr = Some(expression.parse::<AccountFilter>().map_err(|e| {
+                        anyhow::anyhow!("Invalid --expr {}: {:#}", expression, e)
+                    })?);
+                }
+                ("", "--changed-only") => changed_only = true,
+                (_, "--changed-only") => bail!("--changed-only only applies to process"),
+                ("" | "simulate", "--extended") => extended = true,
+                (_, "--extended") => bail!("--extended only applies to process and simulate"),
+                ("" | "report", "--tag") => tag = Some(value(&arg, args.next())?),
+                (_, "--dispute-timeout-days") => {
+                    let days: u64 = parse_value(&arg, args.next())?;
+                    engine.dispute_timeout = Some(days.saturating_mul(24 * 60 * 60));
+                }
+                (_, "--dunning-grace-days") => {
+                    let days: u64 = parse_value(&arg, args.next())?;
+                    engine.dunning_grace = Some(days.saturating_mul(24 * 60 * 60));
+                }
+                (_, "--reversal-window-hours") => {
+                    let hours: u64 = parse_value(&arg, args.next())?;
+                    engine.reversal_window = Some(hours.saturating_mul(60 * 60));
+                }
+                ("settle", "--template") => template = Some(value(&arg, args.next())?),
+                ("export" | "history" | "flows", "--format") => {
+                    format = Some(value(&arg, args.next())?)
+                }
+                (_, "--format") => input_format = Some(value(&arg, args.next())?.parse()?),
+                ("export" | "history", "--currency") => {
+                    currency = Some(value(&arg, args.next())?);
+                }
+                ("export", "--debtor-account") => {
+                    debtor_account = Some(value(&arg, args.next())?);
+                }
+                ("test-fixtures", "--update") => update = true,
+                ("admin" | "loadtest", "--url") | ("replicate", "--from") | ("relay", "--to") => {
+                    url = Some(value(&arg, args.next())?);
+                }
+                ("admin", "--author") => author = Some(value(&arg, args.next())?),
+                ("loadtest", "--rate") => rate = Some(parse_rate(&value(&arg, args.next())?)?),
+                ("loadtest", "--duration") => {
+                    duration = Some(parse_duration(&value(&arg, args.next())?)?);
+                }
+                ("simulate", "--start") => start = parse_value(&arg, args.next())?,
+                ("simulate", "--step") => {
+                    step = parse_duration(&value(&arg, args.next())?)?.as_secs();
+                }
+                ("simulate", "--then") => {
+                    then = parse_duration(&value(&arg, args.next())?)?.as_secs();
+                }
+                ("loadtest", "--mix") => mix = Some(value(&arg, args.next())?.parse()?),
+                ("loadtest", "--batch") => batch = parse_value(&arg, args.next())?,
+                ("loadtest", "--connections") => connections = parse_value(&arg, args.next())?,
+                ("loadtest", "--clients") => clients = parse_value(&arg, args.next())?,
+                ("loadtest", "--seed") => seed = parse_value(&arg, args.next())?,
+                ("relay", "--cursor") => cursor = Some(value(&arg, args.next())?),
+                ("replicate" | "relay", "--every") => every = Some(parse_value(&arg, args.next())?),
+                ("serve", "--ha-lock") => ha_lock = Some(value(&arg, args.next())?),
+                ("serve", "--ha-state") => ha_state = Some(value(&arg, args.next())?),
+                ("serve", "--event-log") => event_log = Some(value(&arg, args.next())?),
+                ("serve", "--compact-every") => compact_every = parse_value(&arg, args.next())?,
+                ("serve", "--event-log-retention") => {
+                    event_log_retention = parse_value(&arg, args.next())?;
+                }
+                ("serve", "--idempotency-keys") => {
+                    idempotency_keys = Some(value(&arg, args.next())?);
+                }
+                ("serve", "--idempotency-retention-hours") => {
+                    idempotency_retention_hours = parse_value(&arg, args.next())?;
+                }
+                ("serve", "--max-body-bytes") => max_body_bytes = parse_value(&arg, args.next())?,
+                ("serve", "--request-timeout-secs") => {
+                    request_timeout_secs = parse_value(&arg, args.next())?;
+                }
+                ("serve", "--config") => config = Some(value(&arg, args.next())?),
+                ("serve", "--listen") => listen = Some(value(&arg, args.next())?),
+                ("serve", "--alert-webhook") => alert_webhook = Some(value(&arg, args.next())?),
+                ("serve", "--dead-letter") => dead_letter = Some(value(&arg, args.next())?),
+                ("serve", "--dedup-window") => {
+                    dedup = Some(value(&arg, args.next())?.parse()?);
+                }
+                (_, flag) if flag.starts_with("--") => {
+                    bail!(Message::UnknownFlag {
+                        flag: flag.to_string()
+                    })
+                }
+                _ => positionals.push(arg),
+            }
+        }
+        let mut positionals = positionals.into_iter();
+        let mut amount_format = AmountFormat::new(decimal_separator, thousands_separator)?;
+        match (scale, scale_policy) {
+            (Some(decimals), policy) => {
+                amount_format = amount_format.with_scale(ScaleLimit {
+                    decimals,
+                    policy: policy.unwrap_or(ScalePolicy::Reject),
+                });
+            }
+            (None, Some(_)) => bail!("--scale-policy needs a --scale"),
+            (None, None) => {}
+        }
+
+        let command = match command.as_str() {
+            "settle" => Command::Settle { template },
+            "export" => {
+                let format = match (format.as_deref(), debtor_account) {
+                    (Some("camt.053"), _) => ExportFormat::Camt053,
+                    (Some("pain.001"), Some(debtor_account)) => {
+                        ExportFormat::Pain001 { debtor_account }
+                    }
+                    (Some("pain.001"), None) => bail!("pain.001 needs a --debtor-account"),
+                    (Some(format), _) => bail!("Unknown export format {}", format),
+                    (None, _) => bail!("Expected an export --format, camt.053 or pain.001"),
+                };
+                let currency = match currency {
+                    None => bail!("Expected the --currency of the amounts"),
+                    Some(currency) => currency,
+                };
+                Command::Export(ExportOptions { format, currency })
+            }
+            "history" => {
+                let client = parse_next(&mut positionals, "client")?;
+                let format = match (format.as_deref(), currency) {
+                    (None | Some("csv"), _) => HistoryFormat::Csv,
+                    (Some("qif"), _) => HistoryFormat::Qif,
+                    (Some("ofx"), Some(currency)) => HistoryFormat::Ofx { currency },
+                    (Some("ofx"), None) => bail!("ofx needs the --currency of the amounts"),
+                    (Some(format), _) => bail!("Unknown history format {}", format),
+                };
+                Command::History { client, format }
+            }
+            "disputes list" => Command::Disputes(DisputesCommand::List),
+            "escrows" => Command::Escrows,
+            "flows" => Command::Flows {
+                format: match format.as_deref() {
+                    None | Some("csv") => FlowFormat::Csv,
+                    Some("dot") => FlowFormat::Dot,
+                    Some("graphml") => FlowFormat::GraphMl,
+                    Some(format) => bail!("Unknown flows format {}", format),
+                },
+            },
+            "disputes show" => Command::Disputes(DisputesCommand::Show {
+                client: parse_next(&mut positionals, "client")?,
+                tx: parse_next(&mut positionals, "tx")?,
+            }),
+            "report" => {
+                let reports = [
+                    groups.is_some(),
+                    by_source,
+                    by_category,
+                    by_holder,
+                    pockets,
+                    wallets,
+                    negative,
+                    pending_withdrawals,
+                    promo_credits,
+                    dispute_stats,
+                ]
+                .into_iter()
+                .filter(|report| *report)
+                .count();
+                if reports > 0 && tag.is_some() {
+                    bail!("report --groups, --by-source, --by-category, --by-holder, --pockets, --wallets, --negative, --pending-withdrawals, --promo-credits and --dispute-stats can't be limited to a --tag");
+                }
+                if reports > 1 {
+                    bail!("report takes one of --groups, --by-source, --by-category, --by-holder, --pockets, --wallets, --negative, --pending-withdrawals, --promo-credits and --dispute-stats");
+                }
+                Command::Report {
+                    groups,
+                    by_source,
+                    by_category,
+                    by_holder,
+                    pockets,
+                    wallets,
+                    negative,
+                    pending_withdrawals,
+                    promo_credits,
+                    dispute_stats,
+                }
+            }
+            "merge-snapshots" => {
+                let snapshots = positionals.by_ref().collect::<Vec<_>>();
+                if snapshots.len() < 2 {
+                    bail!("Expected at least two snapshots to merge");
+                }
+                Command::MergeSnapshots {
+                    snapshots,
+                    on_conflict: on_conflict.unwrap_or(OnConflict::Fail),
+                }
+            }
+            "query" => Command::Query {
+                snapshot: parse_next(&mut positionals, "snapshot")?,
+                query: match positionals.next().as_deref() {
+                    Some("accounts") => Query::Accounts(expr.take()),
+                    None if expr.is_some() => Query::Accounts(expr.take()),
+                    Some("account") => Query::Account(parse_next(&mut positionals, "client")?),
+                    Some("transaction") => Query::Transaction(parse_next(&mut positionals, "tx")?),
+                    Some("history") => Query::History(parse_next(&mut positionals, "client")?),
+                    _ => bail!("Expected query accounts, account, transaction or history"),
+                },
+            },
+            "diff-snapshots" => Command::DiffSnapshots {
+                before: parse_next(&mut positionals, "snapshot before")?,
+                after: parse_next(&mut positionals, "snapshot after")?,
+            },
+            "compare-configs" => Command::CompareConfigs {
+                before: parse_next(&mut positionals, "settings before")?,
+                after: parse_next(&mut positionals, "settings after")?,
+            },
+            "check-refs" => {
+                let files = positionals.by_ref().collect::<Vec<_>>();
+                if files.is_empty() {
+                    bail!("Expected the files to check");
+                }
+                if external_client_ids {
+                    bail!("check-refs doesn't support --external-client-ids");
+                }
+                Command::CheckRefs { files }
+            }
+            "test-fixtures" => Command::TestFixtures {
+                dir: positionals
+                    .next()
+                    .unwrap_or_else(|| "tests/fixtures".to_string()),
+                update,
+            },
+            "re-encrypt" => Command::ReEncrypt {
+                state: parse_next(&mut positionals, "state file")?,
+            },
+            "serve" => {
+                if ha_lock.is_some() && ha_state.is_none() && event_log.is_none() {
+                    bail!("--ha-lock needs an --ha-state or --event-log to take over from");
+                }
+                if ha_state.is_some() && event_log.is_some() {
+                    bail!("Keep the state in either an --ha-state or an --event-log, not both");
+                }
+                if request_timeout_secs == 0 {
+                    bail!("--request-timeout-secs needs to be at least 1");
+                }
+                Command::Serve(ServeOptions {
+                    listen: listen.unwrap_or_else(|| "127.0.0.1:8080".to_string()),
+                    dedup,
+                    dead_letter,
+                    alert_webhook,
+                    amount_format,
+                    config,
+                    ha_lock,
+                    ha_state,
+                    event_log,
+                    compact_every,
+                    event_log_retention,
+                    idempotency_keys,
+                    idempotency_retention: idempotency_retention_hours * 60 * 60,
+                    otlp_endpoint: otlp_endpoint.clone(),
+                    max_body_bytes,
+                    request_timeout: Duration::from_secs(request_timeout_secs),
+                })
+            }
+            "admin" => {
+                let command = match positionals.next().as_deref() {
+                    Some("accounts") => AdminCommand::Accounts,
+                    Some("lock") => AdminCommand::Lock {
+                        client: parse_next(&mut positionals, "client")?,
+                        reason: positionals.next(),
+                    },
+                    Some("unlock") => AdminCommand::Unlock(parse_next(&mut positionals, "client")?),
+                    Some("close") => AdminCommand::Close(parse_next(&mut positionals, "client")?),
+                    Some("adjust") => AdminCommand::Adjust {
+                        client: parse_next(&mut positionals, "client")?,
+                        amount: parse_next::<BigDecimal>(&mut positionals, "amount")?.to_string(),
+                    },
+                    Some("stats") => AdminCommand::Stats,
+                    Some("notes") => AdminCommand::Notes(parse_next(&mut positionals, "client")?),
+                    Some("note") => AdminCommand::Note {
+                        client: parse_next(&mut positionals, "client")?,
+                        text: parse_next(&mut positionals, "note")?,
+                        author,
+                    },
+                    Some("withdrawals") => AdminCommand::Withdrawals,
+                    Some("approve") => AdminCommand::Approve {
+                        client: parse_next(&mut positionals, "client")?,
+                        tx: parse_next(&mut positionals, "tx")?,
+                    },
+                    Some("deny") => AdminCommand::Deny {
+                        client: parse_next(&mut positionals, "client")?,
+                        tx: parse_next(&mut positionals, "tx")?,
+                    },
+                    Some("holds") => AdminCommand::Holds(parse_next(&mut positionals, "client")?),
+                    Some(action @ ("hold" | "release")) => AdminCommand::Hold {
+                        client: parse_next(&mut positionals, "client")?,
+                        amount: parse_next::<BigDecimal>(&mut positionals, "amount")?.to_string(),
+                        reason: parse_next::<String>(&mut positionals, "reason")?
+                            .parse::<HoldReason>()?
+                            .to_string(),
+                        author,
+                        release: action == "release",
+                    },
+                    _ => {
+                        bail!("Expected admin accounts, lock, unlock, close, adjust, stats, notes, note, withdrawals, approve, deny, holds, hold or release")
+                    }
+                };
+                Command::Admin {
+                    url: url.context("admin needs the --url of the server")?,
+                    command,
+                }
+            }
+            "replicate" => Command::Replicate {
+                url: url.context("replicate needs the --from url of the primary")?,
+                state: parse_next(&mut positionals, "state file")?,
+                every,
+            },
+            "relay" => {
+                let dir = parse_next::<String>(&mut positionals, "event log")?;
+                Command::Relay {
+                    url: url.context("relay needs the --to url of the consumer")?,
+                    cursor: cursor.unwrap_or_else(|| format!("{}/relay.cursor", dir)),
+                    dir,
+                    every,
+                }
+            }
+            "loadtest" => {
+                if batch == 0 || connections == 0 {
+                    bail!("loadtest needs a --batch and --connections of at least 1");
+                }
+                Command::Loadtest(LoadtestOptions {
+                    url: url.context("loadtest needs the --url of the server")?,
+                    rate: rate.context("loadtest needs a --rate, e.g. 5000/s")?,
+                    duration: duration.context("loadtest needs a --duration, e.g. 10m")?,
+                    mix: match mix {
+                        Some(mix) => mix,
+                        None => "deposit:70,withdrawal:25,dispute:5".parse()?,
+                    },
+                    batch,
+                    connections,
+                    clients,
+                    seed,
+                })
+            }
+            "simulate" => Command::Simulate { start, step, then },
+            _ => Command::Process,
+        };
+        if expr.is_some() {
+            bail!("--expr only filters query accounts");
+        }
+        let input = positionals.next();
+        let needs_input = !matches!(
+            command,
+            Command::Serve(_)
+                | Command::Admin { .. }
+                | Command::Replicate { .. }
+                | Command::Relay { .. }
+                | Command::Loadtest(_)
+                | Command::MergeSnapshots { .. }
+                | Command::CheckRefs { .. }
+                | Command::DiffSnapshots { .. }
+                | Command::Query { .. }
+                | Command::ReEncrypt { .. }
+                | Command::TestFixtures { .. }
+        );
+        if let Command::CompareConfigs { .. } = command {
+            if input.as_deref() == Some("-") {
+                bail!("compare-configs reads the input twice, it can't be stdin");
+            }
+            if external_client_ids || partitions.is_some() {
+                bail!("compare-configs doesn't support --external-client-ids or --partition-by-client");
+            }
+        }
+        if shard_accounts_out.is_some() && partitions.is_none() {
+            bail!("--shard-accounts-out needs --partition-by-client");
+        }
+        if shard_accounts_out.is_some() && atomic {
+            bail!(
+                "--shard-accounts-out writes accounts before --atomic knows whether to apply them"
+            );
+        }
+        if let (Command::Simulate { .. }, Some(_)) = (&command, partitions) {
+            bail!("simulate runs on one clock, it can't --partition-by-client");
+        }
+        if partitions.is_some() && snapshot.is_some() {
+            bail!("--partition-by-client can't continue from a --snapshot");
+        }
+        if resume_from_accounts.is_some() && snapshot.is_some() {
+            bail!("Continue from either a --snapshot or --resume-from-accounts, not both");
+        }
+        if partitions.is_some() && resume_from_accounts.is_some() {
+            bail!("--partition-by-client can't continue from --resume-from-accounts");
+        }
+        if let (true, Command::Serve(serve)) = (encrypt_state, &command) {
+            if serve.ha_state.is_some() {
+                bail!("--ha-state can't be encrypted yet");
+            }
+            if serve.event_log.is_some() {
+                bail!("--event-log can't be encrypted yet");
+            }
+        }
+        if input.is_none() && snapshot.is_none() && needs_input {
+            bail!(Message::ExpectedFilename);
+        }
+        if let Some(arg) = positionals.next() {
+            bail!(Message::UnexpectedArgument { argument: arg });
+        }
+        if outputs.is_empty() {
+            outputs.push("-".to_string());
+        }
+        if output_metadata == Some(OutputMetadata::Sidecar) && outputs.iter().any(|out| out == "-")
+        {
+            bail!("--output-metadata sidecar needs every output to be a file, there's nothing next to stdout");
+        }
+
+        Ok(Self {
+            command,
+            input,
+            snapshot,
+            resume_from_accounts,
+            partitions,
+            shard_accounts_out,
+            profile,
+            report_out,
+            anomalies_out,
+            anomaly_threshold,
+            snapshot_out,
+            encrypt_state,
+            pseudonymizer,
+            external_client_ids,
+            changed_only,
+            extended,
+            output_metadata,
+            outputs,
+            rejects,
+            engine,
+            tags,
+            tag,
+            amount_format,
+            input_format,
+            memory_stats,
+            plugins,
+            source,
+            max_rows,
+            atomic,
+            signal_snapshot,
+            otlp_endpoint,
+            locale,
+            audit_log,
+            enrich,
+        })
+    }
+}
+
+fn char_value(flag: &str, value: Option<String>) -> Result<char> {
+    let value = self::value(flag, value)?;
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(separator), None) => Ok(separator),
+        _ => bail!(Message::ExpectedCharacter {
+            flag: flag.to_string(),
+            value,
+        }),
+    }
+}
+
+fn parse_next<T: std::str::FromStr>(
+    positionals: &mut impl Iterator<Item = String>,
+    name: &str,
+) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match positionals.next() {
+        None => bail!("Expected a {}", name),
+        Some(value) => value
+            .parse()
+            .with_context(|| format!("Invalid {} {}", name, value)),
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = self::value(flag, value)?;
+    value.parse().with_context(|| Message::InvalidValue {
+        flag: flag.to_string(),
+        value: value.clone(),
+    })
+}
+
+/// Transactions a second, `5000/s` or just `5000`
+fn parse_rate(rate: &str) -> Result<u64> {
+    let per_second = rate.strip_suffix("/s").unwrap_or(rate);
+    match per_second.parse() {
+        Ok(0) | Err(_) => bail!("Invalid rate {}, expected e.g. 5000/s", rate),
+        Ok(rate) => Ok(rate),
+    }
+}
+
+/// `30s`, `10m`, `1h` or `30d`
+fn parse_duration(duration: &str) -> Result<Duration> {
+    let (number, unit) = duration.split_at(duration.len().saturating_sub(1));
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => bail!(
+            "Invalid duration {}, expected e.g. 30s, 10m, 1h or 30d",
+            duration
+        ),
+    };
+    let number = number
+        .parse::<u64>()
+        .with_context(|| format!("Invalid duration {}", duration))?;
+    Ok(Duration::from_secs(number * seconds))
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String> {
+    match value {
+        None => bail!(Message::ExpectedValue {
+            flag: flag.to_string()
+        }),
+        Some(value) => Ok(value),
+    }
+}
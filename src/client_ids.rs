@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+
+/// The ids clients have in sources that don't number them, like UUIDs or customer codes,
+/// and the dense ids the engine knows them by, handed out in the order the clients are first
+/// seen. Kept in snapshots so a client keeps its id from one run to the next
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientIds {
+    internal: HashMap<String, ClientId>,
+    /// By internal id
+    external: Vec<String>,
+}
+
+impl ClientIds {
+    /// The engine's id for the client, a new one the first time it's seen. Fails once every
+    /// id of the build's `ClientId` is taken
+    pub fn intern(&mut self, external: &str) -> Result<ClientId> {
+        if let Some(&client) = self.internal.get(external) {
+            return Ok(client);
+        }
+        let client = ClientId::try_from(self.external.len())
+            .ok()
+            .with_context(|| format!("No client id left for {}", external))?;
+        self.internal.insert(external.to_string(), client);
+        self.external.push(external.to_string());
+        Ok(client)
+    }
+
+    pub fn internal(&self, external: &str) -> Option<ClientId> {
+        self.internal.get(external).copied()
+    }
+
+    pub fn external(&self, client: ClientId) -> Option<&str> {
+        self.external.get(client as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.external.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.external.is_empty()
+    }
+
+    /// Takes over the ids of another engine's clients. They have to agree on the clients both
+    /// know, fails with the first internal id they don't
+    pub fn merge(&mut self, other: &Self) -> Result<(), ClientId> {
+        let shared = self.external.len().min(other.external.len());
+        if let Some(index) = (0..shared).find(|&i| self.external[i] != other.external[i]) {
+            return Err(ClientId::try_from(index).expect("an interned id"));
+        }
+        for external in &other.external[shared..] {
+            if self.internal.contains_key(external) {
+                return Err(other.internal[external]);
+            }
+            self.intern(external).expect("ids of the other engine fit");
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for ClientIds {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.external.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        let mut ids = Self::default();
+        for external in Vec::<String>::decode(decoder)? {
+            let known = ids.len();
+            ids.intern(&external)?;
+            if ids.len() == known {
+                bail!("Client {} is in the snapshot twice", external);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::AmountFormat;
+    use crate::transaction::read_external_transactions;
+
+    #[test]
+    fn should_intern_external_ids() {
+        let mut ids = ClientIds::default();
+        let alice = ids.intern("3f2a-alice").unwrap();
+        let bob = ids.intern("BOB-17").unwrap();
+        assert_eq!((alice, bob), (0, 1));
+        assert_eq!(ids.intern("3f2a-alice").unwrap(), alice);
+        assert_eq!(ids.external(bob), Some("BOB-17"));
+        assert_eq!(ids.internal("carol"), None);
+        assert_eq!(ids.external(2), None);
+
+        let mut encoder = Encoder::new();
+        ids.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+        let decoded = ClientIds::decode(&mut Decoder::new(&bytes).unwrap()).unwrap();
+        assert_eq!(decoded, ids);
+
+        let mut more = ids.clone();
+        more.intern("carol").unwrap();
+        ids.merge(&more).unwrap();
+        assert_eq!(ids.internal("carol"), Some(2));
+        let mut other = ClientIds::default();
+        other.intern("BOB-17").unwrap();
+        assert_eq!(ids.merge(&other), Err(0));
+    }
+
+    #[test]
+    fn should_read_transactions_of_external_clients() {
+        let input = "type,client,tx,amount,seller\n\
+                     deposit,3f2a-alice,1,10,\n\
+                     escrow_open,BOB-17,2,5,3f2a-alice\n\
+                     deposit,3f2a-alice,3,x,\n";
+        let mut ids = ClientIds::default();
+        let rows = read_external_transactions(input.as_bytes(), AmountFormat::default(), |id| {
+            ids.intern(id)
+        })
+        .collect::<Vec<_>>();
+        let clients = rows
+            .iter()
+            .map(|row| row.as_ref().map(|raw| raw.client()).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(clients, [Some(0), Some(1), None]);
+        assert_eq!(ids.internal("BOB-17"), Some(1));
+        assert_eq!(ids.len(), 2);
+    }
+}
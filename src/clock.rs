@@ -0,0 +1,99 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::date::unix_now;
+
+/// The time an engine goes by, unix seconds, see `TransactionEngine::set_clock`. Dispute
+/// timeouts, velocity windows, the ages of negative balances and notes all do
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// So configs with a clock can be printed
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Clock({})", self.now())
+    }
+}
+
+/// The system clock, what engines that aren't time-aware use
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        unix_now()
+    }
+}
+
+/// A clock that only moves when it's told to, so a simulation or a test can fast-forward
+/// through days in no time and get the same result every run. Clones share the time
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock(Arc<AtomicU64>);
+
+impl VirtualClock {
+    pub fn new(start: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(start)))
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+
+    /// Times going backwards are ignored, like `TransactionEngine::advance_time` does
+    pub fn set(&self, now: u64) {
+        self.0.fetch_max(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::transaction::Transaction;
+    use crate::transaction_engine::TransactionEngine;
+
+    /// What an embedding application might pass, its own idea of the time
+    struct Frozen(u64);
+
+    impl Clock for Frozen {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn should_go_by_the_clock_of_the_config() {
+        let config = EngineConfig {
+            clock: Some(Arc::new(Frozen(1_700_000_000))),
+            ..EngineConfig::default()
+        };
+        let mut engine = TransactionEngine::with_config(config.clone());
+        assert_eq!(engine.now(), 1_700_000_000);
+        engine
+            .process(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            })
+            .unwrap();
+        engine.add_note(1, None, "called").unwrap();
+        assert_eq!(engine.account(1).unwrap().notes()[0].at, 1_700_000_000);
+
+        let restored = TransactionEngine::from_snapshot(&engine.snapshot(), config).unwrap();
+        assert_eq!(restored.now(), 1_700_000_000);
+        engine.reconfigure(EngineConfig::default());
+        assert!(engine.config().clock.is_some());
+        assert!(format!("{:?}", engine.config()).contains("Clock(1700000000)"));
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::alerts::AlertRules;
+use crate::approval::ApprovalPolicy;
+use crate::clock::Clock;
+use crate::fees::FeeSchedule;
+use crate::joint::JointAccounts;
+use crate::lock::ReviewLockPolicy;
+use crate::reserve::ReserveRequirements;
+use crate::rules::RuleSet;
+use crate::screening::Blocklist;
+use crate::transaction::ClientId;
+use crate::transform::Transform;
+
+/// Tunables for the transaction engine, the defaults match the original behaviour but for
+/// the fixes to it that `compat` can undo
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct EngineConfig {
+    /// Open disputes older than this many seconds are resolved automatically,
+    /// which only happens once the engine is time-aware, i.e. the input has timestamps
+    pub dispute_timeout: Option<u64>,
+    /// Accounts whose available balance has been negative for longer than this many seconds
+    /// are flagged for dunning, see `dunning`
+    pub dunning_grace: Option<u64>,
+    /// How many seconds after a withdrawal a `reversal` can still undo it, reversals are
+    /// rejected without one
+    pub reversal_window: Option<u64>,
+    /// Transactions any registered `RiskScorer` scores above this are held for review
+    /// instead of being applied
+    pub risk_threshold: Option<f64>,
+    /// Rewrites every transaction before the rules and risk scorers see it
+    pub transform: Transform,
+    /// Clients whose transactions are rejected or held whatever the rules say
+    pub blocklist: Blocklist,
+    /// Checked before the risk scorers, a matching rule rejects the transaction
+    pub rules: RuleSet,
+    /// What deposits and withdrawals cost, credited to the schedule's fee account
+    pub fees: FeeSchedule,
+    /// The withdrawals that wait for an `approve` before they settle, by account
+    pub approvals: ApprovalPolicy,
+    /// What every account has to keep available, by client and tag
+    pub reserves: ReserveRequirements,
+    /// Clients whose transactions go to an account they share with others
+    pub joint_accounts: JointAccounts,
+    /// Synthetic clients, e.g. staging traffic, whose transactions are processed as usual
+    /// but who are left out of the accounts, reports and settlement
+    pub test_clients: HashSet<ClientId>,
+    /// Like `test_clients`, every client with this tag
+    pub test_tag: Option<String>,
+    /// Panic when a transaction leaves its account in a state that breaks an invariant,
+    /// see `TransactionEngine::check_invariants`
+    pub check_invariants: bool,
+    /// Balance thresholds reported to the registered `AlertObserver`s
+    pub alerts: AlertRules,
+    /// Transactions that take longer than this to apply are logged with the size of their
+    /// account, to find the accounts that make processing slow
+    pub latency_budget: Option<Duration>,
+    /// Drop the stored transactions that can't be disputed anymore, to bound the memory
+    /// of a server that runs forever. Balances, ledgers and cases are kept
+    pub pruning: Option<Pruning>,
+    /// Caps on what the engine holds, so a malformed or malicious input can't exhaust the
+    /// memory of a shared server
+    pub limits: Limits,
+    /// What an account that's `LockState::ReviewLocked` still takes
+    pub review_lock: ReviewLockPolicy,
+    /// What an `opening` row does to an account that was opened from an accounts csv
+    pub opening_policy: OpeningPolicy,
+    /// Whose semantics disputes follow, `Compat::Legacy` for reconciliations that were
+    /// built against the quirks of earlier versions
+    pub compat: Compat,
+    /// The clock the engine follows instead of the system clock, e.g. one the embedding
+    /// application controls, see `TransactionEngine::set_clock`. Settings files can't set it
+    pub clock: Option<Arc<dyn Clock>>,
+}
+
+/// Which version of the dispute semantics the engine follows. Every change to them is made
+/// for `Current` only, `Legacy` keeps reproducing the original engine's results bit for bit
+/// so downstream systems can migrate when they're ready
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compat {
+    #[default]
+    Current,
+    /// Disputes are read off the exact shape of what the client sent for a tx: a second
+    /// dispute that was rejected keeps the first from being resolved, a resolve or
+    /// chargeback before any dispute keeps the tx from being disputed and a resolved
+    /// dispute can still be charged back
+    Legacy,
+}
+
+impl Compat {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Compat::Current => "current",
+            Compat::Legacy => "legacy",
+        }
+    }
+}
+
+impl FromStr for Compat {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "current" => Ok(Compat::Current),
+            "legacy" => Ok(Compat::Legacy),
+            other => bail!("Unknown compat {}, expected current or legacy", other),
+        }
+    }
+}
+
+/// How an `opening` row of the input and the opening balance an account was seeded with,
+/// see `TransactionEngine::open_accounts`, are reconciled. Every conflict is kept in
+/// `TransactionEngine::opening_conflicts` whichever way it's settled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OpeningPolicy {
+    /// The row is rejected with `TransactionError::OpeningConflict`
+    #[default]
+    Error,
+    /// The available balance becomes the row's amount
+    PreferFile,
+    /// The row is ignored
+    PreferSeed,
+    /// The row is applied as a deposit on top of the seeded balance
+    Sum,
+}
+
+impl OpeningPolicy {
+    pub const fn name(self) -> &'static str {
+        match self {
+            OpeningPolicy::Error => "error",
+            OpeningPolicy::PreferFile => "prefer-file",
+            OpeningPolicy::PreferSeed => "prefer-seed",
+            OpeningPolicy::Sum => "sum",
+        }
+    }
+}
+
+/// Transactions that would take the engine past a limit are rejected, see
+/// `TransactionError::TooManyAccounts` and `TransactionError::TooManyTransactions`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Transactions of a client without an account are rejected once there are this many
+    pub max_accounts: Option<usize>,
+    /// Stored transactions over all accounts, rejected ones included,
+    /// after which transactions are rejected instead of stored
+    pub max_stored_transactions: Option<usize>,
+}
+
+/// Which stored transactions `EngineConfig::pruning` drops. Those of a tx whose dispute has
+/// been resolved, charged back or expired always, a tx can only be disputed once
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pruning {
+    /// Also deposits and withdrawals stored this many seconds ago, unless they're disputed.
+    /// Disputing them afterwards is rejected, so this is the window for disputes
+    pub after: Option<u64>,
+}
+
+impl EngineConfig {
+    /// This config with the tunables of a `key=value` settings file, which a running server
+    /// reloads when it changes:
+    /// ```text
+    /// risk_threshold=0.8
+    /// dispute_timeout_days=30
+    /// dunning_grace_days=14
+    /// reversal_window_hours=24
+    /// rules=rules.txt
+    /// blocklist=blocklist.txt
+    /// transform=transform.txt
+    /// fees=fees.txt
+    /// approvals=approvals.txt
+    /// reserves=reserves.txt
+    /// alerts=alerts.txt
+    /// latency_budget_us=500
+    /// max_accounts=100000
+    /// max_stored_transactions=10000000
+    /// review_lock_allows=deposit,dispute
+    /// compat=legacy
+    /// ```
+    /// An empty value turns the setting off. Nothing is changed unless the whole file is valid
+    pub fn with_settings(&self, settings: &str) -> Result<Self> {
+        let mut config = self.clone();
+        for (index, line) in settings.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            config
+                .set(line)
+                .with_context(|| format!("Invalid setting on line {}", index + 1))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, line: &str) -> Result<()> {
+        let (key, value) = line.split_once('=').context("Expected key=value")?;
+        let value = Some(value.trim()).filter(|value| !value.is_empty());
+        let read = |path: &str| {
+            std::fs::read_to_string(path).with_context(|| format!("Could not read {}", path))
+        };
+        match key.trim() {
+            "risk_threshold" => {
+                self.risk_threshold = value
+                    .map(str::parse)
+                    .transpose()
+                    .context("Invalid risk threshold")?;
+            }
+            "dispute_timeout_days" => {
+                let days = value
+                    .map(str::parse::<u64>)
+                    .transpose()
+                    .context("Invalid number of days")?;
+                self.dispute_timeout = days.map(|days| days.saturating_mul(24 * 60 * 60));
+            }
+            "dunning_grace_days" => {
+                let days = value
+                    .map(str::parse::<u64>)
+                    .transpose()
+                    .context("Invalid number of days")?;
+                self.dunning_grace = days.map(|days| days.saturating_mul(24 * 60 * 60));
+            }
+            "reversal_window_hours" => {
+                let hours = value
+                    .map(str::parse::<u64>)
+                    .transpose()
+                    .context("Invalid number of hours")?;
+                self.reversal_window = hours.map(|hours| hours.saturating_mul(60 * 60));
+            }
+            "rules" => {
+                self.rules = match value {
+                    None => RuleSet::default(),
+                    Some(path) => RuleSet::parse(&read(path)?)?,
+                };
+            }
+            "blocklist" => {
+                self.blocklist = match value {
+                    None => Blocklist::default(),
+                    Some(path) => Blocklist::parse(&read(path)?)?,
+                };
+            }
+            "fees" => {
+                self.fees = match value {
+                    None => FeeSchedule::default(),
+                    Some(path) => FeeSchedule::parse(&read(path)?)?,
+                };
+            }
+            "approvals" => {
+                self.approvals = match value {
+                    None => ApprovalPolicy::default(),
+                    Some(path) => ApprovalPolicy::parse(&read(path)?)?,
+                };
+            }
+            "reserves" => {
+                self.reserves = match value {
+                    None => ReserveRequirements::default(),
+                    Some(path) => ReserveRequirements::parse(&read(path)?)?,
+                };
+            }
+            "transform" => {
+                self.transform = match value {
+                    None => Transform::default(),
+                    Some(path) => Transform::parse(&read(path)?)?,
+                };
+            }
+            "alerts" => {
+                self.alerts = match value {
+                    None => AlertRules::default(),
+                    Some(path) => AlertRules::parse(&read(path)?)?,
+                };
+            }
+            "latency_budget_us" => {
+                self.latency_budget = value
+                    .map(str::parse)
+                    .transpose()
+                    .context("Invalid latency budget")?
+                    .map(Duration::from_micros);
+            }
+            "max_accounts" => {
+                self.limits.max_accounts = value
+                    .map(str::parse)
+                    .transpose()
+                    .context("Invalid number of accounts")?;
+            }
+            "max_stored_transactions" => {
+                self.limits.max_stored_transactions = value
+                    .map(str::parse)
+                    .transpose()
+                    .context("Invalid number of transactions")?;
+            }
+            "review_lock_allows" => {
+                self.review_lock = value
+                    .map(ReviewLockPolicy::parse)
+                    .transpose()?
+                    .unwrap_or_default();
+            }
+            "compat" => self.compat = value.map(str::parse).transpose()?.unwrap_or_default(),
+            other => bail!("Unknown setting {}", other),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_apply_settings_or_nothing() {
+        let base = EngineConfig {
+            risk_threshold: Some(0.5),
+            ..EngineConfig::default()
+        };
+        let config = base
+            .with_settings(
+                "# tunables\nrisk_threshold=\n\ndispute_timeout_days = 2\nlatency_budget_us=250\nmax_accounts=10",
+            )
+            .unwrap();
+        assert_eq!(config.risk_threshold, None);
+        assert_eq!(config.latency_budget, Some(Duration::from_micros(250)));
+        assert_eq!(config.dispute_timeout, Some(2 * 24 * 60 * 60));
+        assert_eq!(config.limits.max_accounts, Some(10));
+        assert_eq!(config.review_lock, ReviewLockPolicy::default());
+        let config = base.with_settings("review_lock_allows=deposit").unwrap();
+        assert_eq!(config.review_lock.types().collect::<Vec<_>>(), ["deposit"]);
+
+        assert!(base
+            .with_settings("dispute_timeout_days=2\nrisk_threshold=high")
+            .is_err());
+        assert!(base.with_settings("rate_limit=10").is_err());
+        assert!(base.with_settings("rules=/no/such/file").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the unix epoch according to the system clock
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// A point in time in UTC, broken down for the export formats that need calendar dates
+#[derive(Debug, PartialEq, Eq)]
+pub struct UtcDateTime {
+    pub year: u64,
+    pub month: u64,
+    pub day: u64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+}
+
+impl UtcDateTime {
+    /// From seconds since the unix epoch
+    pub const fn from_timestamp(timestamp: u64) -> Self {
+        let (year, month, day) = civil_from_days(timestamp / 86_400);
+        let seconds = timestamp % 86_400;
+        Self {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds % 3600 / 60,
+            second: seconds % 60,
+        }
+    }
+
+    /// `2022-08-08`
+    pub fn iso_date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// `2022-08-08T23:06:40Z`
+    pub fn iso_date_time(&self) -> String {
+        format!(
+            "{}T{:02}:{:02}:{:02}Z",
+            self.iso_date(),
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+/// Days since 1970-01-01 to a (year, month, day), see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+const fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_format_dates_in_utc() {
+        let date = UtcDateTime::from_timestamp(1_660_000_000);
+        assert_eq!(date.iso_date(), "2022-08-08");
+        assert_eq!(date.iso_date_time(), "2022-08-08T23:06:40Z");
+        assert_eq!(
+            UtcDateTime::from_timestamp(951_782_400).iso_date(),
+            "2000-02-29"
+        );
+    }
+}
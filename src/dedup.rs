@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+use crate::transaction::{ClientId, RawTransaction, TransactionType};
+
+/// How long a delivered transaction is remembered for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupWindow {
+    /// The last n transactions of every client
+    Count(usize),
+    /// The transactions of the last n seconds, by their timestamp or else when they arrived
+    Seconds(u64),
+}
+
+/// `1000` is a count, `300s` is seconds
+impl FromStr for DedupWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(window: &str) -> Result<Self> {
+        let parsed = match window.strip_suffix('s') {
+            Some(seconds) => seconds.parse().map(DedupWindow::Seconds),
+            None => window.parse().map(DedupWindow::Count),
+        };
+        match parsed.with_context(|| format!("Invalid dedup window {}", window))? {
+            DedupWindow::Count(0) | DedupWindow::Seconds(0) => {
+                bail!("The dedup window can't be empty")
+            }
+            window => Ok(window),
+        }
+    }
+}
+
+/// A dispute of tx 1 isn't a duplicate of the deposit tx 1, so the type is part of the key,
+/// with the name of a custom type
+type Key = (TransactionType, Option<String>, u32);
+
+#[derive(Default)]
+struct Seen {
+    order: VecDeque<Key>,
+    keys: HashSet<Key>,
+}
+
+/// Drops transactions that were already delivered, which message buses with at-least-once
+/// delivery do every now and then. Only transactions within the window are remembered, and
+/// only clients with any of those
+pub struct Deduplicator {
+    window: DedupWindow,
+    seen: HashMap<ClientId, Seen>,
+    /// For time windows, (remembered at, client) of every transaction remembered, oldest first
+    expiries: VecDeque<(u64, ClientId)>,
+    /// Latest time seen, the window trails it
+    now: u64,
+    deduplicated: u64,
+}
+
+impl Deduplicator {
+    pub fn new(window: DedupWindow) -> Self {
+        Self {
+            window,
+            seen: HashMap::new(),
+            expiries: VecDeque::new(),
+            now: 0,
+            deduplicated: 0,
+        }
+    }
+
+    /// Whether the transaction was delivered before, remembers it if not.
+    /// `now` is in unix seconds and only matters for time windows. A transaction that's older
+    /// than one seen before is remembered as long as that one is, time doesn't go back
+    pub fn is_duplicate(&mut self, raw: &RawTransaction, now: u64) -> bool {
+        self.now = self.now.max(now);
+        if let DedupWindow::Seconds(seconds) = self.window {
+            self.expire(self.now.saturating_sub(seconds));
+        }
+        let key = (
+            raw.transaction_type(),
+            raw.custom_type().map(str::to_string),
+            raw.tx(),
+        );
+        let seen = self.seen.entry(raw.client()).or_default();
+        if seen.keys.contains(&key) {
+            self.deduplicated += 1;
+            return true;
+        }
+        seen.keys.insert(key.clone());
+        seen.order.push_back(key);
+        match self.window {
+            DedupWindow::Count(count) => {
+                if seen.order.len() > count {
+                    if let Some(expired) = seen.order.pop_front() {
+                        seen.keys.remove(&expired);
+                    }
+                }
+            }
+            DedupWindow::Seconds(_) => self.expiries.push_back((self.now, raw.client())),
+        }
+        false
+    }
+
+    /// Forgets what was remembered before `oldest`, and the clients that leaves nothing of
+    fn expire(&mut self, oldest: u64) {
+        while let Some(&(at, client)) = self.expiries.front() {
+            if at >= oldest {
+                break;
+            }
+            self.expiries.pop_front();
+            // remembered in the same order for every client as for all of them
+            let Some(seen) = self.seen.get_mut(&client) else {
+                continue;
+            };
+            if let Some(expired) = seen.order.pop_front() {
+                seen.keys.remove(&expired);
+            }
+            if seen.order.is_empty() {
+                self.seen.remove(&client);
+            }
+        }
+    }
+
+    /// How many transactions have been dropped as duplicates
+    pub const fn deduplicated(&self) -> u64 {
+        self.deduplicated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(csv_row: &str) -> RawTransaction {
+        let csv = format!("type,client,tx,amount\n{}\n", csv_row);
+        crate::transaction::csv_reader(csv.as_bytes())
+            .deserialize()
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_drop_duplicates_within_the_window() {
+        let mut deduplicator = Deduplicator::new("2".parse().unwrap());
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,1,10"), 0));
+        assert!(deduplicator.is_duplicate(&raw("deposit,1,1,10"), 0));
+        assert!(!deduplicator.is_duplicate(&raw("dispute,1,1,"), 0));
+        assert!(!deduplicator.is_duplicate(&raw("deposit,2,1,10"), 0));
+        // the deposit has been pushed out of client 1's window by now
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,2,10"), 0));
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,1,10"), 0));
+        assert_eq!(deduplicator.deduplicated(), 1);
+
+        let mut deduplicator = Deduplicator::new("60s".parse().unwrap());
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,1,10"), 1000));
+        assert!(deduplicator.is_duplicate(&raw("deposit,1,1,10"), 1060));
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,1,10"), 1061));
+        assert!("0s".parse::<DedupWindow>().is_err());
+    }
+
+    #[test]
+    fn should_remember_late_transactions_as_long_as_the_latest() {
+        let mut deduplicator = Deduplicator::new("60s".parse().unwrap());
+        assert!(!deduplicator.is_duplicate(&raw("deposit,2,1,10"), 1000));
+        // out of order, the window trails the latest time seen rather than its own
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,1,10"), 900));
+        assert!(deduplicator.is_duplicate(&raw("deposit,1,1,10"), 1001));
+        assert!(deduplicator.is_duplicate(&raw("deposit,1,1,10"), 1060));
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,1,10"), 1061));
+        assert_eq!(deduplicator.deduplicated(), 2);
+    }
+
+    #[test]
+    fn should_forget_the_clients_with_nothing_in_the_window() {
+        let mut deduplicator = Deduplicator::new("60s".parse().unwrap());
+        for client in 1..=3 {
+            let row = format!("deposit,{},{},10", client, client);
+            assert!(!deduplicator.is_duplicate(&raw(&row), 1000));
+        }
+        assert!(!deduplicator.is_duplicate(&raw("deposit,1,4,10"), 1030));
+        assert_eq!(deduplicator.seen.len(), 3);
+
+        assert!(!deduplicator.is_duplicate(&raw("deposit,4,5,10"), 1070));
+        let mut clients = deduplicator.seen.keys().copied().collect::<Vec<_>>();
+        clients.sort_unstable();
+        assert_eq!(clients, [1, 4]);
+        assert_eq!(deduplicator.seen[&1].keys.len(), 1);
+        assert_eq!(deduplicator.expiries.len(), 2);
+
+        assert!(!deduplicator.is_duplicate(&raw("deposit,4,6,10"), 1200));
+        assert_eq!(deduplicator.seen.len(), 1);
+        assert_eq!(deduplicator.expiries.len(), 1);
+    }
+}
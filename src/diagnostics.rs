@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::screening::Screening;
+use crate::transaction::ClientId;
+
+/// A stored transaction `EngineConfig::pruning` dropped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pruned {
+    pub client: ClientId,
+    pub tx: u32,
+    /// Why it can't be disputed anymore
+    pub reason: String,
+}
+
+/// A transaction that took longer than `EngineConfig::latency_budget` to apply, with what
+/// might explain it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowTransaction {
+    /// The type column
+    pub kind: String,
+    pub client: ClientId,
+    pub tx: u32,
+    pub elapsed: Duration,
+    pub budget: Duration,
+    /// How many transactions the account has stored, and ledger entries
+    pub stored: usize,
+    pub entries: usize,
+    /// How long went to copying an account shared with a clone of the engine
+    pub copy_wait: Duration,
+}
+
+/// A deposit or withdrawal an `Enricher` failed to look up, it's applied without
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnrichmentFailed {
+    pub client: ClientId,
+    pub tx: u32,
+    pub error: String,
+}
+
+/// A screening a `ScreeningObserver` failed to record, e.g. to the audit log. The transaction
+/// is stopped all the same
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnrecordedScreening {
+    pub screening: Screening,
+    pub error: String,
+}
+
+/// Told about what the engine notices about its own work, register with
+/// `TransactionEngine::register_diagnostics_observer`. The engine doesn't log anything itself,
+/// the binary writes these to stderr. Called in the middle of processing, like the alerts
+pub trait DiagnosticsObserver: Send + Sync {
+    fn pruned(&self, _pruned: &Pruned) {}
+    fn slow(&self, _slow: &SlowTransaction) {}
+    fn enrichment_failed(&self, _failed: &EnrichmentFailed) {}
+    fn screening_unrecorded(&self, _unrecorded: &UnrecordedScreening) {}
+}
@@ -0,0 +1,223 @@
+use anyhow::{bail, Result};
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseStatus {
+    Open,
+    Resolved,
+    ChargedBack,
+    /// Nothing happened for so long that the dispute was resolved automatically
+    Expired,
+}
+
+impl CaseStatus {
+    pub const fn name(self) -> &'static str {
+        match self {
+            CaseStatus::Open => "open",
+            CaseStatus::Resolved => "resolved",
+            CaseStatus::ChargedBack => "charged_back",
+            CaseStatus::Expired => "expired",
+        }
+    }
+}
+
+impl Snapshot for CaseStatus {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.tag(*self as u8);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(match decoder.tag()? {
+            0 => CaseStatus::Open,
+            1 => CaseStatus::Resolved,
+            2 => CaseStatus::ChargedBack,
+            3 => CaseStatus::Expired,
+            tag => bail!("Invalid case status {} in snapshot", tag),
+        })
+    }
+}
+
+/// Evidence or other context a support agent attached to a case
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    /// Unix timestamp (seconds)
+    pub added_at: u64,
+    pub text: String,
+}
+
+impl HeapSize for Note {
+    fn heap_size(&self) -> usize {
+        self.text.heap_size()
+    }
+}
+
+impl Snapshot for Note {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.added_at.encode(encoder);
+        self.text.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            added_at: u64::decode(decoder)?,
+            text: String::decode(decoder)?,
+        })
+    }
+}
+
+/// A dispute of a transaction, from the dispute until it's resolved or charged back.
+/// The status follows the transactions the engine processes, so it can't be changed directly
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    client: ClientId,
+    tx: u32,
+    amount: BigDecimal,
+    status: CaseStatus,
+    opened_at: u64,
+    closed_at: Option<u64>,
+    notes: Vec<Note>,
+}
+
+impl Case {
+    pub(crate) fn open(client: ClientId, tx: u32, amount: BigDecimal, opened_at: u64) -> Self {
+        Self {
+            client,
+            tx,
+            amount,
+            status: CaseStatus::Open,
+            opened_at,
+            closed_at: None,
+            notes: vec![],
+        }
+    }
+
+    pub(crate) fn close(&mut self, status: CaseStatus, closed_at: u64) {
+        self.status = status;
+        self.closed_at = Some(closed_at);
+    }
+
+    pub const fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub const fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    /// The amount of the disputed transaction
+    pub const fn amount(&self) -> &BigDecimal {
+        &self.amount
+    }
+
+    pub const fn status(&self) -> CaseStatus {
+        self.status
+    }
+
+    /// Unix timestamp (seconds) of the dispute
+    pub const fn opened_at(&self) -> u64 {
+        self.opened_at
+    }
+
+    /// Unix timestamp (seconds) of the resolve or chargeback, if any
+    pub const fn closed_at(&self) -> Option<u64> {
+        self.closed_at
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// `at` is the engine's time, `TransactionEngine::now`
+    pub fn add_note(&mut self, text: impl Into<String>, at: u64) {
+        self.notes.push(Note {
+            added_at: at,
+            text: text.into(),
+        });
+    }
+}
+
+/// How a client's disputes turned out, counted from its cases
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DisputeStats {
+    pub raised: usize,
+    pub open: usize,
+    pub resolved: usize,
+    pub charged_back: usize,
+    pub expired: usize,
+}
+
+impl DisputeStats {
+    pub fn of(cases: &[Case]) -> Self {
+        let mut stats = Self {
+            raised: cases.len(),
+            ..Self::default()
+        };
+        for case in cases {
+            *match case.status {
+                CaseStatus::Open => &mut stats.open,
+                CaseStatus::Resolved => &mut stats.resolved,
+                CaseStatus::ChargedBack => &mut stats.charged_back,
+                CaseStatus::Expired => &mut stats.expired,
+            } += 1;
+        }
+        stats
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct CsvDisputeStats {
+    pub client: ClientId,
+    pub raised: usize,
+    pub open: usize,
+    pub resolved: usize,
+    pub charged_back: usize,
+    pub expired: usize,
+}
+
+impl CsvDisputeStats {
+    pub const fn new(client: ClientId, stats: DisputeStats) -> Self {
+        Self {
+            client,
+            raised: stats.raised,
+            open: stats.open,
+            resolved: stats.resolved,
+            charged_back: stats.charged_back,
+            expired: stats.expired,
+        }
+    }
+}
+
+impl HeapSize for Case {
+    fn heap_size(&self) -> usize {
+        self.amount.heap_size() + self.notes.heap_size()
+    }
+}
+
+impl Snapshot for Case {
+    fn encode(&self, encoder: &mut Encoder) {
+        Client(self.client).encode(encoder);
+        self.tx.encode(encoder);
+        self.amount.encode(encoder);
+        self.status.encode(encoder);
+        self.opened_at.encode(encoder);
+        self.closed_at.encode(encoder);
+        self.notes.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            client: Client::decode(decoder)?.0,
+            tx: u32::decode(decoder)?,
+            amount: BigDecimal::decode(decoder)?,
+            status: CaseStatus::decode(decoder)?,
+            opened_at: u64::decode(decoder)?,
+            closed_at: Option::decode(decoder)?,
+            notes: Vec::decode(decoder)?,
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Serialize;
+
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+/// An account whose available balance is negative, e.g. after a dispute of money that was
+/// already withdrawn, for collections to follow up on
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvNegativeBalance {
+    pub client: ClientId,
+    pub available: String,
+    pub total: String,
+    /// Unix seconds
+    pub negative_since: u64,
+    pub age_seconds: u64,
+    /// Negative for longer than `EngineConfig::dunning_grace`
+    pub dunning: bool,
+}
+
+/// Every account with a negative available balance, by client. The age is by the engine's
+/// time, which is that of the input once it has timestamps
+pub fn negative_balances(engine: &TransactionEngine) -> Vec<CsvNegativeBalance> {
+    let now = engine.now();
+    let grace = engine.config().dunning_grace;
+    let mut negative = engine
+        .account_views()
+        .filter_map(|account| {
+            let since = account.negative_since()?;
+            let age = now.saturating_sub(since);
+            Some(CsvNegativeBalance {
+                client: account.client(),
+                available: format!("{:.4}", account.available()),
+                total: format!("{:.4}", account.total()),
+                negative_since: since,
+                age_seconds: age,
+                dunning: grace.is_some_and(|grace| age > grace),
+            })
+        })
+        .collect::<Vec<_>>();
+    negative.sort_by_key(|balance| balance.client);
+    negative
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::config::EngineConfig;
+    use crate::transaction::Transaction;
+
+    use super::*;
+
+    #[test]
+    fn should_flag_accounts_negative_for_longer_than_the_grace_period() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            dunning_grace: Some(100),
+            ..EngineConfig::default()
+        });
+        let deposit = |client, tx| Transaction::Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let withdraw = |client, tx| Transaction::Withdrawal {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        engine.advance_time(1000);
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            engine.process(deposit(client, tx)).unwrap();
+            engine.process(withdraw(client, tx + 10)).unwrap();
+        }
+        // disputing deposits that were withdrawn already
+        engine
+            .process(Transaction::Dispute { client: 1, tx: 1 })
+            .unwrap();
+        engine.advance_time(1050);
+        engine
+            .process(Transaction::Dispute { client: 2, tx: 2 })
+            .unwrap();
+        engine.advance_time(1150);
+        let negative = negative_balances(&engine)
+            .into_iter()
+            .map(|balance| (balance.client, balance.age_seconds, balance.dunning))
+            .collect::<Vec<_>>();
+        assert_eq!(negative, [(1, 150, true), (2, 100, false)]);
+
+        engine
+            .process(Transaction::Resolve { client: 1, tx: 1 })
+            .unwrap();
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        assert_eq!(negative_balances(&restored).len(), 1);
+        assert_eq!(negative_balances(&restored)[0].available, "-10.0000");
+    }
+}
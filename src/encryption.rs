@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Start of an encrypted state file, the last two bytes are the format version
+pub const MAGIC: &[u8; 8] = b"PGSEAL\x00\x01";
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+pub type Key = [u8; 32];
+
+/// Where the keys for state at rest come from. Files record the id of the key they were written
+/// with, so older keys keep working after the current one is rotated
+pub trait KeyProvider {
+    /// The key new files are encrypted with
+    fn current(&self) -> Result<(&str, Key)>;
+    fn key(&self, id: &str) -> Result<Key>;
+}
+
+/// Keys from the environment: `PLAYGROUND_STATE_KEYS=2025:<64 hex digits>,2026:<64 hex digits>`,
+/// the current one is `PLAYGROUND_STATE_KEY_ID` or else the last one listed
+pub struct EnvKeys {
+    keys: HashMap<String, Key>,
+    current: Option<String>,
+}
+
+impl EnvKeys {
+    pub fn from_env() -> Result<Self> {
+        let keys = std::env::var("PLAYGROUND_STATE_KEYS")
+            .context("Encrypted state needs the keys in PLAYGROUND_STATE_KEYS")?;
+        Self::parse(&keys, std::env::var("PLAYGROUND_STATE_KEY_ID").ok())
+    }
+
+    pub fn parse(keys: &str, current: Option<String>) -> Result<Self> {
+        let mut parsed = HashMap::new();
+        let mut last = None;
+        for entry in keys
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            // don't echo the entry, it might be a key
+            let (id, hex) = entry
+                .split_once(':')
+                .context("Expected state keys as id:key")?;
+            let key = parse_key(hex).with_context(|| format!("Invalid state key {}", id))?;
+            parsed.insert(id.to_string(), key);
+            last = Some(id.to_string());
+        }
+        let current = current.or(last);
+        if let Some(current) = &current {
+            if !parsed.contains_key(current) {
+                bail!("There's no state key {}", current);
+            }
+        }
+        Ok(Self {
+            keys: parsed,
+            current,
+        })
+    }
+}
+
+impl KeyProvider for EnvKeys {
+    fn current(&self) -> Result<(&str, Key)> {
+        match &self.current {
+            None => bail!("There are no state keys"),
+            Some(id) => Ok((id, self.key(id)?)),
+        }
+    }
+
+    fn key(&self, id: &str) -> Result<Key> {
+        self.keys.get(id).copied().with_context(|| {
+            format!(
+                "The state was encrypted with key {}, which isn't configured",
+                id
+            )
+        })
+    }
+}
+
+fn parse_key(hex: &str) -> Result<Key> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        bail!("Expected 64 hex digits");
+    }
+    let mut key = [0; 32];
+    for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits)?, 16)?;
+    }
+    Ok(key)
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encrypts a snapshot (or any state) with the current key, AES-256-GCM with a random nonce.
+/// The header, including the key id, is authenticated too
+pub fn encrypt_state(plaintext: &[u8], keys: &dyn KeyProvider) -> Result<Vec<u8>> {
+    let (id, key) = keys.current()?;
+    let id_len = u8::try_from(id.len()).context("State key ids can be at most 255 bytes")?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let mut sealed = MAGIC.to_vec();
+    sealed.push(id_len);
+    sealed.extend_from_slice(id.as_bytes());
+    sealed.extend_from_slice(&nonce);
+    let ciphertext = Aes256Gcm::new(&key.into())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &sealed,
+            },
+        )
+        .map_err(|_| anyhow!("Failed to encrypt the state"))?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn decrypt_state(sealed: &[u8], keys: &dyn KeyProvider) -> Result<Vec<u8>> {
+    let rest = match sealed.strip_prefix(MAGIC.as_slice()) {
+        Some(rest) => rest,
+        None if sealed.starts_with(&MAGIC[..6]) => bail!("Unsupported encrypted state version"),
+        None => bail!("The state isn't encrypted"),
+    };
+    let id_len = usize::from(*rest.first().context("Encrypted state is truncated")?);
+    if rest.len() < 1 + id_len + NONCE_LEN + TAG_LEN {
+        bail!("Encrypted state is truncated");
+    }
+    let id = std::str::from_utf8(&rest[1..=id_len]).context("Invalid state key id")?;
+    let nonce = Nonce::from_slice(&rest[1 + id_len..1 + id_len + NONCE_LEN]);
+    let (header, ciphertext) = sealed.split_at(MAGIC.len() + 1 + id_len + NONCE_LEN);
+    Aes256Gcm::new(&keys.key(id)?.into())
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| {
+            anyhow!("Failed to decrypt the state, the key is wrong or the file was modified")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decrypt_state_with_the_key_it_was_encrypted_with() {
+        let old = "07".repeat(32);
+        let new = "2a".repeat(32);
+        let keys = EnvKeys::parse(&format!("old:{}", old), None).unwrap();
+        let sealed = encrypt_state(b"snapshot", &keys).unwrap();
+        assert!(is_encrypted(&sealed));
+
+        let rotated = EnvKeys::parse(&format!("old:{},new:{}", old, new), None).unwrap();
+        assert_eq!(decrypt_state(&sealed, &rotated).unwrap(), b"snapshot");
+        let resealed = encrypt_state(&decrypt_state(&sealed, &rotated).unwrap(), &rotated).unwrap();
+        let only_new = EnvKeys::parse(&format!("new:{}", new), None).unwrap();
+        assert_eq!(decrypt_state(&resealed, &only_new).unwrap(), b"snapshot");
+        assert!(decrypt_state(&sealed, &only_new).is_err());
+    }
+
+    #[test]
+    fn should_reject_state_that_was_modified_or_cut_short() {
+        let keys = EnvKeys::parse(&format!("2026:{}", "5c".repeat(32)), None).unwrap();
+        let sealed = encrypt_state(b"a snapshot of more than one block", &keys).unwrap();
+        // the header with the key id and nonce, the ciphertext and the tag
+        for i in 0..sealed.len() {
+            let mut forged = sealed.clone();
+            forged[i] ^= 1;
+            assert!(decrypt_state(&forged, &keys).is_err(), "byte {}", i);
+        }
+        for len in 0..sealed.len() {
+            assert!(
+                decrypt_state(&sealed[..len], &keys).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+        assert_eq!(
+            decrypt_state(&sealed, &keys).unwrap(),
+            b"a snapshot of more than one block"
+        );
+    }
+}
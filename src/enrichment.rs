@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::http::Target;
+use crate::json::Json;
+use crate::ledger::EntryKind;
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::{self, ClientId, Transaction};
+use crate::transaction_engine::{AccountView, TransactionEngine};
+
+/// What a lookup knows about a transaction, e.g. where a card was used. Kept on the ledger
+/// entries of the deposit or withdrawal and those of the disputes of it, for the statements
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Enrichment {
+    pub merchant: Option<String>,
+    pub category: Option<String>,
+}
+
+impl Enrichment {
+    /// `merchant (category)`, or whichever of them there is
+    pub fn describe(&self) -> String {
+        match (&self.merchant, &self.category) {
+            (Some(merchant), Some(category)) => format!("{} ({})", merchant, category),
+            (Some(merchant), None) => merchant.clone(),
+            (None, Some(category)) => category.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn of(merchant: Option<String>, category: Option<String>) -> Option<Self> {
+        let enrichment = Self {
+            merchant: merchant.filter(|merchant| !merchant.is_empty()),
+            category: category.filter(|category| !category.is_empty()),
+        };
+        (enrichment != Self::default()).then_some(enrichment)
+    }
+}
+
+impl HeapSize for Enrichment {
+    fn heap_size(&self) -> usize {
+        self.merchant.as_ref().map_or(0, String::heap_size)
+            + self.category.as_ref().map_or(0, String::heap_size)
+    }
+}
+
+impl Snapshot for Enrichment {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.merchant.encode(encoder);
+        self.category.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            merchant: Option::decode(decoder)?,
+            category: Option::decode(decoder)?,
+        })
+    }
+}
+
+/// Looks up deposits and withdrawals as they're applied, register with
+/// `TransactionEngine::register_enricher`. The lookup is optional, a transaction it knows
+/// nothing about, or that it fails to look up, is applied all the same. The engine tells its
+/// `DiagnosticsObserver`s about the failures
+pub trait Enricher: Send + Sync {
+    fn enrich(&self, transaction: &Transaction) -> Result<Option<Enrichment>>;
+}
+
+#[derive(Deserialize)]
+struct LookupRow {
+    tx: u32,
+    merchant: Option<String>,
+    category: Option<String>,
+}
+
+/// A csv of `tx,merchant,category`, read up front
+pub struct LookupFile(HashMap<u32, Enrichment>);
+
+impl LookupFile {
+    pub fn read(reader: impl Read) -> Result<Self> {
+        let mut lookup = HashMap::new();
+        for row in transaction::csv_reader(reader).into_deserialize::<LookupRow>() {
+            let row = row.context("Invalid row in the enrichment lookup")?;
+            if let Some(enrichment) = Enrichment::of(row.merchant, row.category) {
+                lookup.insert(row.tx, enrichment);
+            }
+        }
+        Ok(Self(lookup))
+    }
+}
+
+impl Enricher for LookupFile {
+    fn enrich(&self, transaction: &Transaction) -> Result<Option<Enrichment>> {
+        Ok(self.0.get(&transaction.tx()).cloned())
+    }
+}
+
+/// A service answering `GET <url>/<tx>?client=<client>` with
+/// `{"merchant":"...","category":"..."}`, or 404 for transactions it doesn't know
+pub struct LookupService {
+    target: Target,
+}
+
+impl LookupService {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            target: Target::parse(url)?,
+        })
+    }
+}
+
+impl Enricher for LookupService {
+    fn enrich(&self, transaction: &Transaction) -> Result<Option<Enrichment>> {
+        let path = format!("{}?client={}", transaction.tx(), transaction.client());
+        let reply = self
+            .target
+            .send("GET", &path, None, Duration::from_secs(2))?;
+        match reply.status {
+            404 => Ok(None),
+            200 => {
+                let body = Json::parse(&String::from_utf8_lossy(&reply.body))?;
+                let field = |name| body.get(name).and_then(Json::as_str).map(str::to_string);
+                Ok(Enrichment::of(field("merchant"), field("category")))
+            }
+            status => anyhow::bail!("The lookup answered {}", status),
+        }
+    }
+}
+
+/// What one client's enriched deposits and withdrawals of one category came to
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CategoryTotals {
+    pub category: String,
+    pub deposits: usize,
+    pub received: BigDecimal,
+    pub withdrawals: usize,
+    pub spent: BigDecimal,
+    /// What the category did to the total balance, including the chargebacks of its deposits
+    pub net: BigDecimal,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvCategorySpending {
+    pub client: ClientId,
+    pub category: String,
+    pub deposits: usize,
+    pub received: String,
+    pub withdrawals: usize,
+    pub spent: String,
+    pub net: String,
+}
+
+/// The totals per category of the account's ledger, sorted by category. Entries without a
+/// category aren't in any of them
+pub fn category_totals(account: &AccountView<'_>) -> Vec<CategoryTotals> {
+    let mut totals = BTreeMap::<&str, CategoryTotals>::new();
+    for entry in account.ledger() {
+        let Some(category) = entry
+            .enrichment
+            .as_ref()
+            .and_then(|enrichment| enrichment.category.as_deref())
+        else {
+            continue;
+        };
+        let totals = totals.entry(category).or_insert_with(|| CategoryTotals {
+            category: category.to_string(),
+            ..CategoryTotals::default()
+        });
+        match entry.kind {
+            EntryKind::Deposit => {
+                totals.deposits += 1;
+                totals.received += &entry.available;
+            }
+            EntryKind::Withdrawal => {
+                totals.withdrawals += 1;
+                totals.spent -= &entry.available;
+            }
+            _ => {}
+        }
+        totals.net += entry.total();
+    }
+    totals.into_values().collect()
+}
+
+/// One row per client and category, for spending insights, sorted by client and category
+pub fn spending(engine: &TransactionEngine) -> Vec<CsvCategorySpending> {
+    let mut accounts = engine.account_views().collect::<Vec<_>>();
+    accounts.sort_by_key(|account| account.client());
+    accounts
+        .iter()
+        .flat_map(|account| {
+            category_totals(account)
+                .into_iter()
+                .map(|totals| CsvCategorySpending {
+                    client: account.client(),
+                    category: totals.category,
+                    deposits: totals.deposits,
+                    received: format!("{:.4}", totals.received),
+                    withdrawals: totals.withdrawals,
+                    spent: format!("{:.4}", totals.spent),
+                    net: format!("{:.4}", totals.net),
+                })
+        })
+        .collect()
+}
+
+/// A lookup file, or a service when it's an `http://` url
+pub fn open(lookup: &str) -> Result<Box<dyn Enricher>> {
+    if lookup.starts_with("http://") {
+        return Ok(Box::new(LookupService::new(lookup)?));
+    }
+    let file = std::fs::File::open(lookup)
+        .with_context(|| format!("Failed to open the enrichment lookup {}", lookup))?;
+    Ok(Box::new(LookupFile::read(file)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::diagnostics::{DiagnosticsObserver, EnrichmentFailed};
+
+    #[test]
+    fn should_enrich_transactions_and_their_disputes() {
+        let lookup = "tx,merchant,category\n1,ACME Groceries,groceries\n2,,transport\n3,,\n";
+        let mut engine = TransactionEngine::new();
+        engine.register_enricher(Box::new(LookupFile::read(lookup.as_bytes()).unwrap()));
+        for transaction in [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(2),
+                wallet: None,
+            },
+            Transaction::Deposit {
+                client: 1,
+                tx: 3,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            },
+            Transaction::Dispute { client: 1, tx: 1 },
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), EngineConfig::default()).unwrap();
+        let enrichments = restored
+            .account(1)
+            .unwrap()
+            .ledger()
+            .iter()
+            .map(|entry| {
+                (
+                    entry.kind,
+                    entry.enrichment.as_deref().map(Enrichment::describe),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            enrichments,
+            [
+                (
+                    EntryKind::Deposit,
+                    Some("ACME Groceries (groceries)".to_string())
+                ),
+                (EntryKind::Withdrawal, Some("transport".to_string())),
+                (EntryKind::Deposit, None),
+                (
+                    EntryKind::Hold,
+                    Some("ACME Groceries (groceries)".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_failed_lookups_and_ask_the_next_enricher() {
+        struct Unreachable;
+        impl Enricher for Unreachable {
+            fn enrich(&self, _transaction: &Transaction) -> Result<Option<Enrichment>> {
+                anyhow::bail!("The lookup answered 503")
+            }
+        }
+        struct Collect(std::sync::Arc<std::sync::Mutex<Vec<EnrichmentFailed>>>);
+        impl DiagnosticsObserver for Collect {
+            fn enrichment_failed(&self, failed: &EnrichmentFailed) {
+                self.0.lock().unwrap().push(failed.clone());
+            }
+        }
+
+        let failed = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut engine = TransactionEngine::new();
+        engine.register_diagnostics_observer(Box::new(Collect(failed.clone())));
+        engine.register_enricher(Box::new(Unreachable));
+        engine.register_enricher(Box::new(
+            LookupFile::read("tx,merchant,category\n1,,groceries\n".as_bytes()).unwrap(),
+        ));
+        engine
+            .process(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            })
+            .unwrap();
+
+        let ledger = engine.account(1).unwrap().ledger();
+        assert_eq!(
+            ledger[0].enrichment.as_deref().map(Enrichment::describe),
+            Some("groceries".to_string())
+        );
+        assert_eq!(
+            *failed.lock().unwrap(),
+            [EnrichmentFailed {
+                client: 1,
+                tx: 1,
+                error: "The lookup answered 503".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn should_total_spending_per_client_and_category() {
+        let lookup = "tx,merchant,category
+                      1,Payroll,salary
+                      2,ACME Groceries,groceries
+                      3,Corner Shop,groceries
+                      4,City Transit,transport
+                      5,Payroll,salary
+                      6,Bakery,
+";
+        let mut engine = TransactionEngine::new();
+        engine.register_enricher(Box::new(LookupFile::read(lookup.as_bytes()).unwrap()));
+        for (client, tx, amount, deposit) in [
+            (1, 1, 100, true),
+            (1, 2, 20, false),
+            (1, 3, 5, false),
+            (1, 4, 3, false),
+            (2, 5, 40, true),
+            (1, 6, 2, false),
+        ] {
+            let amount = BigDecimal::from(amount);
+            engine
+                .process(match deposit {
+                    true => Transaction::Deposit {
+                        client,
+                        tx,
+                        amount,
+                        wallet: None,
+                    },
+                    false => Transaction::Withdrawal {
+                        client,
+                        tx,
+                        amount,
+                        wallet: None,
+                    },
+                })
+                .unwrap();
+        }
+        engine
+            .process(Transaction::Dispute { client: 2, tx: 5 })
+            .unwrap();
+        engine
+            .process(Transaction::Chargeback { client: 2, tx: 5 })
+            .unwrap();
+
+        let rows = spending(&engine)
+            .into_iter()
+            .map(|row| {
+                (
+                    row.client,
+                    row.category,
+                    row.deposits,
+                    row.received,
+                    row.withdrawals,
+                    row.spent,
+                    row.net,
+                )
+            })
+            .collect::<Vec<_>>();
+        let row = |client,
+                   category: &str,
+                   deposits,
+                   received: &str,
+                   withdrawals,
+                   spent: &str,
+                   net: &str| {
+            (
+                client,
+                category.to_string(),
+                deposits,
+                received.to_string(),
+                withdrawals,
+                spent.to_string(),
+                net.to_string(),
+            )
+        };
+        assert_eq!(
+            rows,
+            [
+                row(1, "groceries", 0, "0.0000", 2, "25.0000", "-25.0000"),
+                row(1, "salary", 1, "100.0000", 0, "0.0000", "100.0000"),
+                row(1, "transport", 0, "0.0000", 1, "3.0000", "-3.0000"),
+                row(2, "salary", 1, "40.0000", 0, "0.0000", "0.0000"),
+            ]
+        );
+
+        let info = crate::iso20022::MessageInfo {
+            currency: "EUR".to_string(),
+            created_at: 0,
+        };
+        let mut statement = vec![];
+        crate::iso20022::write_camt053(&engine, &info, &mut statement).unwrap();
+        assert!(String::from_utf8(statement).unwrap().contains(
+            "<AddtlStmtInf>groceries received 0.0000 spent 25.0000; \
+             salary received 100.0000 spent 0.0000; \
+             transport received 0.0000 spent 3.0000</AddtlStmtInf>"
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+
+use crate::transaction_engine::TransactionError;
+
+/// The stable code of everything a row can fail with, for automation to match on instead of
+/// the messages, which change. E1xxx are transactions the engine rejected, E2xxx rows it
+/// couldn't parse. Codes are never reused, a code that's no longer used stays retired
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorCode {
+    InsufficientFunds,
+    AccountLocked,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    HeldForReview,
+    RejectedByRule,
+    ExcessDecimals,
+    UnknownAccount,
+    Pruned,
+    TooManyAccounts,
+    TooManyTransactions,
+    RejectedByHandler,
+    TransformFailed,
+    InvalidTransfer,
+    InvalidEscrow,
+    UnknownEscrow,
+    PendingCommit,
+    UnknownPrepared,
+    Blocked,
+    OpeningConflict,
+    NotPendingApproval,
+    BelowReserve,
+    InvalidPromoCredit,
+    InvalidHold,
+    AccountClosed,
+    InvalidReversal,
+    AlreadyReversed,
+    /// The csv reader couldn't read the row or it doesn't fit the columns, e.g. a client
+    /// that isn't a number
+    MalformedRow,
+    /// The amount isn't written in the input's amount format
+    InvalidAmount,
+    MissingAmount,
+    /// An `escrow_open` without a seller
+    MissingSeller,
+    /// A `promo_credit` without an expiry
+    MissingExpiry,
+    /// A type that's neither built in nor handled by a plugin
+    UnknownType,
+}
+
+impl ErrorCode {
+    pub const ALL: [Self; 34] = [
+        Self::InsufficientFunds,
+        Self::AccountLocked,
+        Self::UnknownTransaction,
+        Self::AlreadyDisputed,
+        Self::NotDisputed,
+        Self::HeldForReview,
+        Self::RejectedByRule,
+        Self::ExcessDecimals,
+        Self::UnknownAccount,
+        Self::Pruned,
+        Self::TooManyAccounts,
+        Self::TooManyTransactions,
+        Self::RejectedByHandler,
+        Self::TransformFailed,
+        Self::InvalidTransfer,
+        Self::InvalidEscrow,
+        Self::UnknownEscrow,
+        Self::PendingCommit,
+        Self::UnknownPrepared,
+        Self::Blocked,
+        Self::OpeningConflict,
+        Self::NotPendingApproval,
+        Self::BelowReserve,
+        Self::InvalidPromoCredit,
+        Self::InvalidHold,
+        Self::AccountClosed,
+        Self::InvalidReversal,
+        Self::AlreadyReversed,
+        Self::MalformedRow,
+        Self::InvalidAmount,
+        Self::MissingAmount,
+        Self::MissingSeller,
+        Self::UnknownType,
+        Self::MissingExpiry,
+    ];
+
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::InsufficientFunds => "E1001",
+            Self::AccountLocked => "E1002",
+            Self::UnknownTransaction => "E1003",
+            Self::AlreadyDisputed => "E1004",
+            Self::NotDisputed => "E1005",
+            Self::HeldForReview => "E1006",
+            Self::RejectedByRule => "E1007",
+            Self::ExcessDecimals => "E1008",
+            Self::UnknownAccount => "E1009",
+            Self::Pruned => "E1010",
+            Self::TooManyAccounts => "E1011",
+            Self::TooManyTransactions => "E1012",
+            Self::RejectedByHandler => "E1013",
+            Self::TransformFailed => "E1014",
+            Self::InvalidTransfer => "E1015",
+            Self::InvalidEscrow => "E1016",
+            Self::UnknownEscrow => "E1017",
+            Self::PendingCommit => "E1018",
+            Self::UnknownPrepared => "E1019",
+            Self::Blocked => "E1020",
+            Self::OpeningConflict => "E1021",
+            Self::NotPendingApproval => "E1022",
+            Self::BelowReserve => "E1023",
+            Self::InvalidPromoCredit => "E1024",
+            Self::InvalidHold => "E1025",
+            Self::AccountClosed => "E1026",
+            Self::InvalidReversal => "E1027",
+            Self::AlreadyReversed => "E1028",
+            Self::MalformedRow => "E2001",
+            Self::InvalidAmount => "E2002",
+            Self::MissingAmount => "E2003",
+            Self::MissingSeller => "E2004",
+            Self::UnknownType => "E2005",
+            Self::MissingExpiry => "E2006",
+        }
+    }
+
+    /// An error with this code, `message` is what it says
+    pub fn error(self, message: impl fmt::Display) -> CodedError {
+        CodedError {
+            code: self,
+            message: message.to_string(),
+        }
+    }
+
+    /// The code of an error, if it or one of its causes has one
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        error
+            .downcast_ref::<CodedError>()
+            .map(|coded| coded.code)
+            .or_else(|| {
+                error
+                    .downcast_ref::<TransactionError>()
+                    .map(TransactionError::code)
+            })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// An error of a row with its code, the code isn't part of the message
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn should_find_the_code_of_an_error() {
+        let mut codes = ErrorCode::ALL.map(ErrorCode::code).to_vec();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+        assert_eq!(ErrorCode::InsufficientFunds.code(), "E1001");
+        assert_eq!(ErrorCode::AccountLocked.code(), "E1002");
+
+        let error = Err::<(), _>(ErrorCode::MissingAmount.error("Transaction 1 has no amount"))
+            .context("Failed to read input.csv")
+            .unwrap_err();
+        assert_eq!(ErrorCode::of(&error), Some(ErrorCode::MissingAmount));
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to read input.csv: Transaction 1 has no amount"
+        );
+        let error = anyhow::Error::new(TransactionError::AccountLocked);
+        assert_eq!(ErrorCode::of(&error), Some(ErrorCode::AccountLocked));
+        assert_eq!(ErrorCode::of(&anyhow::anyhow!("disk full")), None);
+    }
+}
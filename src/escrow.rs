@@ -0,0 +1,166 @@
+use anyhow::{bail, Result};
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscrowStatus {
+    Open,
+    /// The seller got the money
+    Released,
+    /// The buyer got the money back
+    Refunded,
+}
+
+impl EscrowStatus {
+    pub const fn name(self) -> &'static str {
+        match self {
+            EscrowStatus::Open => "open",
+            EscrowStatus::Released => "released",
+            EscrowStatus::Refunded => "refunded",
+        }
+    }
+}
+
+impl Snapshot for EscrowStatus {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.tag(*self as u8);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(match decoder.tag()? {
+            0 => EscrowStatus::Open,
+            1 => EscrowStatus::Released,
+            2 => EscrowStatus::Refunded,
+            tag => bail!("Invalid escrow status {} in snapshot", tag),
+        })
+    }
+}
+
+/// Money a buyer has put aside for a seller, held in the buyer's account from the
+/// `escrow_open` until an `escrow_release` pays the seller or an `escrow_refund` gives it back.
+/// Like a dispute case it follows the transactions, it can't be changed directly
+#[derive(Clone, Debug, PartialEq)]
+pub struct Escrow {
+    buyer: ClientId,
+    seller: ClientId,
+    tx: u32,
+    amount: BigDecimal,
+    status: EscrowStatus,
+    opened_at: u64,
+    closed_at: Option<u64>,
+}
+
+impl Escrow {
+    pub(crate) fn open(
+        buyer: ClientId,
+        seller: ClientId,
+        tx: u32,
+        amount: BigDecimal,
+        opened_at: u64,
+    ) -> Self {
+        Self {
+            buyer,
+            seller,
+            tx,
+            amount,
+            status: EscrowStatus::Open,
+            opened_at,
+            closed_at: None,
+        }
+    }
+
+    pub(crate) fn close(&mut self, status: EscrowStatus, closed_at: u64) {
+        self.status = status;
+        self.closed_at = Some(closed_at);
+    }
+
+    pub const fn buyer(&self) -> ClientId {
+        self.buyer
+    }
+
+    pub const fn seller(&self) -> ClientId {
+        self.seller
+    }
+
+    /// The tx of the `escrow_open`, the release or refund refers to it
+    pub const fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    pub const fn amount(&self) -> &BigDecimal {
+        &self.amount
+    }
+
+    pub const fn status(&self) -> EscrowStatus {
+        self.status
+    }
+
+    /// Unix timestamp (seconds)
+    pub const fn opened_at(&self) -> u64 {
+        self.opened_at
+    }
+
+    /// Unix timestamp (seconds) of the release or refund, if any
+    pub const fn closed_at(&self) -> Option<u64> {
+        self.closed_at
+    }
+}
+
+impl HeapSize for Escrow {
+    fn heap_size(&self) -> usize {
+        self.amount.heap_size()
+    }
+}
+
+impl Snapshot for Escrow {
+    fn encode(&self, encoder: &mut Encoder) {
+        Client(self.buyer).encode(encoder);
+        Client(self.seller).encode(encoder);
+        self.tx.encode(encoder);
+        self.amount.encode(encoder);
+        self.status.encode(encoder);
+        self.opened_at.encode(encoder);
+        self.closed_at.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            buyer: Client::decode(decoder)?.0,
+            seller: Client::decode(decoder)?.0,
+            tx: u32::decode(decoder)?,
+            amount: BigDecimal::decode(decoder)?,
+            status: EscrowStatus::decode(decoder)?,
+            opened_at: u64::decode(decoder)?,
+            closed_at: Option::decode(decoder)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvEscrow {
+    pub buyer: ClientId,
+    pub seller: ClientId,
+    pub tx: u32,
+    pub amount: String,
+    pub status: &'static str,
+    pub opened_at: u64,
+    pub closed_at: Option<u64>,
+}
+
+impl From<&Escrow> for CsvEscrow {
+    fn from(escrow: &Escrow) -> Self {
+        Self {
+            buyer: escrow.buyer,
+            seller: escrow.seller,
+            tx: escrow.tx,
+            amount: format!("{:.4}", escrow.amount),
+            status: escrow.status.name(),
+            opened_at: escrow.opened_at,
+            closed_at: escrow.closed_at,
+        }
+    }
+}
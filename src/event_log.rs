@@ -0,0 +1,399 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::chaos::{self, Seam};
+use crate::config::EngineConfig;
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+/// The changes to a server's engine, appended as they're made so a restart continues where it
+/// left off, in a directory of snapshots and segments named after the sequence number they
+/// start at:
+/// ```text
+/// 00000000000000000120.snapshot  the engine at sequence 120
+/// 00000000000000000120.log       every change after it, as the output of `changes_since`
+/// ```
+/// Every so often the log is compacted: the engine is folded into a new snapshot, a new
+/// segment is started and the snapshots and segments past the retention are deleted, so the
+/// directory doesn't keep growing.
+/// A change is only confirmed once its record is whole on disk, so the log is also the outbox
+/// of what changed: `replay` reads back the same changes, in the same order, however often
+pub struct EventLog {
+    dir: PathBuf,
+    segment: File,
+    /// The sequence number the log has the changes up to
+    sequence: u64,
+    /// That of the latest snapshot, what the current segment is named after
+    snapshot: u64,
+    /// Seconds between compactions
+    compact_every: u64,
+    /// Unix seconds
+    compacted_at: u64,
+    /// How many snapshots are kept, with the segments after them
+    retention: usize,
+}
+
+impl EventLog {
+    /// Whether there's anything in `dir` to recover from
+    pub fn exists(dir: &str) -> bool {
+        files(Path::new(dir), "snapshot").is_ok_and(|snapshots| !snapshots.is_empty())
+    }
+
+    /// The engine as it was after the last change logged in `dir`: the latest snapshot that can
+    /// be read with the segments from it on applied. A change cut short by a crash is the last
+    /// one and was never confirmed, it's dropped. What was left out is returned with it
+    pub fn recover(dir: &str, config: EngineConfig) -> Result<(TransactionEngine, Recovery)> {
+        let dir = Path::new(dir);
+        let snapshots = files(dir, "snapshot")?;
+        let mut recovery = Recovery::default();
+        let (start, mut engine) = snapshots
+            .iter()
+            .rev()
+            .find_map(|&sequence| {
+                let path = file(dir, sequence, "snapshot");
+                let engine = fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|snapshot| {
+                        TransactionEngine::from_snapshot(&snapshot, config.clone())
+                    });
+                match engine {
+                    Ok(engine) => Some((sequence, engine)),
+                    Err(e) => {
+                        recovery.skipped_snapshots.push((path, format!("{:#}", e)));
+                        None
+                    }
+                }
+            })
+            .with_context(|| format!("No snapshot in {} could be read", dir.display()))?;
+        for sequence in files(dir, "log")?.into_iter().filter(|&s| s >= start) {
+            let path = file(dir, sequence, "log");
+            let segment = fs::read(&path)
+                .with_context(|| format!("Failed to read segment {}", path.display()))?;
+            let mut rest = segment.as_slice();
+            while let Some((record, after)) = split_record(rest) {
+                engine
+                    .apply_changes(record)
+                    .with_context(|| format!("Invalid change in segment {}", path.display()))?;
+                rest = after;
+            }
+            if !rest.is_empty() {
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)?
+                    .set_len((segment.len() - rest.len()) as u64)?;
+                recovery.truncated_segments.push(path);
+            }
+        }
+        Ok((engine, recovery))
+    }
+
+    /// Reads the log in `dir` back from the latest snapshot at or before sequence `since` and
+    /// calls `each` with the engine after every change logged after it, e.g. to relay them.
+    /// Without `since` it starts at the oldest snapshot, which is passed to `each` first as a
+    /// change of every account in it. Only reads, a change still being written is left for
+    /// the next time. Fails if the changes after `since` were compacted away already
+    pub fn replay(
+        dir: &str,
+        since: Option<u64>,
+        config: EngineConfig,
+        mut each: impl FnMut(&TransactionEngine, &LoggedChange) -> Result<()>,
+    ) -> Result<()> {
+        let path = Path::new(dir);
+        let snapshots = files(path, "snapshot")?;
+        let start = match since {
+            None => snapshots.first(),
+            Some(since) => snapshots.iter().rev().find(|&&sequence| sequence <= since),
+        };
+        let start = *start.with_context(|| match since {
+            None => format!("No snapshot in {}", dir),
+            Some(since) => format!(
+                "The event log {} starts after sequence {}, the changes after it were compacted away",
+                dir, since
+            ),
+        })?;
+        let snapshot = file(path, start, "snapshot");
+        let mut engine = fs::read(&snapshot)
+            .map_err(anyhow::Error::from)
+            .and_then(|snapshot| TransactionEngine::from_snapshot(&snapshot, config))
+            .with_context(|| format!("Failed to read snapshot {}", snapshot.display()))?;
+        if since.is_none() {
+            each(&engine, &LoggedChange::of(&engine, 0))?;
+        }
+        let since = since.unwrap_or(start);
+        for sequence in files(path, "log")?.into_iter().filter(|&s| s >= start) {
+            let segment_path = file(path, sequence, "log");
+            let segment = fs::read(&segment_path)
+                .with_context(|| format!("Failed to read segment {}", segment_path.display()))?;
+            let mut rest = segment.as_slice();
+            while let Some((record, after)) = split_record(rest) {
+                let before = engine.sequence();
+                engine.apply_changes(record).with_context(|| {
+                    format!("Invalid change in segment {}", segment_path.display())
+                })?;
+                if engine.sequence() > since {
+                    each(&engine, &LoggedChange::of(&engine, before))?;
+                }
+                rest = after;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts logging the changes to `transaction_engine` in `dir`, with a snapshot of it,
+    /// compacting every `compact_every` seconds and keeping `retention` snapshots
+    pub fn open(
+        dir: &str,
+        transaction_engine: &TransactionEngine,
+        compact_every: u64,
+        retention: usize,
+        now: u64,
+    ) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create the event log {}", dir.display()))?;
+        let sequence = transaction_engine.sequence();
+        let mut log = Self {
+            segment: start_segment(&dir, sequence)?,
+            dir,
+            sequence,
+            snapshot: sequence,
+            compact_every,
+            compacted_at: now,
+            retention: retention.max(1),
+        };
+        log.compact(transaction_engine)?;
+        Ok(log)
+    }
+
+    /// Appends what changed since the last call, and compacts the log if it's time to. Failing
+    /// to is fatal, like failing to write `--ha-state`, the change would be lost on a restart.
+    /// What a failed append wrote is cut off again, so it can be retried
+    pub fn append(&mut self, transaction_engine: &TransactionEngine, now: u64) -> Result<()> {
+        if transaction_engine.sequence() > self.sequence {
+            if let Some(changes) = transaction_engine.changes_since(self.sequence) {
+                let length = u32::try_from(changes.len()).context("The change is too large")?;
+                let end = self.segment.metadata()?.len();
+                let written = self
+                    .segment
+                    .write_all(&length.to_le_bytes())
+                    .and_then(|_| chaos::inject(Seam::Store))
+                    .and_then(|_| self.segment.write_all(&changes))
+                    .and_then(|_| self.segment.sync_data());
+                if let Err(e) = written {
+                    // recovery stops at a torn record, the changes appended after it would be lost
+                    self.segment.set_len(end)?;
+                    return Err(e).context("Failed to write to the event log");
+                }
+            }
+            self.sequence = transaction_engine.sequence();
+        }
+        if now >= self.compacted_at + self.compact_every {
+            self.compact(transaction_engine)?;
+            self.compacted_at = now;
+        }
+        Ok(())
+    }
+
+    /// Folds the log into a snapshot of the engine and deletes what's past the retention
+    pub fn compact(&mut self, transaction_engine: &TransactionEngine) -> Result<()> {
+        let sequence = transaction_engine.sequence();
+        let path = file(&self.dir, sequence, "snapshot");
+        if sequence != self.snapshot || !path.exists() {
+            let temporary = path.with_extension("tmp");
+            let mut snapshot = File::create(&temporary)
+                .with_context(|| format!("Failed to write the snapshot {}", path.display()))?;
+            chaos::inject(Seam::Store)?;
+            snapshot.write_all(&transaction_engine.snapshot())?;
+            snapshot.sync_all()?;
+            fs::rename(&temporary, &path)
+                .with_context(|| format!("Failed to replace the snapshot {}", path.display()))?;
+            if sequence != self.snapshot {
+                self.segment = start_segment(&self.dir, sequence)?;
+            }
+            self.sequence = sequence;
+            self.snapshot = sequence;
+        }
+
+        let snapshots = files(&self.dir, "snapshot")?;
+        let expired = snapshots.len().saturating_sub(self.retention);
+        let oldest = snapshots.get(expired).copied().unwrap_or(sequence);
+        for &old in &snapshots[..expired] {
+            fs::remove_file(file(&self.dir, old, "snapshot"))?;
+        }
+        for old in files(&self.dir, "log")?.into_iter().filter(|&s| s < oldest) {
+            fs::remove_file(file(&self.dir, old, "log"))?;
+        }
+        Ok(())
+    }
+}
+
+/// What `EventLog::recover` left out to get the engine back, for the caller to report
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// The snapshots newer than the one recovered from that couldn't be read, with why
+    pub skipped_snapshots: Vec<(PathBuf, String)>,
+    /// The segments a change cut short by a crash was dropped from the end of
+    pub truncated_segments: Vec<PathBuf>,
+}
+
+/// One change read back from the log by `EventLog::replay`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoggedChange {
+    /// The sequence number the change brought the engine to, unique to the change
+    pub sequence: u64,
+    /// The engine's `applied_sequence` after the change
+    pub applied: u64,
+    /// The clients whose accounts it changed, in order
+    pub clients: Vec<ClientId>,
+}
+
+impl LoggedChange {
+    fn of(engine: &TransactionEngine, before: u64) -> Self {
+        Self {
+            sequence: engine.sequence(),
+            applied: engine.applied_sequence(),
+            clients: engine.changed_since(before),
+        }
+    }
+}
+
+fn file(dir: &Path, sequence: u64, extension: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", sequence, extension))
+}
+
+/// The sequence numbers of the files with the extension in `dir`, in order
+fn files(dir: &Path, extension: &str) -> Result<Vec<u64>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("Failed to list {}", dir.display())),
+    };
+    let mut sequences = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == extension) {
+            if let Some(sequence) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                sequences.push(sequence);
+            }
+        }
+    }
+    sequences.sort_unstable();
+    Ok(sequences)
+}
+
+fn start_segment(dir: &Path, sequence: u64) -> Result<File> {
+    let path = file(dir, sequence, "log");
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open the segment {}", path.display()))
+}
+
+/// The first record of a segment and what's after it, `None` if there isn't a whole one
+fn split_record(segment: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (length, rest) = segment.split_first_chunk::<4>()?;
+    let length = usize::try_from(u32::from_le_bytes(*length)).ok()?;
+    (rest.len() >= length).then(|| rest.split_at(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn should_recover_from_the_log_after_compacting_it() {
+        let dir = std::env::temp_dir().join(format!("event-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.to_str().unwrap();
+        let deposit = |tx| Transaction::Deposit {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let balance = |engine: &TransactionEngine| engine.account_views().next().unwrap().total();
+        assert!(!EventLog::exists(path));
+
+        let mut engine = TransactionEngine::new();
+        engine.process(deposit(1)).unwrap();
+        let mut log = EventLog::open(path, &engine, 60, 2, 1000).unwrap();
+        for tx in 2..=4 {
+            engine.process(deposit(tx)).unwrap();
+            log.append(&engine, 1000 + u64::from(tx)).unwrap();
+        }
+        assert_eq!(files(&dir, "snapshot").unwrap(), [1]);
+        let (recovered, recovery) = EventLog::recover(path, EngineConfig::default()).unwrap();
+        assert_eq!(balance(&recovered), BigDecimal::from(40));
+        assert_eq!(recovery, Recovery::default());
+        let replayed = |since| {
+            let mut changes = vec![];
+            EventLog::replay(path, since, EngineConfig::default(), |engine, change| {
+                changes.push((change.sequence, change.applied, balance(engine)));
+                assert_eq!(change.clients, [1]);
+                Ok(())
+            })
+            .map(|()| changes)
+        };
+        let ten = |times| BigDecimal::from(10 * times);
+        assert_eq!(
+            replayed(None).unwrap(),
+            [
+                (1, 1, ten(1)),
+                (2, 2, ten(2)),
+                (3, 3, ten(3)),
+                (4, 4, ten(4))
+            ]
+        );
+        assert_eq!(replayed(Some(3)).unwrap(), [(4, 4, ten(4))]);
+
+        // every minute the log is folded into a snapshot, only the last two are kept
+        for tx in 5..=7 {
+            engine.process(deposit(tx)).unwrap();
+            log.append(&engine, 1000 + u64::from(tx - 4) * 60).unwrap();
+        }
+        assert_eq!(files(&dir, "snapshot").unwrap(), [6, 7]);
+        assert_eq!(files(&dir, "log").unwrap(), [6, 7]);
+        // a relay that fell behind the retention would miss changes
+        assert!(replayed(Some(4)).is_err());
+        assert_eq!(replayed(Some(6)).unwrap(), [(7, 7, ten(7))]);
+
+        // a change the crash cut short is dropped
+        engine.process(deposit(8)).unwrap();
+        log.append(&engine, 1200).unwrap();
+        let segment = file(&dir, 7, "log");
+        let length = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(length - 1)
+            .unwrap();
+        let (recovered, recovery) = EventLog::recover(path, EngineConfig::default()).unwrap();
+        assert_eq!(recovered.sequence(), 7);
+        assert_eq!(balance(&recovered), BigDecimal::from(70));
+        assert_eq!(recovery.truncated_segments, [segment]);
+        assert!(recovery.skipped_snapshots.is_empty());
+
+        // a snapshot that can't be read is passed over for the one before it
+        let broken = file(&dir, 8, "snapshot");
+        fs::write(&broken, b"not a snapshot").unwrap();
+        let (recovered, recovery) = EventLog::recover(path, EngineConfig::default()).unwrap();
+        assert_eq!(recovered.sequence(), 7);
+        assert_eq!(recovery.skipped_snapshots.len(), 1);
+        assert_eq!(recovery.skipped_snapshots[0].0, broken);
+        assert!(recovery.truncated_segments.is_empty());
+        assert!(EventLog::exists(path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
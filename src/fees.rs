@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::transaction::{ClientId, Transaction};
+
+/// What deposits and withdrawals cost, read from a file with the account the fees go to and
+/// the fee of each transaction type:
+/// ```text
+/// # the fees accrue to the internal client 65535
+/// account 65535
+/// deposit 0.5%
+/// withdrawal 1% + 0.25
+/// # what the card network bills us for a chargeback
+/// chargeback 15
+/// # paid by the fee account instead of the client
+/// chargeback_payer fee_account
+/// ```
+/// A fee is a percentage of the amount, a flat amount or both, rounded to 4 decimals.
+/// A chargeback's fee is a flat amount. The fee account's own transactions are free
+#[derive(Clone, Debug, Default)]
+pub struct FeeSchedule {
+    account: Option<ClientId>,
+    deposit: Option<Fee>,
+    withdrawal: Option<Fee>,
+    chargeback: Option<Fee>,
+    /// Whether the fee account pays the chargeback fees rather than the clients
+    absorbs_chargebacks: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fee {
+    pub percent: BigDecimal,
+    pub flat: BigDecimal,
+}
+
+impl FeeSchedule {
+    pub fn parse(schedule: &str) -> Result<Self> {
+        let mut fees = Self::default();
+        for (index, line) in schedule.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("Expected a type and its fee on line {}", index + 1))?;
+            let invalid = || format!("Invalid {} on line {}", key, index + 1);
+            match key {
+                "account" => fees.account = Some(value.trim().parse().with_context(invalid)?),
+                "deposit" => fees.deposit = Some(value.parse().with_context(invalid)?),
+                "withdrawal" => fees.withdrawal = Some(value.parse().with_context(invalid)?),
+                "chargeback" => {
+                    let fee: Fee = value.parse().with_context(invalid)?;
+                    if !fee.percent.is_zero() {
+                        bail!(
+                            "A chargeback fee is a flat amount, line {} isn't",
+                            index + 1
+                        );
+                    }
+                    fees.chargeback = Some(fee);
+                }
+                "chargeback_payer" => {
+                    fees.absorbs_chargebacks = match value.trim() {
+                        "client" => false,
+                        "fee_account" => true,
+                        _ => bail!(
+                            "Expected client or fee_account as the chargeback_payer on line {}",
+                            index + 1
+                        ),
+                    }
+                }
+                _ => bail!("Unknown fee {} on line {}", key, index + 1),
+            }
+        }
+        if fees.account.is_none() && !fees.is_empty() {
+            bail!("The fee schedule has no account for the fees");
+        }
+        Ok(fees)
+    }
+
+    /// The client the fees are credited to
+    pub const fn account(&self) -> Option<ClientId> {
+        self.account
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.deposit.is_none() && self.withdrawal.is_none() && self.chargeback.is_none()
+    }
+
+    /// What the transaction costs its client, zero if it's free. A deposit's fee is never
+    /// more than the deposit, a chargeback's is only charged if it succeeds
+    pub fn fee(&self, transaction: &Transaction) -> BigDecimal {
+        let fee = match transaction {
+            _ if Some(transaction.client()) == self.account => None,
+            Transaction::Chargeback { .. } if self.absorbs_chargebacks => None,
+            Transaction::Deposit { amount, .. } => self
+                .deposit
+                .as_ref()
+                .map(|fee| fee.of(amount).min(amount.clone())),
+            Transaction::Withdrawal { amount, .. } => {
+                self.withdrawal.as_ref().map(|fee| fee.of(amount))
+            }
+            Transaction::Chargeback { .. } => self.chargeback.as_ref().map(|fee| fee.flat.clone()),
+            _ => None,
+        };
+        fee.filter(|fee| fee > &BigDecimal::zero())
+            .unwrap_or_else(BigDecimal::zero)
+    }
+
+    /// What the fee account pays for the client's chargeback itself, with
+    /// `chargeback_payer fee_account`, zero if the client pays its fee
+    pub fn absorbed_fee(&self, transaction: &Transaction) -> BigDecimal {
+        match (transaction, &self.chargeback) {
+            (Transaction::Chargeback { client, .. }, Some(fee))
+                if self.absorbs_chargebacks && Some(*client) != self.account =>
+            {
+                fee.flat.clone()
+            }
+            _ => BigDecimal::zero(),
+        }
+    }
+}
+
+impl Fee {
+    pub fn of(&self, amount: &BigDecimal) -> BigDecimal {
+        (amount * &self.percent / BigDecimal::from(100) + &self.flat).round(4)
+    }
+}
+
+impl FromStr for Fee {
+    type Err = anyhow::Error;
+
+    /// `1%`, `0.25` or `1% + 0.25`
+    fn from_str(fee: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        for part in fee.split('+').map(str::trim) {
+            let (amount, total) = match part.strip_suffix('%') {
+                Some(percent) => (percent.trim(), &mut parsed.percent),
+                None => (part, &mut parsed.flat),
+            };
+            let amount =
+                BigDecimal::from_str(amount).with_context(|| format!("Invalid fee {}", part))?;
+            if amount < BigDecimal::zero() {
+                bail!("Fees can't be negative, {} is", part);
+            }
+            *total += amount;
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_charge_the_scheduled_fees() {
+        let fees =
+            FeeSchedule::parse("# fees\naccount 9\ndeposit 0.5%\nwithdrawal 1% + 0.25").unwrap();
+        let fee = |transaction| fees.fee(&transaction).to_string();
+        let amount = |amount: &str| BigDecimal::from_str(amount).unwrap();
+        assert_eq!(
+            fee(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: amount("10.01"),
+                wallet: None,
+            }),
+            "0.0501"
+        );
+        assert_eq!(
+            fee(Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: amount("10"),
+                wallet: None,
+            }),
+            "0.35"
+        );
+        // the fee account doesn't pay itself
+        assert_eq!(
+            fee(Transaction::Withdrawal {
+                client: 9,
+                tx: 3,
+                amount: amount("10"),
+                wallet: None,
+            }),
+            "0"
+        );
+        assert_eq!(fee(Transaction::Dispute { client: 1, tx: 1 }), "0");
+
+        let chargeback = Transaction::Chargeback { client: 1, tx: 1 };
+        let fees = FeeSchedule::parse("account 9\nchargeback 15").unwrap();
+        assert_eq!(fees.fee(&chargeback).to_string(), "15");
+        assert!(fees.absorbed_fee(&chargeback).is_zero());
+        let fees =
+            FeeSchedule::parse("account 9\nchargeback 15\nchargeback_payer fee_account").unwrap();
+        assert!(fees.fee(&chargeback).is_zero());
+        assert_eq!(fees.absorbed_fee(&chargeback).to_string(), "15");
+
+        assert!(FeeSchedule::parse("deposit 1%").is_err());
+        assert!(FeeSchedule::parse("account 9\ndeposit -1%").is_err());
+        assert!(FeeSchedule::parse("account 9\nchargeback 1%").is_err());
+        assert!(FeeSchedule::parse("account 9\nchargeback_payer bank").is_err());
+        assert!(FeeSchedule::parse("account 9\nrefund 1").is_err());
+        assert!(FeeSchedule::parse("account 9").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+use std::iter::Peekable;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+
+use crate::transaction_engine::AccountView;
+
+/// A condition on an account, to answer questions about a snapshot without exporting it
+/// to another tool first:
+/// ```text
+/// locked == true && total > 1000
+/// (held > 0 || !locked) && client != 7
+/// ```
+/// `client`, `available`, `held` and `total` are compared with numbers, `locked` with `true`
+/// or `false` or on its own. `&&` binds tighter than `||`, `!` negates and parentheses group
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountFilter {
+    Amount(Field, Comparison, BigDecimal),
+    Locked(bool),
+    And(Box<AccountFilter>, Box<AccountFilter>),
+    Or(Box<AccountFilter>, Box<AccountFilter>),
+    Not(Box<AccountFilter>),
+}
+
+/// The numbers of an account a filter can compare
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &AccountView<'_>) -> bool {
+        match self {
+            AccountFilter::Amount(field, comparison, value) => {
+                let actual = match field {
+                    Field::Client => BigDecimal::from(account.client()),
+                    Field::Available => account.available().clone(),
+                    Field::Held => account.held().clone(),
+                    Field::Total => account.total(),
+                };
+                match comparison {
+                    Comparison::Equal => actual == *value,
+                    Comparison::NotEqual => actual != *value,
+                    Comparison::Less => actual < *value,
+                    Comparison::LessOrEqual => actual <= *value,
+                    Comparison::Greater => actual > *value,
+                    Comparison::GreaterOrEqual => actual >= *value,
+                }
+            }
+            AccountFilter::Locked(locked) => account.locked() == *locked,
+            AccountFilter::And(left, right) => left.matches(account) && right.matches(account),
+            AccountFilter::Or(left, right) => left.matches(account) || right.matches(account),
+            AccountFilter::Not(filter) => !filter.matches(account),
+        }
+    }
+}
+
+impl FromStr for AccountFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokens(expression)?.into_iter().peekable(),
+        };
+        let filter = parser.or()?;
+        match parser.tokens.next() {
+            None => Ok(filter),
+            Some(token) => bail!("Unexpected {} in {}", token, expression),
+        }
+    }
+}
+
+fn tokens(expression: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut chars = expression.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' | ')' => c.to_string(),
+            '&' | '|' | '=' => match chars.next_if(|&(_, next)| next == c) {
+                Some(_) => format!("{}{}", c, c),
+                None => bail!("Expected {}{} at {}", c, c, start),
+            },
+            '!' | '<' | '>' => match chars.next_if(|&(_, next)| next == '=') {
+                Some(_) => format!("{}=", c),
+                None => c.to_string(),
+            },
+            c if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((index, next)) =
+                    chars.next_if(|&(_, next)| next.is_alphanumeric() || next == '.' || next == '_')
+                {
+                    end = index + next.len_utf8();
+                }
+                expression[start..end].to_string()
+            }
+            c => bail!("Unexpected {} at {}", c, start),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser<I: Iterator<Item = String>> {
+    tokens: Peekable<I>,
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    fn or(&mut self) -> Result<AccountFilter> {
+        let mut filter = self.and()?;
+        while self.tokens.next_if(|token| token == "||").is_some() {
+            filter = AccountFilter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<AccountFilter> {
+        let mut filter = self.unary()?;
+        while self.tokens.next_if(|token| token == "&&").is_some() {
+            filter = AccountFilter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<AccountFilter> {
+        let token = self
+            .tokens
+            .next()
+            .context("The expression ends too early")?;
+        match token.as_str() {
+            "!" => Ok(AccountFilter::Not(Box::new(self.unary()?))),
+            "(" => {
+                let filter = self.or()?;
+                match self.tokens.next().as_deref() {
+                    Some(")") => Ok(filter),
+                    _ => bail!("Expected a ) to close the ("),
+                }
+            }
+            "locked" => {
+                let comparison = self.tokens.next_if(|token| token == "==" || token == "!=");
+                let Some(comparison) = comparison else {
+                    return Ok(AccountFilter::Locked(true));
+                };
+                let locked = match self.tokens.next().as_deref() {
+                    Some("true") => true,
+                    Some("false") => false,
+                    _ => bail!("locked can only be compared with true or false"),
+                };
+                Ok(AccountFilter::Locked(locked == (comparison == "==")))
+            }
+            field => {
+                let field = match field {
+                    "client" => Field::Client,
+                    "available" => Field::Available,
+                    "held" => Field::Held,
+                    "total" => Field::Total,
+                    other => bail!(
+                        "Unknown field {}, expected client, available, held, total or locked",
+                        other
+                    ),
+                };
+                let comparison = match self.tokens.next().as_deref() {
+                    Some("==") => Comparison::Equal,
+                    Some("!=") => Comparison::NotEqual,
+                    Some("<") => Comparison::Less,
+                    Some("<=") => Comparison::LessOrEqual,
+                    Some(">") => Comparison::Greater,
+                    Some(">=") => Comparison::GreaterOrEqual,
+                    _ => bail!("Expected a comparison after {}", token),
+                };
+                let value = self
+                    .tokens
+                    .next()
+                    .context("The expression ends too early")?;
+                let value = value
+                    .parse()
+                    .with_context(|| format!("{} isn't a number", value))?;
+                Ok(AccountFilter::Amount(field, comparison, value))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+    use crate::transaction_engine::TransactionEngine;
+
+    #[test]
+    fn should_filter_accounts_by_the_expression() {
+        let mut engine = TransactionEngine::new();
+        for (client, tx, amount) in [(1, 1, 500), (2, 2, 2000), (3, 3, 1500)] {
+            engine
+                .process(Transaction::Deposit {
+                    client,
+                    tx,
+                    amount: BigDecimal::from(amount),
+                    wallet: None,
+                })
+                .unwrap();
+        }
+        engine
+            .process(Transaction::Deposit {
+                client: 2,
+                tx: 4,
+                amount: BigDecimal::from(1200),
+                wallet: None,
+            })
+            .unwrap();
+        engine
+            .process(Transaction::Dispute { client: 2, tx: 2 })
+            .unwrap();
+        engine
+            .process(Transaction::Chargeback { client: 2, tx: 2 })
+            .unwrap();
+
+        let matching = |expression: &str| {
+            let filter = expression.parse::<AccountFilter>().unwrap();
+            let mut clients = engine
+                .account_views()
+                .filter(|account| filter.matches(account))
+                .map(|account| account.client())
+                .collect::<Vec<_>>();
+            clients.sort_unstable();
+            clients
+        };
+        assert_eq!(matching("locked == true && total > 1000"), [2]);
+        assert_eq!(matching("total>1000"), [2, 3]);
+        assert_eq!(matching("!locked && total >= 500"), [1, 3]);
+        assert_eq!(
+            matching("(client == 1 || client == 2) && locked != true"),
+            [1]
+        );
+        assert_eq!(matching("held > 0 || available < 600.5"), [1]);
+
+        assert!("total >".parse::<AccountFilter>().is_err());
+        assert!("total > x".parse::<AccountFilter>().is_err());
+        assert!("locked == 1".parse::<AccountFilter>().is_err());
+        assert!("balance > 1".parse::<AccountFilter>().is_err());
+        assert!("(total > 1".parse::<AccountFilter>().is_err());
+        assert!("total > 1 & locked".parse::<AccountFilter>().is_err());
+        assert!("total > 1 locked".parse::<AccountFilter>().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+/// Runs every `name.csv` in `dir` through this binary and compares what it writes to stdout with
+/// `name.expected.csv`. Extra arguments can go in `name.args`, paths in them are relative to `dir`.
+/// The engine doesn't promise an order of the accounts, so rows are compared sorted.
+/// With `update` the expected files are written instead of compared
+pub fn run(dir: &str, update: bool) -> Result<()> {
+    let mut inputs = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read fixture directory {}", dir))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    inputs.retain(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        name.ends_with(".csv") && !name.ends_with(".expected.csv")
+    });
+    inputs.sort();
+    if inputs.is_empty() {
+        bail!("There are no fixtures in {}", dir);
+    }
+
+    let mut failed = vec![];
+    for input in &inputs {
+        let name = input
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let expected_path = input.with_file_name(format!("{}.expected.csv", name));
+        let actual = run_fixture(Path::new(dir), input)
+            .with_context(|| format!("Failed to run fixture {}", name))?;
+        if update {
+            fs::write(&expected_path, &actual)?;
+            println!("updated {}", name);
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if normalize(&expected) == actual {
+            println!("ok {}", name);
+        } else {
+            println!(
+                "FAILED {}\n--- expected\n{}--- actual\n{}",
+                name, expected, actual
+            );
+            failed.push(name);
+        }
+    }
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} fixtures failed: {}",
+            failed.len(),
+            inputs.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn run_fixture(dir: &Path, input: &Path) -> Result<String> {
+    let args_path = input.with_extension("args");
+    let args = match fs::read_to_string(&args_path) {
+        Ok(args) => args.split_whitespace().map(String::from).collect(),
+        Err(_) => vec![],
+    };
+    let output = Command::new(std::env::current_exe()?)
+        .current_dir(dir)
+        .args(&args)
+        .arg(input.file_name().map(PathBuf::from).unwrap_or_default())
+        .output()?;
+    // rejects are an expected outcome of a fixture, anything else is a failure
+    if !matches!(output.status.code(), Some(0 | 2)) {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(normalize(&String::from_utf8(output.stdout)?))
+}
+
+/// The header stays first, the rows are sorted
+fn normalize(output: &str) -> String {
+    let mut lines = output.lines().filter(|line| !line.is_empty());
+    let header = lines.next();
+    let mut rows = lines.collect::<Vec<_>>();
+    rows.sort_unstable();
+    header
+        .into_iter()
+        .chain(rows)
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::escrow::EscrowStatus;
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+pub enum FlowFormat {
+    /// An edge list, one row per payer and payee
+    Csv,
+    /// Graphviz, e.g. `dot -Tsvg`
+    Dot,
+    /// For graph tools like Gephi or yEd
+    GraphMl,
+}
+
+/// Everything one client paid another, the released escrows from a buyer to a seller.
+/// Transfers only move money between the pockets of one account, so they aren't flows
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flow {
+    pub from: ClientId,
+    pub to: ClientId,
+    pub count: u64,
+    pub amount: BigDecimal,
+    /// Whether the money can find its way back to `from`, through the payees of `to`
+    pub circular: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CsvFlow {
+    pub from: ClientId,
+    pub to: ClientId,
+    pub count: u64,
+    pub amount: String,
+    pub circular: bool,
+}
+
+/// The flows between the engine's clients, by payer and payee
+pub fn flows(transaction_engine: &TransactionEngine) -> Vec<Flow> {
+    let mut edges = BTreeMap::<(ClientId, ClientId), (u64, BigDecimal)>::new();
+    for escrow in transaction_engine.escrows() {
+        if escrow.status() == EscrowStatus::Released {
+            let (count, amount) = edges.entry((escrow.buyer(), escrow.seller())).or_default();
+            *count += 1;
+            *amount += escrow.amount();
+        }
+    }
+    let mut payees = BTreeMap::<ClientId, BTreeSet<ClientId>>::new();
+    for &(from, to) in edges.keys() {
+        payees.entry(from).or_default().insert(to);
+    }
+    edges
+        .into_iter()
+        .map(|((from, to), (count, amount))| Flow {
+            from,
+            to,
+            count,
+            amount,
+            circular: reaches(&payees, to, from),
+        })
+        .collect()
+}
+
+/// Whether following the payees from `start` gets to `target`
+fn reaches(
+    payees: &BTreeMap<ClientId, BTreeSet<ClientId>>,
+    start: ClientId,
+    target: ClientId,
+) -> bool {
+    let mut seen = BTreeSet::from([start]);
+    let mut next = vec![start];
+    while let Some(client) = next.pop() {
+        if client == target {
+            return true;
+        }
+        for &payee in payees.get(&client).into_iter().flatten() {
+            if seen.insert(payee) {
+                next.push(payee);
+            }
+        }
+    }
+    false
+}
+
+pub fn write_flows(flows: &[Flow], format: &FlowFormat, writer: &mut dyn Write) -> Result<()> {
+    match format {
+        FlowFormat::Csv => write_csv(flows, writer),
+        FlowFormat::Dot => write_dot(flows, writer),
+        FlowFormat::GraphMl => write_graphml(flows, writer),
+    }
+}
+
+fn write_csv(flows: &[Flow], writer: &mut dyn Write) -> Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for flow in flows {
+        csv_writer.serialize(CsvFlow::from(flow))?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// Circular flows are drawn red so they stand out
+fn write_dot(flows: &[Flow], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, "digraph flows {{")?;
+    for flow in flows {
+        let color = match flow.circular {
+            true => ", color=red",
+            false => "",
+        };
+        writeln!(
+            writer,
+            "  {} -> {} [label=\"{:.4} ({})\", weight={}{}];",
+            flow.from, flow.to, flow.amount, flow.count, flow.count, color
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+fn write_graphml(flows: &[Flow], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="amount" for="edge" attr.name="amount" attr.type="double"/>"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="count" for="edge" attr.name="count" attr.type="long"/>"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="circular" for="edge" attr.name="circular" attr.type="boolean"/>"#
+    )?;
+    writeln!(writer, r#"  <graph id="flows" edgedefault="directed">"#)?;
+    let clients = flows
+        .iter()
+        .flat_map(|flow| [flow.from, flow.to])
+        .collect::<BTreeSet<_>>();
+    for client in clients {
+        writeln!(writer, r#"    <node id="{}"/>"#, client)?;
+    }
+    for flow in flows {
+        writeln!(
+            writer,
+            r#"    <edge source="{}" target="{}">"#,
+            flow.from, flow.to
+        )?;
+        writeln!(
+            writer,
+            r#"      <data key="amount">{:.4}</data>"#,
+            flow.amount
+        )?;
+        writeln!(writer, r#"      <data key="count">{}</data>"#, flow.count)?;
+        writeln!(
+            writer,
+            r#"      <data key="circular">{}</data>"#,
+            flow.circular
+        )?;
+        writeln!(writer, "    </edge>")?;
+    }
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")?;
+    Ok(())
+}
+
+impl From<&Flow> for CsvFlow {
+    fn from(flow: &Flow) -> Self {
+        Self {
+            from: flow.from,
+            to: flow.to,
+            count: flow.count,
+            amount: format!("{:.4}", flow.amount),
+            circular: flow.circular,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::Transaction;
+
+    use super::*;
+
+    #[test]
+    fn should_aggregate_what_clients_paid_each_other() {
+        let mut engine = TransactionEngine::new();
+        for (client, tx) in (1..=4).zip(1..) {
+            engine
+                .process(Transaction::Deposit {
+                    client,
+                    tx,
+                    amount: BigDecimal::from(100),
+                    wallet: None,
+                })
+                .unwrap();
+        }
+        // 1 pays 2 twice, 2 pays 3 and 3 pays 1 back, 4 pays 1 but nothing comes back
+        for (tx, (buyer, seller, amount, released)) in (11..).zip([
+            (1, 2, 10, true),
+            (1, 2, 5, true),
+            (2, 3, 7, true),
+            (3, 1, 6, true),
+            (4, 1, 20, true),
+            (2, 4, 50, false),
+        ]) {
+            engine
+                .process(Transaction::EscrowOpen {
+                    client: buyer,
+                    tx,
+                    amount: BigDecimal::from(amount),
+                    seller,
+                })
+                .unwrap();
+            let close = match released {
+                true => Transaction::EscrowRelease { client: buyer, tx },
+                false => Transaction::EscrowRefund { client: buyer, tx },
+            };
+            engine.process(close).unwrap();
+        }
+
+        let flows = flows(&engine);
+        let edges = flows
+            .iter()
+            .map(|flow| (flow.from, flow.to, flow.count, flow.circular))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            [
+                (1, 2, 2, true),
+                (2, 3, 1, true),
+                (3, 1, 1, true),
+                (4, 1, 1, false)
+            ]
+        );
+        assert_eq!(flows[0].amount, BigDecimal::from(15));
+
+        let mut dot = vec![];
+        write_flows(&flows, &FlowFormat::Dot, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("  1 -> 2 [label=\"15.0000 (2)\", weight=2, color=red];"));
+        assert!(dot.contains("  4 -> 1 [label=\"20.0000 (1)\", weight=1];"));
+        let mut graphml = vec![];
+        write_flows(&flows, &FlowFormat::GraphMl, &mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<node ").count(), 4);
+        assert!(graphml.contains(r#"<edge source="4" target="1">"#));
+    }
+}
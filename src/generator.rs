@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::BigDecimal;
+
+use crate::transaction::{ClientId, Transaction, TransactionType};
+
+/// How many of each kind of transaction are generated, relative to the others:
+/// ```text
+/// deposit:70,withdrawal:25,dispute:5
+/// ```
+/// Deposits, withdrawals, disputes, resolves and chargebacks can be mixed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mix(Vec<(TransactionType, u32)>);
+
+impl FromStr for Mix {
+    type Err = anyhow::Error;
+
+    fn from_str(mix: &str) -> Result<Self> {
+        let mut weights = vec![];
+        for part in mix.split(',') {
+            let (kind, weight) = part
+                .split_once(':')
+                .with_context(|| format!("Expected kind:weight, got {}", part))?;
+            let kind = match kind.trim() {
+                "deposit" => TransactionType::Deposit,
+                "withdrawal" => TransactionType::Withdrawal,
+                "dispute" => TransactionType::Dispute,
+                "resolve" => TransactionType::Resolve,
+                "chargeback" => TransactionType::Chargeback,
+                other => bail!(
+                    "Can't generate {}, only deposit, withdrawal, dispute, resolve and chargeback",
+                    other
+                ),
+            };
+            let weight = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid weight {}", weight))?;
+            weights.push((kind, weight));
+        }
+        if weights.iter().all(|&(_, weight)| weight == 0) {
+            bail!("The mix has no weight");
+        }
+        Ok(Self(weights))
+    }
+}
+
+/// Transactions that look like real traffic, the same ones for the same seed: deposits and
+/// withdrawals of up to 1000 with cents, spread over the clients, and disputes of recent
+/// deposits that are then resolved or charged back. A dispute, resolve or chargeback with
+/// nothing to refer to yet is a deposit instead, so most of what's generated is applied
+pub struct Generator {
+    mix: Mix,
+    state: u64,
+    first_client: u64,
+    clients: u64,
+    next_tx: u32,
+    /// The latest deposits, to dispute
+    deposits: VecDeque<(ClientId, u32)>,
+    /// Open disputes, to resolve or charge back
+    disputes: VecDeque<(ClientId, u32)>,
+}
+
+/// How many recent deposits are kept to be disputed
+const RECENT: usize = 1000;
+
+/// splitmix64, plenty for traffic and faults that only have to look random
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Generator {
+    /// For `clients` clients from `first_client` on, with tx ids from `first_tx` on, so
+    /// generators with ranges of their own can run side by side against one engine. Clients
+    /// past the last id of the build's `ClientId` are all the last one
+    pub fn new(mix: Mix, first_client: u64, clients: u64, first_tx: u32, seed: u64) -> Self {
+        Self {
+            mix,
+            state: seed,
+            first_client,
+            clients: clients.max(1),
+            next_tx: first_tx,
+            deposits: VecDeque::new(),
+            disputes: VecDeque::new(),
+        }
+    }
+
+    fn random(&mut self) -> u64 {
+        splitmix64(&mut self.state)
+    }
+
+    fn kind(&mut self) -> TransactionType {
+        let total = self
+            .mix
+            .0
+            .iter()
+            .map(|&(_, weight)| u64::from(weight))
+            .sum::<u64>();
+        let mut pick = self.random() % total;
+        for &(kind, weight) in &self.mix.0 {
+            match pick.checked_sub(u64::from(weight)) {
+                Some(rest) => pick = rest,
+                None => return kind,
+            }
+        }
+        unreachable!("the pick is below the total weight")
+    }
+
+    fn client(&mut self) -> ClientId {
+        let offset = self.random() % self.clients;
+        ClientId::try_from(self.first_client.saturating_add(offset)).unwrap_or(ClientId::MAX)
+    }
+
+    /// Up to `max` with two decimals, at least 0.01
+    fn amount(&mut self, max: u64) -> BigDecimal {
+        let cents = 1 + self.random() % (max * 100);
+        BigDecimal::new(BigInt::from(cents), 2)
+    }
+
+    fn tx(&mut self) -> u32 {
+        let tx = self.next_tx;
+        self.next_tx = self.next_tx.wrapping_add(1);
+        tx
+    }
+
+    fn deposit(&mut self) -> Transaction {
+        let (client, tx) = (self.client(), self.tx());
+        if self.deposits.len() == RECENT {
+            self.deposits.pop_front();
+        }
+        self.deposits.push_back((client, tx));
+        Transaction::Deposit {
+            client,
+            tx,
+            amount: self.amount(1000),
+            wallet: None,
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        let transaction = match self.kind() {
+            TransactionType::Withdrawal => Transaction::Withdrawal {
+                client: self.client(),
+                tx: self.tx(),
+                amount: self.amount(250),
+                wallet: None,
+            },
+            TransactionType::Dispute => match self.deposits.pop_front() {
+                None => self.deposit(),
+                Some((client, tx)) => {
+                    self.disputes.push_back((client, tx));
+                    Transaction::Dispute { client, tx }
+                }
+            },
+            TransactionType::Resolve => match self.disputes.pop_front() {
+                None => self.deposit(),
+                Some((client, tx)) => Transaction::Resolve { client, tx },
+            },
+            TransactionType::Chargeback => match self.disputes.pop_front() {
+                None => self.deposit(),
+                Some((client, tx)) => Transaction::Chargeback { client, tx },
+            },
+            _ => self.deposit(),
+        };
+        Some(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction_engine::TransactionEngine;
+
+    #[test]
+    fn should_generate_the_mix_of_transactions() {
+        let mix = "deposit:70,withdrawal:25,dispute:5".parse::<Mix>().unwrap();
+        let generated = Generator::new(mix.clone(), 1, 100, 1, 42)
+            .take(10_000)
+            .collect::<Vec<_>>();
+        let count = |kind| {
+            generated
+                .iter()
+                .filter(|transaction| transaction.type_name() == kind)
+                .count()
+        };
+        assert!((6500..7500).contains(&count("deposit")));
+        assert!((2000..3000).contains(&count("withdrawal")));
+        assert!((300..700).contains(&count("dispute")));
+        assert!(generated
+            .iter()
+            .all(|transaction| (1..=100).contains(&transaction.client())));
+
+        let again = Generator::new(mix, 1, 100, 1, 42).take(100);
+        assert!(again
+            .zip(&generated)
+            .all(|(a, b)| format!("{:?}", a) == format!("{:?}", b)));
+
+        let mut engine = TransactionEngine::new();
+        let applied = generated
+            .into_iter()
+            .filter(|transaction| engine.process(transaction.clone()).is_ok())
+            .count();
+        assert!(applied > 8000, "only {} applied", applied);
+
+        assert!("deposit:1,transfer:1".parse::<Mix>().is_err());
+        assert!("deposit:x".parse::<Mix>().is_err());
+        assert!("deposit:0".parse::<Mix>().is_err());
+    }
+}
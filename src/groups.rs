@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+/// Clients that are reported on together, e.g. the accounts of one household.
+/// A client is in at most one group
+#[derive(Clone, Debug, Default)]
+pub struct ClientGroups {
+    groups: HashMap<ClientId, String>,
+}
+
+#[derive(Deserialize)]
+struct CsvGroup {
+    client: ClientId,
+    group: String,
+}
+
+impl ClientGroups {
+    /// Reads a `client,group` csv, a client listed twice ends up in the last group
+    pub fn read_csv(reader: impl Read) -> Result<Self> {
+        let mut groups = Self::default();
+        for row in crate::transaction::csv_reader(reader).deserialize() {
+            let CsvGroup { client, group } = row?;
+            groups.groups.insert(client, group);
+        }
+        Ok(groups)
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&str> {
+        self.groups.get(&client).map(String::as_str)
+    }
+}
+
+/// Totals of the accounts of a group
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvGroupSummary {
+    pub group: String,
+    pub accounts: usize,
+    pub locked: usize,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+}
+
+/// One summary per group with an account the engine knows about, sorted by group.
+/// Clients without a group are left out
+pub fn summarize(engine: &TransactionEngine, groups: &ClientGroups) -> Vec<CsvGroupSummary> {
+    #[derive(Default)]
+    struct Totals {
+        accounts: usize,
+        locked: usize,
+        available: BigDecimal,
+        total: BigDecimal,
+    }
+
+    let mut totals = BTreeMap::<&str, Totals>::new();
+    for account in engine.account_views() {
+        if let Some(group) = groups.get(account.client()) {
+            let totals = totals.entry(group).or_default();
+            totals.accounts += 1;
+            totals.locked += usize::from(account.locked());
+            totals.available += account.available();
+            totals.total += account.total();
+        }
+    }
+    totals
+        .into_iter()
+        .map(|(group, totals)| CsvGroupSummary {
+            group: group.to_string(),
+            accounts: totals.accounts,
+            locked: totals.locked,
+            available: format!("{:.4}", totals.available),
+            held: format!("{:.4}", &totals.total - &totals.available),
+            total: format!("{:.4}", totals.total),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn should_summarize_accounts_by_group() {
+        let mut engine = TransactionEngine::new();
+        for (client, tx, amount) in [(1, 1, 10), (2, 2, 5), (3, 3, 1)] {
+            let _ = engine.process(Transaction::Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(amount),
+                wallet: None,
+            });
+        }
+        let _ = engine.process(Transaction::Dispute { client: 2, tx: 2 });
+        let groups =
+            ClientGroups::read_csv("client,group\n1,smith\n2,smith\n4,jones\n".as_bytes()).unwrap();
+
+        assert_eq!(
+            summarize(&engine, &groups),
+            vec![CsvGroupSummary {
+                group: "smith".to_string(),
+                accounts: 2,
+                locked: 0,
+                available: "10.0000".to_string(),
+                held: "5.0000".to_string(),
+                total: "15.0000".to_string(),
+            }]
+        );
+    }
+}
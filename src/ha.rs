@@ -0,0 +1,49 @@
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use playground::lease::{self, LeaderLock};
+
+use crate::run_report;
+
+/// How long a leader's lease lasts without being renewed
+const LEASE_SECONDS: u64 = 10;
+const RENEW_EVERY: Duration = Duration::from_secs(3);
+const STANDBY_POLL: Duration = Duration::from_secs(1);
+
+/// Stands by until this instance holds the leader lock and then keeps renewing it from a
+/// thread of its own. Losing the lock ends the process, two leaders would both change the state
+pub fn lead(url: &str) -> Result<()> {
+    let owner = format!(
+        "{}:{}",
+        std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string()),
+        std::process::id()
+    );
+    let mut lock = lease::open(url, owner, LEASE_SECONDS)?;
+    let mut announced = false;
+    while !lock.try_acquire()? {
+        if !announced {
+            eprintln!("Standing by, {} is held by another instance", url);
+            announced = true;
+        }
+        thread::sleep(STANDBY_POLL);
+    }
+    eprintln!("Took the lead with {}", url);
+    thread::spawn(move || renew(lock.as_mut()));
+    Ok(())
+}
+
+fn renew(lock: &mut dyn LeaderLock) {
+    loop {
+        thread::sleep(RENEW_EVERY);
+        match lock.renew() {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("Lost the leader lock, stopping");
+                std::process::exit(run_report::EXIT_FATAL);
+            }
+            // the lease is still good for a while, a later renewal may work
+            Err(e) => eprintln!("Failed to renew the leader lock: {:#}", e),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::transaction::Transaction;
+use crate::transaction_engine::{AccountMut, TransactionError};
+
+/// A kind of transaction the engine doesn't know itself, e.g. a fee or a cashback, with its
+/// own value of the type column. Registered with `TransactionEngine::register_handler`,
+/// its transactions go through the rules and risk scorers like any other, but aren't stored,
+/// so they can't be disputed
+pub trait TransactionHandler: Send + Sync {
+    /// The type column of its transactions, one of the built in types can't be taken over
+    fn kind(&self) -> &str;
+
+    /// Applies a `Transaction::Custom` of its kind to the client's account, which isn't locked.
+    /// Nothing is rolled back when it returns an error, so it should check before it changes
+    /// anything. Rejecting it for a reason of its own is `TransactionError::RejectedByHandler`
+    fn apply(
+        &self,
+        transaction: &Transaction,
+        account: &mut AccountMut<'_>,
+    ) -> Result<(), TransactionError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::amount::AmountFormat;
+    use crate::transaction::{read_transactions, Transaction};
+    use crate::transaction_engine::TransactionEngine;
+
+    use super::*;
+
+    /// Takes a flat fee, unless the account can't pay it
+    struct Fee;
+
+    impl TransactionHandler for Fee {
+        fn kind(&self) -> &str {
+            "fee"
+        }
+
+        fn apply(
+            &self,
+            transaction: &Transaction,
+            account: &mut AccountMut<'_>,
+        ) -> Result<(), TransactionError> {
+            let fee = transaction.amount().cloned().unwrap_or(BigDecimal::from(1));
+            if account.view().available() < &fee {
+                return Err(TransactionError::RejectedByHandler(
+                    "fee_unpaid".to_string(),
+                ));
+            }
+            account.debit(&fee)
+        }
+    }
+
+    #[test]
+    fn should_apply_custom_types_with_their_handler() {
+        let mut engine = TransactionEngine::with_config(crate::config::EngineConfig {
+            check_invariants: true,
+            ..Default::default()
+        });
+        engine.register_handler(Box::new(Fee)).unwrap();
+        assert!(engine.register_handler(Box::new(Fee)).is_err());
+
+        let input = "type,client,tx,amount\ndeposit,1,1,10\nfee,1,2,\nfee,1,3,20\nfee,2,4,\n";
+        let results = read_transactions(input.as_bytes(), AmountFormat::default())
+            .map(|raw| crate::session::apply(&mut engine, raw.unwrap()).unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::RejectedByHandler(
+                    "fee_unpaid".to_string()
+                )),
+                Err(TransactionError::RejectedByHandler(
+                    "fee_unpaid".to_string()
+                )),
+            ]
+        );
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available(), &BigDecimal::from(9));
+        assert_eq!(account.transactions().count(), 1);
+        assert_eq!(account.ledger()[1].kind.name(), "custom");
+
+        let unknown = "type,client,tx,amount\nbonus,1,5,1\n";
+        let raw = read_transactions(unknown.as_bytes(), AmountFormat::default())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(crate::session::apply(&mut engine, raw).is_err());
+    }
+
+    /// Applies nothing, for a type column of its own
+    struct Named(&'static str);
+
+    impl TransactionHandler for Named {
+        fn kind(&self) -> &str {
+            self.0
+        }
+
+        fn apply(&self, _: &Transaction, _: &mut AccountMut<'_>) -> Result<(), TransactionError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_refuse_handlers_for_the_built_in_types() {
+        let mut engine = TransactionEngine::new();
+        for kind in [
+            "deposit",
+            "chargeback",
+            "transfer",
+            "escrow_open",
+            "escrow_release",
+            "escrow_refund",
+            "approve",
+            "deny",
+            "wallet_transfer",
+            "promo_credit",
+            "reversal",
+        ] {
+            assert!(
+                engine.register_handler(Box::new(Named(kind))).is_err(),
+                "{}",
+                kind
+            );
+        }
+        engine.register_handler(Box::new(Named("Deposit"))).unwrap();
+    }
+}
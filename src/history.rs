@@ -0,0 +1,387 @@
+use std::io::Write;
+
+use anyhow::Result;
+use bigdecimal::{Signed, Zero};
+use serde::Serialize;
+
+use crate::date::UtcDateTime;
+use crate::iso20022;
+use crate::ledger::{EntryKind, LedgerEntry};
+use crate::locale::Locale;
+use crate::notes::Note;
+use crate::transaction::ClientId;
+use crate::transaction_engine::AccountView;
+
+pub enum HistoryFormat {
+    /// Every ledger entry of the account, including holds and releases
+    Csv,
+    /// OFX 2.2 bank statement, for personal finance tools
+    Ofx { currency: String },
+    /// Quicken interchange format, for accounting tools
+    Qif,
+}
+
+/// A ledger entry or, with only `at` and `note`, a note about the account
+#[derive(Serialize)]
+struct CsvLedgerEntry {
+    client: ClientId,
+    tx: Option<u32>,
+    kind: &'static str,
+    available: Option<String>,
+    held: Option<String>,
+    source: Option<String>,
+    sequence: Option<u64>,
+    merchant: Option<String>,
+    category: Option<String>,
+    wallet: Option<String>,
+    promo: Option<String>,
+    /// Whether the entry is of a withdrawal that was reversed, or is its reversal
+    reversed: Option<bool>,
+    at: Option<String>,
+    note: Option<String>,
+}
+
+/// Writes the history of a single client's account.
+/// The transactions carry no dates, so OFX and QIF date everything at `created_at`.
+/// Their statement lines are described in `locale`, the csv has the names of the entry kinds
+pub fn write_history(
+    account: AccountView<'_>,
+    format: &HistoryFormat,
+    locale: Locale,
+    created_at: u64,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let created_at = UtcDateTime::from_timestamp(created_at);
+    match format {
+        HistoryFormat::Csv => write_csv(account, writer),
+        HistoryFormat::Ofx { currency } => {
+            write_ofx(account, currency, locale, &created_at, writer)
+        }
+        HistoryFormat::Qif => write_qif(account, locale, &created_at, writer),
+    }
+}
+
+fn write_csv(account: AccountView<'_>, writer: &mut dyn Write) -> Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for entry in account.ledger() {
+        csv_writer.serialize(CsvLedgerEntry {
+            client: account.client(),
+            tx: Some(entry.tx),
+            kind: entry.kind.name(),
+            available: Some(format!("{:.4}", entry.available)),
+            held: Some(format!("{:.4}", entry.held)),
+            source: entry.source.as_deref().map(str::to_string),
+            sequence: entry.sequence,
+            merchant: entry
+                .enrichment
+                .as_ref()
+                .and_then(|enrichment| enrichment.merchant.clone()),
+            category: entry
+                .enrichment
+                .as_ref()
+                .and_then(|enrichment| enrichment.category.clone()),
+            wallet: entry.wallet.clone(),
+            promo: Some(format!("{:.4}", entry.promo)),
+            reversed: Some(
+                matches!(entry.kind, EntryKind::Withdrawal | EntryKind::Reversal)
+                    && account.is_reversed(entry.tx),
+            ),
+            at: None,
+            note: None,
+        })?;
+    }
+    // the entries have no dates, so the notes can't go between them
+    for note in account.notes() {
+        csv_writer.serialize(CsvLedgerEntry {
+            client: account.client(),
+            tx: None,
+            kind: "note",
+            available: None,
+            held: None,
+            source: None,
+            sequence: None,
+            merchant: None,
+            category: None,
+            wallet: None,
+            promo: None,
+            reversed: None,
+            at: Some(UtcDateTime::from_timestamp(note.at).iso_date_time()),
+            note: Some(describe(note)),
+        })?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// A note with who wrote it, if anyone said
+pub fn describe(note: &Note) -> String {
+    match &note.author {
+        None => note.text.clone(),
+        Some(author) => format!("{}: {}", author, note.text),
+    }
+}
+
+/// Only entries that moved money in or out of the account,
+/// holds and releases are internal to us
+fn movements<'a>(account: AccountView<'a>) -> impl Iterator<Item = &'a LedgerEntry> {
+    account
+        .ledger()
+        .iter()
+        .filter(|entry| !entry.total().is_zero())
+}
+
+fn write_ofx(
+    account: AccountView<'_>,
+    currency: &str,
+    locale: Locale,
+    created_at: &UtcDateTime,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let date = format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        created_at.year,
+        created_at.month,
+        created_at.day,
+        created_at.hour,
+        created_at.minute,
+        created_at.second
+    );
+    let status = "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>";
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>"#
+    )?;
+    writeln!(writer, "<OFX>")?;
+    writeln!(writer, "  <SIGNONMSGSRSV1>")?;
+    writeln!(
+        writer,
+        "    <SONRS>{}<DTSERVER>{}</DTSERVER><LANGUAGE>{}</LANGUAGE></SONRS>",
+        status,
+        date,
+        locale.ofx_language()
+    )?;
+    writeln!(writer, "  </SIGNONMSGSRSV1>")?;
+    writeln!(writer, "  <BANKMSGSRSV1>")?;
+    writeln!(writer, "    <STMTTRNRS>")?;
+    writeln!(
+        writer,
+        "      <TRNUID>{}</TRNUID>{}",
+        account.client(),
+        status
+    )?;
+    writeln!(writer, "      <STMTRS>")?;
+    writeln!(writer, "        <CURDEF>{}</CURDEF>", currency)?;
+    writeln!(
+        writer,
+        "        <BANKACCTFROM><BANKID>PLAYGROUND</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>",
+        account.client()
+    )?;
+    writeln!(writer, "        <BANKTRANLIST>")?;
+    writeln!(
+        writer,
+        "          <DTSTART>{}</DTSTART><DTEND>{}</DTEND>",
+        date, date
+    )?;
+    for entry in movements(account) {
+        let total = entry.total();
+        let transaction_type = if total.is_negative() {
+            "DEBIT"
+        } else {
+            "CREDIT"
+        };
+        writeln!(writer, "          <STMTTRN>")?;
+        writeln!(
+            writer,
+            "            <TRNTYPE>{}</TRNTYPE>",
+            transaction_type
+        )?;
+        writeln!(writer, "            <DTPOSTED>{}</DTPOSTED>", date)?;
+        writeln!(writer, "            <TRNAMT>{:.4}</TRNAMT>", total)?;
+        // a chargeback shares the tx id with what it charges back
+        writeln!(
+            writer,
+            "            <FITID>{}-{}</FITID>",
+            entry.tx,
+            entry.kind.name()
+        )?;
+        writeln!(
+            writer,
+            "            <NAME>{}</NAME>",
+            locale.entry(entry.kind)
+        )?;
+        if let Some(enrichment) = &entry.enrichment {
+            writeln!(
+                writer,
+                "            <MEMO>{}</MEMO>",
+                iso20022::escape(&enrichment.describe())
+            )?;
+        }
+        writeln!(writer, "          </STMTTRN>")?;
+    }
+    writeln!(writer, "        </BANKTRANLIST>")?;
+    writeln!(
+        writer,
+        "        <LEDGERBAL><BALAMT>{:.4}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>",
+        account.total(),
+        date
+    )?;
+    writeln!(
+        writer,
+        "        <AVAILBAL><BALAMT>{:.4}</BALAMT><DTASOF>{}</DTASOF></AVAILBAL>",
+        account.available(),
+        date
+    )?;
+    writeln!(writer, "      </STMTRS>")?;
+    writeln!(writer, "    </STMTTRNRS>")?;
+    writeln!(writer, "  </BANKMSGSRSV1>")?;
+    writeln!(writer, "</OFX>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_qif(
+    account: AccountView<'_>,
+    locale: Locale,
+    created_at: &UtcDateTime,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    writeln!(writer, "!Type:Bank")?;
+    for entry in movements(account) {
+        writeln!(
+            writer,
+            "D{:02}/{:02}/{:04}",
+            created_at.month, created_at.day, created_at.year
+        )?;
+        writeln!(writer, "T{:.4}", entry.total())?;
+        writeln!(writer, "N{}", entry.tx)?;
+        writeln!(writer, "P{}", locale.entry(entry.kind))?;
+        if let Some(enrichment) = &entry.enrichment {
+            if let Some(merchant) = &enrichment.merchant {
+                writeln!(writer, "M{}", merchant)?;
+            }
+            if let Some(category) = &enrichment.category {
+                writeln!(writer, "L{}", category)?;
+            }
+        }
+        writeln!(writer, "^")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::transaction::Transaction::{Chargeback, Deposit, Dispute};
+    use crate::transaction_engine::TransactionEngine;
+
+    use super::*;
+
+    fn engine() -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        for transaction in [
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            },
+            Deposit {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(3),
+                wallet: None,
+            },
+            Dispute { client: 1, tx: 2 },
+            Chargeback { client: 1, tx: 2 },
+        ] {
+            let _ = engine.process(transaction);
+        }
+        engine
+    }
+
+    #[test]
+    fn should_write_qif_movements() {
+        let engine = engine();
+        let mut output = vec![];
+        write_history(
+            engine.account(1).unwrap(),
+            &HistoryFormat::Qif,
+            Locale::En,
+            1_660_000_000,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "!Type:Bank\n\
+            D08/08/2022\nT10.0000\nN1\nPdeposit\n^\n\
+            D08/08/2022\nT3.0000\nN2\nPdeposit\n^\n\
+            D08/08/2022\nT-3.0000\nN2\nPchargeback\n^\n"
+        );
+    }
+
+    #[test]
+    fn should_write_the_notes_after_the_entries() {
+        let mut engine = engine();
+        engine.advance_time(1_660_000_000);
+        engine
+            .add_note(
+                1,
+                Some("alice".to_string()),
+                "Chargeback, called the customer",
+            )
+            .unwrap();
+        assert_eq!(
+            engine.add_note(2, None, "No such client"),
+            Err(crate::transaction_engine::TransactionError::UnknownAccount)
+        );
+        let mut output = vec![];
+        write_history(
+            engine.account(1).unwrap(),
+            &HistoryFormat::Csv,
+            Locale::En,
+            1_660_000_000,
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "client,tx,kind,available,held,source,sequence,merchant,category,wallet,promo,reversed,at,note"
+        );
+        assert_eq!(lines[1], "1,1,deposit,10.0000,0.0000,,1,,,,0.0000,false,,");
+        assert_eq!(
+            lines.last().unwrap(),
+            &"1,,note,,,,,,,,,,2022-08-08T23:06:40Z,\"alice: Chargeback, called the customer\""
+        );
+    }
+
+    #[test]
+    fn should_write_ofx_statement() {
+        let engine = engine();
+        let mut output = vec![];
+        write_history(
+            engine.account(1).unwrap(),
+            &HistoryFormat::Ofx {
+                currency: "EUR".to_string(),
+            },
+            Locale::Sv,
+            1_660_000_000,
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("<STMTTRN>").count(), 3);
+        assert!(output.contains("<FITID>2-chargeback</FITID>"));
+        assert!(output.contains("<TRNAMT>-3.0000</TRNAMT>"));
+        assert!(output.contains("<LEDGERBAL><BALAMT>10.0000</BALAMT>"));
+        assert!(output.contains("<DTSERVER>20220808230640</DTSERVER>"));
+        assert!(output.contains("<LANGUAGE>SWE</LANGUAGE>"));
+        assert!(output.contains("<NAME>återbetalning</NAME>"));
+    }
+}
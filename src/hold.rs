@@ -0,0 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use bigdecimal::BigDecimal;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+
+/// Why an operator held or released money of an account
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HoldReason {
+    /// A court order or a request from the authorities
+    LegalHold,
+    /// Looked into, e.g. for fraud, until it's cleared
+    Investigation,
+    /// Waiting for documents, e.g. of a KYC review
+    Compliance,
+    Other,
+}
+
+impl HoldReason {
+    pub const fn code(self) -> &'static str {
+        match self {
+            HoldReason::LegalHold => "legal_hold",
+            HoldReason::Investigation => "investigation",
+            HoldReason::Compliance => "compliance",
+            HoldReason::Other => "other",
+        }
+    }
+}
+
+impl FromStr for HoldReason {
+    type Err = anyhow::Error;
+
+    fn from_str(code: &str) -> Result<Self> {
+        Ok(match code {
+            "legal_hold" => HoldReason::LegalHold,
+            "investigation" => HoldReason::Investigation,
+            "compliance" => HoldReason::Compliance,
+            "other" => HoldReason::Other,
+            other => bail!(
+                "Unknown hold reason {}, expected legal_hold, investigation, compliance or other",
+                other
+            ),
+        })
+    }
+}
+
+impl fmt::Display for HoldReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// An operator moving money of an account between available and held outside of any
+/// transaction, see `TransactionEngine::hold`. Kept with the account as its audit trail,
+/// the ledger has the balance changes
+#[derive(Clone, Debug, PartialEq)]
+pub struct HoldAction {
+    /// Unix timestamp (seconds)
+    pub at: u64,
+    /// Positive for a hold, negative for a release
+    pub amount: BigDecimal,
+    pub reason: HoldReason,
+    /// Who did it, if they said
+    pub author: Option<String>,
+}
+
+impl HeapSize for HoldAction {
+    fn heap_size(&self) -> usize {
+        self.amount.heap_size() + self.author.as_ref().map_or(0, String::heap_size)
+    }
+}
+
+impl Snapshot for HoldAction {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.at.encode(encoder);
+        self.amount.encode(encoder);
+        self.reason.code().to_string().encode(encoder);
+        self.author.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            at: u64::decode(decoder)?,
+            amount: BigDecimal::decode(decoder)?,
+            reason: String::decode(decoder)?.parse()?,
+            author: Option::decode(decoder)?,
+        })
+    }
+}
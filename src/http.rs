@@ -0,0 +1,173 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::chaos::{self, Seam, Verdict};
+
+/// A plain HTTP/1.1 client for the webhooks, the admin commands and `payments-client`, there's
+/// no HTTP crate in the build. One request per connection, like the server
+#[derive(Clone)]
+pub struct Target {
+    /// host:port
+    address: String,
+    host: String,
+    /// Requests go to paths below this one
+    path: String,
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Target {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            // TLS needs a crate this build doesn't have, put a proxy in front of https servers
+            Some((scheme, _)) => bail!("Unsupported scheme '{}': {}", scheme, url),
+            None => bail!("Expected an http:// url, got {}", url),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Self {
+            address,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// `path` is relative to the url's, empty for the url itself
+    pub fn send(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<(&str, &[u8])>,
+        timeout: Duration,
+    ) -> Result<Reply> {
+        self.send_with_headers(method, path, &[], body, timeout)
+    }
+
+    /// `send` with more headers, e.g. a `traceparent`
+    pub fn send_with_headers(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, String)],
+        body: Option<(&str, &[u8])>,
+        timeout: Duration,
+    ) -> Result<Reply> {
+        let verdict = chaos::inject(Seam::Sink)?;
+        let mut stream = TcpStream::connect(&self.address)
+            .with_context(|| format!("Failed to connect to {}", self.address))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let target = match path {
+            "" => self.path.clone(),
+            path => format!("{}/{}", self.path.trim_end_matches('/'), path),
+        };
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            method, target, self.host
+        )?;
+        let (content_type, body) = body.unwrap_or(("", &[]));
+        if !content_type.is_empty() {
+            write!(stream, "Content-Type: {}\r\n", content_type)?;
+        }
+        for (name, value) in headers {
+            write!(stream, "{}: {}\r\n", name, value)?;
+        }
+        write!(stream, "Content-Length: {}\r\n\r\n", body.len())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+        if verdict == Verdict::Drop {
+            bail!("The reply from {} was lost", self.address);
+        }
+        read_reply(BufReader::new(stream))
+    }
+}
+
+fn read_reply(mut reader: impl BufRead) -> Result<Reply> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = match line.split_whitespace().nth(1).map(str::parse) {
+        Some(Ok(status)) => status,
+        _ => bail!("Unexpected response {}", line.trim()),
+    };
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut reply = Reply {
+        status,
+        headers,
+        body: vec![],
+    };
+    if reply.header("transfer-encoding") == Some("chunked") {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size)?;
+            let size = usize::from_str_radix(size.trim(), 16)
+                .with_context(|| format!("Invalid chunk size {}", size.trim()))?;
+            if size == 0 {
+                break;
+            }
+            let start = reply.body.len();
+            reply.body.resize(start + size, 0);
+            reader
+                .read_exact(&mut reply.body[start..])
+                .context("The response ended in the middle of a chunk")?;
+            reader.read_line(&mut String::new())?;
+        }
+    } else {
+        // the server closes the connection after the body
+        reader.read_to_end(&mut reply.body)?;
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_chunked_replies() {
+        let reply = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nX-Next-Cursor: 7\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n";
+        let reply = read_reply(reply.as_bytes()).unwrap();
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.header("x-next-cursor"), Some("7"));
+        assert_eq!(reply.body, b"abcde");
+        assert!(read_reply(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab".as_bytes()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::json::Json;
+use crate::sha256::{hex, Sha256};
+
+/// The responses to requests sent with an `Idempotency-Key`, so a client that retries after a
+/// timeout gets the original answer instead of having the request applied twice.
+/// Every response is appended to a file as a JSON line before it's sent, the file is read
+/// back and compacted when the server starts. Keys are forgotten after the retention window
+pub struct IdempotencyStore {
+    path: String,
+    file: File,
+    /// Seconds
+    retention: u64,
+    responses: HashMap<String, StoredResponse>,
+    /// (stored at, key) in the order they were stored, to forget them
+    order: VecDeque<(u64, String)>,
+    /// The lines of the file that couldn't be read when it was opened
+    skipped: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredResponse {
+    /// Of the request, the same key with another request is refused
+    pub fingerprint: String,
+    pub status: u16,
+    pub body: Json,
+    /// Unix seconds
+    pub at: u64,
+}
+
+/// What a request's key is known for
+#[derive(Debug, PartialEq)]
+pub enum Lookup<'a> {
+    New,
+    Replay(&'a StoredResponse),
+    /// The key was used for a different request
+    Mismatch,
+}
+
+impl IdempotencyStore {
+    /// Reads the responses of `path` that are still within `retention` seconds of `now`
+    /// and writes only those back, the file is created if there isn't one. Lines that can't be
+    /// read aren't written back either, see `skipped`
+    pub fn open(path: &str, retention: u64, now: u64) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Could not read {}", path)),
+        };
+        let mut responses = HashMap::new();
+        let mut order = VecDeque::new();
+        let mut skipped = vec![];
+        for (index, line) in text.lines().enumerate() {
+            // a line cut short by a crash is the last one, its response was never sent
+            let Some((key, response)) = Json::parse(line).ok().and_then(|json| parse(&json)) else {
+                skipped.push(index + 1);
+                continue;
+            };
+            order.push_back((response.at, key.clone()));
+            responses.insert(key, response);
+        }
+        let oldest = now.saturating_sub(retention);
+        order.retain(|(at, key)| *at >= oldest && responses[key].at == *at);
+        responses.retain(|_, response| response.at >= oldest);
+
+        let temporary = format!("{}.tmp", path);
+        let mut compacted = File::create(&temporary)
+            .with_context(|| format!("Failed to write the idempotency keys to {}", temporary))?;
+        for (_, key) in &order {
+            writeln!(compacted, "{}", line(key, &responses[key]))?;
+        }
+        compacted.sync_all()?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Failed to replace the idempotency keys in {}", path))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path))?;
+        Ok(Self {
+            path: path.to_string(),
+            file,
+            retention,
+            responses,
+            order,
+            skipped,
+        })
+    }
+
+    /// The numbers of the lines `open` couldn't read, and dropped, for the caller to report
+    pub fn skipped(&self) -> &[usize] {
+        &self.skipped
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+
+    pub fn lookup(&mut self, key: &str, fingerprint: &str, now: u64) -> Lookup<'_> {
+        self.expire(now);
+        match self.responses.get(key) {
+            None => Lookup::New,
+            Some(response) if response.fingerprint == fingerprint => Lookup::Replay(response),
+            Some(_) => Lookup::Mismatch,
+        }
+    }
+
+    /// Remembers the response to the key's request, it's on disk when this returns
+    pub fn store(&mut self, key: &str, response: StoredResponse) -> Result<()> {
+        writeln!(self.file, "{}", line(key, &response))
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("Failed to store the idempotency key in {}", self.path))?;
+        self.order.push_back((response.at, key.to_string()));
+        self.responses.insert(key.to_string(), response);
+        Ok(())
+    }
+
+    fn expire(&mut self, now: u64) {
+        let oldest = now.saturating_sub(self.retention);
+        while self.order.front().is_some_and(|(at, _)| *at < oldest) {
+            if let Some((at, key)) = self.order.pop_front() {
+                // a key stored again after it expired is only forgotten with its latest time
+                if self
+                    .responses
+                    .get(&key)
+                    .is_some_and(|stored| stored.at == at)
+                {
+                    self.responses.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// What makes two requests the same one, their method, path, query and body
+pub fn fingerprint(method: &str, path: &str, query: &[(String, String)], body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    for (key, value) in query {
+        hasher.update(format!("\n{}={}", key, value).as_bytes());
+    }
+    hasher.update(b"\n\n");
+    hasher.update(body);
+    hex(&hasher.finish())
+}
+
+fn line(key: &str, response: &StoredResponse) -> Json {
+    Json::object([
+        ("key", Json::from(key)),
+        ("fingerprint", Json::from(response.fingerprint.as_str())),
+        ("status", Json::from(response.status)),
+        ("body", response.body.clone()),
+        ("at", Json::from(response.at)),
+    ])
+}
+
+fn parse(json: &Json) -> Option<(String, StoredResponse)> {
+    let number = |name| match json.get(name) {
+        Some(Json::Number(number)) => number.parse::<u64>().ok(),
+        _ => None,
+    };
+    Some((
+        json.get("key")?.as_str()?.to_string(),
+        StoredResponse {
+            fingerprint: json.get("fingerprint")?.as_str()?.to_string(),
+            status: number("status")?.try_into().ok()?,
+            body: json.get("body")?.clone(),
+            at: number("at")?,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_replay_stored_responses_until_they_expire() {
+        let path = std::env::temp_dir().join(format!("idempotency-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let response = |at| StoredResponse {
+            fingerprint: fingerprint("POST", "/transactions", &[], b"deposit,1,1,10"),
+            status: 200,
+            body: Json::array([Json::from("applied")]),
+            at,
+        };
+
+        let mut store = IdempotencyStore::open(path, 60, 1000).unwrap();
+        let fingerprint = response(0).fingerprint;
+        assert_eq!(store.lookup("a", &fingerprint, 1000), Lookup::New);
+        store.store("a", response(1000)).unwrap();
+        store.store("b", response(1030)).unwrap();
+        assert_eq!(store.lookup("a", "other", 1000), Lookup::Mismatch);
+
+        // after a restart, a is past the window
+        let mut store = IdempotencyStore::open(path, 60, 1070).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.lookup("a", &fingerprint, 1070), Lookup::New);
+        assert_eq!(
+            store.lookup("b", &fingerprint, 1070),
+            Lookup::Replay(&response(1030))
+        );
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 1);
+        assert!(store.skipped().is_empty());
+
+        // a line cut short by a crash is dropped
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        write!(file, "{{\"key\":\"c\",").unwrap();
+        let store = IdempotencyStore::open(path, 60, 1070).unwrap();
+        assert_eq!(store.skipped(), [2]);
+        assert_eq!(store.len(), 1);
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 1);
+        fs::remove_file(path).unwrap();
+    }
+}
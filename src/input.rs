@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use playground::amount::AmountFormat;
+use playground::avro;
+use playground::protobuf;
+use playground::transaction::{self, RawTransaction};
+use playground::xml;
+
+/// What the transactions in an input are written as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    Xml,
+    /// A `TransactionBatch` from `proto/transaction.proto`
+    Protobuf,
+    /// An Avro object container file
+    Avro,
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "csv" => Ok(InputFormat::Csv),
+            "xml" => Ok(InputFormat::Xml),
+            "protobuf" => Ok(InputFormat::Protobuf),
+            "avro" => Ok(InputFormat::Avro),
+            other => bail!(
+                "Unknown input format {}, expected csv, xml, protobuf or avro",
+                other
+            ),
+        }
+    }
+}
+
+impl InputFormat {
+    /// By the extension of the source, csv if it doesn't have a known one
+    pub fn of(source: &str) -> Self {
+        let extension = source.rsplit_once('.').map(|(_, extension)| extension);
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("xml") => InputFormat::Xml,
+            Some("pb") => InputFormat::Protobuf,
+            Some("avro") => InputFormat::Avro,
+            _ => InputFormat::Csv,
+        }
+    }
+
+    pub fn read_transactions<'a>(
+        self,
+        source: impl Read + 'a,
+        amount_format: AmountFormat,
+    ) -> Box<dyn Iterator<Item = Result<RawTransaction>> + 'a> {
+        match self {
+            InputFormat::Csv => Box::new(transaction::read_transactions(source, amount_format)),
+            InputFormat::Xml => Box::new(xml::read_transactions(source, amount_format)),
+            InputFormat::Protobuf => Box::new(protobuf::read_batch(source, amount_format)),
+            InputFormat::Avro => Box::new(avro::read_container(source, amount_format)),
+        }
+    }
+}
+
+/// Opens the transaction source given on the command line,
+/// `-` means stdin, anything else without a `scheme://` is treated as a file path.
+/// The csv reader gets a plain `Read` either way so sources can be streamed
+pub fn open_source(source: &str) -> Result<Box<dyn Read>> {
+    if source == "-" {
+        return Ok(Box::new(io::stdin()));
+    }
+    if let Some((scheme, _)) = source.split_once("://") {
+        match scheme {
+            // these need an object storage client (and its credential chain)
+            // which this build doesn't ship with, download the object first
+            "s3" | "gs" => bail!(
+                "Reading {}:// objects isn't supported in this build, download {} and pass the file",
+                scheme,
+                source
+            ),
+            _ => bail!("Unsupported input scheme '{}': {}", scheme, source),
+        }
+    }
+    let file =
+        File::open(source).with_context(|| format!("Failed to open input file {}", source))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_refuse_urls_instead_of_taking_them_for_paths() {
+        let error = |source| format!("{:#}", open_source(source).err().unwrap());
+        assert_eq!(
+            error("s3://bucket/transactions.csv"),
+            "Reading s3:// objects isn't supported in this build, \
+             download s3://bucket/transactions.csv and pass the file"
+        );
+        assert!(error("gs://bucket/transactions.csv").starts_with("Reading gs:// objects"));
+        assert_eq!(
+            error("ftp://host/transactions.csv"),
+            "Unsupported input scheme 'ftp': ftp://host/transactions.csv"
+        );
+    }
+}
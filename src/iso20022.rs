@@ -0,0 +1,612 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use bigdecimal::{BigDecimal, Signed, Zero};
+
+use crate::date::UtcDateTime;
+use crate::enrichment;
+use crate::history;
+use crate::ledger::{EntryKind, LedgerEntry};
+use crate::settlement::Payout;
+use crate::sha256::{self, Sha256};
+use crate::transaction_engine::TransactionEngine;
+
+/// Things the messages need that the transactions themselves don't carry
+pub struct MessageInfo {
+    pub currency: String,
+    /// Unix timestamp (seconds) the message is created at
+    pub created_at: u64,
+}
+
+impl MessageInfo {
+    /// Unique per message, messages created in the same second differ by a hash of their kind
+    /// and what's in them. Short enough for the ids that add a client id to it to stay within
+    /// the 35 characters the schemas allow
+    fn message_id(&self, kind: &str, content: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [kind, &self.currency, content] {
+            hasher.update(part.as_bytes());
+            hasher.update(b"\n");
+        }
+        format!(
+            "PLAYGROUND-{}-{}",
+            self.created_at,
+            sha256::hex(&hasher.finish()[..3])
+        )
+    }
+}
+
+/// Renders a camt.053 bank to customer statement with one `Stmt` per account,
+/// containing every movement of the account's total balance from the ledger.
+/// Holds and releases only move money between available and held so they show up in the
+/// closing available balance rather than as entries
+pub fn write_camt053(
+    engine: &TransactionEngine,
+    info: &MessageInfo,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let created_at = UtcDateTime::from_timestamp(info.created_at);
+    let (date, date_time) = (created_at.iso_date(), created_at.iso_date_time());
+    let currency = escape(&info.currency);
+
+    let mut accounts = engine.account_views().collect::<Vec<_>>();
+    accounts.sort_by_key(|account| account.client());
+    let balances = accounts
+        .iter()
+        .map(|account| {
+            format!(
+                "{} {} {} {};",
+                account.client(),
+                account.ledger().len(),
+                account.total(),
+                account.available()
+            )
+        })
+        .collect::<String>();
+    let message_id = info.message_id("camt.053", &balances);
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">"#
+    )?;
+    writeln!(writer, "  <BkToCstmrStmt>")?;
+    writeln!(writer, "    <GrpHdr>")?;
+    writeln!(writer, "      <MsgId>{}</MsgId>", message_id)?;
+    writeln!(writer, "      <CreDtTm>{}</CreDtTm>", date_time)?;
+    writeln!(writer, "    </GrpHdr>")?;
+    for account in accounts {
+        let closing = account.total();
+        let opening = account
+            .ledger()
+            .iter()
+            .fold(closing.clone(), |balance, entry| balance - entry.total());
+
+        writeln!(writer, "    <Stmt>")?;
+        writeln!(writer, "      <Id>{}-{}</Id>", message_id, account.client())?;
+        writeln!(writer, "      <CreDtTm>{}</CreDtTm>", date_time)?;
+        writeln!(writer, "      <Acct>")?;
+        writeln!(
+            writer,
+            "        <Id><Othr><Id>{}</Id></Othr></Id>",
+            account.client()
+        )?;
+        writeln!(writer, "        <Ccy>{}</Ccy>", currency)?;
+        writeln!(writer, "      </Acct>")?;
+        for (code, balance) in [
+            ("OPBD", &opening),
+            ("CLBD", &closing),
+            ("CLAV", account.available()),
+        ] {
+            writeln!(writer, "      <Bal>")?;
+            writeln!(
+                writer,
+                "        <Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>",
+                code
+            )?;
+            write_amount(writer, "        ", "Amt", &currency, balance)?;
+            writeln!(writer, "        <Dt><Dt>{}</Dt></Dt>", date)?;
+            writeln!(writer, "      </Bal>")?;
+        }
+        for entry in account.ledger().iter().filter(|e| !e.total().is_zero()) {
+            write_entry(writer, &currency, &date, entry)?;
+        }
+        // the spending per category first, then the notes
+        let information = enrichment::category_totals(&account)
+            .into_iter()
+            .map(|totals| {
+                format!(
+                    "{} received {:.4} spent {:.4}",
+                    totals.category, totals.received, totals.spent
+                )
+            })
+            .chain(account.notes().iter().map(|note| {
+                let at = UtcDateTime::from_timestamp(note.at).iso_date();
+                format!("{} {}", at, history::describe(note))
+            }))
+            .collect::<Vec<_>>();
+        if !information.is_empty() {
+            // the schema allows 500 characters, the latest notes are the ones cut off
+            let information = information.join("; ").chars().take(500).collect::<String>();
+            writeln!(
+                writer,
+                "      <AddtlStmtInf>{}</AddtlStmtInf>",
+                escape(&information)
+            )?;
+        }
+        writeln!(writer, "    </Stmt>")?;
+    }
+    writeln!(writer, "  </BkToCstmrStmt>")?;
+    writeln!(writer, "</Document>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_entry(
+    writer: &mut dyn Write,
+    currency: &str,
+    date: &str,
+    entry: &LedgerEntry,
+) -> Result<()> {
+    let code = match entry.kind {
+        EntryKind::Deposit => "DEPOSIT",
+        EntryKind::Withdrawal => "WITHDRAWAL",
+        EntryKind::Hold => "HOLD",
+        EntryKind::Release => "RELEASE",
+        EntryKind::Chargeback => "CHARGEBACK",
+        EntryKind::Expiry => "EXPIRY",
+        EntryKind::Opening => "OPENING",
+        EntryKind::Adjustment => "ADJUSTMENT",
+        EntryKind::Lock => "LOCK",
+        EntryKind::Unlock => "UNLOCK",
+        EntryKind::Custom => "CUSTOM",
+        EntryKind::Transfer => "TRANSFER",
+        EntryKind::EscrowHold => "ESCROW_HOLD",
+        EntryKind::EscrowRelease => "ESCROW_RELEASE",
+        EntryKind::EscrowRefund => "ESCROW_REFUND",
+        EntryKind::Fee => "FEE",
+        EntryKind::PendingWithdrawal => "PENDING_WITHDRAWAL",
+        EntryKind::Approval => "APPROVAL",
+        EntryKind::PromoCredit => "PROMO_CREDIT",
+        EntryKind::PromoExpiry => "PROMO_EXPIRY",
+        EntryKind::AdminHold => "ADMIN_HOLD",
+        EntryKind::AdminRelease => "ADMIN_RELEASE",
+        EntryKind::ChargebackFee => "CHARGEBACK_FEE",
+        EntryKind::Reversal => "REVERSAL",
+        EntryKind::Denial => "DENIAL",
+    };
+    writeln!(writer, "      <Ntry>")?;
+    writeln!(writer, "        <NtryRef>{}</NtryRef>", entry.tx)?;
+    write_amount(writer, "        ", "Amt", currency, &entry.total())?;
+    writeln!(writer, "        <Sts>BOOK</Sts>")?;
+    writeln!(writer, "        <BookgDt><Dt>{}</Dt></BookgDt>", date)?;
+    writeln!(
+        writer,
+        "        <BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>",
+        code
+    )?;
+    if let Some(enrichment) = &entry.enrichment {
+        writeln!(
+            writer,
+            "        <AddtlNtryInf>{}</AddtlNtryInf>",
+            escape(&enrichment.describe())
+        )?;
+    }
+    writeln!(writer, "      </Ntry>")?;
+    Ok(())
+}
+
+/// ISO 20022 amounts are unsigned, the sign goes in a separate credit/debit indicator
+fn write_amount(
+    writer: &mut dyn Write,
+    indent: &str,
+    element: &str,
+    currency: &str,
+    amount: &BigDecimal,
+) -> Result<()> {
+    let indicator = if amount.is_negative() { "DBIT" } else { "CRDT" };
+    writeln!(
+        writer,
+        r#"{}<{} Ccy="{}">{:.4}</{}>"#,
+        indent,
+        element,
+        currency,
+        amount.abs(),
+        element
+    )?;
+    writeln!(writer, "{}<CdtDbtInd>{}</CdtDbtInd>", indent, indicator)?;
+    Ok(())
+}
+
+/// Renders a pain.001 credit transfer initiation paying out the settlement payouts
+/// from `debtor_account` to each client
+pub fn write_pain001(
+    payouts: &[Payout],
+    debtor_account: &str,
+    info: &MessageInfo,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    if payouts.is_empty() {
+        // the schema requires at least one transfer
+        bail!("There is nothing to pay out");
+    }
+    let created_at = UtcDateTime::from_timestamp(info.created_at);
+    let (date, date_time) = (created_at.iso_date(), created_at.iso_date_time());
+    let currency = escape(&info.currency);
+    let debtor_account = escape(debtor_account);
+    let count = payouts.len();
+    let sum = payouts
+        .iter()
+        .fold(BigDecimal::zero(), |sum, payout| sum + &payout.amount);
+    let transfers = payouts
+        .iter()
+        .map(|payout| format!("{} {};", payout.client, payout.amount))
+        .collect::<String>();
+    let message_id = info.message_id("pain.001", &format!("{} {}", debtor_account, transfers));
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.03">"#
+    )?;
+    writeln!(writer, "  <CstmrCdtTrfInitn>")?;
+    writeln!(writer, "    <GrpHdr>")?;
+    writeln!(writer, "      <MsgId>{}</MsgId>", message_id)?;
+    writeln!(writer, "      <CreDtTm>{}</CreDtTm>", date_time)?;
+    writeln!(writer, "      <NbOfTxs>{}</NbOfTxs>", count)?;
+    writeln!(writer, "      <CtrlSum>{:.4}</CtrlSum>", sum)?;
+    writeln!(
+        writer,
+        "      <InitgPty><Nm>{}</Nm></InitgPty>",
+        debtor_account
+    )?;
+    writeln!(writer, "    </GrpHdr>")?;
+    writeln!(writer, "    <PmtInf>")?;
+    writeln!(writer, "      <PmtInfId>{}-1</PmtInfId>", message_id)?;
+    writeln!(writer, "      <PmtMtd>TRF</PmtMtd>")?;
+    writeln!(writer, "      <NbOfTxs>{}</NbOfTxs>", count)?;
+    writeln!(writer, "      <CtrlSum>{:.4}</CtrlSum>", sum)?;
+    writeln!(writer, "      <ReqdExctnDt>{}</ReqdExctnDt>", date)?;
+    writeln!(writer, "      <Dbtr><Nm>{}</Nm></Dbtr>", debtor_account)?;
+    writeln!(
+        writer,
+        "      <DbtrAcct><Id><Othr><Id>{}</Id></Othr></Id></DbtrAcct>",
+        debtor_account
+    )?;
+    writeln!(writer, "      <DbtrAgt><FinInstnId/></DbtrAgt>")?;
+    for payout in payouts {
+        writeln!(writer, "      <CdtTrfTxInf>")?;
+        writeln!(
+            writer,
+            "        <PmtId><EndToEndId>{}-{}</EndToEndId></PmtId>",
+            message_id, payout.client
+        )?;
+        writeln!(
+            writer,
+            r#"        <Amt><InstdAmt Ccy="{}">{:.4}</InstdAmt></Amt>"#,
+            currency, payout.amount
+        )?;
+        writeln!(writer, "        <Cdtr><Nm>{}</Nm></Cdtr>", payout.client)?;
+        writeln!(
+            writer,
+            "        <CdtrAcct><Id><Othr><Id>{}</Id></Othr></Id></CdtrAcct>",
+            payout.client
+        )?;
+        writeln!(writer, "      </CdtTrfTxInf>")?;
+    }
+    writeln!(writer, "    </PmtInf>")?;
+    writeln!(writer, "  </CstmrCdtTrfInitn>")?;
+    writeln!(writer, "</Document>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::Transaction::{Deposit, Dispute, Withdrawal};
+    use crate::xml::{Event, Events};
+
+    use super::*;
+
+    fn info() -> MessageInfo {
+        MessageInfo {
+            currency: "EUR".to_string(),
+            created_at: 1_660_000_000,
+        }
+    }
+
+    /// The path and text of every element in the order they start, failing unless the XML is
+    /// well-formed: one root, every element closed in order and no text outside the root
+    fn elements(xml: &[u8]) -> Vec<(String, String)> {
+        let mut events = Events::new(xml);
+        let mut open = vec![];
+        let mut elements: Vec<(String, String)> = vec![];
+        let mut roots = 0;
+        loop {
+            match events.next().unwrap() {
+                Event::Start { name, empty, .. } => {
+                    if open.is_empty() {
+                        roots += 1;
+                    }
+                    open.push(name);
+                    elements.push((open.join("/"), String::new()));
+                    if empty {
+                        open.pop();
+                    }
+                }
+                Event::End(name) => assert_eq!(open.pop(), Some(name)),
+                Event::Text(text) if open.is_empty() => assert!(text.trim().is_empty()),
+                Event::Text(text) => {
+                    let path = open.join("/");
+                    let element = elements.iter_mut().rev().find(|(other, _)| *other == path);
+                    element.unwrap().1.push_str(text.trim());
+                }
+                Event::Eof => break,
+            }
+        }
+        assert!(open.is_empty(), "{:?} aren't closed", open);
+        assert_eq!(roots, 1);
+        elements
+    }
+
+    /// The texts of the elements at `path`
+    fn texts<'a>(elements: &'a [(String, String)], path: &str) -> Vec<&'a str> {
+        elements
+            .iter()
+            .filter(|(element, _)| element == path)
+            .map(|(_, text)| text.as_str())
+            .collect()
+    }
+
+    fn engine() -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        for (client, tx) in [(1, 1), (2, 2)] {
+            let _ = engine.process(Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            });
+        }
+        let _ = engine.process(Withdrawal {
+            client: 2,
+            tx: 3,
+            amount: BigDecimal::from(4),
+            wallet: None,
+        });
+        engine
+    }
+
+    #[test]
+    fn should_render_camt053_statement() {
+        let mut engine = TransactionEngine::new();
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        let _ = engine.process(Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: BigDecimal::from(3),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+
+        let mut output = vec![];
+        write_camt053(&engine, &info(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            output,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr>
+      <MsgId>PLAYGROUND-1660000000-5942b1</MsgId>
+      <CreDtTm>2022-08-08T23:06:40Z</CreDtTm>
+    </GrpHdr>
+    <Stmt>
+      <Id>PLAYGROUND-1660000000-5942b1-1</Id>
+      <CreDtTm>2022-08-08T23:06:40Z</CreDtTm>
+      <Acct>
+        <Id><Othr><Id>1</Id></Othr></Id>
+        <Ccy>EUR</Ccy>
+      </Acct>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">0.0000</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2022-08-08</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">7.0000</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2022-08-08</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLAV</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">3.0000</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Dt><Dt>2022-08-08</Dt></Dt>
+      </Bal>
+      <Ntry>
+        <NtryRef>1</NtryRef>
+        <Amt Ccy="EUR">10.0000</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2022-08-08</Dt></BookgDt>
+        <BkTxCd><Prtry><Cd>DEPOSIT</Cd></Prtry></BkTxCd>
+      </Ntry>
+      <Ntry>
+        <NtryRef>2</NtryRef>
+        <Amt Ccy="EUR">3.0000</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2022-08-08</Dt></BookgDt>
+        <BkTxCd><Prtry><Cd>WITHDRAWAL</Cd></Prtry></BkTxCd>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>
+"#
+        );
+    }
+
+    #[test]
+    fn should_render_pain001_with_control_sums() {
+        let payouts = vec![
+            Payout {
+                client: 1,
+                amount: BigDecimal::from(10),
+            },
+            Payout {
+                client: 2,
+                amount: BigDecimal::from(5),
+            },
+        ];
+        let mut output = vec![];
+        write_pain001(&payouts, "OUR-ACCOUNT & CO", &info(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("<NbOfTxs>2</NbOfTxs>"));
+        assert!(output.contains("<CtrlSum>15.0000</CtrlSum>"));
+        assert!(output.contains("<Dbtr><Nm>OUR-ACCOUNT &amp; CO</Nm></Dbtr>"));
+        assert!(output.contains(r#"<InstdAmt Ccy="EUR">5.0000</InstdAmt>"#));
+        assert!(output.contains("<EndToEndId>PLAYGROUND-1660000000-53a562-2</EndToEndId>"));
+        assert!(write_pain001(&[], "OUR-ACCOUNT", &info(), &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn should_write_well_formed_camt053_with_the_required_elements() {
+        let mut output = vec![];
+        write_camt053(&engine(), &info(), &mut output).unwrap();
+        let elements = elements(&output);
+        assert_eq!(elements[0].0, "Document");
+
+        let statement = "Document/BkToCstmrStmt/Stmt";
+        assert_eq!(texts(&elements, statement).len(), 2);
+        for (path, count) in [
+            ("Document/BkToCstmrStmt/GrpHdr/MsgId", 1),
+            ("Document/BkToCstmrStmt/GrpHdr/CreDtTm", 1),
+            ("Document/BkToCstmrStmt/Stmt/Id", 2),
+            ("Document/BkToCstmrStmt/Stmt/CreDtTm", 2),
+            ("Document/BkToCstmrStmt/Stmt/Acct/Id/Othr/Id", 2),
+            ("Document/BkToCstmrStmt/Stmt/Bal/Tp/CdOrPrtry/Cd", 6),
+            ("Document/BkToCstmrStmt/Stmt/Bal/Amt", 6),
+            ("Document/BkToCstmrStmt/Stmt/Bal/CdtDbtInd", 6),
+            ("Document/BkToCstmrStmt/Stmt/Bal/Dt/Dt", 6),
+            ("Document/BkToCstmrStmt/Stmt/Ntry/Amt", 3),
+            ("Document/BkToCstmrStmt/Stmt/Ntry/CdtDbtInd", 3),
+            ("Document/BkToCstmrStmt/Stmt/Ntry/Sts", 3),
+            ("Document/BkToCstmrStmt/Stmt/Ntry/BkTxCd/Prtry/Cd", 3),
+        ] {
+            let texts = texts(&elements, path);
+            assert_eq!(texts.len(), count, "{}", path);
+            assert!(texts.iter().all(|text| !text.is_empty()), "{}", path);
+        }
+        // Max35Text
+        for path in [
+            "Document/BkToCstmrStmt/GrpHdr/MsgId",
+            "Document/BkToCstmrStmt/Stmt/Id",
+        ] {
+            assert!(texts(&elements, path).iter().all(|id| id.len() <= 35));
+        }
+    }
+
+    #[test]
+    fn should_write_well_formed_pain001_with_the_required_elements() {
+        let mut output = vec![];
+        let payouts = crate::settlement::payouts(&engine());
+        write_pain001(&payouts, "OUR-ACCOUNT & CO", &info(), &mut output).unwrap();
+        let elements = elements(&output);
+        assert_eq!(elements[0].0, "Document");
+
+        for (path, count) in [
+            ("Document/CstmrCdtTrfInitn/GrpHdr/MsgId", 1),
+            ("Document/CstmrCdtTrfInitn/GrpHdr/CreDtTm", 1),
+            ("Document/CstmrCdtTrfInitn/GrpHdr/NbOfTxs", 1),
+            ("Document/CstmrCdtTrfInitn/GrpHdr/InitgPty/Nm", 1),
+            ("Document/CstmrCdtTrfInitn/PmtInf/PmtInfId", 1),
+            ("Document/CstmrCdtTrfInitn/PmtInf/PmtMtd", 1),
+            ("Document/CstmrCdtTrfInitn/PmtInf/ReqdExctnDt", 1),
+            ("Document/CstmrCdtTrfInitn/PmtInf/Dbtr/Nm", 1),
+            ("Document/CstmrCdtTrfInitn/PmtInf/DbtrAcct/Id/Othr/Id", 1),
+            (
+                "Document/CstmrCdtTrfInitn/PmtInf/CdtTrfTxInf/PmtId/EndToEndId",
+                2,
+            ),
+            (
+                "Document/CstmrCdtTrfInitn/PmtInf/CdtTrfTxInf/Amt/InstdAmt",
+                2,
+            ),
+            (
+                "Document/CstmrCdtTrfInitn/PmtInf/CdtTrfTxInf/CdtrAcct/Id/Othr/Id",
+                2,
+            ),
+        ] {
+            let texts = texts(&elements, path);
+            assert_eq!(texts.len(), count, "{}", path);
+            assert!(texts.iter().all(|text| !text.is_empty()), "{}", path);
+        }
+        // an agent is required, even without anything known about it
+        let agent = "Document/CstmrCdtTrfInitn/PmtInf/DbtrAgt/FinInstnId";
+        assert_eq!(texts(&elements, agent).len(), 1);
+        assert_eq!(
+            texts(&elements, "Document/CstmrCdtTrfInitn/PmtInf/Dbtr/Nm"),
+            ["OUR-ACCOUNT & CO"]
+        );
+        for path in [
+            "Document/CstmrCdtTrfInitn/GrpHdr/MsgId",
+            "Document/CstmrCdtTrfInitn/PmtInf/PmtInfId",
+            "Document/CstmrCdtTrfInitn/PmtInf/CdtTrfTxInf/PmtId/EndToEndId",
+        ] {
+            assert!(texts(&elements, path).iter().all(|id| id.len() <= 35));
+        }
+    }
+
+    #[test]
+    fn should_give_messages_created_in_the_same_second_their_own_ids() {
+        let message_id = |write: &dyn Fn(&mut Vec<u8>)| {
+            let mut output = vec![];
+            write(&mut output);
+            let elements = elements(&output);
+            let header = elements
+                .iter()
+                .find(|(path, _)| path.ends_with("GrpHdr/MsgId"));
+            header.unwrap().1.clone()
+        };
+        let mut engine = engine();
+        let statement = |engine: &TransactionEngine| {
+            message_id(&|output| write_camt053(engine, &info(), output).unwrap())
+        };
+        let transfer = |engine: &TransactionEngine| {
+            message_id(&|output| {
+                let payouts = crate::settlement::payouts(engine);
+                write_pain001(&payouts, "OUR-ACCOUNT", &info(), output).unwrap();
+            })
+        };
+        let (first_statement, first_transfer) = (statement(&engine), transfer(&engine));
+        assert_eq!(first_statement, statement(&engine));
+        assert_ne!(first_statement, first_transfer);
+
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 4,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        });
+        assert_ne!(statement(&engine), first_statement);
+        assert_ne!(transfer(&engine), first_transfer);
+    }
+}
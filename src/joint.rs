@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use anyhow::{bail, Result};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::ledger::EntryKind;
+use crate::transaction::{ClientId, Transaction};
+use crate::transaction_engine::TransactionEngine;
+
+/// Clients that share one account, e.g. a couple's joint account. The transactions of every
+/// holder are applied to the shared account, so a dispute finds the tx whichever holder sent
+/// it, and the ledger entries remember the holder who did
+#[derive(Clone, Debug, Default)]
+pub struct JointAccounts {
+    accounts: HashMap<ClientId, ClientId>,
+}
+
+#[derive(Deserialize)]
+struct CsvHolder {
+    client: ClientId,
+    account: ClientId,
+}
+
+impl JointAccounts {
+    /// Reads a `client,account` csv, the account is a client id of its own. An account can't
+    /// be a holder of another one, and a client listed twice ends up with the last account
+    pub fn read_csv(reader: impl Read) -> Result<Self> {
+        let mut joint = Self::default();
+        for row in crate::transaction::csv_reader(reader).deserialize() {
+            let CsvHolder { client, account } = row?;
+            if client != account {
+                joint.accounts.insert(client, account);
+            }
+        }
+        if let Some((client, account)) = joint
+            .accounts
+            .iter()
+            .find(|(_, account)| joint.accounts.contains_key(account))
+        {
+            bail!(
+                "Client {} holds account {}, which is a holder of account {} itself",
+                client,
+                account,
+                joint.accounts[account]
+            );
+        }
+        Ok(joint)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// The account the client's transactions go to, its own unless it's a joint holder
+    pub fn account(&self, client: ClientId) -> ClientId {
+        self.accounts.get(&client).copied().unwrap_or(client)
+    }
+
+    /// The transaction for the holder's account, an escrow for a holder is paid to the account
+    pub fn map(&self, mut transaction: Transaction) -> Transaction {
+        if self.accounts.is_empty() {
+            return transaction;
+        }
+        match &mut transaction {
+            Transaction::Deposit { client, .. }
+            | Transaction::Withdrawal { client, .. }
+            | Transaction::Dispute { client, .. }
+            | Transaction::Resolve { client, .. }
+            | Transaction::Chargeback { client, .. }
+            | Transaction::Transfer { client, .. }
+            | Transaction::EscrowRelease { client, .. }
+            | Transaction::EscrowRefund { client, .. }
+            | Transaction::Approve { client, .. }
+            | Transaction::Deny { client, .. }
+            | Transaction::Reversal { client, .. }
+            | Transaction::WalletTransfer { client, .. }
+            | Transaction::PromoCredit { client, .. }
+            | Transaction::Custom { client, .. } => *client = self.account(*client),
+            Transaction::EscrowOpen { client, seller, .. } => {
+                *client = self.account(*client);
+                *seller = self.account(*seller);
+            }
+        }
+        transaction
+    }
+}
+
+/// What one holder did to an account, the account itself is a holder of the transactions
+/// that were sent with its own id
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvHolderSummary {
+    /// The account
+    pub client: ClientId,
+    pub holder: ClientId,
+    pub deposits: usize,
+    pub deposited: String,
+    pub withdrawals: usize,
+    pub withdrawn: String,
+    /// How much the holder's transactions changed the total balance of the account
+    pub net: String,
+}
+
+/// One summary per account and holder, sorted by account and holder. Operator actions and
+/// the credits from other accounts are the account's own
+pub fn summarize(engine: &TransactionEngine) -> Vec<CsvHolderSummary> {
+    #[derive(Default)]
+    struct Totals {
+        deposits: usize,
+        deposited: BigDecimal,
+        withdrawals: usize,
+        withdrawn: BigDecimal,
+        net: BigDecimal,
+    }
+
+    let mut totals = BTreeMap::<(ClientId, ClientId), Totals>::new();
+    for account in engine.account_views() {
+        for entry in account.ledger() {
+            let holder = entry.holder.unwrap_or(account.client());
+            let totals = totals.entry((account.client(), holder)).or_default();
+            match entry.kind {
+                EntryKind::Deposit => {
+                    totals.deposits += 1;
+                    totals.deposited += &entry.available;
+                }
+                EntryKind::Withdrawal => {
+                    totals.withdrawals += 1;
+                    totals.withdrawn -= &entry.available;
+                }
+                _ => {}
+            }
+            totals.net += entry.total();
+        }
+    }
+    totals
+        .into_iter()
+        .map(|((client, holder), totals)| CsvHolderSummary {
+            client,
+            holder,
+            deposits: totals.deposits,
+            deposited: format!("{:.4}", totals.deposited),
+            withdrawals: totals.withdrawals,
+            withdrawn: format!("{:.4}", totals.withdrawn),
+            net: format!("{:.4}", totals.net),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+
+    #[test]
+    fn should_apply_the_holders_transactions_to_their_account() {
+        let joint = JointAccounts::read_csv("client,account\n2,1\n3,1\n".as_bytes()).unwrap();
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            joint_accounts: joint,
+            ..EngineConfig::default()
+        });
+        let amount = |amount| BigDecimal::from(amount);
+        for transaction in [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: amount(10),
+                wallet: None,
+            },
+            Transaction::Deposit {
+                client: 2,
+                tx: 2,
+                amount: amount(20),
+                wallet: None,
+            },
+            Transaction::Withdrawal {
+                client: 3,
+                tx: 3,
+                amount: amount(5),
+                wallet: None,
+            },
+            // the other holder disputes the deposit
+            Transaction::Dispute { client: 3, tx: 2 },
+            Transaction::Deposit {
+                client: 4,
+                tx: 4,
+                amount: amount(1),
+                wallet: None,
+            },
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        assert!(engine.account(2).is_none());
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available(), &amount(5));
+        assert_eq!(account.held(), &amount(20));
+
+        let summaries = summarize(&engine)
+            .into_iter()
+            .map(|summary| (summary.client, summary.holder, summary.net))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            [
+                (1, 1, "10.0000".to_string()),
+                (1, 2, "20.0000".to_string()),
+                (1, 3, "-5.0000".to_string()),
+                (4, 4, "1.0000".to_string()),
+            ]
+        );
+
+        assert!(JointAccounts::read_csv("client,account\n2,1\n1,5\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt::{self, Write};
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+
+/// Just enough JSON for API responses and reports, and for reading the odd schema
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// Already formatted, e.g. `12` or `0.5`
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    pub fn array<T: Into<Json>>(items: impl IntoIterator<Item = T>) -> Self {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser { text, position: 0 };
+        let json = parser.value()?;
+        parser.whitespace();
+        if parser.position < text.len() {
+            bail!("Unexpected {} after the JSON", parser.rest());
+        }
+        Ok(json)
+    }
+
+    /// The field of an object, `None` for anything else
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        let rest = &self.text[self.position..];
+        &rest[..rest
+            .char_indices()
+            .nth(20)
+            .map_or(rest.len(), |(end, _)| end)]
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.whitespace();
+        let eaten = self.text[self.position..].starts_with(token);
+        if eaten {
+            self.position += token.len();
+        }
+        eaten
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if !self.eat(token) {
+            bail!("Expected {} at {}", token, self.rest());
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.whitespace();
+        let rest = &self.text[self.position..];
+        Ok(match rest.chars().next() {
+            Some('{') => {
+                self.position += 1;
+                let mut fields = vec![];
+                if !self.eat("}") {
+                    loop {
+                        self.whitespace();
+                        let key = self.string()?;
+                        self.expect(":")?;
+                        fields.push((key, self.value()?));
+                        if self.eat("}") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Json::Object(fields)
+            }
+            Some('[') => {
+                self.position += 1;
+                let mut items = vec![];
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Json::Array(items)
+            }
+            Some('"') => Json::String(self.string()?),
+            Some('-' | '0'..='9') => {
+                let length = rest
+                    .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                    .unwrap_or(rest.len());
+                let number = &rest[..length];
+                if number.parse::<f64>().is_err() {
+                    bail!("Invalid number {}", number);
+                }
+                self.position += length;
+                Json::Number(number.to_string())
+            }
+            _ if self.eat("null") => Json::Null,
+            _ if self.eat("true") => Json::Bool(true),
+            _ if self.eat("false") => Json::Bool(false),
+            _ => bail!("Unexpected {}", self.rest()),
+        })
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect("\"")?;
+        let mut value = String::new();
+        let mut chars = self.text[self.position..].char_indices();
+        loop {
+            let (index, c) = chars.next().context("Unterminated string")?;
+            match c {
+                '"' => {
+                    self.position += index + 1;
+                    return Ok(value);
+                }
+                '\\' => {
+                    let (_, escaped) = chars.next().context("Unterminated string")?;
+                    value.push(match escaped {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let hex = (0..4)
+                                .map(|_| chars.next().map(|(_, c)| c))
+                                .collect::<Option<String>>()
+                                .context("Unterminated string")?;
+                            let code = u32::from_str_radix(&hex, 16)
+                                .with_context(|| format!("Invalid escape \\u{}", hex))?;
+                            // surrogate pairs aren't worth it for schemas
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        other => other,
+                    });
+                }
+                c => value.push(c),
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+macro_rules! json_number {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Json {
+            fn from(value: $t) -> Self {
+                Json::Number(value.to_string())
+            }
+        })*
+    };
+}
+
+json_number!(u16, u32, u64, usize, i64);
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        if value.is_finite() {
+            Json::Number(value.to_string())
+        } else {
+            Json::Null
+        }
+    }
+}
+
+/// Amounts are written as strings with four decimals, same as the csv output,
+/// so no precision is lost on the way through a float
+impl From<&BigDecimal> for Json {
+    fn from(value: &BigDecimal) -> Self {
+        Json::String(format!("{:.4}", value))
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => f.write_str(value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_write_nested_json() {
+        let json = Json::object([
+            ("client", Json::from(1_u16)),
+            ("amount", Json::from(&BigDecimal::from(2))),
+            ("note", Json::from("say \"hi\"\n")),
+            ("tags", Json::array(["a", "b"])),
+            ("score", Json::from(f64::NAN)),
+            ("locked", Json::from(false)),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            r#"{"client":1,"amount":"2.0000","note":"say \"hi\"\n","tags":["a","b"],"score":null,"locked":false}"#
+        );
+    }
+
+    #[test]
+    fn should_parse_what_it_writes() {
+        let text = r#" {"a": [1, -2.5e3, "x\"\u00e9"], "b": {"c": null, "d": true}, "e": []} "#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json.to_string(),
+            r#"{"a":[1,-2.5e3,"x\"é"],"b":{"c":null,"d":true},"e":[]}"#
+        );
+        assert_eq!(
+            json.get("b").and_then(|b| b.get("d")),
+            Some(&Json::Bool(true))
+        );
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+        assert!(Json::parse(r#"{"a": 1,}"#).is_err());
+        assert!(Json::parse("[1] 2").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use crate::date::unix_now;
+
+/// Decides which of several instances is the leader, the others stand by until the leader's
+/// lease runs out. Holders have to renew well within the lease and stop leading as soon as
+/// a renewal says they lost it
+pub trait LeaderLock: Send {
+    /// Whether this instance now holds the lease
+    fn try_acquire(&mut self) -> Result<bool>;
+    /// Extends the lease, false if someone else holds it now
+    fn renew(&mut self) -> Result<bool>;
+}
+
+/// The lock given on the command line, a path or `file://` url of a lease file on storage the
+/// instances share. Redis and etcd need clients the build doesn't have
+pub fn open(url: &str, owner: String, ttl_seconds: u64) -> Result<Box<dyn LeaderLock>> {
+    match url.split_once("://") {
+        None => Ok(Box::new(FileLease::new(url, owner, ttl_seconds))),
+        Some(("file", path)) => Ok(Box::new(FileLease::new(path, owner, ttl_seconds))),
+        Some((scheme @ ("redis" | "etcd"), _)) => bail!(
+            "{} leader locks are not supported in this build, use a lease file",
+            scheme
+        ),
+        Some((scheme, _)) => bail!("Unsupported leader lock scheme '{}': {}", scheme, url),
+    }
+}
+
+/// A lease in a file with the owner and when it expires (unix seconds). Replaced with a
+/// rename, so readers never see half a lease, and read back to find out who won a race
+pub struct FileLease {
+    path: PathBuf,
+    owner: String,
+    ttl_seconds: u64,
+}
+
+impl FileLease {
+    pub fn new(path: impl Into<PathBuf>, owner: String, ttl_seconds: u64) -> Self {
+        Self {
+            path: path.into(),
+            owner,
+            ttl_seconds,
+        }
+    }
+
+    /// The owner and expiry of the current lease, `None` if there is none
+    fn read(&self) -> Result<Option<(String, u64)>> {
+        let lease = match fs::read_to_string(&self.path) {
+            Ok(lease) => lease,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read the lease"),
+        };
+        let (owner, expires_at) = lease
+            .trim()
+            .rsplit_once(' ')
+            .with_context(|| format!("Invalid lease in {}", self.path.display()))?;
+        let expires_at = expires_at
+            .parse()
+            .with_context(|| format!("Invalid lease in {}", self.path.display()))?;
+        Ok(Some((owner.to_string(), expires_at)))
+    }
+
+    fn write(&self) -> Result<()> {
+        let lease = format!("{} {}\n", self.owner, unix_now() + self.ttl_seconds);
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(format!(".{}", std::process::id()));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary)?;
+        file.write_all(lease.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path).context("Failed to replace the lease")?;
+        Ok(())
+    }
+
+    fn held(&self) -> Result<bool> {
+        Ok(self.read()?.is_some_and(|(owner, _)| owner == self.owner))
+    }
+}
+
+impl LeaderLock for FileLease {
+    fn try_acquire(&mut self) -> Result<bool> {
+        match self.read()? {
+            Some((owner, expires_at)) if owner != self.owner && expires_at > unix_now() => {
+                Ok(false)
+            }
+            _ => {
+                self.write()?;
+                // another standby may have written its lease at the same time, the last rename wins
+                self.held()
+            }
+        }
+    }
+
+    fn renew(&mut self) -> Result<bool> {
+        if !self.held()? {
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_hand_over_expired_leases() {
+        let path = std::env::temp_dir().join(format!("leader-{}.lock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut leader = FileLease::new(&path, "a".to_string(), 60);
+        let mut standby = FileLease::new(&path, "b".to_string(), 60);
+        assert!(leader.try_acquire().unwrap());
+        assert!(!standby.try_acquire().unwrap());
+        assert!(leader.renew().unwrap());
+
+        // a lease that has run out is up for grabs
+        let mut expired = FileLease::new(&path, "a".to_string(), 0);
+        assert!(expired.renew().unwrap());
+        assert!(standby.try_acquire().unwrap());
+        assert!(!leader.renew().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(open("redis://localhost", "a".to_string(), 10).is_err());
+    }
+}
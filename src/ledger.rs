@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::enrichment::Enrichment;
+use crate::memory::HeapSize;
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+
+/// What caused a ledger entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+    /// Funds moved from available to held by a dispute
+    Hold,
+    /// Held funds released back to available by a resolve
+    Release,
+    /// Held funds removed by a chargeback
+    Chargeback,
+    /// Held funds released back to available because the dispute timed out
+    Expiry,
+    /// Balances carried over from the accounts csv of a previous run
+    Opening,
+    /// A correction of the available balance by an operator
+    Adjustment,
+    /// Locked by an operator, doesn't change the balances
+    Lock,
+    /// Unlocked by an operator, doesn't change the balances
+    Unlock,
+    /// Money moved in or out of available by a `TransactionHandler`
+    Custom,
+    /// Money moved between available and a pocket, or between pockets, see `pocket`
+    Transfer,
+    /// Funds moved from available to held by an `escrow_open`
+    EscrowHold,
+    /// Held funds removed from the buyer, or added to the seller's available, by an
+    /// `escrow_release`. The seller's entry has the tx of the buyer's escrow
+    EscrowRelease,
+    /// Held funds released back to the buyer's available by an `escrow_refund`
+    EscrowRefund,
+    /// What a deposit or withdrawal cost, or the fee credited to the fee account
+    Fee,
+    /// Funds moved from available to held by a withdrawal that waits for an `approve` or `deny`
+    PendingWithdrawal,
+    /// Held funds of a pending withdrawal back in available by its `approve`, which takes
+    /// them out with a withdrawal entry right after
+    Approval,
+    /// Promotional credits added, see `promo`
+    PromoCredit,
+    /// What was left of a promotional credit removed at its expiry
+    PromoExpiry,
+    /// Funds moved from available to held by an operator, see `TransactionEngine::hold`
+    AdminHold,
+    /// Funds held by an operator released back to available
+    AdminRelease,
+    /// What a chargeback cost, taken from the client or the fee account, or credited to
+    /// the fee account, see `FeeSchedule`
+    ChargebackFee,
+    /// What a withdrawal took out of available given back by its `reversal`
+    Reversal,
+    /// Held funds of a pending withdrawal back in available by its `deny`
+    Denial,
+}
+
+impl EntryKind {
+    pub const fn name(self) -> &'static str {
+        match self {
+            EntryKind::Deposit => "deposit",
+            EntryKind::Withdrawal => "withdrawal",
+            EntryKind::Hold => "hold",
+            EntryKind::Release => "release",
+            EntryKind::Chargeback => "chargeback",
+            EntryKind::Expiry => "expiry",
+            EntryKind::Opening => "opening",
+            EntryKind::Adjustment => "adjustment",
+            EntryKind::Lock => "lock",
+            EntryKind::Unlock => "unlock",
+            EntryKind::Custom => "custom",
+            EntryKind::Transfer => "transfer",
+            EntryKind::EscrowHold => "escrow_hold",
+            EntryKind::EscrowRelease => "escrow_release",
+            EntryKind::EscrowRefund => "escrow_refund",
+            EntryKind::Fee => "fee",
+            EntryKind::PendingWithdrawal => "pending_withdrawal",
+            EntryKind::Approval => "approval",
+            EntryKind::PromoCredit => "promo_credit",
+            EntryKind::PromoExpiry => "promo_expiry",
+            EntryKind::AdminHold => "admin_hold",
+            EntryKind::AdminRelease => "admin_release",
+            EntryKind::ChargebackFee => "chargeback_fee",
+            EntryKind::Reversal => "reversal",
+            EntryKind::Denial => "denial",
+        }
+    }
+}
+
+impl Snapshot for EntryKind {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.tag(*self as u8);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(match decoder.tag()? {
+            0 => EntryKind::Deposit,
+            1 => EntryKind::Withdrawal,
+            2 => EntryKind::Hold,
+            3 => EntryKind::Release,
+            4 => EntryKind::Chargeback,
+            5 => EntryKind::Expiry,
+            6 => EntryKind::Opening,
+            7 => EntryKind::Adjustment,
+            8 => EntryKind::Lock,
+            9 => EntryKind::Unlock,
+            10 => EntryKind::Custom,
+            11 => EntryKind::Transfer,
+            12 => EntryKind::EscrowHold,
+            13 => EntryKind::EscrowRelease,
+            14 => EntryKind::EscrowRefund,
+            15 => EntryKind::Fee,
+            16 => EntryKind::PendingWithdrawal,
+            17 => EntryKind::Approval,
+            18 => EntryKind::PromoCredit,
+            19 => EntryKind::PromoExpiry,
+            20 => EntryKind::AdminHold,
+            21 => EntryKind::AdminRelease,
+            22 => EntryKind::ChargebackFee,
+            23 => EntryKind::Reversal,
+            24 => EntryKind::Denial,
+            tag => bail!("Invalid ledger entry kind {} in snapshot", tag),
+        })
+    }
+}
+
+/// One change that was actually applied to an account's balances,
+/// the amounts are signed deltas so summing an account's entries gives its balances
+#[derive(Clone, Debug, PartialEq)]
+pub struct LedgerEntry {
+    pub tx: u32,
+    pub kind: EntryKind,
+    pub available: BigDecimal,
+    pub held: BigDecimal,
+    /// Where the transaction behind it came from, e.g. an acquirer's feed, see
+    /// `TransactionEngine::set_source`. Operator actions and expiries have none
+    pub source: Option<Arc<str>>,
+    /// The pocket a transfer moved money into, negative when out of it. `available` is
+    /// what it did to the available balance, zero between two pockets
+    pub pockets: Vec<(String, BigDecimal)>,
+    /// The sequence number of the transaction behind it, see
+    /// `TransactionEngine::applied_sequence`. Operator actions and expiries have none
+    pub sequence: Option<u64>,
+    /// What the registered `Enricher`s know about the deposit or withdrawal, also on the
+    /// entries of its disputes
+    pub enrichment: Option<Arc<Enrichment>>,
+    /// The joint holder who sent the transaction behind it, see `EngineConfig::joint_accounts`.
+    /// None when the account's own id did
+    pub holder: Option<ClientId>,
+    /// The wallet whose balances `available` and `held` are, None for the main one. A wallet
+    /// transfer has an entry for each wallet
+    pub wallet: Option<String>,
+    /// What it did to the promotional credits, a withdrawal takes them before `available`
+    pub promo: BigDecimal,
+}
+
+impl LedgerEntry {
+    pub fn new(tx: u32, kind: EntryKind, available: BigDecimal, held: BigDecimal) -> Self {
+        Self {
+            tx,
+            kind,
+            available,
+            held,
+            source: None,
+            pockets: vec![],
+            sequence: None,
+            enrichment: None,
+            holder: None,
+            wallet: None,
+            promo: BigDecimal::zero(),
+        }
+    }
+
+    /// The entry in `wallet`'s balances
+    pub fn in_wallet(mut self, wallet: Option<&str>) -> Self {
+        self.wallet = wallet.map(str::to_string);
+        self
+    }
+
+    /// The entry with what it did to the promotional credits
+    pub fn with_promo(mut self, promo: BigDecimal) -> Self {
+        self.promo = promo;
+        self
+    }
+
+    /// How much the entry changed the account's total balance, the pockets and the promotional
+    /// credits are part of it
+    pub fn total(&self) -> BigDecimal {
+        self.pockets.iter().fold(
+            &self.available + &self.held + &self.promo,
+            |total, (_, amount)| total + amount,
+        )
+    }
+}
+
+impl HeapSize for LedgerEntry {
+    fn heap_size(&self) -> usize {
+        // the source is shared with the engine's other entries from it
+        self.available.heap_size()
+            + self.held.heap_size()
+            + self
+                .pockets
+                .iter()
+                .map(|(pocket, amount)| pocket.capacity() + amount.heap_size())
+                .sum::<usize>()
+            + self.enrichment.as_deref().map_or(0, HeapSize::heap_size)
+            + self.wallet.as_ref().map_or(0, String::capacity)
+            + self.promo.heap_size()
+    }
+}
+
+impl Snapshot for LedgerEntry {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.tx.encode(encoder);
+        self.kind.encode(encoder);
+        self.available.encode(encoder);
+        self.held.encode(encoder);
+        self.source.as_deref().map(str::to_string).encode(encoder);
+        self.pockets.encode(encoder);
+        self.sequence.encode(encoder);
+        self.enrichment.as_deref().cloned().encode(encoder);
+        self.holder.map(Client).encode(encoder);
+        self.wallet.encode(encoder);
+        self.promo.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            tx: u32::decode(decoder)?,
+            kind: EntryKind::decode(decoder)?,
+            available: BigDecimal::decode(decoder)?,
+            held: BigDecimal::decode(decoder)?,
+            source: match decoder.version() {
+                ..=3 => None,
+                _ => Option::<String>::decode(decoder)?.map(Arc::from),
+            },
+            pockets: match decoder.version() {
+                ..=4 => vec![],
+                _ => Vec::decode(decoder)?,
+            },
+            sequence: match decoder.version() {
+                ..=12 => None,
+                _ => Option::decode(decoder)?,
+            },
+            enrichment: match decoder.version() {
+                ..=13 => None,
+                _ => Option::<Enrichment>::decode(decoder)?.map(Arc::new),
+            },
+            holder: match decoder.version() {
+                ..=15 => None,
+                _ => Option::<Client>::decode(decoder)?.map(|holder| holder.0),
+            },
+            wallet: match decoder.version() {
+                ..=16 => None,
+                _ => Option::decode(decoder)?,
+            },
+            promo: match decoder.version() {
+                ..=17 => BigDecimal::zero(),
+                _ => BigDecimal::decode(decoder)?,
+            },
+        })
+    }
+}
@@ -0,0 +1,64 @@
+pub mod alerts;
+pub mod amount;
+pub mod anomaly;
+pub mod approval;
+pub mod avro;
+pub mod chaos;
+pub mod client_ids;
+pub mod clock;
+pub mod config;
+pub mod date;
+pub mod dedup;
+pub mod diagnostics;
+pub mod dispute;
+pub mod dunning;
+pub mod encryption;
+pub mod enrichment;
+pub mod errors;
+pub mod escrow;
+pub mod event_log;
+pub mod fees;
+pub mod filter;
+pub mod flows;
+pub mod generator;
+pub mod groups;
+pub mod handler;
+pub mod history;
+pub mod hold;
+pub mod http;
+pub mod idempotency;
+pub mod iso20022;
+pub mod joint;
+pub mod json;
+pub mod lease;
+pub mod ledger;
+pub mod locale;
+pub mod lock;
+pub mod memory;
+pub mod msgpack;
+pub mod notes;
+pub mod plugin;
+pub mod profile;
+pub mod promo;
+pub mod protobuf;
+pub mod pseudonym;
+pub mod read_only;
+pub mod refs;
+pub mod reserve;
+pub mod risk;
+pub mod rules;
+pub mod screening;
+pub mod session;
+pub mod settlement;
+pub mod sha256;
+pub mod snapshot;
+pub mod sources;
+pub mod store;
+pub mod tags;
+pub mod trace;
+pub mod transaction;
+pub mod transaction_engine;
+pub mod transform;
+pub mod velocity;
+pub mod wallet;
+pub mod xml;
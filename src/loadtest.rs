@@ -0,0 +1,154 @@
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use playground::generator::Generator;
+use playground::http::Target;
+use playground::json::Json;
+use playground::transaction;
+
+use crate::cli::LoadtestOptions;
+
+/// What one connection saw
+#[derive(Default)]
+struct Tally {
+    requests: u64,
+    transactions: u64,
+    /// Requests that failed or weren't answered with a 2xx
+    errors: u64,
+    /// Transactions the server answered but didn't apply
+    rejected: u64,
+    latencies: Vec<Duration>,
+}
+
+/// Posts generated transactions to the server at `options.url` for as long as `options.duration`, in
+/// batches, as close to `options.rate` transactions a second as it keeps up with, and writes
+/// the latencies and error rates it saw as JSON. Every connection has clients and tx ids of
+/// its own, so their disputes refer to deposits the server has. The pace doesn't wait for a
+/// slow server, a request that's late is sent right away and the latency shows it
+pub fn run(options: &LoadtestOptions, writer: &mut dyn Write) -> Result<()> {
+    let target = Target::parse(&options.url)?;
+    let clients = (options.clients / options.connections as u64).max(1);
+    let txs = u32::MAX / options.connections as u32;
+    let interval =
+        Duration::from_secs_f64((options.batch * options.connections) as f64 / options.rate as f64);
+    let start = Instant::now();
+    let tallies = thread::scope(|scope| {
+        let workers = (0..options.connections)
+            .map(|connection| {
+                let generator = Generator::new(
+                    options.mix.clone(),
+                    1 + connection as u64 * clients,
+                    clients,
+                    1 + connection as u32 * txs,
+                    options.seed.wrapping_add(connection as u64),
+                );
+                let target = &target;
+                scope.spawn(move || drive(target, generator, options, start, interval))
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("a load test connection panicked"))
+            .collect::<Vec<_>>()
+    });
+    let elapsed = start.elapsed();
+
+    let mut total = Tally::default();
+    for tally in tallies {
+        total.requests += tally.requests;
+        total.transactions += tally.transactions;
+        total.errors += tally.errors;
+        total.rejected += tally.rejected;
+        total.latencies.extend(tally.latencies);
+    }
+    writeln!(writer, "{}", summary(&mut total, elapsed))?;
+    Ok(())
+}
+
+fn drive(
+    target: &Target,
+    mut generator: Generator,
+    options: &LoadtestOptions,
+    start: Instant,
+    interval: Duration,
+) -> Tally {
+    let mut tally = Tally::default();
+    let mut due = start;
+    while due.duration_since(start) < options.duration {
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        due += interval;
+        let batch = generator.by_ref().take(options.batch).collect::<Vec<_>>();
+        let mut body = vec![];
+        transaction::write_transactions(&batch, &mut body).expect("writing to memory");
+        let sent = Instant::now();
+        let reply = target.send(
+            "POST",
+            "transactions",
+            Some(("text/csv", &body)),
+            Duration::from_secs(10),
+        );
+        tally.latencies.push(sent.elapsed());
+        tally.requests += 1;
+        tally.transactions += batch.len() as u64;
+        match reply {
+            Ok(reply) if (200..300).contains(&reply.status) => {
+                let body = String::from_utf8_lossy(&reply.body);
+                if let Ok(Json::Array(outcomes)) = Json::parse(&body) {
+                    tally.rejected += outcomes
+                        .iter()
+                        .filter(|outcome| {
+                            outcome.get("status").and_then(Json::as_str) == Some("rejected")
+                        })
+                        .count() as u64;
+                }
+            }
+            _ => tally.errors += 1,
+        }
+    }
+    tally
+}
+
+fn summary(total: &mut Tally, elapsed: Duration) -> Json {
+    total.latencies.sort_unstable();
+    let percentile = |p: usize| {
+        let index = total.latencies.len().saturating_sub(1) * p / 100;
+        let latency = total.latencies.get(index).copied().unwrap_or_default();
+        Json::from(latency.as_secs_f64() * 1000.0)
+    };
+    let ratio = |count: u64, of: u64| match of {
+        0 => 0.0,
+        of => count as f64 / of as f64,
+    };
+    Json::object([
+        ("requests", Json::from(total.requests)),
+        ("transactions", Json::from(total.transactions)),
+        ("duration_s", Json::from(elapsed.as_secs_f64())),
+        (
+            "rate",
+            Json::from(total.transactions as f64 / elapsed.as_secs_f64().max(f64::EPSILON)),
+        ),
+        ("errors", Json::from(total.errors)),
+        (
+            "error_rate",
+            Json::from(ratio(total.errors, total.requests)),
+        ),
+        ("rejected", Json::from(total.rejected)),
+        (
+            "rejected_rate",
+            Json::from(ratio(total.rejected, total.transactions)),
+        ),
+        (
+            "latency_ms",
+            Json::object([
+                ("p50", percentile(50)),
+                ("p90", percentile(90)),
+                ("p99", percentile(99)),
+                ("max", percentile(100)),
+            ]),
+        ),
+    ])
+}
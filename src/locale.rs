@@ -0,0 +1,270 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use crate::errors::ErrorCode;
+use crate::ledger::EntryKind;
+
+/// The language of what's written for people rather than programs: log lines, command line
+/// errors and the text of statements. Machine output, the csvs, JSON, reasons and error codes,
+/// is the same in every locale
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Sv,
+}
+
+/// Something said to an operator, in whichever locale they want it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// What a fatal error is prefixed with
+    Error,
+    /// What the progress logged on SIGUSR1 is prefixed with
+    Progress,
+    RowsRead(u64),
+    ExpectedValue {
+        flag: String,
+    },
+    InvalidValue {
+        flag: String,
+        value: String,
+    },
+    ExpectedCharacter {
+        flag: String,
+        value: String,
+    },
+    UnknownFlag {
+        flag: String,
+    },
+    UnexpectedArgument {
+        argument: String,
+    },
+    ExpectedFilename,
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    /// `en` or `sv`, a region like `sv-SE` or `sv_SE.UTF-8` is ignored
+    fn from_str(locale: &str) -> anyhow::Result<Self> {
+        let language = locale.split(['-', '_', '.']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Ok(Self::En),
+            "sv" => Ok(Self::Sv),
+            _ => bail!("Unknown locale {}, expected en or sv", locale),
+        }
+    }
+}
+
+impl Locale {
+    /// The `--locale` of the arguments, so the errors of the rest of them can be reported in it
+    pub fn of_args(args: &[String]) -> Self {
+        args.iter()
+            .position(|arg| arg == "--locale")
+            .and_then(|index| args.get(index + 1))
+            .and_then(|locale| locale.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn text(self, message: &Message) -> String {
+        match (self, message) {
+            (Self::En, Message::Error) => "Error".to_string(),
+            (Self::Sv, Message::Error) => "Fel".to_string(),
+            (Self::En, Message::Progress) => "Progress".to_string(),
+            (Self::Sv, Message::Progress) => "Förlopp".to_string(),
+            (Self::En, Message::RowsRead(rows)) => format!("{} rows read", rows),
+            (Self::Sv, Message::RowsRead(rows)) => format!("{} rader lästa", rows),
+            (Self::En, Message::ExpectedValue { flag }) => {
+                format!("Expected a value after {}", flag)
+            }
+            (Self::Sv, Message::ExpectedValue { flag }) => format!("{} saknar ett värde", flag),
+            (Self::En, Message::InvalidValue { flag, value }) => {
+                format!("Invalid value for {}: {}", flag, value)
+            }
+            (Self::Sv, Message::InvalidValue { flag, value }) => {
+                format!("Ogiltigt värde för {}: {}", flag, value)
+            }
+            (Self::En, Message::ExpectedCharacter { flag, value }) => {
+                format!("{} expects a single character, got {}", flag, value)
+            }
+            (Self::Sv, Message::ExpectedCharacter { flag, value }) => {
+                format!("{} ska vara ett enda tecken, inte {}", flag, value)
+            }
+            (Self::En, Message::UnknownFlag { flag }) => format!("Unknown flag {}", flag),
+            (Self::Sv, Message::UnknownFlag { flag }) => format!("Okänd flagga {}", flag),
+            (Self::En, Message::UnexpectedArgument { argument }) => {
+                format!("Unexpected argument {}", argument)
+            }
+            (Self::Sv, Message::UnexpectedArgument { argument }) => {
+                format!("Oväntat argument {}", argument)
+            }
+            (Self::En, Message::ExpectedFilename) => "Expected a filename".to_string(),
+            (Self::Sv, Message::ExpectedFilename) => "Ett filnamn saknas".to_string(),
+        }
+    }
+
+    /// The message of an error, in this locale if it's one of the catalog's
+    pub fn render(self, error: &anyhow::Error) -> String {
+        match error.downcast_ref::<Message>() {
+            Some(message) => self.text(message),
+            None => error.to_string(),
+        }
+    }
+
+    /// What an error code means, the details of an error are only in English
+    pub const fn describe(self, code: ErrorCode) -> &'static str {
+        match (self, code) {
+            (Self::En, ErrorCode::InsufficientFunds) => "insufficient funds",
+            (Self::Sv, ErrorCode::InsufficientFunds) => "otillräckligt saldo",
+            (Self::En, ErrorCode::AccountLocked) => "the account is locked",
+            (Self::Sv, ErrorCode::AccountLocked) => "kontot är låst",
+            (Self::En, ErrorCode::UnknownTransaction) => "the transaction does not exist",
+            (Self::Sv, ErrorCode::UnknownTransaction) => "transaktionen finns inte",
+            (Self::En, ErrorCode::AlreadyDisputed) => "the transaction is already disputed",
+            (Self::Sv, ErrorCode::AlreadyDisputed) => "transaktionen är redan bestridd",
+            (Self::En, ErrorCode::NotDisputed) => "the transaction is not disputed",
+            (Self::Sv, ErrorCode::NotDisputed) => "transaktionen är inte bestridd",
+            (Self::En, ErrorCode::HeldForReview) => "held for review",
+            (Self::Sv, ErrorCode::HeldForReview) => "väntar på granskning",
+            (Self::En, ErrorCode::RejectedByRule) => "rejected by a rule",
+            (Self::Sv, ErrorCode::RejectedByRule) => "nekad av en regel",
+            (Self::En, ErrorCode::ExcessDecimals) => "the amount has too many decimals",
+            (Self::Sv, ErrorCode::ExcessDecimals) => "beloppet har för många decimaler",
+            (Self::En, ErrorCode::UnknownAccount) => "the account does not exist",
+            (Self::Sv, ErrorCode::UnknownAccount) => "kontot finns inte",
+            (Self::En, ErrorCode::Pruned) => "the transaction was pruned",
+            (Self::Sv, ErrorCode::Pruned) => "transaktionen är rensad",
+            (Self::En, ErrorCode::TooManyAccounts) => "too many accounts",
+            (Self::Sv, ErrorCode::TooManyAccounts) => "för många konton",
+            (Self::En, ErrorCode::TooManyTransactions) => "too many stored transactions",
+            (Self::Sv, ErrorCode::TooManyTransactions) => "för många sparade transaktioner",
+            (Self::En, ErrorCode::RejectedByHandler) => "rejected by its handler",
+            (Self::Sv, ErrorCode::RejectedByHandler) => "nekad av sin hanterare",
+            (Self::En, ErrorCode::TransformFailed) => "the transform failed",
+            (Self::Sv, ErrorCode::TransformFailed) => "omvandlingen misslyckades",
+            (Self::En, ErrorCode::InvalidTransfer) => "invalid transfer",
+            (Self::Sv, ErrorCode::InvalidTransfer) => "ogiltig överföring",
+            (Self::En, ErrorCode::InvalidEscrow) => "invalid escrow",
+            (Self::Sv, ErrorCode::InvalidEscrow) => "ogiltig deposition",
+            (Self::En, ErrorCode::UnknownEscrow) => "the escrow is not open",
+            (Self::Sv, ErrorCode::UnknownEscrow) => "depositionen är inte öppen",
+            (Self::En, ErrorCode::PendingCommit) => "a transaction is waiting to be committed",
+            (Self::Sv, ErrorCode::PendingCommit) => "en transaktion väntar på att bekräftas",
+            (Self::En, ErrorCode::UnknownPrepared) => "nothing is prepared with the token",
+            (Self::Sv, ErrorCode::UnknownPrepared) => "inget är förberett med nyckeln",
+            (Self::En, ErrorCode::Blocked) => "the client is on the blocklist",
+            (Self::Sv, ErrorCode::Blocked) => "kunden är spärrad",
+            (Self::En, ErrorCode::OpeningConflict) => "the account already has an opening balance",
+            (Self::Sv, ErrorCode::OpeningConflict) => "kontot har redan ett ingående saldo",
+            (Self::En, ErrorCode::NotPendingApproval) => {
+                "the withdrawal is not waiting for approval"
+            }
+            (Self::Sv, ErrorCode::NotPendingApproval) => "uttaget väntar inte på godkännande",
+            (Self::En, ErrorCode::BelowReserve) => "the account would go below its reserve",
+            (Self::Sv, ErrorCode::BelowReserve) => "kontot skulle gå under sin reserv",
+            (Self::En, ErrorCode::InvalidPromoCredit) => "invalid promotional credit",
+            (Self::Sv, ErrorCode::InvalidPromoCredit) => "ogiltig kampanjkredit",
+            (Self::En, ErrorCode::InvalidHold) => "invalid hold or release",
+            (Self::Sv, ErrorCode::InvalidHold) => "ogiltig spärr eller frisläppning",
+            (Self::En, ErrorCode::AccountClosed) => "the account is closed",
+            (Self::Sv, ErrorCode::AccountClosed) => "kontot är avslutat",
+            (Self::En, ErrorCode::InvalidReversal) => "the withdrawal can't be reversed",
+            (Self::Sv, ErrorCode::InvalidReversal) => "uttaget kan inte återföras",
+            (Self::En, ErrorCode::AlreadyReversed) => "the withdrawal has been reversed",
+            (Self::Sv, ErrorCode::AlreadyReversed) => "uttaget har återförts",
+            (Self::En, ErrorCode::MalformedRow) => "the row can't be read",
+            (Self::Sv, ErrorCode::MalformedRow) => "raden går inte att läsa",
+            (Self::En, ErrorCode::InvalidAmount) => "invalid amount",
+            (Self::Sv, ErrorCode::InvalidAmount) => "ogiltigt belopp",
+            (Self::En, ErrorCode::MissingAmount) => "the amount is missing",
+            (Self::Sv, ErrorCode::MissingAmount) => "beloppet saknas",
+            (Self::En, ErrorCode::MissingSeller) => "the seller is missing",
+            (Self::Sv, ErrorCode::MissingSeller) => "säljaren saknas",
+            (Self::En, ErrorCode::MissingExpiry) => "the expiry is missing",
+            (Self::Sv, ErrorCode::MissingExpiry) => "utgångstiden saknas",
+            (Self::En, ErrorCode::UnknownType) => "unknown transaction type",
+            (Self::Sv, ErrorCode::UnknownType) => "okänd transaktionstyp",
+        }
+    }
+
+    /// The text of a statement line, English is the kind's name
+    pub const fn entry(self, kind: EntryKind) -> &'static str {
+        match self {
+            Self::En => kind.name(),
+            Self::Sv => match kind {
+                EntryKind::Deposit => "insättning",
+                EntryKind::Withdrawal => "uttag",
+                EntryKind::Hold => "reservation",
+                EntryKind::Release => "frisläppt reservation",
+                EntryKind::Chargeback => "återbetalning",
+                EntryKind::Expiry => "utgången tvist",
+                EntryKind::Opening => "ingående saldo",
+                EntryKind::Adjustment => "justering",
+                EntryKind::Lock => "låsning",
+                EntryKind::Unlock => "upplåsning",
+                EntryKind::Custom => "övrigt",
+                EntryKind::Transfer => "överföring",
+                EntryKind::EscrowHold => "deposition",
+                EntryKind::EscrowRelease => "utbetald deposition",
+                EntryKind::EscrowRefund => "återbetald deposition",
+                EntryKind::Fee => "avgift",
+                EntryKind::PendingWithdrawal => "väntande uttag",
+                EntryKind::Approval => "godkänt uttag",
+                EntryKind::PromoCredit => "kampanjkredit",
+                EntryKind::PromoExpiry => "utgången kampanjkredit",
+                EntryKind::AdminHold => "spärrat belopp",
+                EntryKind::AdminRelease => "frisläppt spärr",
+                EntryKind::ChargebackFee => "återbetalningsavgift",
+                EntryKind::Reversal => "återfört uttag",
+                EntryKind::Denial => "nekat uttag",
+            },
+        }
+    }
+
+    /// The OFX `LANGUAGE`, ISO 639-2
+    pub const fn ofx_language(self) -> &'static str {
+        match self {
+            Self::En => "ENG",
+            Self::Sv => "SWE",
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Locale::En.text(self))
+    }
+}
+
+impl std::error::Error for Message {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_say_messages_in_the_locale() {
+        assert_eq!("sv_SE.UTF-8".parse::<Locale>().unwrap(), Locale::Sv);
+        assert!("de".parse::<Locale>().is_err());
+        let args = ["playground", "--locale", "sv", "--bogus"].map(String::from);
+        assert_eq!(Locale::of_args(&args), Locale::Sv);
+        assert_eq!(Locale::of_args(&args[..2]), Locale::En);
+
+        let error = anyhow::Error::new(Message::UnknownFlag {
+            flag: "--bogus".to_string(),
+        });
+        assert_eq!(error.to_string(), "Unknown flag --bogus");
+        assert_eq!(Locale::Sv.render(&error), "Okänd flagga --bogus");
+        assert_eq!(
+            Locale::Sv.render(&anyhow::anyhow!("disk full")),
+            "disk full"
+        );
+        assert_eq!(Locale::En.entry(EntryKind::Deposit), "deposit");
+        assert_eq!(
+            Locale::Sv.describe(ErrorCode::MissingAmount),
+            "beloppet saknas"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use anyhow::{bail, Result};
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::Transaction;
+use crate::transaction_engine::TransactionError;
+
+/// Whether an account is locked and why, which decides what it can still do
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LockState {
+    #[default]
+    Active,
+    /// Locked by an operator while something is looked into, see `TransactionEngine::lock`.
+    /// Deposits still come in and the disputes of earlier transactions run their course, but
+    /// no money leaves the account
+    ReviewLocked {
+        /// What the operator said, empty if they didn't
+        reason: String,
+    },
+    /// Locked by a chargeback, everything is rejected until an operator unlocks it
+    ChargebackLocked,
+    /// Closed by an operator for good, everything is rejected and it can't be unlocked
+    Closed,
+}
+
+impl LockState {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            LockState::Active => "active",
+            LockState::ReviewLocked { .. } => "review_locked",
+            LockState::ChargebackLocked => "chargeback_locked",
+            LockState::Closed => "closed",
+        }
+    }
+
+    pub const fn is_locked(&self) -> bool {
+        !matches!(self, LockState::Active)
+    }
+
+    /// The operator's reason of a review lock
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            LockState::ReviewLocked { reason } => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Whether disputes can still be resolved and charged back, or expire
+    pub const fn settles_disputes(&self) -> bool {
+        matches!(self, LockState::Active | LockState::ReviewLocked { .. })
+    }
+
+    /// Whether the account takes the transaction in this state, `account_locked` or
+    /// `account_closed` if it doesn't. What a review lock lets through is up to `review`
+    pub fn check(
+        &self,
+        transaction: &Transaction,
+        review: &ReviewLockPolicy,
+    ) -> Result<(), TransactionError> {
+        match self {
+            LockState::Active => Ok(()),
+            LockState::ReviewLocked { .. } if review.allows(transaction.type_name()) => Ok(()),
+            LockState::Closed => Err(TransactionError::AccountClosed),
+            _ => Err(TransactionError::AccountLocked),
+        }
+    }
+
+    /// How much it takes away, when two states of an account are merged the stricter one wins
+    pub const fn severity(&self) -> u8 {
+        match self {
+            LockState::Active => 0,
+            LockState::ReviewLocked { .. } => 1,
+            LockState::ChargebackLocked => 2,
+            LockState::Closed => 3,
+        }
+    }
+}
+
+/// The transactions a review locked account still takes, by their type column, custom types
+/// included. By default money can come in and the disputes of earlier transactions run their
+/// course, but nothing leaves the account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReviewLockPolicy {
+    allowed: Vec<String>,
+}
+
+impl Default for ReviewLockPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: ["deposit", "dispute", "resolve", "chargeback"]
+                .map(str::to_string)
+                .to_vec(),
+        }
+    }
+}
+
+impl ReviewLockPolicy {
+    /// A comma separated list of types, e.g. `deposit,dispute`, or `none`
+    pub fn parse(types: &str) -> Result<Self> {
+        if types.trim() == "none" {
+            return Ok(Self { allowed: vec![] });
+        }
+        let mut allowed = vec![];
+        for kind in types.split(',').map(str::trim) {
+            if kind.is_empty() || kind.contains(char::is_whitespace) {
+                bail!("Invalid transaction type '{}'", kind);
+            }
+            allowed.push(kind.to_string());
+        }
+        Ok(Self { allowed })
+    }
+
+    pub fn allows(&self, kind: &str) -> bool {
+        self.allowed.iter().any(|allowed| allowed == kind)
+    }
+
+    /// The allowed types, in the order they were listed
+    pub fn types(&self) -> impl Iterator<Item = &str> {
+        self.allowed.iter().map(String::as_str)
+    }
+}
+
+impl HeapSize for LockState {
+    fn heap_size(&self) -> usize {
+        match self {
+            LockState::ReviewLocked { reason } => reason.heap_size(),
+            _ => 0,
+        }
+    }
+}
+
+impl Snapshot for LockState {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.tag(self.severity());
+        if let LockState::ReviewLocked { reason } = self {
+            reason.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(match decoder.tag()? {
+            0 => LockState::Active,
+            1 => LockState::ReviewLocked {
+                reason: String::decode(decoder)?,
+            },
+            2 => LockState::ChargebackLocked,
+            3 => LockState::Closed,
+            tag => bail!("Invalid lock state {} in snapshot", tag),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+
+    #[test]
+    fn should_take_what_the_state_allows() {
+        let amount = BigDecimal::from(1);
+        let operations = [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: amount.clone(),
+                wallet: None,
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: amount.clone(),
+                wallet: None,
+            },
+            Transaction::Dispute { client: 1, tx: 1 },
+            Transaction::Resolve { client: 1, tx: 1 },
+            Transaction::Chargeback { client: 1, tx: 1 },
+            Transaction::Approve { client: 1, tx: 2 },
+            Transaction::Reversal { client: 1, tx: 2 },
+            Transaction::Custom {
+                kind: "fee".to_string(),
+                client: 1,
+                tx: 3,
+                amount: None,
+            },
+        ];
+        let review = LockState::ReviewLocked {
+            reason: String::new(),
+        };
+        let (ok, locked, closed) = (
+            Ok(()),
+            Err(TransactionError::AccountLocked),
+            Err(TransactionError::AccountClosed),
+        );
+        let default = ReviewLockPolicy::default();
+        let withdrawals = ReviewLockPolicy::parse("withdrawal, fee").unwrap();
+        let none = ReviewLockPolicy::parse("none").unwrap();
+        #[rustfmt::skip]
+        let table = [
+            //                                         deposit  withdrawal  dispute  resolve  chargeback  approve  reversal  custom
+            (&LockState::Active, &default,            [&ok,     &ok,        &ok,     &ok,     &ok,        &ok,     &ok,      &ok]),
+            (&LockState::Active, &none,               [&ok,     &ok,        &ok,     &ok,     &ok,        &ok,     &ok,      &ok]),
+            (&review, &default,                       [&ok,     &locked,    &ok,     &ok,     &ok,        &locked, &locked,  &locked]),
+            (&review, &withdrawals,                   [&locked, &ok,        &locked, &locked, &locked,    &locked, &locked,  &ok]),
+            (&review, &none,                          [&locked, &locked,    &locked, &locked, &locked,    &locked, &locked,  &locked]),
+            (&LockState::ChargebackLocked, &default,  [&locked, &locked,    &locked, &locked, &locked,    &locked, &locked,  &locked]),
+            (&LockState::Closed, &default,            [&closed, &closed,    &closed, &closed, &closed,    &closed, &closed,  &closed]),
+        ];
+        for (state, policy, expected) in table {
+            for (operation, expected) in operations.iter().zip(expected) {
+                assert_eq!(
+                    &state.check(operation, policy),
+                    expected,
+                    "{} {}",
+                    state.name(),
+                    operation.type_name()
+                );
+            }
+        }
+
+        assert!(ReviewLockPolicy::parse("deposit,,dispute").is_err());
+        assert_eq!(
+            withdrawals.types().collect::<Vec<_>>(),
+            ["withdrawal", "fee"]
+        );
+    }
+}
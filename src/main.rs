@@ -1,47 +1,1792 @@
-use std::io;
+use std::io::{Read, Write};
+use std::iter;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use csv::Trim;
+use anyhow::{bail, Context, Result};
+use playground::alerts::LogAlerts;
+use playground::anomaly::{Baseline, CsvAnomaly};
+use playground::approval::CsvPendingWithdrawal;
+use playground::client_ids::ClientIds;
+use playground::clock::VirtualClock;
+use playground::config::EngineConfig;
+use playground::date::{self, UtcDateTime};
+use playground::diagnostics::{
+    DiagnosticsObserver, EnrichmentFailed, Pruned, SlowTransaction, UnrecordedScreening,
+};
+use playground::dispute::{Case, CsvDisputeStats};
+use playground::dunning;
+use playground::encryption::{self, EnvKeys};
+use playground::enrichment;
+use playground::errors::ErrorCode;
+use playground::escrow::CsvEscrow;
+use playground::event_log::EventLog;
+use playground::flows;
+use playground::groups::{self, ClientGroups};
+use playground::history::{self, HistoryFormat};
+use playground::iso20022::{self, MessageInfo};
+use playground::joint;
+use playground::json::Json;
+use playground::locale::{Locale, Message};
+use playground::plugin;
+use playground::profile::{Stage, TimedRead};
+use playground::promo::CsvPromoCredit;
+use playground::read_only::ReadOnlyEngine;
+use playground::refs::RefChecker;
+use playground::risk::HeuristicScorer;
+use playground::screening::AuditLog;
+use playground::session::{self, CancellationToken, ProcessingSession};
+use playground::settlement::{self, SettlementTemplate};
+use playground::sha256::HashingRead;
+use playground::sources;
+use playground::tags::{self, AccountTags};
+use playground::transaction::{self, CsvCase, CsvReject, RawTransaction, TransactionType};
+use playground::transaction_engine::{OnConflict, TransactionEngine};
 
-use crate::transaction::{RawTransaction, Transaction};
-use crate::transaction_engine::TransactionEngine;
+use crate::cli::{
+    Command, DisputesCommand, ExportFormat, Options, OutputMetadata, Query, ServeOptions,
+};
+use crate::input::InputFormat;
+use crate::otlp::BatchTelemetry;
+use crate::output::{ClientColumn, OutputSink};
+use crate::run_report::{AdjustedAmount, InputDigest, RunStats};
 
-mod transaction;
-mod transaction_engine;
+mod admin;
+mod cli;
+mod fixtures;
+mod ha;
+mod input;
+mod loadtest;
+mod otlp;
+mod output;
+mod relay;
+mod replicate;
+mod run_report;
+mod server;
+mod signal;
+mod webhook;
 
-fn main() -> Result<()> {
-    // Hacky argument parsing, for a real CLI I would've used a crate like clap
-    let mut args = std::env::args();
-    let _binary_path = args.next();
-    let file_path = match args.next() {
-        None => {
-            eprintln!("Expected a filename");
-            std::process::exit(-1);
+fn main() {
+    let started = Instant::now();
+    let args = std::env::args().collect::<Vec<_>>();
+    let options = match Options::parse(args.iter().cloned()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", Locale::of_args(&args).render(&e));
+            std::process::exit(run_report::EXIT_FATAL);
         }
-        Some(path) => path,
     };
 
-    let mut transaction_engine = TransactionEngine::new();
+    // a server traces its requests instead
+    let telemetry = match (&options.otlp_endpoint, &options.command) {
+        (Some(endpoint), command) if !matches!(command, Command::Serve(_)) => {
+            match BatchTelemetry::start(endpoint, command.name()) {
+                Ok(telemetry) => Some(telemetry),
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    std::process::exit(run_report::EXIT_FATAL);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let mut stats = RunStats::default();
+    let result = run(&options, &mut stats, telemetry.as_ref());
+    let exit_code = match &result {
+        Ok(()) => stats.exit_code(),
+        Err(e) => {
+            let locale = options.locale;
+            let error = locale.text(&Message::Error);
+            match ErrorCode::of(e) {
+                // the details are in English, what the code means is said in the locale too
+                Some(code) if locale != Locale::En => {
+                    eprintln!("{} {}: {}: {:?}", error, code, locale.describe(code), e);
+                }
+                Some(code) => eprintln!("{} {}: {:?}", error, code, e),
+                None => eprintln!("{}: {:?}", error, e),
+            }
+            run_report::EXIT_FATAL
+        }
+    };
+    if let Some(telemetry) = telemetry {
+        telemetry.finish(
+            exit_code,
+            result.as_ref().err().map(|e| format!("{:#}", e)),
+            &run_report::metrics(&stats, Some(started.elapsed())),
+        );
+    }
+    if let Some(destination) = &options.report_out {
+        let written = output::open_destination(destination).and_then(|mut writer| {
+            run_report::write_report(
+                &options,
+                &stats,
+                started.elapsed(),
+                exit_code,
+                result.as_ref().err(),
+                &mut writer,
+            )
+        });
+        if let Err(e) = written {
+            eprintln!("Failed to write the run report: {:?}", e);
+            std::process::exit(run_report::EXIT_FATAL);
+        }
+    }
+    std::process::exit(exit_code);
+}
+
+fn run(options: &Options, stats: &mut RunStats, telemetry: Option<&BatchTelemetry>) -> Result<()> {
+    if let Command::Admin { url, command } = &options.command {
+        let mut writer = output::open_destination(&options.outputs[0])?;
+        return admin::run(url, command, &mut writer);
+    }
+    if let Command::Replicate { url, state, every } = &options.command {
+        return replicate::run(url, state, *every, &options.engine);
+    }
+    if let Command::Relay {
+        dir,
+        url,
+        cursor,
+        every,
+    } = &options.command
+    {
+        return relay::run(dir, url, cursor, *every, &options.engine);
+    }
+    if let Command::Loadtest(loadtest) = &options.command {
+        let mut writer = output::open_destination(&options.outputs[0])?;
+        return loadtest::run(loadtest, &mut writer);
+    }
+    if let Command::CompareConfigs { before, after } = &options.command {
+        return compare_configs(options, before, after);
+    }
+    if let Command::CheckRefs { files } = &options.command {
+        return check_refs(options, stats, files);
+    }
+    if let Command::Serve(ServeOptions {
+        ha_lock: Some(lock),
+        ..
+    }) = &options.command
+    {
+        ha::lead(lock)?;
+    }
+    let span = telemetry.map(|telemetry| telemetry.span("process input"));
+    let mut transaction_engine = process(options, stats, telemetry)?;
+    if let (Some(telemetry), Some(mut span)) = (telemetry, span) {
+        span.attributes.extend([
+            ("playground.rows", Json::from(stats.rows)),
+            ("playground.rejected", Json::from(stats.rejected())),
+        ]);
+        telemetry.record(span);
+    }
+    let output_span = telemetry.map(|telemetry| telemetry.span("write outputs"));
+    stats.record_accounts(&transaction_engine);
+    stats.record_opening_conflicts(&transaction_engine);
+    if options.memory_stats {
+        stats.record_memory(&transaction_engine);
+        if let Some((engine, process)) = &stats.memory {
+            eprintln!("Memory: {}", run_report::memory_json(engine, process));
+        }
+    }
+    let mut profile = transaction_engine.take_profile();
+    let serialize_started = Instant::now();
+    if let Some(destination) = &options.snapshot_out {
+        write_state(&transaction_engine.snapshot(), destination, options)?;
+    }
+
+    let client_column = client_column(
+        options,
+        &Arc::new(Mutex::new(transaction_engine.client_ids().clone())),
+    );
+    let preamble = write_output_metadata(options, stats)?;
+    match &options.command {
+        Command::Process | Command::Simulate { .. } => {
+            let changed = options
+                .changed_only
+                .then(|| transaction_engine.changed_since(stats.started_at));
+            let accounts = transaction_engine.accounts().into_iter().filter(|account| {
+                changed
+                    .as_ref()
+                    .is_none_or(|changed| changed.binary_search(&account.client).is_ok())
+                    && options
+                        .tag
+                        .as_ref()
+                        .is_none_or(|tag| transaction_engine.tags().has(account.client, tag))
+            });
+            if options.extended {
+                let mut account_sink = output::csv_sinks_with_preamble(
+                    &options.outputs,
+                    client_column.as_ref(),
+                    &preamble,
+                )?;
+                for account in accounts {
+                    let reserve = transaction_engine.reserve(account.client);
+                    let lock = transaction_engine
+                        .account(account.client)
+                        .map(|view| view.lock_state().clone())
+                        .unwrap_or_default();
+                    account_sink.write(&account.extended(&reserve, &lock))?;
+                }
+                account_sink.flush()?;
+            } else {
+                let mut account_sink = output::csv_sinks_with_preamble(
+                    &options.outputs,
+                    client_column.as_ref(),
+                    &preamble,
+                )?;
+                for account in accounts {
+                    account_sink.write(&account)?;
+                }
+                account_sink.flush()?;
+            }
+        }
+        Command::Settle { template } => {
+            let template = match template {
+                None => SettlementTemplate::default(),
+                Some(path) => SettlementTemplate::parse(&std::fs::read_to_string(path)?)?,
+            };
+            let payouts = settlement::payouts(&transaction_engine);
+            for destination in &options.outputs {
+                template.render(&payouts, &mut output::open_destination(destination)?)?;
+            }
+        }
+        Command::Export(export) => {
+            let info = MessageInfo {
+                currency: export.currency.clone(),
+                created_at: date::unix_now(),
+            };
+            for destination in &options.outputs {
+                let mut writer = output::open_destination(destination)?;
+                match &export.format {
+                    ExportFormat::Camt053 => {
+                        iso20022::write_camt053(&transaction_engine, &info, &mut writer)?;
+                    }
+                    ExportFormat::Pain001 { debtor_account } => iso20022::write_pain001(
+                        &settlement::payouts(&transaction_engine),
+                        debtor_account,
+                        &info,
+                        &mut writer,
+                    )?,
+                }
+            }
+        }
+        Command::History { client, format } => {
+            let account = match transaction_engine.account(*client) {
+                None => bail!("There are no transactions for client {}", client),
+                Some(account) => account,
+            };
+            for destination in &options.outputs {
+                history::write_history(
+                    account,
+                    format,
+                    options.locale,
+                    date::unix_now(),
+                    &mut output::open_destination(destination)?,
+                )?;
+            }
+        }
+        Command::Disputes(DisputesCommand::List) => {
+            let mut cases = transaction_engine
+                .cases()
+                .map(CsvCase::from)
+                .collect::<Vec<_>>();
+            cases.sort_by_key(|case| (case.client, case.tx));
+            let mut case_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for case in &cases {
+                case_sink.write(case)?;
+            }
+            case_sink.flush()?;
+        }
+        Command::Escrows => {
+            let mut escrows = transaction_engine
+                .escrows()
+                .map(CsvEscrow::from)
+                .collect::<Vec<_>>();
+            escrows.sort_by_key(|escrow| (escrow.buyer, escrow.tx));
+            let mut escrow_sink = output::csv_sinks(&options.outputs, None)?;
+            for escrow in &escrows {
+                escrow_sink.write(escrow)?;
+            }
+            escrow_sink.flush()?;
+        }
+        Command::Flows { format } => {
+            let flows = flows::flows(&transaction_engine);
+            for destination in &options.outputs {
+                flows::write_flows(&flows, format, &mut output::open_destination(destination)?)?;
+            }
+        }
+        Command::Report {
+            by_source: true, ..
+        } => {
+            let mut summary_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for summary in sources::summarize(&transaction_engine) {
+                summary_sink.write(&summary)?;
+            }
+            summary_sink.flush()?;
+        }
+        Command::Report {
+            by_category: true, ..
+        } => {
+            let mut spending_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for spending in enrichment::spending(&transaction_engine) {
+                spending_sink.write(&spending)?;
+            }
+            spending_sink.flush()?;
+        }
+        Command::Report {
+            by_holder: true, ..
+        } => {
+            let mut holder_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for summary in joint::summarize(&transaction_engine) {
+                holder_sink.write(&summary)?;
+            }
+            holder_sink.flush()?;
+        }
+        Command::Report {
+            pending_withdrawals: true,
+            ..
+        } => {
+            let mut pending = transaction_engine
+                .pending_withdrawals()
+                .map(CsvPendingWithdrawal::from)
+                .collect::<Vec<_>>();
+            pending.sort_by_key(|pending| (pending.client, pending.requested_at, pending.tx));
+            let mut pending_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for pending in &pending {
+                pending_sink.write(pending)?;
+            }
+            pending_sink.flush()?;
+        }
+        Command::Report {
+            promo_credits: true,
+            ..
+        } => {
+            let mut accounts = transaction_engine.account_views().collect::<Vec<_>>();
+            accounts.sort_by_key(|account| account.client());
+            let mut promo_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for account in accounts {
+                for credit in account.promo_credits() {
+                    promo_sink.write(&CsvPromoCredit::new(account.client(), credit))?;
+                }
+            }
+            promo_sink.flush()?;
+        }
+        Command::Report {
+            dispute_stats: true,
+            ..
+        } => {
+            let mut accounts = transaction_engine
+                .account_views()
+                .filter(|account| !account.cases().is_empty())
+                .collect::<Vec<_>>();
+            accounts.sort_by_key(|account| account.client());
+            let mut stats_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for account in accounts {
+                stats_sink.write(&CsvDisputeStats::new(
+                    account.client(),
+                    account.dispute_stats(),
+                ))?;
+            }
+            stats_sink.flush()?;
+        }
+        Command::Report { negative: true, .. } => {
+            let mut negative_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for balance in dunning::negative_balances(&transaction_engine) {
+                negative_sink.write(&balance)?;
+            }
+            negative_sink.flush()?;
+        }
+        Command::Report { pockets: true, .. } => {
+            let mut pockets = transaction_engine
+                .account_views()
+                .flat_map(|account| account.to_csv_pockets())
+                .collect::<Vec<_>>();
+            pockets.sort_by(|a, b| (a.client, &a.pocket).cmp(&(b.client, &b.pocket)));
+            let mut pocket_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for pocket in &pockets {
+                pocket_sink.write(pocket)?;
+            }
+            pocket_sink.flush()?;
+        }
+        Command::Report { wallets: true, .. } => {
+            let mut wallets = transaction_engine
+                .account_views()
+                .flat_map(|account| account.to_csv_wallets())
+                .collect::<Vec<_>>();
+            wallets.sort_by(|a, b| (a.client, &a.wallet).cmp(&(b.client, &b.wallet)));
+            let mut wallet_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for wallet in &wallets {
+                wallet_sink.write(wallet)?;
+            }
+            wallet_sink.flush()?;
+        }
+        Command::Report { groups: None, .. } => {
+            let mut summary_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for summary in tags::summarize(&transaction_engine) {
+                if options.tag.as_ref().is_none_or(|tag| &summary.tag == tag) {
+                    summary_sink.write(&summary)?;
+                }
+            }
+            summary_sink.flush()?;
+        }
+        Command::Report {
+            groups: Some(groups),
+            ..
+        } => {
+            let groups = ClientGroups::read_csv(input::open_source(groups)?)
+                .with_context(|| format!("Failed to read client groups from {}", groups))?;
+            let mut summary_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for summary in groups::summarize(&transaction_engine, &groups) {
+                summary_sink.write(&summary)?;
+            }
+            summary_sink.flush()?;
+        }
+        Command::MergeSnapshots {
+            snapshots,
+            on_conflict,
+        } => {
+            let mut merged = read_snapshot(&snapshots[0], &options.engine)?;
+            for snapshot in &snapshots[1..] {
+                merged.merge(read_snapshot(snapshot, &options.engine)?, *on_conflict)?;
+            }
+            let snapshot = merged.snapshot();
+            for destination in &options.outputs {
+                write_state(&snapshot, destination, options)?;
+            }
+        }
+        Command::DiffSnapshots { before, after } => {
+            let before = read_snapshot(before, &options.engine)?;
+            let after = read_snapshot(after, &options.engine)?;
+            let mut delta_sink = output::csv_sinks_with_preamble(
+                &options.outputs,
+                client_column.as_ref(),
+                &preamble,
+            )?;
+            for delta in TransactionEngine::diff(&before, &after) {
+                delta_sink.write(&delta.to_csv())?;
+            }
+            delta_sink.flush()?;
+        }
+        Command::Query { snapshot, query } => {
+            let replica = ReadOnlyEngine::load(snapshot, options.engine.clone())?;
+            let client_column =
+                crate::client_column(options, &Arc::new(Mutex::new(replica.client_ids().clone())));
+            match query {
+                Query::Accounts(_) | Query::Account(_) => {
+                    let mut account_sink = output::csv_sinks_with_preamble(
+                        &options.outputs,
+                        client_column.as_ref(),
+                        &preamble,
+                    )?;
+                    let mut accounts = match query {
+                        Query::Account(client) => match replica.account(*client) {
+                            None => bail!("There is no account for client {}", client),
+                            Some(account) => vec![account.to_csv_account()],
+                        },
+                        Query::Accounts(Some(filter)) => replica
+                            .account_views()
+                            .filter(|account| filter.matches(account))
+                            .map(|account| account.to_csv_account())
+                            .collect(),
+                        _ => replica.accounts(),
+                    };
+                    accounts.sort_by_key(|account| account.client);
+                    for account in &accounts {
+                        account_sink.write(account)?;
+                    }
+                    account_sink.flush()?;
+                }
+                Query::Transaction(tx) => {
+                    let found = match replica.find_transaction(*tx) {
+                        None => bail!("There is no deposit or withdrawal with tx {}", tx),
+                        Some(found) => found,
+                    };
+                    let mut transaction_sink = output::csv_sinks_with_preamble(
+                        &options.outputs,
+                        client_column.as_ref(),
+                        &preamble,
+                    )?;
+                    transaction_sink.write(&found.to_csv())?;
+                    transaction_sink.flush()?;
+                }
+                Query::History(client) => {
+                    let account = match replica.account(*client) {
+                        None => bail!("There are no transactions for client {}", client),
+                        Some(account) => account,
+                    };
+                    for destination in &options.outputs {
+                        history::write_history(
+                            account,
+                            &HistoryFormat::Csv,
+                            options.locale,
+                            date::unix_now(),
+                            &mut output::open_destination(destination)?,
+                        )?;
+                    }
+                }
+            }
+        }
+        Command::TestFixtures { dir, update } => fixtures::run(dir, *update)?,
+        Command::ReEncrypt { state } => {
+            let keys = EnvKeys::from_env()?;
+            let mut sealed = vec![];
+            input::open_source(state)?.read_to_end(&mut sealed)?;
+            let plaintext = if encryption::is_encrypted(&sealed) {
+                encryption::decrypt_state(&sealed, &keys)?
+            } else {
+                sealed
+            };
+            let resealed = encryption::encrypt_state(&plaintext, &keys)?;
+            for destination in &options.outputs {
+                let mut writer = output::open_destination(destination)?;
+                writer.write_all(&resealed)?;
+                writer.flush()?;
+            }
+        }
+        Command::Serve(serve) => server::serve(serve, transaction_engine)?,
+        Command::Admin { .. }
+        | Command::Replicate { .. }
+        | Command::Relay { .. }
+        | Command::Loadtest(_)
+        | Command::CompareConfigs { .. }
+        | Command::CheckRefs { .. } => {
+            unreachable!(
+                "admin, replicate, relay, loadtest, compare-configs and check-refs return before processing"
+            )
+        }
+        Command::Disputes(DisputesCommand::Show { client, tx }) => {
+            let case = match transaction_engine.case(*client, *tx) {
+                None => bail!(
+                    "Transaction {} of client {} has not been disputed",
+                    tx,
+                    client
+                ),
+                Some(case) => case,
+            };
+            for destination in &options.outputs {
+                write_case(case, &mut output::open_destination(destination)?)?;
+            }
+        }
+    }
+
+    if let (Some(profile), Some(destination)) = (&mut profile, &options.profile) {
+        profile.record_since(Stage::Serialize, serialize_started);
+        profile.write_folded(&mut output::open_destination(destination)?)?;
+    }
+    if let (Some(telemetry), Some(span)) = (telemetry, output_span) {
+        telemetry.record(span);
+    }
+    Ok(())
+}
+
+fn write_case(case: &Case, writer: &mut dyn Write) -> Result<()> {
+    let time = |timestamp| UtcDateTime::from_timestamp(timestamp).iso_date_time();
+    writeln!(writer, "client: {}", case.client())?;
+    writeln!(writer, "tx: {}", case.tx())?;
+    writeln!(writer, "amount: {:.4}", case.amount())?;
+    writeln!(writer, "status: {}", case.status().name())?;
+    writeln!(writer, "opened at: {}", time(case.opened_at()))?;
+    if let Some(closed_at) = case.closed_at() {
+        writeln!(writer, "closed at: {}", time(closed_at))?;
+    }
+    for note in case.notes() {
+        writeln!(writer, "note {}: {}", time(note.added_at), note.text)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_snapshot(source: &str, config: &EngineConfig) -> Result<TransactionEngine> {
+    let mut snapshot = vec![];
+    input::open_source(source)?.read_to_end(&mut snapshot)?;
+    // encrypted snapshots are recognized whether or not --encrypt-state is given
+    if encryption::is_encrypted(&snapshot) {
+        snapshot = encryption::decrypt_state(&snapshot, &EnvKeys::from_env()?)
+            .with_context(|| format!("Failed to decrypt snapshot {}", source))?;
+    }
+    TransactionEngine::from_snapshot(&snapshot, config.clone())
+        .with_context(|| format!("Failed to read snapshot {}", source))
+}
+
+/// Writes engine state, encrypted with `--encrypt-state`
+fn write_state(state: &[u8], destination: &str, options: &Options) -> Result<()> {
+    let mut writer = output::open_destination(destination)?;
+    if options.encrypt_state {
+        writer.write_all(&encryption::encrypt_state(state, &EnvKeys::from_env()?)?)?;
+    } else {
+        writer.write_all(state)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Runs the input through a fresh engine, writing rejected transactions as we go.
+/// With `--atomic` the engine is thrown away if anything was rejected, before any output
+/// or state is written
+fn process(
+    options: &Options,
+    stats: &mut RunStats,
+    telemetry: Option<&BatchTelemetry>,
+) -> Result<TransactionEngine> {
+    let mut transaction_engine = new_engine(options, &options.engine)?;
+    let (clock, step, then) = match options.command {
+        Command::Simulate { start, step, then } => (Some(VirtualClock::new(start)), step, then),
+        _ => (None, 0, 0),
+    };
+    if let Some(clock) = &clock {
+        transaction_engine.set_clock(Arc::new(clock.clone()));
+    }
+    stats.started_at = transaction_engine.sequence();
+    let client_ids = Arc::new(Mutex::new(transaction_engine.client_ids().clone()));
+    let client_column = client_column(options, &client_ids);
+    let mut reject_sink = output::csv_sinks(&options.rejects, client_column.as_ref())?;
+    let baseline = options
+        .anomalies_out
+        .as_ref()
+        .map(|_| Baseline::capture(&transaction_engine));
+    let continued = taken_over_state(options).or(recovered_event_log(options));
+    let mut transaction_engine = match (&options.input, continued) {
+        // the previous leader or run already processed the input
+        (None, _) | (_, Some(_)) => transaction_engine,
+        (Some(input), None) => {
+            // reading is timed on its own, the rest of the time in the csv reader is parsing
+            let (source, hasher) = HashingRead::new(input::open_source(input)?);
+            let (source, read_time) = TimedRead::new(source);
+            let mut adjusted = vec![];
+            let format = options
+                .input_format
+                .unwrap_or_else(|| InputFormat::of(input));
+            let rows = match options.external_client_ids {
+                false => format.read_transactions(source, options.amount_format),
+                true if format == InputFormat::Csv => {
+                    let client_ids = Arc::clone(&client_ids);
+                    Box::new(transaction::read_external_transactions(
+                        source,
+                        options.amount_format,
+                        move |external| {
+                            client_ids
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .intern(external)
+                        },
+                    ))
+                }
+                true => bail!("--external-client-ids only works with csv input"),
+            };
+            let rows = limit_rows(rows, options.max_rows).inspect(|row| {
+                // the engine catches up with the clock before applying the row
+                if let Some(clock) = &clock {
+                    clock.advance(step);
+                }
+                if let Ok(raw) = row {
+                    adjusted.extend(AdjustedAmount::of(raw));
+                }
+            });
+            let (mut transaction_engine, csv_time) = match options.partitions {
+                None => process_sequential(
+                    transaction_engine,
+                    rows,
+                    &mut reject_sink,
+                    stats,
+                    options,
+                    telemetry,
+                )?,
+                Some(partitions) => process_partitioned(
+                    &transaction_engine,
+                    rows,
+                    partitions,
+                    &mut reject_sink,
+                    stats,
+                    options,
+                )?,
+            };
+            if let Some(profile) = transaction_engine.profile_mut() {
+                profile.record(Stage::Read, read_time.get());
+                profile.record(Stage::Parse, csv_time.saturating_sub(read_time.get()));
+            }
+            stats.inputs.push(InputDigest::new(input, &hasher.borrow()));
+            stats.adjusted_amounts = adjusted;
+            if options.external_client_ids {
+                let client_ids = client_ids.lock().unwrap_or_else(|e| e.into_inner());
+                transaction_engine.set_client_ids(client_ids.clone());
+            }
+            transaction_engine
+        }
+    };
+    if let Some(clock) = &clock {
+        clock.advance(then);
+        transaction_engine.catch_up();
+    }
+    reject_sink.flush()?;
+    if options.atomic && stats.rejected() > 0 {
+        let reasons = stats
+            .rejects_by_reason
+            .iter()
+            .map(|(reason, count)| format!("{} {}", count, reason))
+            .collect::<Vec<_>>();
+        bail!(
+            "{} of the {} rows were rejected ({}), --atomic applied none of them",
+            stats.rejected(),
+            stats.rows,
+            reasons.join(", ")
+        );
+    }
+    if let (Some(baseline), Some(anomalies_out)) = (baseline, &options.anomalies_out) {
+        let mut sink =
+            output::csv_sinks(std::slice::from_ref(anomalies_out), client_column.as_ref())?;
+        for anomaly in baseline.anomalies(&transaction_engine, options.anomaly_threshold) {
+            sink.write(&CsvAnomaly::from(&anomaly))?;
+        }
+        sink.flush()?;
+    }
+    Ok(transaction_engine)
+}
+
+/// Describes the run with `--output-metadata`, writing the sidecars right away. Returns the
+/// comment lines that go before the csv outputs, if that's how they're described
+fn write_output_metadata(options: &Options, stats: &RunStats) -> Result<String> {
+    let metadata = run_report::metadata_json(options, stats);
+    match options.output_metadata {
+        None => Ok(String::new()),
+        Some(OutputMetadata::Comments) => {
+            let Json::Object(fields) = metadata else {
+                unreachable!("the metadata is an object")
+            };
+            Ok(fields
+                .iter()
+                .map(|(key, value)| format!("# {}: {}\n", key, value))
+                .collect())
+        }
+        Some(OutputMetadata::Sidecar) => {
+            for destination in &options.outputs {
+                let mut sidecar = output::open_destination(&format!("{}.meta.json", destination))?;
+                writeln!(sidecar, "{}", metadata)?;
+                sidecar.flush()?;
+            }
+            Ok(String::new())
+        }
+    }
+}
+
+/// How clients are written in the csv outputs: pseudonymized with `--pseudonymize`, else
+/// by the ids they have in the input once the engine knows any
+fn client_column(options: &Options, client_ids: &Arc<Mutex<ClientIds>>) -> Option<ClientColumn> {
+    let external = options.external_client_ids
+        || !client_ids
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_empty();
+    match &options.pseudonymizer {
+        Some(pseudonymizer) => Some(ClientColumn::Pseudonyms(pseudonymizer.clone())),
+        None if external => Some(ClientColumn::External(Arc::clone(client_ids))),
+        None => None,
+    }
+}
+
+/// Processes the input once under each settings file, on top of the options' config, and
+/// writes how the accounts the second run ends with differ from those of the first
+fn compare_configs(options: &Options, before: &str, after: &str) -> Result<()> {
+    let input = options
+        .input
+        .as_deref()
+        .context("compare-configs needs an input")?;
+    let format = options
+        .input_format
+        .unwrap_or_else(|| InputFormat::of(input));
+    let run = |settings: &str| -> Result<TransactionEngine> {
+        let text = std::fs::read_to_string(settings)
+            .with_context(|| format!("Failed to read settings {}", settings))?;
+        let config = options
+            .engine
+            .with_settings(&text)
+            .with_context(|| format!("Invalid settings in {}", settings))?;
+        let rows = limit_rows(
+            format.read_transactions(input::open_source(input)?, options.amount_format),
+            options.max_rows,
+        );
+        let engine = new_engine(options, &config)?;
+        let (_, session) = session::process_stream(engine, rows, &CancellationToken::new())?;
+        Ok(session.into_engine())
+    };
+    let before = run(before)?;
+    let after = run(after)?;
+    let mut delta_sink = output::csv_sinks(
+        &options.outputs,
+        client_column(options, &Arc::new(Mutex::new(after.client_ids().clone()))).as_ref(),
+    )?;
+    for delta in TransactionEngine::diff(&before, &after) {
+        delta_sink.write(&delta.to_csv())?;
+    }
+    delta_sink.flush()?;
+    Ok(())
+}
+
+/// Writes the dangling references of the files, which count as rejects for the exit code
+/// and the run report
+fn check_refs(options: &Options, stats: &mut RunStats, files: &[String]) -> Result<()> {
+    let mut checker = RefChecker::new(options.engine.joint_accounts.clone());
+    for file in files {
+        let (source, hasher) = HashingRead::new(input::open_source(file)?);
+        let format = options
+            .input_format
+            .unwrap_or_else(|| InputFormat::of(file));
+        let rows = format
+            .read_transactions(source, options.amount_format)
+            .inspect(|_| stats.rows += 1);
+        checker.add_file(file, rows)?;
+        stats.inputs.push(InputDigest::new(file, &hasher.borrow()));
+    }
+    let dangling = checker.dangling();
+    stats.applied = stats.rows - dangling.len() as u64;
+    let mut sink = output::csv_sinks(&options.outputs, None)?;
+    for reference in &dangling {
+        *stats
+            .rejects_by_reason
+            .entry(reference.reason.to_string())
+            .or_default() += 1;
+        *stats.rejects_by_code.entry(reference.code).or_default() += 1;
+        sink.write(reference)?;
+    }
+    sink.flush()?;
+    Ok(())
+}
+
+/// Fails the run on the row after `max_rows`, so a huge input isn't read to the end
+fn limit_rows(
+    rows: impl Iterator<Item = Result<RawTransaction>>,
+    max_rows: Option<usize>,
+) -> impl Iterator<Item = Result<RawTransaction>> {
+    rows.enumerate().map(move |(index, row)| match max_rows {
+        Some(max) if index >= max => bail!("The input has more than the maximum of {} rows", max),
+        _ => row,
+    })
+}
+
+/// The state a previous leader left behind, if serving with `--ha-state` and there is one
+fn taken_over_state(options: &Options) -> Option<&str> {
+    match &options.command {
+        Command::Serve(ServeOptions {
+            ha_state: Some(state),
+            ..
+        }) if Path::new(state).exists() => Some(state),
+        _ => None,
+    }
+}
+
+/// The event log of a previous run, if serving with `--event-log` and there is one
+fn recovered_event_log(options: &Options) -> Option<&str> {
+    match &options.command {
+        Command::Serve(ServeOptions {
+            event_log: Some(dir),
+            ..
+        }) if EventLog::exists(dir) => Some(dir),
+        _ => None,
+    }
+}
+
+/// The engine the input is processed by, configured with `config` rather than
+/// `options.engine` so `compare-configs` can set up one per settings file
+fn new_engine(options: &Options, config: &EngineConfig) -> Result<TransactionEngine> {
+    let taken_over = taken_over_state(options);
+    let recovered = recovered_event_log(options);
+    let mut transaction_engine = match (recovered, taken_over.or(options.snapshot.as_deref())) {
+        (Some(dir), _) => {
+            let (transaction_engine, recovery) = EventLog::recover(dir, config.clone())
+                .with_context(|| format!("Failed to recover from the event log {}", dir))?;
+            for (path, error) in &recovery.skipped_snapshots {
+                eprintln!("Skipped snapshot {}: {}", path.display(), error);
+            }
+            for path in &recovery.truncated_segments {
+                eprintln!(
+                    "Dropped a change cut short at the end of {}",
+                    path.display()
+                );
+            }
+            transaction_engine
+        }
+        (None, None) => TransactionEngine::with_config(config.clone()),
+        (None, Some(snapshot)) => read_snapshot(snapshot, config)?,
+    };
+    if let (Some(accounts), None) = (&options.resume_from_accounts, taken_over.or(recovered)) {
+        let accounts = transaction::csv_reader(input::open_source(accounts)?)
+            .into_deserialize()
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to read accounts from {}", accounts))?;
+        transaction_engine.open_accounts(accounts)?;
+    }
+    if config.risk_threshold.is_some() {
+        transaction_engine.register_scorer(Box::new(HeuristicScorer::default()));
+    }
+    for path in &options.plugins {
+        let plugin = plugin::load(path)?;
+        if let Some(handler) = plugin.handler {
+            transaction_engine.register_handler(handler)?;
+        }
+        if let Some(scorer) = plugin.scorer {
+            transaction_engine.register_scorer(scorer);
+        }
+    }
+    // partitions are clones of this engine, so they tag with it too
+    transaction_engine.set_source(options.source.as_deref());
+    if options.profile.is_some() {
+        transaction_engine.enable_profiling();
+    }
+    if !config.alerts.is_empty() {
+        transaction_engine.register_alert_observer(Box::new(LogAlerts));
+    }
+    if let Some(path) = &options.audit_log {
+        transaction_engine.register_screening_observer(Box::new(AuditLog::open(path)?));
+    }
+    transaction_engine.register_diagnostics_observer(Box::new(LogDiagnostics));
+    if let Some(lookup) = &options.enrich {
+        transaction_engine.register_enricher(enrichment::open(lookup)?);
+    }
+    // before processing, so test tags and tag alerts apply from the first transaction
+    if let Some(tags) = &options.tags {
+        transaction_engine
+            .tags_mut()
+            .merge(AccountTags::read_csv(input::open_source(tags)?)?);
+    }
+    Ok(transaction_engine)
+}
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_path(file_path)?;
+/// Writes what the engine noticed about its own work to stderr, with the rest of the logs
+struct LogDiagnostics;
 
-    for result in csv_reader.deserialize() {
-        // Transaction is how I want transactions to be represented,
-        // But I couldn't figure out how to use the csv crate to parse directly into that format
-        // so I parse into an intermediate, RawTransaction, and then convert manually
-        let raw: RawTransaction = result?;
-        let transaction: Transaction = raw.into();
-        transaction_engine.process(transaction);
+impl DiagnosticsObserver for LogDiagnostics {
+    fn pruned(&self, pruned: &Pruned) {
+        eprintln!(
+            "Pruned the stored transactions of tx {} of client {}, {}",
+            pruned.tx, pruned.client, pruned.reason
+        );
     }
 
-    let accounts = transaction_engine.accounts();
-    let mut csv_writer = csv::Writer::from_writer(io::stdout());
-    for account in accounts {
-        csv_writer.serialize(account)?;
+    fn slow(&self, slow: &SlowTransaction) {
+        eprintln!(
+            "Slow transaction: {} {} of client {} took {}µs to apply, over the budget of {}µs. \
+             The account has {} transactions and {} ledger entries, {}µs went to copying it",
+            slow.kind,
+            slow.tx,
+            slow.client,
+            slow.elapsed.as_micros(),
+            slow.budget.as_micros(),
+            slow.stored,
+            slow.entries,
+            slow.copy_wait.as_micros()
+        );
     }
-    csv_writer.flush()?;
 
+    fn enrichment_failed(&self, failed: &EnrichmentFailed) {
+        eprintln!("Failed to enrich tx {}: {}", failed.tx, failed.error);
+    }
+
+    fn screening_unrecorded(&self, unrecorded: &UnrecordedScreening) {
+        let screening = &unrecorded.screening;
+        eprintln!(
+            "The screening of tx {} of client {} ({}) wasn't recorded: {}",
+            screening.tx,
+            screening.client,
+            screening.action.name(),
+            unrecorded.error
+        );
+    }
+}
+
+/// Returns the engine and the time spent in the csv reader
+fn process_sequential(
+    transaction_engine: TransactionEngine,
+    mut rows: impl Iterator<Item = Result<RawTransaction>>,
+    reject_sink: &mut impl OutputSink<CsvReject>,
+    stats: &mut RunStats,
+    options: &Options,
+    telemetry: Option<&BatchTelemetry>,
+) -> Result<(TransactionEngine, Duration)> {
+    let mut usr1 = signal::listen();
+    let mut csv_time = Duration::ZERO;
+    let timed_rows = iter::from_fn(|| {
+        let started = Instant::now();
+        let row = rows.next();
+        csv_time += started.elapsed();
+        row
+    });
+    let mut session = ProcessingSession::new(transaction_engine, timed_rows);
+    let transaction_engine = loop {
+        let report = session.next_n(1000)?;
+        stats.record_applied(report.applied);
+        for (transaction, error) in &report.rejected {
+            let reject = CsvReject::new(transaction, error);
+            stats.record_reject(&reject);
+            reject_sink.write(&reject)?;
+        }
+        if usr1.received() {
+            dump_progress(session.engine(), stats, options);
+        }
+        if let Some(telemetry) = telemetry {
+            telemetry.progress(|| run_report::metrics(stats, None));
+        }
+        if report.finished {
+            break session.into_engine();
+        }
+    };
+    Ok((transaction_engine, csv_time))
+}
+
+/// Logs how far processing is and writes the `--signal-snapshot`, processing carries on
+/// whether or not that works
+fn dump_progress(transaction_engine: &TransactionEngine, stats: &RunStats, options: &Options) {
+    eprintln!(
+        "{}: {}",
+        options.locale.text(&Message::Progress),
+        run_report::progress_json(stats, transaction_engine)
+    );
+    if let Some(destination) = &options.signal_snapshot {
+        match write_state(&transaction_engine.snapshot(), destination, options) {
+            Ok(()) => eprintln!("Wrote a snapshot to {}", destination),
+            Err(e) => eprintln!("Failed to write a snapshot to {}: {:#}", destination, e),
+        }
+    }
+}
+
+/// Like `process_sequential`, but the clients are split over `partitions` copies of the engine on threads
+/// of their own, which are merged once the whole input has been read.
+/// A client's transactions all go to the same engine so the merge can't conflict,
+/// but the rejects come out grouped by engine instead of in input order
+fn process_partitioned(
+    transaction_engine: &TransactionEngine,
+    mut rows: impl Iterator<Item = Result<RawTransaction>>,
+    partitions: usize,
+    reject_sink: &mut impl OutputSink<CsvReject>,
+    stats: &mut RunStats,
+    options: &Options,
+) -> Result<(TransactionEngine, Duration)> {
+    let mut senders = vec![];
+    let mut workers = vec![];
+    // the partitions say when they're done, in whatever order that is
+    let (done, finished) = mpsc::channel();
+    for partition in 0..partitions {
+        let (sender, receiver) = mpsc::sync_channel::<RawTransaction>(1024);
+        // every partition starts out with the config and tags, merging them is a no-op
+        let mut transaction_engine = transaction_engine.clone();
+        let done = done.clone();
+        workers.push(thread::spawn(move || {
+            let apply = || {
+                let mut applied = 0;
+                let mut rejects = vec![];
+                for raw in receiver {
+                    match session::apply(&mut transaction_engine, raw)? {
+                        (_, Ok(())) => applied += 1,
+                        (transaction, Err(error)) => {
+                            rejects.push(CsvReject::new(&transaction, &error));
+                        }
+                    }
+                }
+                anyhow::Ok((applied, rejects))
+            };
+            let result = apply().map(|(applied, rejects)| (transaction_engine, applied, rejects));
+            // only fails once the run has given up
+            let _ = done.send((partition, result));
+        }));
+        senders.push(sender);
+    }
+    drop(done);
+
+    let joint_accounts = &transaction_engine.config().joint_accounts;
+    let mut usr1 = signal::listen();
+    let mut csv_time = Duration::ZERO;
+    let mut read = 0;
+    loop {
+        let started = Instant::now();
+        let row = rows.next();
+        csv_time += started.elapsed();
+        let raw = match row {
+            None => break,
+            Some(row) => row?,
+        };
+        read += 1;
+        if usr1.received() {
+            // the partitions' engines are busy on their own threads until the input ends
+            let locale = options.locale;
+            eprintln!(
+                "{}: {}",
+                locale.text(&Message::Progress),
+                locale.text(&Message::RowsRead(read))
+            );
+            if options.signal_snapshot.is_some() {
+                eprintln!("No snapshot, the partitions are only merged at the end");
+            }
+        }
+        if partitions > 1 && raw.transaction_type() == TransactionType::EscrowOpen {
+            // releasing it credits the seller, whose account may be in another partition
+            bail!(
+                "Escrows pay sellers in other partitions, tx {} can't be --partition-by-client",
+                raw.tx()
+            );
+        }
+        // a joint holder's transactions go to the partition of the account they hold
+        let account = joint_accounts.account(raw.client());
+        // the remainder is below the partition count, so it fits
+        #[allow(clippy::useless_conversion)]
+        let partition = (u64::from(account) % partitions as u64) as usize;
+        if senders[partition].send(raw).is_err() {
+            // the engine gave up, its error is reported below
+            break;
+        }
+    }
+    drop(senders);
+
+    let mut shard_sink = match &options.shard_accounts_out {
+        None => None,
+        Some(destination) => Some(output::open_destination(destination)?),
+    };
+    let mut results = (0..partitions).map(|_| None).collect::<Vec<_>>();
+    for (partition, result) in finished {
+        if let (Some(sink), Ok((transaction_engine, _, _))) = (&mut shard_sink, &result) {
+            write_shard_accounts(sink, partition, transaction_engine)?;
+        }
+        results[partition] = Some(result);
+    }
+
+    let mut merged: Option<TransactionEngine> = None;
+    for (worker, result) in workers.into_iter().zip(results) {
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
+        }
+        let (transaction_engine, applied, rejects) =
+            result.expect("every partition that didn't panic is done")?;
+        stats.record_applied(applied);
+        for reject in &rejects {
+            stats.record_reject(reject);
+            reject_sink.write(reject)?;
+        }
+        match &mut merged {
+            None => merged = Some(transaction_engine),
+            // every partition credits the fees of its clients to the fee account
+            Some(merged) => merged.merge(transaction_engine, OnConflict::SumFeeAccount)?,
+        }
+    }
+    let merged = merged.unwrap_or_else(|| transaction_engine.clone());
+    Ok((merged, csv_time))
+}
+
+/// A line of JSON per account of the partition, flushed right away so a loader can start on
+/// them while the other partitions are still busy. A client only ever is in one partition, so
+/// the accounts are final, except for the fee account that every partition has a part of
+fn write_shard_accounts(
+    sink: &mut dyn Write,
+    partition: usize,
+    transaction_engine: &TransactionEngine,
+) -> Result<()> {
+    for account in transaction_engine.accounts() {
+        let line = Json::object([
+            ("shard", Json::from(partition)),
+            ("client", Json::from(account.client)),
+            ("available", Json::from(account.available)),
+            ("held", Json::from(account.held)),
+            ("total", Json::from(account.total)),
+            ("locked", Json::from(account.locked)),
+        ]);
+        writeln!(sink, "{}", line)?;
+    }
+    sink.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A path in the temp dir that no other test, or run of the tests, writes to
+    fn temp_path(name: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("{}-{}-{}", std::process::id(), count, name))
+    }
+
+    #[test]
+    fn should_end_up_with_the_same_accounts_when_partitioned() {
+        let input = temp_path("playground_partitioned_input.csv");
+        let mut transactions = "type,client,tx,amount\n".to_string();
+        for tx in 0..100 {
+            transactions.push_str(&format!("deposit,{},{},{}.5\n", tx % 7, tx, tx));
+            if tx % 10 == 0 {
+                transactions.push_str(&format!("dispute,{},{},\n", tx % 7, tx));
+            }
+        }
+        std::fs::write(&input, transactions).unwrap();
+        let accounts = |extra_args: &[&str]| {
+            let args = ["playground", "process", input.to_str().unwrap()]
+                .iter()
+                .chain(extra_args)
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>();
+            let mut accounts = process(
+                &Options::parse(args.into_iter()).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .unwrap()
+            .accounts();
+            accounts.sort_by_key(|account| account.client);
+            accounts
+        };
+
+        let shards = temp_path("playground_partitioned_shards.ndjson");
+        let sequential = accounts(&[]);
+        let partitioned = accounts(&[
+            "--partition-by-client",
+            "3",
+            "--shard-accounts-out",
+            shards.to_str().unwrap(),
+        ]);
+        let shards_written = std::fs::read_to_string(&shards).unwrap();
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&shards).unwrap();
+
+        assert_eq!(sequential.len(), 7);
+        assert_eq!(partitioned, sequential);
+        let mut shard_lines = shards_written.lines().collect::<Vec<_>>();
+        shard_lines.sort_unstable();
+        assert_eq!(shard_lines.len(), 7);
+        assert_eq!(
+            shard_lines[0],
+            r#"{"shard":0,"client":0,"available":"671.5000","held":"71.0000","total":"742.5000","locked":false}"#
+        );
+    }
+
+    #[test]
+    fn should_add_up_the_fees_of_the_partitions() {
+        let (input, fees) = (
+            temp_path("playground_partitioned_fees_input.csv"),
+            temp_path("playground_partitioned_fees.txt"),
+        );
+        let mut transactions = "type,client,tx,amount\n".to_string();
+        for tx in 0..20 {
+            transactions.push_str(&format!("deposit,{},{},10\n", tx % 5, tx));
+            transactions.push_str(&format!("withdrawal,{},{},1\n", tx % 5, 100 + tx));
+        }
+        std::fs::write(&input, transactions).unwrap();
+        std::fs::write(&fees, "account 65535\ndeposit 1%\nwithdrawal 0.5\n").unwrap();
+        let (input_arg, fees_arg) = (input.to_str().unwrap(), fees.to_str().unwrap());
+        let accounts = |extra_args: &[&str]| {
+            let args = ["playground", input_arg, "--fees", fees_arg]
+                .iter()
+                .chain(extra_args)
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>();
+            let mut accounts = process(
+                &Options::parse(args.into_iter()).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .unwrap()
+            .accounts();
+            accounts.sort_by_key(|account| account.client);
+            accounts
+        };
+
+        let sequential = accounts(&[]);
+        let partitioned = accounts(&["--partition-by-client", "3"]);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&fees).unwrap();
+
+        assert_eq!(partitioned, sequential);
+        let fee_account = partitioned.last().unwrap();
+        assert_eq!(fee_account.client, 65535);
+        assert_eq!(fee_account.total, "12.0000");
+    }
+
+    #[test]
+    fn should_partition_joint_holders_with_the_account_they_hold() {
+        let (input, joint) = (
+            temp_path("playground_partitioned_joint_input.csv"),
+            temp_path("playground_partitioned_joint.csv"),
+        );
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10\n\
+             deposit,2,2,5\n\
+             withdrawal,2,3,12\n\
+             dispute,2,1,\n\
+             deposit,3,4,1\n",
+        )
+        .unwrap();
+        std::fs::write(&joint, "client,account\n2,1\n").unwrap();
+        let (input_arg, joint_arg) = (input.to_str().unwrap(), joint.to_str().unwrap());
+        let accounts = |extra_args: &[&str]| {
+            let args = ["playground", input_arg, "--joint-accounts", joint_arg]
+                .iter()
+                .chain(extra_args)
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>();
+            let mut accounts = process(
+                &Options::parse(args.into_iter()).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .unwrap()
+            .accounts();
+            accounts.sort_by_key(|account| account.client);
+            accounts
+        };
+
+        let sequential = accounts(&[]);
+        let partitioned = accounts(&["--partition-by-client", "2"]);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&joint).unwrap();
+
+        assert_eq!(partitioned, sequential);
+        let totals = partitioned
+            .iter()
+            .map(|account| (account.client, account.total.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(totals, [(1, "3.0000"), (3, "1.0000")]);
+    }
+
+    #[test]
+    fn should_refuse_escrows_when_partitioned() {
+        let input = temp_path("playground_partitioned_escrow_input.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount,seller\n\
+             deposit,1,1,10,\n\
+             escrow_open,1,2,4,2\n\
+             escrow_release,1,2,,\n",
+        )
+        .unwrap();
+        let run_with = |extra_args: &[&str]| {
+            let args = ["playground", input.to_str().unwrap()]
+                .iter()
+                .chain(extra_args)
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>();
+            process(
+                &Options::parse(args.into_iter()).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .map(|transaction_engine| transaction_engine.accounts().len())
+            .map_err(|error| error.to_string())
+        };
+
+        let sequential = run_with(&[]);
+        // the buyer and the seller are in different partitions
+        let partitioned = run_with(&["--partition-by-client", "2"]);
+        std::fs::remove_file(&input).unwrap();
+
+        assert_eq!(sequential, Ok(2));
+        assert_eq!(
+            partitioned,
+            Err(
+                "Escrows pay sellers in other partitions, tx 2 can't be --partition-by-client"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn should_write_each_partitions_accounts_to_the_shards() {
+        let mut transaction_engine = TransactionEngine::new();
+        for (client, tx, amount) in [(1, 1, "2.5"), (3, 2, "10")] {
+            let deposit = transaction::Transaction::Deposit {
+                client,
+                tx,
+                amount: amount.parse().unwrap(),
+                wallet: None,
+            };
+            transaction_engine.process(deposit).unwrap();
+        }
+        let mut written = vec![];
+        write_shard_accounts(&mut written, 1, &transaction_engine).unwrap();
+        let mut lines = std::str::from_utf8(&written)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            [
+                r#"{"shard":1,"client":1,"available":"2.5000","held":"0.0000","total":"2.5000","locked":false}"#,
+                r#"{"shard":1,"client":3,"available":"10.0000","held":"0.0000","total":"10.0000","locked":false}"#,
+            ]
+        );
+
+        // the shards come from partitions, and can't be taken back by --atomic
+        let parse = |args: &[&str]| {
+            let args = [
+                "playground",
+                "input.csv",
+                "--shard-accounts-out",
+                "shards.ndjson",
+            ]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string());
+            Options::parse(args).map(|_| ()).map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            parse(&[]),
+            Err("--shard-accounts-out needs --partition-by-client".to_string())
+        );
+        assert!(parse(&["--partition-by-client", "2", "--atomic"]).is_err());
+        assert_eq!(parse(&["--partition-by-client", "2"]), Ok(()));
+    }
+
+    #[test]
+    fn should_only_write_the_accounts_the_batch_changed() {
+        let (first, second, snapshot, out) = (
+            temp_path("playground_changed_first.csv"),
+            temp_path("playground_changed_second.csv"),
+            temp_path("playground_changed.bin"),
+            temp_path("playground_changed_accounts.csv"),
+        );
+        std::fs::write(
+            &first,
+            "type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+deposit,3,3,1
+",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "type,client,tx,amount
+deposit,3,4,2
+dispute,1,1,
+",
+        )
+        .unwrap();
+        let run_with = |args: &[&str]| {
+            let args = ["playground"].iter().chain(args).map(|arg| arg.to_string());
+            run(
+                &Options::parse(args).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .unwrap();
+            let mut lines = std::fs::read_to_string(&out)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            lines.sort_unstable();
+            lines
+        };
+        let (snapshot_arg, out_arg) = (snapshot.to_str().unwrap(), out.to_str().unwrap());
+        let all = run_with(&[
+            first.to_str().unwrap(),
+            "--snapshot-out",
+            snapshot_arg,
+            "-o",
+            out_arg,
+        ]);
+        let changed = run_with(&[
+            second.to_str().unwrap(),
+            "--snapshot",
+            snapshot_arg,
+            "--changed-only",
+            "-o",
+            out_arg,
+        ]);
+        for file in [first, second, snapshot, out] {
+            std::fs::remove_file(file).unwrap();
+        }
+
+        assert_eq!(all.len(), 4);
+        assert_eq!(
+            changed,
+            [
+                "1,0.0000,10.0000,10.0000,false",
+                "3,3.0000,0.0000,3.0000,false",
+                "client,available,held,total,locked",
+            ]
+        );
+        let report = ["playground", "report", "--changed-only"].map(String::from);
+        let Err(error) = Options::parse(report.into_iter()) else {
+            panic!("--changed-only was taken for a report");
+        };
+        assert_eq!(error.to_string(), "--changed-only only applies to process");
+    }
+
+    #[test]
+    fn should_describe_the_run_in_or_next_to_the_outputs() {
+        let (input, out) = (
+            temp_path("playground_metadata_input.csv"),
+            temp_path("playground_metadata_accounts.csv"),
+        );
+        let sidecar = PathBuf::from(format!("{}.meta.json", out.display()));
+        let transactions = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\n";
+        std::fs::write(&input, transactions).unwrap();
+        let (input_arg, out_arg) = (input.to_str().unwrap(), out.to_str().unwrap());
+        let run_with = |args: &[&str]| {
+            let args = ["playground", input_arg, "-o", out_arg]
+                .iter()
+                .chain(args)
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>();
+            run(
+                &Options::parse(args.into_iter()).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .unwrap();
+            std::fs::read_to_string(&out).unwrap()
+        };
+
+        let commented = run_with(&["--output-metadata", "comments"]);
+        let lines = commented.lines().collect::<Vec<_>>();
+        let digest =
+            playground::sha256::hex(&playground::sha256::Sha256::digest(transactions.as_bytes()));
+        assert!(lines[0].starts_with(&format!(
+            "# engine_version: \"{}\"",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert_eq!(lines[1], "# command: \"process\"");
+        assert!(lines[3].starts_with("# inputs: [{\"source\":"));
+        assert!(lines[3].contains(&digest));
+        assert_eq!(
+            lines[5..],
+            [
+                "# rows: 2",
+                "# applied: 1",
+                "# rejected: 1",
+                "# accounts: 1",
+                "client,available,held,total,locked",
+                "1,10.0000,0.0000,10.0000,false"
+            ]
+        );
+
+        let plain = run_with(&["--output-metadata", "sidecar"]);
+        let metadata = Json::parse(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+        let other_config = run_with(&["--output-metadata", "sidecar", "--max-rows", "5"]);
+        let other = Json::parse(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+        for file in [&input, &out, &sidecar] {
+            std::fs::remove_file(file).unwrap();
+        }
+        assert_eq!(
+            plain,
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+        );
+        assert_eq!(other_config, plain);
+        assert_eq!(metadata.get("rejected"), Some(&Json::from(1_u64)));
+        let config = |metadata: &Json| metadata.get("config_sha256").cloned();
+        assert!(config(&metadata).is_some());
+        assert_ne!(config(&metadata), config(&other));
+
+        let to_stdout =
+            ["playground", "input.csv", "--output-metadata", "sidecar"].map(String::from);
+        assert!(Options::parse(to_stdout.into_iter()).is_err());
+    }
+
+    #[test]
+    fn should_write_how_the_accounts_differ_under_the_second_config() {
+        let (input, current, limited, out) = (
+            temp_path("playground_compare_input.csv"),
+            temp_path("playground_compare_current.settings"),
+            temp_path("playground_compare_limited.settings"),
+            temp_path("playground_compare_deltas.csv"),
+        );
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10\n\
+             withdrawal,1,2,4\n\
+             deposit,2,3,5\n\
+             deposit,1,4,1\n",
+        )
+        .unwrap();
+        std::fs::write(&current, "# the defaults\n").unwrap();
+        std::fs::write(&limited, "max_accounts=1\n").unwrap();
+        let compare = |before: &Path, after: &Path| {
+            let args = [
+                "playground",
+                "compare-configs",
+                before.to_str().unwrap(),
+                after.to_str().unwrap(),
+                input.to_str().unwrap(),
+                "-o",
+                out.to_str().unwrap(),
+            ]
+            .map(String::from);
+            run(
+                &Options::parse(args.into_iter()).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )?;
+            Ok::<_, anyhow::Error>(std::fs::read_to_string(&out).unwrap())
+        };
+        let same = compare(&current, &current).unwrap();
+        let deltas = compare(&current, &limited).unwrap();
+        std::fs::write(&limited, "max_accounts=none\n").unwrap();
+        let invalid = compare(&current, &limited);
+        for file in [&input, &current, &limited, &out] {
+            std::fs::remove_file(file).unwrap();
+        }
+        // nothing differs, not even a header is written
+        assert_eq!(same, "");
+        // the second config doesn't open an account for client 2
+        assert_eq!(
+            deltas,
+            "client,change,available,held,total,locked,entries\n2,removed,-5.0000,0.0000,-5.0000,,0\n"
+        );
+        let error = invalid.unwrap_err().to_string();
+        assert!(error.starts_with("Invalid settings in "), "{}", error);
+    }
+
+    #[test]
+    fn should_apply_nothing_atomically_when_a_row_is_rejected() {
+        let input = temp_path("playground_atomic_input.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\n",
+        )
+        .unwrap();
+        let args = ["playground", input.to_str().unwrap(), "--atomic"].map(String::from);
+        let mut stats = RunStats::default();
+        let result = process(&Options::parse(args.into_iter()).unwrap(), &mut stats, None);
+        std::fs::remove_file(&input).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(stats.rejects_by_reason["insufficient_funds"], 1);
+    }
+
+    /// The accounts of `transactions`, and those of a run that only resumes from them with
+    /// `--resume-from-accounts`
+    fn resumed(
+        name: &str,
+        transactions: &str,
+    ) -> (Vec<transaction::CsvAccount>, Vec<transaction::CsvAccount>) {
+        let (input, empty, accounts_csv) = (
+            temp_path(&format!("playground_{}_input.csv", name)),
+            temp_path(&format!("playground_{}_empty.csv", name)),
+            temp_path(&format!("playground_{}_accounts.csv", name)),
+        );
+        std::fs::write(&input, transactions).unwrap();
+        std::fs::write(&empty, "type,client,tx,amount\n").unwrap();
+        let accounts = |args: &[&str]| {
+            let args = ["playground"].iter().chain(args).map(|arg| arg.to_string());
+            let mut accounts = process(
+                &Options::parse(args).unwrap(),
+                &mut RunStats::default(),
+                None,
+            )
+            .unwrap()
+            .accounts();
+            accounts.sort_by_key(|account| account.client);
+            accounts
+        };
+
+        let first = accounts(&[input.to_str().unwrap()]);
+        let mut writer = csv::Writer::from_path(&accounts_csv).unwrap();
+        for account in &first {
+            writer.serialize(account).unwrap();
+        }
+        writer.flush().unwrap();
+        let second = accounts(&[
+            empty.to_str().unwrap(),
+            "--resume-from-accounts",
+            accounts_csv.to_str().unwrap(),
+        ]);
+        for file in [input, empty, accounts_csv] {
+            std::fs::remove_file(file).unwrap();
+        }
+        (first, second)
+    }
+
+    #[test]
+    fn should_resume_the_total_of_accounts_with_pockets() {
+        let (first, second) = resumed(
+            "pockets",
+            "type,client,tx,amount,from_pocket,to_pocket\n\
+             deposit,1,1,100.0,,\n\
+             transfer,1,2,40.0,,savings\n\
+             deposit,2,3,5.0,,\n",
+        );
+        assert_eq!(first[0].available, "60.0000");
+        assert_eq!(first[0].total, "100.0000");
+        assert_eq!(second, first);
+    }
+
+    #[test]
+    fn should_resume_the_total_of_accounts_with_wallets() {
+        let (first, second) = resumed(
+            "wallets",
+            "type,client,tx,amount,wallet,from_wallet,to_wallet\n\
+             deposit,1,1,50.0,savings,,\n\
+             deposit,1,2,10.0,,,\n\
+             wallet_transfer,1,3,20.0,,savings,\n\
+             dispute,1,1,,,,\n",
+        );
+        assert_eq!(first[0].available, "30.0000");
+        assert_eq!(first[0].held, "0.0000");
+        assert_eq!(first[0].total, "60.0000");
+        assert_eq!(second, first);
+    }
+
+    #[test]
+    fn should_resume_the_total_of_accounts_with_promotional_credits() {
+        let (first, second) = resumed(
+            "promo",
+            "type,client,tx,amount,expires_at\n\
+             deposit,1,1,10.0,\n\
+             promo_credit,1,2,5.0,4102444800\n\
+             withdrawal,1,3,2.0,\n",
+        );
+        assert_eq!(first[0].available, "10.0000");
+        assert_eq!(first[0].total, "13.0000");
+        assert_eq!(second, first);
+    }
+}
@@ -0,0 +1,92 @@
+use std::mem::size_of;
+
+use bigdecimal::BigDecimal;
+
+/// Roughly how much heap memory a value owns, on top of its own size. Estimated from the
+/// lengths rather than the capacities, and without the allocator's overhead, so it's a
+/// lower bound that's good for comparing accounts and runs rather than an exact number
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+/// The digits are stored as a vector of 64 bit words, up to 19 decimal digits in each
+impl HeapSize for BigDecimal {
+    fn heap_size(&self) -> usize {
+        let digits = usize::try_from(self.digits()).unwrap_or(usize::MAX);
+        digits / 19 * 8 + 8
+    }
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+/// What the engine holds, see `TransactionEngine::memory_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub accounts: usize,
+    /// Stored transactions, rejected ones included
+    pub transactions: usize,
+    pub ledger_entries: usize,
+    /// The accounts with everything in them, as estimated by `HeapSize`
+    pub estimated_heap_bytes: usize,
+}
+
+/// Resident memory of this process as the OS sees it, only known on Linux
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessMemory {
+    pub rss_bytes: Option<u64>,
+    pub peak_rss_bytes: Option<u64>,
+}
+
+impl ProcessMemory {
+    pub fn current() -> Self {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        // e.g. "VmHWM:     1234 kB"
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.trim().strip_suffix("kB"))
+                .and_then(|kb| kb.trim().parse::<u64>().ok())
+                .map(|kb| kb * 1024)
+        };
+        Self {
+            rss_bytes: field("VmRSS:"),
+            peak_rss_bytes: field("VmHWM:"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn should_estimate_by_length() {
+        let small = BigDecimal::from_str("1.5").unwrap();
+        let large = BigDecimal::from_str("123456789012345678901234567890.5").unwrap();
+        assert_eq!(small.heap_size(), 8);
+        assert_eq!(large.heap_size(), 16);
+        assert_eq!(
+            vec![small, large].heap_size(),
+            2 * size_of::<BigDecimal>() + 24
+        );
+        assert_eq!(vec!["ab".to_string()].heap_size(), size_of::<String>() + 2);
+        if cfg!(target_os = "linux") {
+            let memory = ProcessMemory::current();
+            assert!(memory.peak_rss_bytes >= memory.rss_bytes);
+            assert!(memory.rss_bytes.is_some());
+        }
+    }
+}
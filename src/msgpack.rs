@@ -0,0 +1,254 @@
+use anyhow::{bail, Context, Result};
+
+use crate::json::Json;
+
+/// Nested arrays and maps deeper than this are refused rather than recursed into
+const MAX_DEPTH: usize = 64;
+
+/// MessagePack encoding of a `Json` value, the same data in fewer bytes and without
+/// any text to format or parse. Numbers are written as the smallest integer that holds
+/// them, or as a 64 bit float if they aren't integers
+pub fn encode(json: &Json) -> Vec<u8> {
+    let mut bytes = vec![];
+    write(json, &mut bytes);
+    bytes
+}
+
+fn write(json: &Json, out: &mut Vec<u8>) {
+    match json {
+        Json::Null => out.push(0xc0),
+        Json::Bool(value) => out.push(if *value { 0xc3 } else { 0xc2 }),
+        Json::Number(number) => match (number.parse::<i64>(), number.parse::<u64>()) {
+            (Ok(value), _) => write_int(value, out),
+            (_, Ok(value)) => {
+                out.push(0xcf);
+                out.extend(value.to_be_bytes());
+            }
+            _ => {
+                out.push(0xcb);
+                out.extend(number.parse::<f64>().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        },
+        Json::String(value) => {
+            write_length(value.len(), 0xa0, [0xd9, 0xda, 0xdb], out);
+            out.extend(value.as_bytes());
+        }
+        Json::Array(items) => {
+            write_length(items.len(), 0x90, [0, 0xdc, 0xdd], out);
+            for item in items {
+                write(item, out);
+            }
+        }
+        Json::Object(fields) => {
+            write_length(fields.len(), 0x80, [0, 0xde, 0xdf], out);
+            for (key, value) in fields {
+                write(&Json::String(key.clone()), out);
+                write(value, out);
+            }
+        }
+    }
+}
+
+fn write_int(value: i64, out: &mut Vec<u8>) {
+    match value {
+        // positive and negative fixints are the value's own low byte
+        -32..=0x7f => out.push(value as u8),
+        0x80..=0xff => out.extend([0xcc, value as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend((value as u32).to_be_bytes());
+        }
+        -0x80..=-33 => out.extend([0xd0, value as u8]),
+        -0x8000..=-0x81 => {
+            out.push(0xd1);
+            out.extend((value as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.push(0xd2);
+            out.extend((value as i32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xd3);
+            out.extend(value.to_be_bytes());
+        }
+    }
+}
+
+/// `fix` is the first byte of the short form, which holds lengths up to 31 for strings and
+/// 15 for arrays and maps, `long` the markers of the 8, 16 and 32 bit forms.
+/// Arrays and maps have no 8 bit form
+fn write_length(length: usize, fix: u8, long: [u8; 3], out: &mut Vec<u8>) {
+    let fix_max = if fix == 0xa0 { 31 } else { 15 };
+    if length <= fix_max {
+        out.push(fix | length as u8);
+    } else if length <= 0xff && long[0] != 0 {
+        out.extend([long[0], length as u8]);
+    } else if length <= 0xffff {
+        out.push(long[1]);
+        out.extend((length as u16).to_be_bytes());
+    } else {
+        out.push(long[2]);
+        // nothing we encode comes close to 4 GiB
+        out.extend((length as u32).to_be_bytes());
+    }
+}
+
+/// The `Json` value of a MessagePack encoded one. Binary and extension values have no
+/// JSON equivalent and are refused, map keys have to be strings
+pub fn decode(bytes: &[u8]) -> Result<Json> {
+    let mut reader = Reader { bytes };
+    let json = reader.value(0)?;
+    if !reader.bytes.is_empty() {
+        bail!("{} bytes left after the value", reader.bytes.len());
+    }
+    Ok(json)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            bail!("The input ended in the middle of a value");
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn length(&mut self, bytes: usize) -> Result<usize> {
+        Ok(match bytes {
+            1 => usize::from(self.take(1)?[0]),
+            2 => usize::from(u16::from_be_bytes(self.array()?)),
+            _ => usize::try_from(u32::from_be_bytes(self.array()?))?,
+        })
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            bail!("Values are nested more than {} deep", MAX_DEPTH);
+        }
+        let marker = self.take(1)?[0];
+        Ok(match marker {
+            0x00..=0x7f => Json::from(u64::from(marker)),
+            0x80..=0x8f => self.map(usize::from(marker & 0x0f), depth)?,
+            0x90..=0x9f => self.items(usize::from(marker & 0x0f), depth)?,
+            0xa0..=0xbf => self.string(usize::from(marker & 0x1f))?,
+            0xc0 => Json::Null,
+            0xc2 => Json::Bool(false),
+            0xc3 => Json::Bool(true),
+            0xca => Json::from(f64::from(f32::from_be_bytes(self.array()?))),
+            0xcb => Json::from(f64::from_be_bytes(self.array()?)),
+            0xcc => Json::from(u64::from(self.take(1)?[0])),
+            0xcd => Json::from(u64::from(u16::from_be_bytes(self.array()?))),
+            0xce => Json::from(u64::from(u32::from_be_bytes(self.array()?))),
+            0xcf => Json::from(u64::from_be_bytes(self.array()?)),
+            0xd0 => Json::from(i64::from(i8::from_be_bytes(self.array()?))),
+            0xd1 => Json::from(i64::from(i16::from_be_bytes(self.array()?))),
+            0xd2 => Json::from(i64::from(i32::from_be_bytes(self.array()?))),
+            0xd3 => Json::from(i64::from_be_bytes(self.array()?)),
+            0xd9 => {
+                let length = self.length(1)?;
+                self.string(length)?
+            }
+            0xda => {
+                let length = self.length(2)?;
+                self.string(length)?
+            }
+            0xdb => {
+                let length = self.length(4)?;
+                self.string(length)?
+            }
+            0xdc => {
+                let length = self.length(2)?;
+                self.items(length, depth)?
+            }
+            0xdd => {
+                let length = self.length(4)?;
+                self.items(length, depth)?
+            }
+            0xde => {
+                let length = self.length(2)?;
+                self.map(length, depth)?
+            }
+            0xdf => {
+                let length = self.length(4)?;
+                self.map(length, depth)?
+            }
+            0xe0..=0xff => Json::from(i64::from(marker as i8)),
+            0xc4..=0xc6 => bail!("Binary values aren't supported"),
+            0xc7..=0xc9 | 0xd4..=0xd8 => bail!("Extension values aren't supported"),
+            0xc1 => bail!("Invalid marker 0xc1"),
+        })
+    }
+
+    fn string(&mut self, length: usize) -> Result<Json> {
+        let text = std::str::from_utf8(self.take(length)?).context("Invalid UTF-8 in a string")?;
+        Ok(Json::from(text))
+    }
+
+    fn items(&mut self, length: usize, depth: usize) -> Result<Json> {
+        // every item is at least a byte, so a bogus length can't make us allocate much
+        let mut items = Vec::with_capacity(length.min(self.bytes.len()));
+        for _ in 0..length {
+            items.push(self.value(depth + 1)?);
+        }
+        Ok(Json::Array(items))
+    }
+
+    fn map(&mut self, length: usize, depth: usize) -> Result<Json> {
+        let mut fields = Vec::with_capacity(length.min(self.bytes.len() / 2));
+        for _ in 0..length {
+            let key = match self.value(depth + 1)? {
+                Json::String(key) => key,
+                other => bail!("Map keys have to be strings, got {}", other),
+            };
+            fields.push((key, self.value(depth + 1)?));
+        }
+        Ok(Json::Object(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_round_trip_json() {
+        let json = Json::object([
+            ("client", Json::from(65_535_u16)),
+            ("tx", Json::from(u64::MAX)),
+            ("offset", Json::from(-200_i64)),
+            ("amount", Json::from("1.5000")),
+            ("score", Json::from(0.25)),
+            ("tags", Json::array(["a".repeat(40), String::new()])),
+            ("locked", Json::from(false)),
+            ("reason", Json::Null),
+        ]);
+        let bytes = encode(&json);
+        assert_eq!(
+            &bytes[..8],
+            &[0x88, 0xa6, b'c', b'l', b'i', b'e', b'n', b't']
+        );
+        assert_eq!(&bytes[8..11], &[0xcd, 0xff, 0xff]);
+        assert_eq!(decode(&bytes).unwrap(), json);
+        assert!(bytes.len() < json.to_string().len());
+
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&[0xc4, 1, 0]).is_err());
+        assert!(decode(&[0x81, 1, 2]).is_err());
+        assert!(decode(&[0x91; 100]).is_err());
+    }
+}
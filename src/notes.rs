@@ -0,0 +1,38 @@
+use anyhow::Result;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+
+/// Something support wrote down about an account, e.g. why it was locked, kept with the
+/// account so whoever looks at it next has the context. Notes don't change the balances
+/// and can't be edited, a correction is another note
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    /// Unix timestamp (seconds)
+    pub at: u64,
+    /// Who wrote it, if they said
+    pub author: Option<String>,
+    pub text: String,
+}
+
+impl HeapSize for Note {
+    fn heap_size(&self) -> usize {
+        self.author.as_ref().map_or(0, String::heap_size) + self.text.heap_size()
+    }
+}
+
+impl Snapshot for Note {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.at.encode(encoder);
+        self.author.encode(encoder);
+        self.text.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            at: u64::decode(decoder)?,
+            author: Option::decode(decoder)?,
+            text: String::decode(decoder)?,
+        })
+    }
+}
@@ -0,0 +1,231 @@
+use std::cell::{Cell, RefCell};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use playground::http::Target;
+use playground::json::Json;
+use playground::trace::{self, Metric, Span, TraceContext};
+
+enum Export {
+    Traces(Vec<Span>),
+    Metrics(Json),
+}
+
+/// Sends spans and metrics to an OpenTelemetry collector as OTLP/HTTP JSON, from a thread of
+/// its own like the alert webhook. What can't be delivered is logged and dropped
+pub struct OtlpExporter {
+    sender: Sender<Export>,
+    thread: JoinHandle<()>,
+}
+
+impl OtlpExporter {
+    /// `endpoint` is the collector's base url, e.g. `http://collector:4318`
+    pub fn start(endpoint: &str) -> Result<Self> {
+        let target = Target::parse(endpoint)?;
+        let (sender, receiver) = mpsc::channel::<Export>();
+        let thread = thread::spawn(move || {
+            for export in receiver {
+                let (path, body, what) = match export {
+                    Export::Traces(spans) => (
+                        "v1/traces",
+                        trace::otlp_traces("playground", &spans),
+                        "spans",
+                    ),
+                    Export::Metrics(metrics) => ("v1/metrics", metrics, "metrics"),
+                };
+                if let Err(e) = post(&target, path, &body.to_string()) {
+                    eprintln!("Failed to export {}: {:#}", what, e);
+                }
+            }
+        });
+        Ok(Self { sender, thread })
+    }
+
+    pub fn export(&self, spans: Vec<Span>) {
+        if !spans.is_empty() {
+            // only fails once the thread is gone, which it never is on its own
+            let _ = self.sender.send(Export::Traces(spans));
+        }
+    }
+
+    /// `start` is when the counts started, unix nanoseconds
+    pub fn export_metrics(&self, start: u64, metrics: &[Metric]) {
+        let metrics = trace::otlp_metrics("playground", start, metrics);
+        let _ = self.sender.send(Export::Metrics(metrics));
+    }
+
+    /// Waits until everything exported so far was sent, before the process exits
+    pub fn finish(self) {
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            eprintln!("The OTLP exporter failed");
+        }
+    }
+}
+
+/// The trace and metrics of a batch run with `--otlp-endpoint`: a span for the whole run with
+/// one for each part of it, continuing the trace of the `TRACEPARENT` environment variable if
+/// a scheduler set one, and the run's counts every so often while it's processing
+pub struct BatchTelemetry {
+    exporter: OtlpExporter,
+    run: Span,
+    spans: RefCell<Vec<Span>>,
+    last_export: Cell<Instant>,
+}
+
+/// How often a long run's counts are exported while it's processing
+const PROGRESS_EVERY: Duration = Duration::from_secs(10);
+
+impl BatchTelemetry {
+    pub fn start(endpoint: &str, command: &str) -> Result<Self> {
+        let parent = std::env::var("TRACEPARENT")
+            .ok()
+            .and_then(|traceparent| TraceContext::parse(&traceparent).ok());
+        let context = parent.map_or_else(TraceContext::root, |parent| parent.child());
+        Ok(Self {
+            exporter: OtlpExporter::start(endpoint)?,
+            run: Span::start(format!("playground {}", command), context, parent.as_ref()),
+            spans: RefCell::default(),
+            last_export: Cell::new(Instant::now()),
+        })
+    }
+
+    /// A span of a part of the run, `record` it when it's done
+    pub fn span(&self, name: &str) -> Span {
+        Span::start(name, self.run.context.child(), Some(&self.run.context))
+    }
+
+    pub fn record(&self, mut span: Span) {
+        span.finish();
+        self.spans.borrow_mut().push(span);
+    }
+
+    /// Exports the metrics if the last export was a while ago
+    pub fn progress(&self, metrics: impl FnOnce() -> Vec<Metric>) {
+        if self.last_export.get().elapsed() >= PROGRESS_EVERY {
+            self.exporter.export_metrics(self.run.start, &metrics());
+            self.last_export.set(Instant::now());
+        }
+    }
+
+    /// Exports the final metrics and the spans, `error` is why the run failed if it did
+    pub fn finish(mut self, exit_code: i32, error: Option<String>, metrics: &[Metric]) {
+        self.run
+            .attributes
+            .push(("process.exit.code", Json::from(i64::from(exit_code))));
+        self.run.error = error;
+        self.run.finish();
+        let mut spans = self.spans.into_inner();
+        spans.insert(0, self.run);
+        self.exporter.export_metrics(spans[0].start, metrics);
+        self.exporter.export(spans);
+        self.exporter.finish();
+    }
+}
+
+fn post(target: &Target, path: &str, body: &str) -> Result<()> {
+    let body = Some(("application/json", body.as_bytes()));
+    let reply = target.send("POST", path, body, Duration::from_secs(5))?;
+    if !(200..300).contains(&reply.status) {
+        bail!("Unexpected response status {}", reply.status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use playground::trace::MetricValue;
+
+    #[test]
+    fn should_export_the_run_and_its_parts_when_it_finishes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let collector = thread::spawn(move || {
+            let mut requests = vec![];
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut path = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if path.is_empty() {
+                        path = line.split(' ').nth(1).unwrap().to_string();
+                    }
+                    if let Some(value) = line.trim().strip_prefix("Content-Length: ") {
+                        length = value.parse().unwrap();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+                )
+                .unwrap();
+                requests.push((
+                    path,
+                    Json::parse(&String::from_utf8(body).unwrap()).unwrap(),
+                ));
+            }
+            requests
+        });
+
+        let telemetry = BatchTelemetry::start(&endpoint, "process").unwrap();
+        let read = telemetry.span("read");
+        telemetry.record(read);
+        // the first progress is only due after a while
+        telemetry.progress(|| panic!("exported too soon"));
+        let applied = Metric {
+            name: "playground.applied",
+            unit: "{row}",
+            value: MetricValue::Count(3),
+            attributes: vec![],
+        };
+        telemetry.finish(2, Some("2 rows rejected".to_string()), &[applied]);
+
+        let requests = collector.join().unwrap();
+        let paths = requests
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["/v1/metrics", "/v1/traces"]);
+        let metrics = requests[0].1.to_string();
+        assert!(metrics.contains(r#""name":"playground.applied""#));
+        assert!(metrics.contains(r#""asInt":"3""#));
+
+        let items = |json: &'_ Json, key| match json.get(key) {
+            Some(Json::Array(items)) => items.clone(),
+            _ => panic!("no {}", key),
+        };
+        let scope = &items(&items(&requests[1].1, "resourceSpans")[0], "scopeSpans")[0];
+        let spans = items(scope, "spans");
+        let field = |span: &Json, key| span.get(key).and_then(Json::as_str).map(str::to_string);
+        let names = spans
+            .iter()
+            .map(|span| field(span, "name"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                Some("playground process".to_string()),
+                Some("read".to_string())
+            ]
+        );
+        assert_eq!(field(&spans[1], "parentSpanId"), field(&spans[0], "spanId"));
+        assert_eq!(field(&spans[1], "traceId"), field(&spans[0], "traceId"));
+        let traces = requests[1].1.to_string();
+        assert!(traces.contains(r#"{"key":"process.exit.code","value":{"intValue":"2"}}"#));
+        assert!(traces.contains(r#""message":"2 rows rejected""#));
+    }
+}
@@ -0,0 +1,221 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use csv::StringRecord;
+use playground::client_ids::ClientIds;
+use playground::pseudonym::Pseudonymizer;
+use playground::transaction::ClientId;
+use serde::Serialize;
+
+/// Somewhere the records of one output stream (accounts, rejects...) end up
+pub trait OutputSink<T> {
+    fn write(&mut self, record: &T) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// What the `client` column of the csv outputs has instead of the engine's id
+#[derive(Clone)]
+pub enum ClientColumn {
+    Pseudonyms(Pseudonymizer),
+    /// The ids the clients have in the input. Shared with the input, rejected rows are written
+    /// while it's still interning new clients
+    External(Arc<Mutex<ClientIds>>),
+}
+
+impl ClientColumn {
+    /// Clients without an external id, e.g. the fee account, keep theirs
+    fn name(&self, client: ClientId) -> String {
+        match self {
+            ClientColumn::Pseudonyms(pseudonymizer) => pseudonymizer.pseudonym(client),
+            ClientColumn::External(ids) => ids
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .external(client)
+                .map_or_else(|| client.to_string(), str::to_string),
+        }
+    }
+}
+
+/// Writes records as csv rows to any writer, typically stdout or a file
+pub struct CsvSink {
+    writer: csv::Writer<Box<dyn Write>>,
+    /// Replaces the `client` column
+    client_column: Option<ClientColumn>,
+    wrote_headers: bool,
+}
+
+impl CsvSink {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            client_column: None,
+            wrote_headers: false,
+        }
+    }
+
+    pub fn with_client_column(writer: Box<dyn Write>, client_column: ClientColumn) -> Self {
+        Self {
+            client_column: Some(client_column),
+            ..Self::new(writer)
+        }
+    }
+}
+
+/// The records have a numeric `client` field and serde can't change its type on the way out,
+/// so the record is serialized on its own and the column replaced afterwards.
+/// Returns the headers and the row
+fn replace_clients<T: Serialize>(
+    record: &T,
+    client_column: &ClientColumn,
+) -> Result<(StringRecord, StringRecord)> {
+    let mut serialized = csv::Writer::from_writer(vec![]);
+    serialized.serialize(record)?;
+    let serialized = serialized
+        .into_inner()
+        .map_err(|e| anyhow!("Failed to serialize record: {}", e))?;
+    let mut reader = csv::Reader::from_reader(serialized.as_slice());
+    let headers = reader.headers()?.clone();
+    let column = headers.iter().position(|header| header == "client");
+    let row = match reader.records().next() {
+        None => bail!("Failed to serialize record"),
+        Some(row) => row?,
+    };
+    let mut replaced = StringRecord::new();
+    for (i, field) in row.iter().enumerate() {
+        if Some(i) == column {
+            replaced.push_field(&client_column.name(field.parse()?));
+        } else {
+            replaced.push_field(field);
+        }
+    }
+    Ok((headers, replaced))
+}
+
+impl<T: Serialize> OutputSink<T> for CsvSink {
+    fn write(&mut self, record: &T) -> Result<()> {
+        match &self.client_column {
+            None => self.writer.serialize(record)?,
+            Some(client_column) => {
+                let (headers, row) = replace_clients(record, client_column)?;
+                if !self.wrote_headers {
+                    self.writer.write_record(&headers)?;
+                    self.wrote_headers = true;
+                }
+                self.writer.write_record(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Sends every record to all of its sinks
+pub struct MultiSink<T> {
+    sinks: Vec<Box<dyn OutputSink<T>>>,
+}
+
+impl<T> MultiSink<T> {
+    pub fn new(sinks: Vec<Box<dyn OutputSink<T>>>) -> Self {
+        Self { sinks }
+    }
+}
+
+impl<T> OutputSink<T> for MultiSink<T> {
+    fn write(&mut self, record: &T) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.write(record)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Opens a destination given on the command line,
+/// `-` means stdout, anything else without a `scheme://` is treated as a file path
+pub fn open_destination(destination: &str) -> Result<Box<dyn Write>> {
+    if destination == "-" {
+        return Ok(Box::new(io::stdout()));
+    }
+    if let Some((scheme, _)) = destination.split_once("://") {
+        // S3 and CDC-topic sinks need clients we don't ship, and a url must never end up as a
+        // directory tree of that name
+        bail!(
+            "Writing to {}:// isn't supported in this build, destinations are files or - for stdout: {}",
+            scheme,
+            destination
+        );
+    }
+    let file = File::create(destination)
+        .with_context(|| format!("Failed to create output file {}", destination))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+/// Like `open_destination`, but files are appended to instead of replaced,
+/// for logs that should survive a restart
+pub fn open_log(destination: &str) -> Result<Box<dyn Write>> {
+    if destination == "-" || destination.contains("://") {
+        return open_destination(destination);
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(destination)
+        .with_context(|| format!("Failed to open log file {}", destination))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+pub fn csv_sinks<T: Serialize>(
+    destinations: &[String],
+    client_column: Option<&ClientColumn>,
+) -> Result<MultiSink<T>> {
+    csv_sinks_with_preamble(destinations, client_column, "")
+}
+
+/// Like `csv_sinks`, with `preamble` written before the csv, e.g. comment lines
+pub fn csv_sinks_with_preamble<T: Serialize>(
+    destinations: &[String],
+    client_column: Option<&ClientColumn>,
+    preamble: &str,
+) -> Result<MultiSink<T>> {
+    let mut sinks: Vec<Box<dyn OutputSink<T>>> = vec![];
+    for destination in destinations {
+        let mut writer = open_destination(destination)?;
+        writer.write_all(preamble.as_bytes())?;
+        sinks.push(Box::new(match client_column {
+            None => CsvSink::new(writer),
+            Some(client_column) => CsvSink::with_client_column(writer, client_column.clone()),
+        }));
+    }
+    Ok(MultiSink::new(sinks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_refuse_urls_instead_of_writing_files_named_after_them() {
+        let cwd = std::env::current_dir().unwrap();
+        for destination in ["s3://bucket/accounts.csv", "kafka://broker/topic"] {
+            for open in [open_destination, open_log] {
+                let error = format!("{:#}", open(destination).err().unwrap());
+                assert!(error.starts_with("Writing to "), "{}", error);
+                assert!(error.ends_with(destination), "{}", error);
+            }
+            let scheme = destination.split_once(':').unwrap().0;
+            assert!(!cwd.join(format!("{}:", scheme)).exists());
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::BigDecimal;
+
+use crate::handler::TransactionHandler;
+use crate::risk::RiskScorer;
+use crate::transaction::{ClientId, Transaction};
+use crate::transaction_engine::{AccountMut, AccountView, TransactionError};
+
+/// What `playground_plugin_abi` has to return, bumped whenever the functions below change.
+/// Builds with wider client ids have their width in the upper half, the client of the
+/// functions is a `uint32_t` or `uint64_t` there and plugins have to be built for it
+pub const ABI_VERSION: u32 = match ClientId::BITS {
+    16 => 1,
+    bits => bits << 16 | 1,
+};
+
+/// How much a handler can write back, its reason or the change to the balance
+const OUT_LEN: usize = 256;
+
+/// `uint32_t playground_plugin_abi(void)`, every plugin has it
+type AbiFn = unsafe extern "C" fn() -> u32;
+/// `const char *playground_handler_kind(void)`, the custom transaction type it handles
+type KindFn = unsafe extern "C" fn() -> *const c_char;
+/// `int32_t playground_handler_apply(uint16_t client, uint32_t tx, const char *amount,
+/// const char *available, char *out, size_t out_len)`, see `DynamicHandler`
+type ApplyFn =
+    unsafe extern "C" fn(ClientId, u32, *const c_char, *const c_char, *mut c_char, usize) -> i32;
+/// `double playground_score(const char *type, uint16_t client, uint32_t tx,
+/// const char *amount, const char *available)`, see `DynamicScorer`
+type ScoreFn =
+    unsafe extern "C" fn(*const c_char, ClientId, u32, *const c_char, *const c_char) -> f64;
+
+/// What a shared library plugin provides, a handler, a risk scorer or both
+pub struct Plugin {
+    pub handler: Option<Box<dyn TransactionHandler>>,
+    pub scorer: Option<Box<dyn RiskScorer>>,
+}
+
+/// Loads a plugin built against the C ABI of this module, so policies can ship without a new
+/// binary. Amounts and balances cross the boundary as decimal strings and an absent amount
+/// is a null pointer. The library is never unloaded, its functions have to be thread safe
+pub fn load(path: &str) -> Result<Plugin> {
+    let library = open(path)?;
+    let abi = symbol(library, "playground_plugin_abi")
+        .with_context(|| format!("{} is not a playground plugin", path))?;
+    // the loader can't check the signatures, the names and the ABI version are all there is
+    // SAFETY: every plugin exports `playground_plugin_abi` as an `AbiFn`, it's the one symbol
+    // whose signature never changes so the version can be checked before anything else
+    let abi = unsafe { std::mem::transmute::<*mut c_void, AbiFn>(abi)() };
+    if abi != ABI_VERSION {
+        bail!(
+            "{} is built for plugin ABI {}, this is ABI {}",
+            path,
+            abi,
+            ABI_VERSION
+        );
+    }
+    let handler = match (
+        symbol(library, "playground_handler_kind"),
+        symbol(library, "playground_handler_apply"),
+    ) {
+        (Some(kind), Some(apply)) => {
+            // SAFETY: the ABI version matched, so the symbol is a `KindFn` of this version
+            let kind = unsafe { std::mem::transmute::<*mut c_void, KindFn>(kind)() };
+            if kind.is_null() {
+                bail!("The handler of {} has no type", path);
+            }
+            // SAFETY: a non-null type is a nul terminated string the library keeps, and it's
+            // never unloaded
+            let kind = unsafe { CStr::from_ptr(kind) }.to_str()?.to_string();
+            // SAFETY: the ABI version matched, so the symbol is an `ApplyFn` of this version
+            let apply = unsafe { std::mem::transmute::<*mut c_void, ApplyFn>(apply) };
+            Some(Box::new(DynamicHandler { kind, apply }) as Box<dyn TransactionHandler>)
+        }
+        (None, None) => None,
+        _ => bail!("{} has only half of a handler", path),
+    };
+    let scorer = symbol(library, "playground_score").map(|score| {
+        // SAFETY: the ABI version matched, so the symbol is a `ScoreFn` of this version
+        let score = unsafe { std::mem::transmute::<*mut c_void, ScoreFn>(score) };
+        Box::new(DynamicScorer { score }) as Box<dyn RiskScorer>
+    });
+    if handler.is_none() && scorer.is_none() {
+        bail!("{} has neither a handler nor a scorer", path);
+    }
+    Ok(Plugin { handler, scorer })
+}
+
+/// Asks the plugin what a transaction of its type does. It returns 0 to apply it, with the
+/// amount to add to the available balance in `out`, negative to take it out, or nothing for
+/// no change, 1 to reject it with the reason in `out`, or 2 to lock the account
+struct DynamicHandler {
+    kind: String,
+    apply: ApplyFn,
+}
+
+impl TransactionHandler for DynamicHandler {
+    fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    fn apply(
+        &self,
+        transaction: &Transaction,
+        account: &mut AccountMut<'_>,
+    ) -> Result<(), TransactionError> {
+        let amount = transaction.amount().map(decimal);
+        let available = decimal(account.view().available());
+        let mut out = [0_u8; OUT_LEN];
+        // SAFETY: the strings are nul terminated and outlive the call, `out` has room for
+        // `out.len()` bytes, and the library is never unloaded
+        let code = unsafe {
+            (self.apply)(
+                transaction.client(),
+                transaction.tx(),
+                amount
+                    .as_ref()
+                    .map_or(std::ptr::null(), |amount| amount.as_ptr()),
+                available.as_ptr(),
+                out.as_mut_ptr().cast(),
+                out.len(),
+            )
+        };
+        let invalid =
+            || TransactionError::RejectedByHandler(format!("invalid_{}_output", self.kind));
+        // an unterminated reply isn't an empty one, the handler overran or forgot the nul
+        let out = CStr::from_bytes_until_nul(&out)
+            .ok()
+            .and_then(|out| out.to_str().ok())
+            .ok_or_else(invalid)?;
+        match code {
+            0 if out.is_empty() => Ok(()),
+            0 => account.credit(&BigDecimal::from_str(out).map_err(|_| invalid())?),
+            1 => Err(TransactionError::RejectedByHandler(out.to_string())),
+            2 => {
+                account.lock(self.kind.clone());
+                Ok(())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The plugin's score of a transaction, its account's available balance is null for the
+/// first transaction of a client
+struct DynamicScorer {
+    score: ScoreFn,
+}
+
+impl RiskScorer for DynamicScorer {
+    fn score(&self, transaction: &Transaction, account: Option<AccountView<'_>>) -> f64 {
+        let kind = CString::new(transaction.type_name()).unwrap_or_default();
+        let amount = transaction.amount().map(decimal);
+        let available = account.map(|account| decimal(account.available()));
+        // SAFETY: the strings are nul terminated or null and outlive the call, and the
+        // library is never unloaded
+        unsafe {
+            (self.score)(
+                kind.as_ptr(),
+                transaction.client(),
+                transaction.tx(),
+                amount
+                    .as_ref()
+                    .map_or(std::ptr::null(), |amount| amount.as_ptr()),
+                available
+                    .as_ref()
+                    .map_or(std::ptr::null(), |available| available.as_ptr()),
+            )
+        }
+    }
+}
+
+fn decimal(amount: &BigDecimal) -> CString {
+    CString::new(amount.to_string()).expect("decimals have no nul bytes")
+}
+
+#[cfg(unix)]
+fn open(path: &str) -> Result<*mut c_void> {
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: i32) -> *mut c_void;
+        fn dlerror() -> *const c_char;
+    }
+    const RTLD_NOW: i32 = 2;
+    let filename = CString::new(path).context("Invalid plugin path")?;
+    // SAFETY: the filename is nul terminated, loading runs the library's initialisers, which
+    // is what loading a plugin means
+    let library = unsafe { dlopen(filename.as_ptr(), RTLD_NOW) };
+    if library.is_null() {
+        // SAFETY: dlerror takes nothing and returns null or a nul terminated message
+        let error = unsafe { dlerror() };
+        let error = match error.is_null() {
+            true => "unknown error".into(),
+            // SAFETY: not null, so a nul terminated message that's valid until the next call
+            false => unsafe { CStr::from_ptr(error) }.to_string_lossy(),
+        };
+        bail!("Failed to load plugin {}: {}", path, error);
+    }
+    Ok(library)
+}
+
+#[cfg(not(unix))]
+fn open(path: &str) -> Result<*mut c_void> {
+    bail!(
+        "Failed to load plugin {}, plugins are only supported on unix",
+        path
+    )
+}
+
+#[cfg(unix)]
+fn symbol(library: *mut c_void, name: &str) -> Option<*mut c_void> {
+    extern "C" {
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    }
+    let name = CString::new(name).ok()?;
+    // SAFETY: the library is a handle from dlopen that's never closed, the name is nul
+    // terminated
+    let symbol = unsafe { dlsym(library, name.as_ptr()) };
+    (!symbol.is_null()).then_some(symbol)
+}
+
+#[cfg(not(unix))]
+fn symbol(_library: *mut c_void, _name: &str) -> Option<*mut c_void> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use super::*;
+    use crate::transaction_engine::TransactionEngine;
+
+    /// Builds plugin-fixture with rustc and the features, with this build's client ids unless
+    /// they're among them
+    fn fixture(features: &[&str]) -> PathBuf {
+        let name = match features {
+            [] => "default".to_string(),
+            features => features.join("_"),
+        };
+        let library = std::env::temp_dir().join(format!(
+            "playground_plugin_{}_{}.so",
+            name,
+            std::process::id()
+        ));
+        let mut rustc = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into()));
+        rustc
+            .args(["--edition", "2021", "--crate-type", "cdylib", "-o"])
+            .arg(&library)
+            .arg(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/plugin-fixture/src/lib.rs"
+            ));
+        let width = match ClientId::BITS {
+            32 => Some("client-id-u32"),
+            64 => Some("client-id-u64"),
+            _ => None,
+        }
+        .filter(|_| {
+            !features
+                .iter()
+                .any(|feature| feature.starts_with("client-id"))
+        });
+        for feature in features.iter().copied().chain(width) {
+            rustc.args(["--cfg", &format!("feature=\"{}\"", feature)]);
+        }
+        let output = rustc.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        library
+    }
+
+    fn load_fixture(features: &[&str]) -> Result<Plugin> {
+        let library = fixture(features);
+        let plugin = load(library.to_str().unwrap());
+        std::fs::remove_file(library).unwrap();
+        plugin
+    }
+
+    fn fee(tx: u32, amount: u32) -> Transaction {
+        Transaction::Custom {
+            kind: "fee".to_string(),
+            client: 1,
+            tx,
+            amount: Some(BigDecimal::from(amount)),
+        }
+    }
+
+    fn engine_with(plugin: Plugin) -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        engine.register_handler(plugin.handler.unwrap()).unwrap();
+        engine
+            .process(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            })
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn should_apply_and_score_with_the_plugin() {
+        let plugin = load_fixture(&[]).unwrap();
+        let scorer = plugin.scorer.as_ref().unwrap();
+        let withdrawal = Transaction::Withdrawal {
+            client: 1,
+            tx: 9,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        };
+        assert_eq!(scorer.score(&withdrawal, None), 0.9);
+        assert_eq!(scorer.score(&fee(9, 1), None), 0.1);
+
+        let mut engine = engine_with(plugin);
+        assert_eq!(engine.process(fee(2, 3)), Ok(()));
+        assert_eq!(
+            engine.process(fee(3, 20)),
+            Err(TransactionError::RejectedByHandler(
+                "fee_unpaid".to_string()
+            ))
+        );
+        assert_eq!(engine.account(1).unwrap().available(), &BigDecimal::from(7));
+    }
+
+    #[test]
+    fn should_reject_replies_without_a_nul() {
+        let mut engine = engine_with(load_fixture(&["unterminated"]).unwrap());
+        assert_eq!(
+            engine.process(fee(2, 3)),
+            Err(TransactionError::RejectedByHandler(
+                "invalid_fee_output".to_string()
+            ))
+        );
+        assert_eq!(
+            engine.account(1).unwrap().available(),
+            &BigDecimal::from(10)
+        );
+    }
+
+    #[test]
+    fn should_refuse_libraries_built_for_another_abi() {
+        let error = |features: &[&str]| format!("{:#}", load_fixture(features).err().unwrap());
+        assert!(error(&["wrong-abi"]).contains(&format!("this is ABI {}", ABI_VERSION)));
+        let other_width = match ClientId::BITS {
+            64 => "client-id-u32",
+            _ => "client-id-u64",
+        };
+        assert!(error(&[other_width]).contains("is built for plugin ABI"));
+        assert!(error(&["half-handler"]).contains("has only half of a handler"));
+        assert!(format!("{:#}", load("libc.so.6").err().unwrap())
+            .contains("is not a playground plugin"));
+    }
+}
@@ -0,0 +1,127 @@
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The parts of a run that are timed with `--profile`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Waiting for the input's bytes
+    Read,
+    /// Turning the bytes into transactions
+    Parse,
+    /// Business rules and risk scoring
+    Validate,
+    /// Updating the account
+    Apply,
+    /// Writing the outputs
+    Serialize,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Read,
+        Stage::Parse,
+        Stage::Validate,
+        Stage::Apply,
+        Stage::Serialize,
+    ];
+
+    /// Where the stage goes in a flame graph
+    const fn stack(self) -> &'static str {
+        match self {
+            Stage::Read => "playground;input;read",
+            Stage::Parse => "playground;input;parse",
+            Stage::Validate => "playground;engine;validate",
+            Stage::Apply => "playground;engine;apply",
+            Stage::Serialize => "playground;output;serialize",
+        }
+    }
+}
+
+/// Time spent per stage, summed over the whole run
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    elapsed: [Duration; 5],
+}
+
+impl Profile {
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        self.elapsed[stage as usize] += elapsed;
+    }
+
+    pub fn record_since(&mut self, stage: Stage, started: Instant) {
+        self.record(stage, started.elapsed());
+    }
+
+    pub const fn elapsed(&self, stage: Stage) -> Duration {
+        self.elapsed[stage as usize]
+    }
+
+    /// Adds the time of another run, e.g. another partition of the input
+    pub fn merge(&mut self, other: &Self) {
+        for stage in Stage::ALL {
+            self.record(stage, other.elapsed(stage));
+        }
+    }
+
+    /// Writes the folded stacks format flame graph tools read, one `stack microseconds` line per stage
+    pub fn write_folded(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for stage in Stage::ALL {
+            let micros = self.elapsed(stage).as_micros();
+            if micros > 0 {
+                writeln!(writer, "{} {}", stage.stack(), micros)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Adds up the time spent waiting in the wrapped reader, so reading can be told apart from parsing
+pub struct TimedRead<R> {
+    inner: R,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl<R: Read> TimedRead<R> {
+    /// The cell is updated as the reader is read from
+    pub fn new(inner: R) -> (Self, Rc<Cell<Duration>>) {
+        let elapsed = Rc::new(Cell::new(Duration::ZERO));
+        let reader = Self {
+            inner,
+            elapsed: Rc::clone(&elapsed),
+        };
+        (reader, elapsed)
+    }
+}
+
+impl<R: Read> Read for TimedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let read = self.inner.read(buf);
+        self.elapsed.set(self.elapsed.get() + started.elapsed());
+        read
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_write_folded_stacks_of_the_stages_that_took_time() {
+        let mut profile = Profile::default();
+        profile.record(Stage::Parse, Duration::from_micros(1500));
+        let mut other = Profile::default();
+        other.record(Stage::Parse, Duration::from_micros(500));
+        other.record(Stage::Apply, Duration::from_millis(3));
+        profile.merge(&other);
+
+        let mut folded = vec![];
+        profile.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "playground;input;parse 2000\nplayground;engine;apply 3000\n"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use bigdecimal::{BigDecimal, Zero};
+use serde::Serialize;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+
+/// What's left of a `promo_credit`. Withdrawals take the credits that expire first before
+/// they touch the available balance, whatever is left at `expires_at` is gone
+#[derive(Clone, Debug, PartialEq)]
+pub struct PromoCredit {
+    pub tx: u32,
+    pub amount: BigDecimal,
+    /// Unix timestamp (seconds)
+    pub expires_at: u64,
+}
+
+/// Takes up to `amount` out of the credits, which are in the order they expire, and returns
+/// how much it took. Spent credits are removed
+pub fn spend(credits: &mut Vec<PromoCredit>, amount: &BigDecimal) -> BigDecimal {
+    let mut left = amount.clone();
+    for credit in credits.iter_mut() {
+        if left.is_zero() {
+            break;
+        }
+        let taken = (&credit.amount).min(&left).clone();
+        credit.amount -= &taken;
+        left -= taken;
+    }
+    credits.retain(|credit| !credit.amount.is_zero());
+    amount - left
+}
+
+impl HeapSize for PromoCredit {
+    fn heap_size(&self) -> usize {
+        self.amount.heap_size()
+    }
+}
+
+impl Snapshot for PromoCredit {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.tx.encode(encoder);
+        self.amount.encode(encoder);
+        self.expires_at.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            tx: u32::decode(decoder)?,
+            amount: BigDecimal::decode(decoder)?,
+            expires_at: u64::decode(decoder)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvPromoCredit {
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: String,
+    pub expires_at: u64,
+}
+
+impl CsvPromoCredit {
+    pub fn new(client: ClientId, credit: &PromoCredit) -> Self {
+        Self {
+            client,
+            tx: credit.tx,
+            amount: format!("{:.4}", credit.amount),
+            expires_at: credit.expires_at,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::{BufReader, Read};
+
+use anyhow::{bail, Context, Result};
+use csv::StringRecord;
+
+use crate::amount::AmountFormat;
+use crate::transaction::{RawTransaction, RowParser};
+
+/// Messages bigger than this are refused rather than allocated, a transaction is a few dozen bytes
+const MAX_MESSAGE_LENGTH: u64 = 1 << 16;
+
+const TYPES: [&str; 5] = ["deposit", "withdrawal", "dispute", "resolve", "chargeback"];
+
+/// The columns of a transactions csv, which is what a `Transaction` message is mapped to
+pub fn headers() -> StringRecord {
+    StringRecord::from(vec!["type", "client", "tx", "amount", "timestamp"])
+}
+
+/// Reads a `TransactionBatch` (see `proto/transaction.proto`) one transaction at a time.
+/// The amounts are decimal strings, so only the scale of `format` applies to them
+pub fn read_batch<R: Read>(
+    reader: R,
+    format: AmountFormat,
+) -> impl Iterator<Item = Result<RawTransaction>> {
+    let parser = RowParser::new(headers(), format.without_locale());
+    records(reader).map(move |record| parser.parse(record?))
+}
+
+/// The transactions of a `TransactionBatch` as csv records, see `headers`.
+/// There's no protobuf crate in the build, the wire format is simple enough to decode by hand
+pub fn records<R: Read>(reader: R) -> impl Iterator<Item = Result<StringRecord>> {
+    let mut reader = BufReader::new(reader);
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let record = next_transaction(&mut reader).transpose();
+        done = !matches!(record, Some(Ok(_)));
+        record
+    })
+}
+
+fn next_transaction(reader: &mut impl Read) -> Result<Option<StringRecord>> {
+    loop {
+        let key = match varint(reader)? {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+        match (key >> 3, key & 7) {
+            (1, 2) => {
+                let message = length_delimited(reader)?;
+                return TransactionMessage::decode(&message).map(Some);
+            }
+            (_, wire_type) => skip(reader, wire_type)?,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TransactionMessage {
+    transaction_type: u64,
+    client: u64,
+    tx: u64,
+    amount: String,
+    timestamp: Option<u64>,
+}
+
+impl TransactionMessage {
+    fn decode(mut message: &[u8]) -> Result<StringRecord> {
+        let mut transaction = Self::default();
+        let reader = &mut message;
+        while let Some(key) = varint(reader)? {
+            match (key >> 3, key & 7) {
+                (1, 0) => transaction.transaction_type = required(varint(reader)?)?,
+                (2, 0) => transaction.client = required(varint(reader)?)?,
+                (3, 0) => transaction.tx = required(varint(reader)?)?,
+                (4, 2) => {
+                    transaction.amount = String::from_utf8(length_delimited(reader)?)
+                        .context("The amount isn't valid UTF-8")?;
+                }
+                (5, 0) => transaction.timestamp = Some(required(varint(reader)?)?),
+                (_, wire_type) => skip(reader, wire_type)?,
+            }
+        }
+        let transaction_type = usize::try_from(transaction.transaction_type)
+            .ok()
+            .and_then(|index| TYPES.get(index))
+            .with_context(|| {
+                format!("Unknown transaction type {}", transaction.transaction_type)
+            })?;
+        Ok(StringRecord::from(vec![
+            transaction_type.to_string(),
+            transaction.client.to_string(),
+            transaction.tx.to_string(),
+            transaction.amount,
+            transaction
+                .timestamp
+                .map(|timestamp| timestamp.to_string())
+                .unwrap_or_default(),
+        ]))
+    }
+}
+
+fn required(value: Option<u64>) -> Result<u64> {
+    value.context("The message ended in the middle of a field")
+}
+
+/// `None` at the end of the input, which is only fine between fields
+fn varint(reader: &mut impl Read) -> Result<Option<u64>> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            bail!("The input ended in the middle of a varint");
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    bail!("Varint longer than 10 bytes")
+}
+
+fn length_delimited(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let length = required(varint(reader)?)?;
+    if length > MAX_MESSAGE_LENGTH {
+        bail!("Field of {} bytes is too long", length);
+    }
+    let mut bytes = vec![0; usize::try_from(length)?];
+    reader
+        .read_exact(&mut bytes)
+        .context("The input ended in the middle of a field")?;
+    Ok(bytes)
+}
+
+/// Fields this version doesn't know, e.g. added by a newer sender
+fn skip(reader: &mut impl Read, wire_type: u64) -> Result<()> {
+    match wire_type {
+        0 => {
+            required(varint(reader)?)?;
+        }
+        1 => reader.read_exact(&mut [0; 8])?,
+        2 => {
+            length_delimited(reader)?;
+        }
+        5 => reader.read_exact(&mut [0; 4])?,
+        other => bail!("Unsupported wire type {}", other),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionType;
+
+    fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    #[test]
+    fn should_read_a_transaction_batch() {
+        let mut deposit = vec![];
+        // type is left out, proto3 doesn't send defaults, so it's a deposit
+        for (field, value) in [(2, 300), (3, 1)] {
+            encode_varint(field << 3, &mut deposit);
+            encode_varint(value, &mut deposit);
+        }
+        deposit.extend([4 << 3 | 2, 3]);
+        deposit.extend(b"1.5");
+        // an unknown fixed32 field
+        deposit.extend([9 << 3 | 5, 1, 2, 3, 4]);
+        let dispute = [1 << 3, 2, 2 << 3, 1, 3 << 3, 1, 5 << 3, 100];
+
+        let mut batch = vec![];
+        for message in [&deposit[..], &dispute[..]] {
+            batch.push(1 << 3 | 2);
+            encode_varint(message.len() as u64, &mut batch);
+            batch.extend(message);
+        }
+        let transactions = read_batch(&batch[..], AmountFormat::default())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(transactions[0].transaction_type(), TransactionType::Deposit);
+        assert_eq!(transactions[0].client(), 300);
+        assert_eq!(
+            transactions[0]
+                .amount()
+                .map(|amount| amount.normalized().to_string()),
+            Some("1.5".to_string())
+        );
+        assert_eq!(transactions[1].transaction_type(), TransactionType::Dispute);
+        assert_eq!(transactions[1].amount(), None);
+        assert_eq!(transactions[1].timestamp(), Some(100));
+
+        assert!(
+            read_batch(&batch[..batch.len() - 1], AmountFormat::default())
+                .last()
+                .unwrap()
+                .is_err()
+        );
+    }
+}
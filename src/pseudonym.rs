@@ -0,0 +1,41 @@
+use crate::sha256::{self, hmac};
+use crate::transaction::ClientId;
+
+/// Replaces client ids in outputs with a keyed hash of them, so reports can be shared without
+/// exposing real ids. The same secret gives the same pseudonyms, so reports from different runs
+/// can still be joined, but without the secret they can't be traced back to a client
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    secret: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// 16 hex digits of HMAC-SHA256 of the client id, plenty for 65536 clients not to collide
+    pub fn pseudonym(&self, client: ClientId) -> String {
+        let digest = hmac(&self.secret, client.to_string().as_bytes());
+        sha256::hex(&digest[..8])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_give_the_same_pseudonym_for_the_same_secret_only() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+        assert_eq!(pseudonymizer.pseudonym(1), pseudonymizer.pseudonym(1));
+        assert_eq!(pseudonymizer.pseudonym(1).len(), 16);
+        assert_ne!(pseudonymizer.pseudonym(1), pseudonymizer.pseudonym(2));
+        assert_ne!(
+            pseudonymizer.pseudonym(1),
+            Pseudonymizer::new(b"other").pseudonym(1)
+        );
+    }
+}
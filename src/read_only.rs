@@ -0,0 +1,74 @@
+use std::fs;
+use std::ops::Deref;
+
+use anyhow::{Context, Result};
+
+use crate::config::EngineConfig;
+use crate::encryption::{self, EnvKeys};
+use crate::transaction_engine::TransactionEngine;
+
+/// A snapshot loaded to be looked at, e.g. production data an analyst queries. Everything
+/// that reads the engine can be called through `Deref`, nothing that changes it, and the
+/// snapshot is never written back. Cloning it gives an engine of one's own to try things on
+pub struct ReadOnlyEngine {
+    engine: TransactionEngine,
+}
+
+impl ReadOnlyEngine {
+    pub fn from_snapshot(snapshot: &[u8], config: EngineConfig) -> Result<Self> {
+        Ok(Self {
+            engine: TransactionEngine::from_snapshot(snapshot, config)?,
+        })
+    }
+
+    /// Reads the snapshot in `path`, decrypted with the keys from the environment if it's
+    /// encrypted. The file is only opened for reading
+    pub fn load(path: &str, config: EngineConfig) -> Result<Self> {
+        let mut snapshot =
+            fs::read(path).with_context(|| format!("Failed to read snapshot {}", path))?;
+        if encryption::is_encrypted(&snapshot) {
+            snapshot = encryption::decrypt_state(&snapshot, &EnvKeys::from_env()?)
+                .with_context(|| format!("Failed to decrypt snapshot {}", path))?;
+        }
+        Self::from_snapshot(&snapshot, config)
+            .with_context(|| format!("Failed to read snapshot {}", path))
+    }
+}
+
+impl Deref for ReadOnlyEngine {
+    type Target = TransactionEngine;
+
+    fn deref(&self) -> &Self::Target {
+        &self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn should_answer_queries_of_a_snapshot() {
+        let mut engine = TransactionEngine::new();
+        engine
+            .process(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            })
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("read-only-{}.bin", std::process::id()));
+        fs::write(&path, engine.snapshot()).unwrap();
+
+        let replica =
+            ReadOnlyEngine::load(path.to_str().unwrap(), EngineConfig::default()).unwrap();
+        assert_eq!(replica.account(1).unwrap().total(), BigDecimal::from(10));
+        assert!(replica.find_transaction(1).unwrap().applied);
+        assert_eq!(replica.accounts().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::errors::ErrorCode;
+use crate::joint::JointAccounts;
+use crate::transaction::{ClientId, RawTransaction, TransactionType};
+
+/// Checks that the disputes, resolves and chargebacks of a set of input files refer to a
+/// deposit or withdrawal of the same account that comes before them, across the files in the
+/// order they're added. Nothing is applied, so balances and locks play no part and every
+/// dangling reference is found, not only the first one of an account
+#[derive(Default)]
+pub struct RefChecker {
+    files: Vec<String>,
+    /// Whose account a joint holder's transactions go to, like the engine's
+    joint_accounts: JointAccounts,
+    /// The first deposit or withdrawal of every account with the tx, with its position.
+    /// Tx ids only have to be unique within an account, so there can be several
+    origins: HashMap<u32, Vec<(ClientId, Position)>>,
+    references: Vec<Reference>,
+}
+
+/// The file, by when it was added, and the row in it, counted from 1 without the header
+type Position = (usize, usize);
+
+struct Reference {
+    transaction_type: &'static str,
+    client: ClientId,
+    /// The client's account, its own unless it's a joint holder
+    account: ClientId,
+    tx: u32,
+    position: Position,
+}
+
+/// A dispute, resolve or chargeback that the engine would reject as an unknown transaction
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvDanglingRef {
+    pub file: String,
+    pub row: usize,
+    #[serde(rename = "type")]
+    pub transaction_type: &'static str,
+    pub client: ClientId,
+    pub tx: u32,
+    /// `unknown_tx` if no file has a deposit or withdrawal with the tx, `other_client` if it's
+    /// another client's and `later` if it only comes after the reference
+    pub reason: &'static str,
+    /// The `ErrorCode` the engine would reject it with
+    pub code: &'static str,
+}
+
+impl RefChecker {
+    /// A checker that resolves joint holders to their accounts, see
+    /// `EngineConfig::joint_accounts`
+    pub fn new(joint_accounts: JointAccounts) -> Self {
+        Self {
+            joint_accounts,
+            ..Self::default()
+        }
+    }
+
+    /// Reads the rows of the next file, failing on the first row that can't be parsed
+    pub fn add_file(
+        &mut self,
+        file: &str,
+        rows: impl Iterator<Item = Result<RawTransaction>>,
+    ) -> Result<()> {
+        let index = self.files.len();
+        self.files.push(file.to_string());
+        for (row, raw) in rows.enumerate() {
+            let raw = raw.with_context(|| format!("Row {} of {}", row + 1, file))?;
+            let position = (index, row + 1);
+            let account = self.joint_accounts.account(raw.client());
+            let transaction_type = match raw.transaction_type() {
+                TransactionType::Deposit | TransactionType::Withdrawal => {
+                    let origins = self.origins.entry(raw.tx()).or_default();
+                    if origins.iter().all(|(client, _)| *client != account) {
+                        origins.push((account, position));
+                    }
+                    continue;
+                }
+                TransactionType::Dispute => "dispute",
+                TransactionType::Resolve => "resolve",
+                TransactionType::Chargeback => "chargeback",
+                _ => continue,
+            };
+            self.references.push(Reference {
+                transaction_type,
+                client: raw.client(),
+                account,
+                tx: raw.tx(),
+                position,
+            });
+        }
+        Ok(())
+    }
+
+    /// Every dangling reference, in the order of the files and rows
+    pub fn dangling(&self) -> Vec<CsvDanglingRef> {
+        self.references
+            .iter()
+            .filter_map(|reference| {
+                let origin = self.origins.get(&reference.tx).map(|origins| {
+                    origins
+                        .iter()
+                        .find(|(account, _)| *account == reference.account)
+                });
+                let reason = match origin {
+                    None => "unknown_tx",
+                    Some(None) => "other_client",
+                    Some(Some((_, position))) if *position > reference.position => "later",
+                    Some(Some(_)) => return None,
+                };
+                let (file, row) = reference.position;
+                Some(CsvDanglingRef {
+                    file: self.files[file].clone(),
+                    row,
+                    transaction_type: reference.transaction_type,
+                    client: reference.client,
+                    tx: reference.tx,
+                    reason,
+                    code: ErrorCode::UnknownTransaction.code(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::AmountFormat;
+    use crate::transaction::read_transactions;
+
+    #[test]
+    fn should_find_the_references_without_an_earlier_transaction_of_the_client() {
+        let mut checker = RefChecker::default();
+        let first = "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            dispute,1,1,\n\
+            dispute,2,1,\n\
+            chargeback,1,3,\n";
+        let second = "type,client,tx,amount\n\
+            withdrawal,1,3,5\n\
+            resolve,1,1,\n\
+            dispute,1,3,\n\
+            dispute,1,4,\n";
+        for (file, csv) in [("first.csv", first), ("second.csv", second)] {
+            let rows = read_transactions(csv.as_bytes(), AmountFormat::default());
+            checker.add_file(file, rows).unwrap();
+        }
+        let dangling = checker
+            .dangling()
+            .into_iter()
+            .map(|reference| (reference.file, reference.row, reference.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dangling,
+            [
+                ("first.csv".to_string(), 3, "other_client"),
+                ("first.csv".to_string(), 4, "later"),
+                ("second.csv".to_string(), 4, "unknown_tx"),
+            ]
+        );
+
+        let broken = "type,client,tx,amount\ndeposit,1,x,10\n";
+        let rows = read_transactions(broken.as_bytes(), AmountFormat::default());
+        assert!(checker.add_file("broken.csv", rows).is_err());
+    }
+
+    #[test]
+    fn should_tell_apart_the_txs_of_different_accounts() {
+        // 3 holds the joint account of 1
+        let joint = JointAccounts::read_csv("client,account\n3,1\n".as_bytes()).unwrap();
+        let mut checker = RefChecker::new(joint);
+        let first = "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            dispute,2,1,\n";
+        let second = "type,client,tx,amount\n\
+            deposit,2,1,5\n\
+            dispute,2,1,\n\
+            resolve,1,1,\n\
+            chargeback,3,1,\n\
+            deposit,4,2,1\n\
+            dispute,5,2,\n";
+        for (file, csv) in [("first.csv", first), ("second.csv", second)] {
+            let rows = read_transactions(csv.as_bytes(), AmountFormat::default());
+            checker.add_file(file, rows).unwrap();
+        }
+        let dangling = checker
+            .dangling()
+            .into_iter()
+            .map(|reference| (reference.file, reference.row, reference.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dangling,
+            [
+                ("first.csv".to_string(), 2, "later"),
+                ("second.csv".to_string(), 6, "other_client"),
+            ]
+        );
+    }
+}
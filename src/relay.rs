@@ -0,0 +1,187 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use playground::config::EngineConfig;
+use playground::event_log::{EventLog, LoggedChange};
+use playground::http::Target;
+use playground::json::Json;
+use playground::transaction_engine::AccountView;
+
+use crate::replicate;
+
+/// Publishes the changes a server logged in its `--event-log` to `url`, one event per account
+/// a change touched, every `every` seconds or once without. Only changes whose record is whole
+/// on disk are published, so nothing the server didn't confirm is, and `cursor` is moved past a
+/// change once all of its events were accepted. A relay that stopped halfway publishes the
+/// change again, with the same `Idempotency-Key`s, consumers drop what they already have
+pub fn run(
+    dir: &str,
+    url: &str,
+    cursor: &str,
+    every: Option<u64>,
+    config: &EngineConfig,
+) -> Result<()> {
+    let target = Target::parse(url)?;
+    loop {
+        match relay(dir, &target, cursor, config) {
+            Ok(0) => {}
+            Ok(published) => eprintln!("Published {} events", published),
+            // a consumer that's down for a bit shouldn't stop a relay that keeps polling
+            Err(e) if every.is_some() => eprintln!("Failed to relay: {:#}", e),
+            Err(e) => return Err(e),
+        }
+        match every {
+            None => return Ok(()),
+            Some(seconds) => thread::sleep(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+/// Publishes the changes after the cursor, returns how many events that was
+fn relay(dir: &str, target: &Target, cursor: &str, config: &EngineConfig) -> Result<usize> {
+    let since = match fs::read_to_string(cursor) {
+        Ok(since) => Some(
+            since
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid cursor in {}", cursor))?,
+        ),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read the cursor {}", cursor)),
+    };
+    let mut published = 0;
+    EventLog::replay(dir, since, config.clone(), |engine, change| {
+        for view in change
+            .clients
+            .iter()
+            .filter_map(|&client| engine.account(client))
+        {
+            let key = format!("{}-{}", change.sequence, view.client());
+            let body = event_json(&key, change, &view).to_string();
+            let reply = target.send_with_headers(
+                "POST",
+                "",
+                &[("Idempotency-Key", key.clone())],
+                Some(("application/json", body.as_bytes())),
+                Duration::from_secs(5),
+            )?;
+            if !(200..300).contains(&reply.status) {
+                bail!("The consumer answered {} to event {}", reply.status, key);
+            }
+            published += 1;
+        }
+        replicate::write_atomically(cursor, change.sequence.to_string().as_bytes())
+    })?;
+    Ok(published)
+}
+
+/// The account as the change left it. `key` is the same every time the change is published
+fn event_json(key: &str, change: &LoggedChange, account: &AccountView<'_>) -> Json {
+    Json::object([
+        ("key", Json::from(key)),
+        ("sequence", Json::from(change.sequence)),
+        ("applied", Json::from(change.applied)),
+        ("client", Json::from(account.client())),
+        (
+            "available",
+            Json::from(format!("{:.4}", account.available())),
+        ),
+        ("held", Json::from(format!("{:.4}", account.held()))),
+        ("total", Json::from(format!("{:.4}", account.total()))),
+        ("locked", Json::from(account.locked())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use bigdecimal::BigDecimal;
+    use playground::transaction::Transaction;
+    use playground::transaction_engine::TransactionEngine;
+
+    use super::*;
+
+    #[test]
+    fn should_publish_every_change_until_the_consumer_took_all_of_it() {
+        let dir = std::env::temp_dir().join(format!("playground-relay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (path, cursor) = (dir.join("log"), dir.join("cursor"));
+        let (path, cursor) = (path.to_str().unwrap(), cursor.to_str().unwrap());
+        let deposit = |client, tx| Transaction::Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let mut engine = TransactionEngine::new();
+        engine.process(deposit(1, 1)).unwrap();
+        let mut log = EventLog::open(path, &engine, 60, 2, 1000).unwrap();
+        for (client, tx) in [(2, 2), (1, 3)] {
+            engine.process(deposit(client, tx)).unwrap();
+            log.append(&engine, 1000).unwrap();
+        }
+
+        // the consumer is down for the second event
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = Target::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let consumer = thread::spawn(move || {
+            let mut events = vec![];
+            for status in [200, 503, 200, 200] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let (mut key, mut length) = (None, 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Idempotency-Key: ") {
+                        key = Some(value.to_string());
+                    }
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                events.push((key.unwrap(), String::from_utf8(body).unwrap()));
+            }
+            events
+        });
+
+        let config = EngineConfig::default();
+        let error = relay(path, &target, cursor, &config).unwrap_err();
+        assert_eq!(error.to_string(), "The consumer answered 503 to event 2-2");
+        // the snapshot's accounts were all published
+        assert_eq!(fs::read_to_string(cursor).unwrap(), "1");
+        assert_eq!(relay(path, &target, cursor, &config).unwrap(), 2);
+        assert_eq!(fs::read_to_string(cursor).unwrap(), "3");
+        assert_eq!(relay(path, &target, cursor, &config).unwrap(), 0);
+
+        let events = consumer.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let keys = events
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["1-1", "2-2", "2-2", "3-1"]);
+        assert_eq!(events[1].1, events[2].1);
+        assert_eq!(
+            events[3].1,
+            r#"{"key":"3-1","sequence":3,"applied":3,"client":1,"available":"20.0000","held":"0.0000","total":"20.0000","locked":false}"#
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use playground::config::EngineConfig;
+use playground::http::Target;
+use playground::transaction_engine::TransactionEngine;
+
+/// Keeps the snapshot in `state` in sync with the server at `url`, e.g. in another region to fail
+/// over to. Asks for the changes since the snapshot's sequence number every `every` seconds,
+/// or once without. The snapshot is only replaced once a batch of changes has been applied
+pub fn run(url: &str, state: &str, every: Option<u64>, config: &EngineConfig) -> Result<()> {
+    let target = Target::parse(url)?;
+    let mut engine = match fs::read(state) {
+        Ok(snapshot) => TransactionEngine::from_snapshot(&snapshot, config.clone())
+            .with_context(|| format!("Failed to read snapshot {}", state))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => TransactionEngine::with_config(config.clone()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read snapshot {}", state)),
+    };
+    loop {
+        match pull(&target, &mut engine) {
+            Ok(None) => {}
+            Ok(Some(sequence)) => {
+                write_atomically(state, &engine.snapshot())?;
+                eprintln!("Replicated up to sequence {}", sequence);
+            }
+            // a primary that's down for a bit shouldn't stop a replica that keeps polling
+            Err(e) if every.is_some() => eprintln!("Failed to replicate: {:#}", e),
+            Err(e) => return Err(e),
+        }
+        match every {
+            None => return Ok(()),
+            Some(seconds) => thread::sleep(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+/// Applies the changes the primary has, returns the new sequence number if there were any
+fn pull(target: &Target, engine: &mut TransactionEngine) -> Result<Option<u64>> {
+    let mut since = engine.sequence();
+    let mut reply = target.send(
+        "GET",
+        &format!("export/changes?since={}", since),
+        None,
+        Duration::from_secs(60),
+    )?;
+    if reply.status == 409 {
+        // the primary went back to an older snapshot, what we have may never have happened
+        eprintln!("The primary is behind sequence {}, starting over", since);
+        since = 0;
+        reply = target.send("GET", "export/changes", None, Duration::from_secs(60))?;
+    }
+    if reply.status != 200 {
+        bail!(
+            "The primary answered {}: {}",
+            reply.status,
+            String::from_utf8_lossy(&reply.body)
+        );
+    }
+    if since > 0 && reply.header("X-Sequence") == Some(since.to_string().as_str()) {
+        return Ok(None);
+    }
+    engine.apply_changes(&reply.body).map(Some)
+}
+
+pub fn write_atomically(path: &str, bytes: &[u8]) -> Result<()> {
+    let mut temporary = Path::new(path).as_os_str().to_owned();
+    temporary.push(".tmp");
+    let mut file = fs::File::create(&temporary)
+        .with_context(|| format!("Failed to write next to {}", path))?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path).with_context(|| format!("Failed to replace {}", path))?;
+    Ok(())
+}
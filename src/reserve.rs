@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::tags::AccountTags;
+use crate::transaction::ClientId;
+
+/// How much of its available balance an account has to keep, read from a file with the
+/// reserve of every account, of the accounts with a tag, e.g. their tier, and of single
+/// accounts:
+/// ```text
+/// # every account keeps 10, premium accounts 1000 and client 7 nothing
+/// default 10
+/// tag premium 1000
+/// client 7 0
+/// ```
+/// A client's own line wins over its tags, and the highest reserve of its tags over the default
+#[derive(Clone, Debug, Default)]
+pub struct ReserveRequirements {
+    default: Option<BigDecimal>,
+    tags: Vec<(String, BigDecimal)>,
+    clients: HashMap<ClientId, BigDecimal>,
+}
+
+impl ReserveRequirements {
+    pub fn parse(requirements: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (index, line) in requirements.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let reserve = |reserve: &str| {
+                let reserve = reserve
+                    .parse::<BigDecimal>()
+                    .with_context(|| format!("Invalid reserve on line {}", index + 1))?;
+                if reserve < BigDecimal::zero() {
+                    bail!("Negative reserve on line {}", index + 1);
+                }
+                Ok(reserve)
+            };
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["default", amount] => parsed.default = Some(reserve(amount)?),
+                ["tag", tag, amount] => parsed.tags.push((tag.to_string(), reserve(amount)?)),
+                ["client", client, amount] => {
+                    let client = client
+                        .parse()
+                        .with_context(|| format!("Invalid client on line {}", index + 1))?;
+                    parsed.clients.insert(client, reserve(amount)?);
+                }
+                _ => bail!(
+                    "Expected default <amount>, tag <tag> <amount> or client <id> <amount> on line {}",
+                    index + 1
+                ),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.tags.is_empty() && self.clients.is_empty()
+    }
+
+    /// What the client's account has to keep available, zero if it has no reserve
+    pub fn reserve(&self, client: ClientId, tags: &AccountTags) -> BigDecimal {
+        if let Some(reserve) = self.clients.get(&client) {
+            return reserve.clone();
+        }
+        self.tags
+            .iter()
+            .filter(|(tag, _)| tags.has(client, tag))
+            .map(|(_, reserve)| reserve)
+            .max()
+            .or(self.default.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pick_the_reserve_of_the_client_then_its_tags_then_the_default() {
+        let reserves = ReserveRequirements::parse(
+            "# tiers\ndefault 10\ntag premium 1000\ntag gold 500\nclient 7 0\n",
+        )
+        .unwrap();
+        let mut tags = AccountTags::default();
+        tags.add(2, "gold");
+        tags.add(2, "premium");
+        tags.add(7, "premium");
+        assert_eq!(reserves.reserve(1, &tags), BigDecimal::from(10));
+        assert_eq!(reserves.reserve(2, &tags), BigDecimal::from(1000));
+        assert_eq!(reserves.reserve(7, &tags), BigDecimal::zero());
+        assert_eq!(
+            ReserveRequirements::default().reserve(1, &tags),
+            BigDecimal::zero()
+        );
+
+        assert!(ReserveRequirements::parse("default -1").is_err());
+        assert!(ReserveRequirements::parse("tier gold 5").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use bigdecimal::ToPrimitive;
+
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::Transaction;
+use crate::transaction_engine::AccountView;
+
+/// Decides how risky a transaction is before the engine applies it,
+/// transactions scoring above `EngineConfig::risk_threshold` are held for review
+pub trait RiskScorer: Send + Sync {
+    /// Higher is riskier. `account` is `None` for the first transaction of a client
+    fn score(&self, transaction: &Transaction, account: Option<AccountView<'_>>) -> f64;
+}
+
+/// A transaction the risk scorers didn't let through, waiting for someone to look at it
+#[derive(Clone, Debug)]
+pub struct PendingTransaction {
+    pub transaction: Transaction,
+    pub score: f64,
+}
+
+impl Snapshot for PendingTransaction {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.transaction.encode(encoder);
+        self.score.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            transaction: Transaction::decode(decoder)?,
+            score: f64::decode(decoder)?,
+        })
+    }
+}
+
+/// A held transaction someone decided not to apply
+#[derive(Clone, Debug)]
+pub struct DeniedTransaction {
+    pub transaction: Transaction,
+    pub score: f64,
+    pub reason: String,
+}
+
+impl Snapshot for DeniedTransaction {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.transaction.encode(encoder);
+        self.score.encode(encoder);
+        self.reason.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            transaction: Transaction::decode(decoder)?,
+            score: f64::decode(decoder)?,
+            reason: String::decode(decoder)?,
+        })
+    }
+}
+
+/// Running mean and variance of an account's applied deposit and withdrawal amounts,
+/// kept up to date as transactions are applied so scorers don't have to scan the history
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AmountStats {
+    count: u64,
+    mean: f64,
+    // sum of squared differences from the mean, see Welford's online algorithm
+    m2: f64,
+}
+
+impl AmountStats {
+    pub fn add(&mut self, amount: f64) {
+        self.count += 1;
+        let delta = amount - self.mean;
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.count as f64;
+        self.mean += delta / count;
+        self.m2 += delta * (amount - self.mean);
+    }
+
+    /// Combines the stats of two sets of amounts, as if all of them had been added to one
+    pub fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let (count, other_count) = (self.count as f64, other.count as f64);
+        let total = count + other_count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other_count / total;
+        self.m2 += other.m2 + delta * delta * count * other_count / total;
+        self.count += other.count;
+    }
+
+    /// The stats of the amounts added after `earlier`, which are stats of the same account
+    /// from before, undoing `merge`
+    pub fn since(&self, earlier: &Self) -> Self {
+        let count = self.count.saturating_sub(earlier.count);
+        if count == 0 {
+            return Self::default();
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let (total, before, after) = (self.count as f64, earlier.count as f64, count as f64);
+        let mean = (self.mean * total - earlier.mean * before) / after;
+        let delta = mean - earlier.mean;
+        Self {
+            count,
+            mean,
+            m2: (self.m2 - earlier.m2 - delta * delta * before * after / total).max(0.0),
+        }
+    }
+
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub const fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample standard deviation, zero until there are two amounts
+    pub fn std_dev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.count as f64;
+        (self.m2 / (count - 1.0)).sqrt()
+    }
+
+    /// How many standard deviations `amount` is from the mean,
+    /// `None` if the amounts so far have no spread to compare against
+    pub fn z_score(&self, amount: f64) -> Option<f64> {
+        let std_dev = self.std_dev();
+        if std_dev == 0.0 {
+            return None;
+        }
+        Some((amount - self.mean) / std_dev)
+    }
+}
+
+impl Snapshot for AmountStats {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.count.encode(encoder);
+        self.mean.encode(encoder);
+        self.m2.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            count: u64::decode(decoder)?,
+            mean: f64::decode(decoder)?,
+            m2: f64::decode(decoder)?,
+        })
+    }
+}
+
+/// The bundled scorer: how unusually large the amount is for the client (z-score),
+/// plus a penalty for every dispute the client has raised, weighted by how it turned out
+pub struct HeuristicScorer {
+    /// Amounts aren't compared until the client has this many deposits and withdrawals
+    pub min_samples: u64,
+    /// For every dispute, whatever came of it
+    pub dispute_weight: f64,
+    /// On top, for every dispute that was resolved or expired
+    pub resolved_weight: f64,
+    /// On top, for every dispute that was charged back
+    pub chargeback_weight: f64,
+}
+
+impl Default for HeuristicScorer {
+    fn default() -> Self {
+        Self {
+            min_samples: 5,
+            dispute_weight: 1.0,
+            resolved_weight: 0.0,
+            chargeback_weight: 2.0,
+        }
+    }
+}
+
+impl RiskScorer for HeuristicScorer {
+    fn score(&self, transaction: &Transaction, account: Option<AccountView<'_>>) -> f64 {
+        let (account, amount) = match (account, transaction.amount()) {
+            (Some(account), Some(amount)) => (account, amount),
+            _ => return 0.0,
+        };
+        let stats = account.amount_stats();
+        let unusual_amount = if stats.count() < self.min_samples {
+            0.0
+        } else {
+            amount
+                .to_f64()
+                .and_then(|amount| stats.z_score(amount))
+                .map_or(0.0, |z_score| z_score.max(0.0))
+        };
+        let disputes = account.dispute_stats();
+        #[allow(clippy::cast_precision_loss)]
+        let weighted = self.dispute_weight * disputes.raised as f64
+            + self.resolved_weight * (disputes.resolved + disputes.expired) as f64
+            + self.chargeback_weight * disputes.charged_back as f64;
+        unusual_amount + weighted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_running_mean_and_deviation() {
+        let mut stats = AmountStats::default();
+        for amount in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(amount);
+        }
+        assert_eq!(stats.count(), 8);
+        assert!((stats.mean() - 5.0).abs() < 1e-9);
+        assert!((stats.std_dev() - 2.138_089_935).abs() < 1e-9);
+        assert_eq!(AmountStats::default().z_score(1.0), None);
+    }
+
+    #[test]
+    fn should_merge_stats_like_one_sequence() {
+        let mut left = AmountStats::default();
+        let mut right = AmountStats::default();
+        for amount in [2.0, 4.0, 4.0] {
+            left.add(amount);
+        }
+        for amount in [4.0, 5.0, 5.0, 7.0, 9.0] {
+            right.add(amount);
+        }
+        left.merge(&right);
+        assert_eq!(left.count(), 8);
+        assert!((left.mean() - 5.0).abs() < 1e-9);
+        assert!((left.std_dev() - 2.138_089_935).abs() < 1e-9);
+    }
+}
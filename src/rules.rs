@@ -0,0 +1,509 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::transaction::Transaction;
+use crate::transaction_engine::AccountView;
+use crate::velocity::{Totals, Window};
+
+/// Business rules operators can change without recompiling, read from a file with one rule per line:
+/// ```text
+/// # withdrawals above the daily limit need to go through the branch
+/// type == "withdrawal" && amount > 10000 => reject("limit")
+/// locked || available < 0 => reject("account_state")
+/// ```
+/// The condition can use the transaction's `type`, `client`, `tx` and `amount` (empty for
+/// disputes, resolves and chargebacks) and the account's current `available`, `total` and
+/// `locked`, combined with `== != < <= > >= && || ! + - * /` and parentheses.
+/// How many deposits and withdrawals the account had applied in the last hour or day, and
+/// how much they were for, are `deposits_1h`, `deposited_1h`, `withdrawals_24h`, `withdrawn_24h`
+/// and so on, not counting the transaction itself. See `Velocity`.
+/// The first rule that matches rejects the transaction with its reason
+#[derive(Clone, Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    condition: Expr,
+    reason: String,
+}
+
+impl RuleSet {
+    pub fn parse(rules: &str) -> Result<Self> {
+        let rules = rules
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                Rule::parse(line).with_context(|| format!("Invalid rule on line {}", index + 1))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The reason of the first rule rejecting the transaction, if any
+    /// `now` is the engine's time, what the velocity windows end at
+    pub fn check(
+        &self,
+        transaction: &Transaction,
+        account: Option<AccountView<'_>>,
+        now: u64,
+    ) -> Option<&str> {
+        let scope = Scope {
+            transaction,
+            account,
+            now,
+        };
+        self.rules
+            .iter()
+            .find(|rule| rule.condition.eval(&scope).truthy())
+            .map(|rule| rule.reason.as_str())
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Self> {
+        let mut parser = Parser::new(line)?;
+        let condition = parser.expression()?;
+        parser.expect(&Token::Arrow)?;
+        parser.expect(&Token::Identifier("reject".to_string()))?;
+        parser.expect(&Token::LeftParen)?;
+        let reason = match parser.next() {
+            Some(Token::String(reason)) => reason,
+            _ => bail!("Expected the reason of the reject as a string"),
+        };
+        parser.expect(&Token::RightParen)?;
+        if let Some(token) = parser.next() {
+            bail!("Unexpected {:?} after the reject", token);
+        }
+        Ok(Self { condition, reason })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Token {
+    Identifier(String),
+    Number(BigDecimal),
+    String(String),
+    Operator(Operator),
+    Not,
+    LeftParen,
+    RightParen,
+    Arrow,
+    /// A single `=`, for the assignments of a `Transform`
+    Assign,
+    Comma,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    /// Higher binds tighter
+    const fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Add | Operator::Subtract => 4,
+            Operator::Multiply | Operator::Divide => 5,
+            _ => 3,
+        }
+    }
+}
+
+pub(crate) fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        // after a value a minus subtracts, anywhere else it starts a negative number
+        let after_value = matches!(
+            tokens.last(),
+            Some(Token::Identifier(_) | Token::Number(_) | Token::String(_) | Token::RightParen)
+        );
+        let mut followed_by = |expected| chars.next_if_eq(&expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '=' if followed_by('>') => Token::Arrow,
+            '=' if followed_by('=') => Token::Operator(Operator::Equal),
+            '=' => Token::Assign,
+            ',' => Token::Comma,
+            '+' => Token::Operator(Operator::Add),
+            '-' if after_value => Token::Operator(Operator::Subtract),
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '!' if followed_by('=') => Token::Operator(Operator::NotEqual),
+            '!' => Token::Not,
+            '<' if followed_by('=') => Token::Operator(Operator::LessOrEqual),
+            '<' => Token::Operator(Operator::Less),
+            '>' if followed_by('=') => Token::Operator(Operator::GreaterOrEqual),
+            '>' => Token::Operator(Operator::Greater),
+            '&' if followed_by('&') => Token::Operator(Operator::And),
+            '|' if followed_by('|') => Token::Operator(Operator::Or),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        None => bail!("Unterminated string"),
+                        Some('"') => break,
+                        Some(c) => string.push(c),
+                    }
+                }
+                Token::String(string)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut number = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                Token::Number(
+                    BigDecimal::from_str(&number)
+                        .with_context(|| format!("Invalid number {}", number))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut identifier = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    identifier.push(c);
+                }
+                Token::Identifier(identifier)
+            }
+            c => bail!("Unexpected character {}", c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Expr {
+    Field(Field),
+    Literal(Value),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Available,
+    Total,
+    Locked,
+    /// How many deposits the account had in the window
+    Deposits(Window),
+    /// What the deposits in the window add up to
+    Deposited(Window),
+    Withdrawals(Window),
+    Withdrawn(Window),
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ok(match name {
+            "type" => Field::Type,
+            "client" => Field::Client,
+            "tx" => Field::Tx,
+            "amount" => Field::Amount,
+            "available" => Field::Available,
+            "total" => Field::Total,
+            "locked" => Field::Locked,
+            "deposits_1h" => Field::Deposits(Window::Hour),
+            "deposits_24h" => Field::Deposits(Window::Day),
+            "deposited_1h" => Field::Deposited(Window::Hour),
+            "deposited_24h" => Field::Deposited(Window::Day),
+            "withdrawals_1h" => Field::Withdrawals(Window::Hour),
+            "withdrawals_24h" => Field::Withdrawals(Window::Day),
+            "withdrawn_1h" => Field::Withdrawn(Window::Hour),
+            "withdrawn_24h" => Field::Withdrawn(Window::Day),
+            _ => bail!("Unknown field {}", name),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    /// E.g. the amount of a dispute, compares unequal to everything but itself
+    Empty,
+    Bool(bool),
+    Number(BigDecimal),
+    String(String),
+}
+
+impl Value {
+    pub(crate) fn truthy(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => left.partial_cmp(right),
+            (Value::String(left), Value::String(right)) => left.partial_cmp(right),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(line: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: tokenize(line)?,
+            position: 0,
+        })
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    pub(crate) fn expect(&mut self, expected: &Token) -> Result<()> {
+        match self.next() {
+            Some(token) if &token == expected => Ok(()),
+            Some(token) => bail!("Expected {:?} but found {:?}", expected, token),
+            None => bail!("Expected {:?} but the rule ended", expected),
+        }
+    }
+
+    pub(crate) fn expression(&mut self) -> Result<Expr> {
+        self.binary(1)
+    }
+
+    /// Precedence climbing, only looks at operators binding at least as tight as `precedence`
+    fn binary(&mut self, precedence: u8) -> Result<Expr> {
+        let mut left = self.unary()?;
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position) {
+            let operator = *operator;
+            if operator.precedence() < precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(operator.precedence() + 1)?;
+            left = Expr::Binary(Box::new(left), operator, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        Ok(match self.next() {
+            Some(Token::Not) => Expr::Not(Box::new(self.unary()?)),
+            Some(Token::LeftParen) => {
+                let expr = self.expression()?;
+                self.expect(&Token::RightParen)?;
+                expr
+            }
+            Some(Token::Number(number)) => Expr::Literal(Value::Number(number)),
+            Some(Token::String(string)) => Expr::Literal(Value::String(string)),
+            Some(Token::Identifier(name)) => match name.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                name => Expr::Field(name.parse()?),
+            },
+            Some(token) => bail!("Unexpected {:?}", token),
+            None => bail!("Expected a condition"),
+        })
+    }
+}
+
+pub(crate) struct Scope<'a> {
+    pub(crate) transaction: &'a Transaction,
+    pub(crate) account: Option<AccountView<'a>>,
+    pub(crate) now: u64,
+}
+
+impl Scope<'_> {
+    fn field(&self, field: Field) -> Value {
+        // a client's first transaction sees the balances of a fresh account
+        match field {
+            Field::Type => Value::String(self.transaction.type_name().to_string()),
+            Field::Client => Value::Number(self.transaction.client().into()),
+            Field::Tx => Value::Number(self.transaction.tx().into()),
+            Field::Amount => self
+                .transaction
+                .amount()
+                .map_or(Value::Empty, |amount| Value::Number(amount.clone())),
+            Field::Available => Value::Number(
+                self.account
+                    .map_or_else(BigDecimal::zero, |account| account.available().clone()),
+            ),
+            Field::Total => Value::Number(
+                self.account
+                    .map_or_else(BigDecimal::zero, |account| account.total()),
+            ),
+            Field::Locked => Value::Bool(self.account.is_some_and(|account| account.locked())),
+            Field::Deposits(window) => Value::Number(self.deposits(window).count.into()),
+            Field::Deposited(window) => Value::Number(self.deposits(window).sum),
+            Field::Withdrawals(window) => Value::Number(self.withdrawals(window).count.into()),
+            Field::Withdrawn(window) => Value::Number(self.withdrawals(window).sum),
+        }
+    }
+
+    fn deposits(&self, window: Window) -> Totals {
+        self.account.map_or_else(Totals::default, |account| {
+            account.velocity().deposits(window, self.now)
+        })
+    }
+
+    fn withdrawals(&self, window: Window) -> Totals {
+        self.account.map_or_else(Totals::default, |account| {
+            account.velocity().withdrawals(window, self.now)
+        })
+    }
+}
+
+impl Expr {
+    pub(crate) fn eval(&self, scope: &Scope<'_>) -> Value {
+        match self {
+            Expr::Field(field) => scope.field(*field),
+            Expr::Literal(value) => value.clone(),
+            Expr::Not(expr) => Value::Bool(!expr.eval(scope).truthy()),
+            Expr::Binary(left, Operator::Or, right) => {
+                Value::Bool(left.eval(scope).truthy() || right.eval(scope).truthy())
+            }
+            Expr::Binary(left, Operator::And, right) => {
+                Value::Bool(left.eval(scope).truthy() && right.eval(scope).truthy())
+            }
+            Expr::Binary(
+                left,
+                operator @ (Operator::Add
+                | Operator::Subtract
+                | Operator::Multiply
+                | Operator::Divide),
+                right,
+            ) => match (left.eval(scope), right.eval(scope)) {
+                (Value::Number(left), Value::Number(right)) => match operator {
+                    Operator::Add => Value::Number(left + right),
+                    Operator::Subtract => Value::Number(left - right),
+                    Operator::Multiply => Value::Number(left * right),
+                    _ if right.is_zero() => Value::Empty,
+                    _ => Value::Number(left / right),
+                },
+                // like comparing them, arithmetic on anything but numbers has no result
+                _ => Value::Empty,
+            },
+            Expr::Binary(left, operator, right) => {
+                let left = left.eval(scope);
+                let right = right.eval(scope);
+                let ordering = left.compare(&right);
+                Value::Bool(match operator {
+                    Operator::Equal => left == right,
+                    Operator::NotEqual => left != right,
+                    Operator::Less => ordering == Some(Ordering::Less),
+                    Operator::LessOrEqual => {
+                        matches!(ordering, Some(Ordering::Less | Ordering::Equal))
+                    }
+                    Operator::Greater => ordering == Some(Ordering::Greater),
+                    Operator::GreaterOrEqual => {
+                        matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                    Operator::Or
+                    | Operator::And
+                    | Operator::Add
+                    | Operator::Subtract
+                    | Operator::Multiply
+                    | Operator::Divide => unreachable!("handled above"),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_with_the_first_matching_rule() {
+        let rules = RuleSet::parse(
+            r#"
+            # comments and blank lines are skipped
+            type == "withdrawal" && amount > 10000 => reject("limit")
+            !(client == 1 || client == 2) && amount >= 5 => reject("unknown_client")
+            "#,
+        )
+        .unwrap();
+        let withdrawal = |client, amount| Transaction::Withdrawal {
+            client,
+            tx: 1,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+
+        assert_eq!(rules.check(&withdrawal(1, 20000), None, 0), Some("limit"));
+        assert_eq!(rules.check(&withdrawal(1, 10000), None, 0), None);
+        assert_eq!(
+            rules.check(&withdrawal(3, 5), None, 0),
+            Some("unknown_client")
+        );
+        assert_eq!(
+            rules.check(&Transaction::Dispute { client: 3, tx: 1 }, None, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn should_report_invalid_rules() {
+        let error = RuleSet::parse("amount > 1 => reject(\"a\")\nbalance > 1 => reject(\"b\")")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid rule on line 2");
+        assert!(RuleSet::parse("amount > => reject(\"a\")").is_err());
+        assert!(RuleSet::parse("amount > 1").is_err());
+        assert!(RuleSet::parse("amount = 1 => reject(\"a\")").is_err());
+    }
+
+    #[test]
+    fn should_do_arithmetic_before_comparing() {
+        let rules = RuleSet::parse(
+            "amount * 2 - 1 > available + 10 / 2 => reject(\"big\")\nclient-1 == -2 => reject(\"c\")",
+        )
+        .unwrap();
+        let deposit = |client, amount| Transaction::Deposit {
+            client,
+            tx: 1,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        assert_eq!(rules.check(&deposit(1, 4), None, 0), Some("big"));
+        assert_eq!(rules.check(&deposit(1, 3), None, 0), None);
+        assert_eq!(
+            rules.check(&Transaction::Dispute { client: 1, tx: 1 }, None, 0),
+            None
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+use playground::amount::ScalePolicy;
+use playground::date;
+use playground::errors::ErrorCode;
+use playground::json::Json;
+use playground::memory::{MemoryStats, ProcessMemory};
+use playground::sha256::{self, Sha256};
+use playground::trace::{Metric, MetricValue};
+use playground::transaction::{ClientId, CsvReject, RawTransaction};
+use playground::transaction_engine::{OpeningConflict, TransactionEngine};
+
+use crate::cli::Options;
+
+/// Everything went fine
+pub const EXIT_SUCCESS: i32 = 0;
+/// The run finished, but some transactions were rejected or held for review
+pub const EXIT_REJECTS: i32 = 2;
+/// The run didn't finish, e.g. invalid arguments, unreadable input or a broken output
+pub const EXIT_FATAL: i32 = 3;
+
+/// What happened during a run, collected as it goes for the `--report-out` report
+#[derive(Default)]
+pub struct RunStats {
+    pub rows: u64,
+    pub applied: u64,
+    pub rejects_by_reason: BTreeMap<String, u64>,
+    /// The same rejects by their `ErrorCode`
+    pub rejects_by_code: BTreeMap<&'static str, u64>,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub inputs: Vec<InputDigest>,
+    /// Amounts that had more decimals than `--scale` and were truncated or rounded
+    pub adjusted_amounts: Vec<AdjustedAmount>,
+    /// With `--memory-stats`, once the input is processed
+    pub memory: Option<(MemoryStats, ProcessMemory)>,
+    /// The engine's sequence number before the input, the accounts changed after it are the
+    /// ones the run touched
+    pub started_at: u64,
+    /// The `opening` rows for accounts that had been seeded, see `TransactionEngine::process_opening`
+    pub opening_conflicts: Vec<OpeningConflict>,
+}
+
+pub struct AdjustedAmount {
+    pub client: ClientId,
+    pub tx: u32,
+    pub original: String,
+    pub adjusted: String,
+}
+
+impl AdjustedAmount {
+    pub fn of(raw: &RawTransaction) -> Option<Self> {
+        let original = raw.original_amount()?;
+        Some(Self {
+            client: raw.client(),
+            tx: raw.tx(),
+            original: original.normalized().to_string(),
+            adjusted: raw.amount().map(ToString::to_string).unwrap_or_default(),
+        })
+    }
+}
+
+pub struct InputDigest {
+    pub source: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+impl InputDigest {
+    pub fn new(source: &str, hasher: &Sha256) -> Self {
+        Self {
+            source: source.to_string(),
+            bytes: hasher.len(),
+            sha256: sha256::hex(&hasher.finish()),
+        }
+    }
+}
+
+impl RunStats {
+    pub fn record_applied(&mut self, rows: usize) {
+        self.rows += rows as u64;
+        self.applied += rows as u64;
+    }
+
+    pub fn record_reject(&mut self, reject: &CsvReject) {
+        self.rows += 1;
+        *self
+            .rejects_by_reason
+            .entry(reject.reason.clone())
+            .or_default() += 1;
+        *self.rejects_by_code.entry(reject.code).or_default() += 1;
+    }
+
+    pub fn record_accounts(&mut self, transaction_engine: &TransactionEngine) {
+        self.accounts = 0;
+        self.locked_accounts = 0;
+        for account in transaction_engine.account_views() {
+            self.accounts += 1;
+            self.locked_accounts += usize::from(account.locked());
+        }
+    }
+
+    pub fn record_opening_conflicts(&mut self, transaction_engine: &TransactionEngine) {
+        self.opening_conflicts = transaction_engine.opening_conflicts().to_vec();
+    }
+
+    pub fn record_memory(&mut self, transaction_engine: &TransactionEngine) {
+        self.memory = Some((transaction_engine.memory_stats(), ProcessMemory::current()));
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejects_by_reason.values().sum()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.rejected() > 0 {
+            EXIT_REJECTS
+        } else {
+            EXIT_SUCCESS
+        }
+    }
+}
+
+/// What `--memory-stats` adds to the report, and the server to its `/stats`
+pub fn memory_json(engine: &MemoryStats, process: &ProcessMemory) -> Json {
+    Json::object([
+        ("accounts", Json::from(engine.accounts)),
+        ("transactions", Json::from(engine.transactions)),
+        ("ledger_entries", Json::from(engine.ledger_entries)),
+        (
+            "estimated_heap_bytes",
+            Json::from(engine.estimated_heap_bytes),
+        ),
+        ("rss_bytes", Json::from(process.rss_bytes)),
+        ("peak_rss_bytes", Json::from(process.peak_rss_bytes)),
+    ])
+}
+
+/// How far a run that's still going is, logged when it gets SIGUSR1
+pub fn progress_json(stats: &RunStats, transaction_engine: &TransactionEngine) -> Json {
+    Json::object([
+        ("rows", Json::from(stats.rows)),
+        ("applied", Json::from(stats.applied)),
+        ("rejected", Json::from(stats.rejected())),
+        (
+            "rejects_by_reason",
+            Json::Object(
+                stats
+                    .rejects_by_reason
+                    .iter()
+                    .map(|(reason, count)| (reason.clone(), Json::from(*count)))
+                    .collect(),
+            ),
+        ),
+        ("rejects_by_code", rejects_by_code(stats)),
+        (
+            "memory",
+            memory_json(
+                &transaction_engine.memory_stats(),
+                &ProcessMemory::current(),
+            ),
+        ),
+    ])
+}
+
+fn rejects_by_code(stats: &RunStats) -> Json {
+    Json::Object(
+        stats
+            .rejects_by_code
+            .iter()
+            .map(|(code, count)| ((*code).to_string(), Json::from(*count)))
+            .collect(),
+    )
+}
+
+/// The counts of a run for `--otlp-endpoint`, while it's going or once it's `finished`,
+/// after how long. The accounts and the duration are only known then
+pub fn metrics(stats: &RunStats, finished: Option<Duration>) -> Vec<Metric> {
+    let metric = |name, unit, value| Metric {
+        name,
+        unit,
+        value,
+        attributes: vec![],
+    };
+    let mut metrics = vec![
+        metric("playground.rows", "{row}", MetricValue::Count(stats.rows)),
+        metric(
+            "playground.applied",
+            "{row}",
+            MetricValue::Count(stats.applied),
+        ),
+    ];
+    metrics.extend(
+        stats
+            .rejects_by_reason
+            .iter()
+            .map(|(reason, count)| Metric {
+                attributes: vec![("reason", Json::from(reason.as_str()))],
+                ..metric("playground.rejected", "{row}", MetricValue::Count(*count))
+            }),
+    );
+    if let Some(duration) = finished {
+        // gauges of counts are whole numbers, far below where a float loses precision
+        #[allow(clippy::cast_precision_loss)]
+        metrics.extend([
+            metric(
+                "playground.accounts",
+                "{account}",
+                MetricValue::Gauge(stats.accounts as f64),
+            ),
+            metric(
+                "playground.locked_accounts",
+                "{account}",
+                MetricValue::Gauge(stats.locked_accounts as f64),
+            ),
+            metric(
+                "playground.run.duration",
+                "s",
+                MetricValue::Gauge(duration.as_secs_f64()),
+            ),
+        ]);
+    }
+    metrics
+}
+
+/// The report written with `--report-out`, `error` is set if the run failed
+pub fn write_report(
+    options: &Options,
+    stats: &RunStats,
+    duration: Duration,
+    exit_code: i32,
+    error: Option<&anyhow::Error>,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let client_json = |client| client_json(options, client);
+    let report = Json::object([
+        (
+            "status",
+            Json::from(match exit_code {
+                EXIT_SUCCESS => "success",
+                EXIT_REJECTS => "completed_with_rejects",
+                _ => "fatal",
+            }),
+        ),
+        ("exit_code", Json::from(i64::from(exit_code))),
+        (
+            "error",
+            Json::from(error.map(|error| format!("{:#}", error))),
+        ),
+        (
+            "error_code",
+            Json::from(error.and_then(ErrorCode::of).map(ErrorCode::code)),
+        ),
+        (
+            "duration_ms",
+            Json::from(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)),
+        ),
+        (
+            "counts",
+            Json::object([
+                ("rows", Json::from(stats.rows)),
+                ("applied", Json::from(stats.applied)),
+                ("rejected", Json::from(stats.rejected())),
+                ("accounts", Json::from(stats.accounts)),
+                ("locked_accounts", Json::from(stats.locked_accounts)),
+            ]),
+        ),
+        (
+            "rejects_by_reason",
+            Json::Object(
+                stats
+                    .rejects_by_reason
+                    .iter()
+                    .map(|(reason, count)| (reason.clone(), Json::from(*count)))
+                    .collect(),
+            ),
+        ),
+        ("rejects_by_code", rejects_by_code(stats)),
+        ("config", config_json(options)),
+        ("inputs", inputs_json(stats)),
+        (
+            "adjusted_amounts",
+            Json::array(stats.adjusted_amounts.iter().map(|amount| {
+                Json::object([
+                    ("client", client_json(amount.client)),
+                    ("tx", Json::from(amount.tx)),
+                    ("original", Json::from(amount.original.as_str())),
+                    ("adjusted", Json::from(amount.adjusted.as_str())),
+                ])
+            })),
+        ),
+        (
+            "opening_conflicts",
+            Json::array(stats.opening_conflicts.iter().map(|conflict| {
+                Json::object([
+                    ("client", client_json(conflict.client)),
+                    ("tx", Json::from(conflict.tx)),
+                    ("seeded", Json::from(format!("{:.4}", conflict.seeded))),
+                    ("file", Json::from(format!("{:.4}", conflict.file))),
+                    ("policy", Json::from(conflict.policy.name())),
+                ])
+            })),
+        ),
+        (
+            "memory",
+            Json::from(
+                stats
+                    .memory
+                    .as_ref()
+                    .map(|(engine, process)| memory_json(engine, process)),
+            ),
+        ),
+    ]);
+    writeln!(writer, "{}", report)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// What an output was made from, for `--output-metadata`: the engine's version, a digest of
+/// its config, the inputs and what became of their rows
+pub fn metadata_json(options: &Options, stats: &RunStats) -> Json {
+    let config = config_json(options).to_string();
+    Json::object([
+        ("engine_version", Json::from(env!("CARGO_PKG_VERSION"))),
+        ("command", Json::from(options.command.name())),
+        ("generated_at", Json::from(date::unix_now())),
+        ("inputs", inputs_json(stats)),
+        (
+            "config_sha256",
+            Json::from(sha256::hex(&Sha256::digest(config.as_bytes()))),
+        ),
+        ("rows", Json::from(stats.rows)),
+        ("applied", Json::from(stats.applied)),
+        ("rejected", Json::from(stats.rejected())),
+        ("accounts", Json::from(stats.accounts)),
+    ])
+}
+
+fn client_json(options: &Options, client: ClientId) -> Json {
+    match &options.pseudonymizer {
+        None => Json::from(client),
+        Some(pseudonymizer) => Json::from(pseudonymizer.pseudonym(client)),
+    }
+}
+
+/// The settings that decide what the engine makes of the input
+fn config_json(options: &Options) -> Json {
+    let config = &options.engine;
+    let mut test_clients = config.test_clients.iter().copied().collect::<Vec<_>>();
+    test_clients.sort_unstable();
+    let test_clients = test_clients
+        .into_iter()
+        .map(|client| client_json(options, client));
+    let scale = options.amount_format.scale();
+    Json::object([
+        (
+            "dispute_timeout_seconds",
+            Json::from(config.dispute_timeout),
+        ),
+        ("risk_threshold", Json::from(config.risk_threshold)),
+        ("dunning_grace_seconds", Json::from(config.dunning_grace)),
+        (
+            "reversal_window_seconds",
+            Json::from(config.reversal_window),
+        ),
+        (
+            "latency_budget_us",
+            Json::from(
+                config
+                    .latency_budget
+                    .map(|budget| u64::try_from(budget.as_micros()).unwrap_or(u64::MAX)),
+            ),
+        ),
+        ("prune_history", Json::from(config.pruning.is_some())),
+        (
+            "prune_after_seconds",
+            Json::from(config.pruning.and_then(|pruning| pruning.after)),
+        ),
+        ("max_accounts", Json::from(config.limits.max_accounts)),
+        (
+            "max_stored_transactions",
+            Json::from(config.limits.max_stored_transactions),
+        ),
+        ("max_rows", Json::from(options.max_rows)),
+        ("atomic", Json::from(options.atomic)),
+        ("rules", Json::from(config.rules.len())),
+        ("fee_account", Json::from(config.fees.account())),
+        ("test_clients", Json::array(test_clients)),
+        ("test_tag", Json::from(config.test_tag.clone())),
+        ("partitions", Json::from(options.partitions)),
+        ("pseudonymized", Json::from(options.pseudonymizer.is_some())),
+        ("scale", Json::from(scale.map(|scale| scale.decimals))),
+        (
+            "scale_policy",
+            Json::from(scale.map(|scale| match scale.policy {
+                ScalePolicy::Reject => "reject",
+                ScalePolicy::Truncate => "truncate",
+                ScalePolicy::Round => "round",
+            })),
+        ),
+        ("opening_policy", Json::from(config.opening_policy.name())),
+        (
+            "review_lock_allows",
+            Json::array(config.review_lock.types().map(Json::from)),
+        ),
+        ("compat", Json::from(config.compat.name())),
+    ])
+}
+
+fn inputs_json(stats: &RunStats) -> Json {
+    Json::array(stats.inputs.iter().map(|input| {
+        Json::object([
+            ("source", Json::from(input.source.as_str())),
+            ("bytes", Json::from(input.bytes)),
+            ("sha256", Json::from(input.sha256.as_str())),
+        ])
+    }))
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+
+use crate::json::Json;
+use crate::transaction::ClientId;
+
+/// Clients whose transactions are stopped before the rules see them, e.g. from a sanctions
+/// list, read from a file with one client per line:
+/// ```text
+/// # reject or hold, reject if there's no action
+/// action hold
+/// 17
+/// 42
+/// ```
+/// Every transaction of a listed client is screened, disputes and resolves too
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Blocklist {
+    clients: HashSet<ClientId>,
+    action: ScreeningAction,
+}
+
+/// What happens to the transactions of a listed client
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScreeningAction {
+    /// Rejected as `blocked`
+    #[default]
+    Reject,
+    /// Queued for review like a risky transaction, someone decides whether it's applied
+    Hold,
+}
+
+impl ScreeningAction {
+    pub const fn name(self) -> &'static str {
+        match self {
+            ScreeningAction::Reject => "reject",
+            ScreeningAction::Hold => "hold",
+        }
+    }
+}
+
+/// A transaction the blocklist stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screening {
+    /// The engine's time
+    pub at: u64,
+    pub client: ClientId,
+    pub tx: u32,
+    pub kind: String,
+    pub action: ScreeningAction,
+}
+
+/// Told about every transaction the blocklist stops, register with
+/// `TransactionEngine::register_screening_observer`. Called in the middle of processing,
+/// like `AlertObserver`. A screening it fails to record goes to the `DiagnosticsObserver`s
+pub trait ScreeningObserver: Send + Sync {
+    fn screened(&self, screening: &Screening) -> Result<()>;
+}
+
+impl Blocklist {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut blocklist = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["action", "reject"] => blocklist.action = ScreeningAction::Reject,
+                ["action", "hold"] => blocklist.action = ScreeningAction::Hold,
+                [client] => {
+                    let client = client.parse().with_context(|| {
+                        format!("Invalid client {} on line {}", client, index + 1)
+                    })?;
+                    blocklist.clients.insert(client);
+                }
+                _ => bail!("Invalid blocklist line {}: {}", index + 1, line),
+            }
+        }
+        Ok(blocklist)
+    }
+
+    /// What to do with a transaction of the client, `None` if they aren't listed
+    pub fn screen(&self, client: ClientId) -> Option<ScreeningAction> {
+        self.clients.contains(&client).then_some(self.action)
+    }
+
+    pub const fn action(&self) -> ScreeningAction {
+        self.action
+    }
+
+    /// The listed clients, in no particular order
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+/// Appends every screening to a file as a line of JSON, for compliance to go through:
+/// `{"at":1700000000,"client":17,"tx":3,"type":"withdrawal","action":"hold"}`
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the audit log {}", path))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl ScreeningObserver for AuditLog {
+    fn screened(&self, screening: &Screening) -> Result<()> {
+        let line = Json::object([
+            ("at", Json::from(screening.at)),
+            ("client", Json::from(screening.client)),
+            ("tx", Json::from(screening.tx)),
+            ("type", Json::from(screening.kind.as_str())),
+            ("action", Json::from(screening.action.name())),
+        ]);
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(file, "{}", line).context("Failed to write to the audit log")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::diagnostics::{DiagnosticsObserver, UnrecordedScreening};
+    use crate::transaction::Transaction::{Deposit, Withdrawal};
+    use crate::transaction_engine::{TransactionEngine, TransactionError};
+
+    struct Collect(Arc<Mutex<Vec<Screening>>>);
+
+    impl ScreeningObserver for Collect {
+        fn screened(&self, screening: &Screening) -> Result<()> {
+            self.0.lock().unwrap().push(screening.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_parse_the_blocklist() {
+        let blocklist = Blocklist::parse("# sanctions\naction hold\n17\n\n42\n").unwrap();
+        assert_eq!(blocklist.screen(17), Some(ScreeningAction::Hold));
+        assert_eq!(blocklist.screen(1), None);
+        assert_eq!(blocklist.len(), 2);
+        assert_eq!(
+            Blocklist::parse("17").unwrap().action(),
+            ScreeningAction::Reject
+        );
+        assert!(Blocklist::parse("action ignore").is_err());
+        assert!(Blocklist::parse("client 17").is_err());
+        assert!(Blocklist::parse("-1").is_err());
+
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            blocklist: Blocklist::parse("2").unwrap(),
+            ..EngineConfig::default()
+        });
+        let screened = Arc::new(Mutex::new(vec![]));
+        engine.register_screening_observer(Box::new(Collect(Arc::clone(&screened))));
+        let deposit = |client, tx| Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        assert_eq!(engine.process(deposit(1, 1)), Ok(()));
+        assert_eq!(
+            engine.process(deposit(2, 2)),
+            Err(TransactionError::Blocked)
+        );
+
+        let mut config = engine.config().clone();
+        config.blocklist = blocklist;
+        engine.reconfigure(config);
+        assert_eq!(
+            engine.process(deposit(17, 3)),
+            Err(TransactionError::HeldForReview)
+        );
+        assert_eq!(engine.process(deposit(2, 4)), Ok(()));
+        assert_eq!(engine.pending_review()[0].transaction.tx(), 3);
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 42,
+                tx: 5,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            }),
+            Err(TransactionError::HeldForReview)
+        );
+        let screened = screened.lock().unwrap();
+        let screened = screened
+            .iter()
+            .map(|screening| (screening.client, screening.kind.as_str(), screening.action))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            screened,
+            [
+                (2, "deposit", ScreeningAction::Reject),
+                (17, "deposit", ScreeningAction::Hold),
+                (42, "withdrawal", ScreeningAction::Hold)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_the_screenings_that_could_not_be_recorded() {
+        struct Full;
+        impl ScreeningObserver for Full {
+            fn screened(&self, _screening: &Screening) -> Result<()> {
+                bail!("No space left on device")
+            }
+        }
+        struct Unrecorded(Arc<Mutex<Vec<UnrecordedScreening>>>);
+        impl DiagnosticsObserver for Unrecorded {
+            fn screening_unrecorded(&self, unrecorded: &UnrecordedScreening) {
+                self.0.lock().unwrap().push(unrecorded.clone());
+            }
+        }
+
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            blocklist: Blocklist::parse("2").unwrap(),
+            ..EngineConfig::default()
+        });
+        let unrecorded = Arc::new(Mutex::new(vec![]));
+        engine.register_screening_observer(Box::new(Full));
+        engine.register_diagnostics_observer(Box::new(Unrecorded(Arc::clone(&unrecorded))));
+        // stopped whether or not that could be recorded
+        assert_eq!(
+            engine.process(Deposit {
+                client: 2,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            }),
+            Err(TransactionError::Blocked)
+        );
+        let unrecorded = unrecorded.lock().unwrap();
+        assert_eq!(unrecorded.len(), 1);
+        assert_eq!(unrecorded[0].screening.tx, 1);
+        assert_eq!(unrecorded[0].error, "No space left on device");
+    }
+}
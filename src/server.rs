@@ -0,0 +1,2059 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use bigdecimal::{BigDecimal, Zero};
+use csv::StringRecord;
+use playground::amount::AmountFormat;
+use playground::approval::PendingWithdrawal;
+use playground::chaos::{self, Seam, Verdict};
+use playground::config::EngineConfig;
+use playground::date;
+use playground::dedup::Deduplicator;
+use playground::dispute::CaseStatus;
+use playground::errors::ErrorCode;
+use playground::event_log::EventLog;
+use playground::hold::{HoldAction, HoldReason};
+use playground::idempotency::{self, IdempotencyStore, Lookup, StoredResponse};
+use playground::json::Json;
+use playground::memory::ProcessMemory;
+use playground::msgpack;
+use playground::notes::Note;
+use playground::protobuf;
+use playground::risk::PendingTransaction;
+use playground::screening::Blocklist;
+use playground::session;
+use playground::tags::AccountTags;
+use playground::trace::{Span, TraceContext};
+use playground::transaction::{self, ClientId, RowParser, Transaction};
+use playground::transaction_engine::{
+    AccountView, FoundTransaction, TransactionEngine, TransactionError,
+};
+
+use crate::cli::ServeOptions;
+use crate::otlp::OtlpExporter;
+use crate::output;
+use crate::run_report;
+use crate::webhook::WebhookAlerts;
+
+/// Serves the HTTP API until the process is stopped.
+/// Requests are handled one at a time on this thread, which keeps the engine free of locks
+/// and is plenty for an internal admin/ingest API
+pub fn serve(options: &ServeOptions, mut transaction_engine: TransactionEngine) -> Result<()> {
+    let mut tracing = Tracing {
+        exporter: match &options.otlp_endpoint {
+            None => None,
+            Some(endpoint) => Some(OtlpExporter::start(endpoint)?),
+        },
+        ..Tracing::default()
+    };
+    if let Some(url) = &options.alert_webhook {
+        let alerts = WebhookAlerts::start(url)?;
+        tracing.alerts = Some(alerts.trace());
+        transaction_engine.register_alert_observer(Box::new(alerts));
+    }
+    let dead_letters = match &options.dead_letter {
+        None => None,
+        Some(destination) => Some(output::open_log(destination)?),
+    };
+    let listener = TcpListener::bind(&options.listen)
+        .with_context(|| format!("Failed to bind {}", options.listen))?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    let settings = options.config.as_ref().map(|path| Settings {
+        path: path.clone(),
+        modified: None,
+        base: transaction_engine.config().clone(),
+    });
+    let event_log = match &options.event_log {
+        None => None,
+        Some(dir) => Some(EventLog::open(
+            dir,
+            &transaction_engine,
+            options.compact_every,
+            options.event_log_retention,
+            date::unix_now(),
+        )?),
+    };
+    let mut server = Server {
+        transaction_engine,
+        deduplicator: options.dedup.map(Deduplicator::new),
+        dead_letters,
+        amount_format: options.amount_format,
+        settings,
+        state: options.ha_state.clone(),
+        event_log,
+        idempotency: match &options.idempotency_keys {
+            None => None,
+            Some(path) => {
+                let store = IdempotencyStore::open(
+                    path,
+                    options.idempotency_retention,
+                    date::unix_now(),
+                )
+                .with_context(|| format!("Failed to load the idempotency keys of {}", path))?;
+                for line in store.skipped() {
+                    eprintln!("Skipped the idempotency key on line {} of {}", line, path);
+                }
+                Some(store)
+            }
+        },
+        tracing,
+    };
+    if let Some(path) = &options.config {
+        server
+            .reload(true)
+            .with_context(|| format!("Failed to apply {}", path))?;
+    }
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        // checked as requests come in, changes don't matter to an idle server
+        if let Some(path) = server
+            .settings
+            .as_ref()
+            .map(|settings| settings.path.clone())
+        {
+            match server.reload(false) {
+                Ok(true) => eprintln!("Reloaded {}", path),
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to reload {}, keeping the config: {:#}", path, e),
+            }
+        }
+        let timeout = Some(options.request_timeout);
+        if let Err(e) = stream
+            .set_read_timeout(timeout)
+            .and_then(|()| stream.set_write_timeout(timeout))
+        {
+            eprintln!("Failed to set the timeouts of a connection: {}", e);
+            continue;
+        }
+        let request = Request::read(&mut stream, options.max_body_bytes);
+        let injected = match request {
+            Ok(_) => chaos::inject(Seam::Source),
+            Err(_) => Ok(Verdict::Deliver),
+        };
+        let result = match (request, injected) {
+            (_, Ok(Verdict::Drop)) => continue,
+            (_, Err(e)) => {
+                Response::error(503, &e.to_string()).write(&mut stream, BodyFormat::Json)
+            }
+            (Ok(request), _) => match request.path.strip_prefix("/export/") {
+                Some(dataset) if request.method == "GET" => {
+                    server.export(dataset, &request, &mut stream)
+                }
+                _ => {
+                    // a request that fails is answered with 500 and the server carries on
+                    let response = server.handle_traced(&request).unwrap_or_else(|e| {
+                        eprintln!(
+                            "Failed to handle {} {}: {:#}",
+                            request.method, request.path, e
+                        );
+                        Response::error(500, "Internal error")
+                    });
+                    // before answering, so whatever the client was told survives a takeover.
+                    // The only fatal error, a leader that can't hand over has to stop
+                    if request.method != "GET" {
+                        server.write_state()?;
+                    }
+                    response.write(&mut stream, BodyFormat::accepted(&request))
+                }
+            },
+            (Err(e), _) => {
+                let status = if e.is::<BodyTooLarge>() {
+                    413
+                } else if e.is::<HeadersTooLarge>() {
+                    431
+                } else {
+                    400
+                };
+                Response::error(status, &e.to_string())
+            }
+            .write(&mut stream, BodyFormat::Json),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+struct Server {
+    transaction_engine: TransactionEngine,
+    /// Drops transactions that are posted more than once, with `--dedup-window`
+    deduplicator: Option<Deduplicator>,
+    /// Gets a JSON line for every row that was rejected or couldn't be parsed, with `--dead-letter`
+    dead_letters: Option<Box<dyn Write>>,
+    amount_format: AmountFormat,
+    /// With `--config`
+    settings: Option<Settings>,
+    /// With `--ha-state`, written after every request that could have changed the engine
+    state: Option<String>,
+    /// With `--event-log`, appended to after every request that could have changed the engine
+    event_log: Option<EventLog>,
+    /// With `--idempotency-keys`, the responses to requests with an `Idempotency-Key`
+    idempotency: Option<IdempotencyStore>,
+    tracing: Tracing,
+}
+
+/// The trace of the request being handled, continued from its `traceparent` header if it has
+/// one. Its spans are exported with `--otlp-endpoint`
+#[derive(Default)]
+struct Tracing {
+    exporter: Option<OtlpExporter>,
+    /// The alert webhook's, alerts fired by the request are posted as part of its trace
+    alerts: Option<Arc<Mutex<Option<TraceContext>>>>,
+    request: Option<Span>,
+    /// Of the transactions the request applied
+    spans: Vec<Span>,
+}
+
+impl Tracing {
+    /// A span below the request's, only recorded when they're exported
+    fn span(&self, name: &str) -> Option<Span> {
+        match (&self.exporter, &self.request) {
+            (Some(_), Some(request)) => Some(Span::start(
+                name,
+                request.context.child(),
+                Some(&request.context),
+            )),
+            _ => None,
+        }
+    }
+
+    fn trace_id(&self) -> Option<String> {
+        self.request
+            .as_ref()
+            .map(|request| request.context.trace_id())
+    }
+}
+
+/// The settings file of a server and the config from the command line it's applied on top of
+struct Settings {
+    path: String,
+    /// When the file was last applied, or failed to be
+    modified: Option<SystemTime>,
+    base: EngineConfig,
+}
+
+impl Server {
+    /// Replaces the `--ha-state` file in one go, a standby taking over never sees half of it,
+    /// or appends the changes to the `--event-log`. Failing to is fatal, the leader would
+    /// otherwise keep accepting changes it can't hand over
+    fn write_state(&mut self) -> Result<()> {
+        if let Some(event_log) = &mut self.event_log {
+            return event_log.append(&self.transaction_engine, date::unix_now());
+        }
+        let path = match &self.state {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let temporary = format!("{}.tmp", path);
+        let mut file = fs::File::create(&temporary)
+            .with_context(|| format!("Failed to write the state to {}", temporary))?;
+        file.write_all(&self.transaction_engine.snapshot())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Failed to replace the state in {}", path))?;
+        Ok(())
+    }
+
+    /// Applies the settings file again if it changed since the last time or if `force`d,
+    /// returns whether it was. An invalid file leaves the config as it was
+    fn reload(&mut self, force: bool) -> Result<bool> {
+        let settings = match &mut self.settings {
+            None => bail!("The server wasn't started with a --config"),
+            Some(settings) => settings,
+        };
+        let modified = fs::metadata(&settings.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if !force && modified == settings.modified {
+            return Ok(false);
+        }
+        // so a broken file is reported once rather than on every request
+        settings.modified = modified;
+        let text = fs::read_to_string(&settings.path)
+            .with_context(|| format!("Could not read {}", settings.path))?;
+        let config = settings.base.with_settings(&text)?;
+        self.transaction_engine.reconfigure(config);
+        Ok(true)
+    }
+
+    /// `handle_once` in a span of the request, the response says which with a `traceresponse`
+    /// header. An invalid `traceparent` is ignored and the request starts a trace of its own
+    fn handle_traced(&mut self, request: &Request) -> Result<Response> {
+        let parent = request
+            .header("traceparent")
+            .and_then(|traceparent| TraceContext::parse(traceparent).ok());
+        let context = parent.map_or_else(TraceContext::root, |parent| parent.child());
+        let mut span = Span::start(
+            format!("{} {}", request.method, request.path),
+            context,
+            parent.as_ref(),
+        );
+        span.attributes
+            .push(("http.request.method", Json::from(request.method.as_str())));
+        span.attributes
+            .push(("url.path", Json::from(request.path.as_str())));
+        self.tracing.request = Some(span);
+        self.set_alert_trace(Some(context));
+        let response = self.handle_once(request);
+        self.set_alert_trace(None);
+
+        let mut span = self
+            .tracing
+            .request
+            .take()
+            .expect("the request's span is set");
+        let mut response = response?;
+        span.attributes
+            .push(("http.response.status_code", Json::from(response.status)));
+        if response.status >= 500 {
+            span.error = Some(response.body.to_string());
+        }
+        span.finish();
+        response
+            .headers
+            .push(("traceresponse", context.traceparent()));
+        if let Some(exporter) = &self.tracing.exporter {
+            let mut spans = std::mem::take(&mut self.tracing.spans);
+            spans.insert(0, span);
+            exporter.export(spans);
+        }
+        Ok(response)
+    }
+
+    fn set_alert_trace(&self, context: Option<TraceContext>) {
+        if let Some(Ok(mut trace)) = self.tracing.alerts.as_ref().map(|trace| trace.lock()) {
+            *trace = context;
+        }
+    }
+
+    /// A request with an `Idempotency-Key` that was answered before gets the same answer
+    /// again, without being handled twice. The answer is stored before it's sent, failing
+    /// to is fatal like failing to write the state
+    fn handle_once(&mut self, request: &Request) -> Result<Response> {
+        let (store, key) = match (&mut self.idempotency, request.header("idempotency-key")) {
+            (Some(store), Some(key)) if request.method != "GET" => (store, key),
+            _ => return Ok(self.handle(request)),
+        };
+        let now = date::unix_now();
+        let fingerprint = idempotency::fingerprint(
+            &request.method,
+            &request.path,
+            &request.query,
+            &request.body,
+        );
+        match store.lookup(key, &fingerprint, now) {
+            Lookup::Replay(stored) => {
+                let mut response = Response::json(stored.status, stored.body.clone());
+                response
+                    .headers
+                    .push(("Idempotent-Replayed", "true".to_string()));
+                return Ok(response);
+            }
+            Lookup::Mismatch => {
+                return Ok(Response::error(
+                    422,
+                    "The Idempotency-Key was already used for a different request",
+                ))
+            }
+            Lookup::New => {}
+        }
+        let response = self.handle(request);
+        if let Some(store) = &mut self.idempotency {
+            store.store(
+                key,
+                StoredResponse {
+                    fingerprint,
+                    status: response.status,
+                    body: response.body.clone(),
+                    at: now,
+                },
+            )?;
+        }
+        Ok(response)
+    }
+
+    fn handle(&mut self, request: &Request) -> Response {
+        let segments = request
+            .path
+            .trim_matches('/')
+            .split('/')
+            .collect::<Vec<_>>();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["accounts"]) => match AccountFilter::of(request) {
+                Ok(filter) => self.list_accounts(&filter),
+                Err(e) => Response::error(400, &e.to_string()),
+            },
+            ("GET", ["accounts", client]) => match client.parse::<ClientId>() {
+                Err(_) => Response::error(400, "Invalid client id"),
+                // test clients are left out of the listing but can still look themselves up
+                Ok(client) => match self.transaction_engine.account(client) {
+                    None => Response::error(404, "No such account"),
+                    Some(account) => {
+                        Response::json(200, account_json(account, self.transaction_engine.tags()))
+                    }
+                },
+            },
+            ("GET", ["accounts", client, "notes"]) => match client.parse::<ClientId>() {
+                Err(_) => Response::error(400, "Invalid client id"),
+                Ok(client) => match self.transaction_engine.account(client) {
+                    None => Response::error(404, "No such account"),
+                    Some(account) => {
+                        Response::json(200, Json::array(account.notes().iter().map(note_json)))
+                    }
+                },
+            },
+            ("GET", ["accounts", client, "holds"]) => match client.parse::<ClientId>() {
+                Err(_) => Response::error(400, "Invalid client id"),
+                Ok(client) => match self.transaction_engine.account(client) {
+                    None => Response::error(404, "No such account"),
+                    Some(account) => {
+                        Response::json(200, Json::array(account.holds().iter().map(hold_json)))
+                    }
+                },
+            },
+            ("POST", ["accounts", client, action @ ("hold" | "release")]) => {
+                let client = match client.parse::<ClientId>() {
+                    Err(_) => return Response::error(400, "Invalid client id"),
+                    Ok(client) => client,
+                };
+                let amount = match request.query("amount").map(BigDecimal::from_str) {
+                    Some(Ok(amount)) => amount,
+                    _ => return Response::error(400, "Expected an amount"),
+                };
+                let reason = match request.query("reason").map(HoldReason::from_str) {
+                    Some(Ok(reason)) => reason,
+                    Some(Err(e)) => return Response::error(400, &e.to_string()),
+                    None => return Response::error(400, "Expected a reason"),
+                };
+                let author = request.query("author").map(str::to_string);
+                let result = match *action {
+                    "hold" => self.transaction_engine.hold(client, amount, reason, author),
+                    _ => self
+                        .transaction_engine
+                        .release(client, amount, reason, author),
+                };
+                match result {
+                    Ok(()) => Response::json(200, Json::object([("status", Json::from("ok"))])),
+                    Err(TransactionError::UnknownAccount) => {
+                        Response::error(404, "No such account")
+                    }
+                    Err(error) => Response::rejected(error),
+                }
+            }
+            ("POST", ["accounts", client, "notes"]) => {
+                let client = match client.parse::<ClientId>() {
+                    Err(_) => return Response::error(400, "Invalid client id"),
+                    Ok(client) => client,
+                };
+                let text = match std::str::from_utf8(&request.body).map(str::trim) {
+                    Ok(text) if !text.is_empty() => text,
+                    _ => return Response::error(400, "Expected the text of the note as the body"),
+                };
+                let author = request.query("author").map(str::to_string);
+                match self.transaction_engine.add_note(client, author, text) {
+                    Ok(()) => Response::json(200, Json::object([("status", Json::from("ok"))])),
+                    Err(TransactionError::UnknownAccount) => {
+                        Response::error(404, "No such account")
+                    }
+                    Err(error) => Response::rejected(error),
+                }
+            }
+            (method @ ("PUT" | "DELETE"), ["accounts", client, "tags", tag]) => {
+                let client = match client.parse::<ClientId>() {
+                    Err(_) => return Response::error(400, "Invalid client id"),
+                    Ok(client) => client,
+                };
+                let tag = percent_decode(tag);
+                let tags = self.transaction_engine.tags_mut();
+                if method == "PUT" {
+                    tags.add(client, tag);
+                } else if !tags.remove(client, &tag) {
+                    return Response::error(404, "No such tag");
+                }
+                Response::json(200, Json::object([("status", Json::from("ok"))]))
+            }
+            ("POST", ["accounts", client, action @ ("lock" | "unlock" | "close" | "adjust")]) => {
+                let client = match client.parse::<ClientId>() {
+                    Err(_) => return Response::error(400, "Invalid client id"),
+                    Ok(client) => client,
+                };
+                let result = match *action {
+                    "lock" => self
+                        .transaction_engine
+                        .lock(client, request.query("reason").unwrap_or_default()),
+                    "unlock" => self.transaction_engine.unlock(client),
+                    "close" => self.transaction_engine.close(client),
+                    _ => match request.query("amount").map(BigDecimal::from_str) {
+                        Some(Ok(amount)) => self.transaction_engine.adjust(client, amount),
+                        _ => return Response::error(400, "Expected an amount to adjust by"),
+                    },
+                };
+                match result {
+                    Ok(()) => Response::json(200, Json::object([("status", Json::from("ok"))])),
+                    Err(TransactionError::UnknownAccount) => {
+                        Response::error(404, "No such account")
+                    }
+                    Err(error) => Response::rejected(error),
+                }
+            }
+            ("GET", ["stats"]) => Response::json(200, self.stats()),
+            ("GET", ["config"]) => {
+                let config = self.transaction_engine.config();
+                Response::json(
+                    200,
+                    Json::object([
+                        ("risk_threshold", Json::from(config.risk_threshold)),
+                        ("dispute_timeout", Json::from(config.dispute_timeout)),
+                        ("rules", Json::from(config.rules.len())),
+                        ("blocklist", Json::from(config.blocklist.len())),
+                        ("alerts", Json::from(config.alerts.len())),
+                        (
+                            "latency_budget_us",
+                            Json::from(config.latency_budget.map(|budget| {
+                                u64::try_from(budget.as_micros()).unwrap_or(u64::MAX)
+                            })),
+                        ),
+                        ("max_accounts", Json::from(config.limits.max_accounts)),
+                        (
+                            "max_stored_transactions",
+                            Json::from(config.limits.max_stored_transactions),
+                        ),
+                    ]),
+                )
+            }
+            ("GET", ["blocklist"]) => {
+                let blocklist = &self.transaction_engine.config().blocklist;
+                let mut clients = blocklist.clients().collect::<Vec<_>>();
+                clients.sort_unstable();
+                Response::json(
+                    200,
+                    Json::object([
+                        ("action", Json::from(blocklist.action().name())),
+                        ("clients", Json::array(clients.into_iter().map(Json::from))),
+                    ]),
+                )
+            }
+            // replaces the list until the settings file next changes, if it has a blocklist
+            ("PUT", ["blocklist"]) => {
+                let blocklist = match std::str::from_utf8(&request.body)
+                    .map_err(anyhow::Error::from)
+                    .and_then(Blocklist::parse)
+                {
+                    Err(e) => return Response::error(400, &format!("{:#}", e)),
+                    Ok(blocklist) => blocklist,
+                };
+                if let Some(settings) = &mut self.settings {
+                    settings.base.blocklist = blocklist.clone();
+                }
+                let mut config = self.transaction_engine.config().clone();
+                config.blocklist = blocklist;
+                self.transaction_engine.reconfigure(config);
+                Response::json(200, Json::object([("status", Json::from("ok"))]))
+            }
+            ("POST", ["config", "reload"]) => match self.reload(true) {
+                Ok(_) => Response::json(200, Json::object([("status", Json::from("ok"))])),
+                Err(e) => Response::error(400, &format!("{:#}", e)),
+            },
+            ("POST", ["transactions"]) => {
+                let content_type = request.header("content-type").map(media_type);
+                let source = request
+                    .query("source")
+                    .or_else(|| request.header("x-source"));
+                self.transaction_engine.set_source(source);
+                let response = self.submit(&request.body, content_type);
+                self.transaction_engine.set_source(None);
+                response
+            }
+            ("GET", ["transactions", tx]) => match tx.parse::<u32>() {
+                Err(_) => Response::error(400, "Invalid tx id"),
+                Ok(tx) => match self.transaction_engine.find_transaction(tx) {
+                    None => Response::error(404, "No such transaction"),
+                    Some(found) => Response::json(200, found_json(&found)),
+                },
+            },
+            ("GET", ["metrics"]) => Response::json(
+                200,
+                Json::object([(
+                    "deduplicated",
+                    Json::from(self.deduplicator.as_ref().map(Deduplicator::deduplicated)),
+                )]),
+            ),
+            ("GET", ["withdrawals"]) => {
+                let mut pending = self
+                    .transaction_engine
+                    .pending_withdrawals()
+                    .collect::<Vec<_>>();
+                pending.sort_by_key(|pending| (pending.requested_at, pending.client, pending.tx));
+                Response::json(200, Json::array(pending.into_iter().map(withdrawal_json)))
+            }
+            // the same as posting an approve or deny transaction
+            ("POST", ["withdrawals", client, tx, decision @ ("approve" | "deny")]) => {
+                let (client, tx) = match (client.parse::<ClientId>(), tx.parse::<u32>()) {
+                    (Ok(client), Ok(tx)) => (client, tx),
+                    _ => return Response::error(400, "Invalid client or tx id"),
+                };
+                let transaction = if *decision == "approve" {
+                    Transaction::Approve { client, tx }
+                } else {
+                    Transaction::Deny { client, tx }
+                };
+                match self.transaction_engine.process(transaction) {
+                    Ok(()) => Response::json(200, Json::object([("status", Json::from("ok"))])),
+                    Err(TransactionError::NotPendingApproval) => {
+                        Response::error(404, "No such withdrawal waiting for approval")
+                    }
+                    Err(error) => Response::rejected(error),
+                }
+            }
+            ("GET", ["review"]) => Response::json(
+                200,
+                Json::array(
+                    self.transaction_engine
+                        .pending_review()
+                        .iter()
+                        .map(pending_json),
+                ),
+            ),
+            ("POST", ["review", client, tx, decision @ ("approve" | "deny")]) => {
+                let (client, tx) = match (client.parse::<ClientId>(), tx.parse::<u32>()) {
+                    (Ok(client), Ok(tx)) => (client, tx),
+                    _ => return Response::error(400, "Invalid client or tx id"),
+                };
+                let result = if *decision == "approve" {
+                    self.transaction_engine.approve(client, tx)
+                } else {
+                    let reason = request.query("reason").unwrap_or_default();
+                    self.transaction_engine.deny(client, tx, reason)
+                };
+                match result {
+                    Ok(()) => Response::json(200, Json::object([("status", Json::from("ok"))])),
+                    Err(TransactionError::UnknownTransaction) => {
+                        Response::error(404, "No such transaction pending review")
+                    }
+                    Err(error) => Response::rejected(error),
+                }
+            }
+            _ => Response::error(404, "Not found"),
+        }
+    }
+
+    /// A page of the accounts that match, in client order. There's a next page if the
+    /// response has a cursor header, it's passed as `cursor` to get it
+    fn list_accounts(&self, filter: &AccountFilter) -> Response {
+        let tags = self.transaction_engine.tags();
+        let mut accounts = self
+            .transaction_engine
+            .account_views()
+            .filter(|account| filter.matches(account, tags))
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(AccountView::client);
+        let next_cursor = (accounts.len() > filter.limit)
+            .then(|| accounts[filter.limit - 1].client().to_string());
+        accounts.truncate(filter.limit);
+        let mut response = Response::json(
+            200,
+            Json::array(accounts.iter().map(|account| account_json(*account, tags))),
+        );
+        if let Some(cursor) = next_cursor {
+            response.headers.push(("X-Next-Cursor", cursor));
+        }
+        response
+    }
+
+    /// Totals over every account, for keeping an eye on a running instance
+    fn stats(&self) -> Json {
+        let engine = &self.transaction_engine;
+        let (mut accounts, mut locked) = (0_u64, 0_u64);
+        let (mut available, mut held, mut total) =
+            (BigDecimal::zero(), BigDecimal::zero(), BigDecimal::zero());
+        for account in engine.account_views() {
+            accounts += 1;
+            locked += u64::from(account.locked());
+            available += account.available();
+            // like the accounts' held, what's set aside in pockets and wallets isn't held
+            held += account.held();
+            total += account.total();
+        }
+        let open_disputes = engine
+            .cases()
+            .filter(|case| case.status() == CaseStatus::Open)
+            .count();
+        Json::object([
+            ("accounts", Json::from(accounts)),
+            ("locked", Json::from(locked)),
+            ("available", Json::from(&available)),
+            ("held", Json::from(&held)),
+            ("total", Json::from(&total)),
+            ("open_disputes", Json::from(open_disputes)),
+            ("pending_review", Json::from(engine.pending_review().len())),
+            (
+                "pending_withdrawals",
+                Json::from(engine.pending_withdrawals().count()),
+            ),
+            (
+                "memory",
+                run_report::memory_json(&engine.memory_stats(), &ProcessMemory::current()),
+            ),
+        ])
+    }
+
+    /// Writes every account or stored transaction as it goes, in chunks, rather than building
+    /// the whole body first. Rejected transactions are stored and exported too, so replaying an
+    /// export gives the same accounts. An error halfway through can't change the status anymore,
+    /// the connection is closed without the last chunk so the client can tell it's incomplete
+    fn export(&self, dataset: &str, request: &Request, stream: &mut impl Write) -> Result<()> {
+        if dataset == "changes" {
+            return self.changes(request, stream);
+        }
+        let format = match request.query("format") {
+            None | Some("csv") => StreamFormat::Csv,
+            Some("ndjson") => StreamFormat::Ndjson,
+            Some(other) => {
+                let message = format!("Unknown format {}, expected csv or ndjson", other);
+                return Response::error(400, &message).write(stream, BodyFormat::Json);
+            }
+        };
+        if !matches!(dataset, "accounts" | "transactions") {
+            return Response::error(404, "Not found").write(stream, BodyFormat::Json);
+        }
+        write_head(stream, 200, format.content_type(), None, &[])?;
+        let mut body = ChunkedWriter::new(stream);
+        let tags = self.transaction_engine.tags();
+        let mut accounts = self.transaction_engine.account_views().collect::<Vec<_>>();
+        accounts.sort_by_key(AccountView::client);
+        match (dataset, format) {
+            ("accounts", StreamFormat::Csv) => {
+                let mut writer = csv::Writer::from_writer(&mut body);
+                for account in &accounts {
+                    writer.serialize(account.to_csv_account())?;
+                }
+                writer.flush()?;
+            }
+            ("accounts", StreamFormat::Ndjson) => {
+                for account in &accounts {
+                    writeln!(body, "{}", account_json(*account, tags))?;
+                }
+            }
+            (_, StreamFormat::Csv) => {
+                let mut writer = csv::Writer::from_writer(&mut body);
+                writer.write_record(["type", "client", "tx", "amount"])?;
+                for transaction in accounts.iter().flat_map(AccountView::transactions) {
+                    writer.write_record([
+                        transaction.type_name().to_string(),
+                        transaction.client().to_string(),
+                        transaction.tx().to_string(),
+                        transaction
+                            .amount()
+                            .map(|amount| format!("{:.4}", amount))
+                            .unwrap_or_default(),
+                    ])?;
+                }
+                writer.flush()?;
+            }
+            (_, StreamFormat::Ndjson) => {
+                for transaction in accounts.iter().flat_map(AccountView::transactions) {
+                    writeln!(body, "{}", Json::object(transaction_json(&transaction)))?;
+                }
+            }
+        }
+        body.finish()
+    }
+
+    /// `GET /export/changes?since=<sequence>`, the engine's changes for a replica to apply,
+    /// everything without a `since`. The sequence number they go up to is in `X-Sequence`
+    fn changes(&self, request: &Request, stream: &mut impl Write) -> Result<()> {
+        let since = match request.query("since").map(str::parse::<u64>) {
+            None => 0,
+            Some(Ok(since)) => since,
+            Some(Err(_)) => {
+                return Response::error(400, "Invalid since").write(stream, BodyFormat::Json)
+            }
+        };
+        let engine = &self.transaction_engine;
+        let changes = match engine.changes_since(since) {
+            None => {
+                let message = format!(
+                    "Sequence {} is ahead of the server's {}, start over from 0",
+                    since,
+                    engine.sequence()
+                );
+                return Response::error(409, &message).write(stream, BodyFormat::Json);
+            }
+            Some(changes) => changes,
+        };
+        let sequence = ("X-Sequence", engine.sequence().to_string());
+        write_head(
+            stream,
+            200,
+            "application/octet-stream",
+            Some(changes.len()),
+            &[sequence],
+        )?;
+        stream.write_all(&changes)?;
+        Ok(())
+    }
+
+    /// The body is a transactions csv, same format as the input files, a protobuf
+    /// `TransactionBatch` or a MessagePack array of transaction maps with the csv's columns
+    /// as keys. The response has the outcome of every row
+    fn submit(&mut self, body: &[u8], content_type: Option<&str>) -> Response {
+        let (parser, records): (_, Box<dyn Iterator<Item = Result<StringRecord>>>) = if matches!(
+            content_type,
+            Some("application/x-protobuf" | "application/protobuf")
+        ) {
+            let parser = RowParser::new(protobuf::headers(), self.amount_format.without_locale());
+            (parser, Box::new(protobuf::records(body)))
+        } else if BodyFormat::of(content_type) == Some(BodyFormat::MessagePack) {
+            let transactions = match msgpack::decode(body) {
+                Ok(Json::Array(transactions)) => transactions,
+                Ok(_) => return Response::error(400, "Expected an array of transactions"),
+                Err(e) => return Response::error(400, &format!("{:#}", e)),
+            };
+            let parser = RowParser::new(protobuf::headers(), self.amount_format.without_locale());
+            (parser, Box::new(transactions.into_iter().map(map_record)))
+        } else {
+            let mut reader = transaction::csv_reader(body);
+            match reader.headers() {
+                Ok(headers) => {
+                    let parser = RowParser::new(headers.clone(), self.amount_format);
+                    let records = reader
+                        .into_records()
+                        .map(|record| Ok(record.map_err(|e| ErrorCode::MalformedRow.error(e))?));
+                    (parser, Box::new(records))
+                }
+                Err(e) => {
+                    self.dead_letter(
+                        &String::from_utf8_lossy(body),
+                        "parse",
+                        &e.to_string(),
+                        None,
+                        Some(ErrorCode::MalformedRow),
+                    );
+                    return Response::coded_error(400, &e.to_string(), ErrorCode::MalformedRow);
+                }
+            }
+        };
+        let mut results = vec![];
+        for record in records {
+            let message = record
+                .as_ref()
+                .map(|record| record.iter().collect::<Vec<_>>().join(","))
+                .unwrap_or_default();
+            let row = record.and_then(|record| parser.parse(record));
+            if let (Ok(raw), Some(deduplicator)) = (&row, &mut self.deduplicator) {
+                let now = raw.timestamp().unwrap_or_else(date::unix_now);
+                if deduplicator.is_duplicate(raw, now) {
+                    results.push(Json::object([
+                        ("client", Json::from(raw.client())),
+                        ("tx", Json::from(raw.tx())),
+                        ("status", Json::from("duplicate")),
+                    ]));
+                    continue;
+                }
+            }
+            let span = self.tracing.span("process");
+            let outcome = row.and_then(|raw| session::apply(&mut self.transaction_engine, raw));
+            if let Some(mut span) = span {
+                match &outcome {
+                    Err(e) => {
+                        span.error = Some(e.to_string());
+                        if let Some(code) = ErrorCode::of(e) {
+                            span.attributes
+                                .push(("error.code", Json::from(code.code())));
+                        }
+                    }
+                    Ok((transaction, result)) => {
+                        span.attributes.extend([
+                            ("transaction.type", Json::from(transaction.type_name())),
+                            ("transaction.client", Json::from(transaction.client())),
+                            ("transaction.tx", Json::from(transaction.tx())),
+                        ]);
+                        if let Err(error) = result {
+                            span.attributes.extend([
+                                ("transaction.reason", Json::from(error.reason())),
+                                ("error.code", Json::from(error.code().code())),
+                            ]);
+                        }
+                    }
+                }
+                span.finish();
+                self.tracing.spans.push(span);
+            }
+            match &outcome {
+                Err(e) => {
+                    self.dead_letter(&message, "parse", &e.to_string(), None, ErrorCode::of(e));
+                }
+                // held transactions aren't dead, they wait for a decision
+                Ok((_, Err(TransactionError::HeldForReview))) | Ok((_, Ok(()))) => {}
+                Ok((_, Err(error))) => {
+                    self.dead_letter(
+                        &message,
+                        "validate",
+                        &error.to_string(),
+                        Some(error.reason()),
+                        Some(error.code()),
+                    );
+                }
+            }
+            results.push(match outcome {
+                Err(e) => Json::object([
+                    ("error", Json::from(e.to_string())),
+                    ("code", Json::from(ErrorCode::of(&e).map(ErrorCode::code))),
+                ]),
+                Ok((transaction, result)) => outcome_json(
+                    &transaction,
+                    result,
+                    self.transaction_engine.applied_sequence(),
+                ),
+            });
+        }
+        Response::json(200, Json::Array(results))
+    }
+
+    /// Failing to write a dead letter is logged, it shouldn't stop the ingest
+    fn dead_letter(
+        &mut self,
+        message: &str,
+        stage: &str,
+        error: &str,
+        reason: Option<&str>,
+        code: Option<ErrorCode>,
+    ) {
+        let writer = match &mut self.dead_letters {
+            None => return,
+            Some(writer) => writer,
+        };
+        let letter = Json::object([
+            ("received_at", Json::from(date::unix_now())),
+            ("stage", Json::from(stage)),
+            ("error", Json::from(error)),
+            ("reason", Json::from(reason)),
+            ("code", Json::from(code.map(ErrorCode::code))),
+            ("message", Json::from(message)),
+            ("trace_id", Json::from(self.tracing.trace_id())),
+        ]);
+        if let Err(e) = writeln!(writer, "{}", letter).and_then(|()| writer.flush()) {
+            eprintln!("Failed to write dead letter: {}", e);
+        }
+    }
+}
+
+/// The query of `GET /accounts`
+struct AccountFilter {
+    limit: usize,
+    /// The last client of the previous page
+    cursor: Option<ClientId>,
+    tag: Option<String>,
+    locked: Option<bool>,
+    /// Of the total balance
+    min_balance: Option<BigDecimal>,
+}
+
+impl AccountFilter {
+    const DEFAULT_LIMIT: usize = 1000;
+    const MAX_LIMIT: usize = 10_000;
+
+    fn of(request: &Request) -> Result<Self> {
+        let limit = match request.query("limit") {
+            None => Self::DEFAULT_LIMIT,
+            Some(limit) => match limit.parse() {
+                Ok(limit @ 1..=Self::MAX_LIMIT) => limit,
+                _ => bail!("The limit has to be between 1 and {}", Self::MAX_LIMIT),
+            },
+        };
+        let cursor = request
+            .query("cursor")
+            .map(str::parse)
+            .transpose()
+            .context("Invalid cursor")?;
+        let locked = request
+            .query("locked")
+            .map(str::parse)
+            .transpose()
+            .context("locked has to be true or false")?;
+        let min_balance = request
+            .query("min_balance")
+            .map(BigDecimal::from_str)
+            .transpose()
+            .context("Invalid min_balance")?;
+        Ok(Self {
+            limit,
+            cursor,
+            tag: request.query("tag").map(String::from),
+            locked,
+            min_balance,
+        })
+    }
+
+    fn matches(&self, account: &AccountView<'_>, tags: &AccountTags) -> bool {
+        self.cursor.is_none_or(|cursor| account.client() > cursor)
+            && self.locked.is_none_or(|locked| account.locked() == locked)
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| tags.has(account.client(), tag))
+            && self
+                .min_balance
+                .as_ref()
+                .is_none_or(|min_balance| account.total() >= *min_balance)
+    }
+}
+
+/// The fields of a transaction map in the order of `protobuf::headers`, amounts can be
+/// strings or numbers
+fn map_record(transaction: Json) -> Result<StringRecord> {
+    if !matches!(transaction, Json::Object(_)) {
+        bail!("Expected a transaction map, got {}", transaction);
+    }
+    protobuf::headers()
+        .iter()
+        .map(|field| match transaction.get(field) {
+            None | Some(Json::Null) => Ok(String::new()),
+            Some(Json::String(value) | Json::Number(value)) => Ok(value.clone()),
+            Some(other) => bail!("Invalid {} {}", field, other),
+        })
+        .collect()
+}
+
+fn account_json(account: AccountView<'_>, tags: &AccountTags) -> Json {
+    let pockets = Json::object(
+        account
+            .pockets()
+            .iter()
+            .map(|(pocket, balance)| (pocket.as_str(), Json::from(format!("{:.4}", balance)))),
+    );
+    let wallets = Json::object(account.wallets().iter().map(|(wallet, balances)| {
+        (
+            wallet.as_str(),
+            Json::object([
+                (
+                    "available",
+                    Json::from(format!("{:.4}", balances.available)),
+                ),
+                ("held", Json::from(format!("{:.4}", balances.held))),
+            ]),
+        )
+    }));
+    let account = account.to_csv_account();
+    Json::object([
+        ("client", Json::from(account.client)),
+        ("available", Json::from(account.available.as_str())),
+        ("held", Json::from(account.held.as_str())),
+        ("total", Json::from(account.total.as_str())),
+        ("locked", Json::from(account.locked)),
+        ("pockets", pockets),
+        ("wallets", wallets),
+        (
+            "tags",
+            Json::array(tags.get(account.client).map(Json::from)),
+        ),
+    ])
+}
+
+fn transaction_json(transaction: &Transaction) -> Vec<(&'static str, Json)> {
+    vec![
+        ("type", Json::from(transaction.type_name())),
+        ("client", Json::from(transaction.client())),
+        ("tx", Json::from(transaction.tx())),
+        ("amount", Json::from(transaction.amount())),
+    ]
+}
+
+fn found_json(found: &FoundTransaction<'_>) -> Json {
+    let mut fields = transaction_json(&found.transaction);
+    fields.push(("applied", Json::from(found.applied)));
+    fields.push((
+        "dispute",
+        found.case.map_or(Json::Null, |case| {
+            Json::object([
+                ("status", Json::from(case.status().name())),
+                ("opened_at", Json::from(case.opened_at())),
+                ("closed_at", Json::from(case.closed_at())),
+            ])
+        }),
+    ));
+    Json::object(fields)
+}
+
+fn note_json(note: &Note) -> Json {
+    Json::object([
+        ("at", Json::from(note.at)),
+        ("author", Json::from(note.author.as_deref())),
+        ("text", Json::from(note.text.as_str())),
+    ])
+}
+
+fn hold_json(action: &HoldAction) -> Json {
+    Json::object([
+        ("at", Json::from(action.at)),
+        ("amount", Json::from(format!("{:.4}", action.amount))),
+        ("reason", Json::from(action.reason.code())),
+        ("author", Json::from(action.author.as_deref())),
+    ])
+}
+
+fn withdrawal_json(pending: &PendingWithdrawal) -> Json {
+    Json::object([
+        ("client", Json::from(pending.client)),
+        ("tx", Json::from(pending.tx)),
+        ("amount", Json::from(format!("{:.4}", pending.amount))),
+        ("requested_at", Json::from(pending.requested_at)),
+    ])
+}
+
+fn pending_json(pending: &PendingTransaction) -> Json {
+    let mut fields = transaction_json(&pending.transaction);
+    fields.push(("score", Json::from(pending.score)));
+    Json::object(fields)
+}
+
+/// `sequence` is the engine's `applied_sequence` right after the transaction, its own if it
+/// was applied
+fn outcome_json(
+    transaction: &Transaction,
+    result: Result<(), TransactionError>,
+    sequence: u64,
+) -> Json {
+    let mut fields = transaction_json(transaction);
+    match result {
+        Ok(()) => {
+            fields.push(("status", Json::from("applied")));
+            fields.push(("sequence", Json::from(sequence)));
+        }
+        Err(error) => {
+            fields.push(("status", Json::from("rejected")));
+            fields.push(("reason", Json::from(error.reason())));
+            fields.push(("code", Json::from(error.code().code())));
+        }
+    }
+    Json::object(fields)
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// A request whose Content-Length is over `--max-body-bytes`
+#[derive(Debug)]
+struct BodyTooLarge {
+    length: usize,
+    limit: usize,
+}
+
+impl std::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The body is {} bytes, at most {} are accepted",
+            self.length, self.limit
+        )
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// The longest request line or header line that's read, in bytes
+const MAX_LINE: usize = 8 * 1024;
+/// How many headers a request can have
+const MAX_HEADERS: usize = 100;
+
+/// A request line or header that's over `MAX_LINE`, or more headers than `MAX_HEADERS`
+#[derive(Debug)]
+struct HeadersTooLarge(String);
+
+impl std::fmt::Display for HeadersTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for HeadersTooLarge {}
+
+/// Reads a line of at most `MAX_LINE` bytes, a longer one isn't read to its end
+fn read_line(reader: &mut impl BufRead, what: &str) -> Result<String> {
+    let mut line = String::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE as u64 + 1)
+        .read_line(&mut line)?;
+    if read > MAX_LINE {
+        bail!(HeadersTooLarge(format!(
+            "The {} is over {} bytes",
+            what, MAX_LINE
+        )));
+    }
+    Ok(line)
+}
+
+impl Request {
+    /// A body longer than `max_body` isn't read, it fails with `BodyTooLarge`. Neither are lines
+    /// over `MAX_LINE` or more than `MAX_HEADERS` headers, they fail with `HeadersTooLarge`
+    fn read(stream: &mut impl Read, max_body: usize) -> Result<Self> {
+        let mut reader = BufReader::new(stream);
+        let line = read_line(&mut reader, "request line")?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+            _ => bail!("Malformed request line"),
+        };
+
+        let mut headers = vec![];
+        loop {
+            let line = read_line(&mut reader, "header")?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                bail!(HeadersTooLarge(format!(
+                    "There are more than {} headers",
+                    MAX_HEADERS
+                )));
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Self::new(&method, &target, headers, vec![]);
+        let length = match request.header("content-length") {
+            None => 0,
+            Some(length) => length.parse::<usize>().context("Invalid Content-Length")?,
+        };
+        if length > max_body {
+            bail!(BodyTooLarge {
+                length,
+                limit: max_body
+            });
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = body;
+        Ok(request)
+    }
+
+    fn new(method: &str, target: &str, headers: Vec<(String, String)>, body: Vec<u8>) -> Self {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect();
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            headers,
+            body,
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What `/export/...` writes a row as
+#[derive(Clone, Copy)]
+enum StreamFormat {
+    Csv,
+    /// A JSON object per line
+    Ndjson,
+}
+
+impl StreamFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            StreamFormat::Csv => "text/csv",
+            StreamFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// A body in `Transfer-Encoding: chunked`, sent a chunk at a time as it fills up so
+/// the length doesn't have to be known up front
+struct ChunkedWriter<W: Write> {
+    stream: W,
+    chunk: Vec<u8>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    const CHUNK_SIZE: usize = 16 * 1024;
+
+    fn new(stream: W) -> Self {
+        Self {
+            stream,
+            chunk: Vec::with_capacity(Self::CHUNK_SIZE),
+        }
+    }
+
+    fn send_chunk(&mut self) -> io::Result<()> {
+        if !self.chunk.is_empty() {
+            write!(self.stream, "{:x}\r\n", self.chunk.len())?;
+            self.stream.write_all(&self.chunk)?;
+            self.stream.write_all(b"\r\n")?;
+            self.chunk.clear();
+        }
+        Ok(())
+    }
+
+    /// Sends what's left and the empty chunk that ends the body
+    fn finish(mut self) -> Result<()> {
+        self.send_chunk()?;
+        self.stream.write_all(b"0\r\n\r\n")?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(bytes);
+        if self.chunk.len() >= Self::CHUNK_SIZE {
+            self.send_chunk()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_chunk()?;
+        self.stream.flush()
+    }
+}
+
+/// `application/json; charset=utf-8` is `application/json`
+fn media_type(header: &str) -> &str {
+    header.split(';').next().unwrap_or_default().trim()
+}
+
+/// What a response body is encoded as, JSON unless the request accepts MessagePack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BodyFormat {
+    Json,
+    MessagePack,
+}
+
+impl BodyFormat {
+    fn of(media_type: Option<&str>) -> Option<Self> {
+        match media_type? {
+            "application/json" | "application/*" | "*/*" => Some(BodyFormat::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(BodyFormat::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    /// The first of the `Accept` header's types we can write, quality values aren't weighed
+    fn accepted(request: &Request) -> Self {
+        request
+            .header("accept")
+            .and_then(|accept| {
+                accept
+                    .split(',')
+                    .find_map(|media| Self::of(Some(media_type(media))))
+            })
+            .unwrap_or(BodyFormat::Json)
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            BodyFormat::Json => "application/json",
+            BodyFormat::MessagePack => "application/msgpack",
+        }
+    }
+
+    fn encode(self, json: &Json) -> Vec<u8> {
+        match self {
+            BodyFormat::Json => json.to_string().into_bytes(),
+            BodyFormat::MessagePack => msgpack::encode(json),
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Json,
+}
+
+impl Response {
+    fn json(status: u16, body: Json) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, Json::object([("error", Json::from(message))]))
+    }
+
+    /// An error of a row, with its `ErrorCode`
+    fn coded_error(status: u16, message: &str, code: ErrorCode) -> Self {
+        Self::json(
+            status,
+            Json::object([
+                ("error", Json::from(message)),
+                ("code", Json::from(code.code())),
+            ]),
+        )
+    }
+
+    fn rejected(error: TransactionError) -> Self {
+        Self::json(
+            409,
+            Json::object([
+                ("error", Json::from(error.to_string())),
+                ("reason", Json::from(error.reason())),
+                ("code", Json::from(error.code().code())),
+            ]),
+        )
+    }
+
+    fn write(&self, stream: &mut impl Write, format: BodyFormat) -> Result<()> {
+        let body = format.encode(&self.body);
+        write_head(
+            stream,
+            self.status,
+            format.content_type(),
+            Some(body.len()),
+            &self.headers,
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// The status line and headers, the body is chunked if there's no length
+fn write_head(
+    stream: &mut impl Write,
+    status: u16,
+    content_type: &str,
+    length: Option<usize>,
+    headers: &[(&'static str, String)],
+) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        422 => "Unprocessable Entity",
+        _ => "Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nConnection: close\r\n",
+        status, reason, content_type
+    )?;
+    match length {
+        Some(length) => write!(stream, "Content-Length: {}\r\n", length)?,
+        None => stream.write_all(b"Transfer-Encoding: chunked\r\n")?,
+    }
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    stream.write_all(b"\r\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use playground::approval::ApprovalPolicy;
+    use playground::dedup::DedupWindow;
+    use playground::risk::HeuristicScorer;
+
+    use super::*;
+
+    fn post(server: &mut Server, target: &str, body: &str) -> (u16, String) {
+        let response = server.handle(&Request::new("POST", target, vec![], body.into()));
+        (response.status, response.body.to_string())
+    }
+
+    #[test]
+    fn should_hold_approve_and_deny_over_http() {
+        let mut transaction_engine = TransactionEngine::with_config(EngineConfig {
+            risk_threshold: Some(0.5),
+            ..EngineConfig::default()
+        });
+        transaction_engine.register_scorer(Box::new(HeuristicScorer {
+            min_samples: 0,
+            ..HeuristicScorer::default()
+        }));
+        let mut server = Server {
+            transaction_engine,
+            deduplicator: Some(Deduplicator::new(DedupWindow::Count(10))),
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+
+        let (status, body) = post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\ndeposit,1,2,5\ndeposit,1,3,7\n",
+        );
+        assert_eq!(status, 200);
+        assert!(body.contains(
+            r#""tx":2,"amount":"5.0000","status":"rejected","reason":"held_for_review""#
+        ));
+
+        let (_, body) = post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount\ndeposit,1,1,10\n",
+        );
+        assert_eq!(body, r#"[{"client":1,"tx":1,"status":"duplicate"}]"#);
+        let response = server.handle(&Request::new("GET", "/metrics", vec![], vec![]));
+        assert_eq!(response.body.to_string(), r#"{"deduplicated":1}"#);
+
+        assert_eq!(post(&mut server, "/review/1/2/approve", "").0, 200);
+        assert_eq!(
+            post(&mut server, "/review/1/3/deny?reason=looks+odd%21", "").0,
+            200
+        );
+        assert_eq!(post(&mut server, "/review/1/3/deny", "").0, 404);
+        assert_eq!(server.transaction_engine.denied()[0].reason, "looks odd!");
+
+        let response = server.handle(&Request::new("PUT", "/accounts/1/tags/vip", vec![], vec![]));
+        assert_eq!(response.status, 200);
+        let response = server.handle(&Request::new("GET", "/accounts/1", vec![], vec![]));
+        assert_eq!(
+            response.body.to_string(),
+            r#"{"client":1,"available":"5.0000","held":"10.0000","total":"15.0000","locked":false,"pockets":{},"wallets":{},"tags":["vip"]}"#
+        );
+    }
+
+    #[test]
+    fn should_refuse_bodies_over_the_limit_without_reading_them() {
+        let request = |length: usize| {
+            format!(
+                "POST /transactions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                length,
+                "x".repeat(length.min(16))
+            )
+        };
+        let read = |raw: String| Request::read(&mut raw.as_bytes(), 16);
+        assert_eq!(read(request(16)).unwrap().body, b"x".repeat(16));
+        for length in [17, usize::MAX] {
+            let error = read(request(length)).err().unwrap();
+            assert!(error.is::<BodyTooLarge>());
+        }
+    }
+
+    #[test]
+    fn should_refuse_long_lines_and_too_many_headers() {
+        let read = |raw: String| Request::read(&mut raw.as_bytes(), 16);
+        let header = |length: usize| format!("X-Long: {}\r\n", "x".repeat(length - 10));
+        let request = format!("GET /accounts HTTP/1.1\r\n{}\r\n", header(MAX_LINE));
+        assert_eq!(
+            read(request).unwrap().header("x-long").unwrap().len(),
+            MAX_LINE - 10
+        );
+        let request = format!("GET /accounts HTTP/1.1\r\n{}\r\n", header(MAX_LINE + 1));
+        assert!(read(request).err().unwrap().is::<HeadersTooLarge>());
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert!(read(request).err().unwrap().is::<HeadersTooLarge>());
+
+        let headers = |count: usize| "X-A: 1\r\n".repeat(count);
+        let request = format!("GET /accounts HTTP/1.1\r\n{}\r\n", headers(MAX_HEADERS));
+        assert_eq!(read(request).unwrap().headers.len(), MAX_HEADERS);
+        let request = format!("GET /accounts HTTP/1.1\r\n{}\r\n", headers(MAX_HEADERS + 1));
+        assert!(read(request).err().unwrap().is::<HeadersTooLarge>());
+        // a line that never ends is cut off too
+        let request = format!("GET /accounts HTTP/1.1\r\nX-Long: {}", "x".repeat(MAX_LINE));
+        assert!(read(request).err().unwrap().is::<HeadersTooLarge>());
+    }
+
+    #[test]
+    fn should_list_and_approve_pending_withdrawals() {
+        let mut server = Server {
+            transaction_engine: TransactionEngine::with_config(EngineConfig {
+                approvals: ApprovalPolicy::parse("default 20").unwrap(),
+                ..EngineConfig::default()
+            }),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        let (status, _) = post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount\ndeposit,1,1,100\nwithdrawal,1,2,50\nwithdrawal,1,3,5\n",
+        );
+        assert_eq!(status, 200);
+        let response = server.handle(&Request::new("GET", "/withdrawals", vec![], vec![]));
+        assert!(response
+            .body
+            .to_string()
+            .starts_with(r#"[{"client":1,"tx":2,"amount":"50.0000","requested_at":"#));
+
+        assert_eq!(post(&mut server, "/withdrawals/1/3/approve", "").0, 404);
+        assert_eq!(post(&mut server, "/withdrawals/1/2/approve", "").0, 200);
+        let response = server.handle(&Request::new("GET", "/withdrawals", vec![], vec![]));
+        assert_eq!(response.body.to_string(), "[]");
+        let account = server.transaction_engine.account(1).unwrap();
+        assert_eq!(account.total(), BigDecimal::from(45));
+
+        // a denied one is available again
+        let (status, _) = post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount\nwithdrawal,1,4,30\n",
+        );
+        assert_eq!(status, 200);
+        let account = server.transaction_engine.account(1).unwrap();
+        assert_eq!(account.held(), &BigDecimal::from(30));
+        assert_eq!(post(&mut server, "/withdrawals/1/4/deny", "").0, 200);
+        assert_eq!(post(&mut server, "/withdrawals/1/4/deny", "").0, 404);
+        assert_eq!(post(&mut server, "/withdrawals/1/4/approve", "").0, 404);
+        let account = server.transaction_engine.account(1).unwrap();
+        assert_eq!(account.available(), &BigDecimal::from(45));
+        assert_eq!(account.held(), &BigDecimal::zero());
+    }
+
+    #[test]
+    fn should_page_and_filter_accounts() {
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        let mut csv = "type,client,tx,amount\n".to_string();
+        for client in 1..=5 {
+            csv.push_str(&format!("deposit,{},{},{}\n", client, client, client * 10));
+        }
+        csv.push_str("dispute,4,4,\nchargeback,4,4,\n");
+        post(&mut server, "/transactions", &csv);
+
+        let get = |server: &mut Server, target: &str| {
+            let response = server.handle(&Request::new("GET", target, vec![], vec![]));
+            let clients = match &response.body {
+                Json::Array(accounts) => accounts
+                    .iter()
+                    .map(|account| account.get("client").unwrap().to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                other => other.to_string(),
+            };
+            let cursor = response
+                .headers
+                .iter()
+                .find(|(name, _)| *name == "X-Next-Cursor")
+                .map(|(_, cursor)| cursor.clone());
+            (response.status, clients, cursor)
+        };
+        let (_, clients, cursor) = get(&mut server, "/accounts?limit=2");
+        assert_eq!((clients.as_str(), cursor.as_deref()), ("1,2", Some("2")));
+        let (_, clients, cursor) = get(&mut server, "/accounts?limit=2&cursor=2");
+        assert_eq!((clients.as_str(), cursor.as_deref()), ("3,4", Some("4")));
+        let (_, clients, cursor) = get(&mut server, "/accounts?limit=2&cursor=4");
+        assert_eq!((clients.as_str(), cursor), ("5", None));
+
+        assert_eq!(get(&mut server, "/accounts?locked=true").1, "4");
+        assert_eq!(
+            get(&mut server, "/accounts?locked=false&min_balance=25").1,
+            "3,5"
+        );
+        assert_eq!(get(&mut server, "/accounts?limit=0").0, 400);
+        assert_eq!(get(&mut server, "/accounts?min_balance=lots").0, 400);
+    }
+
+    #[test]
+    fn should_sum_up_the_accounts_in_the_stats() {
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount,from_pocket,to_pocket\n\
+             deposit,1,1,100,,\n\
+             transfer,1,2,30,,savings\n\
+             deposit,2,3,10,,\n\
+             dispute,2,3,,,\n",
+        );
+
+        let stats = server
+            .handle(&Request::new("GET", "/stats", vec![], vec![]))
+            .body;
+        let stat = |name: &str| stats.get(name).unwrap().to_string();
+        assert_eq!(stat("accounts"), "2");
+        assert_eq!(stat("available"), "\"70.0000\"");
+        // the pocket isn't held
+        assert_eq!(stat("held"), "\"10.0000\"");
+        assert_eq!(stat("total"), "\"110.0000\"");
+        assert_eq!(stat("open_disputes"), "1");
+    }
+
+    #[test]
+    fn should_reload_the_config_when_asked() {
+        let path = std::env::temp_dir().join(format!("tunables-{}.conf", std::process::id()));
+        std::fs::write(&path, "risk_threshold=0.5\n").unwrap();
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: Some(Settings {
+                path: path.to_str().unwrap().to_string(),
+                modified: None,
+                base: EngineConfig::default(),
+            }),
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        assert!(server.reload(false).unwrap());
+        assert!(!server.reload(false).unwrap());
+        assert_eq!(server.transaction_engine.config().risk_threshold, Some(0.5));
+
+        std::fs::write(&path, "risk_threshold=0.9\ndispute_timeout_days=x\n").unwrap();
+        assert_eq!(post(&mut server, "/config/reload", "").0, 400);
+        assert_eq!(server.transaction_engine.config().risk_threshold, Some(0.5));
+        std::fs::write(&path, "dispute_timeout_days=1\n").unwrap();
+        assert_eq!(post(&mut server, "/config/reload", "").0, 200);
+        std::fs::remove_file(&path).unwrap();
+        let response = server.handle(&Request::new("GET", "/config", vec![], vec![]));
+        // the threshold is back to the command line's, which had none
+        assert_eq!(
+            response.body.to_string(),
+            r#"{"risk_threshold":null,"dispute_timeout":86400,"rules":0,"blocklist":0,"alerts":0,"latency_budget_us":null,"max_accounts":null,"max_stored_transactions":null}"#
+        );
+
+        let response = server.handle(&Request::new(
+            "PUT",
+            "/blocklist",
+            vec![],
+            b"action hold\n7\n3\n".to_vec(),
+        ));
+        assert_eq!(response.status, 200);
+        let response = server.handle(&Request::new("GET", "/blocklist", vec![], vec![]));
+        assert_eq!(
+            response.body.to_string(),
+            r#"{"action":"hold","clients":[3,7]}"#
+        );
+        // the settings file doesn't have a blocklist, so the list outlasts its reloads
+        std::fs::write(&path, "dispute_timeout_days=2\n").unwrap();
+        assert!(server.reload(true).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(server.transaction_engine.config().blocklist.len(), 2);
+        let response = server.handle(&Request::new("PUT", "/blocklist", vec![], b"x".to_vec()));
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn should_stream_exports_in_chunks() {
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount\ndeposit,2,1,10\ndeposit,1,2,5\nwithdrawal,1,3,9\n",
+        );
+        let export = |target: &str| {
+            let request = Request::new("GET", target, vec![], vec![]);
+            let dataset = request.path.strip_prefix("/export/").unwrap().to_string();
+            let mut stream = vec![];
+            server.export(&dataset, &request, &mut stream).unwrap();
+            String::from_utf8(stream).unwrap()
+        };
+
+        let accounts = export("/export/accounts");
+        let (head, body) = accounts.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        let csv = "client,available,held,total,locked\n1,5.0000,0.0000,5.0000,false\n2,10.0000,0.0000,10.0000,false\n";
+        assert_eq!(body, format!("{:x}\r\n{}\r\n0\r\n\r\n", csv.len(), csv));
+
+        let transactions = export("/export/transactions?format=ndjson");
+        let lines = transactions
+            .lines()
+            .filter(|line| line.starts_with('{'))
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            r#"{"type":"withdrawal","client":1,"tx":3,"amount":"9.0000"}"#
+        );
+        assert!(export("/export/accounts?format=xml").starts_with("HTTP/1.1 400"));
+        assert!(export("/export/cases").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn should_take_and_negotiate_message_pack() {
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        let transactions = Json::array([
+            Json::object([
+                ("type", Json::from("deposit")),
+                ("client", Json::from(1_u16)),
+                ("tx", Json::from(1_u32)),
+                ("amount", Json::from("2.5")),
+            ]),
+            Json::object([
+                ("type", Json::from("withdrawal")),
+                ("client", Json::from(1_u16)),
+                ("tx", Json::from(2_u32)),
+                ("amount", Json::from(1.25)),
+            ]),
+        ]);
+        let headers = vec![(
+            "content-type".to_string(),
+            "application/msgpack".to_string(),
+        )];
+        let request = Request::new(
+            "POST",
+            "/transactions",
+            headers,
+            msgpack::encode(&transactions),
+        );
+        let response = server.handle(&request);
+        assert_eq!(response.status, 200);
+        assert!(!response.body.to_string().contains("rejected"));
+        assert_eq!(
+            server
+                .transaction_engine
+                .account(1)
+                .unwrap()
+                .to_csv_account()
+                .available,
+            "1.2500"
+        );
+
+        let accept = |accept: &str| {
+            let headers = vec![("accept".to_string(), accept.to_string())];
+            BodyFormat::accepted(&Request::new("GET", "/accounts", headers, vec![]))
+        };
+        assert_eq!(accept("application/msgpack"), BodyFormat::MessagePack);
+        assert_eq!(
+            accept("text/html, application/x-msgpack;q=0.9"),
+            BodyFormat::MessagePack
+        );
+        assert_eq!(
+            accept("application/json, application/msgpack"),
+            BodyFormat::Json
+        );
+        assert_eq!(accept("text/html"), BodyFormat::Json);
+    }
+
+    #[test]
+    fn should_write_dead_letters_for_rows_that_were_not_applied() {
+        let path = std::env::temp_dir().join(format!("dead-letters-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: Some(output::open_log(path.to_str().unwrap()).unwrap()),
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        let (status, _) = post(
+            &mut server,
+            "/transactions",
+            "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\nrefund,1,3,5\n",
+        );
+        assert_eq!(status, 200);
+        drop(server);
+
+        let letters = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let letters = letters.lines().collect::<Vec<_>>();
+        assert_eq!(letters.len(), 2);
+        assert!(letters[0].contains(
+            r#""stage":"validate","error":"insufficient funds","reason":"insufficient_funds","code":"E1001","message":"withdrawal,1,2,20""#
+        ));
+        assert!(letters[1].contains(r#""stage":"parse""#));
+        assert!(letters[1].contains(r#""code":"E2005""#));
+        assert!(letters[1].contains(r#""message":"refund,1,3,5""#));
+    }
+
+    #[test]
+    fn should_answer_a_repeated_idempotency_key_without_applying_it_again() {
+        let path =
+            std::env::temp_dir().join(format!("idempotency-keys-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: Some(IdempotencyStore::open(&path, 60, date::unix_now()).unwrap()),
+            tracing: Tracing::default(),
+        };
+        let mut post = |key: &str, body: &str| {
+            let headers = vec![("idempotency-key".to_string(), key.to_string())];
+            let request = Request::new("POST", "/transactions", headers, body.into());
+            server.handle_once(&request).unwrap()
+        };
+        let first = post("a", "type,client,tx,amount\ndeposit,1,1,10\n");
+        let again = post("a", "type,client,tx,amount\ndeposit,1,1,10\n");
+        assert_eq!(again.body, first.body);
+        assert!(again
+            .headers
+            .contains(&("Idempotent-Replayed", "true".to_string())));
+        assert_eq!(
+            post("a", "type,client,tx,amount\ndeposit,1,2,10\n").status,
+            422
+        );
+        assert_eq!(
+            server.transaction_engine.account(1).unwrap().available(),
+            &BigDecimal::from(10)
+        );
+
+        // the keys outlive the server
+        let mut store = IdempotencyStore::open(&path, 60, date::unix_now()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        let fingerprint = idempotency::fingerprint(
+            "POST",
+            "/transactions",
+            &[],
+            b"type,client,tx,amount\ndeposit,1,1,10\n",
+        );
+        assert!(matches!(
+            store.lookup("a", &fingerprint, date::unix_now()),
+            Lookup::Replay(_)
+        ));
+    }
+
+    #[test]
+    fn should_continue_the_trace_of_a_request() {
+        let mut server = Server {
+            transaction_engine: TransactionEngine::new(),
+            deduplicator: None,
+            dead_letters: None,
+            amount_format: AmountFormat::default(),
+            settings: None,
+            state: None,
+            event_log: None,
+            idempotency: None,
+            tracing: Tracing::default(),
+        };
+        let mut traceresponse = |headers| {
+            let request = Request::new("POST", "/accounts/1/lock", headers, vec![]);
+            let response = server.handle_traced(&request).unwrap();
+            let (_, traceresponse) = response
+                .headers
+                .iter()
+                .find(|(name, _)| *name == "traceresponse")
+                .cloned()
+                .unwrap();
+            TraceContext::parse(&traceresponse).unwrap()
+        };
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = traceresponse(vec![("traceparent".to_string(), traceparent.to_string())]);
+        assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(context.traceparent(), traceparent);
+        let context = traceresponse(vec![("traceparent".to_string(), "garbage".to_string())]);
+        assert_ne!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(server.tracing.request.is_none());
+    }
+}
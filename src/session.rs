@@ -0,0 +1,296 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::config::EngineConfig;
+use crate::errors::ErrorCode;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::{RawTransaction, Transaction, TransactionType};
+use crate::transaction_engine::{TransactionEngine, TransactionError};
+
+/// Applies one row of a transactions csv, returning the transaction and what the engine said about it
+pub fn apply(
+    transaction_engine: &mut TransactionEngine,
+    raw: RawTransaction,
+) -> Result<(Transaction, Result<(), TransactionError>)> {
+    // Transaction is how I want transactions to be represented,
+    // But I couldn't figure out how to use the csv crate to parse directly into that format
+    // so I parse into an intermediate, RawTransaction, and then convert manually
+    if let Some(timestamp) = raw.timestamp() {
+        transaction_engine.advance_time(timestamp);
+    }
+    if let Some(kind) = raw.custom_type() {
+        if !transaction_engine.handles(kind) {
+            return Err(ErrorCode::UnknownType
+                .error(format!(
+                    "Unknown transaction type {} of tx {} of client {}",
+                    kind,
+                    raw.tx(),
+                    raw.client()
+                ))
+                .into());
+        }
+    }
+    let excess_decimals = raw.excess_decimals();
+    let opening = raw.transaction_type() == TransactionType::Opening;
+    let transaction: Transaction = raw.try_into()?;
+    if excess_decimals {
+        return Ok((transaction, Err(TransactionError::ExcessDecimals)));
+    }
+    let result = match (&transaction, opening) {
+        (
+            Transaction::Deposit {
+                client, tx, amount, ..
+            },
+            true,
+        ) => transaction_engine.process_opening(*client, *tx, amount.clone()),
+        _ => transaction_engine.process(transaction.clone()),
+    };
+    Ok((transaction, result))
+}
+
+/// What happened to the rows of one `ProcessingSession::next_n`
+#[derive(Debug, Default)]
+pub struct ChunkReport {
+    /// Rows taken from the input, applied or not
+    pub processed: usize,
+    pub applied: usize,
+    pub rejected: Vec<(Transaction, TransactionError)>,
+    /// Rows taken from the input since the session started, including before a resume
+    pub position: u64,
+    /// The input has no more rows
+    pub finished: bool,
+}
+
+/// How many rows `ProcessingSession::run` processes between looking at its token
+pub const CANCEL_CHECK_ROWS: usize = 256;
+
+/// Asks a `ProcessingSession::run` to stop from another thread, e.g. when the user aborts an
+/// import. Clones share the token
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Processes the whole input unless `cancel` is cancelled first, see `ProcessingSession::run`
+pub fn process_stream<I: Iterator<Item = Result<RawTransaction>>>(
+    transaction_engine: TransactionEngine,
+    input: I,
+    cancel: &CancellationToken,
+) -> Result<(ChunkReport, ProcessingSession<I>)> {
+    let mut session = ProcessingSession::new(transaction_engine, input);
+    let report = session.run(cancel)?;
+    Ok((report, session))
+}
+
+/// An engine working its way through an input, for host applications that want to decide
+/// when processing happens, e.g. a chunk per scheduler tick, and checkpoint in between
+pub struct ProcessingSession<I> {
+    transaction_engine: TransactionEngine,
+    input: I,
+    position: u64,
+}
+
+impl<I: Iterator<Item = Result<RawTransaction>>> ProcessingSession<I> {
+    pub const fn new(transaction_engine: TransactionEngine, input: I) -> Self {
+        Self {
+            transaction_engine,
+            input,
+            position: 0,
+        }
+    }
+
+    /// Continues from a `state_snapshot`. `input` has to yield the same rows as the input of
+    /// the session the snapshot was taken of, from the start, the rows that session
+    /// had already processed are skipped
+    pub fn resume(snapshot: &[u8], config: EngineConfig, mut input: I) -> Result<Self> {
+        let mut decoder = Decoder::new(snapshot)?;
+        let position = u64::decode(&mut decoder)?;
+        let transaction_engine = TransactionEngine::decode_state(&mut decoder, config)?;
+        decoder.finish()?;
+        for skipped in 0..position {
+            if input.next().is_none() {
+                bail!(
+                    "The input ended after {} rows, but the snapshot was taken after {}",
+                    skipped,
+                    position
+                );
+            }
+        }
+        Ok(Self {
+            transaction_engine,
+            input,
+            position,
+        })
+    }
+
+    /// Processes up to `n` rows. Rows the csv reader couldn't parse are errors, like in the cli,
+    /// the rows before them in the chunk have been applied by then
+    pub fn next_n(&mut self, n: usize) -> Result<ChunkReport> {
+        let mut report = ChunkReport::default();
+        while report.processed < n {
+            let raw = match self.input.next() {
+                None => {
+                    report.finished = true;
+                    break;
+                }
+                Some(raw) => raw?,
+            };
+            self.position += 1;
+            report.processed += 1;
+            match apply(&mut self.transaction_engine, raw)? {
+                (_, Ok(())) => report.applied += 1,
+                (transaction, Err(error)) => report.rejected.push((transaction, error)),
+            }
+        }
+        report.position = self.position;
+        Ok(report)
+    }
+
+    /// Processes the rest of the input, stopping early when `cancel` is cancelled. The token is
+    /// looked at every `CANCEL_CHECK_ROWS` rows, so a few more rows may be applied after it is.
+    /// The report has all the rows of the run, and is not `finished` if it was cancelled, the
+    /// session can then be checkpointed with `state_snapshot` to continue later
+    pub fn run(&mut self, cancel: &CancellationToken) -> Result<ChunkReport> {
+        let mut report = ChunkReport {
+            position: self.position,
+            ..ChunkReport::default()
+        };
+        while !report.finished && !cancel.is_cancelled() {
+            let chunk = self.next_n(CANCEL_CHECK_ROWS)?;
+            report.processed += chunk.processed;
+            report.applied += chunk.applied;
+            report.rejected.extend(chunk.rejected);
+            report.position = chunk.position;
+            report.finished = chunk.finished;
+        }
+        Ok(report)
+    }
+
+    /// The engine and how far into the input the session is, see `resume`
+    pub fn state_snapshot(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        self.position.encode(&mut encoder);
+        self.transaction_engine.encode_state(&mut encoder);
+        encoder.into_bytes()
+    }
+
+    pub const fn engine(&self) -> &TransactionEngine {
+        &self.transaction_engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut TransactionEngine {
+        &mut self.transaction_engine
+    }
+
+    pub fn into_engine(self) -> TransactionEngine {
+        self.transaction_engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(csv: &str) -> impl Iterator<Item = Result<RawTransaction>> + '_ {
+        crate::transaction::csv_reader(csv.as_bytes())
+            .into_deserialize()
+            .map(|row| row.map_err(anyhow::Error::from))
+    }
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,20
+deposit,2,3,5
+dispute,2,3,
+";
+
+    #[test]
+    fn should_process_in_chunks_and_resume_from_a_snapshot() {
+        let mut session = ProcessingSession::new(TransactionEngine::new(), rows(INPUT));
+
+        let report = session.next_n(2).unwrap();
+        assert_eq!(
+            (report.processed, report.applied, report.position),
+            (2, 1, 2)
+        );
+        assert_eq!(report.rejected[0].1, TransactionError::InsufficientFunds);
+        assert!(!report.finished);
+
+        let mut resumed = ProcessingSession::resume(
+            &session.state_snapshot(),
+            EngineConfig::default(),
+            rows(INPUT),
+        )
+        .unwrap();
+        let report = resumed.next_n(10).unwrap();
+        assert_eq!(
+            (report.processed, report.applied, report.position),
+            (2, 2, 4)
+        );
+        assert!(report.finished);
+
+        let mut accounts = resumed.engine().accounts();
+        accounts.sort_by_key(|account| account.client);
+        assert_eq!(accounts[0].available, "10.0000");
+        assert_eq!(accounts[1].held, "5.0000");
+    }
+
+    #[test]
+    fn should_stop_when_cancelled_and_continue_from_the_snapshot() {
+        let mut input = "type,client,tx,amount\n".to_string();
+        for tx in 1..=600 {
+            input.push_str(&format!("deposit,1,{},1\n", tx));
+        }
+        let cancel = CancellationToken::new();
+        let cancel_on_first_row = cancel.clone();
+        let input_rows = rows(&input).inspect(move |_| cancel_on_first_row.cancel());
+
+        let (report, session) =
+            process_stream(TransactionEngine::new(), input_rows, &cancel).unwrap();
+        assert_eq!(
+            (report.processed, report.position, report.finished),
+            (CANCEL_CHECK_ROWS, CANCEL_CHECK_ROWS as u64, false)
+        );
+
+        let mut resumed = ProcessingSession::resume(
+            &session.state_snapshot(),
+            EngineConfig::default(),
+            rows(&input),
+        )
+        .unwrap();
+        let report = resumed.run(&CancellationToken::new()).unwrap();
+        assert_eq!(
+            (report.processed, report.applied),
+            (600 - CANCEL_CHECK_ROWS, 600 - CANCEL_CHECK_ROWS)
+        );
+        assert!(report.finished);
+        assert_eq!(resumed.engine().accounts()[0].available, "600.0000");
+    }
+
+    #[test]
+    fn should_refuse_to_resume_with_a_shorter_input() {
+        let mut session = ProcessingSession::new(TransactionEngine::new(), rows(INPUT));
+        session.next_n(4).unwrap();
+        let input = rows("type,client,tx,amount\ndeposit,1,1,10\n");
+        assert!(ProcessingSession::resume(
+            &session.state_snapshot(),
+            EngineConfig::default(),
+            input
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+/// What we owe a client at the end of the day
+#[derive(Debug, PartialEq)]
+pub struct Payout {
+    pub client: ClientId,
+    pub amount: BigDecimal,
+}
+
+/// One payout per client with money available, sorted by client id.
+/// Held funds are still under dispute and locked accounts are frozen, so neither is paid out.
+/// The fee account holds our own revenue, not a client's money, so it isn't paid out either
+pub fn payouts(engine: &TransactionEngine) -> Vec<Payout> {
+    let fee_account = engine.config().fees.account();
+    let mut payouts = engine
+        .account_views()
+        .filter(|account| Some(account.client()) != fee_account)
+        .filter(|account| !account.locked() && account.available() > &BigDecimal::zero())
+        .map(|account| Payout {
+            client: account.client(),
+            amount: account.available().clone(),
+        })
+        .collect::<Vec<_>>();
+    payouts.sort_by_key(|payout| payout.client);
+    payouts
+}
+
+/// How the payout file looks, read from a `key=value` file:
+/// ```text
+/// header=client_id;amount;bank_reference
+/// row={client};{amount};
+/// footer=END
+/// ```
+/// `header` and `footer` are optional, `row` supports the `{client}` and `{amount}` placeholders
+pub struct SettlementTemplate {
+    header: Option<String>,
+    row: String,
+    footer: Option<String>,
+}
+
+impl Default for SettlementTemplate {
+    fn default() -> Self {
+        Self {
+            header: Some("client,amount,bank_reference".to_string()),
+            // the bank reference is filled in by the payment processor
+            row: "{client},{amount},".to_string(),
+            footer: None,
+        }
+    }
+}
+
+impl SettlementTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut header = None;
+        let mut row = None;
+        let mut footer = None;
+        for line in template.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("header", value)) => header = Some(value.to_string()),
+                Some(("row", value)) => row = Some(value.to_string()),
+                Some(("footer", value)) => footer = Some(value.to_string()),
+                _ => bail!("Unexpected line in settlement template: {}", line),
+            }
+        }
+        let row = match row {
+            None => bail!("Settlement template is missing a row"),
+            Some(row) => row,
+        };
+        let unknown = row
+            .replace("{client}", "")
+            .replace("{amount}", "")
+            .contains('{');
+        if unknown {
+            bail!("Unknown placeholder in settlement template row: {}", row);
+        }
+        Ok(Self {
+            header,
+            row,
+            footer,
+        })
+    }
+
+    pub fn render(&self, payouts: &[Payout], writer: &mut dyn Write) -> Result<()> {
+        if let Some(header) = &self.header {
+            writeln!(writer, "{}", header)?;
+        }
+        for payout in payouts {
+            let row = self
+                .row
+                .replace("{client}", &payout.client.to_string())
+                .replace("{amount}", &format!("{:.4}", payout.amount));
+            writeln!(writer, "{}", row)?;
+        }
+        if let Some(footer) = &self.footer {
+            writeln!(writer, "{}", footer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::fees::FeeSchedule;
+    use crate::transaction::Transaction::Deposit;
+
+    #[test]
+    fn should_not_pay_out_the_fees_we_charged() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            fees: FeeSchedule::parse("account 9\ndeposit 1%").unwrap(),
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(100),
+            wallet: None,
+        });
+        assert_eq!(engine.account(9).unwrap().available(), &BigDecimal::from(1));
+        assert_eq!(
+            payouts(&engine),
+            vec![Payout {
+                client: 1,
+                amount: BigDecimal::from(99),
+            }]
+        );
+    }
+
+    #[test]
+    fn should_render_payouts_with_template() {
+        let template =
+            SettlementTemplate::parse("header=id;amount;ref\nrow={client};{amount};\nfooter=END")
+                .unwrap();
+        let payouts = vec![
+            Payout {
+                client: 1,
+                amount: BigDecimal::from(10),
+            },
+            Payout {
+                client: 2,
+                amount: BigDecimal::from(3),
+            },
+        ];
+        let mut output = vec![];
+        template.render(&payouts, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "id;amount;ref\n1;10.0000;\n2;3.0000;\nEND\n"
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_placeholders() {
+        assert!(SettlementTemplate::parse("row={client};{iban}").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use hmac::{Hmac, Mac};
+use sha2::Digest;
+
+/// SHA-256 that keeps count of what went through it, for fingerprinting inputs
+#[derive(Clone, Default)]
+pub struct Sha256 {
+    hasher: sha2::Sha256,
+    length: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        sha2::Sha256::digest(data).into()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        self.hasher.update(data);
+    }
+
+    /// How many bytes have been hashed
+    pub const fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// The hash of everything so far, more can still be added afterwards
+    pub fn finish(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+}
+
+/// HMAC-SHA256, RFC 2104
+pub fn hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// Lower case hex, how digests are usually shown
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hashes everything read through it, so an input can be fingerprinted while it's processed
+pub struct HashingRead<R> {
+    inner: R,
+    hasher: Rc<RefCell<Sha256>>,
+}
+
+impl<R: Read> HashingRead<R> {
+    /// The hasher is updated as the reader is read from
+    pub fn new(inner: R) -> (Self, Rc<RefCell<Sha256>>) {
+        let hasher = Rc::new(RefCell::new(Sha256::new()));
+        let reader = Self {
+            inner,
+            hasher: Rc::clone(&hasher),
+        };
+        (reader, hasher)
+    }
+}
+
+impl<R: Read> Read for HashingRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.borrow_mut().update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_known_digests() {
+        assert_eq!(
+            hex(&Sha256::digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&Sha256::digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(
+            hex(&Sha256::digest(long)),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+
+        let (mut reader, hasher) = HashingRead::new(&long[..]);
+        let mut read = vec![0; 10];
+        while reader.read(&mut read).unwrap() > 0 {}
+        assert_eq!(hasher.borrow().finish(), Sha256::digest(long));
+        assert_eq!(hasher.borrow().len(), 56);
+    }
+
+    #[test]
+    fn should_match_known_hmacs() {
+        // RFC 4231 test cases 2 and 6, the second one has a key longer than a block
+        assert_eq!(
+            hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+}
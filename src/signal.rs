@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How often SIGUSR1 came since `listen` was first called
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(target_os = "linux")]
+const SIGUSR1: i32 = 10;
+#[cfg(all(unix, not(target_os = "linux")))]
+const SIGUSR1: i32 = 30;
+
+#[cfg(unix)]
+extern "C" fn handle(_signal: i32) {
+    RECEIVED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Catches SIGUSR1 from here on instead of being terminated by it, the `Usr1` tells when it
+/// comes. Signals are a unix thing, elsewhere this does nothing
+pub fn listen() -> Usr1 {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn signal(signal: i32, handler: extern "C" fn(i32)) -> usize;
+        }
+        // SAFETY: the handler only counts, which is all a signal handler can safely do
+        unsafe {
+            signal(SIGUSR1, handle);
+        }
+    }
+    Usr1 {
+        seen: RECEIVED.load(Ordering::SeqCst),
+    }
+}
+
+/// The SIGUSR1s after a `listen`. Every listener sees every signal, runs side by side don't
+/// take them from each other
+pub struct Usr1 {
+    seen: u64,
+}
+
+impl Usr1 {
+    /// Whether SIGUSR1 came since the last time this was asked
+    pub fn received(&mut self) -> bool {
+        let received = RECEIVED.load(Ordering::SeqCst);
+        let new = received != self.seen;
+        self.seen = received;
+        new
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_tell_every_listener_once_per_signal() {
+        extern "C" {
+            fn raise(signal: i32) -> i32;
+        }
+        let mut first = listen();
+        let mut second = listen();
+        assert!(!first.received());
+        // SAFETY: the handler is installed, so the signal doesn't terminate the tests
+        assert_eq!(unsafe { raise(SIGUSR1) }, 0);
+        assert!(first.received());
+        assert!(!first.received());
+        let mut third = listen();
+        assert!(!third.received());
+        assert!(second.received());
+        assert!(!second.received());
+    }
+}
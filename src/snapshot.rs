@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bigdecimal::BigDecimal;
+
+use crate::transaction::ClientId;
+
+/// Start of every snapshot, the last two bytes are the format version
+pub const MAGIC: &[u8; 8] = b"PGSNAP\x00\x15";
+
+/// Snapshots of this version and later can still be read, `Decoder::version` tells which
+/// fields they have
+pub const OLDEST_VERSION: u16 = 1;
+
+/// Engine state written to bytes so processing can continue elsewhere or later.
+/// The format is a plain little endian encoding of the fields in declaration order,
+/// with lengths before collections and a tag byte before enum variants
+pub trait Snapshot: Sized {
+    fn encode(&self, encoder: &mut Encoder);
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self>;
+}
+
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self {
+            bytes: MAGIC.to_vec(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// An enum variant
+    pub fn tag(&mut self, tag: u8) {
+        self.bytes.push(tag);
+    }
+
+    pub fn length(&mut self, len: usize) {
+        // usize is at most 64 bits on every platform we build for
+        (len as u64).encode(self);
+    }
+}
+
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    version: u16,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let (magic, version) = MAGIC.split_at(6);
+        let current = u16::from_be_bytes([version[0], version[1]]);
+        match bytes.strip_prefix(magic) {
+            Some([high, low, bytes @ ..]) => {
+                let version = u16::from_be_bytes([*high, *low]);
+                if !(OLDEST_VERSION..=current).contains(&version) {
+                    bail!("Unsupported snapshot version {}", version);
+                }
+                Ok(Self { bytes, version })
+            }
+            _ => bail!("Not a snapshot"),
+        }
+    }
+
+    /// Format version of the snapshot being decoded
+    pub const fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("Snapshot is truncated");
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub fn tag(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn length(&mut self) -> Result<usize> {
+        usize::try_from(u64::decode(self)?).context("Snapshot collection is too large")
+    }
+
+    /// Fails if there's anything left, a snapshot is decoded in one go
+    pub fn finish(self) -> Result<()> {
+        if !self.bytes.is_empty() {
+            bail!("Unexpected data at the end of the snapshot");
+        }
+        Ok(())
+    }
+}
+
+macro_rules! impl_snapshot_for_number {
+    ($($number:ty),*) => {
+        $(impl Snapshot for $number {
+            fn encode(&self, encoder: &mut Encoder) {
+                encoder.bytes(&self.to_le_bytes());
+            }
+
+            fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+                let bytes = decoder.bytes(std::mem::size_of::<Self>())?;
+                Ok(Self::from_le_bytes(bytes.try_into()?))
+            }
+        })*
+    };
+}
+
+impl_snapshot_for_number!(u16, u32, u64, f64);
+
+/// A client id in a snapshot. They're written as u64 from version 11 on, whatever the width of
+/// the build's `ClientId`, so a snapshot can be read by any build its ids fit in. Older
+/// snapshots have them as u16
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Client(pub ClientId);
+
+// which conversions do something depends on the client-id features
+#[allow(clippy::useless_conversion)]
+impl Snapshot for Client {
+    fn encode(&self, encoder: &mut Encoder) {
+        u64::from(self.0).encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        if decoder.version() < 11 {
+            return Ok(Self(ClientId::from(u16::decode(decoder)?)));
+        }
+        let client = u64::decode(decoder)?;
+        ClientId::try_from(client).map(Self).map_err(|_| {
+            anyhow!(
+                "Client {} doesn't fit in the client ids of this build, see the client-id features",
+                client
+            )
+        })
+    }
+}
+
+impl Snapshot for bool {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.tag(u8::from(*self));
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        match decoder.tag()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => bail!("Invalid bool {} in snapshot", tag),
+        }
+    }
+}
+
+impl Snapshot for String {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.length(self.len());
+        encoder.bytes(self.as_bytes());
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        let len = decoder.length()?;
+        Ok(std::str::from_utf8(decoder.bytes(len)?)?.to_string())
+    }
+}
+
+/// Written as its decimal string, which is exact and keeps the scale
+impl Snapshot for BigDecimal {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.to_string().encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        let decimal = String::decode(decoder)?;
+        BigDecimal::from_str(&decimal).with_context(|| format!("Invalid amount {}", decimal))
+    }
+}
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            None => encoder.tag(0),
+            Some(value) => {
+                encoder.tag(1);
+                value.encode(encoder);
+            }
+        }
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        match decoder.tag()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(decoder)?)),
+            tag => bail!("Invalid option {} in snapshot", tag),
+        }
+    }
+}
+
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.length(self.len());
+        for value in self {
+            value.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        let len = decoder.length()?;
+        // don't trust the length with the allocation, a corrupt one would be huge
+        let mut values = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            values.push(T::decode(decoder)?);
+        }
+        Ok(values)
+    }
+}
+
+impl<T: Snapshot> Snapshot for VecDeque<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.length(self.len());
+        for value in self {
+            value.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Vec::decode(decoder)?.into())
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok((A::decode(decoder)?, B::decode(decoder)?))
+    }
+}
+
+impl<A: Snapshot, B: Snapshot, C: Snapshot> Snapshot for (A, B, C) {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+        self.2.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok((
+            A::decode(decoder)?,
+            B::decode(decoder)?,
+            C::decode(decoder)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Value = (Vec<Option<String>>, BigDecimal, (u16, f64, bool));
+
+    #[test]
+    fn should_round_trip_values() {
+        let value: Value = (
+            vec![Some("ünïcode".to_string()), None],
+            BigDecimal::from_str("-1.2300").unwrap(),
+            (u16::MAX, 0.5, true),
+        );
+        let mut encoder = Encoder::new();
+        value.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+
+        let mut decoder = Decoder::new(&bytes).unwrap();
+        let decoded = Value::decode(&mut decoder).unwrap();
+        decoder.finish().unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(decoded.1.to_string(), "-1.2300");
+
+        let mut truncated = Decoder::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(Value::decode(&mut truncated).is_err());
+        assert!(Decoder::new(b"not a snapshot").is_err());
+        assert_eq!(Decoder::new(b"PGSNAP\x00\x01").unwrap().version(), 1);
+        assert!(Decoder::new(b"PGSNAP\x01\x00").is_err());
+    }
+
+    #[test]
+    fn should_read_client_ids_of_any_width() {
+        let mut encoder = Encoder::new();
+        Client(7).encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+        assert_eq!(bytes.len(), MAGIC.len() + 8);
+        let mut decoder = Decoder::new(&bytes).unwrap();
+        assert_eq!(Client::decode(&mut decoder).unwrap(), Client(7));
+
+        // version 10 had them as u16
+        let mut decoder = Decoder::new(b"PGSNAP\x00\x0a\x07\x00").unwrap();
+        assert_eq!(Client::decode(&mut decoder).unwrap(), Client(7));
+        decoder.finish().unwrap();
+
+        let mut wide = b"PGSNAP\x00\x0b".to_vec();
+        wide.extend(u64::MAX.to_le_bytes());
+        let decoded = Client::decode(&mut Decoder::new(&wide).unwrap());
+        assert_eq!(decoded.is_ok(), ClientId::BITS == 64);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::ledger::EntryKind;
+use crate::transaction_engine::TransactionEngine;
+
+/// What the transactions from one source did to the accounts, to reconcile every feed with
+/// the totals its sender reports, e.g. when an engine is fed by several acquirers
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvSourceSummary {
+    /// Empty for the entries without a source, e.g. operator adjustments
+    pub source: String,
+    pub deposits: usize,
+    pub deposited: String,
+    pub withdrawals: usize,
+    pub withdrawn: String,
+    pub chargebacks: usize,
+    pub charged_back: String,
+    /// How much the source changed the total balance of the accounts, all of the above
+    pub net: String,
+}
+
+/// One summary per source in the ledgers, sorted by source. Only applied transactions are in
+/// the ledgers, the rejected ones are in the rejects output
+pub fn summarize(engine: &TransactionEngine) -> Vec<CsvSourceSummary> {
+    #[derive(Default)]
+    struct Totals {
+        deposits: usize,
+        deposited: BigDecimal,
+        withdrawals: usize,
+        withdrawn: BigDecimal,
+        chargebacks: usize,
+        charged_back: BigDecimal,
+        net: BigDecimal,
+    }
+
+    let mut totals = BTreeMap::<&str, Totals>::new();
+    for account in engine.account_views() {
+        // locks and unlocks don't move any money
+        for entry in account
+            .ledger()
+            .iter()
+            .filter(|entry| !matches!(entry.kind, EntryKind::Lock | EntryKind::Unlock))
+        {
+            let totals = totals
+                .entry(entry.source.as_deref().unwrap_or_default())
+                .or_default();
+            match entry.kind {
+                EntryKind::Deposit => {
+                    totals.deposits += 1;
+                    totals.deposited += &entry.available;
+                }
+                EntryKind::Withdrawal => {
+                    totals.withdrawals += 1;
+                    totals.withdrawn -= &entry.available;
+                }
+                EntryKind::Chargeback => {
+                    totals.chargebacks += 1;
+                    totals.charged_back -= &entry.held;
+                }
+                _ => {}
+            }
+            totals.net += entry.total();
+        }
+    }
+    totals
+        .into_iter()
+        .map(|(source, totals)| CsvSourceSummary {
+            source: source.to_string(),
+            deposits: totals.deposits,
+            deposited: format!("{:.4}", totals.deposited),
+            withdrawals: totals.withdrawals,
+            withdrawn: format!("{:.4}", totals.withdrawn),
+            chargebacks: totals.chargebacks,
+            charged_back: format!("{:.4}", totals.charged_back),
+            net: format!("{:.4}", totals.net),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::Transaction;
+
+    use super::*;
+
+    #[test]
+    fn should_total_the_ledgers_per_source() {
+        let mut engine = TransactionEngine::new();
+        let deposit = |client, tx, amount| Transaction::Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        engine.set_source(Some("acquirer-a"));
+        engine.process(deposit(1, 1, 10)).unwrap();
+        engine.process(deposit(2, 2, 5)).unwrap();
+        engine.set_source(Some("acquirer-b"));
+        engine.process(deposit(1, 3, 7)).unwrap();
+        engine
+            .process(Transaction::Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: BigDecimal::from(3),
+                wallet: None,
+            })
+            .unwrap();
+        // the dispute comes from another source than the deposit, the chargeback is b's
+        engine
+            .process(Transaction::Dispute { client: 2, tx: 2 })
+            .unwrap();
+        engine
+            .process(Transaction::Chargeback { client: 2, tx: 2 })
+            .unwrap();
+        engine.set_source(None);
+        engine.adjust(1, BigDecimal::from(1)).unwrap();
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), Default::default()).unwrap();
+        let summaries = summarize(&restored);
+        assert_eq!(
+            summaries
+                .iter()
+                .map(|summary| (
+                    summary.source.as_str(),
+                    summary.deposits,
+                    summary.charged_back.as_str(),
+                    summary.net.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("", 0, "0.0000", "1.0000"),
+                ("acquirer-a", 2, "0.0000", "15.0000"),
+                ("acquirer-b", 1, "5.0000", "-1.0000"),
+            ]
+        );
+        assert_eq!(summaries[2].withdrawn, "3.0000");
+    }
+}
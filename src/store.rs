@@ -0,0 +1,437 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::amount::CompactAmount;
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::{ClientId, Transaction, TransactionType};
+
+/// The transactions an account has stored, rejected ones included, for disputes to look up.
+/// They're kept as fixed size records in one slab, addressed by their index, with the amount
+/// in the record as a `CompactAmount`, instead of a `Transaction` each with its own heap
+/// allocated amount. The digits of the odd amount that doesn't fit are packed into a single
+/// buffer. Transactions are built again on the way out
+#[derive(Clone)]
+pub struct TransactionStore {
+    client: ClientId,
+    records: Vec<Record>,
+    /// The unscaled amounts that aren't compact, as little endian two's complement
+    digits: Vec<u8>,
+    /// The txs whose transactions were dropped with `prune`
+    pruned: HashSet<u32>,
+    /// The txs of the withdrawals a reversal undid, see `reverse`
+    reversed: HashSet<u32>,
+    /// The names of the wallets the records are in, see `Record::wallet`
+    wallets: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    kind: TransactionType,
+    /// 0 for the main wallet, otherwise the index in `wallets` plus one. Fits next to `kind`
+    /// without making the record bigger
+    wallet: u16,
+    tx: u32,
+    /// When it was stored, unix seconds
+    at: u64,
+    amount: StoredAmount,
+}
+
+#[derive(Clone, Copy)]
+enum StoredAmount {
+    None,
+    Compact(CompactAmount),
+    /// Where the amount's digits are in `digits`
+    Digits {
+        start: u32,
+        len: u32,
+        scale: i64,
+    },
+}
+
+impl TransactionStore {
+    pub fn new(client: ClientId) -> Self {
+        Self {
+            client,
+            records: vec![],
+            digits: vec![],
+            pruned: HashSet::new(),
+            reversed: HashSet::new(),
+            wallets: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Stores the transaction at time `at`, it's at index `len() - 1` afterwards
+    pub fn push(&mut self, transaction: &Transaction, at: u64) {
+        let kind = match transaction {
+            Transaction::Deposit { .. } => TransactionType::Deposit,
+            Transaction::Withdrawal { .. } => TransactionType::Withdrawal,
+            Transaction::Dispute { .. } => TransactionType::Dispute,
+            Transaction::Resolve { .. } => TransactionType::Resolve,
+            Transaction::Chargeback { .. } => TransactionType::Chargeback,
+            Transaction::Transfer { .. }
+            | Transaction::EscrowOpen { .. }
+            | Transaction::EscrowRelease { .. }
+            | Transaction::EscrowRefund { .. }
+            | Transaction::Approve { .. }
+            | Transaction::Deny { .. }
+            | Transaction::Reversal { .. }
+            | Transaction::WalletTransfer { .. }
+            | Transaction::PromoCredit { .. }
+            | Transaction::Custom { .. } => {
+                unreachable!(
+                    "transfers, escrows, approvals, reversals, promotions and custom transactions \
+                     aren't stored"
+                )
+            }
+        };
+        let wallet = transaction
+            .wallet()
+            .map_or(0, |wallet| self.wallet_index(wallet));
+        let amount = match transaction.amount() {
+            None => StoredAmount::None,
+            Some(amount) => match CompactAmount::new(amount) {
+                Some(compact) => StoredAmount::Compact(compact),
+                None => {
+                    let start = self.digits.len();
+                    let (digits, scale) = amount.as_bigint_and_exponent();
+                    self.digits.extend(digits.to_signed_bytes_le());
+                    // 4 GiB of digits is far beyond what fits in memory next to the rest
+                    let offset =
+                        |offset: usize| u32::try_from(offset).expect("transaction store is full");
+                    StoredAmount::Digits {
+                        start: offset(start),
+                        len: offset(self.digits.len() - start),
+                        scale,
+                    }
+                }
+            },
+        };
+        self.records.push(Record {
+            kind,
+            wallet,
+            tx: transaction.tx(),
+            at,
+            amount,
+        });
+    }
+
+    fn wallet_index(&mut self, wallet: &str) -> u16 {
+        let index = match self.wallets.iter().position(|known| known == wallet) {
+            Some(index) => index,
+            None => {
+                self.wallets.push(wallet.to_string());
+                self.wallets.len() - 1
+            }
+        };
+        u16::try_from(index + 1).expect("fewer than 65535 wallets per account")
+    }
+
+    /// When the oldest transaction still stored was, unix seconds
+    pub fn first_stored_at(&self) -> Option<u64> {
+        self.records.first().map(|record| record.at)
+    }
+
+    /// Treats everything as stored at `at`, for snapshots from before the times were kept
+    pub fn restamp(&mut self, at: u64) {
+        for record in &mut self.records {
+            record.at = at;
+        }
+    }
+
+    /// Whether the transactions of `tx` were stored but have been pruned since
+    pub fn is_pruned(&self, tx: u32) -> bool {
+        self.pruned.contains(&tx)
+    }
+
+    /// Drops every stored transaction of the txs, `is_pruned` remembers that they were there.
+    /// The indexes of the transactions stored after them change. The digits of amounts that
+    /// weren't compact stay in the buffer, there are too few of them to be worth moving the rest
+    pub fn prune(&mut self, txs: &[u32]) {
+        if txs.is_empty() {
+            return;
+        }
+        self.pruned.extend(txs);
+        let pruned = &self.pruned;
+        self.records.retain(|record| !pruned.contains(&record.tx));
+        self.reversed.retain(|tx| !pruned.contains(tx));
+    }
+
+    /// Marks the withdrawal `tx` as undone by a reversal, it's still stored
+    pub fn reverse(&mut self, tx: u32) {
+        self.reversed.insert(tx);
+    }
+
+    /// Whether the withdrawal `tx` was undone by a reversal
+    pub fn is_reversed(&self, tx: u32) -> bool {
+        self.reversed.contains(&tx)
+    }
+
+    /// The txs of the deposits and withdrawals stored before `before`, oldest first, up to the
+    /// first one that has to be kept
+    pub fn stored_before(&self, before: u64, keep: impl Fn(u32) -> bool) -> Vec<u32> {
+        self.records
+            .iter()
+            .take_while(|record| record.at < before)
+            .filter(|record| {
+                matches!(
+                    record.kind,
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                )
+            })
+            .map(|record| record.tx)
+            .take_while(|&tx| !keep(tx))
+            .collect()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Transaction> {
+        self.records.get(index).map(|record| self.build(record))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Transaction> + '_ {
+        self.records.iter().map(|record| self.build(record))
+    }
+
+    /// The first transaction stored with `tx`, the one disputes and reversals refer to, and
+    /// when it was stored. A withdrawal that had to be approved is stored when it was
+    pub fn original(&self, tx: u32) -> Option<(Transaction, u64)> {
+        self.records
+            .iter()
+            .find(|record| record.tx == tx)
+            .map(|record| (self.build(record), record.at))
+    }
+
+    /// Every stored transaction that refers to `tx`, oldest first. Only these are built
+    pub fn with_tx(&self, tx: u32) -> impl Iterator<Item = Transaction> + '_ {
+        self.records
+            .iter()
+            .filter(move |record| record.tx == tx)
+            .map(|record| self.build(record))
+    }
+
+    /// Adds the transactions of another store, e.g. when merging accounts
+    pub fn extend(&mut self, other: &Self) {
+        let offset = u32::try_from(self.digits.len()).expect("transaction store is full");
+        self.digits.extend_from_slice(&other.digits);
+        let wallets = other
+            .wallets
+            .iter()
+            .map(|wallet| self.wallet_index(wallet))
+            .collect::<Vec<_>>();
+        self.records.extend(other.records.iter().map(|record| {
+            let wallet = match record.wallet {
+                0 => 0,
+                wallet => wallets[usize::from(wallet) - 1],
+            };
+            match record.amount {
+                StoredAmount::Digits { start, len, scale } => Record {
+                    amount: StoredAmount::Digits {
+                        start: start + offset,
+                        len,
+                        scale,
+                    },
+                    wallet,
+                    ..*record
+                },
+                _ => Record { wallet, ..*record },
+            }
+        }));
+        self.reversed.extend(&other.reversed);
+    }
+
+    /// Written as a `Vec<Transaction>` and when they were stored, snapshots don't depend on
+    /// how they're kept
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.length(self.len());
+        for transaction in self.iter() {
+            transaction.encode(encoder);
+        }
+        encoder.length(self.len());
+        for record in &self.records {
+            record.at.encode(encoder);
+        }
+        let mut pruned = self.pruned.iter().copied().collect::<Vec<_>>();
+        pruned.sort_unstable();
+        pruned.encode(encoder);
+        let mut reversed = self.reversed.iter().copied().collect::<Vec<_>>();
+        reversed.sort_unstable();
+        reversed.encode(encoder);
+    }
+
+    /// Not a `Snapshot`, decoding needs to know whose transactions they are
+    pub fn decode(decoder: &mut Decoder<'_>, client: ClientId) -> anyhow::Result<Self> {
+        let mut store = Self::new(client);
+        let transactions = Vec::<Transaction>::decode(decoder)?;
+        // older snapshots don't have the times, see `restamp`
+        let times = if decoder.version() >= 3 {
+            Vec::<u64>::decode(decoder)?
+        } else {
+            vec![0; transactions.len()]
+        };
+        if times.len() != transactions.len() {
+            anyhow::bail!(
+                "{} transactions of client {} with {} times",
+                transactions.len(),
+                client,
+                times.len()
+            );
+        }
+        for (transaction, at) in transactions.iter().zip(times) {
+            if transaction.client() != client {
+                anyhow::bail!(
+                    "Transaction {} of client {} is stored with client {}",
+                    transaction.tx(),
+                    transaction.client(),
+                    client
+                );
+            }
+            store.push(transaction, at);
+        }
+        if decoder.version() >= 3 {
+            store.pruned = Vec::<u32>::decode(decoder)?.into_iter().collect();
+        }
+        // older snapshots only have the reversals in the ledger, see `Account::decode`
+        if decoder.version() >= 21 {
+            store.reversed = Vec::<u32>::decode(decoder)?.into_iter().collect();
+        }
+        Ok(store)
+    }
+
+    fn build(&self, record: &Record) -> Transaction {
+        let (client, tx) = (self.client, record.tx);
+        let amount = || match record.amount {
+            StoredAmount::Compact(compact) => compact.to_decimal(),
+            StoredAmount::Digits { start, len, scale } => {
+                let digits = &self.digits[start as usize..(start + len) as usize];
+                BigDecimal::new(BigInt::from_signed_bytes_le(digits), scale)
+            }
+            // only deposits and withdrawals are built with an amount, and they always have one
+            StoredAmount::None => BigDecimal::zero(),
+        };
+        let wallet = || match record.wallet {
+            0 => None,
+            wallet => Some(self.wallets[usize::from(wallet) - 1].clone()),
+        };
+        match record.kind {
+            TransactionType::Deposit => Transaction::Deposit {
+                client,
+                tx,
+                amount: amount(),
+                wallet: wallet(),
+            },
+            TransactionType::Withdrawal => Transaction::Withdrawal {
+                client,
+                tx,
+                amount: amount(),
+                wallet: wallet(),
+            },
+            TransactionType::Dispute => Transaction::Dispute { client, tx },
+            TransactionType::Resolve => Transaction::Resolve { client, tx },
+            TransactionType::Chargeback => Transaction::Chargeback { client, tx },
+            TransactionType::Transfer
+            | TransactionType::EscrowOpen
+            | TransactionType::EscrowRelease
+            | TransactionType::EscrowRefund
+            | TransactionType::Approve
+            | TransactionType::Deny
+            | TransactionType::Reversal
+            | TransactionType::WalletTransfer
+            | TransactionType::PromoCredit
+            | TransactionType::Opening
+            | TransactionType::Custom => {
+                unreachable!(
+                    "transfers, escrows, approvals, reversals, promotions, openings and custom \
+                     transactions aren't stored"
+                )
+            }
+        }
+    }
+}
+
+impl HeapSize for TransactionStore {
+    fn heap_size(&self) -> usize {
+        self.records.len() * size_of::<Record>()
+            + self.digits.len()
+            + (self.pruned.len() + self.reversed.len()) * size_of::<u32>()
+            + self.wallets.iter().map(String::capacity).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn should_give_back_what_was_stored() {
+        let amount = |amount| BigDecimal::from_str(amount).unwrap();
+        let mut store = TransactionStore::new(7);
+        store.push(
+            &Transaction::Deposit {
+                client: 7,
+                tx: 1,
+                amount: amount("1.5000"),
+                wallet: None,
+            },
+            0,
+        );
+        store.push(
+            &Transaction::Withdrawal {
+                client: 7,
+                tx: 2,
+                amount: amount("-123456789012345678901234567890.0001"),
+                wallet: None,
+            },
+            0,
+        );
+        store.push(&Transaction::Dispute { client: 7, tx: 1 }, 0);
+
+        let mut other = TransactionStore::new(7);
+        other.push(
+            &Transaction::Deposit {
+                client: 7,
+                tx: 3,
+                amount: amount("0"),
+                wallet: Some("savings".to_string()),
+            },
+            0,
+        );
+        store.extend(&other);
+
+        let described = store
+            .iter()
+            .map(|transaction| format!("{:?}", transaction))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            described,
+            [
+                r#"Deposit { client: 7, tx: 1, amount: BigDecimal("1.5000"), wallet: None }"#,
+                r#"Withdrawal { client: 7, tx: 2, amount: BigDecimal("-123456789012345678901234567890.0001"), wallet: None }"#,
+                "Dispute { client: 7, tx: 1 }",
+                r#"Deposit { client: 7, tx: 3, amount: BigDecimal("0"), wallet: Some("savings") }"#,
+            ]
+        );
+        assert_eq!(store.with_tx(1).count(), 2);
+        assert_eq!(store.get(3).unwrap().tx(), 3);
+        assert!(store.get(4).is_none());
+        assert_eq!(store.heap_size(), 4 * size_of::<Record>() + 14 + 7);
+
+        assert_eq!(store.stored_before(1, |tx| tx == 3), [1, 2]);
+        store.prune(&[1]);
+        assert!(store.is_pruned(1));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(0).unwrap().tx(), 2);
+    }
+}
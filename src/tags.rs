@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Read;
+
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::transaction::ClientId;
+use crate::transaction_engine::TransactionEngine;
+
+/// Labels like `vip`, `test` or `merchant` attached to accounts, they don't change how
+/// transactions are processed but can be used to filter and aggregate the outputs
+#[derive(Clone, Debug, Default)]
+pub struct AccountTags {
+    tags: HashMap<ClientId, BTreeSet<String>>,
+}
+
+#[derive(Deserialize)]
+struct CsvTag {
+    client: ClientId,
+    tag: String,
+}
+
+impl AccountTags {
+    /// Reads a `client,tag` csv, one row per tag
+    pub fn read_csv(reader: impl Read) -> Result<Self> {
+        let mut tags = Self::default();
+        for row in crate::transaction::csv_reader(reader).deserialize() {
+            let CsvTag { client, tag } = row?;
+            tags.add(client, tag);
+        }
+        Ok(tags)
+    }
+
+    pub fn add(&mut self, client: ClientId, tag: impl Into<String>) {
+        self.tags.entry(client).or_default().insert(tag.into());
+    }
+
+    /// Returns whether the client had the tag
+    pub fn remove(&mut self, client: ClientId, tag: &str) -> bool {
+        self.tags
+            .get_mut(&client)
+            .is_some_and(|tags| tags.remove(tag))
+    }
+
+    /// The client's tags, sorted
+    pub fn get(&self, client: ClientId) -> impl Iterator<Item = &str> {
+        self.tags
+            .get(&client)
+            .into_iter()
+            .flat_map(|tags| tags.iter().map(String::as_str))
+    }
+
+    pub fn has(&self, client: ClientId, tag: &str) -> bool {
+        self.tags
+            .get(&client)
+            .is_some_and(|tags| tags.contains(tag))
+    }
+
+    /// Adds all of other's tags
+    pub fn merge(&mut self, other: Self) {
+        for (client, tags) in other.tags {
+            self.tags.entry(client).or_default().extend(tags);
+        }
+    }
+}
+
+impl Snapshot for AccountTags {
+    fn encode(&self, encoder: &mut Encoder) {
+        let mut clients = self.tags.keys().copied().collect::<Vec<_>>();
+        clients.sort_unstable();
+        encoder.length(clients.len());
+        for client in clients {
+            Client(client).encode(encoder);
+            self.get(client)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+                .encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        let tags = Vec::<(Client, Vec<String>)>::decode(decoder)?
+            .into_iter()
+            .map(|(Client(client), tags)| (client, tags.into_iter().collect()))
+            .collect();
+        Ok(Self { tags })
+    }
+}
+
+/// Totals of the accounts with a tag, an account with several tags counts towards each of them
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvTagSummary {
+    pub tag: String,
+    pub accounts: usize,
+    pub locked: usize,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+}
+
+/// One summary per tag in use by an account the engine knows about, sorted by tag
+pub fn summarize(engine: &TransactionEngine) -> Vec<CsvTagSummary> {
+    #[derive(Default)]
+    struct Totals {
+        accounts: usize,
+        locked: usize,
+        available: BigDecimal,
+        total: BigDecimal,
+    }
+
+    let mut totals = BTreeMap::<&str, Totals>::new();
+    for account in engine.account_views() {
+        for tag in engine.tags().get(account.client()) {
+            let totals = totals.entry(tag).or_default();
+            totals.accounts += 1;
+            totals.locked += usize::from(account.locked());
+            totals.available += account.available();
+            totals.total += account.total();
+        }
+    }
+    totals
+        .into_iter()
+        .map(|(tag, totals)| CsvTagSummary {
+            tag: tag.to_string(),
+            accounts: totals.accounts,
+            locked: totals.locked,
+            available: format!("{:.4}", totals.available),
+            held: format!("{:.4}", &totals.total - &totals.available),
+            total: format!("{:.4}", totals.total),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn should_summarize_accounts_by_tag() {
+        let mut engine = TransactionEngine::new();
+        for (client, tx, amount) in [(1, 1, 10), (2, 2, 5), (3, 3, 1)] {
+            let _ = engine.process(Transaction::Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(amount),
+                wallet: None,
+            });
+        }
+        let _ = engine.process(Transaction::Dispute { client: 2, tx: 2 });
+        *engine.tags_mut() =
+            AccountTags::read_csv("client,tag\n1,vip\n2,vip\n2,merchant\n4,vip\n".as_bytes())
+                .unwrap();
+
+        let summary = summarize(&engine);
+
+        assert_eq!(
+            summary,
+            vec![
+                CsvTagSummary {
+                    tag: "merchant".to_string(),
+                    accounts: 1,
+                    locked: 0,
+                    available: "0.0000".to_string(),
+                    held: "5.0000".to_string(),
+                    total: "5.0000".to_string(),
+                },
+                CsvTagSummary {
+                    tag: "vip".to_string(),
+                    accounts: 2,
+                    locked: 0,
+                    available: "10.0000".to_string(),
+                    held: "5.0000".to_string(),
+                    total: "15.0000".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,416 @@
+use std::fs::File;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+use crate::json::Json;
+use crate::sha256::{hex, Sha256};
+
+/// Where a span is in a distributed trace, as in a W3C `traceparent` header:
+/// `00-<32 hex trace id>-<16 hex span id>-<2 hex flags>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// Bit 0 is whether the caller samples the trace
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// The first span of a new trace, sampled
+    pub fn root() -> Self {
+        let mut trace_id = [0; 16];
+        random(&mut trace_id);
+        Self {
+            trace_id,
+            span_id: span_id(),
+            flags: 1,
+        }
+    }
+
+    pub fn parse(traceparent: &str) -> Result<Self> {
+        let parts = traceparent.trim().split('-').collect::<Vec<_>>();
+        let (trace_id, span_id, flags) = match parts.as_slice() {
+            // later versions may add fields after these
+            [version, trace_id, span_id, flags, ..] if *version != "ff" && version.len() == 2 => {
+                (trace_id, span_id, flags)
+            }
+            _ => bail!("Invalid traceparent {}", traceparent),
+        };
+        let mut context = Self {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            flags: 0,
+        };
+        let mut flag = [0];
+        if !unhex(trace_id, &mut context.trace_id)
+            || !unhex(span_id, &mut context.span_id)
+            || !unhex(flags, &mut flag)
+            || context.trace_id == [0; 16]
+            || context.span_id == [0; 8]
+        {
+            bail!("Invalid traceparent {}", traceparent);
+        }
+        context.flags = flag[0];
+        Ok(context)
+    }
+
+    /// A span below this one, in the same trace
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: span_id(),
+            ..*self
+        }
+    }
+
+    pub fn trace_id(&self) -> String {
+        hex(&self.trace_id)
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.flags
+        )
+    }
+}
+
+/// A timed operation of a trace, e.g. a request or a transaction it applied
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub name: String,
+    pub context: TraceContext,
+    pub parent: Option<[u8; 8]>,
+    /// Unix nanoseconds
+    pub start: u64,
+    pub end: u64,
+    pub attributes: Vec<(&'static str, Json)>,
+    /// Whatever went wrong, the span is an error if so
+    pub error: Option<String>,
+}
+
+impl Span {
+    /// Starts now, `parent` is the span it's part of, if any, which can be another service's
+    pub fn start(
+        name: impl Into<String>,
+        context: TraceContext,
+        parent: Option<&TraceContext>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            context,
+            parent: parent.map(|parent| parent.span_id),
+            start: unix_nanos(),
+            end: 0,
+            attributes: vec![],
+            error: None,
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.end = unix_nanos().max(self.start);
+    }
+}
+
+/// The spans as an OTLP/HTTP JSON `ExportTraceServiceRequest`, sent to a collector's
+/// `/v1/traces`
+pub fn otlp_traces(service: &str, spans: &[Span]) -> Json {
+    let spans = spans.iter().map(|span| {
+        let mut fields = vec![
+            ("traceId", Json::from(hex(&span.context.trace_id))),
+            ("spanId", Json::from(hex(&span.context.span_id))),
+            ("name", Json::from(span.name.as_str())),
+            // SPAN_KIND_INTERNAL, the propagation is what makes it part of the caller's trace
+            ("kind", Json::from(1_u32)),
+            ("startTimeUnixNano", Json::from(span.start.to_string())),
+            ("endTimeUnixNano", Json::from(span.end.to_string())),
+            (
+                "attributes",
+                Json::Array(
+                    span.attributes
+                        .iter()
+                        .map(|(key, value)| attribute(key, value))
+                        .collect(),
+                ),
+            ),
+        ];
+        if let Some(parent) = span.parent {
+            fields.push(("parentSpanId", Json::from(hex(&parent))));
+        }
+        if let Some(error) = &span.error {
+            fields.push((
+                "status",
+                Json::object([
+                    ("code", Json::from(2_u32)),
+                    ("message", Json::from(error.as_str())),
+                ]),
+            ));
+        }
+        Json::object(fields)
+    });
+    Json::object([(
+        "resourceSpans",
+        Json::array([Json::object([
+            ("resource", resource(service)),
+            (
+                "scopeSpans",
+                Json::array([Json::object([
+                    ("scope", Json::object([("name", Json::from("playground"))])),
+                    ("spans", Json::Array(spans.collect())),
+                ])]),
+            ),
+        ])]),
+    )])
+}
+
+/// The resource every span and metric belongs to, identified by its `service.name`
+pub fn resource(service: &str) -> Json {
+    Json::object([(
+        "attributes",
+        Json::array([attribute("service.name", &Json::from(service))]),
+    )])
+}
+
+/// An OTLP `KeyValue`, whole numbers are `intValue`s and everything else a string
+pub fn attribute(key: &str, value: &Json) -> Json {
+    let value = match value {
+        Json::Bool(value) => ("boolValue", Json::from(*value)),
+        Json::Number(number) if number.parse::<i64>().is_ok() => {
+            ("intValue", Json::from(number.as_str()))
+        }
+        Json::Number(number) => ("doubleValue", Json::Number(number.clone())),
+        Json::String(value) => ("stringValue", Json::from(value.as_str())),
+        value => ("stringValue", Json::from(value.to_string())),
+    };
+    Json::object([("key", Json::from(key)), ("value", Json::object([value]))])
+}
+
+/// A measurement of a batch run, sent to a collector's `/v1/metrics` with `otlp_metrics`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub value: MetricValue,
+    pub attributes: Vec<(&'static str, Json)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricValue {
+    /// Counted up since the start of the run
+    Count(u64),
+    /// As it is now
+    Gauge(f64),
+}
+
+/// The metrics as an OTLP/HTTP JSON `ExportMetricsServiceRequest`, `start` is when the
+/// counting started in unix nanoseconds. Metrics of the same name are data points of one
+pub fn otlp_metrics(service: &str, start: u64, metrics: &[Metric]) -> Json {
+    let now = unix_nanos().to_string();
+    let mut names = vec![];
+    for metric in metrics {
+        if !names.contains(&metric.name) {
+            names.push(metric.name);
+        }
+    }
+    let metrics = names.into_iter().map(|name| {
+        let points = metrics.iter().filter(|metric| metric.name == name);
+        let first = points.clone().next().expect("every name has a metric");
+        let data_points = Json::Array(
+            points
+                .map(|metric| {
+                    let mut point = vec![
+                        ("startTimeUnixNano", Json::from(start.to_string())),
+                        ("timeUnixNano", Json::from(now.as_str())),
+                        (
+                            "attributes",
+                            Json::Array(
+                                metric
+                                    .attributes
+                                    .iter()
+                                    .map(|(key, value)| attribute(key, value))
+                                    .collect(),
+                            ),
+                        ),
+                    ];
+                    point.push(match metric.value {
+                        MetricValue::Count(count) => ("asInt", Json::from(count.to_string())),
+                        MetricValue::Gauge(value) => ("asDouble", Json::from(value)),
+                    });
+                    Json::object(point)
+                })
+                .collect(),
+        );
+        let data = match first.value {
+            MetricValue::Count(_) => (
+                "sum",
+                Json::object([
+                    ("dataPoints", data_points),
+                    // AGGREGATION_TEMPORALITY_CUMULATIVE
+                    ("aggregationTemporality", Json::from(2_u32)),
+                    ("isMonotonic", Json::from(true)),
+                ]),
+            ),
+            MetricValue::Gauge(_) => ("gauge", Json::object([("dataPoints", data_points)])),
+        };
+        Json::object([
+            ("name", Json::from(name)),
+            ("unit", Json::from(first.unit)),
+            data,
+        ])
+    });
+    Json::object([(
+        "resourceMetrics",
+        Json::array([Json::object([
+            ("resource", resource(service)),
+            (
+                "scopeMetrics",
+                Json::array([Json::object([
+                    ("scope", Json::object([("name", Json::from("playground"))])),
+                    ("metrics", Json::Array(metrics.collect())),
+                ])]),
+            ),
+        ])]),
+    )])
+}
+
+pub fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| {
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+        })
+}
+
+fn span_id() -> [u8; 8] {
+    let mut id = [0; 8];
+    random(&mut id);
+    id
+}
+
+/// Ids only have to be unique, not secret, so without /dev/urandom they're hashed from the
+/// time and a counter
+fn random(bytes: &mut [u8]) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let read = File::open("/dev/urandom").and_then(|mut random| random.read_exact(bytes));
+    if read.is_err() || bytes.iter().all(|&byte| byte == 0) {
+        let mut hasher = Sha256::new();
+        hasher.update(&unix_nanos().to_le_bytes());
+        hasher.update(&COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+        hasher.update(&std::process::id().to_le_bytes());
+        let digest = hasher.finish();
+        bytes.copy_from_slice(&digest[..bytes.len()]);
+    }
+}
+
+fn unhex(text: &str, bytes: &mut [u8]) -> bool {
+    if !text.is_ascii() || text.len() != bytes.len() * 2 {
+        return false;
+    }
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        match u8::from_str_radix(&text[index * 2..index * 2 + 2], 16) {
+            Ok(value) => *byte = value,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_continue_the_callers_trace() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(traceparent).unwrap();
+        assert_eq!(context.traceparent(), traceparent);
+        let child = context.child();
+        assert_eq!(child.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(child.span_id, context.span_id);
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473x-00f067aa0ba902b7-01",
+        ] {
+            assert!(TraceContext::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let mut span = Span::start("process", child, Some(&context));
+        span.attributes.push(("client", Json::from(1_u16)));
+        span.error = Some("insufficient funds".to_string());
+        span.finish();
+        let json = otlp_traces("playground", &[span]).to_string();
+        assert!(json.contains(r#""parentSpanId":"00f067aa0ba902b7""#));
+        assert!(json.contains(r#"{"key":"client","value":{"intValue":"1"}}"#));
+        assert!(json.contains(r#""status":{"code":2,"message":"insufficient funds"}"#));
+
+        let reject = |reason, count| Metric {
+            name: "playground.rejected",
+            unit: "{row}",
+            value: MetricValue::Count(count),
+            attributes: vec![("reason", Json::from(reason))],
+        };
+        let json = otlp_metrics("playground", 0, &[reject("a", 1), reject("b", 2)]).to_string();
+        assert_eq!(json.matches(r#""name":"playground.rejected""#).count(), 1);
+        assert!(json.contains(
+            r#""attributes":[{"key":"reason","value":{"stringValue":"b"}}],"asInt":"2""#
+        ));
+    }
+
+    #[test]
+    fn should_export_counts_as_sums_and_the_rest_as_gauges() {
+        let metrics = [
+            Metric {
+                name: "playground.rows",
+                unit: "{row}",
+                value: MetricValue::Count(10),
+                attributes: vec![],
+            },
+            Metric {
+                name: "playground.memory",
+                unit: "By",
+                value: MetricValue::Gauge(2048.0),
+                attributes: vec![("kind", Json::from("rss"))],
+            },
+            Metric {
+                name: "playground.memory",
+                unit: "By",
+                value: MetricValue::Gauge(512.0),
+                attributes: vec![("kind", Json::from("engine"))],
+            },
+        ];
+        let json = otlp_metrics("playground", 42, &metrics);
+        let field = |json: &Json, key| json.get(key).cloned().unwrap_or(Json::Null);
+        let list = |json: &Json, key| match json.get(key) {
+            Some(Json::Array(items)) => items.clone(),
+            _ => vec![],
+        };
+        let scope = &list(&list(&json, "resourceMetrics")[0], "scopeMetrics")[0];
+        let metrics = list(scope, "metrics");
+        assert_eq!(metrics.len(), 2);
+
+        let (rows, memory) = (&metrics[0], &metrics[1]);
+        assert_eq!(field(rows, "name"), Json::from("playground.rows"));
+        let sum = field(rows, "sum");
+        assert_eq!(field(&sum, "aggregationTemporality"), Json::from(2_u32));
+        assert_eq!(field(&sum, "isMonotonic"), Json::from(true));
+        let point = &list(&sum, "dataPoints")[0];
+        assert_eq!(field(point, "asInt"), Json::from("10"));
+        assert_eq!(field(point, "startTimeUnixNano"), Json::from("42"));
+
+        assert_eq!(field(memory, "unit"), Json::from("By"));
+        let points = list(&field(memory, "gauge"), "dataPoints");
+        let values = points
+            .iter()
+            .map(|point| field(point, "asDouble"))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [Json::from(2048.0), Json::from(512.0)]);
+    }
+}
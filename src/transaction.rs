@@ -1,20 +1,290 @@
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context};
 use bigdecimal::BigDecimal;
-use serde::Deserialize;
+use csv::{StringRecord, Trim};
+use serde::de::value::StrDeserializer;
+use serde::de::{self, IntoDeserializer};
 use serde::Serialize;
+use serde::{Deserialize, Deserializer};
+
+use crate::amount::{AmountFormat, Scaled};
+use crate::dispute::Case;
+use crate::errors::ErrorCode;
+use crate::lock::LockState;
+use crate::memory::HeapSize;
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::transaction_engine::TransactionError;
 
+/// 16 bits unless a wider one is asked for with the client-id-u32 or client-id-u64 feature
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
 pub type ClientId = u16;
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+pub type ClientId = u32;
+#[cfg(feature = "client-id-u64")]
+pub type ClientId = u64;
+
+/// A csv reader for transaction files, which tend to have whitespace around the values
+pub fn csv_reader<R: Read>(reader: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader)
+}
+
+/// Reads the transactions of a csv, with its amounts written in `format`
+pub fn read_transactions<R: Read>(
+    reader: R,
+    format: AmountFormat,
+) -> impl Iterator<Item = anyhow::Result<RawTransaction>> {
+    let mut reader = csv_reader(reader);
+    let (parser, error) = match reader.headers() {
+        Ok(headers) => (RowParser::new(headers.clone(), format), None),
+        Err(e) => (
+            RowParser::new(StringRecord::new(), format),
+            Some(anyhow::Error::new(ErrorCode::MalformedRow.error(e))),
+        ),
+    };
+    let records = error.is_none().then(|| reader.into_records());
+    error.map(Err).into_iter().chain(
+        records
+            .into_iter()
+            .flatten()
+            .map(move |record| parser.parse(record.map_err(|e| ErrorCode::MalformedRow.error(e))?)),
+    )
+}
+
+/// Writes the transactions as a csv `read_transactions` reads back, e.g. to post them to a
+/// server
+pub fn write_transactions<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    writer: impl Write,
+) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "from_pocket",
+        "to_pocket",
+        "seller",
+        "wallet",
+        "from_wallet",
+        "to_wallet",
+        "expires_at",
+    ])?;
+    for transaction in transactions {
+        let (from, to, seller) = match transaction {
+            Transaction::Transfer { from, to, .. } => (from.clone(), to.clone(), None),
+            Transaction::EscrowOpen { seller, .. } => (None, None, Some(seller.to_string())),
+            _ => (None, None, None),
+        };
+        let (from_wallet, to_wallet) = match transaction {
+            Transaction::WalletTransfer { from, to, .. } => (from.clone(), to.clone()),
+            _ => (None, None),
+        };
+        let expires_at = match transaction {
+            Transaction::PromoCredit { expires_at, .. } => expires_at.to_string(),
+            _ => String::new(),
+        };
+        writer.write_record([
+            transaction.type_name().to_string(),
+            transaction.client().to_string(),
+            transaction.tx().to_string(),
+            transaction
+                .amount()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            from.unwrap_or_default(),
+            to.unwrap_or_default(),
+            seller.unwrap_or_default(),
+            transaction.wallet().unwrap_or_default().to_string(),
+            from_wallet.unwrap_or_default(),
+            to_wallet.unwrap_or_default(),
+            expires_at,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Like `read_transactions`, but the `client` and `seller` columns can have ids of any form,
+/// e.g. UUIDs, and `intern` gives the engine's id for them, see `ClientIds::intern`
+pub fn read_external_transactions<'a, R: Read + 'a>(
+    reader: R,
+    format: AmountFormat,
+    mut intern: impl FnMut(&str) -> anyhow::Result<ClientId> + 'a,
+) -> impl Iterator<Item = anyhow::Result<RawTransaction>> + 'a {
+    let mut reader = csv_reader(reader);
+    let headers = reader.headers().cloned();
+    let columns = headers.as_ref().map_or(vec![], |headers| {
+        headers
+            .iter()
+            .enumerate()
+            .filter(|(_, header)| matches!(*header, "client" | "seller"))
+            .map(|(index, _)| index)
+            .collect()
+    });
+    let (parser, error) = match headers {
+        Ok(headers) => (RowParser::new(headers, format), None),
+        Err(e) => (
+            RowParser::new(StringRecord::new(), format),
+            Some(anyhow::Error::new(ErrorCode::MalformedRow.error(e))),
+        ),
+    };
+    let records = error.is_none().then(|| reader.into_records());
+    error
+        .map(Err)
+        .into_iter()
+        .chain(records.into_iter().flatten().map(move |record| {
+            let record = record.map_err(|e| ErrorCode::MalformedRow.error(e))?;
+            let mut interned = StringRecord::new();
+            for (index, value) in record.iter().enumerate() {
+                match columns.contains(&index) && !value.is_empty() {
+                    true => interned.push_field(&intern(value)?.to_string()),
+                    false => interned.push_field(value),
+                }
+            }
+            interned.set_position(record.position().cloned());
+            parser.parse(interned)
+        }))
+}
+
+/// Turns the records of a transactions csv into transactions, for when the records
+/// are needed too, otherwise `read_transactions` is simpler
+pub struct RowParser {
+    headers: StringRecord,
+    amount: Option<usize>,
+    format: AmountFormat,
+}
+
+impl RowParser {
+    pub fn new(headers: StringRecord, format: AmountFormat) -> Self {
+        let amount = headers.iter().position(|header| header == "amount");
+        Self {
+            headers,
+            amount,
+            format,
+        }
+    }
+
+    pub fn parse(&self, mut record: StringRecord) -> anyhow::Result<RawTransaction> {
+        let line = record.position().map_or(0, csv::Position::line);
+        if let Some(value) = self.amount.and_then(|amount| record.get(amount)) {
+            let normalized = self.format.normalize(value).with_context(|| {
+                ErrorCode::InvalidAmount.error(format!("Invalid amount on line {}", line))
+            })?;
+            if let Cow::Owned(normalized) = normalized {
+                let position = record.position().cloned();
+                record = record
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| match Some(index) == self.amount {
+                        true => normalized.as_str(),
+                        false => value,
+                    })
+                    .collect();
+                // so parse errors still point at the line
+                record.set_position(position);
+            }
+        }
+        let mut raw: RawTransaction = record
+            .deserialize(Some(&self.headers))
+            .map_err(|e| ErrorCode::MalformedRow.error(e))?;
+        if let (Some(scale), Some(amount)) = (self.format.scale(), &raw.amount) {
+            match scale.limit(amount) {
+                Scaled::Fits => {}
+                Scaled::Adjusted(adjusted) => raw.original_amount = raw.amount.replace(adjusted),
+                Scaled::Rejected => raw.excess_decimals = true,
+            }
+        }
+        Ok(raw)
+    }
+}
 
 #[derive(Debug, Deserialize)]
 #[allow(clippy::module_name_repetitions)]
 pub struct RawTransaction {
     #[serde(rename = "type")]
-    transaction_type: TransactionType,
+    transaction_type: RawType,
     client: ClientId,
     tx: u32,
-    amount: BigDecimal,
+    /// Disputes, resolves and chargebacks leave this empty
+    #[serde(default, deserialize_with = "decimal")]
+    amount: Option<BigDecimal>,
+    /// Unix timestamp (seconds), optional column that makes the engine time-aware
+    timestamp: Option<u64>,
+    /// The pockets a transfer moves money between, empty for the available balance
+    from_pocket: Option<String>,
+    to_pocket: Option<String>,
+    /// Who an `escrow_open` pays once it's released
+    seller: Option<ClientId>,
+    /// The wallet a deposit or withdrawal is in, empty for the account's main one
+    wallet: Option<String>,
+    /// The wallets a wallet transfer moves money between, empty for the main one
+    from_wallet: Option<String>,
+    to_wallet: Option<String>,
+    /// Unix timestamp (seconds) a promotional credit expires at
+    expires_at: Option<u64>,
+    /// The amount as it was in the input, if it had to be adjusted to the scale
+    #[serde(skip)]
+    original_amount: Option<BigDecimal>,
+    /// The amount has more decimals than the scale allows, so the transaction is rejected
+    #[serde(skip)]
+    excess_decimals: bool,
 }
 
-#[derive(Debug, Deserialize)]
+/// Parses the amount from its text. `BigDecimal` on its own would take what csv makes of it, an
+/// `f64` that loses digits and can't hold e.g. `1e400`
+fn decimal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BigDecimal>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|amount| amount.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+impl RawTransaction {
+    pub const fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub const fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    pub const fn transaction_type(&self) -> TransactionType {
+        match self.transaction_type {
+            RawType::Builtin(transaction_type) => transaction_type,
+            RawType::Custom(_) => TransactionType::Custom,
+        }
+    }
+
+    /// The type column of a transaction of a kind registered with a `TransactionHandler`
+    pub fn custom_type(&self) -> Option<&str> {
+        match &self.transaction_type {
+            RawType::Builtin(_) => None,
+            RawType::Custom(kind) => Some(kind),
+        }
+    }
+
+    pub const fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub const fn amount(&self) -> Option<&BigDecimal> {
+        self.amount.as_ref()
+    }
+
+    pub const fn original_amount(&self) -> Option<&BigDecimal> {
+        self.original_amount.as_ref()
+    }
+
+    pub const fn excess_decimals(&self) -> bool {
+        self.excess_decimals
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
 #[serde(rename_all = "lowercase")]
 #[allow(clippy::module_name_repetitions)]
 pub enum TransactionType {
@@ -23,19 +293,58 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
+    #[serde(rename = "escrow_open")]
+    EscrowOpen,
+    #[serde(rename = "escrow_release")]
+    EscrowRelease,
+    #[serde(rename = "escrow_refund")]
+    EscrowRefund,
+    Approve,
+    Deny,
+    #[serde(rename = "wallet_transfer")]
+    WalletTransfer,
+    #[serde(rename = "promo_credit")]
+    PromoCredit,
+    Reversal,
+    /// A deposit of the client's opening balance, see `TransactionEngine::process_opening`
+    Opening,
+    /// Any other type, for a `TransactionHandler`, see `RawTransaction::custom_type`
+    #[serde(skip)]
+    Custom,
+}
+
+impl TransactionType {
+    /// The built in type a type column of `name` is, None for the ones left to the handlers
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: StrDeserializer<'_, serde::de::value::Error> = name.into_deserializer();
+        Self::deserialize(name).ok()
+    }
 }
 
-#[derive(Debug)]
+/// The type column, anything that isn't one of the built in types is left to the handlers
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawType {
+    Builtin(TransactionType),
+    Custom(String),
+}
+
+#[derive(Clone, Debug)]
 pub enum Transaction {
+    /// `wallet` is one of the client's wallets, `None` for the main one the account's available
+    /// and held are, see `WalletTransfer`
     Deposit {
         client: ClientId,
         tx: u32,
         amount: BigDecimal,
+        wallet: Option<String>,
     },
     Withdrawal {
         client: ClientId,
         tx: u32,
         amount: BigDecimal,
+        wallet: Option<String>,
     },
     Dispute {
         client: ClientId,
@@ -49,6 +358,75 @@ pub enum Transaction {
         client: ClientId,
         tx: u32,
     },
+    /// Moves money between the available balance and the account's named pockets, `None`
+    /// is the available balance. Pockets are part of the total, it doesn't change
+    Transfer {
+        client: ClientId,
+        tx: u32,
+        amount: BigDecimal,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    /// Holds the amount in the client's account for the seller, see `escrow`
+    EscrowOpen {
+        client: ClientId,
+        tx: u32,
+        amount: BigDecimal,
+        seller: ClientId,
+    },
+    /// Pays the seller of the client's escrow `tx`
+    EscrowRelease {
+        client: ClientId,
+        tx: u32,
+    },
+    /// Gives the client the money of its escrow `tx` back
+    EscrowRefund {
+        client: ClientId,
+        tx: u32,
+    },
+    /// Settles the client's withdrawal `tx` that waits for an approval, see
+    /// `EngineConfig::approvals`
+    Approve {
+        client: ClientId,
+        tx: u32,
+    },
+    /// Drops the client's withdrawal `tx` that waits for an approval, its amount is available
+    /// again
+    Deny {
+        client: ClientId,
+        tx: u32,
+    },
+    /// Undoes the client's withdrawal `tx` within the grace window after it, e.g. a payout
+    /// made by mistake, see `EngineConfig::reversal_window`
+    Reversal {
+        client: ClientId,
+        tx: u32,
+    },
+    /// Moves available money between the client's wallets, `None` is the main one. Wallets have
+    /// balances of their own, a dispute holds the money in the wallet of the disputed tx
+    WalletTransfer {
+        client: ClientId,
+        tx: u32,
+        amount: BigDecimal,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    /// Promotional money that's gone at `expires_at` (unix seconds) unless it's spent before.
+    /// It's kept apart from the available balance and withdrawals take it first, see
+    /// `AccountView::promo_credits`
+    PromoCredit {
+        client: ClientId,
+        tx: u32,
+        amount: BigDecimal,
+        expires_at: u64,
+    },
+    /// Applied by the `TransactionHandler` registered for its kind, the engine doesn't store it
+    Custom {
+        kind: String,
+        client: ClientId,
+        tx: u32,
+        amount: Option<BigDecimal>,
+    },
 }
 
 impl Transaction {
@@ -60,6 +438,16 @@ impl Transaction {
             Transaction::Dispute { client, .. } => client,
             Transaction::Resolve { client, .. } => client,
             Transaction::Chargeback { client, .. } => client,
+            Transaction::Transfer { client, .. } => client,
+            Transaction::EscrowOpen { client, .. } => client,
+            Transaction::EscrowRelease { client, .. } => client,
+            Transaction::EscrowRefund { client, .. } => client,
+            Transaction::Approve { client, .. } => client,
+            Transaction::Deny { client, .. } => client,
+            Transaction::Reversal { client, .. } => client,
+            Transaction::WalletTransfer { client, .. } => client,
+            Transaction::PromoCredit { client, .. } => client,
+            Transaction::Custom { client, .. } => client,
         }
     }
 
@@ -71,22 +459,244 @@ impl Transaction {
             Transaction::Dispute { tx, .. } => tx,
             Transaction::Resolve { tx, .. } => tx,
             Transaction::Chargeback { tx, .. } => tx,
+            Transaction::Transfer { tx, .. } => tx,
+            Transaction::EscrowOpen { tx, .. } => tx,
+            Transaction::EscrowRelease { tx, .. } => tx,
+            Transaction::EscrowRefund { tx, .. } => tx,
+            Transaction::Approve { tx, .. } => tx,
+            Transaction::Deny { tx, .. } => tx,
+            Transaction::Reversal { tx, .. } => tx,
+            Transaction::WalletTransfer { tx, .. } => tx,
+            Transaction::PromoCredit { tx, .. } => tx,
+            Transaction::Custom { tx, .. } => tx,
+        }
+    }
+
+    /// The wallet of a deposit or withdrawal, `None` for the main one and other transactions
+    pub fn wallet(&self) -> Option<&str> {
+        match self {
+            Transaction::Deposit { wallet, .. } | Transaction::Withdrawal { wallet, .. } => {
+                wallet.as_deref()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Transaction {
+    /// The name used for this kind of transaction in the csv files
+    pub fn type_name(&self) -> &str {
+        match self {
+            Transaction::Deposit { .. } => "deposit",
+            Transaction::Withdrawal { .. } => "withdrawal",
+            Transaction::Dispute { .. } => "dispute",
+            Transaction::Resolve { .. } => "resolve",
+            Transaction::Chargeback { .. } => "chargeback",
+            Transaction::Transfer { .. } => "transfer",
+            Transaction::EscrowOpen { .. } => "escrow_open",
+            Transaction::EscrowRelease { .. } => "escrow_release",
+            Transaction::EscrowRefund { .. } => "escrow_refund",
+            Transaction::Approve { .. } => "approve",
+            Transaction::Deny { .. } => "deny",
+            Transaction::Reversal { .. } => "reversal",
+            Transaction::WalletTransfer { .. } => "wallet_transfer",
+            Transaction::PromoCredit { .. } => "promo_credit",
+            Transaction::Custom { kind, .. } => kind,
+        }
+    }
+
+    pub const fn amount(&self) -> Option<&BigDecimal> {
+        match self {
+            Transaction::Deposit { amount, .. }
+            | Transaction::Withdrawal { amount, .. }
+            | Transaction::Transfer { amount, .. }
+            | Transaction::EscrowOpen { amount, .. }
+            | Transaction::WalletTransfer { amount, .. }
+            | Transaction::PromoCredit { amount, .. } => Some(amount),
+            Transaction::Custom { amount, .. } => amount.as_ref(),
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::Chargeback { .. }
+            | Transaction::EscrowRelease { .. }
+            | Transaction::EscrowRefund { .. }
+            | Transaction::Approve { .. }
+            | Transaction::Deny { .. }
+            | Transaction::Reversal { .. } => None,
         }
     }
 }
 
-impl From<RawTransaction> for Transaction {
-    fn from(t: RawTransaction) -> Self {
-        match t.transaction_type {
-            TransactionType::Deposit => Self::Deposit {
+impl HeapSize for Transaction {
+    fn heap_size(&self) -> usize {
+        self.amount().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl Snapshot for Transaction {
+    fn encode(&self, encoder: &mut Encoder) {
+        let tag = match self {
+            Transaction::Deposit { .. } => 0,
+            Transaction::Withdrawal { .. } => 1,
+            Transaction::Dispute { .. } => 2,
+            Transaction::Resolve { .. } => 3,
+            Transaction::Chargeback { .. } => 4,
+            Transaction::Custom { .. } => 5,
+            Transaction::Transfer { .. } => 6,
+            Transaction::EscrowOpen { .. } => 7,
+            Transaction::EscrowRelease { .. } => 8,
+            Transaction::EscrowRefund { .. } => 9,
+            Transaction::Approve { .. } => 10,
+            Transaction::WalletTransfer { .. } => 11,
+            Transaction::PromoCredit { .. } => 12,
+            Transaction::Reversal { .. } => 13,
+            Transaction::Deny { .. } => 14,
+        };
+        encoder.tag(tag);
+        Client(self.client()).encode(encoder);
+        self.tx().encode(encoder);
+        match self {
+            // its amount is optional
+            Transaction::Custom { kind, amount, .. } => {
+                kind.encode(encoder);
+                amount.encode(encoder);
+            }
+            Transaction::Transfer {
+                amount, from, to, ..
+            } => {
+                amount.encode(encoder);
+                from.encode(encoder);
+                to.encode(encoder);
+            }
+            Transaction::EscrowOpen { amount, seller, .. } => {
+                amount.encode(encoder);
+                Client(*seller).encode(encoder);
+            }
+            Transaction::Deposit { amount, wallet, .. }
+            | Transaction::Withdrawal { amount, wallet, .. } => {
+                amount.encode(encoder);
+                wallet.encode(encoder);
+            }
+            Transaction::WalletTransfer {
+                amount, from, to, ..
+            } => {
+                amount.encode(encoder);
+                from.encode(encoder);
+                to.encode(encoder);
+            }
+            Transaction::PromoCredit {
+                amount, expires_at, ..
+            } => {
+                amount.encode(encoder);
+                expires_at.encode(encoder);
+            }
+            _ => {
+                if let Some(amount) = self.amount() {
+                    amount.encode(encoder);
+                }
+            }
+        }
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> anyhow::Result<Self> {
+        let tag = decoder.tag()?;
+        let client = Client::decode(decoder)?.0;
+        let tx = u32::decode(decoder)?;
+        let wallet = |decoder: &mut Decoder<'_>| match decoder.version() {
+            ..=16 => Ok(None),
+            _ => Option::decode(decoder),
+        };
+        Ok(match tag {
+            0 => Self::Deposit {
+                client,
+                tx,
+                amount: BigDecimal::decode(decoder)?,
+                wallet: wallet(decoder)?,
+            },
+            1 => Self::Withdrawal {
+                client,
+                tx,
+                amount: BigDecimal::decode(decoder)?,
+                wallet: wallet(decoder)?,
+            },
+            2 => Self::Dispute { client, tx },
+            3 => Self::Resolve { client, tx },
+            4 => Self::Chargeback { client, tx },
+            5 => Self::Custom {
+                kind: String::decode(decoder)?,
+                client,
+                tx,
+                amount: Option::decode(decoder)?,
+            },
+            6 => Self::Transfer {
+                client,
+                tx,
+                amount: BigDecimal::decode(decoder)?,
+                from: Option::decode(decoder)?,
+                to: Option::decode(decoder)?,
+            },
+            7 => Self::EscrowOpen {
+                client,
+                tx,
+                amount: BigDecimal::decode(decoder)?,
+                seller: Client::decode(decoder)?.0,
+            },
+            8 => Self::EscrowRelease { client, tx },
+            9 => Self::EscrowRefund { client, tx },
+            10 => Self::Approve { client, tx },
+            13 => Self::Reversal { client, tx },
+            14 => Self::Deny { client, tx },
+            11 => Self::WalletTransfer {
+                client,
+                tx,
+                amount: BigDecimal::decode(decoder)?,
+                from: Option::decode(decoder)?,
+                to: Option::decode(decoder)?,
+            },
+            12 => Self::PromoCredit {
+                client,
+                tx,
+                amount: BigDecimal::decode(decoder)?,
+                expires_at: u64::decode(decoder)?,
+            },
+            tag => bail!("Invalid transaction type {} in snapshot", tag),
+        })
+    }
+}
+
+impl TryFrom<RawTransaction> for Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(t: RawTransaction) -> Result<Self, Self::Error> {
+        let amount = || match t.amount.clone() {
+            None => Err(ErrorCode::MissingAmount.error(format!(
+                "Transaction {} of client {} is missing an amount",
+                t.tx, t.client
+            ))),
+            Some(amount) => Ok(amount),
+        };
+        let transaction_type = match &t.transaction_type {
+            RawType::Builtin(transaction_type) => *transaction_type,
+            RawType::Custom(kind) => {
+                return Ok(Self::Custom {
+                    kind: kind.clone(),
+                    client: t.client,
+                    tx: t.tx,
+                    amount: t.amount.clone(),
+                })
+            }
+        };
+        Ok(match transaction_type {
+            TransactionType::Deposit | TransactionType::Opening => Self::Deposit {
                 client: t.client,
                 tx: t.tx,
-                amount: t.amount,
+                amount: amount()?,
+                wallet: t.wallet.clone(),
             },
             TransactionType::Withdrawal => Self::Withdrawal {
                 client: t.client,
                 tx: t.tx,
-                amount: t.amount,
+                amount: amount()?,
+                wallet: t.wallet.clone(),
             },
             TransactionType::Dispute => Self::Dispute {
                 client: t.client,
@@ -100,15 +710,204 @@ impl From<RawTransaction> for Transaction {
                 client: t.client,
                 tx: t.tx,
             },
+            TransactionType::Transfer => Self::Transfer {
+                client: t.client,
+                tx: t.tx,
+                amount: amount()?,
+                from: t.from_pocket.clone(),
+                to: t.to_pocket.clone(),
+            },
+            TransactionType::EscrowOpen => Self::EscrowOpen {
+                client: t.client,
+                tx: t.tx,
+                amount: amount()?,
+                seller: match t.seller {
+                    None => {
+                        return Err(ErrorCode::MissingSeller
+                            .error(format!(
+                                "Escrow {} of client {} is missing a seller",
+                                t.tx, t.client
+                            ))
+                            .into())
+                    }
+                    Some(seller) => seller,
+                },
+            },
+            TransactionType::EscrowRelease => Self::EscrowRelease {
+                client: t.client,
+                tx: t.tx,
+            },
+            TransactionType::EscrowRefund => Self::EscrowRefund {
+                client: t.client,
+                tx: t.tx,
+            },
+            TransactionType::Approve => Self::Approve {
+                client: t.client,
+                tx: t.tx,
+            },
+            TransactionType::Deny => Self::Deny {
+                client: t.client,
+                tx: t.tx,
+            },
+            TransactionType::Reversal => Self::Reversal {
+                client: t.client,
+                tx: t.tx,
+            },
+            TransactionType::WalletTransfer => Self::WalletTransfer {
+                client: t.client,
+                tx: t.tx,
+                amount: amount()?,
+                from: t.from_wallet.clone(),
+                to: t.to_wallet.clone(),
+            },
+            TransactionType::PromoCredit => Self::PromoCredit {
+                client: t.client,
+                tx: t.tx,
+                amount: amount()?,
+                expires_at: t.expires_at.ok_or_else(|| {
+                    ErrorCode::MissingExpiry.error(format!(
+                        "Promotional credit {} of client {} is missing an expiry",
+                        t.tx, t.client
+                    ))
+                })?,
+            },
+            TransactionType::Custom => unreachable!("custom types are kept as RawType::Custom"),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CsvAccount {
+    pub client: ClientId,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+impl CsvAccount {
+    /// The account with what it has to keep available, see `EngineConfig::reserves`, and why
+    /// it's locked
+    pub fn extended(self, reserve: &BigDecimal, lock: &LockState) -> CsvExtendedAccount {
+        CsvExtendedAccount {
+            client: self.client,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            reserve: format!("{:.4}", reserve),
+            lock_state: lock.name(),
+            lock_reason: lock.reason().map(str::to_string),
         }
     }
 }
 
+/// The accounts output with `--extended`, a `CsvAccount` with more about the account
 #[derive(Clone, Debug, Serialize, PartialEq)]
-pub struct CsvAccount {
+pub struct CsvExtendedAccount {
     pub client: ClientId,
     pub available: String,
     pub held: String,
     pub total: String,
     pub locked: bool,
+    pub reserve: String,
+    /// `LockState::name`
+    pub lock_state: &'static str,
+    /// What the operator said when they locked it for a review
+    pub lock_reason: Option<String>,
+}
+
+/// What changed about an account between two snapshots, see `TransactionEngine::diff`.
+/// The amounts are the difference, `locked` is only there for accounts that were opened,
+/// locked or unlocked
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvAccountDelta {
+    pub client: ClientId,
+    pub change: &'static str,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: Option<bool>,
+    pub entries: usize,
+}
+
+/// The balance of one of an account's pockets, a row per pocket
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvPocket {
+    pub client: ClientId,
+    pub pocket: String,
+    pub balance: String,
+}
+
+/// The balances of one of an account's wallets, a row per wallet
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvWallet {
+    pub client: ClientId,
+    pub wallet: String,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+}
+
+/// A transaction that the engine refused to apply, and why
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvReject {
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: Option<String>,
+    pub reason: String,
+    /// The `ErrorCode` of the rejection
+    pub code: &'static str,
+}
+
+impl CsvReject {
+    pub fn new(transaction: &Transaction, error: &TransactionError) -> Self {
+        Self {
+            transaction_type: transaction.type_name().to_string(),
+            client: transaction.client(),
+            tx: transaction.tx(),
+            amount: transaction.amount().map(|amount| format!("{:.4}", amount)),
+            reason: error.reason().to_string(),
+            code: error.code().code(),
+        }
+    }
+}
+
+/// A transaction looked up by its tx id, `dispute` is the status of its latest dispute
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvFoundTransaction {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: Option<String>,
+    pub applied: bool,
+    pub dispute: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct CsvCase {
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: String,
+    pub status: &'static str,
+    pub opened_at: u64,
+    pub closed_at: Option<u64>,
+    pub notes: usize,
+}
+
+impl From<&Case> for CsvCase {
+    fn from(case: &Case) -> Self {
+        Self {
+            client: case.client(),
+            tx: case.tx(),
+            amount: format!("{:.4}", case.amount()),
+            status: case.status().name(),
+            opened_at: case.opened_at(),
+            closed_at: case.closed_at(),
+            notes: case.notes().len(),
+        }
+    }
 }
@@ -1,265 +1,3571 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use bigdecimal::{BigDecimal, Zero};
+use anyhow::Context;
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
 
-use crate::transaction::{ClientId, CsvAccount};
-use crate::Transaction;
+use crate::alerts::AlertObserver;
+use crate::approval::PendingWithdrawal;
+use crate::client_ids::ClientIds;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Compat, EngineConfig, OpeningPolicy, Pruning};
+use crate::diagnostics::{
+    DiagnosticsObserver, EnrichmentFailed, Pruned, SlowTransaction, UnrecordedScreening,
+};
+use crate::dispute::{Case, CaseStatus, DisputeStats};
+use crate::enrichment::{Enricher, Enrichment};
+use crate::errors::ErrorCode;
+use crate::escrow::{Escrow, EscrowStatus};
+use crate::handler::TransactionHandler;
+use crate::hold::{HoldAction, HoldReason};
+use crate::ledger::{EntryKind, LedgerEntry};
+use crate::lock::LockState;
+use crate::memory::{HeapSize, MemoryStats};
+use crate::notes::Note;
+use crate::profile::{Profile, Stage};
+use crate::promo::{self, PromoCredit};
+use crate::risk::{AmountStats, DeniedTransaction, PendingTransaction, RiskScorer};
+use crate::screening::{Screening, ScreeningAction, ScreeningObserver};
+use crate::snapshot::{Client, Decoder, Encoder, Snapshot};
+use crate::store::TransactionStore;
+use crate::tags::AccountTags;
+use crate::transaction::{
+    ClientId, CsvAccount, CsvAccountDelta, CsvFoundTransaction, CsvPocket, CsvWallet, Transaction,
+    TransactionType,
+};
+use crate::velocity::Velocity;
+use crate::wallet::Wallet;
 
+/// Why a transaction was not applied to an account
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::module_name_repetitions)]
+pub enum TransactionError {
+    AccountLocked,
+    InsufficientFunds,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    /// Not rejected as such, a risk scorer wants someone to look at it before it's applied
+    HeldForReview,
+    /// One of the configured business rules, with the reason it gave
+    RejectedByRule(String),
+    /// The amount has more decimals than the input's scale allows
+    ExcessDecimals,
+    /// An operator action for a client without an account
+    UnknownAccount,
+    /// The transaction refers to one whose details were dropped, see `EngineConfig::pruning`
+    Pruned,
+    /// A new client when the engine has `Limits::max_accounts` already
+    TooManyAccounts,
+    /// The engine has `Limits::max_stored_transactions` stored already
+    TooManyTransactions,
+    /// The `TransactionHandler` of a custom type, with the reason it gave
+    RejectedByHandler(String),
+    /// `EngineConfig::transform` couldn't rewrite it, with what went wrong
+    TransformFailed(String),
+    /// A transfer of nothing, or from a pocket into itself
+    InvalidTransfer,
+    /// An escrow of nothing, to the buyer itself or with the tx of another escrow
+    InvalidEscrow,
+    /// A release or refund of an escrow that isn't open
+    UnknownEscrow,
+    /// The client has a transaction prepared with `TransactionEngine::prepare`, nothing else
+    /// of theirs is applied until it's committed or aborted
+    PendingCommit,
+    /// A commit or abort with a token that isn't prepared, or not anymore
+    UnknownPrepared,
+    /// The client is on `EngineConfig::blocklist`
+    Blocked,
+    /// An `opening` row for an account that already has an opening balance, see
+    /// `EngineConfig::opening_policy`
+    OpeningConflict,
+    /// An approve or deny of a tx that isn't a withdrawal waiting for approval
+    NotPendingApproval,
+    /// Money out of available that would leave less than the account's reserve, see
+    /// `EngineConfig::reserves`
+    BelowReserve,
+    /// A promotional credit of nothing, or one that expired before it came in
+    InvalidPromoCredit,
+    /// An operator hold or release of nothing, or a release of more than operators hold on the
+    /// account, see `TransactionEngine::hold`
+    InvalidHold,
+    /// Anything for an account an operator closed, see `TransactionEngine::close`
+    AccountClosed,
+    /// A reversal of a tx that isn't an applied withdrawal, one that's older than
+    /// `EngineConfig::reversal_window` or one that's disputed
+    InvalidReversal,
+    /// A reversal or dispute of a withdrawal that was reversed
+    AlreadyReversed,
+}
+
+impl TransactionError {
+    /// Short machine friendly name, used in the rejects output
+    pub fn reason(&self) -> &str {
+        match self {
+            TransactionError::AccountLocked => "account_locked",
+            TransactionError::InsufficientFunds => "insufficient_funds",
+            TransactionError::UnknownTransaction => "unknown_transaction",
+            TransactionError::AlreadyDisputed => "already_disputed",
+            TransactionError::NotDisputed => "not_disputed",
+            TransactionError::HeldForReview => "held_for_review",
+            TransactionError::RejectedByRule(reason)
+            | TransactionError::RejectedByHandler(reason) => reason,
+            TransactionError::ExcessDecimals => "excess_decimals",
+            TransactionError::UnknownAccount => "unknown_account",
+            TransactionError::Pruned => "pruned",
+            TransactionError::TooManyAccounts => "too_many_accounts",
+            TransactionError::TooManyTransactions => "too_many_transactions",
+            TransactionError::TransformFailed(_) => "transform_failed",
+            TransactionError::InvalidTransfer => "invalid_transfer",
+            TransactionError::InvalidEscrow => "invalid_escrow",
+            TransactionError::UnknownEscrow => "unknown_escrow",
+            TransactionError::PendingCommit => "pending_commit",
+            TransactionError::UnknownPrepared => "unknown_prepared",
+            TransactionError::Blocked => "blocked",
+            TransactionError::OpeningConflict => "opening_conflict",
+            TransactionError::NotPendingApproval => "not_pending_approval",
+            TransactionError::BelowReserve => "below_reserve",
+            TransactionError::InvalidPromoCredit => "invalid_promo_credit",
+            TransactionError::InvalidHold => "invalid_hold",
+            TransactionError::AccountClosed => "account_closed",
+            TransactionError::InvalidReversal => "invalid_reversal",
+            TransactionError::AlreadyReversed => "already_reversed",
+        }
+    }
+
+    /// The stable code of the rejection, the same for every reason a rule or handler gives
+    pub const fn code(&self) -> ErrorCode {
+        match self {
+            TransactionError::AccountLocked => ErrorCode::AccountLocked,
+            TransactionError::InsufficientFunds => ErrorCode::InsufficientFunds,
+            TransactionError::UnknownTransaction => ErrorCode::UnknownTransaction,
+            TransactionError::AlreadyDisputed => ErrorCode::AlreadyDisputed,
+            TransactionError::NotDisputed => ErrorCode::NotDisputed,
+            TransactionError::HeldForReview => ErrorCode::HeldForReview,
+            TransactionError::RejectedByRule(_) => ErrorCode::RejectedByRule,
+            TransactionError::ExcessDecimals => ErrorCode::ExcessDecimals,
+            TransactionError::UnknownAccount => ErrorCode::UnknownAccount,
+            TransactionError::Pruned => ErrorCode::Pruned,
+            TransactionError::TooManyAccounts => ErrorCode::TooManyAccounts,
+            TransactionError::TooManyTransactions => ErrorCode::TooManyTransactions,
+            TransactionError::RejectedByHandler(_) => ErrorCode::RejectedByHandler,
+            TransactionError::TransformFailed(_) => ErrorCode::TransformFailed,
+            TransactionError::InvalidTransfer => ErrorCode::InvalidTransfer,
+            TransactionError::InvalidEscrow => ErrorCode::InvalidEscrow,
+            TransactionError::UnknownEscrow => ErrorCode::UnknownEscrow,
+            TransactionError::PendingCommit => ErrorCode::PendingCommit,
+            TransactionError::UnknownPrepared => ErrorCode::UnknownPrepared,
+            TransactionError::Blocked => ErrorCode::Blocked,
+            TransactionError::OpeningConflict => ErrorCode::OpeningConflict,
+            TransactionError::NotPendingApproval => ErrorCode::NotPendingApproval,
+            TransactionError::BelowReserve => ErrorCode::BelowReserve,
+            TransactionError::InvalidPromoCredit => ErrorCode::InvalidPromoCredit,
+            TransactionError::InvalidHold => ErrorCode::InvalidHold,
+            TransactionError::AccountClosed => ErrorCode::AccountClosed,
+            TransactionError::InvalidReversal => ErrorCode::InvalidReversal,
+            TransactionError::AlreadyReversed => ErrorCode::AlreadyReversed,
+        }
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            TransactionError::AccountLocked => "account is locked",
+            TransactionError::InsufficientFunds => "insufficient funds",
+            TransactionError::UnknownTransaction => "referenced transaction does not exist",
+            TransactionError::AlreadyDisputed => "transaction is already disputed",
+            TransactionError::NotDisputed => "transaction is not under dispute",
+            TransactionError::HeldForReview => "transaction is held for review",
+            TransactionError::ExcessDecimals => "amount has more decimals than allowed",
+            TransactionError::UnknownAccount => "account does not exist",
+            TransactionError::Pruned => "referenced transaction can no longer be disputed",
+            TransactionError::TooManyAccounts => "the engine has the maximum number of accounts",
+            TransactionError::TooManyTransactions => {
+                "the engine has the maximum number of stored transactions"
+            }
+            TransactionError::InvalidTransfer => "transfer has to move a positive amount elsewhere",
+            TransactionError::InvalidEscrow => "escrow needs a positive amount and another client",
+            TransactionError::UnknownEscrow => "referenced escrow is not open",
+            TransactionError::PendingCommit => "account has a transaction waiting to be committed",
+            TransactionError::UnknownPrepared => "no transaction is prepared with the token",
+            TransactionError::Blocked => "client is on the blocklist",
+            TransactionError::OpeningConflict => "account already has an opening balance",
+            TransactionError::NotPendingApproval => {
+                "referenced withdrawal is not waiting for approval"
+            }
+            TransactionError::BelowReserve => "available would go below the account's reserve",
+            TransactionError::InvalidPromoCredit => {
+                "promotional credit needs a positive amount and a future expiry"
+            }
+            TransactionError::InvalidHold => {
+                "hold needs a positive amount, release at most what operators hold"
+            }
+            TransactionError::AccountClosed => "account is closed",
+            TransactionError::InvalidReversal => {
+                "only an undisputed withdrawal within the grace window can be reversed"
+            }
+            TransactionError::AlreadyReversed => "withdrawal was reversed",
+            TransactionError::RejectedByRule(reason) => {
+                return write!(f, "rejected by rule: {}", reason)
+            }
+            TransactionError::RejectedByHandler(reason) => {
+                return write!(f, "rejected by its handler: {}", reason)
+            }
+            TransactionError::TransformFailed(error) => {
+                return write!(f, "the transform failed at {}", error)
+            }
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// What `TransactionEngine::merge` does when both engines have an account for a client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Refuse to merge, e.g. when the inputs were supposed to be sharded by client
+    Fail,
+    /// Add up the balances and keep the histories of both
+    Sum,
+    /// Refuse like `Fail`, except for the fee account that every shard credits, which is
+    /// added up like `Sum`
+    SumFeeAccount,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Both engines had an account for the client and `OnConflict::Fail` was asked for, or
+    /// `OnConflict::SumFeeAccount` and it isn't the fee account
+    Account(ClientId),
+    /// The engines know the client by different external ids, whatever `OnConflict` says
+    ExternalId(ClientId),
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeConflict::Account(client) => {
+                write!(f, "client {} has an account in both engines", client)
+            }
+            MergeConflict::ExternalId(client) => {
+                write!(
+                    f,
+                    "client {} has another external id in each engine",
+                    client
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+/// How an account differs between two states of an engine, see `TransactionEngine::diff`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountDelta {
+    pub client: ClientId,
+    pub change: AccountChange,
+    /// After minus before, an account that isn't in one of them has nothing there
+    pub available: BigDecimal,
+    pub held: BigDecimal,
+    /// Whether it's locked afterwards, `None` if that's as it was or it was removed
+    pub locked: Option<bool>,
+    /// How many ledger entries it got in between
+    pub entries: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountChange {
+    Opened,
+    /// Only in the state before, which an engine doesn't do by itself
+    Removed,
+    Changed,
+}
+
+impl AccountChange {
+    pub const fn name(self) -> &'static str {
+        match self {
+            AccountChange::Opened => "opened",
+            AccountChange::Removed => "removed",
+            AccountChange::Changed => "changed",
+        }
+    }
+}
+
+impl AccountDelta {
+    pub fn total(&self) -> BigDecimal {
+        &self.available + &self.held
+    }
+
+    pub fn to_csv(&self) -> CsvAccountDelta {
+        CsvAccountDelta {
+            client: self.client,
+            change: self.change.name(),
+            available: format!("{:.4}", self.available),
+            held: format!("{:.4}", self.held),
+            total: format!("{:.4}", self.total()),
+            locked: self.locked,
+            entries: self.entries,
+        }
+    }
+}
+
+/// An account got into a state the engine should never produce, see `check_invariants`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub client: ClientId,
+    pub invariant: &'static str,
+    /// The account's balances, lock and open disputes at the time
+    pub account: String,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invariant violated for client {}: {} ({})",
+            self.client, self.invariant, self.account
+        )
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// The pocket `TransactionEngine::open_accounts` puts the part of an account's total that isn't
+/// available or held in, so it's written out with the same total again
+pub const RESUMED_POCKET: &str = "resumed";
+
+#[derive(Clone)]
 struct Account {
     client_id: ClientId,
-    transactions: Vec<Transaction>,
+    transactions: TransactionStore,
+    ledger: Vec<LedgerEntry>,
+    cases: Vec<Case>,
+    amount_stats: AmountStats,
     available: BigDecimal,
     held: BigDecimal,
-    locked: bool,
+    /// Money set aside out of available by transfers, by pocket name. Empty pockets are removed
+    pockets: BTreeMap<String, BigDecimal>,
+    /// The client's other wallets by name, `available` and `held` are the main one's. Part of
+    /// the total like the pockets, see `Transaction::WalletTransfer`
+    wallets: BTreeMap<String, Wallet>,
+    /// The escrows the client is the buyer of, oldest first
+    escrows: Vec<Escrow>,
+    /// The withdrawals waiting for an approve, oldest first. Their amounts are held
+    pending_withdrawals: Vec<PendingWithdrawal>,
+    /// What's left of the promotional credits, in the order they expire. Part of the total
+    /// but not of available, see `Transaction::PromoCredit`
+    promo_credits: Vec<PromoCredit>,
+    /// The holds and releases of operators, oldest first. What they hold is part of held
+    holds: Vec<HoldAction>,
+    /// Since when (unix seconds) available has been negative, see `track_negative`
+    negative_since: Option<u64>,
+    /// The deposits and withdrawals of the last hour and day
+    velocity: Velocity,
+    /// What operators wrote about the account, oldest first
+    notes: Vec<Note>,
+    lock: LockState,
+}
+
+/// Whether a dispute, resolve or chargeback of a tx can be applied, given what the client sent
+/// for the tx after its deposit or withdrawal, rejected transactions included
+fn accepts(transaction: &Transaction, after: &[Transaction], compat: Compat) -> bool {
+    let closes = |t: &Transaction| {
+        matches!(
+            t,
+            Transaction::Resolve { .. } | Transaction::Chargeback { .. }
+        )
+    };
+    match (compat, transaction) {
+        (Compat::Legacy, Transaction::Dispute { .. }) => after.is_empty(),
+        (Compat::Legacy, Transaction::Resolve { .. }) => {
+            matches!(after, [Transaction::Dispute { .. }])
+        }
+        (Compat::Legacy, _) => matches!(after, [Transaction::Dispute { .. }, ..]),
+        // a tx can only be disputed once, the dispute is open until it's resolved or charged back
+        (Compat::Current, transaction) => {
+            let disputed = after
+                .iter()
+                .position(|t| matches!(t, Transaction::Dispute { .. }));
+            match (transaction, disputed) {
+                (Transaction::Dispute { .. }, disputed) => disputed.is_none(),
+                (_, None) => false,
+                (_, Some(dispute)) => !after[dispute..].iter().any(closes),
+            }
+        }
+    }
 }
 
 impl Account {
     fn new(id: ClientId) -> Self {
         Self {
             client_id: id,
-            transactions: vec![],
+            transactions: TransactionStore::new(id),
+            ledger: vec![],
+            cases: vec![],
+            amount_stats: AmountStats::default(),
             available: BigDecimal::zero(),
             held: BigDecimal::zero(),
-            locked: false,
+            pockets: BTreeMap::new(),
+            wallets: BTreeMap::new(),
+            escrows: vec![],
+            pending_withdrawals: vec![],
+            promo_credits: vec![],
+            holds: vec![],
+            negative_since: None,
+            velocity: Velocity::default(),
+            notes: vec![],
+            lock: LockState::Active,
         }
     }
-    fn process(&mut self, transaction: Transaction) {
-        if self.locked {
-            // if this was a service, this would be a proper error
-            return;
+    /// `fee` is what a deposit, withdrawal or chargeback costs on top, see
+    /// `EngineConfig::fees`.
+    /// A withdrawal that needs `approval` is held until its approve. Money only leaves the
+    /// available balance as long as `reserve` stays, see `EngineConfig::reserves`. The caller
+    /// checks the lock, see `LockState::check`
+    fn process(
+        &mut self,
+        transaction: Transaction,
+        now: u64,
+        fee: &BigDecimal,
+        compat: Compat,
+        approval: bool,
+        reserve: &BigDecimal,
+    ) -> Result<(), TransactionError> {
+        if let Transaction::Approve { tx, .. } = transaction {
+            // not stored, the withdrawal is once it's approved
+            return self.approve(tx, now);
         }
-        match transaction {
-            Transaction::Deposit { ref amount, .. } => {
-                self.available += amount;
+        if let Transaction::Deny { tx, .. } = transaction {
+            // neither is a denied withdrawal
+            return self.deny(tx);
+        }
+        if transaction.amount().is_none() && self.transactions.is_pruned(transaction.tx()) {
+            // not stored, it would never be pruned
+            return Err(TransactionError::Pruned);
+        }
+        if let Transaction::Transfer {
+            tx,
+            amount,
+            from,
+            to,
+            ..
+        } = &transaction
+        {
+            // not stored, there's nothing to dispute about moving money within the account
+            return self.transfer(*tx, amount, from.as_deref(), to.as_deref(), reserve);
+        }
+        if let Transaction::WalletTransfer {
+            tx,
+            amount,
+            from,
+            to,
+            ..
+        } = &transaction
+        {
+            // not stored either, disputes are of the deposits and withdrawals in the wallets
+            return self.wallet_transfer(*tx, amount, from.as_deref(), to.as_deref(), reserve);
+        }
+        if let Transaction::PromoCredit {
+            tx,
+            amount,
+            expires_at,
+            ..
+        } = &transaction
+        {
+            // not stored, promotional money can't be disputed
+            return self.credit_promo(*tx, amount, *expires_at, now);
+        }
+        if let Some(result) = self.escrow(&transaction, now, reserve) {
+            // the escrows are their own table, only the ledger has their balance changes
+            return result;
+        }
+        let result = match transaction {
+            Transaction::Deposit {
+                tx,
+                ref amount,
+                ref wallet,
+                ..
+            } => {
+                let wallet = wallet.as_deref();
+                *self.balances(wallet).0 += amount;
+                self.ledger.push(
+                    LedgerEntry::new(tx, EntryKind::Deposit, amount.clone(), BigDecimal::zero())
+                        .in_wallet(wallet),
+                );
+                // `FeeSchedule::fee` caps it at the amount, the deposit alone pays for it
+                self.charge(tx, EntryKind::Fee, fee, wallet);
+                Ok(())
             }
-            Transaction::Withdrawal { ref amount, .. } => {
-                if &self.available >= amount {
-                    self.available -= amount;
+            Transaction::Withdrawal {
+                tx,
+                ref amount,
+                ref wallet,
+                ..
+            } if approval => {
+                let wallet = wallet.as_deref();
+                if let Err(error) = self.can_spend(wallet, &(amount + fee), reserve) {
+                    Err(error)
                 } else {
-                    // client didn't have enough money for the withdraw
-                    // since this is a cli we won't do anything
-                    // for a real service this is definitely an
-                    // error we want to both log and report to the user
+                    let (available, held) = self.balances(wallet);
+                    *available -= amount;
+                    *held += amount;
+                    self.ledger.push(
+                        LedgerEntry::new(tx, EntryKind::PendingWithdrawal, -amount, amount.clone())
+                            .in_wallet(wallet),
+                    );
+                    self.pending_withdrawals.push(PendingWithdrawal {
+                        client: self.client_id,
+                        tx,
+                        amount: amount.clone(),
+                        requested_at: now,
+                        wallet: wallet.map(str::to_string),
+                    });
+                    self.charge(tx, EntryKind::Fee, fee, wallet);
+                    // stored once it's approved, it can't be disputed before it settles
+                    self.amount_stats.add(amount.to_f64().unwrap_or_default());
+                    self.velocity.record(&transaction, now);
+                    return Ok(());
                 }
             }
+            Transaction::Withdrawal {
+                tx,
+                ref amount,
+                ref wallet,
+                ..
+            } => {
+                let wallet = wallet.as_deref();
+                // the promotional credits go first, they're only in the main wallet. A negative
+                // amount doesn't take any, or it would add to them
+                let promo = match wallet {
+                    None => self.promo().min(amount.clone()).max(BigDecimal::zero()),
+                    Some(_) => BigDecimal::zero(),
+                };
+                let rest = amount - &promo;
+                self.can_spend(wallet, &(&rest + fee), reserve).map(|()| {
+                    promo::spend(&mut self.promo_credits, &promo);
+                    *self.balances(wallet).0 -= &rest;
+                    self.ledger.push(
+                        LedgerEntry::new(tx, EntryKind::Withdrawal, -rest, BigDecimal::zero())
+                            .in_wallet(wallet)
+                            .with_promo(-promo),
+                    );
+                    self.charge(tx, EntryKind::Fee, fee, wallet);
+                })
+            }
+            Transaction::Dispute { tx, .. } if self.transactions.is_reversed(tx) => {
+                Err(TransactionError::AlreadyReversed)
+            }
             Transaction::Dispute { tx, .. } => {
-                let transactions = self
-                    .transactions
-                    .iter()
-                    .filter(|t| t.tx() == tx)
-                    .collect::<Vec<_>>();
+                let transactions = self.transactions.with_tx(tx).collect::<Vec<_>>();
 
-                if let [Transaction::Withdrawal { amount, .. }
-                | Transaction::Deposit { amount, .. }] = transactions.as_slice()
-                {
-                    self.available -= amount;
-                    self.held += amount;
+                match transactions.as_slice() {
+                    [Transaction::Withdrawal { amount, wallet, .. }
+                    | Transaction::Deposit { amount, wallet, .. }, after @ ..]
+                        if accepts(&transaction, after, compat) =>
+                    {
+                        // held in the wallet the money went to or came out of
+                        let wallet = wallet.as_deref();
+                        let (available, held) = self.balances(wallet);
+                        *available -= amount;
+                        *held += amount;
+                        self.ledger.push(
+                            LedgerEntry::new(tx, EntryKind::Hold, -amount, amount.clone())
+                                .in_wallet(wallet),
+                        );
+                        self.cases
+                            .push(Case::open(self.client_id, tx, amount.clone(), now));
+                        Ok(())
+                    }
+                    [Transaction::Withdrawal { .. } | Transaction::Deposit { .. }, ..] => {
+                        Err(TransactionError::AlreadyDisputed)
+                    }
+                    _ => Err(TransactionError::UnknownTransaction),
                 }
             }
             Transaction::Resolve { tx, .. } => {
-                let transactions = self
-                    .transactions
-                    .iter()
-                    .filter(|t| t.tx() == tx)
-                    .collect::<Vec<_>>();
+                let transactions = self.transactions.with_tx(tx).collect::<Vec<_>>();
 
-                if let [Transaction::Withdrawal { amount, .. }
-                | Transaction::Deposit { amount, .. }, Transaction::Dispute { .. }] =
-                    transactions.as_slice()
-                {
-                    self.available += amount;
-                    self.held -= amount;
+                match transactions.as_slice() {
+                    [Transaction::Withdrawal { amount, wallet, .. }
+                    | Transaction::Deposit { amount, wallet, .. }, after @ ..]
+                        if accepts(&transaction, after, compat) =>
+                    {
+                        let wallet = wallet.as_deref();
+                        let (available, held) = self.balances(wallet);
+                        *available += amount;
+                        *held -= amount;
+                        self.ledger.push(
+                            LedgerEntry::new(tx, EntryKind::Release, amount.clone(), -amount)
+                                .in_wallet(wallet),
+                        );
+                        self.close_case(tx, CaseStatus::Resolved, now);
+                        Ok(())
+                    }
+                    [Transaction::Withdrawal { .. } | Transaction::Deposit { .. }, ..] => {
+                        Err(TransactionError::NotDisputed)
+                    }
+                    _ => Err(TransactionError::UnknownTransaction),
                 }
             }
             Transaction::Chargeback { tx, .. } => {
-                let transactions = self
-                    .transactions
-                    .iter()
-                    .filter(|t| t.tx() == tx)
-                    .collect::<Vec<_>>();
+                let transactions = self.transactions.with_tx(tx).collect::<Vec<_>>();
 
-                if let [Transaction::Withdrawal { amount, .. }
-                | Transaction::Deposit { amount, .. }, Transaction::Dispute { .. }, ..] =
-                    transactions.as_slice()
-                {
-                    self.held -= amount;
-                    self.locked = true;
+                match transactions.as_slice() {
+                    [Transaction::Withdrawal { amount, wallet, .. }
+                    | Transaction::Deposit { amount, wallet, .. }, after @ ..]
+                        if accepts(&transaction, after, compat) =>
+                    {
+                        let wallet = wallet.as_deref();
+                        *self.balances(wallet).1 -= amount;
+                        self.lock = LockState::ChargebackLocked;
+                        self.ledger.push(
+                            LedgerEntry::new(
+                                tx,
+                                EntryKind::Chargeback,
+                                BigDecimal::zero(),
+                                -amount,
+                            )
+                            .in_wallet(wallet),
+                        );
+                        // the account is locked, but the network bills the chargeback anyway
+                        self.charge(tx, EntryKind::ChargebackFee, fee, wallet);
+                        self.close_case(tx, CaseStatus::ChargedBack, now);
+                        Ok(())
+                    }
+                    [Transaction::Withdrawal { .. } | Transaction::Deposit { .. }, ..] => {
+                        Err(TransactionError::NotDisputed)
+                    }
+                    _ => Err(TransactionError::UnknownTransaction),
                 }
             }
+            Transaction::Transfer { .. }
+            | Transaction::EscrowOpen { .. }
+            | Transaction::EscrowRelease { .. }
+            | Transaction::EscrowRefund { .. }
+            | Transaction::Approve { .. }
+            | Transaction::Deny { .. }
+            | Transaction::WalletTransfer { .. }
+            | Transaction::PromoCredit { .. } => unreachable!("applied above"),
+            Transaction::Reversal { .. } => unreachable!("reversals are applied by `reverse`"),
+            Transaction::Custom { .. } => unreachable!("custom transactions go to their handler"),
+        };
+
+        if let (Ok(()), Some(amount)) = (&result, transaction.amount()) {
+            self.amount_stats.add(amount.to_f64().unwrap_or_default());
+            self.velocity.record(&transaction, now);
         }
 
-        self.transactions.push(transaction);
+        // rejected transactions are kept as well, the dispute lookups above
+        // depend on seeing everything the client has sent for a tx
+        self.transactions.push(&transaction, now);
+        result
     }
 
-    fn as_csv_account(&self) -> CsvAccount {
-        CsvAccount {
-            client: self.client_id,
-            available: format!("{:.4}", self.available),
-            held: format!("{:.4}", self.held),
-            total: format!("{:.4}", (self.available.clone() + self.held.clone())),
-            locked: self.locked,
+    /// Starts the clock of a negative available balance, or stops it once it's back at zero
+    /// or above. Called after every change of available
+    fn track_negative(&mut self, now: u64) {
+        if self.available < BigDecimal::zero() {
+            self.negative_since.get_or_insert(now);
+        } else {
+            self.negative_since = None;
         }
     }
-}
 
-pub struct TransactionEngine {
-    // I realize this means I'm storing both the client id as the key
-    // as well as in the Account struct, I assume that client id can't change
-    // but it's still not pretty to store it in two places
-    // but I think using a hashmap here is the cleanest
-    // and I think  account should store the client id
-    accounts: HashMap<ClientId, Account>,
-}
+    /// Takes the fee out of the wallet of the transaction
+    fn charge(&mut self, tx: u32, kind: EntryKind, fee: &BigDecimal, wallet: Option<&str>) {
+        if fee.is_zero() {
+            return;
+        }
+        *self.balances(wallet).0 -= fee;
+        self.ledger
+            .push(LedgerEntry::new(tx, kind, -fee, BigDecimal::zero()).in_wallet(wallet));
+    }
 
-impl TransactionEngine {
-    pub fn new() -> Self {
-        Self {
-            accounts: HashMap::new(),
+    /// The available and held balances of a wallet, `None` being the main one. Opens the
+    /// wallet if it's new
+    fn balances(&mut self, wallet: Option<&str>) -> (&mut BigDecimal, &mut BigDecimal) {
+        match wallet {
+            None => (&mut self.available, &mut self.held),
+            Some(wallet) => {
+                let wallet = self.wallets.entry(wallet.to_string()).or_default();
+                (&mut wallet.available, &mut wallet.held)
+            }
         }
     }
 
-    pub fn process(&mut self, transaction: Transaction) {
-        let account = self
-            .accounts
-            .entry(transaction.client())
-            .or_insert_with(|| Account::new(transaction.client()));
+    /// What's available in a wallet, zero in one that isn't open
+    fn wallet_available(&self, wallet: Option<&str>) -> BigDecimal {
+        match wallet {
+            None => self.available.clone(),
+            Some(wallet) => self
+                .wallets
+                .get(wallet)
+                .map_or_else(BigDecimal::zero, |wallet| wallet.available.clone()),
+        }
+    }
 
-        account.process(transaction);
+    /// Whether `debit` can come out of the wallet's available balance, the main wallet has to
+    /// keep the account's reserve
+    fn can_spend(
+        &self,
+        wallet: Option<&str>,
+        debit: &BigDecimal,
+        reserve: &BigDecimal,
+    ) -> Result<(), TransactionError> {
+        let available = self.wallet_available(wallet);
+        if &available < debit {
+            Err(TransactionError::InsufficientFunds)
+        } else if wallet.is_none() && &(available - debit) < reserve {
+            Err(TransactionError::BelowReserve)
+        } else {
+            Ok(())
+        }
     }
 
-    pub fn accounts(&self) -> Vec<CsvAccount> {
-        self.accounts
-            .values()
-            .map(Account::as_csv_account)
-            .collect()
+    /// What operators hold of the account, see `TransactionEngine::hold`
+    fn admin_held(&self) -> BigDecimal {
+        self.holds
+            .iter()
+            .fold(BigDecimal::zero(), |held, action| held + &action.amount)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
+    /// What's left of the promotional credits
+    fn promo(&self) -> BigDecimal {
+        self.promo_credits
+            .iter()
+            .fold(BigDecimal::zero(), |promo, credit| promo + &credit.amount)
+    }
 
-    use crate::Transaction::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+    fn credit_promo(
+        &mut self,
+        tx: u32,
+        amount: &BigDecimal,
+        expires_at: u64,
+        now: u64,
+    ) -> Result<(), TransactionError> {
+        if amount <= &BigDecimal::zero()
+            || expires_at <= now
+            || self.promo_credits.iter().any(|credit| credit.tx == tx)
+        {
+            return Err(TransactionError::InvalidPromoCredit);
+        }
+        let index = self
+            .promo_credits
+            .partition_point(|credit| credit.expires_at <= expires_at);
+        self.promo_credits.insert(
+            index,
+            PromoCredit {
+                tx,
+                amount: amount.clone(),
+                expires_at,
+            },
+        );
+        self.ledger.push(
+            LedgerEntry::new(
+                tx,
+                EntryKind::PromoCredit,
+                BigDecimal::zero(),
+                BigDecimal::zero(),
+            )
+            .with_promo(amount.clone()),
+        );
+        Ok(())
+    }
 
-    use super::*;
+    /// Removes what's left of the promotional credit tx if it has expired by now, returns
+    /// whether there was anything left
+    fn expire_promo(&mut self, tx: u32, now: u64) -> bool {
+        let Some(index) = self
+            .promo_credits
+            .iter()
+            .position(|credit| credit.tx == tx && credit.expires_at <= now)
+        else {
+            return false;
+        };
+        let credit = self.promo_credits.remove(index);
+        self.ledger.push(
+            LedgerEntry::new(
+                tx,
+                EntryKind::PromoExpiry,
+                BigDecimal::zero(),
+                BigDecimal::zero(),
+            )
+            .with_promo(-credit.amount),
+        );
+        true
+    }
 
-    #[test]
-    fn test_provided_example() {
-        let input = vec![
-            Deposit {
-                client: 1,
-                tx: 1,
-                amount: BigDecimal::from(1),
-            },
-            Deposit {
-                client: 2,
-                tx: 2,
-                amount: BigDecimal::from(2),
-            },
-            Deposit {
-                client: 1,
-                tx: 3,
-                amount: BigDecimal::from(2),
-            },
-            Withdrawal {
-                client: 1,
-                tx: 4,
-                amount: BigDecimal::from_str("1.5").unwrap(),
-            },
-            Withdrawal {
-                client: 2,
-                tx: 5,
-                amount: BigDecimal::from(3),
-            },
-        ];
-        let expected = vec![
-            CsvAccount {
-                client: 1,
-                available: "1.5000".to_string(),
-                held: "0.0000".to_string(),
-                total: "1.5000".to_string(),
-                locked: false,
-            },
-            CsvAccount {
-                client: 2,
-                available: "2.0000".to_string(),
-                held: "0.0000".to_string(),
-                total: "2.0000".to_string(),
-                locked: false,
-            },
-        ];
-        test(input, expected);
+    /// Moves available money from the wallet `from` to `to`, `None` being the main one
+    fn wallet_transfer(
+        &mut self,
+        tx: u32,
+        amount: &BigDecimal,
+        from: Option<&str>,
+        to: Option<&str>,
+        reserve: &BigDecimal,
+    ) -> Result<(), TransactionError> {
+        if amount <= &BigDecimal::zero() || from == to {
+            return Err(TransactionError::InvalidTransfer);
+        }
+        self.can_spend(from, amount, reserve)?;
+        *self.balances(from).0 -= amount;
+        *self.balances(to).0 += amount;
+        for (wallet, available) in [(from, -amount), (to, amount.clone())] {
+            self.ledger.push(
+                LedgerEntry::new(tx, EntryKind::Transfer, available, BigDecimal::zero())
+                    .in_wallet(wallet),
+            );
+        }
+        Ok(())
     }
 
-    #[test]
-    fn should_deposit_money() {
-        test(
-            vec![Deposit {
-                client: 1,
-                tx: 1,
-                amount: BigDecimal::from(1),
-            }],
-            vec![CsvAccount {
-                client: 1,
-                available: "1.0000".to_string(),
-                held: "0.0000".to_string(),
-                total: "1.0000".to_string(),
-                locked: false,
-            }],
+    /// Moves `amount` out of `from` into `to`, `None` being the available balance
+    fn transfer(
+        &mut self,
+        tx: u32,
+        amount: &BigDecimal,
+        from: Option<&str>,
+        to: Option<&str>,
+        reserve: &BigDecimal,
+    ) -> Result<(), TransactionError> {
+        if amount <= &BigDecimal::zero() || from == to {
+            return Err(TransactionError::InvalidTransfer);
+        }
+        match from {
+            None => self.can_spend(None, amount, reserve)?,
+            Some(pocket) => {
+                if self
+                    .pockets
+                    .get(pocket)
+                    .is_none_or(|balance| balance < amount)
+                {
+                    return Err(TransactionError::InsufficientFunds);
+                }
+            }
+        }
+        let mut entry = LedgerEntry::new(
+            tx,
+            EntryKind::Transfer,
+            BigDecimal::zero(),
+            BigDecimal::zero(),
         );
+        match from {
+            None => {
+                self.available -= amount;
+                entry.available = -amount;
+            }
+            Some(pocket) => {
+                let balance = self.pockets.get_mut(pocket).expect("checked above");
+                *balance -= amount;
+                if balance.is_zero() {
+                    self.pockets.remove(pocket);
+                }
+                entry.pockets.push((pocket.to_string(), -amount));
+            }
+        }
+        match to {
+            None => {
+                self.available += amount;
+                entry.available = amount.clone();
+            }
+            Some(pocket) => {
+                *self.pockets.entry(pocket.to_string()).or_default() += amount;
+                entry.pockets.push((pocket.to_string(), amount.clone()));
+            }
+        }
+        self.ledger.push(entry);
+        Ok(())
     }
 
-    #[test]
-    fn should_withdraw_money() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(1),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from_str("0.5").unwrap(),
-                },
-            ],
-            vec![CsvAccount {
-                client: 1,
-                available: "0.5000".to_string(),
-                held: "0.0000".to_string(),
-                total: "0.5000".to_string(),
-                locked: false,
-            }],
+    /// Applies an escrow transaction on the buyer's side, `None` for any other transaction.
+    /// Paying the seller is up to the engine
+    fn escrow(
+        &mut self,
+        transaction: &Transaction,
+        now: u64,
+        reserve: &BigDecimal,
+    ) -> Option<Result<(), TransactionError>> {
+        let (tx, status) = match *transaction {
+            Transaction::EscrowOpen {
+                tx,
+                ref amount,
+                seller,
+                ..
+            } => {
+                return Some(
+                    if amount <= &BigDecimal::zero()
+                        || seller == self.client_id
+                        || self.escrows.iter().any(|escrow| escrow.tx() == tx)
+                    {
+                        Err(TransactionError::InvalidEscrow)
+                    } else if let Err(error) = self.can_spend(None, amount, reserve) {
+                        Err(error)
+                    } else {
+                        self.available -= amount;
+                        self.held += amount;
+                        self.ledger.push(LedgerEntry::new(
+                            tx,
+                            EntryKind::EscrowHold,
+                            -amount,
+                            amount.clone(),
+                        ));
+                        self.escrows.push(Escrow::open(
+                            self.client_id,
+                            seller,
+                            tx,
+                            amount.clone(),
+                            now,
+                        ));
+                        Ok(())
+                    },
+                );
+            }
+            Transaction::EscrowRelease { tx, .. } => (tx, EscrowStatus::Released),
+            Transaction::EscrowRefund { tx, .. } => (tx, EscrowStatus::Refunded),
+            _ => return None,
+        };
+        let escrow = match self.open_escrow(tx) {
+            None => return Some(Err(TransactionError::UnknownEscrow)),
+            Some(escrow) => escrow,
+        };
+        let amount = escrow.amount().clone();
+        escrow.close(status, now);
+        self.held -= &amount;
+        let (kind, available) = match status {
+            EscrowStatus::Refunded => {
+                self.available += &amount;
+                (EntryKind::EscrowRefund, amount.clone())
+            }
+            _ => (EntryKind::EscrowRelease, BigDecimal::zero()),
+        };
+        self.ledger
+            .push(LedgerEntry::new(tx, kind, available, -amount));
+        Some(Ok(()))
+    }
+
+    fn take_pending_withdrawal(&mut self, tx: u32) -> Result<PendingWithdrawal, TransactionError> {
+        let index = self
+            .pending_withdrawals
+            .iter()
+            .position(|pending| pending.tx == tx)
+            .ok_or(TransactionError::NotPendingApproval)?;
+        Ok(self.pending_withdrawals.remove(index))
+    }
+
+    /// Settles the pending withdrawal `tx`, its held amount is withdrawn
+    fn approve(&mut self, tx: u32, now: u64) -> Result<(), TransactionError> {
+        let pending = self.take_pending_withdrawal(tx)?;
+        let (amount, wallet) = (pending.amount, pending.wallet);
+        *self.balances(wallet.as_deref()).1 -= &amount;
+        self.ledger.push(
+            LedgerEntry::new(tx, EntryKind::Approval, amount.clone(), -&amount)
+                .in_wallet(wallet.as_deref()),
+        );
+        self.ledger.push(
+            LedgerEntry::new(tx, EntryKind::Withdrawal, -&amount, BigDecimal::zero())
+                .in_wallet(wallet.as_deref()),
+        );
+        self.transactions.push(
+            &Transaction::Withdrawal {
+                client: self.client_id,
+                tx,
+                amount,
+                wallet,
+            },
+            now,
         );
+        Ok(())
     }
 
-    #[test]
-    fn should_not_withdraw_to_below_zero() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(1),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from(2),
-                },
-            ],
-            vec![CsvAccount {
-                client: 1,
-                available: "1.0000".to_string(),
-                held: "0.0000".to_string(),
-                total: "1.0000".to_string(),
-                locked: false,
-            }],
+    /// Drops the pending withdrawal `tx`, its held amount is available again. The fee it was
+    /// charged when it came in isn't given back, like with a reversal
+    fn deny(&mut self, tx: u32) -> Result<(), TransactionError> {
+        let pending = self.take_pending_withdrawal(tx)?;
+        let (amount, wallet) = (pending.amount, pending.wallet);
+        let (available, held) = self.balances(wallet.as_deref());
+        *available += &amount;
+        *held -= &amount;
+        self.ledger.push(
+            LedgerEntry::new(tx, EntryKind::Denial, amount.clone(), -amount)
+                .in_wallet(wallet.as_deref()),
         );
+        Ok(())
     }
 
-    #[test]
+    /// Undoes the withdrawal tx if it was applied at most `window` seconds before `now` and
+    /// isn't disputed. What it took out of available goes back to its wallet, the fee and the
+    /// promotional credits it spent don't. Not stored, like an approve
+    fn reverse(&mut self, tx: u32, now: u64, window: Option<u64>) -> Result<(), TransactionError> {
+        if self.transactions.is_pruned(tx) {
+            return Err(TransactionError::Pruned);
+        }
+        if self.transactions.is_reversed(tx) {
+            return Err(TransactionError::AlreadyReversed);
+        }
+        let (wallet, at) = match self.transactions.original(tx) {
+            Some((Transaction::Withdrawal { wallet, .. }, at)) => (wallet, at),
+            Some(_) => return Err(TransactionError::InvalidReversal),
+            None => return Err(TransactionError::UnknownTransaction),
+        };
+        let in_window = window.is_some_and(|window| now.saturating_sub(at) <= window);
+        if !in_window || self.cases.iter().any(|case| case.tx() == tx) {
+            return Err(TransactionError::InvalidReversal);
+        }
+        // rejected withdrawals are stored too, only the ledger has what one took out of
+        // available rather than from the promotional credits
+        let Some(applied) = self
+            .ledger
+            .iter()
+            .find(|entry| entry.tx == tx && entry.kind == EntryKind::Withdrawal)
+        else {
+            return Err(TransactionError::InvalidReversal);
+        };
+        let amount = -&applied.available;
+        *self.balances(wallet.as_deref()).0 += &amount;
+        self.ledger.push(
+            LedgerEntry::new(tx, EntryKind::Reversal, amount, BigDecimal::zero())
+                .in_wallet(wallet.as_deref()),
+        );
+        self.transactions.reverse(tx);
+        Ok(())
+    }
+
+    fn open_escrow(&mut self, tx: u32) -> Option<&mut Escrow> {
+        self.escrows
+            .iter_mut()
+            .find(|escrow| escrow.tx() == tx && escrow.status() == EscrowStatus::Open)
+    }
+
+    fn close_case(&mut self, tx: u32, status: CaseStatus, now: u64) {
+        if let Some(case) = self.cases.iter_mut().rev().find(|case| case.tx() == tx) {
+            case.close(status, now);
+        }
+    }
+
+    /// Resolves the dispute of tx if it's still open, like a resolve would, returns whether it was
+    fn expire_dispute(&mut self, tx: u32, now: u64) -> bool {
+        if !self.lock.settles_disputes() {
+            return false;
+        }
+        let case = match self.cases.iter_mut().rev().find(|case| case.tx() == tx) {
+            Some(case) if case.status() == CaseStatus::Open => case,
+            _ => return false,
+        };
+        let amount = case.amount().clone();
+        case.close(CaseStatus::Expired, now);
+        let wallet = self
+            .transactions
+            .with_tx(tx)
+            .find_map(|transaction| transaction.wallet().map(str::to_string));
+        let (available, held) = self.balances(wallet.as_deref());
+        *available += &amount;
+        *held -= &amount;
+        self.ledger.push(
+            LedgerEntry::new(tx, EntryKind::Expiry, amount.clone(), -amount)
+                .in_wallet(wallet.as_deref()),
+        );
+        // so later resolves and disputes of the tx see it as resolved
+        self.transactions.push(
+            &Transaction::Resolve {
+                client: self.client_id,
+                tx,
+            },
+            now,
+        );
+        true
+    }
+
+    /// Drops the stored transactions `pruning` allows to, `closed` is a tx whose dispute was
+    /// just closed. Returns the txs pruned and why
+    fn prune(&mut self, pruning: Pruning, closed: Option<u32>, now: u64) -> Vec<(u32, String)> {
+        let mut pruned = closed
+            .map(|tx| (tx, "its dispute is closed".to_string()))
+            .into_iter()
+            .collect::<Vec<_>>();
+        if let Some(after) = pruning.after {
+            let disputed = |tx| {
+                self.cases
+                    .iter()
+                    .any(|case| case.tx() == tx && case.status() == CaseStatus::Open)
+            };
+            let old = self
+                .transactions
+                .stored_before(now.saturating_sub(after), disputed);
+            pruned.extend(
+                old.into_iter()
+                    .filter(|tx| Some(*tx) != closed)
+                    .map(|tx| (tx, format!("stored more than {} seconds ago", after))),
+            );
+        }
+        let txs = pruned.iter().map(|(tx, _)| *tx).collect::<Vec<_>>();
+        self.transactions.prune(&txs);
+        pruned
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.transactions.extend(&other.transactions);
+        self.ledger.extend(other.ledger);
+        self.cases.extend(other.cases);
+        self.amount_stats.merge(&other.amount_stats);
+        self.velocity.merge(&other.velocity);
+        self.available += other.available;
+        self.held += other.held;
+        for (pocket, balance) in other.pockets {
+            *self.pockets.entry(pocket).or_default() += balance;
+        }
+        for (name, other) in other.wallets {
+            let wallet = self.wallets.entry(name).or_default();
+            wallet.available += other.available;
+            wallet.held += other.held;
+        }
+        self.escrows.extend(other.escrows);
+        self.pending_withdrawals.extend(other.pending_withdrawals);
+        self.promo_credits.extend(other.promo_credits);
+        self.promo_credits.sort_by_key(|credit| credit.expires_at);
+        self.holds.extend(other.holds);
+        self.holds.sort_by_key(|action| action.at);
+        self.notes.extend(other.notes);
+        self.notes.sort_by_key(|note| note.at);
+        self.negative_since = match (self.negative_since, other.negative_since) {
+            _ if self.available >= BigDecimal::zero() => None,
+            (Some(since), Some(other)) => Some(since.min(other)),
+            (since, other) => since.or(other),
+        };
+        if other.lock.severity() > self.lock.severity() {
+            self.lock = other.lock;
+        }
+    }
+
+    /// Walks the whole ledger, so it gets slower the more the account has done
+    fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let open_cases = self
+            .cases
+            .iter()
+            .filter(|case| case.status() == CaseStatus::Open)
+            .collect::<Vec<_>>();
+        let mut ledger_wallets = BTreeMap::<Option<&str>, Wallet>::new();
+        for entry in &self.ledger {
+            let wallet = ledger_wallets.entry(entry.wallet.as_deref()).or_default();
+            wallet.available += &entry.available;
+            wallet.held += &entry.held;
+        }
+        let main = ledger_wallets.remove(&None).unwrap_or_default();
+        let (ledger_available, ledger_held) = (main.available, main.held);
+        let ledger_promo = self
+            .ledger
+            .iter()
+            .fold(BigDecimal::zero(), |promo, entry| promo + &entry.promo);
+        let mut ledger_pockets = BTreeMap::<&str, BigDecimal>::new();
+        for (pocket, amount) in self.ledger.iter().flat_map(|entry| &entry.pockets) {
+            *ledger_pockets.entry(pocket).or_default() += amount;
+        }
+        ledger_pockets.retain(|_, balance| !balance.is_zero());
+        let disputed = open_cases
+            .iter()
+            .fold(BigDecimal::zero(), |held, case| held + case.amount());
+        let escrowed = self
+            .escrows
+            .iter()
+            .filter(|escrow| escrow.status() == EscrowStatus::Open)
+            .fold(BigDecimal::zero(), |held, escrow| held + escrow.amount());
+        let pending = self
+            .pending_withdrawals
+            .iter()
+            .fold(BigDecimal::zero(), |held, pending| held + &pending.amount);
+        // the disputes behind balances carried over from a previous run aren't known
+        let opening = self
+            .ledger
+            .first()
+            .filter(|entry| entry.kind == EntryKind::Opening);
+        let carried_held = opening.map_or_else(BigDecimal::zero, |entry| entry.held.clone());
+        let charged_back = self
+            .cases
+            .iter()
+            .any(|case| case.status() == CaseStatus::ChargedBack);
+
+        let wallets_held = self
+            .wallets
+            .values()
+            .fold(BigDecimal::zero(), |held, wallet| held + &wallet.held);
+
+        let invariant = if self.held < BigDecimal::zero()
+            || self
+                .wallets
+                .values()
+                .any(|wallet| wallet.held < BigDecimal::zero())
+        {
+            "held >= 0"
+        } else if ledger_available != self.available || ledger_held != self.held {
+            "total == available + held == sum of the ledger"
+        } else if !ledger_wallets
+            .iter()
+            .map(|(wallet, balances)| (*wallet, balances))
+            .eq(self
+                .wallets
+                .iter()
+                .map(|(wallet, balances)| (Some(wallet.as_str()), balances)))
+        {
+            "wallets == sum of their entries in the ledger"
+        } else if self
+            .pockets
+            .values()
+            .any(|balance| balance <= &BigDecimal::zero())
+            || !ledger_pockets
+                .iter()
+                .map(|(pocket, balance)| (*pocket, balance))
+                .eq(self
+                    .pockets
+                    .iter()
+                    .map(|(pocket, balance)| (pocket.as_str(), balance)))
+        {
+            "pockets > 0 == sum of the transfers in the ledger"
+        } else if self
+            .promo_credits
+            .iter()
+            .any(|credit| credit.amount <= BigDecimal::zero())
+            || ledger_promo != self.promo()
+        {
+            "promotional credits > 0 == sum of their entries in the ledger"
+        } else if self.admin_held() < BigDecimal::zero() {
+            "operators release at most what they hold"
+        } else if disputed + escrowed + pending + carried_held + self.admin_held()
+            != &self.held + wallets_held
+        {
+            "held == sum of the open disputes, escrows, pending withdrawals and operator holds"
+        } else if self.available < BigDecimal::zero()
+            && open_cases.is_empty()
+            && !charged_back
+            && opening.is_none()
+        {
+            // without an overdraft policy only a dispute or chargeback of money
+            // that was already withdrawn can make it negative
+            "available >= 0 unless disputed or charged back"
+        } else if self.lock.is_locked()
+            && opening.is_none()
+            && !charged_back
+            && !self
+                .ledger
+                .iter()
+                .any(|entry| entry.kind == EntryKind::Lock)
+        {
+            "locked only after a chargeback or by an operator"
+        } else {
+            return Ok(());
+        };
+        Err(InvariantViolation {
+            client: self.client_id,
+            invariant,
+            account: format!(
+                "available {}, held {}, lock {}, open disputes {:?}",
+                self.available,
+                self.held,
+                self.lock.name(),
+                open_cases.iter().map(|case| case.tx()).collect::<Vec<_>>()
+            ),
+        })
+    }
+
+    fn total(&self) -> BigDecimal {
+        let total = self
+            .pockets
+            .values()
+            .fold(&self.available + &self.held, |total, balance| {
+                total + balance
+            });
+        self.wallets
+            .values()
+            .fold(total, |total, wallet| total + wallet.total())
+            + self.promo()
+    }
+
+    fn as_csv_account(&self) -> CsvAccount {
+        CsvAccount {
+            client: self.client_id,
+            available: format!("{:.4}", self.available),
+            held: format!("{:.4}", self.held),
+            total: format!("{:.4}", self.total()),
+            locked: self.lock.is_locked(),
+        }
+    }
+}
+
+impl Snapshot for Account {
+    fn encode(&self, encoder: &mut Encoder) {
+        Client(self.client_id).encode(encoder);
+        self.transactions.encode(encoder);
+        self.ledger.encode(encoder);
+        self.cases.encode(encoder);
+        self.amount_stats.encode(encoder);
+        self.available.encode(encoder);
+        self.held.encode(encoder);
+        self.lock.encode(encoder);
+        self.pockets
+            .iter()
+            .map(|(pocket, balance)| (pocket.clone(), balance.clone()))
+            .collect::<Vec<_>>()
+            .encode(encoder);
+        self.escrows.encode(encoder);
+        self.negative_since.encode(encoder);
+        self.velocity.encode(encoder);
+        self.notes.encode(encoder);
+        self.pending_withdrawals.encode(encoder);
+        self.wallets
+            .iter()
+            .map(|(name, wallet)| (name.clone(), wallet.clone()))
+            .collect::<Vec<_>>()
+            .encode(encoder);
+        self.promo_credits.encode(encoder);
+        self.holds.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> anyhow::Result<Self> {
+        let client_id = Client::decode(decoder)?.0;
+        let mut account = Self {
+            client_id,
+            transactions: TransactionStore::decode(decoder, client_id)?,
+            ledger: Vec::decode(decoder)?,
+            cases: Vec::decode(decoder)?,
+            amount_stats: AmountStats::decode(decoder)?,
+            available: BigDecimal::decode(decoder)?,
+            held: BigDecimal::decode(decoder)?,
+            // a lock by an operator was a lock like a chargeback's until then
+            lock: match decoder.version() {
+                ..=19 if bool::decode(decoder)? => LockState::ChargebackLocked,
+                ..=19 => LockState::Active,
+                _ => LockState::decode(decoder)?,
+            },
+            pockets: match decoder.version() {
+                ..=4 => BTreeMap::new(),
+                _ => Vec::decode(decoder)?.into_iter().collect(),
+            },
+            escrows: match decoder.version() {
+                ..=5 => vec![],
+                _ => Vec::decode(decoder)?,
+            },
+            // older snapshots don't know, a negative balance starts its clock when restored
+            negative_since: match decoder.version() {
+                ..=6 => None,
+                _ => Option::decode(decoder)?,
+            },
+            // older snapshots count from when they're restored
+            velocity: match decoder.version() {
+                ..=8 => Velocity::default(),
+                _ => Velocity::decode(decoder)?,
+            },
+            notes: match decoder.version() {
+                ..=9 => vec![],
+                _ => Vec::decode(decoder)?,
+            },
+            pending_withdrawals: match decoder.version() {
+                ..=14 => vec![],
+                _ => Vec::decode(decoder)?,
+            },
+            wallets: match decoder.version() {
+                ..=16 => BTreeMap::new(),
+                _ => Vec::decode(decoder)?.into_iter().collect(),
+            },
+            promo_credits: match decoder.version() {
+                ..=17 => vec![],
+                _ => Vec::decode(decoder)?,
+            },
+            holds: match decoder.version() {
+                ..=18 => vec![],
+                _ => Vec::decode(decoder)?,
+            },
+        };
+        if decoder.version() <= 20 {
+            let reversed = account
+                .ledger
+                .iter()
+                .filter(|entry| entry.kind == EntryKind::Reversal)
+                .map(|entry| entry.tx)
+                .collect::<Vec<_>>();
+            for tx in reversed {
+                account.transactions.reverse(tx);
+            }
+        }
+        Ok(account)
+    }
+}
+
+/// The kind of the fee account's entry for the transaction's fee
+const fn fee_kind(transaction: &Transaction) -> EntryKind {
+    match transaction {
+        Transaction::Chargeback { .. } => EntryKind::ChargebackFee,
+        _ => EntryKind::Fee,
+    }
+}
+
+/// What the first enricher that knows the transaction has on it. One that fails is reported
+/// and the next one asked
+fn enrich(
+    enrichers: &[Arc<dyn Enricher>],
+    observers: &[Arc<dyn DiagnosticsObserver>],
+    transaction: &Transaction,
+) -> Option<Enrichment> {
+    enrichers
+        .iter()
+        .find_map(|enricher| match enricher.enrich(transaction) {
+            Ok(enrichment) => enrichment,
+            Err(e) => {
+                let failed = EnrichmentFailed {
+                    client: transaction.client(),
+                    tx: transaction.tx(),
+                    error: format!("{:#}", e),
+                };
+                for observer in observers {
+                    observer.enrichment_failed(&failed);
+                }
+                None
+            }
+        })
+}
+
+fn report_pruned(
+    observers: &[Arc<dyn DiagnosticsObserver>],
+    client: ClientId,
+    pruned: Vec<(u32, String)>,
+) {
+    for (tx, reason) in pruned {
+        let pruned = Pruned { client, tx, reason };
+        for observer in observers {
+            observer.pruned(&pruned);
+        }
+    }
+}
+
+/// Read only access to an account, for reports and exports built on top of the engine
+#[derive(Clone, Copy)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AccountView<'a> {
+    account: &'a Account,
+}
+
+impl<'a> AccountView<'a> {
+    pub const fn client(&self) -> ClientId {
+        self.account.client_id
+    }
+
+    pub const fn available(&self) -> &'a BigDecimal {
+        &self.account.available
+    }
+
+    pub const fn held(&self) -> &'a BigDecimal {
+        &self.account.held
+    }
+
+    /// Available, held, the pockets and the wallets
+    pub fn total(&self) -> BigDecimal {
+        self.account.total()
+    }
+
+    /// The balances set aside in pockets, by name, see `Transaction::Transfer`
+    pub const fn pockets(&self) -> &'a BTreeMap<String, BigDecimal> {
+        &self.account.pockets
+    }
+
+    /// The client's wallets other than the main one, by name, see `Transaction::WalletTransfer`
+    pub const fn wallets(&self) -> &'a BTreeMap<String, Wallet> {
+        &self.account.wallets
+    }
+
+    pub const fn locked(&self) -> bool {
+        self.account.lock.is_locked()
+    }
+
+    /// Why it's locked, if it is, which decides what it can still do
+    pub const fn lock_state(&self) -> &'a LockState {
+        &self.account.lock
+    }
+
+    /// Since when (unix seconds) the available balance has been negative, if it is
+    pub const fn negative_since(&self) -> Option<u64> {
+        self.account.negative_since
+    }
+
+    /// When the account's oldest stored transaction was, unix seconds
+    pub fn first_stored_at(&self) -> Option<u64> {
+        self.account.transactions.first_stored_at()
+    }
+
+    /// The deposits and withdrawals of the last hour and day
+    pub const fn velocity(&self) -> &'a Velocity {
+        &self.account.velocity
+    }
+
+    /// What operators wrote about the account, oldest first
+    pub fn notes(&self) -> &'a [Note] {
+        &self.account.notes
+    }
+
+    /// The withdrawals waiting for an approve, oldest first
+    pub fn pending_withdrawals(&self) -> &'a [PendingWithdrawal] {
+        &self.account.pending_withdrawals
+    }
+
+    /// What's left of the promotional credits, the first to expire first
+    pub fn promo_credits(&self) -> &'a [PromoCredit] {
+        &self.account.promo_credits
+    }
+
+    /// The sum of the promotional credits, which withdrawals take before available
+    pub fn promo(&self) -> BigDecimal {
+        self.account.promo()
+    }
+
+    /// The holds and releases of operators, oldest first
+    pub fn holds(&self) -> &'a [HoldAction] {
+        &self.account.holds
+    }
+
+    /// The part of held that operators hold, see `TransactionEngine::hold`
+    pub fn admin_held(&self) -> BigDecimal {
+        self.account.admin_held()
+    }
+
+    pub const fn amount_stats(&self) -> &'a AmountStats {
+        &self.account.amount_stats
+    }
+
+    /// How many disputes the client raised and how they turned out
+    pub fn dispute_stats(&self) -> DisputeStats {
+        DisputeStats::of(&self.account.cases)
+    }
+
+    /// Whether the withdrawal tx was undone by a `reversal`
+    pub fn is_reversed(&self, tx: u32) -> bool {
+        self.account.transactions.is_reversed(tx)
+    }
+
+    /// The account's dispute cases, oldest first
+    pub fn cases(&self) -> &'a [Case] {
+        &self.account.cases
+    }
+
+    pub fn to_csv_account(&self) -> CsvAccount {
+        self.account.as_csv_account()
+    }
+
+    /// One row per pocket, by name
+    pub fn to_csv_pockets(&self) -> Vec<CsvPocket> {
+        self.account
+            .pockets
+            .iter()
+            .map(|(pocket, balance)| CsvPocket {
+                client: self.account.client_id,
+                pocket: pocket.clone(),
+                balance: format!("{:.4}", balance),
+            })
+            .collect()
+    }
+
+    /// One row per wallet, the main one first with an empty name
+    pub fn to_csv_wallets(&self) -> Vec<CsvWallet> {
+        let main = Wallet {
+            available: self.account.available.clone(),
+            held: self.account.held.clone(),
+        };
+        std::iter::once(("", &main))
+            .chain(
+                self.account
+                    .wallets
+                    .iter()
+                    .map(|(name, wallet)| (name.as_str(), wallet)),
+            )
+            .map(|(name, wallet)| CsvWallet {
+                client: self.account.client_id,
+                wallet: name.to_string(),
+                available: format!("{:.4}", wallet.available),
+                held: format!("{:.4}", wallet.held),
+                total: format!("{:.4}", wallet.total()),
+            })
+            .collect()
+    }
+
+    /// Everything the client sent, applied or not, oldest first
+    pub fn transactions(&self) -> impl Iterator<Item = Transaction> + 'a {
+        self.account.transactions.iter()
+    }
+
+    /// The balance changes applied to the account, oldest first
+    pub fn ledger(&self) -> &'a [LedgerEntry] {
+        &self.account.ledger
+    }
+}
+
+/// What a `TransactionHandler` can do to an account. Every change is in the ledger, under the
+/// tx of the custom transaction, and the balances can't be made to break the engine's invariants
+pub struct AccountMut<'a> {
+    account: &'a mut Account,
+    tx: u32,
+}
+
+impl AccountMut<'_> {
+    pub fn view(&self) -> AccountView<'_> {
+        AccountView {
+            account: self.account,
+        }
+    }
+
+    /// Adds to the available balance
+    pub fn credit(&mut self, amount: &BigDecimal) -> Result<(), TransactionError> {
+        if amount < &BigDecimal::zero() {
+            return self.debit(&-amount);
+        }
+        self.account.available += amount;
+        self.account.ledger.push(LedgerEntry::new(
+            self.tx,
+            EntryKind::Custom,
+            amount.clone(),
+            BigDecimal::zero(),
+        ));
+        Ok(())
+    }
+
+    /// Takes from the available balance, not below zero
+    pub fn debit(&mut self, amount: &BigDecimal) -> Result<(), TransactionError> {
+        if amount < &BigDecimal::zero() {
+            return self.credit(&-amount);
+        }
+        if &self.account.available < amount {
+            return Err(TransactionError::InsufficientFunds);
+        }
+        self.account.available -= amount;
+        self.account.ledger.push(LedgerEntry::new(
+            self.tx,
+            EntryKind::Custom,
+            -amount,
+            BigDecimal::zero(),
+        ));
+        Ok(())
+    }
+
+    /// Locks the account for a review, like `TransactionEngine::lock`, until an operator
+    /// unlocks it
+    pub fn lock(&mut self, reason: impl Into<String>) {
+        self.account.lock = LockState::ReviewLocked {
+            reason: reason.into(),
+        };
+        self.account.ledger.push(LedgerEntry::new(
+            self.tx,
+            EntryKind::Lock,
+            BigDecimal::zero(),
+            BigDecimal::zero(),
+        ));
+    }
+}
+
+/// A deposit or withdrawal looked up by its tx id, see `TransactionEngine::find_transaction`
+#[derive(Clone, Debug)]
+pub struct FoundTransaction<'a> {
+    pub client: ClientId,
+    pub transaction: Transaction,
+    /// Rejected transactions are kept too, e.g. a withdrawal without the funds for it
+    pub applied: bool,
+    /// The latest dispute of it, if it has been disputed
+    pub case: Option<&'a Case>,
+}
+
+impl FoundTransaction<'_> {
+    pub fn to_csv(&self) -> CsvFoundTransaction {
+        CsvFoundTransaction {
+            kind: self.transaction.type_name().to_string(),
+            client: self.client,
+            tx: self.transaction.tx(),
+            amount: self
+                .transaction
+                .amount()
+                .map(|amount| format!("{:.4}", amount)),
+            applied: self.applied,
+            dispute: self.case.map(|case| case.status().name()),
+        }
+    }
+}
+
+/// Names a transaction applied by `TransactionEngine::prepare` until it's committed or aborted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PreparedToken(u64);
+
+impl PreparedToken {
+    /// For passing the token to the system the commit is coordinated with, and back
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub const fn id(self) -> u64 {
+        self.0
+    }
+}
+
+/// A prepared transaction and how to take it back
+#[derive(Clone)]
+struct Prepared {
+    token: u64,
+    transaction: Transaction,
+    /// The client's account as it was before, with what other clients' transactions did to
+    /// it since, e.g. the fees credited to the fee account
+    before: Arc<Account>,
+    /// What it credited other clients, the seller of an escrow or the fee account
+    credits: Vec<(ClientId, EntryKind, BigDecimal)>,
+}
+
+impl Snapshot for Prepared {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.token.encode(encoder);
+        self.transaction.encode(encoder);
+        self.before.encode(encoder);
+        self.credits
+            .iter()
+            .map(|(client, kind, amount)| (Client(*client), *kind, amount.clone()))
+            .collect::<Vec<_>>()
+            .encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> anyhow::Result<Self> {
+        Ok(Self {
+            token: u64::decode(decoder)?,
+            transaction: Transaction::decode(decoder)?,
+            before: Arc::new(Account::decode(decoder)?),
+            credits: Vec::<(Client, EntryKind, BigDecimal)>::decode(decoder)?
+                .into_iter()
+                .map(|(Client(client), kind, amount)| (client, kind, amount))
+                .collect(),
+        })
+    }
+}
+
+/// Cloning is cheap, accounts are copied on write, so an engine can be cloned to try out
+/// transactions ("what if this batch was applied?") and thrown away afterwards
+#[derive(Clone)]
+pub struct TransactionEngine {
+    // I realize this means I'm storing both the client id as the key
+    // as well as in the Account struct, I assume that client id can't change
+    // but it's still not pretty to store it in two places
+    // but I think using a hashmap here is the cleanest
+    // and I think  account should store the client id
+    // shared until written to, so cloning an engine doesn't copy every account's history
+    accounts: HashMap<ClientId, Arc<Account>>,
+    config: EngineConfig,
+    /// The latest transaction timestamp seen, the engine is time-aware once this is set
+    time: Option<u64>,
+    /// (opened at, client, tx) of disputes in the order they were opened, used to expire them
+    open_disputes: VecDeque<(u64, ClientId, u32)>,
+    /// (expires at, client, tx) of the promotional credits, built from the accounts rather
+    /// than kept in snapshots, see `index_promo_credits`
+    promo_expiries: BTreeSet<(u64, ClientId, u32)>,
+    scorers: Vec<Arc<dyn RiskScorer>>,
+    alert_observers: Vec<Arc<dyn AlertObserver>>,
+    screening_observers: Vec<Arc<dyn ScreeningObserver>>,
+    diagnostics_observers: Vec<Arc<dyn DiagnosticsObserver>>,
+    review_queue: Vec<PendingTransaction>,
+    denied: Vec<DeniedTransaction>,
+    tags: AccountTags,
+    /// With external client ids, the ids the engine's clients have in the input
+    client_ids: ClientIds,
+    profile: Option<Profile>,
+    /// Counts the changes to the engine, for replicating it with `changes_since`
+    sequence: u64,
+    /// The sequence number of the latest change to every account
+    changed_at: HashMap<ClientId, u64>,
+    /// The sequence number of the latest transaction applied, see `applied_sequence`
+    applied: u64,
+    /// How long the last transaction applied waited for its account to be copied, when it was
+    /// shared with a clone of the engine. Only measured with `EngineConfig::latency_budget`
+    copy_wait: Duration,
+    /// The stored transactions of every account together, for `Limits::max_stored_transactions`
+    stored_transactions: usize,
+    /// What the ledger entries of the transactions processed now are tagged with
+    source: Option<Arc<str>>,
+    handlers: Vec<Arc<dyn TransactionHandler>>,
+    enrichers: Vec<Arc<dyn Enricher>>,
+    /// Transactions applied with `prepare` that aren't committed or aborted yet
+    prepared: Vec<Prepared>,
+    next_token: u64,
+    /// The `opening` rows for accounts that were seeded, not kept in snapshots
+    opening_conflicts: Vec<OpeningConflict>,
+}
+
+/// An `opening` row for an account opened with a balance already, and what
+/// `EngineConfig::opening_policy` made of it
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpeningConflict {
+    pub client: ClientId,
+    pub tx: u32,
+    /// The available balance the account was opened with
+    pub seeded: BigDecimal,
+    /// The amount of the row
+    pub file: BigDecimal,
+    pub policy: OpeningPolicy,
+}
+
+impl Default for TransactionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionEngine {
+    pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    /// An engine with `config.clock` follows it from the start
+    pub fn with_config(config: EngineConfig) -> Self {
+        let mut engine = Self {
+            accounts: HashMap::new(),
+            config,
+            time: None,
+            open_disputes: VecDeque::new(),
+            promo_expiries: BTreeSet::new(),
+            scorers: vec![],
+            alert_observers: vec![],
+            screening_observers: vec![],
+            diagnostics_observers: vec![],
+            review_queue: vec![],
+            denied: vec![],
+            tags: AccountTags::default(),
+            client_ids: ClientIds::default(),
+            profile: None,
+            sequence: 0,
+            changed_at: HashMap::new(),
+            applied: 0,
+            copy_wait: Duration::ZERO,
+            stored_transactions: 0,
+            source: None,
+            handlers: vec![],
+            enrichers: vec![],
+            prepared: vec![],
+            next_token: 1,
+            opening_conflicts: vec![],
+        };
+        engine.catch_up();
+        engine
+    }
+
+    /// Everything the engine has processed, held or denied, so another engine can carry on
+    /// from there with `from_snapshot`. The config and risk scorers are not included
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        self.encode_state(&mut encoder);
+        encoder.into_bytes()
+    }
+
+    pub fn from_snapshot(snapshot: &[u8], config: EngineConfig) -> anyhow::Result<Self> {
+        let mut decoder = Decoder::new(snapshot)?;
+        let engine = Self::decode_state(&mut decoder, config)?;
+        decoder.finish()?;
+        Ok(engine)
+    }
+
+    /// The body of `snapshot`, for snapshots that have more than the engine in them
+    pub fn encode_state(&self, encoder: &mut Encoder) {
+        let mut accounts = self.accounts.values().collect::<Vec<_>>();
+        accounts.sort_by_key(|account| account.client_id);
+        encoder.length(accounts.len());
+        for account in accounts {
+            account.encode(encoder);
+        }
+        self.time.encode(encoder);
+        self.encode_open_disputes(encoder);
+        self.review_queue.encode(encoder);
+        self.denied.encode(encoder);
+        self.tags.encode(encoder);
+        self.sequence.encode(encoder);
+        let mut changed_at = self.changed_at.iter().collect::<Vec<_>>();
+        changed_at.sort_unstable();
+        encoder.length(changed_at.len());
+        for (client, sequence) in changed_at {
+            (Client(*client), *sequence).encode(encoder);
+        }
+        self.prepared.encode(encoder);
+        self.next_token.encode(encoder);
+        self.client_ids.encode(encoder);
+        self.applied.encode(encoder);
+    }
+
+    pub fn decode_state(decoder: &mut Decoder<'_>, config: EngineConfig) -> anyhow::Result<Self> {
+        let mut engine = Self::with_config(config);
+        engine.accounts = Vec::<Account>::decode(decoder)?
+            .into_iter()
+            .map(|account| (account.client_id, Arc::new(account)))
+            .collect();
+        engine.time = Option::decode(decoder)?;
+        engine.open_disputes = Self::decode_open_disputes(decoder)?;
+        engine.review_queue = Vec::decode(decoder)?;
+        engine.denied = Vec::decode(decoder)?;
+        engine.tags = AccountTags::decode(decoder)?;
+        if decoder.version() >= 2 {
+            engine.sequence = u64::decode(decoder)?;
+            engine.changed_at = Vec::<(Client, u64)>::decode(decoder)?
+                .into_iter()
+                .map(|(Client(client), sequence)| (client, sequence))
+                .collect();
+        } else {
+            // nothing is known about older snapshots' changes, treat it all as one
+            engine.sequence = 1;
+            engine.changed_at = engine.accounts.keys().map(|&client| (client, 1)).collect();
+        }
+        if decoder.version() < 3 {
+            // when the transactions were stored wasn't kept yet, as if it was just now
+            let now = engine.now();
+            for account in engine.accounts.values_mut() {
+                Arc::make_mut(account).transactions.restamp(now);
+            }
+        }
+        if decoder.version() >= 8 {
+            engine.prepared = Vec::decode(decoder)?;
+            engine.next_token = u64::decode(decoder)?;
+        }
+        if decoder.version() >= 12 {
+            engine.client_ids = ClientIds::decode(decoder)?;
+        }
+        if decoder.version() >= 13 {
+            engine.applied = u64::decode(decoder)?;
+        }
+        engine.count_stored_transactions();
+        engine.index_promo_credits();
+        Ok(engine)
+    }
+
+    fn encode_open_disputes(&self, encoder: &mut Encoder) {
+        self.open_disputes
+            .iter()
+            .map(|&(opened_at, client, tx)| (opened_at, Client(client), tx))
+            .collect::<Vec<_>>()
+            .encode(encoder);
+    }
+
+    fn decode_open_disputes(
+        decoder: &mut Decoder<'_>,
+    ) -> anyhow::Result<VecDeque<(u64, ClientId, u32)>> {
+        Ok(VecDeque::<(u64, Client, u32)>::decode(decoder)?
+            .into_iter()
+            .map(|(opened_at, Client(client), tx)| (opened_at, client, tx))
+            .collect())
+    }
+
+    /// Counts up with every change, a replica that applied the changes up to a sequence number
+    /// asks for the ones after it
+    pub const fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    fn count_stored_transactions(&mut self) {
+        self.stored_transactions = self
+            .accounts
+            .values()
+            .map(|account| account.transactions.len())
+            .sum();
+    }
+
+    /// Whether `transaction` fits within `EngineConfig::limits`
+    fn check_limits(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let limits = self.config.limits;
+        if let Some(max) = limits.max_accounts {
+            // an escrow's seller gets an account when it's released
+            let seller = match transaction {
+                Transaction::EscrowOpen { seller, .. } => Some(*seller),
+                _ => None,
+            };
+            let new = [Some(transaction.client()), seller]
+                .into_iter()
+                .flatten()
+                .filter(|client| !self.accounts.contains_key(client))
+                .count();
+            if new > 0 && self.accounts.len() + new > max {
+                return Err(TransactionError::TooManyAccounts);
+            }
+        }
+        if let Some(max) = limits.max_stored_transactions {
+            if self.stored_transactions >= max {
+                return Err(TransactionError::TooManyTransactions);
+            }
+        }
+        Ok(())
+    }
+
+    /// The sequence number of the latest transaction applied. Applied transactions are numbered
+    /// from 1 in the order they're applied, which their ledger entries, `changes_since` and the
+    /// server's answers carry, so downstream systems have a total order and a cursor to resume
+    /// from. Unlike `sequence` rejected transactions and operator actions don't take a number,
+    /// the only gaps are left by prepared transactions that were aborted
+    pub const fn applied_sequence(&self) -> u64 {
+        self.applied
+    }
+
+    /// The clients whose accounts changed after sequence number `since`, in order, e.g. to only
+    /// output the accounts a batch touched
+    pub fn changed_since(&self, since: u64) -> Vec<ClientId> {
+        let mut clients = self
+            .changed_at
+            .iter()
+            .filter(|&(client, &sequence)| sequence > since && self.accounts.contains_key(client))
+            .map(|(&client, _)| client)
+            .collect::<Vec<_>>();
+        clients.sort_unstable();
+        clients
+    }
+
+    fn record_change(&mut self, client: Option<ClientId>) {
+        self.sequence += 1;
+        if let Some(client) = client {
+            self.changed_at.insert(client, self.sequence);
+        }
+    }
+
+    /// What changed after sequence number `since`, for `apply_changes` on a replica of the
+    /// engine: the accounts changed since then as a whole, and the time, open disputes,
+    /// review queue and tags, which are small next to the accounts. 0 is everything.
+    /// `None` if `since` is ahead of the engine, e.g. after it was restored from an older
+    /// snapshot, the replica has to start over from 0
+    pub fn changes_since(&self, since: u64) -> Option<Vec<u8>> {
+        if since > self.sequence {
+            return None;
+        }
+        let mut accounts = self
+            .changed_at
+            .iter()
+            .filter(|&(_, &sequence)| sequence > since)
+            .filter_map(|(client, &sequence)| {
+                self.accounts.get(client).map(|account| (sequence, account))
+            })
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|(_, account)| account.client_id);
+        let mut encoder = Encoder::new();
+        since.encode(&mut encoder);
+        self.sequence.encode(&mut encoder);
+        encoder.length(accounts.len());
+        for (sequence, account) in accounts {
+            sequence.encode(&mut encoder);
+            account.encode(&mut encoder);
+        }
+        self.time.encode(&mut encoder);
+        self.encode_open_disputes(&mut encoder);
+        self.review_queue.encode(&mut encoder);
+        self.denied.encode(&mut encoder);
+        self.tags.encode(&mut encoder);
+        self.applied.encode(&mut encoder);
+        Some(encoder.into_bytes())
+    }
+
+    /// Brings a replica up to date with the output of the primary's `changes_since`, returns
+    /// the sequence number it's at now. The replica's own sequence follows the primary's, so
+    /// it should only change through this. Changes from 0 replace everything, others have to
+    /// start at or before the replica's sequence number, and applying them again is harmless
+    pub fn apply_changes(&mut self, changes: &[u8]) -> anyhow::Result<u64> {
+        let mut decoder = Decoder::new(changes)?;
+        let since = u64::decode(&mut decoder)?;
+        let sequence = u64::decode(&mut decoder)?;
+        if since > self.sequence {
+            anyhow::bail!(
+                "The changes start after sequence {}, this replica is at {}",
+                since,
+                self.sequence
+            );
+        }
+        let accounts = Vec::<(u64, Account)>::decode(&mut decoder)?;
+        let time = Option::decode(&mut decoder)?;
+        let open_disputes = Self::decode_open_disputes(&mut decoder)?;
+        let review_queue = Vec::decode(&mut decoder)?;
+        let denied = Vec::decode(&mut decoder)?;
+        let tags = AccountTags::decode(&mut decoder)?;
+        let applied = match decoder.version() {
+            ..=12 => self.applied,
+            _ => u64::decode(&mut decoder)?,
+        };
+        decoder.finish()?;
+
+        if since == 0 {
+            self.accounts.clear();
+            self.changed_at.clear();
+        }
+        for (changed_at, account) in accounts {
+            self.changed_at.insert(account.client_id, changed_at);
+            self.accounts.insert(account.client_id, Arc::new(account));
+        }
+        self.time = time;
+        self.open_disputes = open_disputes;
+        self.review_queue = review_queue;
+        self.denied = denied;
+        self.tags = tags;
+        self.applied = applied;
+        self.sequence = if since == 0 {
+            sequence
+        } else {
+            self.sequence.max(sequence)
+        };
+        self.count_stored_transactions();
+        self.index_promo_credits();
+        Ok(self.sequence)
+    }
+
+    /// Takes over the accounts, cases, review queue and tags of an engine that processed
+    /// other transactions, e.g. another shard of the input. Nothing is merged on conflict.
+    /// What the other engine has prepared counts as committed, its tokens mean nothing here
+    pub fn merge(&mut self, other: Self, on_conflict: OnConflict) -> Result<(), MergeConflict> {
+        if on_conflict != OnConflict::Sum {
+            let fee_account = self
+                .config
+                .fees
+                .account()
+                .filter(|_| on_conflict == OnConflict::SumFeeAccount);
+            let conflict = other
+                .accounts
+                .keys()
+                .filter(|client| self.accounts.contains_key(client))
+                .filter(|&&client| Some(client) != fee_account)
+                .min();
+            if let Some(&client) = conflict {
+                return Err(MergeConflict::Account(client));
+            }
+        }
+        let mut client_ids = self.client_ids.clone();
+        client_ids
+            .merge(&other.client_ids)
+            .map_err(MergeConflict::ExternalId)?;
+        self.client_ids = client_ids;
+
+        for (client, account) in other.accounts {
+            self.record_change(Some(client));
+            match self.accounts.get_mut(&client) {
+                None => {
+                    self.accounts.insert(client, account);
+                }
+                Some(existing) => {
+                    let account = Arc::unwrap_or_clone(account);
+                    for prepared in self.prepared.iter_mut() {
+                        if prepared.transaction.client() == client {
+                            Arc::make_mut(&mut prepared.before).merge(account.clone());
+                        }
+                    }
+                    Arc::make_mut(existing).merge(account);
+                }
+            }
+        }
+        self.time = self.time.max(other.time);
+        self.open_disputes.extend(other.open_disputes);
+        // expiring relies on them being in the order they were opened
+        self.open_disputes
+            .make_contiguous()
+            .sort_by_key(|&(opened_at, _, _)| opened_at);
+        self.index_promo_credits();
+        self.review_queue.extend(other.review_queue);
+        self.denied.extend(other.denied);
+        self.opening_conflicts.extend(other.opening_conflicts);
+        // the engines counted their transactions independently, so the numbers carry on
+        // after the highest
+        self.applied = self.applied.max(other.applied);
+        self.tags.merge(other.tags);
+        if let (Some(profile), Some(other)) = (&mut self.profile, &other.profile) {
+            profile.merge(other);
+        }
+        self.count_stored_transactions();
+        Ok(())
+    }
+
+    /// Starts accounts off with the balances of a previous run's accounts csv, for chained runs
+    /// without snapshots. Only the balances carry over, so funds held there stay held:
+    /// the disputes behind them aren't known, they can't be resolved or charged back. What the
+    /// total has on top of available and held goes into the `RESUMED_POCKET`
+    pub fn open_accounts(
+        &mut self,
+        accounts: impl IntoIterator<Item = CsvAccount>,
+    ) -> anyhow::Result<()> {
+        for csv_account in accounts {
+            let client = csv_account.client;
+            if self.accounts.contains_key(&client) {
+                anyhow::bail!("Client {} already has an account", client);
+            }
+            if let Some(max) = self.config.limits.max_accounts {
+                if self.accounts.len() >= max {
+                    anyhow::bail!("There are more accounts than the maximum of {}", max);
+                }
+            }
+            let amount = |amount: &str| {
+                amount.parse::<BigDecimal>().with_context(|| {
+                    format!(
+                        "Invalid amount {} in the account of client {}",
+                        amount, client
+                    )
+                })
+            };
+            let available = amount(&csv_account.available)?;
+            let held = amount(&csv_account.held)?;
+            // the pockets, other wallets and promotional credits are only part of the total,
+            // which one the rest was in isn't in the csv
+            let set_aside = amount(&csv_account.total)? - &available - &held;
+            if set_aside < BigDecimal::zero() {
+                anyhow::bail!(
+                    "The total of client {} is less than available + held, {} < {} + {}",
+                    client,
+                    csv_account.total,
+                    csv_account.available,
+                    csv_account.held
+                );
+            }
+            let mut account = Account::new(client);
+            let mut opening =
+                LedgerEntry::new(0, EntryKind::Opening, available.clone(), held.clone());
+            if !set_aside.is_zero() {
+                opening
+                    .pockets
+                    .push((RESUMED_POCKET.to_string(), set_aside.clone()));
+                account
+                    .pockets
+                    .insert(RESUMED_POCKET.to_string(), set_aside);
+            }
+            account.ledger.push(opening);
+            account.available = available;
+            account.held = held;
+            if csv_account.locked {
+                account.lock = LockState::ChargebackLocked;
+            }
+            account.track_negative(self.now());
+            self.accounts.insert(client, Arc::new(account));
+            self.record_change(Some(client));
+        }
+        Ok(())
+    }
+
+    /// Adds a scorer that's asked about every transaction before it's applied,
+    /// only has an effect together with `EngineConfig::risk_threshold`
+    pub fn register_scorer(&mut self, scorer: Box<dyn RiskScorer>) {
+        self.scorers.push(Arc::from(scorer));
+    }
+
+    /// Adds the handler of a custom transaction type. Types already taken, built in or by
+    /// another handler, are refused
+    pub fn register_handler(&mut self, handler: Box<dyn TransactionHandler>) -> anyhow::Result<()> {
+        let kind = handler.kind();
+        if TransactionType::from_name(kind).is_some() || self.handles(kind) {
+            anyhow::bail!("There already is a transaction type {}", kind);
+        }
+        self.handlers.push(Arc::from(handler));
+        Ok(())
+    }
+
+    /// Whether a handler is registered for the custom transaction type
+    pub fn handles(&self, kind: &str) -> bool {
+        self.handlers.iter().any(|handler| handler.kind() == kind)
+    }
+
+    /// Adds an observer that's told about every alert of `EngineConfig::alerts` that fires
+    pub fn register_alert_observer(&mut self, observer: Box<dyn AlertObserver>) {
+        self.alert_observers.push(Arc::from(observer));
+    }
+
+    /// Adds an observer that's told about every transaction `EngineConfig::blocklist` stops
+    pub fn register_screening_observer(&mut self, observer: Box<dyn ScreeningObserver>) {
+        self.screening_observers.push(Arc::from(observer));
+    }
+
+    /// Adds an observer that's told about the transactions `EngineConfig::pruning` drops, the
+    /// ones that take longer than `EngineConfig::latency_budget` to apply and what the
+    /// enrichers and screening observers fail at
+    pub fn register_diagnostics_observer(&mut self, observer: Box<dyn DiagnosticsObserver>) {
+        self.diagnostics_observers.push(Arc::from(observer));
+    }
+
+    /// Adds a lookup the deposits and withdrawals are enriched from as they're applied, the
+    /// first enricher that knows a transaction wins
+    pub fn register_enricher(&mut self, enricher: Box<dyn Enricher>) {
+        self.enrichers.push(Arc::from(enricher));
+    }
+
+    /// Tags can be given to clients before they have any transactions
+    pub const fn tags(&self) -> &AccountTags {
+        &self.tags
+    }
+
+    /// Empty unless the clients have external ids, see `ClientIds`
+    pub const fn client_ids(&self) -> &ClientIds {
+        &self.client_ids
+    }
+
+    /// E.g. with the ids interned while reading the input
+    pub fn set_client_ids(&mut self, client_ids: ClientIds) {
+        self.record_change(None);
+        self.client_ids = client_ids;
+    }
+
+    pub fn tags_mut(&mut self) -> &mut AccountTags {
+        self.record_change(None);
+        &mut self.tags
+    }
+
+    /// Transactions held for review by the risk scorers, oldest first
+    pub fn pending_review(&self) -> &[PendingTransaction] {
+        &self.review_queue
+    }
+
+    /// Transactions that were held for review and then denied, oldest first
+    pub fn denied(&self) -> &[DeniedTransaction] {
+        &self.denied
+    }
+
+    /// Applies a held transaction without asking the risk scorers again,
+    /// it can still be rejected for the usual reasons, e.g. insufficient funds
+    pub fn approve(&mut self, client: ClientId, tx: u32) -> Result<(), TransactionError> {
+        let pending = self.take_pending(client, tx)?;
+        self.apply(pending.transaction)
+    }
+
+    /// Drops a held transaction without applying it
+    pub fn deny(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+        reason: impl Into<String>,
+    ) -> Result<(), TransactionError> {
+        let pending = self.take_pending(client, tx)?;
+        self.denied.push(DeniedTransaction {
+            transaction: pending.transaction,
+            score: pending.score,
+            reason: reason.into(),
+        });
+        self.record_change(None);
+        Ok(())
+    }
+
+    fn take_pending(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+    ) -> Result<PendingTransaction, TransactionError> {
+        match self.review_queue.iter().position(|pending| {
+            pending.transaction.client() == client && pending.transaction.tx() == tx
+        }) {
+            None => Err(TransactionError::UnknownTransaction),
+            Some(index) => Ok(self.review_queue.remove(index)),
+        }
+    }
+
+    /// Applies an `opening` row, a deposit of the client's opening balance. For an account
+    /// that was opened from an accounts csv with `open_accounts` that's a conflict, settled by
+    /// `EngineConfig::opening_policy` and kept in `opening_conflicts`
+    pub fn process_opening(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+        amount: BigDecimal,
+    ) -> Result<(), TransactionError> {
+        let seeded = self
+            .accounts
+            .get(&client)
+            .and_then(|account| account.ledger.first())
+            .filter(|entry| entry.kind == EntryKind::Opening)
+            .map(|entry| entry.available.clone());
+        let deposit = Transaction::Deposit {
+            client,
+            tx,
+            amount: amount.clone(),
+            wallet: None,
+        };
+        let Some(seeded) = seeded else {
+            return self.process(deposit);
+        };
+        let policy = self.config.opening_policy;
+        let result = match policy {
+            OpeningPolicy::Error => Err(TransactionError::OpeningConflict),
+            OpeningPolicy::PreferFile => self.adjust(client, &amount - &seeded),
+            OpeningPolicy::PreferSeed => Ok(()),
+            OpeningPolicy::Sum => self.process(deposit),
+        };
+        self.opening_conflicts.push(OpeningConflict {
+            client,
+            tx,
+            seeded,
+            file: amount,
+            policy,
+        });
+        result
+    }
+
+    /// The `opening` rows `process_opening` found a seeded balance for, in the order they
+    /// were processed
+    pub fn opening_conflicts(&self) -> &[OpeningConflict] {
+        &self.opening_conflicts
+    }
+
+    pub fn process(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        self.catch_up();
+        let started = self.profile.is_some().then(Instant::now);
+        let validated = self.validate(transaction);
+        self.record(Stage::Validate, started);
+
+        let transaction = validated?;
+        let budget = self.config.latency_budget;
+        let (kind, client, tx) = (
+            budget.map(|_| transaction.type_name().to_string()),
+            transaction.client(),
+            transaction.tx(),
+        );
+        let started = (self.profile.is_some() || budget.is_some()).then(Instant::now);
+        let result = self.apply(transaction);
+        if let (Some(budget), Some(started), Some(kind)) = (budget, started, kind) {
+            let elapsed = started.elapsed();
+            // there's no account when a limit rejected it
+            if let (true, Some(account)) = (elapsed > budget, self.accounts.get(&client)) {
+                let slow = SlowTransaction {
+                    kind,
+                    client,
+                    tx,
+                    elapsed,
+                    budget,
+                    stored: account.transactions.len(),
+                    entries: account.ledger.len(),
+                    copy_wait: self.copy_wait,
+                };
+                for observer in &self.diagnostics_observers {
+                    observer.slow(&slow);
+                }
+            }
+        }
+        self.record(Stage::Apply, started);
+        result
+    }
+
+    /// The transform, blocklist, business rules and risk scorers, a transaction held for review
+    /// is queued here
+    fn validate(&mut self, transaction: Transaction) -> Result<Transaction, TransactionError> {
+        let transaction = if self.config.transform.is_empty() {
+            transaction
+        } else {
+            let account = self
+                .accounts
+                .get(&self.config.joint_accounts.account(transaction.client()))
+                .map(|account| AccountView { account });
+            self.config
+                .transform
+                .apply(transaction, account, self.now())
+                .map_err(TransactionError::TransformFailed)?
+        };
+        if let Some(action) = self.config.blocklist.screen(transaction.client()) {
+            let screening = Screening {
+                at: self.now(),
+                client: transaction.client(),
+                tx: transaction.tx(),
+                kind: transaction.type_name().to_string(),
+                action,
+            };
+            for observer in &self.screening_observers {
+                // the transaction is stopped whether or not that could be recorded
+                if let Err(e) = observer.screened(&screening) {
+                    let unrecorded = UnrecordedScreening {
+                        screening: screening.clone(),
+                        error: format!("{:#}", e),
+                    };
+                    for observer in &self.diagnostics_observers {
+                        observer.screening_unrecorded(&unrecorded);
+                    }
+                }
+            }
+            return match action {
+                ScreeningAction::Reject => Err(TransactionError::Blocked),
+                ScreeningAction::Hold => {
+                    // first in line for a reviewer, no score gets higher
+                    self.review_queue.push(PendingTransaction {
+                        transaction,
+                        score: f64::INFINITY,
+                    });
+                    self.record_change(None);
+                    Err(TransactionError::HeldForReview)
+                }
+            };
+        }
+        // the rules and risk scorers look at the shared account of a joint holder
+        let account = self
+            .accounts
+            .get(&self.config.joint_accounts.account(transaction.client()))
+            .map(|account| AccountView { account });
+        if let Some(reason) = self.config.rules.check(&transaction, account, self.now()) {
+            return Err(TransactionError::RejectedByRule(reason.to_string()));
+        }
+
+        if let Some(threshold) = self.config.risk_threshold {
+            // a locked account will reject it anyway
+            let review = &self.config.review_lock;
+            if account
+                .is_none_or(|account| account.lock_state().check(&transaction, review).is_ok())
+            {
+                let score = self
+                    .scorers
+                    .iter()
+                    .map(|scorer| scorer.score(&transaction, account))
+                    .fold(0.0, f64::max);
+                if score > threshold {
+                    self.review_queue
+                        .push(PendingTransaction { transaction, score });
+                    self.record_change(None);
+                    return Err(TransactionError::HeldForReview);
+                }
+            }
+        }
+
+        Ok(transaction)
+    }
+
+    fn record(&mut self, stage: Stage, started: Option<Instant>) {
+        if let (Some(profile), Some(started)) = (&mut self.profile, started) {
+            profile.record_since(stage, started);
+        }
+    }
+
+    /// Starts timing how long validating and applying transactions takes
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::default);
+    }
+
+    /// For timing the stages outside the engine, if profiling is enabled
+    pub fn profile_mut(&mut self) -> Option<&mut Profile> {
+        self.profile.as_mut()
+    }
+
+    /// The timings so far, if profiling is enabled, and stops timing
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// The first phase of applying a transaction together with a change elsewhere, e.g. a
+    /// payout by an external system: it's validated and applied as with `process`, but can
+    /// still be taken back with `abort`. Until it's committed or aborted the client's other
+    /// transactions and operator changes are rejected with `PendingCommit`. A rejected
+    /// transaction isn't prepared, there's nothing to commit.
+    /// Prepared transactions are kept in snapshots, so a restarted host can finish them
+    pub fn prepare(&mut self, transaction: Transaction) -> Result<PreparedToken, TransactionError> {
+        self.catch_up();
+        let transaction = self.validate(transaction)?;
+        // applied as the holder sent it, it's the account's that's prepared
+        let applied = transaction.clone();
+        let transaction = self.config.joint_accounts.map(transaction);
+        let client = transaction.client();
+        self.check_pending(client)?;
+        let before = self
+            .accounts
+            .get(&client)
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Account::new(client)));
+        let payee = self.escrow_payee(&transaction)?;
+        let fee = self.config.fees.fee(&transaction);
+        let absorbed = self.config.fees.absorbed_fee(&transaction);
+        self.apply(applied)?;
+
+        let mut credits = vec![];
+        if let Some((seller, amount)) = payee {
+            credits.push((seller, EntryKind::EscrowRelease, amount));
+        }
+        if let Some(fee_account) = self.config.fees.account() {
+            let kind = fee_kind(&transaction);
+            if !fee.is_zero() {
+                credits.push((fee_account, kind, fee));
+            }
+            if !absorbed.is_zero() {
+                credits.push((fee_account, kind, -absorbed));
+            }
+        }
+        let token = self.next_token;
+        self.next_token += 1;
+        self.prepared.push(Prepared {
+            token,
+            transaction,
+            before,
+            credits,
+        });
+        self.record_change(None);
+        Ok(PreparedToken(token))
+    }
+
+    /// Makes a prepared transaction final
+    pub fn commit(&mut self, token: PreparedToken) -> Result<(), TransactionError> {
+        self.take_prepared(token)?;
+        self.record_change(None);
+        Ok(())
+    }
+
+    /// Takes a prepared transaction back, its client's account is as if it never came and
+    /// what it credited other clients is debited again
+    pub fn abort(&mut self, token: PreparedToken) -> Result<(), TransactionError> {
+        let prepared = self.take_prepared(token)?;
+        let (client, tx) = (prepared.transaction.client(), prepared.transaction.tx());
+        let stored = self
+            .accounts
+            .get(&client)
+            .map_or(0, |account| account.transactions.len());
+        self.stored_transactions =
+            self.stored_transactions - stored + prepared.before.transactions.len();
+        self.accounts.insert(client, prepared.before);
+        if let Transaction::Dispute { .. } = prepared.transaction {
+            self.open_disputes
+                .retain(|&(_, disputed, disputed_tx)| (disputed, disputed_tx) != (client, tx));
+        }
+        self.record_change(Some(client));
+        for (other, kind, amount) in prepared.credits {
+            self.credit(other, tx, kind, -amount, None);
+        }
+        Ok(())
+    }
+
+    /// The transactions prepared and not committed or aborted yet, oldest first
+    pub fn prepared(&self) -> impl Iterator<Item = (PreparedToken, &Transaction)> {
+        self.prepared
+            .iter()
+            .map(|prepared| (PreparedToken(prepared.token), &prepared.transaction))
+    }
+
+    fn take_prepared(&mut self, token: PreparedToken) -> Result<Prepared, TransactionError> {
+        match self
+            .prepared
+            .iter()
+            .position(|prepared| prepared.token == token.0)
+        {
+            None => Err(TransactionError::UnknownPrepared),
+            Some(index) => Ok(self.prepared.remove(index)),
+        }
+    }
+
+    fn check_pending(&self, client: ClientId) -> Result<(), TransactionError> {
+        match self
+            .prepared
+            .iter()
+            .any(|prepared| prepared.transaction.client() == client)
+        {
+            true => Err(TransactionError::PendingCommit),
+            false => Ok(()),
+        }
+    }
+
+    fn apply(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let holder = transaction.client();
+        let transaction = self.config.joint_accounts.map(transaction);
+        let holder = (holder != transaction.client()).then_some(holder);
+        self.check_pending(transaction.client())?;
+        // before anything is stored, a rejected transaction would be stored too
+        self.check_limits(&transaction)?;
+        let payee = self.escrow_payee(&transaction)?;
+        let fee = self.config.fees.fee(&transaction);
+        let absorbed = self.config.fees.absorbed_fee(&transaction);
+        let fee_kind = fee_kind(&transaction);
+        let now = self.now();
+        let dispute = match transaction {
+            Transaction::Dispute { client, tx } => Some((now, client, tx)),
+            _ => None,
+        };
+        let promo_expiry = match transaction {
+            Transaction::PromoCredit {
+                client,
+                tx,
+                expires_at,
+                ..
+            } => Some((expires_at, client, tx)),
+            _ => None,
+        };
+        let context = self
+            .checks_invariants()
+            .then(|| format!("{:?}", transaction));
+        let (client, transaction_tx) = (transaction.client(), transaction.tx());
+        let before = (!self.config.alerts.is_empty()).then(|| {
+            self.accounts
+                .get(&client)
+                .map(|account| (account.available.clone(), account.held.clone()))
+        });
+        let account = self
+            .accounts
+            .entry(transaction.client())
+            .or_insert_with(|| Arc::new(Account::new(transaction.client())));
+        // accounts aren't locked, but one shared with a clone of the engine is copied first
+        let copy_started = self.config.latency_budget.is_some().then(Instant::now);
+        let account = Arc::make_mut(account);
+        self.copy_wait = copy_started.map_or(Duration::ZERO, |started| started.elapsed());
+        let stored = account.transactions.len();
+        let entries = account.ledger.len();
+
+        let closes = matches!(
+            transaction,
+            Transaction::Resolve { .. } | Transaction::Chargeback { .. }
+        )
+        .then(|| transaction.tx());
+        let enrichment = match &transaction {
+            Transaction::Deposit { .. } | Transaction::Withdrawal { .. } => {
+                enrich(&self.enrichers, &self.diagnostics_observers, &transaction).map(Arc::new)
+            }
+            Transaction::Dispute { tx, .. }
+            | Transaction::Resolve { tx, .. }
+            | Transaction::Chargeback { tx, .. }
+            | Transaction::Approve { tx, .. }
+            | Transaction::Deny { tx, .. }
+            | Transaction::Reversal { tx, .. } => account
+                .ledger
+                .iter()
+                .find(|entry| {
+                    entry.tx == *tx
+                        && matches!(
+                            entry.kind,
+                            EntryKind::Deposit
+                                | EntryKind::Withdrawal
+                                | EntryKind::PendingWithdrawal
+                        )
+                })
+                .and_then(|entry| entry.enrichment.clone()),
+            _ => None,
+        };
+        let result = match &transaction {
+            Transaction::Reversal { tx, .. } => account
+                .lock
+                .check(&transaction, &self.config.review_lock)
+                .and_then(|()| account.reverse(*tx, now, self.config.reversal_window)),
+            Transaction::Custom { kind, tx, .. } => {
+                match self.handlers.iter().find(|handler| handler.kind() == kind) {
+                    None => Err(TransactionError::RejectedByHandler(format!(
+                        "no handler for {}",
+                        kind
+                    ))),
+                    Some(handler) => account
+                        .lock
+                        .check(&transaction, &self.config.review_lock)
+                        .and_then(|()| {
+                            handler.apply(&transaction, &mut AccountMut { account, tx: *tx })
+                        }),
+                }
+            }
+            _ => {
+                let approval = match &transaction {
+                    Transaction::Withdrawal { amount, .. } => {
+                        self.config.approvals.requires_approval(client, amount)
+                    }
+                    _ => false,
+                };
+                let reserve = self.config.reserves.reserve(client, &self.tags);
+                account
+                    .lock
+                    .check(&transaction, &self.config.review_lock)
+                    .and_then(|()| {
+                        account.process(
+                            transaction,
+                            now,
+                            &fee,
+                            self.config.compat,
+                            approval,
+                            &reserve,
+                        )
+                    })
+            }
+        };
+        if result.is_ok() {
+            self.applied += 1;
+        }
+        for entry in &mut account.ledger[entries..] {
+            entry.source.clone_from(&self.source);
+            entry.sequence = Some(self.applied);
+            entry.enrichment.clone_from(&enrichment);
+            entry.holder = holder;
+        }
+        account.track_negative(now);
+        if let Some(pruning) = self.config.pruning {
+            let closed = closes.filter(|_| result.is_ok());
+            let pruned = account.prune(pruning, closed, now);
+            report_pruned(&self.diagnostics_observers, client, pruned);
+        }
+        self.stored_transactions = self.stored_transactions + account.transactions.len() - stored;
+        if let Some(context) = context {
+            if let Err(violation) = account.check_invariants() {
+                panic!("{} after {}", violation, context);
+            }
+        }
+        if let (Ok(()), Some(before)) = (&result, before) {
+            let alerts = self.config.alerts.fired(
+                client,
+                &self.tags,
+                before.as_ref().map(|(available, held)| (available, held)),
+                (&account.available, &account.held),
+            );
+            for alert in &alerts {
+                for observer in &self.alert_observers {
+                    observer.alert(alert);
+                }
+            }
+        }
+        if let (Ok(()), Some((seller, amount))) = (&result, payee) {
+            let sequence = Some(self.applied);
+            self.credit(
+                seller,
+                transaction_tx,
+                EntryKind::EscrowRelease,
+                amount,
+                sequence,
+            );
+        }
+        if let (Ok(()), Some(fee_account)) = (&result, self.config.fees.account()) {
+            let sequence = Some(self.applied);
+            if !fee.is_zero() {
+                self.credit(fee_account, transaction_tx, fee_kind, fee, sequence);
+            }
+            if !absorbed.is_zero() {
+                self.credit(fee_account, transaction_tx, fee_kind, -absorbed, sequence);
+            }
+        }
+        // rejected transactions are kept in the account too
+        self.record_change(Some(client));
+        result?;
+        if let (Some(dispute), Some(_)) = (dispute, self.config.dispute_timeout) {
+            self.open_disputes.push_back(dispute);
+        }
+        if let Some(expiry) = promo_expiry {
+            self.promo_expiries.insert(expiry);
+        }
+        Ok(())
+    }
+
+    fn index_promo_credits(&mut self) {
+        self.promo_expiries = self
+            .accounts
+            .values()
+            .flat_map(|account| {
+                account
+                    .promo_credits
+                    .iter()
+                    .map(|credit| (credit.expires_at, account.client_id, credit.tx))
+            })
+            .collect();
+    }
+
+    /// The seller and the amount an escrow release pays, checked before the buyer's side
+    /// is applied so it can't be half done
+    fn escrow_payee(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Option<(ClientId, BigDecimal)>, TransactionError> {
+        let Transaction::EscrowRelease { client, tx } = *transaction else {
+            return Ok(None);
+        };
+        let escrow = self.accounts.get(&client).and_then(|account| {
+            account
+                .escrows
+                .iter()
+                .find(|escrow| escrow.tx() == tx && escrow.status() == EscrowStatus::Open)
+        });
+        match escrow {
+            // the buyer's side rejects it
+            None => Ok(None),
+            Some(escrow) => match self.accounts.get(&escrow.seller()) {
+                Some(seller) if seller.lock.is_locked() => Err(TransactionError::AccountLocked),
+                _ => Ok(Some((escrow.seller(), escrow.amount().clone()))),
+            },
+        }
+    }
+
+    /// Adds to the available balance of another client than the transaction's, e.g. an
+    /// escrow's seller, `tx` is the transaction's
+    fn credit(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+        kind: EntryKind,
+        amount: BigDecimal,
+        sequence: Option<u64>,
+    ) {
+        let now = self.now();
+        let account = Arc::make_mut(
+            self.accounts
+                .entry(client)
+                .or_insert_with(|| Arc::new(Account::new(client))),
+        );
+        let mut entry = LedgerEntry::new(tx, kind, amount, BigDecimal::zero());
+        entry.source.clone_from(&self.source);
+        entry.sequence = sequence;
+        let credit = |account: &mut Account| {
+            account.available += &entry.available;
+            account.ledger.push(entry.clone());
+            account.track_negative(now);
+        };
+        credit(account);
+        // aborting the client's prepared transaction mustn't take this back too
+        for prepared in self.prepared.iter_mut() {
+            if prepared.transaction.client() == client {
+                credit(Arc::make_mut(&mut prepared.before));
+            }
+        }
+        self.record_change(Some(client));
+    }
+
+    /// Tags the ledger entries of the transactions processed from here on with where they
+    /// came from, e.g. the input file or the acquirer, until it's set again. See `sources`
+    pub fn set_source(&mut self, source: Option<&str>) {
+        self.source = source.map(Arc::from);
+    }
+
+    pub const fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Swaps the config of a running engine, what's already been processed stays as it is.
+    /// Open disputes are timed from when they were opened, whether or not there was a
+    /// timeout at the time. A config without a clock keeps the engine's
+    pub fn reconfigure(&mut self, mut config: EngineConfig) {
+        if config.clock.is_none() {
+            config.clock = self.config.clock.take();
+        }
+        let mut open_disputes = self
+            .accounts
+            .values()
+            .flat_map(|account| &account.cases)
+            .filter(|case| case.status() == CaseStatus::Open)
+            .map(|case| (case.opened_at(), case.client(), case.tx()))
+            .collect::<Vec<_>>();
+        open_disputes.sort_unstable();
+        self.open_disputes = if config.dispute_timeout.is_some() {
+            open_disputes.into()
+        } else {
+            VecDeque::new()
+        };
+        self.config = config;
+    }
+
+    /// Locks an account by hand for a review, e.g. while fraud is looked into, until it's
+    /// unlocked again. Deposits and the disputes of its transactions are still applied,
+    /// everything that takes money out is rejected, see `LockState`. A review lock doesn't
+    /// lift a stricter one
+    pub fn lock(&mut self, client: ClientId, reason: &str) -> Result<(), TransactionError> {
+        self.operate(client, |account| {
+            match account.lock {
+                LockState::Active | LockState::ReviewLocked { .. } => {
+                    account.lock = LockState::ReviewLocked {
+                        reason: reason.to_string(),
+                    };
+                }
+                LockState::ChargebackLocked => return Err(TransactionError::AccountLocked),
+                LockState::Closed => return Err(TransactionError::AccountClosed),
+            }
+            Ok(LedgerEntry::new(
+                0,
+                EntryKind::Lock,
+                BigDecimal::zero(),
+                BigDecimal::zero(),
+            ))
+        })
+    }
+
+    /// Closes an account for good, everything the client sends is rejected from now on and
+    /// it can't be unlocked. The balances stay as they are
+    pub fn close(&mut self, client: ClientId) -> Result<(), TransactionError> {
+        self.operate(client, |account| {
+            if account.lock == LockState::Closed {
+                return Err(TransactionError::AccountClosed);
+            }
+            account.lock = LockState::Closed;
+            Ok(LedgerEntry::new(
+                0,
+                EntryKind::Lock,
+                BigDecimal::zero(),
+                BigDecimal::zero(),
+            ))
+        })
+    }
+
+    /// Unlocks an account locked by a chargeback or by `lock`, a closed one stays closed
+    pub fn unlock(&mut self, client: ClientId) -> Result<(), TransactionError> {
+        self.operate(client, |account| {
+            if account.lock == LockState::Closed {
+                return Err(TransactionError::AccountClosed);
+            }
+            account.lock = LockState::Active;
+            Ok(LedgerEntry::new(
+                0,
+                EntryKind::Unlock,
+                BigDecimal::zero(),
+                BigDecimal::zero(),
+            ))
+        })
+    }
+
+    /// Corrects the available balance of an account by a positive or negative amount, outside
+    /// of any transaction. Locked accounts can be adjusted too, but not below zero
+    pub fn adjust(&mut self, client: ClientId, amount: BigDecimal) -> Result<(), TransactionError> {
+        self.operate(client, |account| {
+            let available = &account.available + &amount;
+            if available < BigDecimal::zero() && amount < BigDecimal::zero() {
+                return Err(TransactionError::InsufficientFunds);
+            }
+            account.available = available;
+            Ok(LedgerEntry::new(
+                0,
+                EntryKind::Adjustment,
+                amount,
+                BigDecimal::zero(),
+            ))
+        })
+    }
+
+    /// Moves money from the available to the held balance of an account outside of any
+    /// transaction, e.g. for a legal hold or while it's investigated, until it's released.
+    /// Locked accounts can be held too, but no more than they have available
+    pub fn hold(
+        &mut self,
+        client: ClientId,
+        amount: BigDecimal,
+        reason: HoldReason,
+        author: Option<String>,
+    ) -> Result<(), TransactionError> {
+        if amount <= BigDecimal::zero() {
+            return Err(TransactionError::InvalidHold);
+        }
+        let at = self.now();
+        self.operate(client, |account| {
+            if account.available < amount {
+                return Err(TransactionError::InsufficientFunds);
+            }
+            account.available -= &amount;
+            account.held += &amount;
+            account.holds.push(HoldAction {
+                at,
+                amount: amount.clone(),
+                reason,
+                author,
+            });
+            Ok(LedgerEntry::new(0, EntryKind::AdminHold, -&amount, amount))
+        })
+    }
+
+    /// Moves money an operator held back to available, up to what operators hold of the account
+    pub fn release(
+        &mut self,
+        client: ClientId,
+        amount: BigDecimal,
+        reason: HoldReason,
+        author: Option<String>,
+    ) -> Result<(), TransactionError> {
+        if amount <= BigDecimal::zero() {
+            return Err(TransactionError::InvalidHold);
+        }
+        let at = self.now();
+        self.operate(client, |account| {
+            if account.admin_held() < amount {
+                return Err(TransactionError::InvalidHold);
+            }
+            account.available += &amount;
+            account.held -= &amount;
+            account.holds.push(HoldAction {
+                at,
+                amount: -&amount,
+                reason,
+                author,
+            });
+            Ok(LedgerEntry::new(
+                0,
+                EntryKind::AdminRelease,
+                amount.clone(),
+                -amount,
+            ))
+        })
+    }
+
+    /// Adds a note to an account, stamped with the engine's time. Notes aren't in the ledger,
+    /// they don't change anything, so a locked account or one with a prepared transaction
+    /// can have them too
+    pub fn add_note(
+        &mut self,
+        client: ClientId,
+        author: Option<String>,
+        text: impl Into<String>,
+    ) -> Result<(), TransactionError> {
+        let at = self.now();
+        let account = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(TransactionError::UnknownAccount)?;
+        Arc::make_mut(account).notes.push(Note {
+            at,
+            author,
+            text: text.into(),
+        });
+        self.record_change(Some(client));
+        Ok(())
+    }
+
+    /// An operator's change to an existing account, recorded in its ledger
+    fn operate(
+        &mut self,
+        client: ClientId,
+        change: impl FnOnce(&mut Account) -> Result<LedgerEntry, TransactionError>,
+    ) -> Result<(), TransactionError> {
+        let checks_invariants = self.checks_invariants();
+        let now = self.now();
+        self.check_pending(client)?;
+        let account = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(TransactionError::UnknownAccount)?;
+        let account = Arc::make_mut(account);
+        let entry = change(account)?;
+        account.ledger.push(entry);
+        account.track_negative(now);
+        if checks_invariants {
+            if let Err(violation) = account.check_invariants() {
+                panic!("{} after an operator change", violation);
+            }
+        }
+        self.record_change(Some(client));
+        Ok(())
+    }
+
+    /// Moves the engine's notion of time forward to `timestamp` (unix seconds), making it
+    /// time-aware. Times going backwards are ignored, so out of order input is tolerated
+    pub fn advance_time(&mut self, timestamp: u64) {
+        let now = self.time.map_or(timestamp, |time| time.max(timestamp));
+        self.time = Some(now);
+
+        if let Some(timeout) = self.config.dispute_timeout {
+            let checks_invariants = self.checks_invariants();
+            while let Some(&(opened_at, client, tx)) = self.open_disputes.front() {
+                if opened_at.saturating_add(timeout) > now {
+                    break;
+                }
+                self.open_disputes.pop_front();
+                if let Some(account) = self.accounts.get_mut(&client) {
+                    let account = Arc::make_mut(account);
+                    let stored = account.transactions.len();
+                    let expired = account.expire_dispute(tx, now);
+                    account.track_negative(now);
+                    for prepared in self.prepared.iter_mut() {
+                        if prepared.transaction.client() == client {
+                            let before = Arc::make_mut(&mut prepared.before);
+                            before.expire_dispute(tx, now);
+                            before.track_negative(now);
+                        }
+                    }
+                    if let (true, Some(pruning)) = (expired, self.config.pruning) {
+                        let pruned = account.prune(pruning, Some(tx), now);
+                        report_pruned(&self.diagnostics_observers, client, pruned);
+                    }
+                    self.stored_transactions =
+                        self.stored_transactions + account.transactions.len() - stored;
+                    if checks_invariants {
+                        if let Err(violation) = account.check_invariants() {
+                            panic!("{} after the dispute of tx {} expired", violation, tx);
+                        }
+                    }
+                }
+                self.record_change(Some(client));
+            }
+        }
+
+        let checks_invariants = self.checks_invariants();
+        while let Some(&(expires_at, client, tx)) = self.promo_expiries.first() {
+            if expires_at > now {
+                break;
+            }
+            self.promo_expiries.pop_first();
+            let Some(account) = self.accounts.get_mut(&client) else {
+                continue;
+            };
+            let account = Arc::make_mut(account);
+            // nothing to do if withdrawals spent it already
+            if !account.expire_promo(tx, now) {
+                continue;
+            }
+            for prepared in self.prepared.iter_mut() {
+                if prepared.transaction.client() == client {
+                    Arc::make_mut(&mut prepared.before).expire_promo(tx, now);
+                }
+            }
+            if checks_invariants {
+                if let Err(violation) = account.check_invariants() {
+                    panic!("{} after the promotional credit {} expired", violation, tx);
+                }
+            }
+            self.record_change(Some(client));
+        }
+    }
+
+    /// Whether every change is followed by `check_invariants`, with `EngineConfig::check_invariants`
+    /// or when built with the `check-invariants` feature
+    fn checks_invariants(&self) -> bool {
+        self.config.check_invariants || cfg!(feature = "check-invariants")
+    }
+
+    /// Checks that every account is in a state the engine could have produced: balances agree
+    /// with the ledger, held funds are exactly the open disputes and so on.
+    /// Meant for developing new policies, it walks every ledger
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let mut accounts = self.accounts.values().collect::<Vec<_>>();
+        accounts.sort_by_key(|account| account.client_id);
+        accounts
+            .into_iter()
+            .try_for_each(|account| account.check_invariants())
+    }
+
+    /// The engine's time, unix seconds: the latest timestamp of the input or of its clock once
+    /// it's time-aware, the system clock until then
+    pub fn now(&self) -> u64 {
+        self.time.unwrap_or_else(|| SystemClock.now())
+    }
+
+    /// Makes the engine follow the clock instead of the system clock, like `EngineConfig::clock`
+    /// does from the start, e.g. a `VirtualClock` to simulate the passing of days. The engine
+    /// is time-aware from then on and catches up with the clock before every transaction,
+    /// timestamps of the input still move it further
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.config.clock = Some(clock);
+        self.catch_up();
+    }
+
+    /// Moves the engine's time forward to its clock's, expiring what's due by then, e.g. after
+    /// fast-forwarding the clock past the last transaction. Without a clock it does nothing
+    pub fn catch_up(&mut self) {
+        if let Some(now) = self.config.clock.as_ref().map(|clock| clock.now()) {
+            self.advance_time(now);
+        }
+    }
+
+    /// The latest timestamp of the input, if it has them
+    pub const fn time(&self) -> Option<u64> {
+        self.time
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<AccountView<'_>> {
+        self.accounts
+            .get(&client)
+            .map(|account| AccountView { account })
+    }
+
+    /// How many accounts, transactions and ledger entries the engine holds and roughly how
+    /// much memory they take. Walks every account, like `check_invariants`
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for account in self.accounts.values() {
+            stats.accounts += 1;
+            stats.transactions += account.transactions.len();
+            stats.ledger_entries += account.ledger.len();
+            stats.estimated_heap_bytes += std::mem::size_of::<Account>()
+                + account.transactions.heap_size()
+                + account.ledger.heap_size()
+                + account.cases.heap_size()
+                + account.available.heap_size()
+                + account.held.heap_size()
+                + account.escrows.heap_size()
+                + account.pending_withdrawals.heap_size()
+                + account.promo_credits.heap_size()
+                + account.holds.heap_size()
+                + account.velocity.heap_size()
+                + account.notes.heap_size()
+                + account.lock.heap_size()
+                + account
+                    .pockets
+                    .iter()
+                    .map(|(pocket, balance)| pocket.capacity() + balance.heap_size())
+                    .sum::<usize>()
+                + account
+                    .wallets
+                    .iter()
+                    .map(|(name, wallet)| name.capacity() + wallet.heap_size())
+                    .sum::<usize>();
+        }
+        stats
+    }
+
+    /// Every dispute case, open or closed, in no particular order
+    pub fn cases(&self) -> impl Iterator<Item = &Case> {
+        self.accounts.values().flat_map(|account| &account.cases)
+    }
+
+    /// Every escrow, open or not, in no particular order
+    pub fn escrows(&self) -> impl Iterator<Item = &Escrow> {
+        self.accounts.values().flat_map(|account| &account.escrows)
+    }
+
+    /// Every withdrawal waiting for an approve, in no particular order
+    pub fn pending_withdrawals(&self) -> impl Iterator<Item = &PendingWithdrawal> {
+        self.accounts
+            .values()
+            .flat_map(|account| &account.pending_withdrawals)
+    }
+
+    /// The latest case for a transaction of a client, if it has been disputed
+    pub fn case(&self, client: ClientId, tx: u32) -> Option<&Case> {
+        self.accounts
+            .get(&client)
+            .and_then(|account| account.cases.iter().rev().find(|case| case.tx() == tx))
+    }
+
+    /// Like `case` but allows adding notes to it
+    pub fn case_mut(&mut self, client: ClientId, tx: u32) -> Option<&mut Case> {
+        if self.accounts.contains_key(&client) {
+            self.record_change(Some(client));
+        }
+        self.accounts.get_mut(&client).and_then(|account| {
+            Arc::make_mut(account)
+                .cases
+                .iter_mut()
+                .rev()
+                .find(|case| case.tx() == tx)
+        })
+    }
+
+    /// The deposit or withdrawal with the tx id, whichever client sent it. Tx ids are meant to be
+    /// unique, if several clients used one the lowest client's is returned.
+    /// Looks through every account's transactions, so it's for support rather than hot paths
+    pub fn find_transaction(&self, tx: u32) -> Option<FoundTransaction<'_>> {
+        self.accounts
+            .values()
+            .filter_map(|account| {
+                let transaction = account.transactions.iter().find(|transaction| {
+                    transaction.tx() == tx
+                        && matches!(
+                            transaction,
+                            Transaction::Deposit { .. } | Transaction::Withdrawal { .. }
+                        )
+                })?;
+                let applied = account.ledger.iter().any(|entry| {
+                    entry.tx == tx
+                        && matches!(entry.kind, EntryKind::Deposit | EntryKind::Withdrawal)
+                });
+                Some(FoundTransaction {
+                    client: account.client_id,
+                    transaction,
+                    applied,
+                    case: account.cases.iter().rev().find(|case| case.tx() == tx),
+                })
+            })
+            .min_by_key(|found| found.client)
+    }
+
+    /// What the client's account has to keep available, see `EngineConfig::reserves`
+    pub fn reserve(&self, client: ClientId) -> BigDecimal {
+        self.config.reserves.reserve(client, &self.tags)
+    }
+
+    /// Whether the client is configured as a test client, see `EngineConfig::test_clients`
+    pub fn is_test_client(&self, client: ClientId) -> bool {
+        self.config.test_clients.contains(&client)
+            || self
+                .config
+                .test_tag
+                .as_ref()
+                .is_some_and(|tag| self.tags.has(client, tag))
+    }
+
+    /// All accounts except the test clients', in no particular order
+    pub fn account_views(&self) -> impl Iterator<Item = AccountView<'_>> {
+        self.accounts
+            .values()
+            .filter(|account| !self.is_test_client(account.client_id))
+            .map(|account| AccountView { account })
+    }
+
+    /// All accounts except the test clients', in no particular order
+    pub fn accounts(&self) -> Vec<CsvAccount> {
+        self.accounts
+            .values()
+            .filter(|account| !self.is_test_client(account.client_id))
+            .map(|account| account.as_csv_account())
+            .collect()
+    }
+
+    /// Which accounts changed between two states of an engine and by how much, e.g. of the
+    /// snapshots at the start and end of a day, in client order. An account with new ledger
+    /// entries changed even if they cancel out. Test clients are left out, like from `accounts`
+    pub fn diff(before: &Self, after: &Self) -> Vec<AccountDelta> {
+        let mut clients = before
+            .accounts
+            .keys()
+            .chain(after.accounts.keys())
+            .copied()
+            .filter(|&client| !before.is_test_client(client) && !after.is_test_client(client))
+            .collect::<Vec<_>>();
+        clients.sort_unstable();
+        clients.dedup();
+        clients
+            .into_iter()
+            .filter_map(|client| {
+                let (old, new) = (before.accounts.get(&client), after.accounts.get(&client));
+                let change = match (old, new) {
+                    (None, _) => AccountChange::Opened,
+                    (_, None) => AccountChange::Removed,
+                    (Some(old), Some(new))
+                        if old.ledger.len() == new.ledger.len()
+                            && old.available == new.available
+                            && old.held == new.held
+                            && old.lock == new.lock =>
+                    {
+                        return None;
+                    }
+                    _ => AccountChange::Changed,
+                };
+                let balance = |account: Option<&Arc<Account>>| {
+                    account.map_or((BigDecimal::zero(), BigDecimal::zero()), |account| {
+                        (account.available.clone(), account.held.clone())
+                    })
+                };
+                let ((old_available, old_held), (new_available, new_held)) =
+                    (balance(old), balance(new));
+                let len = |account: Option<&Arc<Account>>| account.map_or(0, |a| a.ledger.len());
+                Some(AccountDelta {
+                    client,
+                    change,
+                    available: new_available - old_available,
+                    held: new_held - old_held,
+                    locked: match (
+                        old.map(|a| a.lock.is_locked()),
+                        new.map(|a| a.lock.is_locked()),
+                    ) {
+                        (old, new) if old == new => None,
+                        (_, new) => new,
+                    },
+                    entries: len(new).saturating_sub(len(old)),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::transaction::Transaction::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+
+    use crate::approval::ApprovalPolicy;
+    use crate::clock::VirtualClock;
+    use crate::config::Limits;
+    use crate::fees::FeeSchedule;
+    use crate::reserve::ReserveRequirements;
+    use crate::risk::HeuristicScorer;
+    use crate::rules::RuleSet;
+    use crate::velocity::Window;
+
+    use super::*;
+
+    #[test]
+    fn test_provided_example() {
+        let input = vec![
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            },
+            Deposit {
+                client: 2,
+                tx: 2,
+                amount: BigDecimal::from(2),
+                wallet: None,
+            },
+            Deposit {
+                client: 1,
+                tx: 3,
+                amount: BigDecimal::from(2),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: BigDecimal::from_str("1.5").unwrap(),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 2,
+                tx: 5,
+                amount: BigDecimal::from(3),
+                wallet: None,
+            },
+        ];
+        let expected = vec![
+            CsvAccount {
+                client: 1,
+                available: "1.5000".to_string(),
+                held: "0.0000".to_string(),
+                total: "1.5000".to_string(),
+                locked: false,
+            },
+            CsvAccount {
+                client: 2,
+                available: "2.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "2.0000".to_string(),
+                locked: false,
+            },
+        ];
+        test(input, expected);
+    }
+
+    #[test]
+    fn should_deposit_money() {
+        test(
+            vec![Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            }],
+            vec![CsvAccount {
+                client: 1,
+                available: "1.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "1.0000".to_string(),
+                locked: false,
+            }],
+        );
+    }
+
+    #[test]
+    fn should_withdraw_money() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(1),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from_str("0.5").unwrap(),
+                    wallet: None,
+                },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "0.5000".to_string(),
+                held: "0.0000".to_string(),
+                total: "0.5000".to_string(),
+                locked: false,
+            }],
+        );
+    }
+
+    #[test]
+    fn should_not_withdraw_to_below_zero() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(1),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from(2),
+                    wallet: None,
+                },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "1.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "1.0000".to_string(),
+                locked: false,
+            }],
+        );
+    }
+
+    #[test]
     fn should_handle_decimals() {
         test(
             vec![
@@ -267,230 +3573,2534 @@ mod tests {
                     client: 1,
                     tx: 1,
                     amount: BigDecimal::from(1),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from_str("0.12345").unwrap(),
+                    wallet: None,
+                },
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from_str("0.12345").unwrap(),
+                    wallet: None,
+                },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "1.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "1.0000".to_string(),
+                locked: false,
+            }],
+        );
+    }
+
+    #[test]
+    fn should_dispute_withdrawal() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(1),
+                    wallet: None,
                 },
                 Withdrawal {
                     client: 1,
                     tx: 2,
-                    amount: BigDecimal::from_str("0.12345").unwrap(),
+                    amount: BigDecimal::from_str("0.2").unwrap(),
+                    wallet: None,
                 },
+                Dispute { client: 1, tx: 2 },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "0.6000".to_string(),
+                held: "0.2000".to_string(),
+                total: "0.8000".to_string(),
+                locked: false,
+            }],
+        );
+    }
+
+    #[test]
+    fn should_ignore_second_dispute() {
+        test(
+            vec![
                 Deposit {
                     client: 1,
                     tx: 1,
-                    amount: BigDecimal::from_str("0.12345").unwrap(),
+                    amount: BigDecimal::from(1),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from_str("0.2").unwrap(),
+                    wallet: None,
+                },
+                Dispute { client: 1, tx: 2 },
+                Dispute { client: 1, tx: 2 },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "0.6000".to_string(),
+                held: "0.2000".to_string(),
+                total: "0.8000".to_string(),
+                locked: false,
+            }],
+        );
+    }
+
+    #[test]
+    fn should_resolve_dispute() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(1),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from_str("0.2").unwrap(),
+                    wallet: None,
+                },
+                Dispute { client: 1, tx: 2 },
+                Resolve { client: 1, tx: 2 },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "0.8000".to_string(),
+                held: "0.0000".to_string(),
+                total: "0.8000".to_string(),
+                locked: false,
+            }],
+        )
+    }
+
+    #[test]
+    fn should_ignore_second_resolve() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(1),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from_str("0.2").unwrap(),
+                    wallet: None,
+                },
+                Dispute { client: 1, tx: 2 },
+                Resolve { client: 1, tx: 2 },
+                Resolve { client: 1, tx: 2 },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "0.8000".to_string(),
+                held: "0.0000".to_string(),
+                total: "0.8000".to_string(),
+                locked: false,
+            }],
+        )
+    }
+
+    #[test]
+    fn should_handle_chargeback() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(10),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from(2),
+                    wallet: None,
+                },
+                Dispute { client: 1, tx: 2 },
+                Chargeback { client: 1, tx: 2 },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "6.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "6.0000".to_string(),
+                locked: true,
+            }],
+        )
+    }
+
+    #[test]
+    fn should_ignore_second_chargeback() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(10),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from(2),
+                    wallet: None,
+                },
+                Dispute { client: 1, tx: 2 },
+                Chargeback { client: 1, tx: 2 },
+                Chargeback { client: 1, tx: 2 },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "6.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "6.0000".to_string(),
+                locked: true,
+            }],
+        )
+    }
+
+    #[test]
+    fn should_reproduce_the_legacy_dispute_quirks_in_compat_mode() {
+        let run = |compat, transactions: Vec<Transaction>| {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                compat,
+                ..EngineConfig::default()
+            });
+            let results = transactions
+                .into_iter()
+                .map(|transaction| engine.process(transaction).is_ok())
+                .collect::<Vec<_>>();
+            let account = engine.account_views().next().unwrap();
+            (results, account.available().clone(), account.held().clone())
+        };
+        let deposit = Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let after_second_dispute = || {
+            vec![
+                deposit.clone(),
+                Dispute { client: 1, tx: 1 },
+                Dispute { client: 1, tx: 1 },
+                Resolve { client: 1, tx: 1 },
+            ]
+        };
+        let after_resolve = || {
+            vec![
+                deposit.clone(),
+                Dispute { client: 1, tx: 1 },
+                Resolve { client: 1, tx: 1 },
+                Chargeback { client: 1, tx: 1 },
+            ]
+        };
+        let after_early_resolve = || {
+            vec![
+                deposit.clone(),
+                Resolve { client: 1, tx: 1 },
+                Dispute { client: 1, tx: 1 },
+            ]
+        };
+        let (ten, zero) = (BigDecimal::from(10), BigDecimal::zero());
+
+        assert_eq!(
+            run(Compat::Current, after_second_dispute()),
+            (vec![true, true, false, true], ten.clone(), zero.clone())
+        );
+        assert_eq!(
+            run(Compat::Legacy, after_second_dispute()),
+            (vec![true, true, false, false], zero.clone(), ten.clone())
+        );
+        assert_eq!(
+            run(Compat::Current, after_resolve()),
+            (vec![true, true, true, false], ten.clone(), zero.clone())
+        );
+        // the negative held balance it leaves behind is one the invariants don't allow
+        if !cfg!(feature = "check-invariants") {
+            assert_eq!(
+                run(Compat::Legacy, after_resolve()),
+                (vec![true, true, true, true], ten.clone(), -ten.clone())
+            );
+        }
+        assert_eq!(
+            run(Compat::Current, after_early_resolve()),
+            (vec![true, false, true], zero.clone(), ten.clone())
+        );
+        assert_eq!(
+            run(Compat::Legacy, after_early_resolve()),
+            (vec![true, false, false], ten, zero)
+        );
+    }
+
+    #[test]
+    fn frozen_accounts_should_be_frozen() {
+        test(
+            vec![
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(10),
+                    wallet: None,
+                },
+                Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: BigDecimal::from(2),
+                    wallet: None,
+                },
+                Dispute { client: 1, tx: 2 },
+                Chargeback { client: 1, tx: 2 },
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: BigDecimal::from(10),
+                    wallet: None,
+                },
+            ],
+            vec![CsvAccount {
+                client: 1,
+                available: "6.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "6.0000".to_string(),
+                locked: true,
+            }],
+        )
+    }
+
+    #[test]
+    fn should_report_why_transactions_were_rejected() {
+        let mut engine = TransactionEngine::new();
+        let deposit = |tx| Deposit {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        };
+        assert_eq!(engine.process(deposit(1)), Ok(()));
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            }),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.process(Dispute { client: 1, tx: 9 }),
+            Err(TransactionError::UnknownTransaction)
+        );
+        assert_eq!(engine.process(deposit(3)), Ok(()));
+        assert_eq!(
+            engine.process(Resolve { client: 1, tx: 3 }),
+            Err(TransactionError::NotDisputed)
+        );
+        assert_eq!(engine.process(Dispute { client: 1, tx: 1 }), Ok(()));
+        assert_eq!(
+            engine.process(Dispute { client: 1, tx: 1 }),
+            Err(TransactionError::AlreadyDisputed)
+        );
+        assert_eq!(engine.process(Chargeback { client: 1, tx: 1 }), Ok(()));
+        assert_eq!(
+            engine.process(deposit(4)),
+            Err(TransactionError::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn should_only_record_applied_changes_in_ledger() {
+        let mut engine = TransactionEngine::new();
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(2),
+            wallet: None,
+        });
+        let _ = engine.process(Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        let _ = engine.process(Resolve { client: 1, tx: 1 });
+
+        let kinds = engine
+            .account_views()
+            .flat_map(|account| account.ledger())
+            .map(|entry| (entry.tx, entry.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, EntryKind::Deposit),
+                (1, EntryKind::Hold),
+                (1, EntryKind::Release)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_track_dispute_cases() {
+        let mut engine = TransactionEngine::new();
+        for tx in 1..=3 {
+            let _ = engine.process(Deposit {
+                client: 1,
+                tx,
+                amount: BigDecimal::from(tx),
+                wallet: None,
+            });
+            let _ = engine.process(Dispute { client: 1, tx });
+        }
+        let _ = engine.process(Resolve { client: 1, tx: 1 });
+        let _ = engine.process(Chargeback { client: 1, tx: 2 });
+        let now = engine.now();
+        engine
+            .case_mut(1, 3)
+            .unwrap()
+            .add_note("receipt attached", now);
+
+        let status = |tx| engine.case(1, tx).map(Case::status);
+        assert_eq!(status(1), Some(CaseStatus::Resolved));
+        assert_eq!(status(2), Some(CaseStatus::ChargedBack));
+        assert_eq!(status(3), Some(CaseStatus::Open));
+        assert_eq!(status(4), None);
+
+        let open = engine.case(1, 3).unwrap();
+        assert_eq!(open.amount(), &BigDecimal::from(3));
+        assert_eq!(open.closed_at(), None);
+        assert_eq!(open.notes()[0].text, "receipt attached");
+        assert!(engine.case(1, 1).unwrap().closed_at().is_some());
+        assert_eq!(engine.cases().count(), 3);
+    }
+
+    #[test]
+    fn should_find_transactions_across_accounts() {
+        let mut engine = TransactionEngine::new();
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        let _ = engine.process(Withdrawal {
+            client: 2,
+            tx: 2,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+
+        let found = engine.find_transaction(1).unwrap();
+        assert_eq!(found.client, 1);
+        assert!(found.applied);
+        assert_eq!(found.case.map(Case::status), Some(CaseStatus::Open));
+        let found = engine.find_transaction(2).unwrap();
+        assert_eq!((found.client, found.applied), (2, false));
+        assert!(found.case.is_none());
+        assert!(engine.find_transaction(3).is_none());
+    }
+
+    #[test]
+    fn should_apply_operator_changes() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        assert_eq!(engine.lock(2, ""), Err(TransactionError::UnknownAccount));
+
+        engine.lock(1, "fraud").unwrap();
+        let withdrawal = Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        };
+        assert_eq!(
+            engine.process(withdrawal.clone()),
+            Err(TransactionError::AccountLocked)
+        );
+        engine.adjust(1, BigDecimal::from(-2)).unwrap();
+        assert_eq!(
+            engine.adjust(1, BigDecimal::from(-4)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        engine.unlock(1).unwrap();
+        engine.process(withdrawal).unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available(), &BigDecimal::from(2));
+        assert_eq!(
+            account
+                .ledger()
+                .iter()
+                .map(|entry| entry.kind)
+                .collect::<Vec<_>>(),
+            vec![
+                EntryKind::Deposit,
+                EntryKind::Lock,
+                EntryKind::Adjustment,
+                EntryKind::Unlock,
+                EntryKind::Withdrawal
+            ]
+        );
+    }
+
+    #[test]
+    fn should_allow_what_the_lock_state_allows() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let deposit = |tx| Deposit {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let withdrawal = |tx| Withdrawal {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        };
+        let lock_state =
+            |engine: &TransactionEngine| engine.account(1).unwrap().lock_state().clone();
+        let _ = engine.process(deposit(1));
+        let _ = engine.process(deposit(2));
+
+        // money comes in and disputes go on during a review, nothing leaves
+        engine.lock(1, "kyc refresh").unwrap();
+        assert_eq!(engine.process(deposit(3)), Ok(()));
+        assert_eq!(
+            engine.process(withdrawal(4)),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(engine.process(Dispute { client: 1, tx: 1 }), Ok(()));
+        assert_eq!(
+            engine.accounts()[0]
+                .clone()
+                .extended(&BigDecimal::zero(), &lock_state(&engine))
+                .lock_reason
+                .as_deref(),
+            Some("kyc refresh")
+        );
+
+        // a chargeback locks it harder, a review lock doesn't loosen that
+        assert_eq!(engine.process(Chargeback { client: 1, tx: 1 }), Ok(()));
+        assert_eq!(lock_state(&engine), LockState::ChargebackLocked);
+        assert_eq!(
+            engine.process(deposit(5)),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(engine.lock(1, ""), Err(TransactionError::AccountLocked));
+
+        // and closing it is for good
+        engine.close(1).unwrap();
+        assert_eq!(lock_state(&engine), LockState::Closed);
+        assert_eq!(
+            engine.process(deposit(6)),
+            Err(TransactionError::AccountClosed)
+        );
+        assert_eq!(engine.unlock(1), Err(TransactionError::AccountClosed));
+        assert_eq!(engine.close(1), Err(TransactionError::AccountClosed));
+        assert_eq!(
+            engine.account(1).unwrap().available(),
+            &BigDecimal::from(20)
+        );
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), EngineConfig::default()).unwrap();
+        assert_eq!(lock_state(&restored), LockState::Closed);
+    }
+
+    #[test]
+    fn should_hold_and_release_money_by_hand() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        engine.advance_time(100);
+        let amount = |amount| BigDecimal::from(amount);
+        for (tx, deposit) in [(1, 10), (2, 5)] {
+            engine
+                .process(Deposit {
+                    client: 1,
+                    tx,
+                    amount: amount(deposit),
+                    wallet: None,
+                })
+                .unwrap();
+        }
+        let alice = || Some("alice".to_string());
+        engine
+            .hold(1, amount(8), HoldReason::LegalHold, alice())
+            .unwrap();
+        assert_eq!(
+            engine.hold(1, amount(8), HoldReason::LegalHold, None),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.hold(1, amount(0), HoldReason::Other, None),
+            Err(TransactionError::InvalidHold)
+        );
+        // a dispute's held funds aren't the operators' to release
+        engine.process(Dispute { client: 1, tx: 2 }).unwrap();
+        assert_eq!(
+            engine.release(1, amount(9), HoldReason::LegalHold, None),
+            Err(TransactionError::InvalidHold)
+        );
+        engine
+            .release(1, amount(3), HoldReason::Investigation, None)
+            .unwrap();
+
+        let engine =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available(), &amount(5));
+        assert_eq!(account.held(), &amount(10));
+        assert_eq!(account.admin_held(), amount(5));
+        assert_eq!(
+            account.holds(),
+            [
+                HoldAction {
+                    at: 100,
+                    amount: amount(8),
+                    reason: HoldReason::LegalHold,
+                    author: alice(),
+                },
+                HoldAction {
+                    at: 100,
+                    amount: amount(-3),
+                    reason: HoldReason::Investigation,
+                    author: None,
                 },
-            ],
-            vec![CsvAccount {
+            ]
+        );
+        assert_eq!(
+            account.ledger().last().map(|entry| entry.kind),
+            Some(EntryKind::AdminRelease)
+        );
+    }
+
+    #[test]
+    fn should_expire_stale_disputes_in_time_aware_mode() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            dispute_timeout: Some(100),
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1000);
+        for tx in 1..=2 {
+            let _ = engine.process(Deposit {
                 client: 1,
-                available: "1.0000".to_string(),
+                tx,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            });
+        }
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        engine.advance_time(1050);
+        let _ = engine.process(Dispute { client: 1, tx: 2 });
+        engine.advance_time(1100);
+
+        let case = engine.case(1, 1).unwrap();
+        assert_eq!(case.status(), CaseStatus::Expired);
+        assert_eq!(case.opened_at(), 1000);
+        assert_eq!(case.closed_at(), Some(1100));
+        assert_eq!(engine.case(1, 2).unwrap().status(), CaseStatus::Open);
+        assert_eq!(
+            engine.process(Resolve { client: 1, tx: 1 }),
+            Err(TransactionError::NotDisputed)
+        );
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available(), &BigDecimal::from(5));
+        assert_eq!(account.ledger().last().unwrap().kind, EntryKind::Expiry);
+    }
+
+    #[test]
+    fn should_follow_a_virtual_clock() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            dispute_timeout: Some(100),
+            ..EngineConfig::default()
+        });
+        let clock = VirtualClock::new(1000);
+        engine.set_clock(Arc::new(clock.clone()));
+        assert_eq!(engine.now(), 1000);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        clock.advance(30);
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        assert_eq!(engine.case(1, 1).unwrap().opened_at(), 1030);
+
+        clock.advance(99);
+        engine.catch_up();
+        assert_eq!(engine.case(1, 1).unwrap().status(), CaseStatus::Open);
+        clock.advance(1);
+        engine.catch_up();
+        let case = engine.case(1, 1).unwrap();
+        assert_eq!(case.status(), CaseStatus::Expired);
+        assert_eq!(case.closed_at(), Some(1130));
+        // timestamps of the input still move it further
+        engine.advance_time(5000);
+        assert_eq!(engine.now(), 5000);
+    }
+
+    #[test]
+    fn should_prune_transactions_that_can_no_longer_be_disputed() {
+        struct Collect(Arc<std::sync::Mutex<Vec<Pruned>>>);
+        impl DiagnosticsObserver for Collect {
+            fn pruned(&self, pruned: &Pruned) {
+                self.0.lock().unwrap().push(pruned.clone());
+            }
+        }
+
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            pruning: Some(Pruning { after: Some(100) }),
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let pruned = Arc::new(std::sync::Mutex::new(vec![]));
+        engine.register_diagnostics_observer(Box::new(Collect(Arc::clone(&pruned))));
+        engine.advance_time(1000);
+        for tx in 1..=3 {
+            let _ = engine.process(Deposit {
+                client: 1,
+                tx,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            });
+        }
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        let _ = engine.process(Dispute { client: 1, tx: 2 });
+        assert_eq!(engine.process(Resolve { client: 1, tx: 1 }), Ok(()));
+        // what's left of tx 1 is only in the ledger and the case
+        assert_eq!(engine.account(1).unwrap().transactions().count(), 3);
+        assert_eq!(
+            engine.process(Dispute { client: 1, tx: 1 }),
+            Err(TransactionError::Pruned)
+        );
+
+        // tx 2 is still disputed, which keeps tx 3 that came after it too
+        engine.advance_time(1200);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 4,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        });
+        assert_eq!(engine.account(1).unwrap().transactions().count(), 4);
+        assert_eq!(engine.process(Resolve { client: 1, tx: 2 }), Ok(()));
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 5,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        });
+        let txs = engine
+            .account(1)
+            .unwrap()
+            .transactions()
+            .map(|transaction| transaction.tx())
+            .collect::<Vec<_>>();
+        assert_eq!(txs, [4, 5]);
+        assert_eq!(
+            engine.process(Dispute { client: 1, tx: 3 }),
+            Err(TransactionError::Pruned)
+        );
+        assert_eq!(engine.account(1).unwrap().total(), BigDecimal::from(17));
+        let pruned = pruned
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|pruned| pruned.tx)
+            .collect::<Vec<_>>();
+        assert_eq!(pruned, [1, 2, 3]);
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        assert_eq!(restored.snapshot(), engine.snapshot());
+    }
+
+    #[test]
+    fn should_reject_transactions_past_the_limits() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            limits: Limits {
+                max_accounts: Some(2),
+                max_stored_transactions: Some(4),
+            },
+            ..EngineConfig::default()
+        });
+        let deposit = |client, tx| Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        };
+        assert_eq!(engine.process(deposit(1, 1)), Ok(()));
+        assert_eq!(engine.process(deposit(2, 2)), Ok(()));
+        assert_eq!(
+            engine.process(deposit(3, 3)),
+            Err(TransactionError::TooManyAccounts)
+        );
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: BigDecimal::from(50),
+                wallet: None,
+            }),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.process(deposit(2, 5)), Ok(()));
+        // the rejected withdrawal counts as well
+        assert_eq!(
+            engine.process(deposit(1, 6)),
+            Err(TransactionError::TooManyTransactions)
+        );
+        assert!(engine.account(3).is_none());
+        assert_eq!(engine.memory_stats().transactions, 4);
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        assert_eq!(
+            restored.clone().process(deposit(2, 7)),
+            Err(TransactionError::TooManyTransactions)
+        );
+    }
+
+    #[test]
+    fn should_not_expire_disputes_without_timeout() {
+        let mut engine = TransactionEngine::new();
+        engine.advance_time(0);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        engine.advance_time(u64::MAX);
+        assert_eq!(engine.case(1, 1).unwrap().status(), CaseStatus::Open);
+    }
+
+    #[test]
+    fn should_expire_disputes_opened_before_a_timeout_was_configured() {
+        let mut engine = TransactionEngine::new();
+        engine.advance_time(1000);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        engine.reconfigure(EngineConfig {
+            dispute_timeout: Some(100),
+            ..engine.config().clone()
+        });
+        engine.advance_time(1099);
+        assert_eq!(engine.case(1, 1).unwrap().status(), CaseStatus::Open);
+        engine.advance_time(1100);
+        assert_eq!(engine.case(1, 1).unwrap().status(), CaseStatus::Expired);
+    }
+
+    struct LargeAmountScorer;
+
+    impl RiskScorer for LargeAmountScorer {
+        fn score(&self, transaction: &Transaction, _: Option<AccountView<'_>>) -> f64 {
+            match transaction.amount() {
+                Some(amount) if amount > &BigDecimal::from(100) => 1.0,
+                _ => 0.0,
+            }
+        }
+    }
+
+    #[test]
+    fn should_hold_risky_transactions_for_review() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            risk_threshold: Some(0.5),
+            ..EngineConfig::default()
+        });
+        engine.register_scorer(Box::new(LargeAmountScorer));
+        let deposit = |tx, amount| Deposit {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+
+        assert_eq!(engine.process(deposit(1, 50)), Ok(()));
+        assert_eq!(
+            engine.process(deposit(2, 500)),
+            Err(TransactionError::HeldForReview)
+        );
+        assert_eq!(engine.pending_review().len(), 1);
+        assert_eq!(engine.pending_review()[0].transaction.tx(), 2);
+        assert_eq!(
+            engine.account(1).unwrap().available(),
+            &BigDecimal::from(50)
+        );
+    }
+
+    #[test]
+    fn should_apply_approved_and_drop_denied_transactions() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            risk_threshold: Some(0.5),
+            ..EngineConfig::default()
+        });
+        engine.register_scorer(Box::new(LargeAmountScorer));
+        for tx in 1..=2 {
+            let _ = engine.process(Deposit {
+                client: 1,
+                tx,
+                amount: BigDecimal::from(500),
+                wallet: None,
+            });
+        }
+
+        assert_eq!(engine.approve(1, 1), Ok(()));
+        assert_eq!(engine.deny(1, 2, "suspicious"), Ok(()));
+        assert_eq!(
+            engine.approve(1, 2),
+            Err(TransactionError::UnknownTransaction)
+        );
+        assert!(engine.pending_review().is_empty());
+        assert_eq!(engine.denied()[0].reason, "suspicious");
+        assert_eq!(
+            engine.account(1).unwrap().available(),
+            &BigDecimal::from(500)
+        );
+    }
+
+    #[test]
+    fn should_hold_unusually_large_amounts_with_heuristic_scorer() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            risk_threshold: Some(3.0),
+            ..EngineConfig::default()
+        });
+        engine.register_scorer(Box::new(HeuristicScorer::default()));
+        for (tx, amount) in [10, 12, 9, 11, 10, 10, 50].into_iter().enumerate() {
+            let _ = engine.process(Deposit {
+                client: 1,
+                tx: tx as u32,
+                amount: BigDecimal::from(amount),
+                wallet: None,
+            });
+        }
+        let pending = engine
+            .pending_review()
+            .iter()
+            .map(|pending| pending.transaction.tx())
+            .collect::<Vec<_>>();
+        assert_eq!(pending, vec![6]);
+    }
+
+    #[test]
+    fn should_weight_how_the_disputes_turned_out_in_the_heuristic_score() {
+        let mut engine = TransactionEngine::new();
+        for tx in 1..=4 {
+            let _ = engine.process(Deposit {
+                client: 1,
+                tx,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            });
+        }
+        for tx in 1..=3 {
+            let _ = engine.process(Dispute { client: 1, tx });
+        }
+        let _ = engine.process(Resolve { client: 1, tx: 1 });
+        let _ = engine.process(Chargeback { client: 1, tx: 2 });
+        let account = engine.account(1).unwrap();
+        assert_eq!(
+            account.dispute_stats(),
+            DisputeStats {
+                raised: 3,
+                open: 1,
+                resolved: 1,
+                charged_back: 1,
+                expired: 0,
+            }
+        );
+
+        let deposit = Deposit {
+            client: 1,
+            tx: 5,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        // one point per dispute and two more for the chargeback
+        assert!((HeuristicScorer::default().score(&deposit, Some(account)) - 5.0).abs() < 1e-9);
+        let scorer = HeuristicScorer {
+            resolved_weight: -1.0,
+            chargeback_weight: 0.0,
+            ..HeuristicScorer::default()
+        };
+        assert!((scorer.score(&deposit, Some(account)) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_limit_withdrawals_by_how_many_there_were_lately() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            rules: RuleSet::parse(
+                r#"type == "withdrawal" && (withdrawals_1h >= 2 || withdrawn_24h + amount > 50) => reject("velocity")"#,
+            )
+            .unwrap(),
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1_000_000);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(100),
+            wallet: None,
+        });
+        let withdrawal = |tx, amount| Withdrawal {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        assert_eq!(engine.process(withdrawal(2, 10)), Ok(()));
+        assert_eq!(engine.process(withdrawal(3, 10)), Ok(()));
+        let velocity = Err(TransactionError::RejectedByRule("velocity".to_string()));
+        assert_eq!(engine.process(withdrawal(4, 10)), velocity);
+
+        // an hour later the count is fine, but not what was withdrawn today
+        engine.advance_time(1_000_000 + 3600);
+        assert_eq!(engine.process(withdrawal(5, 31)), velocity);
+        assert_eq!(engine.process(withdrawal(6, 30)), Ok(()));
+        let account = engine.account(1).unwrap();
+        assert_eq!(
+            account.velocity().deposits(Window::Day, engine.now()).count,
+            1
+        );
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), EngineConfig::default()).unwrap();
+        assert_eq!(restored.account(1).unwrap().velocity(), account.velocity());
+    }
+
+    #[test]
+    fn should_reject_transactions_matching_a_rule() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            rules: RuleSet::parse(
+                r#"type == "withdrawal" && amount > available => reject("overdraft")"#,
+            )
+            .unwrap(),
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(11),
+                wallet: None,
+            }),
+            Err(TransactionError::RejectedByRule("overdraft".to_string()))
+        );
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_leave_test_clients_out_of_reports() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            test_clients: [2].into(),
+            test_tag: Some("staging".to_string()),
+            ..EngineConfig::default()
+        });
+        engine.tags_mut().add(3, "staging");
+        for (client, tx) in (1..=3).zip(1..) {
+            let _ = engine.process(Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            });
+        }
+
+        let clients = engine
+            .accounts()
+            .iter()
+            .map(|account| account.client)
+            .collect::<Vec<_>>();
+        assert_eq!(clients, vec![1]);
+        assert_eq!(
+            engine.account(2).unwrap().available(),
+            &BigDecimal::from(10)
+        );
+    }
+
+    #[test]
+    fn should_continue_from_a_snapshot() {
+        let mut engine = TransactionEngine::new();
+        engine.advance_time(100);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from_str("1.5").unwrap(),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        engine.tags_mut().add(1, "vip");
+        engine.add_note(1, None, "disputed on the phone").unwrap();
+
+        let mut restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), EngineConfig::default()).unwrap();
+        assert_eq!(restored.snapshot(), engine.snapshot());
+        assert_eq!(restored.case(1, 1).unwrap().opened_at(), 100);
+        assert_eq!(restored.account(1).unwrap().notes()[0].at, 100);
+        assert_eq!(restored.process(Resolve { client: 1, tx: 1 }), Ok(()));
+        assert!(restored.tags().has(1, "vip"));
+    }
+
+    #[test]
+    fn should_diff_two_states_of_an_engine() {
+        let deposit = |client, tx, amount| Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        let mut engine = TransactionEngine::new();
+        let _ = engine.process(deposit(1, 1, 10));
+        let _ = engine.process(deposit(2, 2, 10));
+        let _ = engine.process(deposit(3, 3, 10));
+        let before = engine.clone();
+
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        let _ = engine.process(Chargeback { client: 1, tx: 1 });
+        // what goes in and out again is still a change
+        let _ = engine.process(deposit(2, 4, 5));
+        let _ = engine.process(Withdrawal {
+            client: 2,
+            tx: 5,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        let _ = engine.process(deposit(4, 6, 7));
+
+        let deltas = TransactionEngine::diff(&before, &engine)
+            .iter()
+            .map(AccountDelta::to_csv)
+            .map(|delta| {
+                (
+                    delta.client,
+                    delta.change,
+                    delta.available,
+                    delta.total,
+                    delta.locked,
+                    delta.entries,
+                )
+            })
+            .collect::<Vec<_>>();
+        let row = |client, change, available: &str, total: &str, locked, entries| {
+            (
+                client,
+                change,
+                available.to_string(),
+                total.to_string(),
+                locked,
+                entries,
+            )
+        };
+        assert_eq!(
+            deltas,
+            [
+                row(1, "changed", "-10.0000", "-10.0000", Some(true), 2),
+                row(2, "changed", "0.0000", "0.0000", None, 2),
+                row(4, "opened", "7.0000", "7.0000", Some(false), 1),
+            ]
+        );
+        assert_eq!(
+            TransactionEngine::diff(&engine, &before)[2].change,
+            AccountChange::Removed
+        );
+    }
+
+    #[test]
+    fn should_keep_a_replica_in_sync() {
+        let deposit = |client, tx| Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let mut primary = TransactionEngine::new();
+        let _ = primary.process(deposit(1, 1));
+        let _ = primary.process(deposit(2, 2));
+        let mut replica = TransactionEngine::new();
+        assert_eq!(
+            replica
+                .apply_changes(&primary.changes_since(0).unwrap())
+                .unwrap(),
+            2
+        );
+
+        let _ = primary.process(Dispute { client: 2, tx: 2 });
+        // rejected, it changes the sequence but takes no number of its own
+        let _ = primary.process(Dispute { client: 2, tx: 2 });
+        primary.tags_mut().add(1, "vip");
+        assert_eq!(primary.applied_sequence(), 3);
+        let numbers = primary
+            .account(2)
+            .unwrap()
+            .ledger()
+            .iter()
+            .map(|entry| entry.sequence)
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, [Some(2), Some(3)]);
+        assert_eq!(primary.changed_since(replica.sequence()), [2]);
+        assert_eq!(primary.changed_since(0), [1, 2]);
+        let changes = primary.changes_since(replica.sequence()).unwrap();
+        assert!(changes.len() < primary.changes_since(0).unwrap().len());
+        assert_eq!(replica.apply_changes(&changes).unwrap(), 5);
+        // applying the same changes twice is harmless
+        assert_eq!(replica.apply_changes(&changes).unwrap(), 5);
+        assert_eq!(replica.snapshot(), primary.snapshot());
+        assert_eq!(replica.applied_sequence(), 3);
+        assert_eq!(replica.process(Resolve { client: 2, tx: 2 }), Ok(()));
+
+        assert!(primary.changes_since(6).is_none());
+        let mut behind = TransactionEngine::new();
+        assert!(behind.apply_changes(&changes).is_err());
+    }
+
+    #[test]
+    fn should_merge_engines() {
+        let deposit = |client, tx| Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let mut left = TransactionEngine::new();
+        let _ = left.process(deposit(1, 1));
+        let _ = left.process(deposit(2, 2));
+        let mut right = TransactionEngine::new();
+        let _ = right.process(deposit(2, 3));
+        let _ = right.process(deposit(3, 4));
+
+        let mut copy =
+            TransactionEngine::from_snapshot(&left.snapshot(), EngineConfig::default()).unwrap();
+        let right_copy =
+            TransactionEngine::from_snapshot(&right.snapshot(), EngineConfig::default()).unwrap();
+        assert_eq!(
+            copy.merge(right_copy, OnConflict::Fail),
+            Err(MergeConflict::Account(2))
+        );
+        assert_eq!(copy.snapshot(), left.snapshot());
+
+        assert_eq!(left.merge(right, OnConflict::Sum), Ok(()));
+        let mut accounts = left.accounts();
+        accounts.sort_by_key(|account| account.client);
+        let totals = accounts
+            .iter()
+            .map(|account| (account.client, account.total.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(totals, vec![(1, "10.0000"), (2, "20.0000"), (3, "10.0000")]);
+        assert_eq!(left.account(2).unwrap().ledger().len(), 2);
+    }
+
+    #[test]
+    fn should_leave_the_original_alone_when_a_clone_processes_transactions() {
+        let mut engine = TransactionEngine::new();
+        for (client, tx) in (1..=2).zip(1..) {
+            engine
+                .process(Deposit {
+                    client,
+                    tx,
+                    amount: BigDecimal::from(10),
+                    wallet: None,
+                })
+                .unwrap();
+        }
+
+        let mut what_if = engine.clone();
+        what_if
+            .process(Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: BigDecimal::from(4),
+                wallet: None,
+            })
+            .unwrap();
+        assert_eq!(
+            what_if.account(1).unwrap().available(),
+            &BigDecimal::from(6)
+        );
+        assert_eq!(
+            engine.account(1).unwrap().available(),
+            &BigDecimal::from(10)
+        );
+        assert_eq!(engine.account(1).unwrap().ledger().len(), 1);
+        // only the account that was written to has been copied
+        assert!(!Arc::ptr_eq(&engine.accounts[&1], &what_if.accounts[&1]));
+        assert!(Arc::ptr_eq(&engine.accounts[&2], &what_if.accounts[&2]));
+    }
+
+    #[test]
+    fn should_find_accounts_that_break_an_invariant() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let transactions = vec![
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(7),
+                wallet: None,
+            },
+            Dispute { client: 1, tx: 1 },
+            Chargeback { client: 1, tx: 1 },
+        ];
+        for transaction in transactions {
+            engine.process(transaction).unwrap();
+        }
+        assert_eq!(engine.check_invariants(), Ok(()));
+
+        Arc::make_mut(engine.accounts.get_mut(&1).unwrap()).held = BigDecimal::from(1);
+        let violation = engine.check_invariants().unwrap_err();
+        assert_eq!(
+            violation.invariant,
+            "total == available + held == sum of the ledger"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "held == sum of the open disputes")]
+    fn should_panic_when_a_transaction_breaks_an_invariant() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        let _ = engine.process(Dispute { client: 1, tx: 1 });
+        // as if a bug had lost the case
+        Arc::make_mut(engine.accounts.get_mut(&1).unwrap())
+            .cases
+            .clear();
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 2,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        });
+    }
+
+    #[test]
+    fn should_move_money_between_pockets() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let transfer = |tx, amount, from: Option<&str>, to: Option<&str>| Transaction::Transfer {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(amount),
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+        };
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        assert_eq!(
+            engine.process(transfer(2, 6, None, Some("savings"))),
+            Ok(())
+        );
+        assert_eq!(
+            engine.process(transfer(3, 2, Some("savings"), Some("holiday"))),
+            Ok(())
+        );
+        assert_eq!(
+            engine.process(transfer(4, 5, Some("savings"), None)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.process(transfer(5, 1, Some("holiday"), Some("holiday"))),
+            Err(TransactionError::InvalidTransfer)
+        );
+        // only what's available can be withdrawn
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 1,
+                tx: 6,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            }),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.process(transfer(7, 2, Some("holiday"), None)),
+            Ok(())
+        );
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let account = restored.account(1).unwrap();
+        assert_eq!(
+            account.to_csv_account(),
+            CsvAccount {
+                client: 1,
+                available: "6.0000".to_string(),
                 held: "0.0000".to_string(),
-                total: "1.0000".to_string(),
+                total: "10.0000".to_string(),
                 locked: false,
-            }],
+            }
+        );
+        assert_eq!(
+            account.to_csv_pockets(),
+            [CsvPocket {
+                client: 1,
+                pocket: "savings".to_string(),
+                balance: "4.0000".to_string(),
+            }]
+        );
+        // the transfers didn't store anything to dispute
+        assert_eq!(account.transactions().count(), 2);
+    }
+
+    #[test]
+    fn should_keep_the_balances_of_every_wallet() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            fees: FeeSchedule::parse("account 9\ndeposit 1").unwrap(),
+            ..EngineConfig::default()
+        });
+        let wallet = |wallet: &str| Some(wallet.to_string());
+        let amount = |amount| BigDecimal::from(amount);
+        let results = [
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: amount(10),
+                wallet: None,
+            },
+            Deposit {
+                client: 1,
+                tx: 2,
+                amount: amount(21),
+                wallet: wallet("savings"),
+            },
+            // the fees came out of the wallets of the deposits
+            Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: amount(15),
+                wallet: None,
+            },
+            Transaction::WalletTransfer {
+                client: 1,
+                tx: 4,
+                amount: amount(8),
+                from: wallet("savings"),
+                to: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 5,
+                amount: amount(15),
+                wallet: None,
+            },
+            Transaction::WalletTransfer {
+                client: 1,
+                tx: 6,
+                amount: amount(20),
+                from: wallet("savings"),
+                to: wallet("holiday"),
+            },
+            // held in the wallet the deposit went to
+            Dispute { client: 1, tx: 2 },
+        ]
+        .map(|transaction| engine.process(transaction));
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds),
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds),
+                Ok(()),
+            ]
+        );
+
+        let mut engine =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!(
+            account.to_csv_account(),
+            CsvAccount {
+                client: 1,
+                available: "2.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "14.0000".to_string(),
+                locked: false,
+            }
+        );
+        assert_eq!(
+            account.to_csv_wallets()[1],
+            CsvWallet {
+                client: 1,
+                wallet: "savings".to_string(),
+                available: "-9.0000".to_string(),
+                held: "21.0000".to_string(),
+                total: "12.0000".to_string(),
+            }
+        );
+
+        engine.process(Resolve { client: 1, tx: 2 }).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.wallets()["savings"].available, amount(12));
+        assert_eq!(account.available(), &amount(2));
+    }
+
+    #[test]
+    fn should_pay_the_seller_or_refund_the_buyer_of_an_escrow() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let open = |tx, amount, seller| Transaction::EscrowOpen {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(amount),
+            seller,
+        };
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        assert_eq!(engine.process(open(2, 6, 2)), Ok(()));
+        assert_eq!(
+            engine.process(open(3, 6, 2)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.process(open(2, 1, 2)),
+            Err(TransactionError::InvalidEscrow)
+        );
+        assert_eq!(
+            engine.process(open(3, 1, 1)),
+            Err(TransactionError::InvalidEscrow)
+        );
+        assert_eq!(engine.process(open(4, 3, 3)), Ok(()));
+        assert_eq!(
+            engine.process(Transaction::EscrowRelease { client: 1, tx: 2 }),
+            Ok(())
+        );
+        assert_eq!(
+            engine.process(Transaction::EscrowRefund { client: 1, tx: 2 }),
+            Err(TransactionError::UnknownEscrow)
+        );
+        assert_eq!(
+            engine.process(Transaction::EscrowRefund { client: 1, tx: 4 }),
+            Ok(())
+        );
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let mut accounts = restored.accounts();
+        accounts.sort_by_key(|account| account.client);
+        let balances = accounts
+            .iter()
+            .map(|account| {
+                (
+                    account.client,
+                    account.available.as_str(),
+                    account.held.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // the seller of the refunded escrow never got an account
+        assert_eq!(balances, [(1, "4.0000", "0.0000"), (2, "6.0000", "0.0000")]);
+        let mut escrows = restored
+            .escrows()
+            .map(|escrow| (escrow.tx(), escrow.seller(), escrow.status()))
+            .collect::<Vec<_>>();
+        escrows.sort_by_key(|(tx, ..)| *tx);
+        assert_eq!(
+            escrows,
+            [
+                (2, 2, EscrowStatus::Released),
+                (4, 3, EscrowStatus::Refunded)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_keep_the_reserve_of_the_account_available() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            reserves: ReserveRequirements::parse("default 10\ntag vip 50").unwrap(),
+            ..EngineConfig::default()
+        });
+        engine.tags_mut().add(2, "vip");
+        let amount = |amount| BigDecimal::from(amount);
+        let results = [
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: amount(30),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: amount(25),
+                wallet: None,
+            },
+            // down to the reserve is fine
+            Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: amount(20),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: amount(11),
+                wallet: None,
+            },
+            Deposit {
+                client: 2,
+                tx: 5,
+                amount: amount(60),
+                wallet: None,
+            },
+            Transaction::Transfer {
+                client: 2,
+                tx: 6,
+                amount: amount(20),
+                from: None,
+                to: Some("rent".to_string()),
+            },
+            // disputes can still take available below the reserve
+            Dispute { client: 2, tx: 5 },
+        ]
+        .map(|transaction| engine.process(transaction));
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(TransactionError::BelowReserve),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds),
+                Ok(()),
+                Err(TransactionError::BelowReserve),
+                Ok(()),
+            ]
+        );
+        assert_eq!(engine.account(1).unwrap().available(), &amount(10));
+        assert_eq!(engine.reserve(2), amount(50));
+        assert_eq!(
+            engine.accounts()[0]
+                .clone()
+                .extended(&engine.reserve(1), &LockState::Active)
+                .reserve,
+            "10.0000"
+        );
+    }
+
+    #[test]
+    fn should_spend_promotional_credits_first_and_expire_the_rest() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1000);
+        let amount = |amount| BigDecimal::from(amount);
+        let promo = |tx, amount, expires_at| Transaction::PromoCredit {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(amount),
+            expires_at,
+        };
+        let results = [
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: amount(10),
+                wallet: None,
+            },
+            promo(2, 5, 3000),
+            promo(3, 4, 2000),
+            promo(4, 1, 1000),
+            promo(2, 1, 4000),
+            // the credit that expires first goes first
+            Withdrawal {
+                client: 1,
+                tx: 5,
+                amount: amount(6),
+                wallet: None,
+            },
+            Withdrawal {
+                client: 1,
+                tx: 6,
+                amount: amount(14),
+                wallet: None,
+            },
+        ]
+        .map(|transaction| engine.process(transaction));
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InvalidPromoCredit),
+                Err(TransactionError::InvalidPromoCredit),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds),
+            ]
+        );
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available(), &amount(10));
+        assert_eq!(account.promo(), amount(3));
+        assert_eq!(account.total(), amount(13));
+
+        let mut engine =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        engine.advance_time(3000);
+        let account = engine.account(1).unwrap();
+        assert!(account.promo_credits().is_empty());
+        let entries = account
+            .ledger()
+            .iter()
+            .map(|entry| (entry.kind, entry.available.clone(), entry.promo.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries[3..],
+            [
+                (EntryKind::Withdrawal, amount(0), amount(-6)),
+                (EntryKind::PromoExpiry, amount(0), amount(-3)),
+            ]
         );
     }
 
     #[test]
-    fn should_dispute_withdrawal() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(1),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from_str("0.2").unwrap(),
-                },
-                Dispute { client: 1, tx: 2 },
-            ],
-            vec![CsvAccount {
-                client: 1,
-                available: "0.6000".to_string(),
-                held: "0.2000".to_string(),
-                total: "0.8000".to_string(),
-                locked: false,
-            }],
+    fn should_not_add_promotional_credits_with_a_negative_withdrawal() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1000);
+        let _ = engine.process(Transaction::PromoCredit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            expires_at: 2000,
+        });
+        let _ = engine.process(Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: BigDecimal::from(-3),
+            wallet: None,
+        });
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.promo(), BigDecimal::from(5));
+        assert_eq!(account.available(), &BigDecimal::from(3));
+        assert_eq!(account.total(), BigDecimal::from(8));
+    }
+
+    #[test]
+    fn should_hold_withdrawals_above_the_threshold_until_approved() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            approvals: ApprovalPolicy::parse("default 100\nclient 2 none").unwrap(),
+            ..EngineConfig::default()
+        });
+        let withdrawal = |client, tx, amount| Withdrawal {
+            client,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        for (client, tx) in [(1, 1), (2, 2)] {
+            let _ = engine.process(Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(500),
+                wallet: None,
+            });
+        }
+        assert_eq!(engine.process(withdrawal(1, 3, 100)), Ok(()));
+        assert_eq!(engine.process(withdrawal(1, 4, 300)), Ok(()));
+        assert_eq!(
+            engine.process(withdrawal(1, 5, 150)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.process(withdrawal(2, 6, 300)), Ok(()));
+        let balances = |engine: &TransactionEngine, client| {
+            let account = engine.account(client).unwrap();
+            (account.available().to_string(), account.held().to_string())
+        };
+        assert_eq!(balances(&engine, 1), ("100".to_string(), "300".to_string()));
+        assert_eq!(balances(&engine, 2), ("200".to_string(), "0".to_string()));
+        assert_eq!(
+            engine.process(Transaction::Approve { client: 1, tx: 3 }),
+            Err(TransactionError::NotPendingApproval)
+        );
+
+        let mut restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let pending = restored
+            .pending_withdrawals()
+            .map(|pending| (pending.client, pending.tx, pending.amount.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(pending, [(1, 4, "300".to_string())]);
+        assert_eq!(
+            restored.process(Transaction::Approve { client: 1, tx: 4 }),
+            Ok(())
+        );
+        assert_eq!(balances(&restored, 1), ("100".to_string(), "0".to_string()));
+        assert_eq!(restored.pending_withdrawals().count(), 0);
+        let kinds = restored
+            .account(1)
+            .unwrap()
+            .ledger()
+            .iter()
+            .map(|entry| entry.kind.name())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                "deposit",
+                "withdrawal",
+                "pending_withdrawal",
+                "approval",
+                "withdrawal"
+            ]
+        );
+        assert_eq!(
+            restored.process(Transaction::Dispute { client: 1, tx: 4 }),
+            Ok(())
         );
     }
 
     #[test]
-    fn should_ignore_second_dispute() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(1),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from_str("0.2").unwrap(),
-                },
-                Dispute { client: 1, tx: 2 },
-                Dispute { client: 1, tx: 2 },
-            ],
-            vec![CsvAccount {
-                client: 1,
-                available: "0.6000".to_string(),
-                held: "0.2000".to_string(),
-                total: "0.8000".to_string(),
-                locked: false,
-            }],
+    fn should_release_the_hold_of_a_denied_withdrawal() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            approvals: ApprovalPolicy::parse("default 100").unwrap(),
+            fees: FeeSchedule::parse("account 9\nwithdrawal 1").unwrap(),
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(500),
+            wallet: None,
+        });
+        let _ = engine.process(Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: BigDecimal::from(300),
+            wallet: None,
+        });
+        let balances = |engine: &TransactionEngine| {
+            let account = engine.account(1).unwrap();
+            (account.available().to_string(), account.held().to_string())
+        };
+        assert_eq!(balances(&engine), ("199".to_string(), "300".to_string()));
+
+        assert_eq!(
+            engine.process(Transaction::Deny { client: 1, tx: 2 }),
+            Ok(())
         );
+        // the fee stays taken
+        assert_eq!(balances(&engine), ("499".to_string(), "0".to_string()));
+        assert_eq!(engine.pending_withdrawals().count(), 0);
+        for transaction in [
+            Transaction::Deny { client: 1, tx: 2 },
+            Transaction::Approve { client: 1, tx: 2 },
+            Transaction::Deny { client: 1, tx: 1 },
+        ] {
+            assert_eq!(
+                engine.process(transaction),
+                Err(TransactionError::NotPendingApproval)
+            );
+        }
+
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let kinds = restored
+            .account(1)
+            .unwrap()
+            .ledger()
+            .iter()
+            .map(|entry| entry.kind.name())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, ["deposit", "pending_withdrawal", "fee", "denial"]);
     }
 
     #[test]
-    fn should_resolve_dispute() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(1),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from_str("0.2").unwrap(),
-                },
-                Dispute { client: 1, tx: 2 },
-                Resolve { client: 1, tx: 2 },
-            ],
-            vec![CsvAccount {
+    fn should_credit_fees_to_the_fee_account() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            fees: FeeSchedule::parse("account 9\ndeposit 1%\nwithdrawal 0.5").unwrap(),
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        assert_eq!(
+            engine.process(Withdrawal {
                 client: 1,
-                available: "0.8000".to_string(),
-                held: "0.0000".to_string(),
-                total: "0.8000".to_string(),
-                locked: false,
-            }],
-        )
+                tx: 2,
+                amount: BigDecimal::from_str("9.5").unwrap(),
+                wallet: None,
+            }),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.process(Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: BigDecimal::from(4),
+                wallet: None,
+            }),
+            Ok(())
+        );
+        let available = |client| engine.account(client).unwrap().available().to_string();
+        assert_eq!(available(1), "5.4");
+        assert_eq!(available(9), "0.6");
+        let kinds = engine
+            .account(1)
+            .unwrap()
+            .ledger()
+            .iter()
+            .map(|entry| entry.kind.name())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, ["deposit", "fee", "withdrawal", "fee"]);
     }
 
     #[test]
-    fn should_ignore_second_resolve() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(1),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from_str("0.2").unwrap(),
-                },
-                Dispute { client: 1, tx: 2 },
-                Resolve { client: 1, tx: 2 },
-                Resolve { client: 1, tx: 2 },
-            ],
-            vec![CsvAccount {
+    fn should_not_charge_more_than_the_deposit() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            fees: FeeSchedule::parse("account 9\ndeposit 1% + 5").unwrap(),
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            engine.process(Deposit {
                 client: 1,
-                available: "0.8000".to_string(),
-                held: "0.0000".to_string(),
-                total: "0.8000".to_string(),
-                locked: false,
-            }],
-        )
+                tx: 1,
+                amount: BigDecimal::from(2),
+                wallet: Some("savings".to_string()),
+            }),
+            Ok(())
+        );
+        assert_eq!(
+            engine.process(Deposit {
+                client: 2,
+                tx: 2,
+                amount: BigDecimal::from(3),
+                wallet: None,
+            }),
+            Ok(())
+        );
+        let account = engine.account(2).unwrap();
+        assert_eq!(account.available().to_string(), "0");
+        assert_eq!(account.total().to_string(), "0");
+        assert_eq!(engine.account(1).unwrap().total().to_string(), "0");
+        assert_eq!(engine.account(9).unwrap().available().to_string(), "5");
     }
 
     #[test]
-    fn should_handle_chargeback() {
-        test(
-            vec![
-                Deposit {
+    fn should_charge_the_chargeback_fee_to_the_client_or_the_fee_account() {
+        let charge_back = |schedule: &str| {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                check_invariants: true,
+                fees: FeeSchedule::parse(schedule).unwrap(),
+                ..EngineConfig::default()
+            });
+            for tx in [1, 2] {
+                let _ = engine.process(Deposit {
                     client: 1,
-                    tx: 1,
+                    tx,
                     amount: BigDecimal::from(10),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from(2),
-                },
-                Dispute { client: 1, tx: 2 },
-                Chargeback { client: 1, tx: 2 },
-            ],
-            vec![CsvAccount {
-                client: 1,
-                available: "6.0000".to_string(),
-                held: "0.0000".to_string(),
-                total: "6.0000".to_string(),
-                locked: true,
-            }],
-        )
+                    wallet: None,
+                });
+            }
+            let _ = engine.process(Dispute { client: 1, tx: 1 });
+            // only a chargeback that succeeds costs anything
+            assert_eq!(
+                engine.process(Chargeback { client: 1, tx: 2 }),
+                Err(TransactionError::NotDisputed)
+            );
+            assert_eq!(engine.process(Chargeback { client: 1, tx: 1 }), Ok(()));
+            let available = |client| engine.account(client).unwrap().available().to_string();
+            let last_kind = |client| {
+                let account = engine.account(client).unwrap();
+                account.ledger().last().unwrap().kind
+            };
+            (available(1), available(9), last_kind(1), last_kind(9))
+        };
+
+        assert_eq!(
+            charge_back("account 9\nchargeback 15"),
+            (
+                "-5".to_string(),
+                "15".to_string(),
+                EntryKind::ChargebackFee,
+                EntryKind::ChargebackFee
+            )
+        );
+        assert_eq!(
+            charge_back("account 9\nchargeback 15\nchargeback_payer fee_account"),
+            (
+                "10".to_string(),
+                "-15".to_string(),
+                EntryKind::Chargeback,
+                EntryKind::ChargebackFee
+            )
+        );
     }
 
     #[test]
-    fn should_ignore_second_chargeback() {
-        test(
-            vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(10),
-                },
-                Withdrawal {
-                    client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from(2),
-                },
-                Dispute { client: 1, tx: 2 },
-                Chargeback { client: 1, tx: 2 },
-                Chargeback { client: 1, tx: 2 },
-            ],
-            vec![CsvAccount {
+    fn should_reverse_withdrawals_within_the_grace_window() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            fees: FeeSchedule::parse("account 9\nwithdrawal 0.5").unwrap(),
+            reversal_window: Some(100),
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1000);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(20),
+            wallet: None,
+        });
+        for tx in [2, 3, 4] {
+            let _ = engine.process(Withdrawal {
                 client: 1,
-                available: "6.0000".to_string(),
-                held: "0.0000".to_string(),
-                total: "6.0000".to_string(),
-                locked: true,
-            }],
+                tx,
+                amount: BigDecimal::from(4),
+                wallet: None,
+            });
+        }
+        let reversal = |tx| Transaction::Reversal { client: 1, tx };
+        let _ = engine.process(Dispute { client: 1, tx: 4 });
+        engine.advance_time(1100);
+
+        assert_eq!(engine.process(reversal(2)), Ok(()));
+        // the fee stays paid, and the disputed withdrawal stays held
+        let available = |engine: &TransactionEngine| engine.account(1).unwrap().available().clone();
+        assert_eq!(available(&engine), BigDecimal::from_str("6.5").unwrap());
+        assert!(engine.account(1).unwrap().is_reversed(2));
+        assert_eq!(
+            engine.process(reversal(2)),
+            Err(TransactionError::AlreadyReversed)
+        );
+        assert_eq!(
+            engine.process(Dispute { client: 1, tx: 2 }),
+            Err(TransactionError::AlreadyReversed)
+        );
+        // a reversal is no dispute, a disputed withdrawal stays disputed
+        assert_eq!(
+            engine.process(reversal(4)),
+            Err(TransactionError::InvalidReversal)
+        );
+        assert_eq!(
+            engine.process(reversal(1)),
+            Err(TransactionError::InvalidReversal)
+        );
+        assert_eq!(
+            engine.process(reversal(5)),
+            Err(TransactionError::UnknownTransaction)
+        );
+        engine.advance_time(1101);
+        assert_eq!(
+            engine.process(reversal(3)),
+            Err(TransactionError::InvalidReversal)
+        );
+        assert_eq!(available(&engine), BigDecimal::from_str("6.5").unwrap());
+    }
+
+    #[test]
+    fn should_reverse_the_withdrawal_of_the_tx_and_only_once() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            reversal_window: Some(100),
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1000);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        let withdrawal = |tx, amount| Withdrawal {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        assert_eq!(engine.process(withdrawal(2, 3)), Ok(()));
+        assert_eq!(engine.process(withdrawal(3, 5)), Ok(()));
+        // sent again with the tx of the first one, rejected but stored
+        engine.advance_time(1090);
+        assert_eq!(
+            engine.process(withdrawal(2, 50)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        // the window is the first one's, which the tx refers to
+        engine.advance_time(1101);
+        assert_eq!(
+            engine.process(Transaction::Reversal { client: 1, tx: 2 }),
+            Err(TransactionError::InvalidReversal)
+        );
+
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            reversal_window: Some(100),
+            ..EngineConfig::default()
+        });
+        engine.advance_time(1000);
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        let _ = engine.process(withdrawal(2, 3));
+        let _ = engine.process(withdrawal(3, 5));
+        let _ = engine.process(withdrawal(2, 50));
+        // not the latest withdrawal, it gives back what the applied one took
+        assert_eq!(
+            engine.process(Transaction::Reversal { client: 1, tx: 2 }),
+            Ok(())
+        );
+        assert_eq!(engine.account(1).unwrap().available(), &BigDecimal::from(5));
+        assert_eq!(
+            engine.process(Transaction::Reversal { client: 1, tx: 2 }),
+            Err(TransactionError::AlreadyReversed)
+        );
+        assert!(!engine.account(1).unwrap().is_reversed(3));
+
+        // the flag is kept, the withdrawal and its reversal both stay in the history
+        let restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        let account = restored.account(1).unwrap();
+        assert!(account.is_reversed(2));
+        assert_eq!(account.transactions().count(), 4);
+        let mut history = vec![];
+        crate::history::write_history(
+            account,
+            &crate::history::HistoryFormat::Csv,
+            crate::locale::Locale::En,
+            0,
+            &mut history,
         )
+        .unwrap();
+        let reversed = String::from_utf8(history)
+            .unwrap()
+            .lines()
+            .filter(|line| line.contains(",true,"))
+            .map(|line| line.split(',').take(3).collect::<Vec<_>>().join(","))
+            .collect::<Vec<_>>();
+        assert_eq!(reversed, ["1,2,withdrawal", "1,2,reversal"]);
     }
 
     #[test]
-    fn frozen_accounts_should_be_frozen() {
-        test(
+    fn should_commit_or_abort_prepared_transactions() {
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            fees: FeeSchedule::parse("account 9\nwithdrawal 0.5").unwrap(),
+            ..EngineConfig::default()
+        });
+        let _ = engine.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        });
+        let withdrawal = |tx| Withdrawal {
+            client: 1,
+            tx,
+            amount: BigDecimal::from(4),
+            wallet: None,
+        };
+        let token = engine.prepare(withdrawal(2)).unwrap();
+        assert_eq!(
+            engine.process(withdrawal(3)),
+            Err(TransactionError::PendingCommit)
+        );
+        assert_eq!(engine.lock(1, ""), Err(TransactionError::PendingCommit));
+        // another client's fee while it's prepared
+        let _ = engine.process(Deposit {
+            client: 2,
+            tx: 4,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        });
+        let _ = engine.process(Withdrawal {
+            client: 2,
+            tx: 5,
+            amount: BigDecimal::from_str("0.5").unwrap(),
+            wallet: None,
+        });
+        let available = |engine: &TransactionEngine, client| {
+            engine.account(client).unwrap().available().to_string()
+        };
+        assert_eq!(available(&engine, 1), "5.5");
+        assert_eq!(available(&engine, 9), "1.0");
+
+        // a restarted host finds it in the snapshot
+        let mut restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), engine.config().clone()).unwrap();
+        assert_eq!(restored.prepared().count(), 1);
+        restored.abort(token).unwrap();
+        assert_eq!(available(&restored, 1), "10");
+        assert_eq!(available(&restored, 9), "0.5");
+        assert_eq!(restored.account(1).unwrap().transactions().count(), 1);
+        assert_eq!(
+            restored.abort(token),
+            Err(TransactionError::UnknownPrepared)
+        );
+        assert_eq!(restored.process(withdrawal(3)), Ok(()));
+
+        engine.commit(token).unwrap();
+        assert_eq!(engine.prepared().count(), 0);
+        assert_eq!(engine.process(withdrawal(3)), Ok(()));
+        assert_eq!(available(&engine, 1), "1.0");
+        assert!(engine
+            .prepare(Dispute { client: 1, tx: 99 })
+            .is_err_and(|e| e == TransactionError::UnknownTransaction));
+    }
+
+    #[test]
+    fn should_number_the_applied_transactions_in_the_order_they_are_applied() {
+        let deposit = |client, tx| Deposit {
+            client,
+            tx,
+            amount: BigDecimal::from(10),
+            wallet: None,
+        };
+        let mut engine = TransactionEngine::new();
+        engine.process(deposit(2, 1)).unwrap();
+        engine.process(deposit(1, 2)).unwrap();
+        let overdraft = Withdrawal {
+            client: 1,
+            tx: 3,
+            amount: BigDecimal::from(50),
+            wallet: None,
+        };
+        assert!(engine.process(overdraft).is_err());
+        assert_eq!(engine.applied_sequence(), 2);
+        let token = engine.prepare(deposit(1, 4)).unwrap();
+        engine.abort(token).unwrap();
+        engine.process(Dispute { client: 1, tx: 2 }).unwrap();
+        engine.process(Resolve { client: 1, tx: 2 }).unwrap();
+        assert_eq!(engine.applied_sequence(), 5);
+
+        let sequences = |engine: &TransactionEngine, client| {
+            engine
+                .account(client)
+                .unwrap()
+                .ledger()
+                .iter()
+                .map(|entry| entry.sequence)
+                .collect::<Vec<_>>()
+        };
+        // the aborted deposit left a gap at 3, the rejected withdrawal took no number
+        assert_eq!(sequences(&engine, 1), [Some(2), Some(4), Some(5)]);
+        assert_eq!(sequences(&engine, 2), [Some(1)]);
+
+        let mut restored =
+            TransactionEngine::from_snapshot(&engine.snapshot(), EngineConfig::default()).unwrap();
+        assert_eq!(restored.applied_sequence(), 5);
+        restored.process(deposit(3, 6)).unwrap();
+        assert_eq!(sequences(&restored, 3), [Some(6)]);
+
+        // a shard that applied fewer carries on after the highest
+        let mut shard = TransactionEngine::new();
+        shard.process(deposit(4, 7)).unwrap();
+        shard.merge(restored, OnConflict::Fail).unwrap();
+        assert_eq!(shard.applied_sequence(), 6);
+        shard.process(deposit(4, 8)).unwrap();
+        assert_eq!(sequences(&shard, 4), [Some(1), Some(7)]);
+    }
+
+    #[test]
+    fn should_tell_which_accounts_changed_since_a_sequence() {
+        let mut engine = TransactionEngine::new();
+        for (client, tx) in [(3, 3), (1, 1), (2, 2)] {
+            let deposit = Deposit {
+                client,
+                tx,
+                amount: BigDecimal::from(10),
+                wallet: None,
+            };
+            engine.process(deposit).unwrap();
+        }
+        let batch = engine.sequence();
+        assert_eq!(engine.changed_since(0), [1, 2, 3]);
+        assert!(engine.changed_since(batch).is_empty());
+
+        let withdrawal = Withdrawal {
+            client: 3,
+            tx: 4,
+            amount: BigDecimal::from(50),
+            wallet: None,
+        };
+        assert!(engine.process(withdrawal).is_err());
+        engine.process(Dispute { client: 2, tx: 2 }).unwrap();
+        let deposit = Deposit {
+            client: 1,
+            tx: 5,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        };
+        engine.process(deposit).unwrap();
+        // in order of the clients, whatever the order of the changes. Rejected transactions
+        // are kept with the account, so they change it too
+        assert_eq!(engine.changed_since(batch), [1, 2, 3]);
+        assert_eq!(engine.changed_since(batch + 1), [1, 2]);
+        assert_eq!(engine.changed_since(batch + 2), [1]);
+    }
+
+    #[test]
+    fn should_continue_from_an_accounts_csv() {
+        let csv_account = |client, available: &str, held: &str, total: &str, locked| CsvAccount {
+            client,
+            available: available.to_string(),
+            held: held.to_string(),
+            total: total.to_string(),
+            locked,
+        };
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        engine
+            .open_accounts([
+                csv_account(1, "10.0000", "5.0000", "15.0000", false),
+                csv_account(2, "-4.0000", "0.0000", "-4.0000", true),
+            ])
+            .unwrap();
+        assert!(engine
+            .open_accounts([csv_account(1, "1", "0", "1", false)])
+            .is_err());
+        assert!(TransactionEngine::new()
+            .open_accounts([csv_account(3, "1", "1", "1", false)])
+            .is_err());
+
+        engine
+            .process(Withdrawal {
+                client: 1,
+                tx: 1,
+                amount: BigDecimal::from(11),
+                wallet: None,
+            })
+            .unwrap_err();
+        engine
+            .process(Deposit {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            })
+            .unwrap();
+        assert_eq!(
+            engine.process(Deposit {
+                client: 2,
+                tx: 3,
+                amount: BigDecimal::from(1),
+                wallet: None,
+            }),
+            Err(TransactionError::AccountLocked)
+        );
+        let mut accounts = engine.accounts();
+        accounts.sort_by_key(|account| account.client);
+        assert_eq!(
+            accounts,
             vec![
-                Deposit {
-                    client: 1,
-                    tx: 1,
-                    amount: BigDecimal::from(10),
-                },
-                Withdrawal {
+                csv_account(1, "11.0000", "5.0000", "16.0000", false),
+                csv_account(2, "-4.0000", "0.0000", "-4.0000", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_settle_opening_rows_for_seeded_accounts_by_the_policy() {
+        let seeded = |opening_policy| {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                opening_policy,
+                ..EngineConfig::default()
+            });
+            engine
+                .open_accounts([CsvAccount {
                     client: 1,
-                    tx: 2,
-                    amount: BigDecimal::from(2),
-                },
-                Dispute { client: 1, tx: 2 },
-                Chargeback { client: 1, tx: 2 },
-                Deposit {
+                    available: "10.0000".to_string(),
+                    held: "0.0000".to_string(),
+                    total: "10.0000".to_string(),
+                    locked: false,
+                }])
+                .unwrap();
+            engine
+        };
+        for (policy, result, balance) in [
+            (
+                OpeningPolicy::Error,
+                Err(TransactionError::OpeningConflict),
+                "10.0000",
+            ),
+            (OpeningPolicy::PreferFile, Ok(()), "15.0000"),
+            (OpeningPolicy::PreferSeed, Ok(()), "10.0000"),
+            (OpeningPolicy::Sum, Ok(()), "25.0000"),
+        ] {
+            let mut engine = seeded(policy);
+            assert_eq!(engine.process_opening(1, 1, BigDecimal::from(15)), result);
+            assert_eq!(engine.accounts()[0].available, balance);
+            assert_eq!(
+                engine.opening_conflicts(),
+                [OpeningConflict {
                     client: 1,
                     tx: 1,
-                    amount: BigDecimal::from(10),
-                },
-            ],
-            vec![CsvAccount {
-                client: 1,
-                available: "6.0000".to_string(),
-                held: "0.0000".to_string(),
-                total: "6.0000".to_string(),
-                locked: true,
-            }],
-        )
+                    seeded: BigDecimal::from(10),
+                    file: BigDecimal::from(15),
+                    policy,
+                }]
+            );
+        }
+
+        // without a seeded balance there's nothing to conflict with, it's a deposit
+        let mut engine = seeded(OpeningPolicy::Error);
+        assert_eq!(engine.process_opening(2, 2, BigDecimal::from(7)), Ok(()));
+        assert_eq!(engine.process(Dispute { client: 2, tx: 2 }), Ok(()));
+        assert!(engine.opening_conflicts().is_empty());
     }
 
     fn test(transactions: Vec<Transaction>, expected: Vec<CsvAccount>) {
         let mut transation_engine = TransactionEngine::new();
         for transaction in transactions {
-            transation_engine.process(transaction);
+            let _ = transation_engine.process(transaction);
         }
         let mut result = transation_engine.accounts();
         result.sort_by_key(|a| a.client);
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn should_report_the_transactions_over_the_latency_budget() {
+        struct Collect(Arc<std::sync::Mutex<Vec<SlowTransaction>>>);
+        impl DiagnosticsObserver for Collect {
+            fn slow(&self, slow: &SlowTransaction) {
+                self.0.lock().unwrap().push(slow.clone());
+            }
+        }
+
+        let slow = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut generous = TransactionEngine::with_config(EngineConfig {
+            latency_budget: Some(Duration::from_secs(60)),
+            ..EngineConfig::default()
+        });
+        generous.register_diagnostics_observer(Box::new(Collect(Arc::clone(&slow))));
+        let _ = generous.process(Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(5),
+            wallet: None,
+        });
+        assert!(slow.lock().unwrap().is_empty());
+
+        let mut engine = TransactionEngine::with_config(EngineConfig {
+            latency_budget: Some(Duration::ZERO),
+            ..EngineConfig::default()
+        });
+        engine.register_diagnostics_observer(Box::new(Collect(Arc::clone(&slow))));
+        for tx in 1..=2 {
+            let _ = engine.process(Deposit {
+                client: 1,
+                tx,
+                amount: BigDecimal::from(5),
+                wallet: None,
+            });
+        }
+        let _ = engine.process(Withdrawal {
+            client: 1,
+            tx: 3,
+            amount: BigDecimal::from(1),
+            wallet: None,
+        });
+        let slow = slow.lock().unwrap();
+        let reported = slow
+            .iter()
+            .map(|slow| (slow.kind.as_str(), slow.tx, slow.stored, slow.entries))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reported,
+            [
+                ("deposit", 1, 1, 1),
+                ("deposit", 2, 2, 2),
+                ("withdrawal", 3, 3, 3)
+            ]
+        );
+        assert!(slow.iter().all(|slow| slow.budget == Duration::ZERO));
+    }
 }
@@ -0,0 +1,230 @@
+use anyhow::{bail, Context, Result};
+use bigdecimal::ToPrimitive;
+
+use crate::rules::{Expr, Field, Parser, Scope, Token, Value};
+use crate::transaction::Transaction;
+use crate::transaction_engine::AccountView;
+
+/// Rewrites transactions before they're validated, e.g. to normalize the client ids of a feed
+/// or convert its amounts, read from a file with one step per line:
+/// ```text
+/// # the second acquirer numbers its clients from 5000
+/// client >= 5000 => set client = client - 5000
+/// type == "deposit" && tx >= 1000000 => set amount = amount * 0.92
+/// ```
+/// The conditions and values are expressions like those of the business rules, with the
+/// transaction as it is after the steps before. Every step that matches sets its fields,
+/// `client`, `tx` or `amount`, which a dispute, resolve or chargeback doesn't have
+#[derive(Clone, Debug, Default)]
+pub struct Transform {
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    condition: Expr,
+    assignments: Vec<(Field, Expr)>,
+}
+
+impl Transform {
+    pub fn parse(script: &str) -> Result<Self> {
+        let steps = script
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                Step::parse(line).with_context(|| format!("Invalid step on line {}", index + 1))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { steps })
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The transaction with the fields of every matching step set. `account` is the one of
+    /// the client it came in for, the client can change along the way. An assignment that
+    /// has no value doesn't fit the field is an error that names the step
+    pub fn apply(
+        &self,
+        mut transaction: Transaction,
+        account: Option<AccountView<'_>>,
+        now: u64,
+    ) -> Result<Transaction, String> {
+        for (index, step) in self.steps.iter().enumerate() {
+            let scope = Scope {
+                transaction: &transaction,
+                account,
+                now,
+            };
+            if !step.condition.eval(&scope).truthy() {
+                continue;
+            }
+            let values = step
+                .assignments
+                .iter()
+                .map(|(field, value)| (*field, value.eval(&scope)))
+                .collect::<Vec<_>>();
+            for (field, value) in values {
+                set(&mut transaction, field, value)
+                    .map_err(|e| format!("step {}: {}", index + 1, e))?;
+            }
+        }
+        Ok(transaction)
+    }
+}
+
+impl Step {
+    fn parse(line: &str) -> Result<Self> {
+        let mut parser = Parser::new(line)?;
+        let condition = parser.expression()?;
+        parser.expect(&Token::Arrow)?;
+        parser.expect(&Token::Identifier("set".to_string()))?;
+        let mut assignments = vec![];
+        loop {
+            let field = match parser.next() {
+                Some(Token::Identifier(name)) => name.parse::<Field>()?,
+                _ => bail!("Expected the field to set"),
+            };
+            if !matches!(field, Field::Client | Field::Tx | Field::Amount) {
+                bail!("Only client, tx and amount can be set, not {:?}", field);
+            }
+            parser.expect(&Token::Assign)?;
+            assignments.push((field, parser.expression()?));
+            match parser.next() {
+                None => break,
+                Some(Token::Comma) => {}
+                Some(token) => bail!("Unexpected {:?} after the assignment", token),
+            }
+        }
+        Ok(Self {
+            condition,
+            assignments,
+        })
+    }
+}
+
+fn set(transaction: &mut Transaction, field: Field, value: Value) -> Result<(), String> {
+    let number = match value {
+        Value::Number(number) => number,
+        value => return Err(format!("{:?} can't be set to {:?}", field, value)),
+    };
+    let integer = || {
+        number
+            .is_integer()
+            .then(|| number.to_u64())
+            .flatten()
+            .ok_or_else(|| format!("{:?} has to be a whole number, not {}", field, number))
+    };
+    match (field, transaction) {
+        (Field::Client, transaction) => {
+            // a u64 already with the client-id-u64 feature
+            #[allow(clippy::useless_conversion)]
+            let client = integer()?
+                .try_into()
+                .map_err(|_| format!("client {} is out of range", number))?;
+            match transaction {
+                Transaction::Deposit { client: c, .. }
+                | Transaction::Withdrawal { client: c, .. }
+                | Transaction::Dispute { client: c, .. }
+                | Transaction::Resolve { client: c, .. }
+                | Transaction::Chargeback { client: c, .. }
+                | Transaction::Transfer { client: c, .. }
+                | Transaction::EscrowOpen { client: c, .. }
+                | Transaction::EscrowRelease { client: c, .. }
+                | Transaction::EscrowRefund { client: c, .. }
+                | Transaction::Approve { client: c, .. }
+                | Transaction::Deny { client: c, .. }
+                | Transaction::Reversal { client: c, .. }
+                | Transaction::WalletTransfer { client: c, .. }
+                | Transaction::PromoCredit { client: c, .. }
+                | Transaction::Custom { client: c, .. } => *c = client,
+            }
+        }
+        (Field::Tx, transaction) => {
+            let tx = integer()?
+                .try_into()
+                .map_err(|_| format!("tx {} is out of range", number))?;
+            match transaction {
+                Transaction::Deposit { tx: t, .. }
+                | Transaction::Withdrawal { tx: t, .. }
+                | Transaction::Dispute { tx: t, .. }
+                | Transaction::Resolve { tx: t, .. }
+                | Transaction::Chargeback { tx: t, .. }
+                | Transaction::Transfer { tx: t, .. }
+                | Transaction::EscrowOpen { tx: t, .. }
+                | Transaction::EscrowRelease { tx: t, .. }
+                | Transaction::EscrowRefund { tx: t, .. }
+                | Transaction::Approve { tx: t, .. }
+                | Transaction::Deny { tx: t, .. }
+                | Transaction::Reversal { tx: t, .. }
+                | Transaction::WalletTransfer { tx: t, .. }
+                | Transaction::PromoCredit { tx: t, .. }
+                | Transaction::Custom { tx: t, .. } => *t = tx,
+            }
+        }
+        (
+            Field::Amount,
+            Transaction::Deposit { amount, .. }
+            | Transaction::Withdrawal { amount, .. }
+            | Transaction::Transfer { amount, .. }
+            | Transaction::EscrowOpen { amount, .. }
+            | Transaction::WalletTransfer { amount, .. }
+            | Transaction::PromoCredit { amount, .. },
+        ) => *amount = number,
+        (Field::Amount, Transaction::Custom { amount, .. }) => *amount = Some(number),
+        (Field::Amount, transaction) => {
+            return Err(format!("a {} has no amount", transaction.type_name()))
+        }
+        (field, _) => return Err(format!("{:?} can't be set", field)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+
+    #[test]
+    fn should_set_the_fields_of_matching_steps() {
+        let transform = Transform::parse(
+            "# normalize the second feed\nclient >= 5000 => set client = client - 5000, tx = tx + 1\n\
+             type == \"deposit\" && client == 1 => set amount = amount * 0.5",
+        )
+        .unwrap();
+        let transaction = transform
+            .apply(
+                Transaction::Deposit {
+                    client: 5001,
+                    tx: 7,
+                    amount: BigDecimal::from(3),
+                    wallet: None,
+                },
+                None,
+                0,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", transaction),
+            r#"Deposit { client: 1, tx: 8, amount: BigDecimal("1.5"), wallet: None }"#
+        );
+
+        let transform = Transform::parse("client == 2 => set amount = 1").unwrap();
+        assert_eq!(
+            transform
+                .apply(Transaction::Dispute { client: 2, tx: 1 }, None, 0)
+                .unwrap_err(),
+            "step 1: a dispute has no amount"
+        );
+        assert!(Transform::parse("true => set locked = true").is_err());
+        assert!(Transform::parse("true => set client = ").is_err());
+        assert!(Transform::parse("true => set client = 1,").is_err());
+    }
+}
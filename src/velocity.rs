@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+use crate::transaction::Transaction;
+
+/// How many deposits and withdrawals an account had applied lately and how much they were
+/// for, kept up to date as they're applied so rules don't have to scan the history.
+/// They're counted by the minute, the windows end at the current minute and go back a whole
+/// number of minutes, so a transaction drops out of the last hour 60 to 61 minutes later
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Velocity {
+    /// Oldest first, only minutes something was applied in, at most a day of them
+    minutes: VecDeque<Minute>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Minute {
+    /// Unix minutes
+    minute: u64,
+    deposits: Totals,
+    withdrawals: Totals,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub count: u64,
+    pub sum: BigDecimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Hour,
+    Day,
+}
+
+impl Window {
+    const fn minutes(self) -> u64 {
+        match self {
+            Window::Hour => 60,
+            Window::Day => 24 * 60,
+        }
+    }
+}
+
+impl Velocity {
+    /// Counts an applied deposit or withdrawal at `now`, unix seconds. Other transactions
+    /// don't count
+    pub fn record(&mut self, transaction: &Transaction, now: u64) {
+        let (is_deposit, amount) = match transaction {
+            Transaction::Deposit { amount, .. } => (true, amount),
+            Transaction::Withdrawal { amount, .. } => (false, amount),
+            _ => return,
+        };
+        let minute = now / 60;
+        self.expire(minute);
+        // the clock doesn't go back in time-aware mode, but the wall clock can
+        let current = match self.minutes.back_mut() {
+            Some(last) if last.minute >= minute => last,
+            _ => {
+                self.minutes.push_back(Minute {
+                    minute,
+                    deposits: Totals::default(),
+                    withdrawals: Totals::default(),
+                });
+                self.minutes.back_mut().expect("just pushed")
+            }
+        };
+        let totals = match is_deposit {
+            true => &mut current.deposits,
+            false => &mut current.withdrawals,
+        };
+        totals.count += 1;
+        totals.sum += amount;
+    }
+
+    /// The deposits applied within the window before `now`
+    pub fn deposits(&self, window: Window, now: u64) -> Totals {
+        self.total(window, now, |minute| &minute.deposits)
+    }
+
+    pub fn withdrawals(&self, window: Window, now: u64) -> Totals {
+        self.total(window, now, |minute| &minute.withdrawals)
+    }
+
+    /// Adds the counts of another account's, as if they had been applied to this one
+    pub fn merge(&mut self, other: &Self) {
+        let mut minutes = self
+            .minutes
+            .drain(..)
+            .chain(other.minutes.iter().cloned())
+            .collect::<Vec<_>>();
+        minutes.sort_by_key(|minute| minute.minute);
+        for minute in minutes {
+            match self.minutes.back_mut() {
+                Some(last) if last.minute == minute.minute => {
+                    last.deposits.count += minute.deposits.count;
+                    last.deposits.sum += minute.deposits.sum;
+                    last.withdrawals.count += minute.withdrawals.count;
+                    last.withdrawals.sum += minute.withdrawals.sum;
+                }
+                _ => self.minutes.push_back(minute),
+            }
+        }
+        if let Some(last) = self.minutes.back().map(|minute| minute.minute) {
+            self.expire(last);
+        }
+    }
+
+    fn total(&self, window: Window, now: u64, totals: impl Fn(&Minute) -> &Totals) -> Totals {
+        let minute = now / 60;
+        let since = minute.saturating_sub(window.minutes() - 1);
+        self.minutes
+            .iter()
+            .rev()
+            .take_while(|counted| counted.minute >= since)
+            .filter(|counted| counted.minute <= minute)
+            .map(totals)
+            .fold(Totals::default(), |mut total, totals| {
+                total.count += totals.count;
+                total.sum += &totals.sum;
+                total
+            })
+    }
+
+    /// Forgets the minutes that are more than a day before `minute`
+    fn expire(&mut self, minute: u64) {
+        let since = minute.saturating_sub(Window::Day.minutes() - 1);
+        while self
+            .minutes
+            .front()
+            .is_some_and(|oldest| oldest.minute < since)
+        {
+            self.minutes.pop_front();
+        }
+    }
+}
+
+impl HeapSize for Velocity {
+    fn heap_size(&self) -> usize {
+        self.minutes.capacity() * std::mem::size_of::<Minute>()
+            + self
+                .minutes
+                .iter()
+                .map(|minute| minute.deposits.sum.heap_size() + minute.withdrawals.sum.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl Snapshot for Velocity {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.minutes.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            minutes: VecDeque::decode(decoder)?,
+        })
+    }
+}
+
+impl Snapshot for Minute {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.minute.encode(encoder);
+        (self.deposits.count, self.deposits.sum.clone()).encode(encoder);
+        (self.withdrawals.count, self.withdrawals.sum.clone()).encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        let minute = u64::decode(decoder)?;
+        let (count, sum) = <(u64, BigDecimal)>::decode(decoder)?;
+        let deposits = Totals { count, sum };
+        let (count, sum) = <(u64, BigDecimal)>::decode(decoder)?;
+        Ok(Self {
+            minute,
+            deposits,
+            withdrawals: Totals { count, sum },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_what_was_applied_within_the_window() {
+        let deposit = |amount| Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: BigDecimal::from(amount),
+            wallet: None,
+        };
+        let mut velocity = Velocity::default();
+        velocity.record(&deposit(5), 0);
+        velocity.record(&deposit(7), 3000);
+        velocity.record(&deposit(1), 3010);
+        velocity.record(
+            &Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: BigDecimal::from(2),
+                wallet: None,
+            },
+            3020,
+        );
+        velocity.record(&Transaction::Dispute { client: 1, tx: 1 }, 3030);
+
+        let hour = velocity.deposits(Window::Hour, 3600);
+        assert_eq!((hour.count, hour.sum), (2, BigDecimal::from(8)));
+        assert_eq!(velocity.deposits(Window::Day, 3600).count, 3);
+        assert_eq!(velocity.withdrawals(Window::Hour, 3600).count, 1);
+        // a day later the first deposit is out of the window, then the others
+        assert_eq!(velocity.deposits(Window::Day, 3000 + 86_340).count, 2);
+        assert_eq!(velocity.deposits(Window::Day, 3000 + 86_400).count, 0);
+
+        let mut merged = Velocity::default();
+        merged.record(&deposit(3), 3005);
+        merged.merge(&velocity);
+        assert_eq!(merged.minutes.len(), 2);
+        assert_eq!(merged.deposits(Window::Day, 3600).sum, BigDecimal::from(16));
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+
+use crate::memory::HeapSize;
+use crate::snapshot::{Decoder, Encoder, Snapshot};
+
+/// The balances of one of a client's wallets other than the main one, which are the account's
+/// available and held. Deposits and withdrawals name the wallet they're in, a dispute holds
+/// the money in the wallet of the disputed tx and `wallet_transfer`s move money between them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Wallet {
+    pub available: BigDecimal,
+    pub held: BigDecimal,
+}
+
+impl Wallet {
+    pub fn total(&self) -> BigDecimal {
+        &self.available + &self.held
+    }
+}
+
+impl HeapSize for Wallet {
+    fn heap_size(&self) -> usize {
+        self.available.heap_size() + self.held.heap_size()
+    }
+}
+
+impl Snapshot for Wallet {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.available.encode(encoder);
+        self.held.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self {
+            available: BigDecimal::decode(decoder)?,
+            held: BigDecimal::decode(decoder)?,
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use playground::alerts::{Alert, AlertObserver};
+use playground::http::Target;
+use playground::json::Json;
+use playground::trace::TraceContext;
+
+/// Posts every alert as JSON to a URL, from a thread of its own so a slow receiver
+/// doesn't hold up processing. Alerts that can't be delivered are logged and dropped
+pub struct WebhookAlerts {
+    sender: Sender<(Alert, Option<TraceContext>)>,
+    trace: Arc<Mutex<Option<TraceContext>>>,
+}
+
+impl WebhookAlerts {
+    pub fn start(url: &str) -> Result<Self> {
+        let target = Target::parse(url)?;
+        let (sender, receiver) = mpsc::channel::<(Alert, Option<TraceContext>)>();
+        thread::spawn(move || {
+            for (alert, trace) in receiver {
+                if let Err(e) = post(&target, &alert_json(&alert, trace.as_ref()), trace) {
+                    eprintln!("Failed to post alert {}: {:#}", alert.name, e);
+                }
+            }
+        });
+        Ok(Self {
+            sender,
+            trace: Arc::default(),
+        })
+    }
+
+    /// The trace of what's being processed, set by the server for every request. Alerts
+    /// fired meanwhile are posted as part of it
+    pub fn trace(&self) -> Arc<Mutex<Option<TraceContext>>> {
+        Arc::clone(&self.trace)
+    }
+}
+
+impl AlertObserver for WebhookAlerts {
+    fn alert(&self, alert: &Alert) {
+        let trace = self
+            .trace
+            .lock()
+            .ok()
+            .and_then(|trace| trace.map(|trace| trace.child()));
+        // only fails once the thread is gone, which it never is on its own
+        let _ = self.sender.send((alert.clone(), trace));
+    }
+}
+
+fn alert_json(alert: &Alert, trace: Option<&TraceContext>) -> String {
+    Json::object([
+        ("alert", Json::from(alert.name.as_str())),
+        ("client", Json::from(alert.client)),
+        ("available", Json::from(format!("{:.4}", alert.available))),
+        ("held", Json::from(format!("{:.4}", alert.held))),
+        ("trace_id", Json::from(trace.map(TraceContext::trace_id))),
+    ])
+    .to_string()
+}
+
+fn post(target: &Target, body: &str, trace: Option<TraceContext>) -> Result<()> {
+    let body = Some(("application/json", body.as_bytes()));
+    let headers = trace
+        .map(|trace| ("traceparent", trace.traceparent()))
+        .into_iter()
+        .collect::<Vec<_>>();
+    let reply = target.send_with_headers("POST", "", &headers, body, Duration::from_secs(5))?;
+    if !(200..300).contains(&reply.status) {
+        bail!("Unexpected response status {}", reply.status);
+    }
+    Ok(())
+}
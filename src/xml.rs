@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Bytes, Read};
+
+use anyhow::{bail, Context, Result};
+use csv::{Position, StringRecord};
+
+use crate::amount::AmountFormat;
+use crate::transaction::{RawTransaction, RowParser};
+
+/// The columns of a transactions csv, which is what a `<transaction>` is mapped to
+const FIELDS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+
+/// Reads transactions from XML, one at a time so large files aren't loaded into memory.
+/// The fields can be child elements or attributes, the root element's name doesn't matter:
+/// ```xml
+/// <transactions>
+///   <transaction><type>deposit</type><client>1</client><tx>1</tx><amount>1.5</amount></transaction>
+///   <transaction type="dispute" client="1" tx="1"/>
+/// </transactions>
+/// ```
+/// There's no XML crate in the build, this handles elements, attributes, text, CDATA,
+/// comments and the predefined and numeric entities, but not DTDs or namespaces
+pub fn read_transactions<R: Read>(
+    reader: R,
+    format: AmountFormat,
+) -> impl Iterator<Item = Result<RawTransaction>> {
+    let parser = RowParser::new(StringRecord::from(FIELDS.to_vec()), format);
+    let mut events = Events::new(reader);
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        match next_transaction(&mut events) {
+            Ok(None) => None,
+            Ok(Some((line, fields))) => {
+                let mut record = FIELDS
+                    .iter()
+                    .map(|field| fields.get(*field).map_or("", String::as_str))
+                    .collect::<StringRecord>();
+                let mut position = Position::new();
+                position.set_line(line);
+                record.set_position(Some(position));
+                Some(parser.parse(record))
+            }
+            // there's no telling where the next transaction starts
+            Err(e) => {
+                done = true;
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// The line a `<transaction>` started on and its fields
+type Fields = (u64, HashMap<String, String>);
+
+fn next_transaction<R: Read>(events: &mut Events<R>) -> Result<Option<Fields>> {
+    loop {
+        match events.next()? {
+            Event::Eof => return Ok(None),
+            Event::Start {
+                name,
+                attributes,
+                empty,
+            } if name == "transaction" => {
+                let line = events.line;
+                let mut fields = attributes.into_iter().collect::<HashMap<_, _>>();
+                if !empty {
+                    read_fields(events, &mut fields)
+                        .with_context(|| format!("Invalid transaction on line {}", line))?;
+                }
+                return Ok(Some((line, fields)));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_fields<R: Read>(
+    events: &mut Events<R>,
+    fields: &mut HashMap<String, String>,
+) -> Result<()> {
+    let mut field: Option<(String, String)> = None;
+    loop {
+        match (events.next()?, &mut field) {
+            (Event::End(name), None) if name == "transaction" => return Ok(()),
+            (Event::Start { name, empty, .. }, None) => {
+                if empty {
+                    fields.insert(name, String::new());
+                } else {
+                    field = Some((name, String::new()));
+                }
+            }
+            (Event::Text(text), Some((_, value))) => value.push_str(&text),
+            (Event::Text(text), None) if text.trim().is_empty() => {}
+            (Event::End(end), Some((name, _))) if end == *name => {
+                if let Some((name, value)) = field.take() {
+                    fields.insert(name, value.trim().to_string());
+                }
+            }
+            (Event::Eof, _) => bail!("The file ended inside the transaction"),
+            (event, _) => bail!("Unexpected {:?}", event),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Event {
+    Start {
+        name: String,
+        attributes: Vec<(String, String)>,
+        /// `<name/>`, there won't be an `End` for it
+        empty: bool,
+    },
+    End(String),
+    Text(String),
+    Eof,
+}
+
+/// Pull parser for the subset of XML described on `read_transactions`
+pub(crate) struct Events<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+    peeked: Option<u8>,
+    /// 1 based, for error messages
+    line: u64,
+}
+
+impl<R: Read> Events<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            peeked: None,
+            line: 1,
+        }
+    }
+
+    fn byte(&mut self) -> Result<Option<u8>> {
+        let byte = match self.peeked.take() {
+            Some(byte) => Some(byte),
+            None => self.bytes.next().transpose()?,
+        };
+        if byte == Some(b'\n') {
+            self.line += 1;
+        }
+        Ok(byte)
+    }
+
+    /// Everything up to and including `end`, without it
+    fn until(&mut self, end: &[u8]) -> Result<Vec<u8>> {
+        let mut read = vec![];
+        while !read.ends_with(end) {
+            match self.byte()? {
+                Some(byte) => read.push(byte),
+                None => bail!(
+                    "Expected {} before the end of the file",
+                    String::from_utf8_lossy(end)
+                ),
+            }
+        }
+        read.truncate(read.len() - end.len());
+        Ok(read)
+    }
+
+    pub(crate) fn next(&mut self) -> Result<Event> {
+        let line = self.line;
+        let event = self
+            .read_event()
+            .with_context(|| format!("Invalid XML on line {}", line))?;
+        Ok(event)
+    }
+
+    fn read_event(&mut self) -> Result<Event> {
+        loop {
+            let first = match self.byte()? {
+                None => return Ok(Event::Eof),
+                Some(first) => first,
+            };
+            if first != b'<' {
+                let mut text = vec![first];
+                loop {
+                    match self.byte()? {
+                        Some(b'<') => {
+                            self.peeked = Some(b'<');
+                            break;
+                        }
+                        Some(byte) => text.push(byte),
+                        None => break,
+                    }
+                }
+                return Ok(Event::Text(unescape(&String::from_utf8(text)?)?));
+            }
+            let tag = match self.byte()? {
+                Some(b'?') => {
+                    self.until(b"?>")?;
+                    continue;
+                }
+                Some(b'!') => match self.byte()? {
+                    Some(b'-') => {
+                        self.until(b"-->")?;
+                        continue;
+                    }
+                    Some(b'[') => {
+                        if self.until(b"[")? != b"CDATA" {
+                            bail!("Expected <![CDATA[");
+                        }
+                        let text = self.until(b"]]>")?;
+                        return Ok(Event::Text(String::from_utf8(text)?));
+                    }
+                    // <!DOCTYPE ...>, declarations inside it aren't supported
+                    _ => {
+                        self.until(b">")?;
+                        continue;
+                    }
+                },
+                Some(byte) => {
+                    let mut tag = vec![byte];
+                    tag.extend(self.until(b">")?);
+                    String::from_utf8(tag)?
+                }
+                None => bail!("The file ended inside a tag"),
+            };
+            if let Some(name) = tag.strip_prefix('/') {
+                return Ok(Event::End(name.trim().to_string()));
+            }
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag.as_str(), false),
+            };
+            let (name, attributes) = parse_tag(tag)?;
+            return Ok(Event::Start {
+                name,
+                attributes,
+                empty,
+            });
+        }
+    }
+}
+
+fn parse_tag(tag: &str) -> Result<(String, Vec<(String, String)>)> {
+    let tag = tag.trim();
+    let (name, mut rest) = tag.split_at(tag.find(char::is_whitespace).unwrap_or(tag.len()));
+    if name.is_empty() {
+        bail!("Expected an element name in <{}>", tag);
+    }
+    let mut attributes = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok((name.to_string(), attributes));
+        }
+        let (attribute, value) = rest
+            .split_once('=')
+            .with_context(|| format!("Expected attribute=\"value\" in <{}>", tag))?;
+        let value = value.trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|quote| matches!(quote, '"' | '\''))
+            .with_context(|| format!("Expected a quoted value in <{}>", tag))?;
+        let (value, remaining) = value[1..]
+            .split_once(quote)
+            .with_context(|| format!("Unterminated value in <{}>", tag))?;
+        attributes.push((attribute.trim().to_string(), unescape(value)?));
+        rest = remaining;
+    }
+}
+
+fn unescape(text: &str) -> Result<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let (entity, remaining) = rest[start + 1..]
+            .split_once(';')
+            .with_context(|| format!("Unterminated entity in {}", text))?;
+        unescaped.push(match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32)
+                .with_context(|| format!("Unknown entity &{};", entity))?,
+        });
+        rest = remaining;
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn should_read_transactions_from_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- exported by the partner -->
+<transactions>
+  <transaction>
+    <type>deposit</type>
+    <client>1</client>
+    <tx>1</tx>
+    <amount><![CDATA[1.5]]></amount>
+  </transaction>
+  <transaction type="dispute" client='1' tx="1"/>
+  <transaction type="withdrawal" client="2"><tx>2</tx><amount>&#51;</amount></transaction>
+</transactions>
+"#;
+        let transactions = read_transactions(xml.as_bytes(), AmountFormat::default())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let summary = transactions
+            .iter()
+            .map(|raw| {
+                (
+                    raw.transaction_type(),
+                    raw.client(),
+                    raw.tx(),
+                    raw.amount().map(|amount| amount.normalized().to_string()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (TransactionType::Deposit, 1, 1, Some("1.5".to_string())),
+                (TransactionType::Dispute, 1, 1, None),
+                (TransactionType::Withdrawal, 2, 2, Some("3".to_string())),
+            ]
+        );
+
+        let broken =
+            "<transactions>\n<transaction><type>deposit</type>\n<client>x</client></transaction>";
+        let error = read_transactions(broken.as_bytes(), AmountFormat::default())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("line: 2"), "{:#}", error);
+        let unterminated = "<transactions><transaction><type>deposit</type>";
+        assert!(
+            read_transactions(unterminated.as_bytes(), AmountFormat::default())
+                .next()
+                .unwrap()
+                .is_err()
+        );
+    }
+}
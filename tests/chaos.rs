@@ -0,0 +1,122 @@
+//! Resilience under injected faults, run with `cargo test --features chaos --test chaos`
+#![cfg(feature = "chaos")]
+
+use std::net::TcpListener;
+use std::process::{Child, Command};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use bigdecimal::BigDecimal;
+use playground::chaos::{self, Faults, Seam};
+use playground::config::EngineConfig;
+use playground::event_log::EventLog;
+use playground::http::Target;
+use playground::transaction::Transaction;
+use playground::transaction_engine::TransactionEngine;
+
+/// The faults are the whole process's, so the tests take turns
+static FAULTS: Mutex<()> = Mutex::new(());
+
+fn scratch(name: &str) -> String {
+    let dir =
+        std::env::temp_dir().join(format!("playground-chaos-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_str().unwrap().to_string()
+}
+
+#[test]
+fn should_recover_every_change_the_event_log_confirmed() {
+    let _turn = FAULTS.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = scratch("log");
+    let mut engine = TransactionEngine::new();
+    let mut log = EventLog::open(&dir, &engine, 3600, 2, 0).unwrap();
+    chaos::install("store:error:0.3".parse().unwrap(), 1);
+    for tx in 1..=200 {
+        engine
+            .process(Transaction::Deposit {
+                client: (tx % 7) as _,
+                tx,
+                amount: BigDecimal::from(tx),
+                wallet: None,
+            })
+            .unwrap();
+        // whoever appends retries until the change is confirmed
+        while log.append(&engine, 0).is_err() {}
+    }
+    assert!(chaos::injected(Seam::Store) > 20);
+    chaos::install(Faults::default(), 0);
+
+    let (recovered, _) = EventLog::recover(&dir, EngineConfig::default()).unwrap();
+    assert!(TransactionEngine::diff(&engine, &recovered).is_empty());
+    assert_eq!(recovered.sequence(), engine.sequence());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn should_apply_every_retried_batch_once() {
+    let _turn = FAULTS.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = scratch("server");
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_playground"))
+            .args(["serve", "--listen", &format!("127.0.0.1:{}", port)])
+            .args(["--idempotency-keys", &format!("{}/keys.jsonl", dir)])
+            .args(["--event-log", &format!("{}/log", dir)])
+            .env(
+                "PLAYGROUND_CHAOS",
+                "source:drop:0.2,source:error:0.2,source:delay=5ms:0.5,store:delay=1ms:0.5",
+            )
+            .env("PLAYGROUND_CHAOS_SEED", "7")
+            .spawn()
+            .unwrap(),
+    );
+    // the replies of a third of the requests that got through are lost on the way back
+    chaos::install("sink:drop:0.3".parse().unwrap(), 3);
+    let target = Target::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+    for batch in 1..=20 {
+        let body = format!("type,client,tx,amount\ndeposit,1,{},1.5\n", batch);
+        let key = format!("batch-{}", batch);
+        let mut tries = 0;
+        loop {
+            let reply = target.send_with_headers(
+                "POST",
+                "transactions",
+                &[("Idempotency-Key", key.clone())],
+                Some(("text/csv", body.as_bytes())),
+                Duration::from_secs(5),
+            );
+            match reply {
+                Ok(reply) if reply.status == 200 => break,
+                _ => {
+                    tries += 1;
+                    assert!(tries < 100, "batch {} never got through", batch);
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+    }
+    assert!(chaos::injected(Seam::Sink) > 0);
+    chaos::install(Faults::default(), 0);
+    drop(server);
+
+    let (recovered, _) =
+        EventLog::recover(&format!("{}/log", dir), EngineConfig::default()).unwrap();
+    let account = recovered.account(1).unwrap();
+    assert_eq!(*account.available(), BigDecimal::from(30));
+    std::fs::remove_dir_all(dir).unwrap();
+}
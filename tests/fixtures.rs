@@ -0,0 +1,20 @@
+use std::process::Command;
+
+/// Runs the `test-fixtures` subcommand over tests/fixtures, regenerate the expected outputs with
+/// `cargo run -- test-fixtures --update`
+#[test]
+fn should_match_the_expected_outputs() {
+    let output = Command::new(env!("CARGO_BIN_EXE_playground"))
+        .args([
+            "test-fixtures",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"),
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,40 @@
+//! The legacy semantics reproduce the baseline's held balances below zero after a chargeback
+//! of a resolved dispute, which the invariants reject
+#![cfg(not(feature = "check-invariants"))]
+
+use std::process::Command;
+
+/// Runs tests/golden/legacy.csv, disputes of deposits and withdrawals with the quirks `--compat
+/// legacy` keeps, and compares the accounts with legacy.baseline.csv. That's what the baseline
+/// commit's binary wrote for the same file, and it only changes if that binary is run again.
+/// Neither binary orders the accounts, so the baseline's rows are stored sorted and every line
+/// has to be the same, byte for byte. Only stdout is compared, the baseline had no exit codes
+/// for rejects
+#[test]
+fn should_write_what_the_baseline_wrote_with_compat_legacy() {
+    let output = Command::new(env!("CARGO_BIN_EXE_playground"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden"))
+        .args(["--compat", "legacy", "legacy.csv"])
+        .output()
+        .unwrap();
+    assert!(
+        matches!(output.status.code(), Some(0 | 2)),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut actual = output.stdout.split_inclusive(|&byte| byte == b'\n');
+    let header = actual.next();
+    let mut rows = actual.collect::<Vec<_>>();
+    rows.sort_unstable();
+    let actual = header.into_iter().chain(rows).flatten().copied();
+
+    let expected = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/golden/legacy.baseline.csv"
+    ))
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(actual.collect()).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+}